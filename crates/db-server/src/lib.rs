@@ -5,23 +5,160 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 mod server {
-    use std::sync::Arc;
+    use std::{
+        path::{Component, Path, PathBuf},
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex, MutexGuard, PoisonError,
+        },
+        thread::JoinHandle,
+        time::{Duration, Instant},
+    };
 
-    use db::{rpc, KeyValueStore};
+    use db::{rpc, KeyValueStore, SetOutcome, StoreStatsSnapshot};
+    use uuid::Uuid;
 
     pub type DataType = Arc<KeyValueStore>;
 
+    /// How long writes and backup snapshots waited for the generation lock,
+    /// bucketed by powers of two: bucket `i` counts waits of `2^(i - 1)` up to
+    /// `2^i` microseconds, bucket 0 the ones under a microsecond and the last
+    /// bucket everything longer. Like the store's own stats, every counter is a
+    /// separate atomic.
+    #[derive(Debug, Default)]
+    pub(crate) struct LockWaits {
+        buckets: [AtomicU64; LockWaits::BUCKETS],
+        max_micros: AtomicU64,
+    }
+
+    impl LockWaits {
+        const BUCKETS: usize = 24;
+
+        pub(crate) fn record(&self, wait: Duration) {
+            let micros = u64::try_from(wait.as_micros()).unwrap_or(u64::MAX);
+            let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+            self.buckets[bucket.min(Self::BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+            self.max_micros.fetch_max(micros, Ordering::Relaxed);
+        }
+
+        pub(crate) fn snapshot(&self) -> LockWaitSnapshot {
+            LockWaitSnapshot {
+                buckets: self
+                    .buckets
+                    .iter()
+                    .map(|bucket| bucket.load(Ordering::Relaxed))
+                    .collect(),
+                max: Duration::from_micros(self.max_micros.load(Ordering::Relaxed)),
+            }
+        }
+    }
+
+    /// The lock waits of a server at one point in time, see `LockWaits`.
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct LockWaitSnapshot {
+        pub buckets: Vec<u64>,
+        /// The longest wait.
+        pub max: Duration,
+    }
+
+    impl LockWaitSnapshot {
+        /// Gets how many waits were recorded.
+        pub fn count(&self) -> u64 {
+            self.buckets.iter().sum()
+        }
+    }
+
+    /// How far along a backup is, see `StupidServer::stats`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct BackupProgress {
+        pub id: Uuid,
+        /// The generation the store was snapshotted at.
+        pub generation: u64,
+        /// How many rows the snapshot holds.
+        pub rows: u64,
+        /// How many of them have been written so far.
+        pub rows_written: u64,
+        /// How long writers were locked out while the store was snapshotted.
+        pub snapshot_window: Duration,
+        /// Whether the background thread is done, successfully or not.
+        pub finished: bool,
+    }
+
+    /// The counters of a server at one point in time, see
+    /// `StupidServer::stats`.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct ServerStats {
+        pub store: StoreStatsSnapshot,
+        /// How many writes the store has applied.
+        pub generation: u64,
+        pub lock_waits: LockWaitSnapshot,
+        /// The most recently started backup, until it's joined by the next
+        /// backup request or `StupidServer::wait_for_backup`.
+        pub backup: Option<BackupProgress>,
+    }
+
+    /// A backup that has been snapshotted and is being written out on a
+    /// background thread.
+    pub(crate) struct RunningBackup {
+        pub(crate) id: Uuid,
+        pub(crate) generation: u64,
+        pub(crate) rows: u64,
+        pub(crate) rows_written: Arc<AtomicU64>,
+        pub(crate) snapshot_window: Duration,
+        pub(crate) handle: JoinHandle<db::Result<()>>,
+    }
+
+    impl RunningBackup {
+        fn progress(&self) -> BackupProgress {
+            BackupProgress {
+                id: self.id,
+                generation: self.generation,
+                rows: self.rows,
+                rows_written: self.rows_written.load(Ordering::Relaxed),
+                snapshot_window: self.snapshot_window,
+                finished: self.handle.is_finished(),
+            }
+        }
+
+        /// Waits for the background thread, turning a panic into an error.
+        fn join(self) -> db::Result<()> {
+            let id = self.id;
+            self.handle
+                .join()
+                .unwrap_or_else(|_| Err(db::Error::Io(format!("backup {} panicked", id))))
+        }
+    }
+
     pub struct StupidServer {
         pub(crate) store: DataType,
+        /// How many writes have been applied to `store`. Writers hold this
+        /// lock while writing, so a backup taking it sees the store at exactly
+        /// this generation.
+        pub(crate) generation: Mutex<u64>,
+        pub(crate) lock_waits: LockWaits,
+        pub(crate) backup: Mutex<Option<RunningBackup>>,
+        /// Backups are only written inside this directory, and refused without
+        /// one.
+        pub(crate) backup_dir: Option<PathBuf>,
     }
 
     impl StupidServer {
         pub fn new() -> Self {
             Self {
                 store: Arc::new(KeyValueStore::empty()),
+                generation: Mutex::new(0),
+                lock_waits: LockWaits::default(),
+                backup: Mutex::new(None),
+                backup_dir: None,
             }
         }
 
+        /// Sets the directory backups are written into, see `backup`.
+        pub fn with_backup_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+            self.backup_dir = Some(dir.into());
+            self
+        }
+
         pub fn request(&self, req: &rpc::GenericRequest) -> rpc::GenericResponse {
             use rpc::generic_request::Request;
             use rpc::generic_response::Response;
@@ -30,6 +167,7 @@ mod server {
                     Request::GetRequest(get) => Response::GetResponse(self.get(get)),
                     Request::SetRequest(set) => Response::SetResponse(self.set(set)),
                     Request::DeleteRequest(del) => Response::DeleteResponse(self.delete(del)),
+                    Request::BackupRequest(bak) => Response::BackupResponse(self.backup(bak)),
                 },
                 None => return rpc::GenericResponse { response: None },
             };
@@ -41,7 +179,11 @@ mod server {
 
         pub fn get(&self, req: &rpc::GetRequest) -> rpc::GetResponse {
             let (value, resp_msg, code) = match self.store.get_clone(req.key.as_str()) {
-                Ok(row) => (row.value().to_string(), "".to_string(), rpc::StatusCode::Ok),
+                Ok(row) => (
+                    row.value().to_display_string(),
+                    "".to_string(),
+                    rpc::StatusCode::Ok,
+                ),
                 Err(err) => ("".to_string(), err.to_string(), rpc::StatusCode::Fail),
             };
             rpc::GetResponse {
@@ -53,11 +195,23 @@ mod server {
 
         pub fn set(&self, req: &rpc::SetRequest) -> rpc::SetResponse {
             let (message, resp_msg, code) = match self
-                .store
-                .set_or_insert(req.key.as_str(), req.value.as_str())
+                .write(|store| store.set_or_insert(req.key.as_str(), req.value.as_str()))
             {
-                Ok(_) => (
-                    format!("set/updated {}", req.key),
+                Ok(previous) => (
+                    match (
+                        SetOutcome::from_previous(previous.as_ref(), &req.value),
+                        previous,
+                    ) {
+                        (SetOutcome::Inserted, _) => {
+                            format!("inserted key {}, no previous value", req.key)
+                        }
+                        (SetOutcome::Updated, Some(previous)) => format!(
+                            "updated key {}, previous value was '{}'",
+                            req.key,
+                            previous.value()
+                        ),
+                        _ => format!("no change to key {}", req.key),
+                    },
                     "".to_string(),
                     rpc::StatusCode::Ok,
                 ),
@@ -72,7 +226,8 @@ mod server {
         }
 
         pub fn delete(&self, req: &rpc::DeleteRequest) -> rpc::DeleteResponse {
-            let (message, resp_msg, code) = match self.store.delete(req.key.as_str()) {
+            let (message, resp_msg, code) = match self.write(|store| store.delete(req.key.as_str()))
+            {
                 Ok(deleted) => (
                     format!("deleted {}", deleted),
                     "".to_string(),
@@ -87,5 +242,530 @@ mod server {
                 status_code: code.into(),
             }
         }
+
+        /// Runs the write `f` against the store under the generation lock,
+        /// bumping the generation if it succeeds.
+        fn write<T, F>(&self, f: F) -> db::Result<T>
+        where
+            F: FnOnce(&KeyValueStore) -> db::Result<T>,
+        {
+            let mut generation = self.lock_generation()?;
+            let result = f(&self.store);
+            if result.is_ok() {
+                *generation += 1;
+            }
+            result
+        }
+
+        /// Locks the generation, recording how long that took in `lock_waits`.
+        fn lock_generation(&self) -> db::Result<MutexGuard<'_, u64>> {
+            let start = Instant::now();
+            let guard = self
+                .generation
+                .lock()
+                .map_err(|err| db::Error::mutex_poisoned(&err))?;
+            self.lock_waits.record(start.elapsed());
+            Ok(guard)
+        }
+
+        /// Gets how many writes the store has applied.
+        pub fn generation(&self) -> u64 {
+            *self
+                .generation
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+        }
+
+        /// Gets the store's operation counts, how long the generation lock took
+        /// to get, and the progress of the current backup.
+        pub fn stats(&self) -> ServerStats {
+            ServerStats {
+                store: self.store.stats(),
+                generation: self.generation(),
+                lock_waits: self.lock_waits.snapshot(),
+                backup: self
+                    .backup
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .as_ref()
+                    .map(RunningBackup::progress),
+            }
+        }
+
+        /// Resolves `destination` inside the backup directory. Only plain
+        /// relative paths are accepted, so nothing can be written outside it.
+        fn backup_path(&self, destination: &str) -> Result<PathBuf, (String, rpc::StatusCode)> {
+            let dir = self.backup_dir.as_ref().ok_or_else(|| {
+                (
+                    "backups are disabled, no backup directory is configured".to_string(),
+                    rpc::StatusCode::FailedPrecondition,
+                )
+            })?;
+            if destination.is_empty() {
+                return Err((
+                    "destination_path is required".to_string(),
+                    rpc::StatusCode::Fail,
+                ));
+            }
+            let relative = Path::new(destination);
+            if !relative
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+            {
+                return Err((
+                    format!(
+                        "destination_path '{}' must be a relative path inside the backup directory",
+                        destination
+                    ),
+                    rpc::StatusCode::Fail,
+                ));
+            }
+            Ok(dir.join(relative))
+        }
+
+        /// Starts an online backup of the store. The rows are cloned under the
+        /// generation lock (the only time writers are blocked) and then written
+        /// to `destination_path`, resolved inside the backup directory, on a
+        /// background thread while normal traffic continues. Only one backup
+        /// may run at a time.
+        ///
+        /// The response holds the generation the snapshot was taken at, and the
+        /// result of the previous backup if it finished since the last request.
+        /// The write's progress shows up in `stats` until the backup is joined.
+        pub fn backup(&self, req: &rpc::BackupRequest) -> rpc::BackupResponse {
+            let fail = |resp_msg: String, code: rpc::StatusCode| rpc::BackupResponse {
+                resp_msg,
+                status_code: code.into(),
+                ..Default::default()
+            };
+
+            let path = match self.backup_path(&req.destination_path) {
+                Ok(path) => path,
+                Err((resp_msg, code)) => return fail(resp_msg, code),
+            };
+            let codec = match rpc::BackupCodec::from_i32(req.codec) {
+                Some(codec) => codec,
+                None => {
+                    return fail(
+                        format!("unsupported backup codec {}", req.codec),
+                        rpc::StatusCode::Fail,
+                    )
+                }
+            };
+
+            let mut running = match self.backup.lock() {
+                Ok(guard) => guard,
+                Err(err) => return fail(err.to_string(), rpc::StatusCode::Fail),
+            };
+            if let Some(backup) = running.as_ref() {
+                if !backup.handle.is_finished() {
+                    return fail(
+                        format!("backup {} is already running", backup.id),
+                        rpc::StatusCode::FailedPrecondition,
+                    );
+                }
+            }
+            let (previous_backup_id, previous_backup_error) = match running.take() {
+                Some(backup) => (
+                    backup.id.to_string(),
+                    backup
+                        .join()
+                        .err()
+                        .map_or_else(String::new, |err| err.to_string()),
+                ),
+                None => Default::default(),
+            };
+            let snapshot_failed = |err: db::Error| rpc::BackupResponse {
+                previous_backup_id: previous_backup_id.clone(),
+                previous_backup_error: previous_backup_error.clone(),
+                ..fail(err.to_string(), rpc::StatusCode::Fail)
+            };
+
+            let (snapshot, generation, snapshot_window) = match self.lock_generation() {
+                Ok(generation) => {
+                    let start = Instant::now();
+                    match self.store.to_disk() {
+                        Ok(snapshot) => (snapshot, *generation, start.elapsed()),
+                        Err(err) => return snapshot_failed(err),
+                    }
+                }
+                Err(err) => return snapshot_failed(err),
+            };
+
+            let id = Uuid::new_v4();
+            let rows = snapshot.data.len() as u64;
+            let fingerprint = snapshot.fingerprint();
+            let rows_written = Arc::new(AtomicU64::new(0));
+            let handle = {
+                let rows_written = Arc::clone(&rows_written);
+                let progress = move |written: usize| {
+                    rows_written.store(written as u64, Ordering::Relaxed);
+                };
+                std::thread::spawn(move || match codec {
+                    rpc::BackupCodec::Json => snapshot.write_to_with_progress(path, progress),
+                })
+            };
+            *running = Some(RunningBackup {
+                id,
+                generation,
+                rows,
+                rows_written,
+                snapshot_window,
+                handle,
+            });
+
+            rpc::BackupResponse {
+                backup_id: id.to_string(),
+                rows,
+                fingerprint,
+                resp_msg: "".to_string(),
+                status_code: rpc::StatusCode::Ok.into(),
+                generation,
+                previous_backup_id,
+                previous_backup_error,
+            }
+        }
+
+        /// Blocks until the most recently started backup has finished writing,
+        /// returning its result, or `None` if no backup has been started.
+        pub fn wait_for_backup(&self) -> Option<db::Result<()>> {
+            let running = self.backup.lock().ok()?.take()?;
+            Some(running.join())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use db::StoreDiskRepr;
+        use pretty_assertions::assert_eq;
+
+        fn backup_request(destination: &str) -> rpc::BackupRequest {
+            rpc::BackupRequest {
+                destination_path: destination.to_string(),
+                codec: rpc::BackupCodec::Json.into(),
+                client_id: "test".to_string(),
+            }
+        }
+
+        fn server_with_backups() -> (StupidServer, tempfile::TempDir) {
+            let dir = tempfile::tempdir().expect("unable to create tempdir");
+            (StupidServer::new().with_backup_dir(dir.path()), dir)
+        }
+
+        fn set(server: &StupidServer, key: &str, value: &str) {
+            let resp = server.set(&rpc::SetRequest {
+                key: key.to_string(),
+                value: value.to_string(),
+                client_id: "test".to_string(),
+            });
+            assert_eq!(resp.status_code, i32::from(rpc::StatusCode::Ok));
+        }
+
+        fn running_backup<F>(f: F) -> RunningBackup
+        where
+            F: FnOnce() -> db::Result<()> + Send + 'static,
+        {
+            RunningBackup {
+                id: Uuid::new_v4(),
+                generation: 0,
+                rows: 0,
+                rows_written: Arc::default(),
+                snapshot_window: Duration::ZERO,
+                handle: std::thread::spawn(f),
+            }
+        }
+
+        /// The writes of `backup_during_write_storm` in order: the rows the
+        /// store starts with, then the storm.
+        fn storm_writes() -> impl Iterator<Item = (String, String)> {
+            (0..1000)
+                .map(|i| (format!("key{}", i), "value".to_string()))
+                .chain((0..5000).map(|i| (format!("storm{}", i % 500), format!("value{}", i))))
+        }
+
+        #[test]
+        fn backup_during_write_storm() {
+            let (server, dir) = server_with_backups();
+            let server = Arc::new(server);
+            for (key, value) in storm_writes().take(1000) {
+                set(&server, &key, &value);
+            }
+
+            let writer = {
+                let server = Arc::clone(&server);
+                std::thread::spawn(move || {
+                    for (key, value) in storm_writes().skip(1000) {
+                        set(&server, &key, &value);
+                    }
+                })
+            };
+
+            let resp = server.backup(&backup_request("backup.json"));
+            assert_eq!(resp.status_code, i32::from(rpc::StatusCode::Ok));
+            assert!(!resp.backup_id.is_empty());
+            assert!(resp.rows >= 1000);
+            assert!((1000..=6000).contains(&resp.generation));
+            assert!(resp.previous_backup_id.is_empty());
+
+            let progress = server
+                .stats()
+                .backup
+                .expect("backup should be in the stats");
+            assert_eq!(progress.id.to_string(), resp.backup_id);
+            assert_eq!(
+                (progress.generation, progress.rows),
+                (resp.generation, resp.rows)
+            );
+            assert!(progress.rows_written <= progress.rows);
+
+            writer.join().expect("writer thread panicked");
+            while server
+                .stats()
+                .backup
+                .is_some_and(|progress| !progress.finished)
+            {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            assert_eq!(
+                server.stats().backup.map(|progress| progress.rows_written),
+                Some(resp.rows)
+            );
+            assert_eq!(server.wait_for_backup(), Some(Ok(())));
+            assert_eq!(server.stats().backup, None);
+            assert_eq!(server.generation(), 6000);
+
+            let restored = StoreDiskRepr::read_from(dir.path().join("backup.json"))
+                .expect("unable to read backup");
+            assert_eq!(restored.data.len() as u64, resp.rows);
+            assert_eq!(restored.fingerprint(), resp.fingerprint);
+
+            // Replaying the writes up to the claimed generation against an
+            // empty store gives back exactly what was backed up.
+            let replayed = KeyValueStore::empty();
+            for (key, value) in storm_writes().take(resp.generation as usize) {
+                assert!(replayed.set_or_insert(&key, &value).is_ok());
+            }
+            let restored = KeyValueStore::from_disk(&restored).expect("unable to load backup");
+            assert_eq!(replayed.content_eq(&restored, true), Ok(true));
+        }
+
+        #[test]
+        fn writes_are_not_blocked_by_backups() {
+            use std::sync::atomic::AtomicBool;
+
+            let (server, _dir) = server_with_backups();
+            let server = Arc::new(server);
+            for i in 0..20_000 {
+                set(&server, &format!("key{}", i), "value");
+            }
+
+            // The writer keeps going until every backup has finished, noting
+            // the store's generation after each write and the backup (if any)
+            // that was still being written at that point.
+            let done = Arc::new(AtomicBool::new(false));
+            let writer = {
+                let server = Arc::clone(&server);
+                let done = Arc::clone(&done);
+                std::thread::spawn(move || {
+                    let mut writes = Vec::new();
+                    while !done.load(Ordering::SeqCst) {
+                        let i = writes.len();
+                        set(
+                            &server,
+                            &format!("key{}", i % 20_000),
+                            &format!("value{}", i),
+                        );
+                        let stats = server.stats();
+                        let running = stats.backup.filter(|backup| !backup.finished);
+                        writes.push((stats.generation, running.map(|backup| backup.id)));
+                    }
+                    writes
+                })
+            };
+            let mut backups = Vec::new();
+            for i in 0..5 {
+                let resp = server.backup(&backup_request(&format!("backup{}.json", i)));
+                assert_eq!(resp.status_code, i32::from(rpc::StatusCode::Ok));
+                let id = Uuid::parse_str(&resp.backup_id).expect("backup id should be a uuid");
+                backups.push((id, resp.generation));
+                // `wait_for_backup` takes the backup out of the stats, so let
+                // the writer see it until it's done.
+                while matches!(server.stats().backup, Some(backup) if !backup.finished) {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                assert_eq!(server.wait_for_backup(), Some(Ok(())));
+            }
+            done.store(true, Ordering::SeqCst);
+            let writes = writer.join().expect("writer thread panicked");
+
+            // The writer is the only one writing, so every write it sees bumps
+            // the generation, and no backup snapshots an older store than the
+            // one before it.
+            assert!(writes.windows(2).all(|pair| pair[0].0 < pair[1].0));
+            assert!(backups.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+            // Writers only ever wait for the snapshot, never for the file to
+            // be written: every backup has writes that landed after its
+            // snapshot and finished while it was still being written.
+            for (id, generation) in &backups {
+                assert!(
+                    writes
+                        .iter()
+                        .any(|(written, running)| written > generation && running == &Some(*id)),
+                    "no write finished while backup {} was running",
+                    id
+                );
+            }
+            assert_eq!(
+                server.stats().lock_waits.count(),
+                20_000 + writes.len() as u64 + 5
+            );
+        }
+
+        #[test]
+        fn only_one_backup_at_a_time() {
+            let (server, _dir) = server_with_backups();
+            let (tx, rx) = std::sync::mpsc::channel::<()>();
+            let blocked = running_backup(move || {
+                rx.recv().ok();
+                Ok(())
+            });
+            let id = blocked.id;
+            *server.backup.lock().unwrap() = Some(blocked);
+
+            let resp = server.backup(&backup_request("second.json"));
+            assert_eq!(
+                resp.status_code,
+                i32::from(rpc::StatusCode::FailedPrecondition)
+            );
+            assert!(resp.resp_msg.contains(&id.to_string()));
+
+            tx.send(()).unwrap();
+            assert_eq!(server.wait_for_backup(), Some(Ok(())));
+            let resp = server.backup(&backup_request("third.json"));
+            assert_eq!(resp.status_code, i32::from(rpc::StatusCode::Ok));
+            assert_eq!(server.wait_for_backup(), Some(Ok(())));
+        }
+
+        #[test]
+        fn backup_reports_previous_result() {
+            let (server, _dir) = server_with_backups();
+            let failed = running_backup(|| Err(db::Error::Io("disk full".to_string())));
+            let id = failed.id;
+            while !failed.handle.is_finished() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            *server.backup.lock().unwrap() = Some(failed);
+
+            let first = server.backup(&backup_request("first.json"));
+            assert_eq!(first.status_code, i32::from(rpc::StatusCode::Ok));
+            assert_eq!(first.previous_backup_id, id.to_string());
+            assert_eq!(
+                first.previous_backup_error,
+                "io error occurred: 'disk full'"
+            );
+
+            while server
+                .stats()
+                .backup
+                .is_some_and(|progress| !progress.finished)
+            {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            let second = server.backup(&backup_request("second.json"));
+            assert_eq!(second.status_code, i32::from(rpc::StatusCode::Ok));
+            assert_eq!(second.previous_backup_id, first.backup_id);
+            assert_eq!(second.previous_backup_error, "");
+            assert_eq!(server.wait_for_backup(), Some(Ok(())));
+        }
+
+        #[test]
+        fn set_messages() {
+            let server = StupidServer::new();
+            let set = |value: &str| {
+                server.set(&rpc::SetRequest {
+                    key: "key".to_string(),
+                    value: value.to_string(),
+                    client_id: "test".to_string(),
+                })
+            };
+
+            let resp = set("value1");
+            assert_eq!(resp.status_code, i32::from(rpc::StatusCode::Ok));
+            assert_eq!(resp.message, "inserted key key, no previous value");
+            assert_eq!(
+                set("value2").message,
+                "updated key key, previous value was 'value1'"
+            );
+            assert_eq!(set("value2").message, "no change to key key");
+        }
+
+        #[test]
+        fn set_rejects_invalid_key() {
+            let server = StupidServer::new();
+            let resp = server.set(&rpc::SetRequest {
+                key: "bad\nkey".to_string(),
+                value: "value".to_string(),
+                client_id: "test".to_string(),
+            });
+            assert_eq!(resp.status_code, i32::from(rpc::StatusCode::Fail));
+            assert!(resp.message.is_empty());
+            assert_eq!(
+                resp.resp_msg,
+                "invalid key \"bad\\nkey\": key contains the control character '\\n'"
+            );
+        }
+
+        #[test]
+        fn backup_requires_destination() {
+            let (server, _dir) = server_with_backups();
+            let resp = server.backup(&rpc::BackupRequest::default());
+            assert_eq!(resp.status_code, i32::from(rpc::StatusCode::Fail));
+            assert_eq!(resp.resp_msg, "destination_path is required");
+            assert!(server.wait_for_backup().is_none());
+        }
+
+        #[test]
+        fn backup_stays_in_backup_dir() {
+            let resp = StupidServer::new().backup(&backup_request("backup.json"));
+            assert_eq!(
+                resp.status_code,
+                i32::from(rpc::StatusCode::FailedPrecondition)
+            );
+
+            let (server, dir) = server_with_backups();
+            for destination in [
+                "../backup.json",
+                "nested/../../backup.json",
+                "/tmp/backup.json",
+            ] {
+                let resp = server.backup(&backup_request(destination));
+                assert_eq!(
+                    resp.status_code,
+                    i32::from(rpc::StatusCode::Fail),
+                    "{}",
+                    destination
+                );
+            }
+            assert!(server.wait_for_backup().is_none());
+
+            std::fs::create_dir(dir.path().join("nested")).expect("unable to create dir");
+            let resp = server.backup(&backup_request("nested/backup.json"));
+            assert_eq!(resp.status_code, i32::from(rpc::StatusCode::Ok));
+            assert_eq!(server.wait_for_backup(), Some(Ok(())));
+            assert!(dir.path().join("nested/backup.json").is_file());
+        }
+
+        #[test]
+        fn backup_rejects_unknown_codec() {
+            let (server, _dir) = server_with_backups();
+            let resp = server.backup(&rpc::BackupRequest {
+                codec: 7,
+                ..backup_request("backup.json")
+            });
+            assert_eq!(resp.status_code, i32::from(rpc::StatusCode::Fail));
+            assert_eq!(resp.resp_msg, "unsupported backup codec 7");
+            assert!(server.wait_for_backup().is_none());
+        }
     }
 }
@@ -22,6 +22,61 @@ pub enum Error {
     JsonSerialize(String),
     #[error("serde_json error occurred during deserialization: '{0}'")]
     JsonDeserialize(String),
+    #[error("io error occurred: '{0}'")]
+    Io(String),
+    #[error("version mismatch: expected version {0} but found version {1}")]
+    VersionMismatch(u64, u64),
+    #[error("key '{key}' is {size} bytes which is over the limit of {limit} bytes")]
+    KeyTooLarge {
+        key: String,
+        size: usize,
+        limit: usize,
+    },
+    #[error("value for key '{key}' is {size} bytes which is over the limit of {limit} bytes")]
+    ValueTooLarge {
+        key: String,
+        size: usize,
+        limit: usize,
+    },
+    #[error("checksum mismatch for key '{0}'")]
+    ChecksumMismatch(String),
+    #[error("invalid row: {0}")]
+    InvalidRow(String),
+    #[error("invalid key {key:?}: {reason}")]
+    InvalidKey { key: String, reason: String },
+    #[error("invalid timestamp: {0}")]
+    InvalidTimestamp(i64),
+    #[error("protobuf error occurred during encoding: '{0}'")]
+    ProtoEncode(String),
+    #[error("protobuf error occurred during decoding: '{0}'")]
+    ProtoDecode(String),
+    #[error("store was accessed from inside one of its own iteration callbacks")]
+    ReentrantAccess,
+    #[error("store is read-only")]
+    ReadOnly,
+    #[error("unsupported store version {0}")]
+    UnsupportedVersion(u8),
+    #[error("store is full, it holds at most {limit} rows but the write needs {attempted}")]
+    CapacityExceeded { limit: usize, attempted: usize },
+    #[error("value of key '{key}' is '{actual}', not the expected value")]
+    ValueMismatch { key: String, actual: String },
+    #[error("operation {index} of the transaction failed: {source}")]
+    TransactionFailed { index: usize, source: Box<Error> },
+    #[error("{0} is not supported by this store")]
+    Unsupported(String),
+    #[error("{value} {op} {delta} for key '{key}' overflows")]
+    Overflow {
+        key: String,
+        value: i64,
+        op: char,
+        delta: i64,
+    },
+    #[error("unable to parse value of key '{key}' as {target_type}: {source_msg}")]
+    ValueParse {
+        key: String,
+        target_type: String,
+        source_msg: String,
+    },
 }
 
 impl Error {
@@ -44,6 +99,92 @@ impl Error {
     pub fn json_de(err: &serde_json::Error) -> Self {
         Self::JsonDeserialize(err.to_string())
     }
+
+    pub fn io(err: &std::io::Error) -> Self {
+        Self::Io(err.to_string())
+    }
+
+    pub fn proto_encode(err: &prost::EncodeError) -> Self {
+        Self::ProtoEncode(err.to_string())
+    }
+
+    pub fn proto_decode(err: &prost::DecodeError) -> Self {
+        Self::ProtoDecode(err.to_string())
+    }
+
+    pub fn version_mismatch(expected: u64, actual: u64) -> Self {
+        Self::VersionMismatch(expected, actual)
+    }
+
+    pub fn key_too_large(key: &str, size: usize, limit: usize) -> Self {
+        Self::KeyTooLarge {
+            key: key.to_string(),
+            size,
+            limit,
+        }
+    }
+
+    pub fn capacity_exceeded(limit: usize, attempted: usize) -> Self {
+        Self::CapacityExceeded { limit, attempted }
+    }
+
+    pub fn invalid_key(key: &str, reason: &str) -> Self {
+        Self::InvalidKey {
+            key: key.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn invalid_row(reason: &str) -> Self {
+        Self::InvalidRow(reason.to_string())
+    }
+
+    pub fn checksum_mismatch(key: &str) -> Self {
+        Self::ChecksumMismatch(key.to_string())
+    }
+
+    pub fn value_too_large(key: &str, size: usize, limit: usize) -> Self {
+        Self::ValueTooLarge {
+            key: key.to_string(),
+            size,
+            limit,
+        }
+    }
+
+    pub fn value_mismatch(key: &str, actual: &str) -> Self {
+        Self::ValueMismatch {
+            key: key.to_string(),
+            actual: actual.to_string(),
+        }
+    }
+
+    pub fn transaction_failed(index: usize, err: Error) -> Self {
+        Self::TransactionFailed {
+            index,
+            source: Box::new(err),
+        }
+    }
+
+    pub fn overflow(key: &str, value: i64, op: char, delta: i64) -> Self {
+        Self::Overflow {
+            key: key.to_string(),
+            value,
+            op,
+            delta,
+        }
+    }
+
+    pub fn unsupported(what: &str) -> Self {
+        Self::Unsupported(what.to_string())
+    }
+
+    pub fn value_parse<T>(key: &str, err: &impl std::fmt::Display) -> Self {
+        Self::ValueParse {
+            key: key.to_string(),
+            target_type: std::any::type_name::<T>().to_string(),
+            source_msg: err.to_string(),
+        }
+    }
 }
 
 impl<T> From<Error> for Result<T> {
@@ -0,0 +1,726 @@
+// Copyright (c) 2022 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    ops::Bound,
+    sync::{Mutex, MutexGuard},
+};
+
+use super::{run_unpoisoned, upsert_base, CasOutcome, DeleteManyReport, StoreLimits};
+use crate::{Row, RowDiskRepr, SetOutcome, StoreDiskRepr};
+
+pub type Data = BTreeMap<String, Row>;
+
+/// A store that keeps its rows ordered by key, so it can answer range and
+/// prefix queries without scanning every row.
+#[derive(Debug, Default)]
+pub struct BTreeStore {
+    data: Mutex<Data>,
+    limits: StoreLimits,
+}
+
+impl BTreeStore {
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty store that rejects keys longer than `max_key_bytes` and
+    /// values larger than `max_value_bytes`. Stores created any other way have
+    /// no limits.
+    pub fn with_limits(max_key_bytes: usize, max_value_bytes: usize) -> Self {
+        Self {
+            limits: StoreLimits::new(max_key_bytes, max_value_bytes),
+            ..Self::default()
+        }
+    }
+
+    fn lock(&self) -> crate::Result<MutexGuard<'_, Data>> {
+        self.data
+            .lock()
+            .map_err(|err| crate::Error::mutex_poisoned(&err))
+    }
+
+    /// Gets a copy of the row for `key`. Expired rows are removed and reported
+    /// as not found, as are rows that have been deleted.
+    pub fn get_clone(&self, key: &str) -> crate::Result<Row> {
+        self.lock()
+            .and_then(|mut data| Self::get_locked(&mut data, key))
+    }
+
+    /// Gets a copy of the row for each of `keys` under a single lock, see
+    /// `KeyValueStore::get_many`.
+    pub fn get_many(&self, keys: &[&str]) -> crate::Result<Vec<(String, crate::Result<Row>)>> {
+        self.lock().map(|mut data| {
+            keys.iter()
+                .map(|&key| (key.to_string(), Self::get_locked(&mut data, key)))
+                .collect()
+        })
+    }
+
+    fn get_locked(data: &mut Data, key: &str) -> crate::Result<Row> {
+        if data.get(key).map_or(false, Row::is_expired) {
+            data.remove(key);
+        }
+        data.get(key)
+            .filter(|row| !row.is_deleted())
+            .cloned()
+            .ok_or(crate::Error::key_not_found(key))
+    }
+
+    pub fn insert(&self, key: &str, value: &str) -> crate::Result<()> {
+        self.limits.check(key, value.len())?;
+        self.lock().and_then(|mut data| {
+            if data.get(key).map_or(false, Row::is_live) {
+                Err(crate::Error::duplicate_key(key))
+            } else {
+                data.insert(key.to_string(), Row::create(key, value));
+                Ok(())
+            }
+        })
+    }
+
+    pub fn insert_row(&self, row: &Row) -> crate::Result<()> {
+        self.limits.check_row(row)?;
+        self.lock().and_then(|mut data| {
+            if data.get(row.key()).map_or(false, Row::is_live) {
+                Err(crate::Error::duplicate_key(row.key()))
+            } else {
+                data.insert(row.key().to_string(), row.clone());
+                Ok(())
+            }
+        })
+    }
+
+    /// Sets the value of `key`, inserting a new row if it doesn't exist (or has
+    /// expired or been deleted). Returns a copy of the row it replaced, or
+    /// `None` if it inserted a new one.
+    pub fn set_or_insert(&self, key: &str, value: &str) -> crate::Result<Option<Row>> {
+        self.limits.check(key, value.len())?;
+        self.lock().map(|mut data| {
+            let mut previous = None;
+            data.entry(key.to_string())
+                .and_modify(|row| {
+                    if row.is_live() {
+                        previous = Some(row.clone());
+                        row.update(value);
+                    } else {
+                        *row = Row::create(key, value);
+                    }
+                })
+                .or_insert_with(|| Row::create(key, value));
+            previous
+        })
+    }
+
+    pub fn set_or_insert_row(&self, row: &Row) -> crate::Result<Option<Row>> {
+        self.limits.check_row(row)?;
+        self.lock().map(|mut data| {
+            let mut previous = None;
+            data.entry(row.key().to_string())
+                .and_modify(|v| {
+                    previous = v.is_live().then(|| v.clone());
+                    v.overwrite_with(row)
+                })
+                .or_insert_with(|| row.clone());
+            previous
+        })
+    }
+
+    pub fn contains(&self, key: &str) -> crate::Result<bool> {
+        self.lock()
+            .map(|data| data.get(key).map_or(false, Row::is_live))
+    }
+
+    /// Gets the number of rows in this store, not counting deleted rows.
+    pub fn len(&self) -> crate::Result<usize> {
+        self.lock()
+            .map(|data| data.values().filter(|row| !row.is_deleted()).count())
+    }
+
+    /// Removes every row from this store, returning how many there were (not
+    /// counting deleted rows, like `len`).
+    pub fn clear(&self) -> crate::Result<usize> {
+        self.lock().map(|mut data| {
+            std::mem::take(&mut *data)
+                .into_values()
+                .filter(|row| !row.is_deleted())
+                .count()
+        })
+    }
+
+    /// Checks whether this store has no rows, not counting deleted rows.
+    pub fn is_empty(&self) -> crate::Result<bool> {
+        self.len().map(|len| len == 0)
+    }
+
+    pub fn delete(&self, key: &str) -> crate::Result<Row> {
+        self.lock().and_then(|mut data| {
+            data.remove(key)
+                .filter(|row| !row.is_deleted())
+                .ok_or(crate::Error::key_not_found(key))
+        })
+    }
+
+    /// Runs `f` on the live row for `key` and stores the result, returning a
+    /// copy of it. The store's mutex is held while `f` runs, so `f` must not
+    /// use the store: calling it from inside `f` deadlocks. Fails with
+    /// [`crate::Error::KeyNotFound`] if there is no live row, or if `f` changes
+    /// the key or breaks this store's limits, in which case nothing is stored.
+    pub fn update_with<F: FnOnce(&mut Row)>(&self, key: &str, f: F) -> crate::Result<Row> {
+        self.lock().and_then(|mut data| {
+            let row = self.limits.modify(Self::get_locked(&mut data, key)?, f)?;
+            data.insert(key.to_string(), row.clone());
+            Ok(row)
+        })
+    }
+
+    /// Same as `update_with`, but when `key` has no live row `f` is run on the
+    /// row made by `default` (which must be for `key`) instead.
+    pub fn upsert_with<D, F>(&self, key: &str, default: D, f: F) -> crate::Result<Row>
+    where
+        D: FnOnce() -> Row,
+        F: FnOnce(&mut Row),
+    {
+        self.lock().and_then(|mut data| {
+            let row = upsert_base(Self::get_locked(&mut data, key).ok(), key, default)?;
+            let row = self.limits.modify(row, f)?;
+            data.insert(key.to_string(), row.clone());
+            Ok(row)
+        })
+    }
+
+    /// Sets the value of `key` to `new` if it currently holds `expected`, all
+    /// under a single lock.
+    pub fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: &str,
+        new: &str,
+    ) -> crate::Result<CasOutcome> {
+        self.limits.check(key, new.len())?;
+        self.lock()
+            .map(|mut data| CasOutcome::swap(data.get_mut(key), expected, new))
+    }
+
+    /// Deletes the row for `key` if it currently holds `expected`, all under a
+    /// single lock.
+    pub fn compare_and_delete(&self, key: &str, expected: &str) -> crate::Result<CasOutcome> {
+        self.lock().map(|mut data| {
+            let outcome = CasOutcome::check(data.get(key), expected);
+            if outcome.is_swapped() {
+                data.remove(key);
+            }
+            outcome
+        })
+    }
+
+    /// Moves the live row for `old_key` to `new_key`, keeping its `created`
+    /// timestamp but bumping `updated`, all under a single lock. Fails with
+    /// [`crate::Error::KeyNotFound`] if there is no live row for `old_key`, and
+    /// with [`crate::Error::DuplicateKey`] if `new_key` already has one.
+    pub fn rename(&self, old_key: &str, new_key: &str) -> crate::Result<()> {
+        self.rename_inner(old_key, new_key, false).map(|_| ())
+    }
+
+    /// Same as `rename`, but any live row for `new_key` is replaced and
+    /// returned instead.
+    pub fn rename_overwrite(&self, old_key: &str, new_key: &str) -> crate::Result<Option<Row>> {
+        self.rename_inner(old_key, new_key, true)
+    }
+
+    fn rename_inner(
+        &self,
+        old_key: &str,
+        new_key: &str,
+        overwrite: bool,
+    ) -> crate::Result<Option<Row>> {
+        self.limits.check(new_key, 0)?;
+        self.lock().and_then(|mut data| {
+            let mut row = Self::get_locked(&mut data, old_key)?;
+            let replaced = Self::get_locked(&mut data, new_key).ok();
+            if replaced.is_some() && !overwrite {
+                return Err(crate::Error::duplicate_key(new_key));
+            }
+            data.remove(old_key);
+            row.rename(new_key);
+            data.insert(new_key.to_string(), row);
+            Ok(replaced.filter(|_| old_key != new_key))
+        })
+    }
+
+    /// Deletes the rows for all of `keys` under a single lock, or none of them
+    /// if any key has no row, see `KeyValueStore::delete_many`.
+    pub fn delete_many(&self, keys: &[&str]) -> crate::Result<Vec<Row>> {
+        self.lock().and_then(|mut data| {
+            let mut seen = HashSet::with_capacity(keys.len());
+            for &key in keys {
+                if !seen.insert(key) || !data.get(key).map_or(false, |row| !row.is_deleted()) {
+                    return Err(crate::Error::key_not_found(key));
+                }
+            }
+            Ok(keys.iter().filter_map(|&key| data.remove(key)).collect())
+        })
+    }
+
+    /// Same as `delete_many`, but keys without a row are skipped and listed in
+    /// the returned report instead of aborting the batch.
+    pub fn try_delete_many(&self, keys: &[&str]) -> crate::Result<DeleteManyReport> {
+        self.lock().map(|mut data| {
+            let mut report = DeleteManyReport::default();
+            for &key in keys {
+                match data.remove(key).filter(|row| !row.is_deleted()) {
+                    Some(row) => report.deleted.push(row),
+                    None => report.missing.push(key.to_string()),
+                }
+            }
+            report
+        })
+    }
+
+    /// Deletes every live row matching `predicate`, returning the deleted rows
+    /// sorted by key. The store stays locked while `predicate` runs, so it must
+    /// not use the store itself.
+    pub fn delete_matching<F: Fn(&Row) -> bool>(&self, predicate: F) -> crate::Result<Vec<Row>> {
+        self.lock().map(|mut data| {
+            let keys = data
+                .values()
+                .filter(|row| row.is_live() && predicate(row))
+                .map(|row| row.key().to_string())
+                .collect::<Vec<_>>();
+            keys.iter().filter_map(|key| data.remove(key)).collect()
+        })
+    }
+
+    /// Removes every row for which `predicate` returns false, returning how
+    /// many were removed. Deleted rows are left alone and aren't passed to
+    /// `predicate`.
+    ///
+    /// The store stays locked while `predicate` runs, so it must not use the
+    /// store itself. If `predicate` panics the panic is passed on to the
+    /// caller, the rows it already rejected stay removed and the rest are
+    /// kept; the lock is released without being poisoned, so the store can
+    /// still be used.
+    pub fn retain<F: Fn(&str, &Row) -> bool>(&self, predicate: F) -> crate::Result<usize> {
+        self.lock().map(|data| {
+            run_unpoisoned(data, |data| {
+                let before = data.len();
+                data.retain(|key, row| row.is_deleted() || predicate(key, row));
+                before - data.len()
+            })
+        })
+    }
+
+    /// Gets the keys of every live (unexpired and not deleted) row, sorted.
+    pub fn keys(&self) -> crate::Result<Vec<String>> {
+        self.lock().map(|data| {
+            data.iter()
+                .filter(|(_, row)| row.is_live())
+                .map(|(key, _)| key.clone())
+                .collect()
+        })
+    }
+
+    /// Same as `keys`, which are already sorted.
+    pub fn keys_sorted(&self) -> crate::Result<Vec<String>> {
+        self.keys()
+    }
+
+    /// Gets every live (unexpired and not deleted) row, sorted by key.
+    pub fn rows(&self) -> crate::Result<Vec<Row>> {
+        self.lock()
+            .map(|data| data.values().filter(|row| row.is_live()).cloned().collect())
+    }
+
+    /// Gets every live row with a key in `from..to`, that is from `from`
+    /// inclusive up to `to` exclusive, sorted by key. An empty or backwards
+    /// range gives no rows.
+    pub fn range(&self, from: &str, to: &str) -> crate::Result<Vec<Row>> {
+        if from >= to {
+            return Ok(Vec::new());
+        }
+        self.lock().map(|data| {
+            data.range::<str, _>((Bound::Included(from), Bound::Excluded(to)))
+                .map(|(_, row)| row)
+                .filter(|row| row.is_live())
+                .cloned()
+                .collect()
+        })
+    }
+
+    /// Gets the live row with the smallest key, if there is one.
+    pub fn first(&self) -> crate::Result<Option<Row>> {
+        self.lock()
+            .map(|data| data.values().find(|row| row.is_live()).cloned())
+    }
+
+    /// Gets the live row with the largest key, if there is one.
+    pub fn last(&self) -> crate::Result<Option<Row>> {
+        self.lock()
+            .map(|data| data.values().rev().find(|row| row.is_live()).cloned())
+    }
+
+    /// Gets the keys of every live row starting with `prefix`, sorted. Only
+    /// the rows in the matching range are visited.
+    pub fn keys_with_prefix(&self, prefix: &str) -> crate::Result<Vec<String>> {
+        self.lock().map(|data| {
+            Self::prefix_range(&data, prefix)
+                .map(|row| row.key().to_string())
+                .collect()
+        })
+    }
+
+    /// Gets every live row whose key starts with `prefix`, sorted by key.
+    pub fn scan_prefix(&self, prefix: &str) -> crate::Result<Vec<Row>> {
+        self.lock()
+            .map(|data| Self::prefix_range(&data, prefix).cloned().collect())
+    }
+
+    /// Counts the live rows whose key starts with `prefix`.
+    pub fn count_prefix(&self, prefix: &str) -> crate::Result<usize> {
+        self.lock()
+            .map(|data| Self::prefix_range(&data, prefix).count())
+    }
+
+    fn prefix_range<'a>(data: &'a Data, prefix: &'a str) -> impl Iterator<Item = &'a Row> {
+        data.range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(move |(key, _)| key.starts_with(prefix))
+            .map(|(_, row)| row)
+            .filter(|row| row.is_live())
+    }
+
+    pub fn to_bytes(&self) -> crate::Result<Vec<u8>> {
+        self.lock()
+            .and_then(|data| serde_json::to_vec(&*data).map_err(|err| crate::Error::json_ser(&err)))
+    }
+
+    /// Loads a store previously written with `to_bytes`, dropping any rows that
+    /// have expired since.
+    pub fn from_bytes(bytes: &[u8]) -> crate::Result<Self> {
+        serde_json::from_slice(bytes)
+            .map_err(|err| crate::Error::json_de(&err))
+            .map(|mut data: Data| {
+                data.retain(|_, row| !row.is_expired());
+                Self {
+                    data: Mutex::new(data),
+                    ..Self::default()
+                }
+            })
+    }
+
+    pub fn to_disk(&self) -> crate::Result<StoreDiskRepr> {
+        self.lock()
+            .map(|data| StoreDiskRepr::from_iter(data.values().map(RowDiskRepr::from)))
+    }
+
+    pub fn into_disk(self) -> crate::Result<StoreDiskRepr> {
+        let data = self.data.into_inner().unwrap_or_else(|e| e.into_inner());
+        Ok(StoreDiskRepr::from_iter(
+            data.into_values().map(RowDiskRepr::from),
+        ))
+    }
+
+    /// Loads a store from `disk`, dropping any rows that have expired. See
+    /// `KeyValueStore::from_disk` for the ways this can fail.
+    pub fn from_disk(disk: &StoreDiskRepr) -> crate::Result<Self> {
+        let data = disk
+            .rows_by_key()?
+            .into_iter()
+            .filter(|(_, row)| !row.is_expired())
+            .collect();
+        Ok(Self {
+            data: Mutex::new(data),
+            ..Self::default()
+        })
+    }
+
+    /// Same as `to_disk`.
+    pub fn to_disk_repr(&self) -> crate::Result<StoreDiskRepr> {
+        self.to_disk()
+    }
+}
+
+impl super::Store for BTreeStore {
+    fn get_clone(&self, key: &str) -> crate::Result<Row> {
+        BTreeStore::get_clone(self, key)
+    }
+
+    fn get_many(&self, keys: &[&str]) -> crate::Result<Vec<(String, crate::Result<Row>)>> {
+        BTreeStore::get_many(self, keys)
+    }
+
+    fn insert(&self, key: &str, value: &str) -> crate::Result<()> {
+        BTreeStore::insert(self, key, value)
+    }
+
+    fn insert_row(&self, row: &Row) -> crate::Result<()> {
+        BTreeStore::insert_row(self, row)
+    }
+
+    fn set_or_insert(&self, key: &str, value: &str) -> crate::Result<Option<Row>> {
+        BTreeStore::set_or_insert(self, key, value)
+    }
+
+    fn set_or_insert_row(&self, row: &Row) -> crate::Result<Option<Row>> {
+        BTreeStore::set_or_insert_row(self, row)
+    }
+
+    fn update_with(&self, key: &str, f: &mut dyn FnMut(&mut Row)) -> crate::Result<Row> {
+        BTreeStore::update_with(self, key, f)
+    }
+
+    fn upsert_with(
+        &self,
+        key: &str,
+        default: &mut dyn FnMut() -> Row,
+        f: &mut dyn FnMut(&mut Row),
+    ) -> crate::Result<Row> {
+        BTreeStore::upsert_with(self, key, default, f)
+    }
+
+    fn contains(&self, key: &str) -> crate::Result<bool> {
+        BTreeStore::contains(self, key)
+    }
+
+    fn len(&self) -> crate::Result<usize> {
+        BTreeStore::len(self)
+    }
+
+    fn clear(&self) -> crate::Result<usize> {
+        BTreeStore::clear(self)
+    }
+
+    fn delete(&self, key: &str) -> crate::Result<Row> {
+        BTreeStore::delete(self, key)
+    }
+
+    fn compare_and_swap(&self, key: &str, expected: &str, new: &str) -> crate::Result<CasOutcome> {
+        BTreeStore::compare_and_swap(self, key, expected, new)
+    }
+
+    fn compare_and_delete(&self, key: &str, expected: &str) -> crate::Result<CasOutcome> {
+        BTreeStore::compare_and_delete(self, key, expected)
+    }
+
+    fn rename(&self, old_key: &str, new_key: &str) -> crate::Result<()> {
+        BTreeStore::rename(self, old_key, new_key)
+    }
+
+    fn rename_overwrite(&self, old_key: &str, new_key: &str) -> crate::Result<Option<Row>> {
+        BTreeStore::rename_overwrite(self, old_key, new_key)
+    }
+
+    fn retain(&self, predicate: &dyn Fn(&str, &Row) -> bool) -> crate::Result<usize> {
+        BTreeStore::retain(self, predicate)
+    }
+
+    fn delete_many(&self, keys: &[&str]) -> crate::Result<Vec<Row>> {
+        BTreeStore::delete_many(self, keys)
+    }
+
+    fn try_delete_many(&self, keys: &[&str]) -> crate::Result<DeleteManyReport> {
+        BTreeStore::try_delete_many(self, keys)
+    }
+
+    fn delete_matching(&self, predicate: &dyn Fn(&Row) -> bool) -> crate::Result<Vec<Row>> {
+        BTreeStore::delete_matching(self, predicate)
+    }
+
+    fn keys(&self) -> crate::Result<Vec<String>> {
+        BTreeStore::keys(self)
+    }
+
+    fn rows(&self) -> crate::Result<Vec<Row>> {
+        BTreeStore::rows(self)
+    }
+
+    fn keys_with_prefix(&self, prefix: &str) -> crate::Result<Vec<String>> {
+        BTreeStore::keys_with_prefix(self, prefix)
+    }
+
+    fn scan_prefix(&self, prefix: &str) -> crate::Result<Vec<Row>> {
+        BTreeStore::scan_prefix(self, prefix)
+    }
+
+    fn count_prefix(&self, prefix: &str) -> crate::Result<usize> {
+        BTreeStore::count_prefix(self, prefix)
+    }
+
+    fn to_disk_repr(&self) -> crate::Result<StoreDiskRepr> {
+        BTreeStore::to_disk_repr(self)
+    }
+
+    fn to_bytes(&self) -> crate::Result<Vec<u8>> {
+        BTreeStore::to_bytes(self)
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "BTreeStore"
+    }
+}
+
+impl super::StoreFactory for BTreeStore {
+    fn from_bytes(bytes: &[u8]) -> crate::Result<Self> {
+        BTreeStore::from_bytes(bytes)
+    }
+
+    fn from_disk(disk: &StoreDiskRepr) -> crate::Result<Self> {
+        BTreeStore::from_disk(disk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{test_helpers, KeyValueStore, Store};
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn keys(rows: &[Row]) -> Vec<&str> {
+        rows.iter().map(Row::key).collect()
+    }
+
+    #[test]
+    fn it_works() {
+        let store = test_helpers::store_with::<BTreeStore>(&[("b", "2"), ("a", "1"), ("c", "3")]);
+        assert_eq!(store.get_clone("a").unwrap().value(), "1");
+        assert_eq!(
+            store.insert("a", "x"),
+            Err(crate::Error::duplicate_key("a"))
+        );
+        assert_eq!(
+            test_helpers::set_outcome(&store, "a", "x"),
+            Ok(SetOutcome::Updated)
+        );
+        assert_eq!(
+            test_helpers::set_outcome(&store, "a", "x"),
+            Ok(SetOutcome::Unchanged)
+        );
+        assert_eq!(
+            test_helpers::set_outcome(&store, "d", "4"),
+            Ok(SetOutcome::Inserted)
+        );
+        assert_eq!(
+            store.keys(),
+            Ok(vec![
+                "a".to_string(),
+                "b".to_string(),
+                "c".to_string(),
+                "d".to_string()
+            ])
+        );
+        assert_eq!(store.delete("b").unwrap().key(), "b");
+        assert_eq!(store.delete("b"), Err(crate::Error::key_not_found("b")));
+        assert_eq!(store.len(), Ok(3));
+    }
+
+    #[test]
+    fn range_boundaries() {
+        let store = test_helpers::store_with::<BTreeStore>(&[
+            ("a", "1"),
+            ("b", "2"),
+            ("b1", "3"),
+            ("c", "4"),
+            ("d", "5"),
+        ]);
+        assert_eq!(keys(&store.range("b", "d").unwrap()), vec!["b", "b1", "c"]);
+        assert_eq!(keys(&store.range("a", "b").unwrap()), vec!["a"]);
+        assert_eq!(keys(&store.range("", "zzz").unwrap()).len(), 5);
+        assert_eq!(keys(&store.range("aa", "b0").unwrap()), vec!["b"]);
+        assert_eq!(store.range("c", "c"), Ok(vec![]));
+        assert_eq!(store.range("d", "a"), Ok(vec![]));
+        assert_eq!(store.range("x", "z"), Ok(vec![]));
+    }
+
+    #[test]
+    fn first_and_last() {
+        let store = BTreeStore::empty();
+        assert_eq!(store.first(), Ok(None));
+        assert_eq!(store.last(), Ok(None));
+
+        let store = test_helpers::store_with::<BTreeStore>(&[("m", "1"), ("z", "2"), ("b", "3")]);
+        assert!(store
+            .insert_row(&Row::create_with_ttl("a", "expired", -1))
+            .is_ok());
+        assert_eq!(store.first().unwrap().unwrap().key(), "b");
+        assert_eq!(store.last().unwrap().unwrap().key(), "z");
+    }
+
+    #[test]
+    fn prefix_scans_use_the_range() {
+        let store = test_helpers::store_with::<BTreeStore>(&[
+            ("user:1", "a"),
+            ("user:1:name", "b"),
+            ("user:2", "c"),
+            ("userx", "d"),
+            ("ключ", "e"),
+        ]);
+        assert_eq!(
+            store.keys_with_prefix("user:"),
+            Ok(vec![
+                "user:1".to_string(),
+                "user:1:name".to_string(),
+                "user:2".to_string()
+            ])
+        );
+        assert_eq!(store.count_prefix(""), Ok(5));
+        assert_eq!(store.count_prefix("user:1"), Ok(2));
+        assert_eq!(store.count_prefix("zzz"), Ok(0));
+        assert_eq!(keys(&store.scan_prefix("клю").unwrap()), vec!["ключ"]);
+    }
+
+    #[test]
+    fn check_fill() {
+        for vals in [100, 200] {
+            let store: BTreeStore = test_helpers::fill_single_thread(vals);
+            assert_eq!(store.len(), Ok(vals));
+        }
+
+        for threads in 1..=8 {
+            for vals in [100, 1000] {
+                let store: BTreeStore = test_helpers::fill_multi_thread(vals, threads);
+                assert_eq!(
+                    store.len(),
+                    Ok(vals),
+                    "check_fill (v = {} t = {}) - did not add the expected number of values",
+                    vals,
+                    threads
+                );
+                let keys = store.keys().unwrap();
+                assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+            }
+        }
+    }
+
+    #[test]
+    fn bytes_roundtrip() {
+        let store: BTreeStore = test_helpers::fill_single_thread(50);
+        let loaded = BTreeStore::from_bytes(&store.to_bytes().unwrap()).unwrap();
+        assert_eq!(loaded.rows(), store.rows());
+        assert!(BTreeStore::from_bytes(b"not json").is_err());
+    }
+
+    #[test]
+    fn disk_interchange_with_key_value_store() {
+        let store: BTreeStore = test_helpers::fill_single_thread(100);
+        let disk = store.to_disk().unwrap();
+        let kv = KeyValueStore::from_disk(&disk).unwrap();
+        assert_eq!(kv.keys_sorted(), store.keys());
+
+        assert!(kv.set_or_insert("key5", "changed").is_ok());
+        let back = BTreeStore::from_disk(&kv.to_disk().unwrap()).unwrap();
+        assert_eq!(back.len(), Ok(100));
+        assert_eq!(back.get_clone("key5").unwrap().value(), "changed");
+        assert!(back.into_disk().unwrap().data.len() == 100);
+
+        let dyn_store: &dyn Store = &store;
+        assert_eq!(dyn_store.to_disk_repr().unwrap().data.len(), 100);
+    }
+
+    #[test]
+    fn shared_suite() {
+        test_helpers::check_store::<BTreeStore>();
+    }
+}
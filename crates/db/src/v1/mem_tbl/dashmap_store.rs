@@ -10,15 +10,32 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::{collections::HashMap, sync::Mutex};
-
-use dashmap::DashMap;
-
-use crate::{Row, RowDiskRepr, StoreByteRepr, StoreDiskRepr};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+
+use super::{
+    checked_apply, largest_rows, sample_with, upsert_base, CasOutcome, DeleteManyReport,
+    EntryChange, EntryRef, EntryState, KeyPattern, StoreLimits, StoreSize, StoreStats, Subscribers,
+};
+use crate::{
+    Op, OpResult, Row, RowDiskRepr, RowEvent, SetOutcome, StoreByteRepr, StoreDiskRepr,
+    StoreIntoIter, StoreSnapshot, StoreStatsSnapshot,
+};
 
 #[derive(Debug, Default)]
 pub struct DashStore {
     data: DashMap<String, Row>,
+    limits: StoreLimits,
+    stats: StoreStats,
+    events: Subscribers,
+    read_only: AtomicBool,
 }
 
 impl DashStore {
@@ -26,70 +43,1081 @@ impl DashStore {
         Self::default()
     }
 
+    /// Creates an empty store that rejects keys longer than `max_key_bytes` and
+    /// values larger than `max_value_bytes`. Stores created any other way have
+    /// no limits.
+    pub fn with_limits(max_key_bytes: usize, max_value_bytes: usize) -> Self {
+        Self {
+            limits: StoreLimits::new(max_key_bytes, max_value_bytes),
+            ..Self::default()
+        }
+    }
+
+    /// Makes this store reject every write with [`crate::Error::ReadOnly`]
+    /// while reads keep working, or lets writes through again, see
+    /// `KeyValueStore::set_read_only`. Each write checks the flag again once
+    /// it holds its key's shard, so a write still waiting for the shard when
+    /// the flag flips is rejected, but writes still running may complete.
+    /// Writes over many keys (like `insert_many` or `clear`) go one key or
+    /// shard at a time, so they can fail part-way with some of their changes
+    /// already made.
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::SeqCst);
+    }
+
+    /// Checks whether this store is currently rejecting writes, see
+    /// `set_read_only`.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::SeqCst)
+    }
+
+    /// Fails with [`crate::Error::ReadOnly`] if this store is read-only. Every
+    /// method that changes the store calls this before touching the map, and
+    /// again once it holds the shard it's changing.
+    fn check_writable(&self) -> crate::Result<()> {
+        if self.is_read_only() {
+            Err(crate::Error::ReadOnly)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Write-locks the shard holding `key`, then fails with
+    /// [`crate::Error::ReadOnly`] if the store was made read-only while this
+    /// was waiting for the lock.
+    fn entry(&self, key: &str) -> crate::Result<Entry<'_, String, Row>> {
+        let entry = self.data.entry(key.to_string());
+        self.check_writable()?;
+        Ok(entry)
+    }
+
+    /// Runs `DashMap::retain` with `f`. If the store is made read-only
+    /// part-way through, every row left is kept and this fails with
+    /// [`crate::Error::ReadOnly`], but the shards already visited stay
+    /// changed.
+    fn retain_writable<F>(&self, mut f: F) -> crate::Result<()>
+    where
+        F: FnMut(&String, &mut Row) -> bool,
+    {
+        let mut stopped = false;
+        self.data.retain(|key, row| {
+            stopped = stopped || self.is_read_only();
+            stopped || f(key, row)
+        });
+        if stopped {
+            Err(crate::Error::ReadOnly)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Creates an empty store that holds at most `max_rows` rows, see
+    /// `KeyValueStore::with_capacity_limit`.
+    ///
+    /// There is no store-wide lock, so the limit is checked before the key's
+    /// shard is locked and inserts racing on other threads can take the store
+    /// a few rows past it.
+    pub fn with_capacity_limit(max_rows: usize) -> Self {
+        Self {
+            limits: StoreLimits::with_max_rows(max_rows),
+            ..Self::default()
+        }
+    }
+
+    /// Gets how many more rows this store can take before it reaches its
+    /// capacity limit, or `None` if it has no limit (the default).
+    pub fn remaining_capacity(&self) -> crate::Result<Option<usize>> {
+        Ok(self.limits.remaining_capacity(self.data.len()))
+    }
+
+    /// Checks whether this store holds the same live rows as `other`, see
+    /// `KeyValueStore::content_eq`. Neither store is locked as a whole, so
+    /// writes racing the comparison may or may not be seen.
+    pub fn content_eq(&self, other: &Self, ignore_timestamps: bool) -> crate::Result<bool> {
+        let ours = self.rows()?;
+        let theirs = other.rows()?;
+        Ok(super::rows_equal(&ours, &theirs, ignore_timestamps))
+    }
+
+    /// Fails with [`crate::Error::CapacityExceeded`] if storing a row for each
+    /// of `keys` would take this store past its capacity limit. Must not be
+    /// called while holding a reference into `data`, as it locks every shard.
+    fn check_capacity<'k>(&self, keys: impl IntoIterator<Item = &'k str>) -> crate::Result<()> {
+        self.limits
+            .check_capacity(|| self.data.len(), keys, |key| self.data.contains_key(key))
+    }
+
+    /// Gets a copy of the row for `key`. Expired rows are removed and reported
+    /// as not found, as are rows that have been deleted.
     pub fn get_clone(&self, key: &str) -> crate::Result<Row> {
-        self.data
+        let result = self.lookup(key);
+        self.stats.get(&result);
+        result
+    }
+
+    fn lookup(&self, key: &str) -> crate::Result<Row> {
+        let row = self
+            .data
             .get(key)
             .map(|r| r.clone())
-            .ok_or(crate::Error::key_not_found(key))
+            .ok_or(crate::Error::key_not_found(key))?;
+        if row.is_expired() {
+            self.data
+                .remove_if(key, |_, row| row.is_expired() && !self.is_read_only());
+            return Err(crate::Error::key_not_found(key));
+        }
+        if row.is_deleted() {
+            return Err(crate::Error::key_not_found(key));
+        }
+        Ok(row)
     }
 
-    pub fn insert(&self, key: &str, value: &str) -> crate::Result<()> {
-        if self.data.contains_key(key) {
-            return Err(crate::Error::duplicate_key(key));
+    /// Gets a copy of the row for each of `keys`, in order, with missing keys
+    /// answered by [`crate::Error::KeyNotFound`]. There is no store-wide lock,
+    /// so unlike `KeyValueStore::get_many` the rows are not read atomically.
+    pub fn get_many(&self, keys: &[&str]) -> crate::Result<Vec<(String, crate::Result<Row>)>> {
+        Ok(keys
+            .iter()
+            .map(|&key| (key.to_string(), self.get_clone(key)))
+            .collect())
+    }
+
+    /// Same as `get_many`, but only returns the rows that were found.
+    pub fn get_existing(&self, keys: &[&str]) -> crate::Result<Vec<Row>> {
+        Ok(keys
+            .iter()
+            .filter_map(|&key| self.get_clone(key).ok())
+            .collect())
+    }
+
+    /// Gets the live row for `key`, or else inserts a row holding the value made
+    /// by `make` and returns that. The key's shard stays write-locked
+    /// throughout, so two racing callers both get the same row and `make` only
+    /// runs for the one that inserts it.
+    pub fn get_or_insert_with<F: FnOnce() -> String>(
+        &self,
+        key: &str,
+        make: F,
+    ) -> crate::Result<Row> {
+        self.check_writable()?;
+        self.limits.check(key, 0)?;
+        self.check_capacity([key])?;
+        let entry = self.entry(key)?;
+        if let Entry::Occupied(entry) = &entry {
+            if entry.get().is_live() {
+                self.stats.get_hit();
+                return Ok(entry.get().clone());
+            }
         }
+        self.stats.get_miss();
+        let value = make();
+        self.limits.check(key, value.len())?;
+        let row = entry.insert(Row::create(key, &value));
+        self.stats.inserted(1);
+        self.events.inserted(&row);
+        Ok(row.clone())
+    }
 
-        self.data.insert(key.to_string(), Row::create(key, value));
-        Ok(())
+    /// Same as `get_or_insert_with`, with a value that's already been made.
+    pub fn get_or_insert(&self, key: &str, value: &str) -> crate::Result<Row> {
+        self.get_or_insert_with(key, || value.to_string())
+    }
+
+    pub fn insert(&self, key: &str, value: &str) -> crate::Result<()> {
+        self.check_writable()?;
+        self.limits.check(key, value.len())?;
+        self.check_capacity([key])?;
+        self.insert_entry(Row::create(key, value))
     }
 
     pub fn insert_row(&self, row: &Row) -> crate::Result<()> {
-        if self.data.contains_key(&row.key) {
-            return Err(crate::Error::duplicate_key(row.key()));
+        self.check_writable()?;
+        self.limits.check_row(row)?;
+        self.check_capacity([row.key()])?;
+        self.insert_entry(row.clone())
+    }
+
+    /// Inserts every pair. Every key, and the room left in the store, is
+    /// checked before anything is inserted, so a batch that fails is never
+    /// partially applied, but there is no store-wide lock so other threads can
+    /// still race it.
+    pub fn insert_many(&self, pairs: &[(&str, &str)]) -> crate::Result<usize> {
+        self.check_writable()?;
+        for &(key, value) in pairs {
+            self.limits.check(key, value.len())?;
+        }
+        let mut seen = HashSet::with_capacity(pairs.len());
+        for &(key, _) in pairs {
+            if !seen.insert(key) || self.contains(key)? {
+                return Err(crate::Error::duplicate_key(key));
+            }
+        }
+        self.check_capacity(pairs.iter().map(|&(key, _)| key))?;
+        for &(key, value) in pairs {
+            self.insert_entry(Row::create(key, value))?;
         }
+        Ok(pairs.len())
+    }
 
-        self.data.insert(row.key().to_string(), row.clone());
-        Ok(())
+    fn insert_entry(&self, row: Row) -> crate::Result<()> {
+        match self.entry(row.key())? {
+            Entry::Occupied(entry) if entry.get().is_live() => {
+                Err(crate::Error::duplicate_key(row.key()))
+            }
+            entry => {
+                let row = entry.insert(row);
+                self.stats.inserted(1);
+                self.events.inserted(&row);
+                Ok(())
+            }
+        }
     }
 
-    pub fn set_or_insert(&self, key: &str, value: &str) -> crate::Result<()> {
-        self.data
-            .entry(key.to_string())
-            .and_modify(|row| row.update(value))
-            .or_insert(Row::create(key, value));
-        Ok(())
+    /// Sets the value of `key`, inserting a new row if it doesn't exist (or has
+    /// expired or been deleted). Returns a copy of the row it replaced, or
+    /// `None` if it inserted a new one.
+    pub fn set_or_insert(&self, key: &str, value: &str) -> crate::Result<Option<Row>> {
+        self.check_writable()?;
+        self.limits.check(key, value.len())?;
+        self.check_capacity([key])?;
+        let mut outcome = SetOutcome::Inserted;
+        let mut previous = None;
+        let row = self
+            .entry(key)?
+            .and_modify(|row| {
+                if !row.is_live() {
+                    *row = Row::create(key, value);
+                    return;
+                }
+                previous = Some(row.clone());
+                if row.update(value) {
+                    outcome = SetOutcome::Updated;
+                } else {
+                    outcome = SetOutcome::Unchanged;
+                }
+            })
+            .or_insert_with(|| Row::create(key, value));
+        self.stats.set(outcome);
+        if outcome != SetOutcome::Unchanged {
+            self.events.replaced(previous.as_ref(), &row);
+        }
+        Ok(previous)
     }
 
-    pub fn set_or_insert_row(&self, row: &Row) -> crate::Result<()> {
-        self.data
-            .entry(row.key().to_string())
-            .and_modify(|v| v.overwrite_with(row))
+    pub fn set_or_insert_row(&self, row: &Row) -> crate::Result<Option<Row>> {
+        self.check_writable()?;
+        self.limits.check_row(row)?;
+        self.check_capacity([row.key()])?;
+        let mut previous = None;
+        let row = self
+            .entry(row.key())?
+            .and_modify(|v| {
+                previous = v.is_live().then(|| v.clone());
+                v.overwrite_with(row)
+            })
             .or_insert(row.clone());
-        Ok(())
+        match previous {
+            Some(_) => self.stats.updated(1),
+            None => self.stats.inserted(1),
+        }
+        self.events.replaced(previous.as_ref(), &row);
+        Ok(previous)
+    }
+
+    /// Adds `delta` to the number stored under `key` and returns the result,
+    /// see `KeyValueStore::increment`. The key's shard stays write-locked
+    /// throughout, so concurrent calls never lose an update.
+    pub fn increment(&self, key: &str, delta: i64) -> crate::Result<i64> {
+        self.apply_delta(key, '+', delta)
+    }
+
+    /// Same as `increment`, but subtracts `delta` instead.
+    pub fn decrement(&self, key: &str, delta: i64) -> crate::Result<i64> {
+        self.apply_delta(key, '-', delta)
+    }
+
+    /// Adds (`op` is `'+'`) or subtracts (`'-'`) `delta` for `increment` and
+    /// `decrement`.
+    fn apply_delta(&self, key: &str, op: char, delta: i64) -> crate::Result<i64> {
+        self.check_writable()?;
+        self.limits.check(key, 0)?;
+        self.check_capacity([key])?;
+        let entry = self.entry(key)?;
+        let value = match &entry {
+            Entry::Occupied(entry) if entry.get().is_live() => entry.get().parse_value::<i64>()?,
+            _ => 0,
+        };
+        let next = checked_apply(value, op, delta)
+            .ok_or_else(|| crate::Error::overflow(key, value, op, delta))?;
+        let next_value = next.to_string();
+        self.limits.check(key, next_value.len())?;
+        match entry {
+            Entry::Occupied(mut entry) if entry.get().is_live() => {
+                let before = self.events.is_watched().then(|| entry.get().clone());
+                if entry.get_mut().update(&next_value) {
+                    self.stats.updated(1);
+                    self.events.updated(before, entry.get());
+                }
+            }
+            entry => {
+                let row = entry.insert(Row::create(key, &next_value));
+                self.stats.inserted(1);
+                self.events.inserted(&row);
+            }
+        }
+        Ok(next)
     }
 
     pub fn contains(&self, key: &str) -> crate::Result<bool> {
-        Ok(self.data.contains_key(key))
+        Ok(self.data.get(key).map_or(false, |row| row.is_live()))
     }
 
+    /// Gets the number of rows in this store, not counting deleted rows.
     pub fn len(&self) -> crate::Result<usize> {
-        Ok(self.data.len())
+        Ok(self.data.iter().filter(|row| !row.is_deleted()).count())
+    }
+
+    /// Removes every row from this store, returning how many there were (not
+    /// counting deleted rows, like `len`).
+    ///
+    /// Shards are cleared one at a time, so readers running alongside just
+    /// start missing rows, and rows inserted meanwhile may or may not survive.
+    pub fn clear(&self) -> crate::Result<usize> {
+        self.check_writable()?;
+        let mut removed = 0;
+        self.retain_writable(|_, row| {
+            if !row.is_deleted() {
+                removed += 1;
+            }
+            false
+        })?;
+        Ok(removed)
+    }
+
+    /// Checks whether this store has no rows, not counting deleted rows.
+    pub fn is_empty(&self) -> crate::Result<bool> {
+        self.len().map(|len| len == 0)
+    }
+
+    /// Same as `len`, but rows that have expired are not counted either.
+    pub fn len_unexpired(&self) -> crate::Result<usize> {
+        Ok(self.data.iter().filter(|row| row.is_live()).count())
+    }
+
+    /// Estimates how much memory the rows of this store take up, counting
+    /// every row it holds, including deleted and expired rows.
+    pub fn memory_usage(&self) -> crate::Result<StoreSize> {
+        Ok(self
+            .data
+            .iter()
+            .fold(StoreSize::default(), |mut size, row| {
+                size.add(&row);
+                size
+            }))
+    }
+
+    /// Gets the `n` live rows with the largest values, largest first. Rows
+    /// with values of the same size are ordered by key.
+    pub fn largest_rows(&self, n: usize) -> crate::Result<Vec<Row>> {
+        let rows = self
+            .data
+            .iter()
+            .map(|row| row.value().clone())
+            .collect::<Vec<_>>();
+        Ok(largest_rows(&rows, n))
+    }
+
+    /// Gets the number of reads, writes and deletes run against this store
+    /// since it was created or `reset_stats` was last called, see
+    /// `KeyValueStore::stats`.
+    pub fn stats(&self) -> StoreStatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    /// Sets every counter reported by `stats` back to zero.
+    pub fn reset_stats(&self) {
+        self.stats.reset()
+    }
+
+    /// Subscribes to the changes made to this store, see
+    /// `KeyValueStore::subscribe`. Events are sent while the changed row's
+    /// shard is locked, so the events for any one key arrive in order, but
+    /// there is no order between keys in different shards.
+    pub fn subscribe(&self) -> std::sync::mpsc::Receiver<RowEvent> {
+        self.events.subscribe()
+    }
+
+    /// Gets the keys of every live (unexpired and not deleted) row, in no
+    /// particular order. The map's shards are visited one at a time, so writes
+    /// made while this runs may or may not be included.
+    pub fn keys(&self) -> crate::Result<Vec<String>> {
+        Ok(self
+            .data
+            .iter()
+            .filter(|entry| entry.value().is_live())
+            .map(|entry| entry.key().clone())
+            .collect())
+    }
+
+    /// Same as `keys`, but sorted.
+    pub fn keys_sorted(&self) -> crate::Result<Vec<String>> {
+        let mut keys = self.keys()?;
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// Picks the key of a live row at random, or `None` if there are none.
+    ///
+    /// This picks one of the map's non-empty shards at random and then a live
+    /// row within it, so only one shard is scanned, but keys aren't all equally
+    /// likely: a key in a shard holding few rows is picked more often than one
+    /// in a crowded shard. Use `sample(1)` for an unbiased pick.
+    pub fn random_key(&self) -> crate::Result<Option<String>> {
+        let shards = self.data.shards();
+        let mut candidates = (0..shards.len())
+            .filter(|&i| !shards[i].read().is_empty())
+            .collect::<Vec<_>>();
+        fastrand::shuffle(&mut candidates);
+        for i in candidates {
+            let shard = shards[i].read();
+            let keys = shard
+                .iter()
+                .filter(|(_, row)| row.get().is_live())
+                .map(|(key, _)| key);
+            if let Some(key) = sample_with(keys, 1, String::clone).pop() {
+                return Ok(Some(key));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Picks up to `n` distinct live rows at random, in random order, with
+    /// the same caveat about concurrent writes as `keys`. Every row is equally
+    /// likely to be picked, and only the picked rows are copied. Asking for at
+    /// least as many rows as the store holds gets all of them.
+    pub fn sample(&self, n: usize) -> crate::Result<Vec<Row>> {
+        let rows = self.data.iter().filter(|entry| entry.value().is_live());
+        Ok(sample_with(rows, n, |entry| entry.value().clone()))
+    }
+
+    /// Gets every live (unexpired and not deleted) row, with the same caveat
+    /// about concurrent writes as `keys`.
+    pub fn rows(&self) -> crate::Result<Vec<Row>> {
+        Ok(self
+            .data
+            .iter()
+            .filter(|entry| entry.value().is_live())
+            .map(|entry| entry.value().clone())
+            .collect())
+    }
+
+    /// Gets the keys of every live row starting with `prefix`, sorted. This is
+    /// a linear scan over the whole map, with the same caveat about concurrent
+    /// writes as `keys`.
+    pub fn keys_with_prefix(&self, prefix: &str) -> crate::Result<Vec<String>> {
+        let mut keys = self
+            .data
+            .iter()
+            .filter(|entry| entry.key().starts_with(prefix) && entry.value().is_live())
+            .map(|entry| entry.key().clone())
+            .collect::<Vec<_>>();
+        keys.sort();
+        Ok(keys)
     }
 
+    /// Gets every live row whose key starts with `prefix`, sorted by key. This
+    /// is a linear scan over the whole map.
+    pub fn scan_prefix(&self, prefix: &str) -> crate::Result<Vec<Row>> {
+        let mut rows = self
+            .data
+            .iter()
+            .filter(|entry| entry.key().starts_with(prefix) && entry.value().is_live())
+            .map(|entry| entry.value().clone())
+            .collect::<Vec<_>>();
+        rows.sort();
+        Ok(rows)
+    }
+
+    /// Gets the keys of every live row matching the glob-style `pattern`,
+    /// sorted, see `KeyValueStore::find_keys`. This is a linear scan over the
+    /// whole map.
+    pub fn find_keys(&self, pattern: &str) -> crate::Result<Vec<String>> {
+        self.find_keys_limited(pattern, usize::MAX)
+    }
+
+    /// Same as `find_keys`, but stops scanning once `max` keys have matched.
+    /// Only those keys are sorted, so which `max` keys are returned is
+    /// unspecified when more than that match.
+    pub fn find_keys_limited(&self, pattern: &str, max: usize) -> crate::Result<Vec<String>> {
+        let pattern = KeyPattern::new(pattern);
+        let mut keys = self
+            .data
+            .iter()
+            .filter(|entry| entry.value().is_live() && pattern.matches(entry.key()))
+            .map(|entry| entry.key().clone())
+            .take(max)
+            .collect::<Vec<_>>();
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// Gets every live row whose key matches the glob-style `pattern`, sorted
+    /// by key, see `find_keys`.
+    pub fn find_rows(&self, pattern: &str) -> crate::Result<Vec<Row>> {
+        let pattern = KeyPattern::new(pattern);
+        let mut rows = self
+            .data
+            .iter()
+            .filter(|entry| entry.value().is_live() && pattern.matches(entry.key()))
+            .map(|entry| entry.value().clone())
+            .collect::<Vec<_>>();
+        rows.sort();
+        Ok(rows)
+    }
+
+    /// Counts the live rows whose key starts with `prefix`. This is a linear
+    /// scan over the whole map.
+    pub fn count_prefix(&self, prefix: &str) -> crate::Result<usize> {
+        Ok(self
+            .data
+            .iter()
+            .filter(|entry| entry.key().starts_with(prefix) && entry.value().is_live())
+            .count())
+    }
+
+    /// Calls `f` with every live (unexpired and not deleted) row, stopping as
+    /// soon as it returns `false`. Returns whether every row was visited.
+    ///
+    /// Each shard of the map is read-locked while its rows are visited, so `f`
+    /// may read from this store but writing to it can deadlock. Like `keys`,
+    /// writes made by other threads while this runs may or may not be seen.
+    pub fn try_for_each<F: FnMut(&Row) -> bool>(&self, mut f: F) -> crate::Result<bool> {
+        Ok(self
+            .data
+            .iter()
+            .filter(|entry| entry.value().is_live())
+            .all(|entry| f(entry.value())))
+    }
+
+    /// Calls `f` with every live row, see `try_for_each`.
+    pub fn for_each<F: FnMut(&Row)>(&self, mut f: F) -> crate::Result<()> {
+        self.try_for_each(|row| {
+            f(row);
+            true
+        })
+        .map(|_| ())
+    }
+
+    /// Folds every live row into an accumulator, see `try_for_each`.
+    pub fn fold<B, F: FnMut(B, &Row) -> B>(&self, init: B, mut f: F) -> crate::Result<B> {
+        Ok(self
+            .data
+            .iter()
+            .filter(|entry| entry.value().is_live())
+            .fold(init, |acc, entry| f(acc, entry.value())))
+    }
+
+    /// Counts the live rows matching `predicate`, see `try_for_each`.
+    pub fn count_where<F: FnMut(&Row) -> bool>(&self, mut predicate: F) -> crate::Result<usize> {
+        self.fold(0, |count, row| count + usize::from(predicate(row)))
+    }
+
+    /// Gets every live row matching `predicate`, sorted by key, see
+    /// `try_for_each`.
+    pub fn find<F: Fn(&Row) -> bool>(&self, predicate: F) -> crate::Result<Vec<Row>> {
+        let mut rows = self.fold(Vec::new(), |mut rows, row| {
+            if predicate(row) {
+                rows.push(row.clone());
+            }
+            rows
+        })?;
+        rows.sort();
+        Ok(rows)
+    }
+
+    /// Gets the first live row the scan reaches that matches `predicate`,
+    /// which could be any of them, and stops scanning there. See
+    /// `try_for_each`.
+    pub fn find_first<F: Fn(&Row) -> bool>(&self, predicate: F) -> crate::Result<Option<Row>> {
+        let mut found = None;
+        self.try_for_each(|row| {
+            if predicate(row) {
+                found = Some(row.clone());
+            }
+            found.is_none()
+        })?;
+        Ok(found)
+    }
+
+    /// Checks whether any live row holds `value`, stopping at the first one.
+    /// This is a linear scan over the whole map.
+    pub fn contains_value(&self, value: &str) -> crate::Result<bool> {
+        self.find_first(|row| row.value() == value)
+            .map(|row| row.is_some())
+    }
+
+    /// Gets the keys of every live row holding `value`, sorted. This is a
+    /// linear scan over the whole map.
+    pub fn keys_with_value(&self, value: &str) -> crate::Result<Vec<String>> {
+        let mut keys = self.fold(Vec::new(), |mut keys, row| {
+            if row.value() == value {
+                keys.push(row.key().to_string());
+            }
+            keys
+        })?;
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// Removes the row for `key` and returns it. Subscribers are sent the
+    /// deleted row while its shard is still locked, just before it's removed,
+    /// since the lock is released as soon as the row is.
     pub fn delete(&self, key: &str) -> crate::Result<Row> {
-        self.data
-            .remove(key)
-            .map(|r| r.1)
-            .ok_or(crate::Error::key_not_found(key))
+        self.check_writable()?;
+        let result = match self.entry(key)? {
+            Entry::Occupied(entry) => {
+                self.events.deleted(entry.get());
+                Ok(entry.remove())
+            }
+            Entry::Vacant(_) => Err(crate::Error::key_not_found(key)),
+        };
+        self.stats.delete(&result);
+        result
+    }
+
+    /// Removes the live row for `key` and returns it, failing with
+    /// [`crate::Error::KeyNotFound`] if there is none. Same as `take_if` with
+    /// a predicate that always passes.
+    pub fn take(&self, key: &str) -> crate::Result<Row> {
+        self.take_if(key, |_| true)?
+            .ok_or_else(|| crate::Error::key_not_found(key))
+    }
+
+    /// Removes the live row for `key` and returns it if `predicate` passes for
+    /// it, or returns `None` and leaves it in place if it doesn't. Fails with
+    /// [`crate::Error::KeyNotFound`] if there is no live row.
+    ///
+    /// The row's shard stays write-locked from checking the row until it's
+    /// removed, so when several threads take the same key only one of them
+    /// gets it. `predicate` must not use the store, as that can deadlock on
+    /// the locked shard.
+    pub fn take_if<P>(&self, key: &str, predicate: P) -> crate::Result<Option<Row>>
+    where
+        P: FnOnce(&Row) -> bool,
+    {
+        self.check_writable()?;
+        let result = match self.entry(key)? {
+            Entry::Occupied(entry) if entry.get().is_live() => {
+                if predicate(entry.get()) {
+                    self.events.deleted(entry.get());
+                    Ok(Some(entry.remove()))
+                } else {
+                    Ok(None)
+                }
+            }
+            _ => Err(crate::Error::key_not_found(key)),
+        };
+        if !matches!(result, Ok(None)) {
+            self.stats.delete(&result);
+        }
+        result
+    }
+
+    /// Bumps the `updated` timestamp of the live row for `key` with
+    /// [`Row::touch`], without changing its value or version, and returns the
+    /// new timestamp. Subscribers see this as an update.
+    pub fn touch(&self, key: &str) -> crate::Result<i64> {
+        self.check_writable()?;
+        let mut row = self
+            .data
+            .get_mut(key)
+            .filter(|row| row.is_live())
+            .ok_or_else(|| crate::Error::key_not_found(key))?;
+        self.check_writable()?;
+        let before = self.events.is_watched().then(|| row.clone());
+        row.touch();
+        self.events.updated(before, &row);
+        Ok(row.updated())
+    }
+
+    /// Calls `touch` for each of `keys`, returning the result for each key in
+    /// order. Missing keys are answered by [`crate::Error::KeyNotFound`]
+    /// without stopping the rest. Each key is locked separately, see
+    /// `get_many`.
+    pub fn touch_many(&self, keys: &[&str]) -> crate::Result<Vec<(String, crate::Result<i64>)>> {
+        self.check_writable()?;
+        Ok(keys
+            .iter()
+            .map(|&key| (key.to_string(), self.touch(key)))
+            .collect())
+    }
+
+    /// Runs `f` with an [`EntryRef`] for `key`, through which it can check
+    /// whether the key has a live row, read it, and set, insert or delete it,
+    /// and returns what `f` returned. The key's shard stays write-locked from
+    /// reading the row until `f`'s changes have been applied, so no other
+    /// write to the key can come between them.
+    ///
+    /// Like `upsert_with`, this fails with [`crate::Error::CapacityExceeded`]
+    /// before running `f` if the store is full and `key` has no row. `f` must
+    /// not use the store: calling it from inside `f` can deadlock on the
+    /// locked shard.
+    pub fn with_entry<R, F>(&self, key: &str, f: F) -> crate::Result<R>
+    where
+        F: FnOnce(EntryRef<'_>) -> R,
+    {
+        self.check_writable()?;
+        self.limits.check(key, 0)?;
+        self.check_capacity([key])?;
+        let entry = self.entry(key)?;
+        let current = match &entry {
+            Entry::Occupied(entry) => Some(entry.get()).filter(|row| row.is_live()).cloned(),
+            Entry::Vacant(_) => None,
+        };
+        let before = self.events.is_watched().then(|| current.clone()).flatten();
+        let mut state = EntryState::new(current);
+        let result = f(EntryRef::new(key, &mut state, &self.limits));
+        match state.into_change() {
+            EntryChange::Unchanged => {}
+            EntryChange::Set { row, inserted } => {
+                let stored = entry.insert(row);
+                if inserted {
+                    self.stats.inserted(1);
+                    self.events.inserted(&stored);
+                } else {
+                    self.stats.updated(1);
+                    self.events.updated(before, &stored);
+                }
+            }
+            EntryChange::Deleted => {
+                if let Entry::Occupied(entry) = entry {
+                    self.events.deleted(entry.get());
+                    entry.remove();
+                    self.stats.deleted(1);
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Runs `f` on the live row for `key` and stores the result, returning a
+    /// copy of it. The row's shard stays write-locked while `f` runs, so `f`
+    /// must not use the store: calling it from inside `f` can deadlock on the
+    /// locked shard. Fails with [`crate::Error::KeyNotFound`] if there is no
+    /// live row, or if `f` changes the key or breaks this store's limits, in
+    /// which case nothing is stored.
+    pub fn update_with<F: FnOnce(&mut Row)>(&self, key: &str, f: F) -> crate::Result<Row> {
+        self.check_writable()?;
+        match self.entry(key)? {
+            Entry::Occupied(mut entry) if entry.get().is_live() => {
+                let row = self.limits.modify(entry.get().clone(), f)?;
+                let before = entry.insert(row.clone());
+                self.stats.updated(1);
+                self.events.updated(Some(before), &row);
+                Ok(row)
+            }
+            _ => Err(crate::Error::key_not_found(key)),
+        }
+    }
+
+    /// Same as `update_with`, but when `key` has no live row `f` is run on the
+    /// row made by `default` (which must be for `key`) instead.
+    pub fn upsert_with<D, F>(&self, key: &str, default: D, f: F) -> crate::Result<Row>
+    where
+        D: FnOnce() -> Row,
+        F: FnOnce(&mut Row),
+    {
+        self.check_writable()?;
+        self.check_capacity([key])?;
+        let entry = self.entry(key)?;
+        let current = match &entry {
+            Entry::Occupied(entry) => Some(entry.get()).filter(|row| row.is_live()).cloned(),
+            Entry::Vacant(_) => None,
+        };
+        let before = self.events.is_watched().then(|| current.clone()).flatten();
+        let inserting = current.is_none();
+        let row = self.limits.modify(upsert_base(current, key, default)?, f)?;
+        let stored = entry.insert(row.clone());
+        if inserting {
+            self.stats.inserted(1);
+            self.events.inserted(&stored);
+        } else {
+            self.stats.updated(1);
+            self.events.updated(before, &stored);
+        }
+        Ok(row)
+    }
+
+    /// Sets the value of `key` to `new` if it currently holds `expected`. The
+    /// row's shard stays write-locked for the whole comparison.
+    pub fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: &str,
+        new: &str,
+    ) -> crate::Result<CasOutcome> {
+        self.check_writable()?;
+        self.limits.check(key, new.len())?;
+        Ok(match self.entry(key)? {
+            Entry::Occupied(mut entry) => {
+                let before = self.events.is_watched().then(|| entry.get().clone());
+                let outcome = CasOutcome::swap(Some(entry.get_mut()), expected, new);
+                if outcome.is_swapped() {
+                    self.stats.updated(1);
+                    self.events.updated(before, entry.get());
+                }
+                outcome
+            }
+            Entry::Vacant(_) => CasOutcome::KeyMissing,
+        })
+    }
+
+    /// Deletes the row for `key` if it currently holds `expected`, see
+    /// `compare_and_swap`.
+    pub fn compare_and_delete(&self, key: &str, expected: &str) -> crate::Result<CasOutcome> {
+        self.check_writable()?;
+        Ok(match self.entry(key)? {
+            Entry::Occupied(entry) => {
+                let outcome = CasOutcome::check(Some(entry.get()), expected);
+                if outcome.is_swapped() {
+                    self.events.deleted(entry.get());
+                    entry.remove();
+                    self.stats.deleted(1);
+                }
+                outcome
+            }
+            Entry::Vacant(_) => CasOutcome::KeyMissing,
+        })
+    }
+
+    /// Moves the live row for `old_key` to `new_key`, keeping its `created`
+    /// timestamp but bumping `updated`. Fails with
+    /// [`crate::Error::KeyNotFound`] if there is no live row for `old_key`, and
+    /// with [`crate::Error::DuplicateKey`] if `new_key` already has one.
+    ///
+    /// The two keys may share a shard, so they can't both be locked at once.
+    /// Instead the row is taken out of `old_key` first and put back if
+    /// `new_key` turns out to be taken, which means readers can briefly miss
+    /// the row under both keys.
+    pub fn rename(&self, old_key: &str, new_key: &str) -> crate::Result<()> {
+        self.rename_inner(old_key, new_key, false).map(|_| ())
+    }
+
+    /// Same as `rename`, but any live row for `new_key` is replaced and
+    /// returned instead.
+    pub fn rename_overwrite(&self, old_key: &str, new_key: &str) -> crate::Result<Option<Row>> {
+        self.rename_inner(old_key, new_key, true)
+    }
+
+    fn rename_inner(
+        &self,
+        old_key: &str,
+        new_key: &str,
+        overwrite: bool,
+    ) -> crate::Result<Option<Row>> {
+        self.check_writable()?;
+        self.limits.check(new_key, 0)?;
+        if old_key == new_key && !overwrite {
+            return self
+                .get_clone(old_key)
+                .and(Err(crate::Error::duplicate_key(new_key)));
+        }
+        let row = match self
+            .data
+            .remove_if(old_key, |_, row| row.is_live() && !self.is_read_only())
+        {
+            Some((_, row)) => row,
+            None => {
+                self.check_writable()?;
+                return Err(crate::Error::key_not_found(old_key));
+            }
+        };
+        let mut renamed = row.clone();
+        renamed.rename(new_key);
+        let entry = match self.entry(new_key) {
+            Ok(entry) => entry,
+            Err(err) => {
+                // Put the row back, unless `old_key` was written meanwhile.
+                self.data.entry(old_key.to_string()).or_insert(row);
+                return Err(err);
+            }
+        };
+        match entry {
+            Entry::Occupied(entry) if !overwrite && entry.get().is_live() => {
+                drop(entry);
+                // Anything written to `old_key` in the meantime wins.
+                self.data.entry(old_key.to_string()).or_insert(row);
+                Err(crate::Error::duplicate_key(new_key))
+            }
+            Entry::Occupied(mut entry) => {
+                let replaced = entry.insert(renamed);
+                self.events.deleted(&row);
+                self.events.replaced(Some(&replaced), entry.get());
+                Ok(Some(replaced).filter(|row| row.is_live() && old_key != new_key))
+            }
+            Entry::Vacant(entry) => {
+                let renamed = entry.insert(renamed);
+                self.events.deleted(&row);
+                self.events.inserted(&renamed);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Deletes the rows for all of `keys`, returning them in the order they
+    /// were given. If any key has no row (or is listed more than once) nothing
+    /// is deleted and that key is reported as [`crate::Error::KeyNotFound`].
+    ///
+    /// Every key is checked before anything is deleted, but the map isn't
+    /// locked as a whole, so a concurrent delete can still fail the batch
+    /// part-way through.
+    pub fn delete_many(&self, keys: &[&str]) -> crate::Result<Vec<Row>> {
+        self.check_writable()?;
+        let mut seen = HashSet::with_capacity(keys.len());
+        for &key in keys {
+            if !seen.insert(key) || !self.contains(key)? {
+                self.stats.delete_missed(1);
+                return Err(crate::Error::key_not_found(key));
+            }
+        }
+        keys.iter().map(|&key| self.delete(key)).collect()
+    }
+
+    /// Same as `delete_many`, but keys without a row are skipped and listed in
+    /// the returned report instead of aborting the batch.
+    pub fn try_delete_many(&self, keys: &[&str]) -> crate::Result<DeleteManyReport> {
+        self.check_writable()?;
+        let mut report = DeleteManyReport::default();
+        for &key in keys {
+            match self.delete(key) {
+                Ok(row) => report.deleted.push(row),
+                Err(_) => report.missing.push(key.to_string()),
+            }
+        }
+        Ok(report)
+    }
+
+    /// Always fails with [`crate::Error::Unsupported`]. Applying a transaction
+    /// atomically needs a lock over every key it touches, and `DashMap` only
+    /// locks one shard at a time; use `KeyValueStore::apply_transaction`
+    /// instead.
+    pub fn apply_transaction(&self, _ops: &[Op]) -> crate::Result<Vec<OpResult>> {
+        Err(crate::Error::unsupported("apply_transaction"))
+    }
+
+    /// Deletes every live row matching `predicate`, returning the deleted rows
+    /// in no particular order. Each shard is write-locked while `predicate`
+    /// runs, so it must not use the store itself.
+    pub fn delete_matching<F: Fn(&Row) -> bool>(&self, predicate: F) -> crate::Result<Vec<Row>> {
+        self.check_writable()?;
+        let mut deleted = Vec::new();
+        let result = self.retain_writable(|_, row| {
+            if row.is_live() && predicate(row) {
+                self.events.deleted(row);
+                deleted.push(row.clone());
+                false
+            } else {
+                true
+            }
+        });
+        self.stats.deleted(deleted.len());
+        result.map(|()| deleted)
+    }
+
+    /// Removes every row for which `predicate` returns false, returning how
+    /// many were removed. Deleted rows are left alone and aren't passed to
+    /// `predicate`.
+    ///
+    /// Each shard stays write-locked while `predicate` runs over its rows, so
+    /// `predicate` must not use the store itself. If `predicate` panics the
+    /// panic is passed on to the caller and the rows it already rejected stay
+    /// removed. `DashMap`'s locks aren't poisoned by a panic, so the store can
+    /// still be used.
+    pub fn retain<F: Fn(&str, &Row) -> bool>(&self, predicate: F) -> crate::Result<usize> {
+        self.check_writable()?;
+        let mut dropped = 0;
+        self.retain_writable(|key, row| {
+            let keep = row.is_deleted() || predicate(key, row);
+            if !keep {
+                dropped += 1;
+            }
+            keep
+        })?;
+        Ok(dropped)
+    }
+
+    /// Removes every row last updated before `since` (in milliseconds, like
+    /// `Row::updated`), returning how many were removed.
+    pub fn retain_updated_since(&self, since: i64) -> crate::Result<usize> {
+        self.retain(|_, row| row.updated() >= since)
+    }
+
+    /// Gets every live row last updated at or after `ts` (in milliseconds,
+    /// like `Row::updated`), sorted by key. A row updated exactly at `ts` is
+    /// included, so passing the time of the last backup never misses a row.
+    pub fn rows_updated_since(&self, ts: i64) -> crate::Result<Vec<Row>> {
+        let mut rows = self
+            .data
+            .iter()
+            .filter(|entry| entry.value().is_live() && entry.value().updated() >= ts)
+            .map(|entry| entry.value().clone())
+            .collect::<Vec<_>>();
+        rows.sort();
+        Ok(rows)
+    }
+
+    /// Gets every live row created strictly before `ts`, sorted by key. A row
+    /// created exactly at `ts` isn't included.
+    pub fn rows_created_before(&self, ts: i64) -> crate::Result<Vec<Row>> {
+        let mut rows = self
+            .data
+            .iter()
+            .filter(|entry| entry.value().is_live() && entry.value().created() < ts)
+            .map(|entry| entry.value().clone())
+            .collect::<Vec<_>>();
+        rows.sort();
+        Ok(rows)
+    }
+
+    /// Deletes every live row last updated strictly before `ts`, returning the
+    /// deleted rows sorted by key. A row updated exactly at `ts` is kept, so
+    /// this removes exactly the rows `rows_updated_since(ts)` leaves out.
+    /// Unlike `retain_updated_since` the rows go through `delete_matching`.
+    pub fn purge_older_than(&self, ts: i64) -> crate::Result<Vec<Row>> {
+        let mut rows = self.delete_matching(|row| row.updated() < ts)?;
+        rows.sort();
+        Ok(rows)
     }
 
     pub fn to_bytes(&self) -> crate::Result<Vec<u8>> {
         serde_json::to_vec(&self.data).map_err(|err| crate::Error::json_ser(&err))
     }
 
+    /// Loads a store previously written with `to_bytes`, dropping any rows that
+    /// have expired since.
     pub fn from_bytes(bytes: &[u8]) -> crate::Result<Self> {
         serde_json::from_slice(bytes)
             .map_err(|err| crate::Error::json_de(&err))
-            .map(|data| Self { data })
+            .map(|data: DashMap<String, Row>| {
+                data.retain(|_, row| !row.is_expired());
+                Self {
+                    data,
+                    ..Self::default()
+                }
+            })
+    }
+
+    /// Copies every row of this store into a [`StoreSnapshot`]. There is no
+    /// store-wide lock, so unlike `KeyValueStore::snapshot` the snapshot is
+    /// only consistent within each shard: writes made while it's being taken
+    /// may show up for some keys and not others.
+    pub fn snapshot(&self) -> crate::Result<StoreSnapshot> {
+        Ok(StoreSnapshot::new(
+            self.data
+                .iter()
+                .map(|entry| (entry.key().clone(), entry.value().clone()))
+                .collect(),
+        ))
     }
 
     pub fn to_disk(&self) -> crate::Result<StoreDiskRepr> {
@@ -104,8 +1132,35 @@ impl DashStore {
         Ok(disk)
     }
 
+    /// Loads a store from `disk`, dropping any rows that have expired. See
+    /// `KeyValueStore::from_disk` for the ways this can fail.
     pub fn from_disk(disk: &StoreDiskRepr) -> crate::Result<Self> {
-        todo!()
+        let data = disk
+            .rows_by_key()?
+            .into_iter()
+            .filter(|(_, row)| !row.is_expired())
+            .collect();
+        Ok(Self {
+            data,
+            ..Self::default()
+        })
+    }
+
+    /// Same as `to_disk`.
+    pub fn to_disk_repr(&self) -> crate::Result<StoreDiskRepr> {
+        self.to_disk()
+    }
+}
+
+/// Deep-copies the rows of a store, one shard at a time, along with its limits.
+/// The copy starts with fresh stats and has no subscribers.
+impl Clone for DashStore {
+    fn clone(&self) -> Self {
+        Self {
+            data: self.data.clone(),
+            limits: self.limits,
+            ..Self::default()
+        }
     }
 }
 
@@ -114,6 +1169,10 @@ impl super::Store for DashStore {
         DashStore::get_clone(self, key)
     }
 
+    fn get_many(&self, keys: &[&str]) -> crate::Result<Vec<(String, crate::Result<Row>)>> {
+        DashStore::get_many(self, keys)
+    }
+
     fn insert(&self, key: &str, value: &str) -> crate::Result<()> {
         DashStore::insert(self, key, value)
     }
@@ -122,14 +1181,31 @@ impl super::Store for DashStore {
         DashStore::insert_row(self, row)
     }
 
-    fn set_or_insert(&self, key: &str, value: &str) -> crate::Result<()> {
+    fn insert_many(&self, pairs: &[(&str, &str)]) -> crate::Result<usize> {
+        DashStore::insert_many(self, pairs)
+    }
+
+    fn set_or_insert(&self, key: &str, value: &str) -> crate::Result<Option<Row>> {
         DashStore::set_or_insert(self, key, value)
     }
 
-    fn set_or_insert_row(&self, row: &Row) -> crate::Result<()> {
+    fn set_or_insert_row(&self, row: &Row) -> crate::Result<Option<Row>> {
         DashStore::set_or_insert_row(self, row)
     }
 
+    fn update_with(&self, key: &str, f: &mut dyn FnMut(&mut Row)) -> crate::Result<Row> {
+        DashStore::update_with(self, key, f)
+    }
+
+    fn upsert_with(
+        &self,
+        key: &str,
+        default: &mut dyn FnMut() -> Row,
+        f: &mut dyn FnMut(&mut Row),
+    ) -> crate::Result<Row> {
+        DashStore::upsert_with(self, key, default, f)
+    }
+
     fn contains(&self, key: &str) -> crate::Result<bool> {
         DashStore::contains(self, key)
     }
@@ -138,154 +1214,279 @@ impl super::Store for DashStore {
         DashStore::len(self)
     }
 
+    fn remaining_capacity(&self) -> crate::Result<Option<usize>> {
+        DashStore::remaining_capacity(self)
+    }
+
+    fn clear(&self) -> crate::Result<usize> {
+        DashStore::clear(self)
+    }
+
+    fn set_read_only(&self, read_only: bool) -> crate::Result<()> {
+        DashStore::set_read_only(self, read_only);
+        Ok(())
+    }
+
+    fn is_read_only(&self) -> bool {
+        DashStore::is_read_only(self)
+    }
+
     fn delete(&self, key: &str) -> crate::Result<Row> {
         DashStore::delete(self, key)
     }
 
-    fn to_disk_repr(&self) -> crate::Result<StoreDiskRepr> {
-        DashStore::to_disk_repr(self)
+    fn touch(&self, key: &str) -> crate::Result<i64> {
+        DashStore::touch(self, key)
     }
-}
 
-impl<'s> FromIterator<(&'s str, Row)> for DashStore {
-    fn from_iter<T: IntoIterator<Item = (&'s str, Row)>>(iter: T) -> Self {
-        let mut data: DashMap<String, Row> =
-            iter.into_iter().map(|(s, r)| (s.to_string(), r)).collect();
-        Self { data }
+    fn touch_many(&self, keys: &[&str]) -> crate::Result<Vec<(String, crate::Result<i64>)>> {
+        DashStore::touch_many(self, keys)
     }
-}
 
-impl<'t, 's: 't> FromIterator<&'t (&'s str, Row)> for DashStore {
-    fn from_iter<T: IntoIterator<Item = &'t (&'s str, Row)>>(iter: T) -> Self {
-        let mut data: DashMap<String, Row> = iter
-            .into_iter()
-            .map(|(s, r)| (s.to_string(), r.clone()))
-            .collect();
-        Self { data }
+    fn compare_and_swap(&self, key: &str, expected: &str, new: &str) -> crate::Result<CasOutcome> {
+        DashStore::compare_and_swap(self, key, expected, new)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use pretty_assertions::{assert_eq, assert_ne};
+    fn compare_and_delete(&self, key: &str, expected: &str) -> crate::Result<CasOutcome> {
+        DashStore::compare_and_delete(self, key, expected)
+    }
 
-    mod helpers {
-        use super::super::*;
-        use pretty_assertions::{assert_eq, assert_ne};
+    fn rename(&self, old_key: &str, new_key: &str) -> crate::Result<()> {
+        DashStore::rename(self, old_key, new_key)
+    }
 
-        pub fn store_with(values: &[(&str, &str)]) -> DashStore {
-            let values = values.to_vec();
-            let mut store = DashStore::empty();
-            for &(key, value) in &values {
-                assert!(
-                    store.insert(key, value).is_ok(),
-                    "store_with - failed to insert ({}, {})",
-                    key,
-                    value
-                );
-            }
+    fn rename_overwrite(&self, old_key: &str, new_key: &str) -> crate::Result<Option<Row>> {
+        DashStore::rename_overwrite(self, old_key, new_key)
+    }
 
-            assert_eq!(
-                store.len().expect("store_with - failed to get length"),
-                values.len()
-            );
+    fn rows_updated_since(&self, ts: i64) -> crate::Result<Vec<Row>> {
+        DashStore::rows_updated_since(self, ts)
+    }
 
-            store
-        }
+    fn rows_created_before(&self, ts: i64) -> crate::Result<Vec<Row>> {
+        DashStore::rows_created_before(self, ts)
+    }
 
-        pub fn fill_single_thread(values: usize) -> DashStore {
-            let store = DashStore::empty();
-            for i in 0..values {
-                let key = format!("key{}", i);
-                let value = format!("value{}", i);
-                assert!(
-                    store.insert(key.as_str(), value.as_str()).is_ok(),
-                    "fill_single_thread - unable to insert ({},{})",
-                    key,
-                    value
-                );
-            }
+    fn purge_older_than(&self, ts: i64) -> crate::Result<Vec<Row>> {
+        DashStore::purge_older_than(self, ts)
+    }
 
-            assert_eq!(
-                store
-                    .len()
-                    .expect("fill_single_thread - unable to get length"),
-                values,
-                "fill_single_thread - did not add the expected number of values",
-            );
+    fn retain(&self, predicate: &dyn Fn(&str, &Row) -> bool) -> crate::Result<usize> {
+        DashStore::retain(self, predicate)
+    }
 
-            store
+    fn delete_many(&self, keys: &[&str]) -> crate::Result<Vec<Row>> {
+        DashStore::delete_many(self, keys)
+    }
+
+    fn try_delete_many(&self, keys: &[&str]) -> crate::Result<DeleteManyReport> {
+        DashStore::try_delete_many(self, keys)
+    }
+
+    fn delete_matching(&self, predicate: &dyn Fn(&Row) -> bool) -> crate::Result<Vec<Row>> {
+        DashStore::delete_matching(self, predicate)
+    }
+
+    fn keys(&self) -> crate::Result<Vec<String>> {
+        DashStore::keys(self)
+    }
+
+    fn rows(&self) -> crate::Result<Vec<Row>> {
+        DashStore::rows(self)
+    }
+
+    fn random_key(&self) -> crate::Result<Option<String>> {
+        DashStore::random_key(self)
+    }
+
+    fn sample(&self, n: usize) -> crate::Result<Vec<Row>> {
+        DashStore::sample(self, n)
+    }
+
+    fn keys_with_prefix(&self, prefix: &str) -> crate::Result<Vec<String>> {
+        DashStore::keys_with_prefix(self, prefix)
+    }
+
+    fn scan_prefix(&self, prefix: &str) -> crate::Result<Vec<Row>> {
+        DashStore::scan_prefix(self, prefix)
+    }
+
+    fn find_keys(&self, pattern: &str) -> crate::Result<Vec<String>> {
+        DashStore::find_keys(self, pattern)
+    }
+
+    fn find_rows(&self, pattern: &str) -> crate::Result<Vec<Row>> {
+        DashStore::find_rows(self, pattern)
+    }
+
+    fn contains_value(&self, value: &str) -> crate::Result<bool> {
+        DashStore::contains_value(self, value)
+    }
+
+    fn keys_with_value(&self, value: &str) -> crate::Result<Vec<String>> {
+        DashStore::keys_with_value(self, value)
+    }
+
+    fn find(&self, predicate: &dyn Fn(&Row) -> bool) -> crate::Result<Vec<Row>> {
+        DashStore::find(self, predicate)
+    }
+
+    fn find_first(&self, predicate: &dyn Fn(&Row) -> bool) -> crate::Result<Option<Row>> {
+        DashStore::find_first(self, predicate)
+    }
+
+    fn count_prefix(&self, prefix: &str) -> crate::Result<usize> {
+        DashStore::count_prefix(self, prefix)
+    }
+
+    fn memory_usage(&self) -> crate::Result<StoreSize> {
+        DashStore::memory_usage(self)
+    }
+
+    fn largest_rows(&self, n: usize) -> crate::Result<Vec<Row>> {
+        DashStore::largest_rows(self, n)
+    }
+
+    fn to_disk_repr(&self) -> crate::Result<StoreDiskRepr> {
+        DashStore::to_disk_repr(self)
+    }
+
+    fn to_bytes(&self) -> crate::Result<Vec<u8>> {
+        DashStore::to_bytes(self)
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "DashStore"
+    }
+}
+
+impl super::StoreFactory for DashStore {
+    fn from_bytes(bytes: &[u8]) -> crate::Result<Self> {
+        DashStore::from_bytes(bytes)
+    }
+
+    fn from_disk(disk: &StoreDiskRepr) -> crate::Result<Self> {
+        DashStore::from_disk(disk)
+    }
+}
+
+impl<'s> FromIterator<(&'s str, Row)> for DashStore {
+    fn from_iter<T: IntoIterator<Item = (&'s str, Row)>>(iter: T) -> Self {
+        let mut data: DashMap<String, Row> =
+            iter.into_iter().map(|(s, r)| (s.to_string(), r)).collect();
+        Self {
+            data,
+            ..Self::default()
         }
+    }
+}
 
-        pub fn fill_multi_thread(values: usize, threads: usize) -> DashStore {
-            use std::sync::Arc;
-            use std::thread;
-
-            if values == 0 {
-                eprintln!("fill_multi_thread - called with values = 0");
-                return DashStore::empty();
-            }
-
-            if threads < 2 {
-                return fill_single_thread(values);
-            }
-
-            let step_size = values / threads;
-            let store = Arc::new(DashStore::empty());
-            let mut ts = Vec::new();
-
-            for t in 0..(threads - 1) {
-                let clone = Arc::clone(&store);
-                let start = t * step_size;
-                let end = start + step_size;
-                // println!("Starting thread #{} with range {}..{}", t + 1, start, end);
-                ts.push(thread::spawn(move || {
-                    for i in start..end {
-                        let key = format!("key{}", i);
-                        let value = format!("value{}", i);
-                        assert!(
-                            clone.insert(key.as_str(), value.as_str()).is_ok(),
-                            "fill_multi_thread - T{} - unable to insert ({},{})",
-                            t + 1,
-                            key,
-                            value
-                        );
-                    }
-                    drop(clone);
-                }));
-            }
+impl<'t, 's: 't> FromIterator<&'t (&'s str, Row)> for DashStore {
+    fn from_iter<T: IntoIterator<Item = &'t (&'s str, Row)>>(iter: T) -> Self {
+        let mut data: DashMap<String, Row> = iter
+            .into_iter()
+            .map(|(s, r)| (s.to_string(), r.clone()))
+            .collect();
+        Self {
+            data,
+            ..Self::default()
+        }
+    }
+}
 
-            for i in ((threads - 1) * step_size)..values {
-                let key = format!("key{}", i);
-                let value = format!("value{}", i);
-                assert!(
-                    store.insert(key.as_str(), value.as_str()).is_ok(),
-                    "fill_multi_thread - unable to insert ({},{})",
-                    key,
-                    value
-                );
+impl FromIterator<(String, String)> for DashStore {
+    /// Builds a store with a new row for every key and value. If a key appears
+    /// more than once the last value wins.
+    fn from_iter<T: IntoIterator<Item = (String, String)>>(iter: T) -> Self {
+        let data = iter
+            .into_iter()
+            .map(|(key, value)| {
+                let row = Row::create(&key, &value);
+                (key, row)
+            })
+            .collect();
+        Self {
+            data,
+            ..Self::default()
+        }
+    }
+}
+
+impl IntoIterator for DashStore {
+    type Item = (String, Row);
+    type IntoIter = StoreIntoIter;
+
+    /// Takes every row out of this store except deleted ones, see
+    /// [`StoreIntoIter`].
+    fn into_iter(self) -> Self::IntoIter {
+        StoreIntoIter::new(self.data)
+    }
+}
+
+impl IntoIterator for &DashStore {
+    type Item = (String, Row);
+    type IntoIter = StoreIntoIter;
+
+    /// Iterates over copies of the rows in this store, taken with `snapshot`
+    /// so the store isn't locked while iterating.
+    ///
+    /// Panics if the snapshot can't be taken, see `snapshot`.
+    fn into_iter(self) -> Self::IntoIter {
+        self.snapshot()
+            .expect("unable to take a snapshot of the store")
+            .into_iter()
+    }
+}
+
+impl Extend<(String, String)> for DashStore {
+    /// Sets the value of every key with `set_or_insert`, inserting the keys
+    /// that don't exist yet.
+    ///
+    /// Panics if any of the writes fails, e.g. because it breaks this store's
+    /// limits; use `set_many` to get the error instead.
+    fn extend<T: IntoIterator<Item = (String, String)>>(&mut self, iter: T) {
+        for (key, value) in iter {
+            if let Err(err) = self.set_or_insert(&key, &value) {
+                panic!("unable to extend store with key '{}': {}", key, err);
             }
+        }
+    }
+}
 
-            let mut i = 1;
-            for handle in ts.into_iter() {
-                handle.join().expect(
-                    format!("fill_multi_thread - unable to join thread {}", i + 1).as_str(),
-                );
-                i += 1;
+impl Extend<Row> for DashStore {
+    /// Stores every row with `set_or_insert_row`, replacing any rows with the
+    /// same keys.
+    ///
+    /// Panics if any of the writes fails, see `Extend<(String, String)>`.
+    fn extend<T: IntoIterator<Item = Row>>(&mut self, iter: T) {
+        for row in iter {
+            if let Err(err) = self.set_or_insert_row(&row) {
+                panic!("unable to extend store with key '{}': {}", row.key(), err);
             }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::{assert_eq, assert_ne};
 
-            let inner: DashStore =
-                Arc::try_unwrap(store).expect("fill_multi_thread - unable to take inner store");
+    mod helpers {
+        use super::super::super::test_helpers;
+        pub use super::super::super::test_helpers::set_outcome;
+        use super::super::*;
 
-            assert_eq!(
-                inner
-                    .len()
-                    .expect("fill_multi_thread - unable to get length"),
-                values,
-                "fill_multi_thread - did not add the expected number of values",
-            );
-            inner
+        pub fn store_with(values: &[(&str, &str)]) -> DashStore {
+            test_helpers::store_with(values)
+        }
+
+        pub fn fill_single_thread(values: usize) -> DashStore {
+            test_helpers::fill_single_thread(values)
+        }
+
+        pub fn fill_multi_thread(values: usize, threads: usize) -> DashStore {
+            test_helpers::fill_multi_thread(values, threads)
         }
     }
 
@@ -300,108 +1501,20 @@ mod tests {
         let store: DashStore = data.iter().collect();
     }
 
-    #[test]
-    fn it_works() {
-        let store = DashStore::empty();
-        let result = store.contains("key");
-        assert!(result.is_ok());
-        assert!(!result.unwrap());
-        let result = store.insert("key", "value");
-        assert!(result.is_ok());
-        let result = store.contains("key");
-        assert!(result.is_ok());
-        assert!(result.unwrap());
-        let result = store.insert("key", "whoops");
-        assert!(result.is_err());
-        let result = store.set_or_insert("key", "whoops");
-        assert!(result.is_ok());
-        let result = store.get_clone("key");
-        assert!(result.is_ok());
-        let row = result.unwrap();
-        assert_eq!(row.value(), "whoops");
-        let result = store.len();
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 1);
-        let result = store.delete("key");
-        assert!(result.is_ok());
-        let result = store.delete("key");
-        assert!(result.is_err());
-    }
-
     #[test]
     fn byte_roundtrip() {
         let original = DashStore::empty();
         assert!(original.insert("key1", "value1").is_ok());
         assert!(original.insert("key2", "value2").is_ok());
         assert!(original.insert("key3", "value3").is_ok());
-        assert_eq!(
-            original
-                .len()
-                .expect("byte_roundtrip (original) - unable to get length"),
-            3
-        );
-        assert_eq!(
-            original
-                .get_clone("key1")
-                .expect("byte_roundtrip (original) - unable to get key1")
-                .value(),
-            "value1"
-        );
-        assert_eq!(
-            original
-                .get_clone("key2")
-                .expect("byte_roundtrip (original) - unable to get key2")
-                .value(),
-            "value2"
-        );
-        assert_eq!(
-            original
-                .get_clone("key3")
-                .expect("byte_roundtrip (original) - unable to get key3")
-                .value(),
-            "value3"
-        );
-        let result = original.to_bytes();
-        assert!(result.is_ok());
-        let bytes = result.unwrap();
+        let bytes = original
+            .to_bytes()
+            .expect("byte_roundtrip - unable to get bytes");
+
+        let clone = DashStore::from_bytes(&bytes).expect("byte_roundtrip - unable to load bytes");
+        assert_eq!(original.content_eq(&clone, false), Ok(true));
         assert!(original.insert("key4", "value4").is_ok());
-        assert_eq!(
-            original
-                .len()
-                .expect("byte_roundtrip (original) - unable to get length"),
-            4
-        );
-        let result = DashStore::from_bytes(&bytes);
-        assert!(result.is_ok());
-        let clone = result.unwrap();
-        assert_eq!(
-            clone
-                .len()
-                .expect("byte_roundtrip (clone) - unable to get length"),
-            3
-        );
-        assert_eq!(
-            clone
-                .get_clone("key1")
-                .expect("byte_roundtrip (clone) - unable to get key1")
-                .value(),
-            "value1"
-        );
-        assert_eq!(
-            clone
-                .get_clone("key2")
-                .expect("byte_roundtrip (clone) - unable to get key2")
-                .value(),
-            "value2"
-        );
-        assert_eq!(
-            clone
-                .get_clone("key3")
-                .expect("byte_roundtrip (clone) - unable to get key3")
-                .value(),
-            "value3"
-        );
-        assert!(clone.get_clone("key4").is_err());
+        assert_eq!(original.content_eq(&clone, true), Ok(false));
     }
 
     #[test]
@@ -743,52 +1856,1600 @@ mod tests {
     #[test]
     fn check_fill_multi_rng() {
         use helpers::fill_multi_thread;
-        use std::iter::repeat_with;
+        use utils::repeat_assert;
         const ITERS: usize = 25;
 
-        let mut i: usize = 0;
-        let mut next = move || {
-            i += 1;
-            i
-        };
+        repeat_assert!(ITERS, seed, |n, rng_seed| {
+            let rng = fastrand::Rng::with_seed(rng_seed);
+            let vals = rng.usize(100..40000);
+            let threads = rng.usize(1..10);
+            println!(
+                "Iteration #{} with {} values and {} threads",
+                n, vals, threads
+            );
+            let store = fill_multi_thread(vals, threads);
+            assert_eq!(
+                store
+                    .len()
+                    .expect("check_fill_multi_random - unable to get length"),
+                vals,
+                "check_fill_multi_random (v = {} t = {}) - did not add the expected number of values",
+                vals,
+                threads
+            );
+            let r = rng.usize(0..vals);
+            let key = format!("key{}", r);
+            let value = format!("value{}", r);
+            let row = store.get_clone(&key);
+            assert!(
+                row.is_ok(),
+                "check_fill_multi_random ({},{},{}) - unable to get value",
+                n,
+                vals,
+                threads
+            );
+            let row = row.unwrap();
+            assert_eq!(
+                row.key(),
+                key,
+                "check_fill_multi_random #{} - row {} does not have the expected key",
+                n,
+                r,
+            );
+            assert_eq!(
+                row.value(),
+                value,
+                "check_fill_multi_random #{} - row {} does not have the expected value",
+                n,
+                r,
+            );
+        });
+    }
 
-        let rand_v = || fastrand::usize(100..40000);
-        let rand_t = || fastrand::usize(1..10);
+    #[test]
+    fn expired_rows() {
+        let store = DashStore::empty();
+        assert!(store.insert("key1", "value1").is_ok());
+        assert!(store
+            .insert_row(&Row::create_with_ttl("key2", "value2", 0))
+            .is_ok());
+        assert!(store
+            .insert_row(&Row::create_with_ttl("key3", "value3", 3600))
+            .is_ok());
+
+        assert_eq!(store.len().unwrap(), 3);
+        assert_eq!(store.len_unexpired().unwrap(), 2);
+        assert!(!store.contains("key2").unwrap());
+        assert!(store.contains("key3").unwrap());
+        assert_eq!(
+            store.get_clone("key2"),
+            Err(crate::Error::key_not_found("key2"))
+        );
+        assert_eq!(store.len().unwrap(), 2, "expired row was not removed");
+        assert_eq!(store.get_clone("key3").unwrap().value(), "value3");
+    }
 
-        repeat_with(|| (next(), rand_v(), rand_t()))
-            .take(ITERS)
-            .for_each(|(n, vals, threads)| {
-                println!("Iteration #{} with {} values and {} threads", n, vals, threads);
-                let store = fill_multi_thread(vals, threads);
-                assert_eq!(
-                    store
-                        .len()
-                        .expect("check_fill_multi_random - unable to get length"),
-                    vals,
-                    "check_fill_multi_random (v = {} t = {}) - did not add the expected number of values",
-                    vals,
-                    threads
-                );
-                let r = fastrand::usize(0..vals);
-                let key = format!("key{}", r);
-                let value = format!("value{}", r);
-                let row = store.get_clone(&key);
-                assert!(row.is_ok(), "check_fill_multi_random ({},{},{}) - unable to get value", n, vals, threads);
-                let row = row.unwrap();
-                assert_eq!(
-                    row.key(),
-                    key,
-                    "check_fill_multi_random #{} - row {} does not have the expected key",
-                    n,
-                    r,
-                );
-                assert_eq!(
-                    row.value(),
-                    value,
-                    "check_fill_multi_random #{} - row {} does not have the expected value",
-                    n,
-                    r,
-                );
-            });
+    #[test]
+    fn set_or_insert_expired_row() {
+        let store = DashStore::empty();
+        let mut row = Row::new("key", "old", 100, 200);
+        row.expires_at = Some(300);
+        assert!(store.insert_row(&row).is_ok());
+        assert!(store.set_or_insert("key", "new").is_ok());
+
+        let row = store.get_clone("key").unwrap();
+        assert_eq!(row.value(), "new");
+        assert_eq!(row.expires_at(), None);
+        assert_eq!(row.version(), 1);
+        assert_ne!(row.created(), 100);
+    }
+
+    #[test]
+    fn ttl_roundtrip() {
+        let store = DashStore::empty();
+        let row = Row::create_with_ttl("key1", "value1", 3600);
+        assert!(store.insert_row(&row).is_ok());
+        assert!(store
+            .insert_row(&Row::create_with_ttl("key2", "value2", 0))
+            .is_ok());
+
+        let bytes = store.to_bytes().expect("unable to get bytes");
+        let loaded = DashStore::from_bytes(&bytes).expect("unable to load bytes");
+        assert_eq!(loaded.len().unwrap(), 1, "expired row was not dropped");
+        assert_eq!(loaded.get_clone("key1"), Ok(row.clone()));
+
+        let disk = store.to_disk().expect("unable to get disk repr");
+        let restored = disk
+            .data
+            .iter()
+            .map(Row::from)
+            .find(|r| r.key() == "key1")
+            .expect("row missing from disk repr");
+        assert_eq!(restored.expires_at(), row.expires_at());
+    }
+
+    #[test]
+    fn set_or_insert_previous() {
+        use super::super::Store;
+
+        let store = DashStore::empty();
+        assert_eq!(store.set_or_insert("key", "value1"), Ok(None));
+        let first = store.get_clone("key").unwrap();
+        assert_eq!(store.set_or_insert("key", "value2"), Ok(Some(first)));
+        let second = store.get_clone("key").unwrap();
+        assert_eq!(
+            store.set_or_insert("key", "value2"),
+            Ok(Some(second.clone()))
+        );
+        assert_eq!(store.get_clone("key"), Ok(second.clone()));
+        assert_eq!(second.version(), 2);
+        assert!(store
+            .insert_row(&Row::create_with_ttl("expired", "value", 0))
+            .is_ok());
+        assert_eq!(store.set_or_insert("expired", "value"), Ok(None));
+
+        assert_eq!(
+            store.set_or_insert_row(&Row::create("key", "value3")),
+            Ok(Some(second))
+        );
+        assert_eq!(
+            store.set_or_insert_row(&Row::create("fresh", "v")),
+            Ok(None)
+        );
+
+        let dyn_store: &dyn Store = &store;
+        let third = store.get_clone("key").unwrap();
+        assert_eq!(dyn_store.set_or_insert("key", "value4"), Ok(Some(third)));
+        assert_eq!(dyn_store.set_or_insert("other", "v"), Ok(None));
+        let fourth = store.get_clone("key").unwrap();
+        assert_eq!(
+            dyn_store.set_or_insert_row(&Row::create("key", "value5")),
+            Ok(Some(fourth))
+        );
+        assert_eq!(
+            helpers::set_outcome(&store, "key", "value5"),
+            Ok(SetOutcome::Unchanged)
+        );
+    }
+
+    #[test]
+    fn size_limits() {
+        let store = DashStore::with_limits(4, 8);
+        assert!(store.insert("key1", "12345678").is_ok());
+        assert_eq!(
+            store.insert("key2", "123456789"),
+            Err(crate::Error::value_too_large("key2", 9, 8))
+        );
+        assert_eq!(
+            store.insert("key10", "value"),
+            Err(crate::Error::key_too_large("key10", 5, 4))
+        );
+        assert!(store.insert_row(&Row::create("key3", "12345678")).is_ok());
+        assert_eq!(
+            store.insert_row(&Row::create("key4", "123456789")),
+            Err(crate::Error::value_too_large("key4", 9, 8))
+        );
+
+        assert_eq!(
+            store.set_or_insert("key1", "123456789"),
+            Err(crate::Error::value_too_large("key1", 9, 8))
+        );
+        assert_eq!(store.get_clone("key1").unwrap().value(), "12345678");
+        assert_eq!(
+            store.set_or_insert_row(&Row::create("key1", "123456789")),
+            Err(crate::Error::value_too_large("key1", 9, 8))
+        );
+        assert_eq!(
+            helpers::set_outcome(&store, "key1", "1234"),
+            Ok(SetOutcome::Updated)
+        );
+        assert_eq!(store.len().unwrap(), 2);
+
+        let unlimited = DashStore::empty();
+        assert!(unlimited
+            .insert(&"k".repeat(1024), &"v".repeat(1 << 20))
+            .is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_keys() {
+        let store = DashStore::empty();
+        for key in ["", "line\nbreak"] {
+            assert!(matches!(
+                store.insert(key, "value"),
+                Err(crate::Error::InvalidKey { .. })
+            ));
+            assert!(matches!(
+                store.set_or_insert(key, "value"),
+                Err(crate::Error::InvalidKey { .. })
+            ));
+        }
+        let long = "k".repeat(super::super::MAX_KEY_LEN + 1);
+        assert!(matches!(
+            store.insert(&long, "value"),
+            Err(crate::Error::InvalidKey { .. })
+        ));
+        assert!(store.insert(&long[1..], "value").is_ok());
+        assert_eq!(store.len().unwrap(), 1);
+    }
+
+    #[test]
+    fn disk_roundtrip() {
+        let original = DashStore::empty();
+        assert!(original.insert("key1", "value1").is_ok());
+        assert!(original.insert("key2", "value2").is_ok());
+        let disk = original.to_disk().expect("unable to get disk repr");
+
+        let loaded = DashStore::from_disk(&disk).expect("unable to load disk repr");
+        assert_eq!(loaded.len().unwrap(), 2);
+        for key in ["key1", "key2"] {
+            assert_eq!(loaded.get_clone(key), original.get_clone(key));
+        }
+
+        let mut disk = disk;
+        disk.data.push(disk.data[0].clone());
+        assert!(matches!(
+            DashStore::from_disk(&disk),
+            Err(crate::Error::DuplicateKey(_))
+        ));
+    }
+
+    #[test]
+    fn disk_repr_interchange() {
+        use crate::KeyValueStore;
+
+        let kv = KeyValueStore::empty();
+        assert!(kv.insert("key1", "value1").is_ok());
+        assert!(kv
+            .insert_row(&Row::new("key2", "value2", 1_000, 2_000))
+            .is_ok());
+        assert!(kv.insert("key3", "").is_ok());
+
+        let disk = kv.to_disk().expect("unable to get disk repr");
+        let dash = DashStore::from_disk(&disk).expect("unable to load into DashStore");
+        assert_eq!(dash.len(), kv.len());
+        for key in ["key1", "key2", "key3"] {
+            assert_eq!(dash.get_clone(key), kv.get_clone(key));
+        }
+
+        let disk = super::super::Store::to_disk_repr(&dash).expect("unable to get disk repr");
+        let back = KeyValueStore::from_disk(&disk).expect("unable to load into KeyValueStore");
+        for key in ["key1", "key2", "key3"] {
+            assert_eq!(back.get_clone(key), kv.get_clone(key));
+        }
+
+        let mut disk = disk;
+        disk.version = StoreDiskRepr::current_version() + 1;
+        assert_eq!(
+            DashStore::from_disk(&disk).err(),
+            Some(crate::Error::UnsupportedVersion(disk.version))
+        );
+    }
+
+    #[test]
+    fn keys_and_rows() {
+        let store = DashStore::empty();
+        assert_eq!(store.keys(), Ok(vec![]));
+        assert_eq!(store.rows(), Ok(vec![]));
+
+        for (key, value) in [("key2", "b"), ("key1", "a"), ("key3", "c")] {
+            assert!(store.insert(key, value).is_ok());
+        }
+        assert!(store
+            .insert_row(&Row::create_with_ttl("expired", "x", -1))
+            .is_ok());
+        let keys = store.keys_sorted().unwrap();
+        assert_eq!(keys, vec!["key1", "key2", "key3"]);
+        let rows = store.rows().unwrap();
+        assert_eq!(rows.len(), 3);
+
+        assert!(store.delete("key3").is_ok());
+        assert!(store.set_or_insert("key1", "changed").is_ok());
+        assert_eq!(keys.len(), 3);
+        assert!(rows.iter().any(|row| row.key() == "key3"));
+        assert!(rows.iter().all(|row| row.value() != "changed"));
+        assert_eq!(super::super::Store::rows(&store).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn iteration() {
+        let store = helpers::store_with(&[("key1", "a"), ("key2", "bb"), ("key3", "ccc")]);
+        let total = store.fold(0, |total, row| total + row.value().size_bytes());
+        assert_eq!(total, Ok(6));
+        assert_eq!(store.count_where(|row| row.value().size_bytes() > 1), Ok(2));
+
+        let mut visited = 0;
+        assert_eq!(
+            store.try_for_each(|_| {
+                visited += 1;
+                false
+            }),
+            Ok(false)
+        );
+        assert_eq!(visited, 1);
+
+        // Reading from inside the callback is fine.
+        let mut found = 0;
+        assert!(store
+            .for_each(|row| found += usize::from(store.contains(row.key()).unwrap()))
+            .is_ok());
+        assert_eq!(found, 3);
+    }
+
+    #[test]
+    fn get_many() {
+        let store = helpers::store_with(&[("key1", "a"), ("key2", "b")]);
+        assert_eq!(store.get_many(&[]), Ok(vec![]));
+        let results = store.get_many(&["key2", "missing", "key2"]).unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].1.as_ref().unwrap().value(), "b");
+        assert_eq!(
+            results[1],
+            (
+                "missing".to_string(),
+                Err(crate::Error::key_not_found("missing"))
+            )
+        );
+        assert_eq!(results[2].0, "key2");
+        assert_eq!(store.get_existing(&["missing", "key1"]).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn insert_many_and_set_many() {
+        use super::super::Store;
+
+        let store = helpers::store_with(&[("key2", "old")]);
+        let dyn_store: &dyn Store = &store;
+        assert_eq!(dyn_store.insert_many(&[]), Ok(0));
+        assert_eq!(
+            dyn_store.insert_many(&[("key1", "a"), ("key2", "b")]),
+            Err(crate::Error::duplicate_key("key2"))
+        );
+        assert_eq!(store.len(), Ok(1));
+
+        let owned = (0..500).map(|i| format!("key{}", i)).collect::<Vec<_>>();
+        let pairs = owned
+            .iter()
+            .map(|k| (k.as_str(), "value"))
+            .filter(|&(k, _)| k != "key2")
+            .collect::<Vec<_>>();
+        assert_eq!(dyn_store.insert_many(&pairs), Ok(499));
+        assert_eq!(store.len(), Ok(500));
+
+        let outcome = dyn_store
+            .set_many(&[("key2", "new"), ("key3", "value"), ("fresh", "x")])
+            .unwrap();
+        assert_eq!(
+            outcome,
+            crate::SetManyOutcome {
+                inserted: 1,
+                updated: 1,
+                unchanged: 1
+            }
+        );
+    }
+
+    #[test]
+    fn delete_many_and_matching() {
+        use super::super::Store;
+
+        let store = helpers::store_with(&[("key1", "a"), ("key2", "bb"), ("key3", "ccc")]);
+        let dyn_store: &dyn Store = &store;
+        assert_eq!(
+            dyn_store.delete_many(&["key1", "missing"]),
+            Err(crate::Error::key_not_found("missing"))
+        );
+        assert_eq!(store.len(), Ok(3));
+
+        let report = dyn_store.try_delete_many(&["missing", "key1"]).unwrap();
+        assert_eq!(report.deleted.len(), 1);
+        assert_eq!(report.missing, vec!["missing".to_string()]);
+
+        assert_eq!(dyn_store.delete_matching(&|_| false), Ok(vec![]));
+        assert_eq!(dyn_store.delete_many(&["key2"]).unwrap()[0].key(), "key2");
+        assert_eq!(dyn_store.delete_matching(&|_| true).unwrap().len(), 1);
+        assert_eq!(store.len(), Ok(0));
+    }
+
+    #[test]
+    fn prefix_scans() {
+        use super::super::Store;
+
+        let store = helpers::store_with(&[("a:1", "x"), ("a:2", "y"), ("b", "z"), ("ä:1", "w")]);
+        let dyn_store: &dyn Store = &store;
+        assert_eq!(dyn_store.count_prefix(""), Ok(4));
+        assert_eq!(dyn_store.keys_with_prefix("c"), Ok(vec![]));
+        assert_eq!(
+            dyn_store.keys_with_prefix("a:"),
+            Ok(vec!["a:1".to_string(), "a:2".to_string()])
+        );
+        assert_eq!(dyn_store.scan_prefix("ä").unwrap()[0].value(), "w");
+        assert_eq!(dyn_store.count_prefix("b"), Ok(1));
+    }
+
+    #[test]
+    fn shared_suite() {
+        super::super::test_helpers::check_store::<DashStore>();
+    }
+
+    #[test]
+    fn compare_and_swap_race() {
+        use std::sync::{Arc, Barrier};
+
+        for _ in 0..20 {
+            let store = Arc::new(helpers::store_with(&[("key", "start")]));
+            let barrier = Arc::new(Barrier::new(2));
+            let handles = (0..2)
+                .map(|t| {
+                    let store = Arc::clone(&store);
+                    let barrier = Arc::clone(&barrier);
+                    std::thread::spawn(move || {
+                        barrier.wait();
+                        store
+                            .compare_and_swap("key", "start", &format!("thread{}", t))
+                            .unwrap()
+                    })
+                })
+                .collect::<Vec<_>>();
+            let swapped = handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .filter(CasOutcome::is_swapped)
+                .count();
+            assert_eq!(swapped, 1);
+            assert_ne!(store.get_clone("key").unwrap().value(), "start");
+        }
+    }
+
+    #[test]
+    fn update_with_counter() {
+        use std::sync::Arc;
+
+        let store = Arc::new(helpers::store_with(&[("counter", "0")]));
+        let handles = (0..8)
+            .map(|_| {
+                let store = Arc::clone(&store);
+                std::thread::spawn(move || {
+                    for _ in 0..250 {
+                        let result = store.update_with("counter", |row| {
+                            let next = row.parse_value::<u64>().unwrap() + 1;
+                            row.update(&next.to_string());
+                        });
+                        assert!(result.is_ok());
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(store.get_clone("counter").unwrap().value(), "2000");
+
+        let unchanged = store.update_with("counter", |_| {}).unwrap();
+        assert_eq!(unchanged, store.get_clone("counter").unwrap());
+        assert_eq!(
+            store.update_with("missing", |_| {}),
+            Err(crate::Error::key_not_found("missing"))
+        );
+    }
+
+    #[test]
+    fn with_entry() {
+        let store = helpers::store_with(&[("keep", "1"), ("drop", "2"), ("bump", "3")]);
+
+        // Conditional delete: only rows holding an even number go.
+        for key in ["keep", "drop"] {
+            let deleted = store
+                .with_entry(key, |mut entry| match entry.row() {
+                    Some(row) if row.parse_value::<u64>().unwrap() % 2 == 0 => entry.delete(),
+                    _ => None,
+                })
+                .unwrap();
+            assert_eq!(deleted.is_some(), key == "drop");
+        }
+        assert!(store.get_clone("keep").is_ok());
+        assert!(store.get_clone("drop").is_err());
+
+        // Conditional update: only bump values under 5.
+        let updated = store
+            .with_entry("bump", |mut entry| {
+                let next = entry.row().unwrap().parse_value::<u64>().unwrap() + 1;
+                (next < 5).then(|| entry.set(&next.to_string())).is_some()
+            })
+            .unwrap();
+        assert!(updated);
+        assert_eq!(store.get_clone("bump").unwrap().value(), "4");
+
+        // Insert through the entry, which refuses existing keys.
+        let inserted = store
+            .with_entry("new", |mut entry| {
+                assert!(!entry.exists());
+                entry.insert("fresh")
+            })
+            .unwrap();
+        assert_eq!(inserted, Ok(()));
+        assert_eq!(store.get_clone("new").unwrap().value(), "fresh");
+        let duplicate = store
+            .with_entry("new", |mut entry| entry.insert("again"))
+            .unwrap();
+        assert_eq!(duplicate, Err(crate::Error::duplicate_key("new")));
+        assert_eq!(store.get_clone("new").unwrap().value(), "fresh");
+
+        // Reading only leaves the store alone.
+        let stats = store.stats();
+        let key = store
+            .with_entry("keep", |entry| entry.key().to_string())
+            .unwrap();
+        assert_eq!(key, "keep");
+        assert_eq!(store.stats(), stats);
+    }
+
+    #[test]
+    fn with_entry_does_not_interleave() {
+        use std::sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        };
+
+        let store = Arc::new(DashStore::empty());
+        let inside = Arc::new(AtomicBool::new(false));
+        let handles = (0..2)
+            .map(|_| {
+                let store = Arc::clone(&store);
+                let inside = Arc::clone(&inside);
+                std::thread::spawn(move || {
+                    for _ in 0..500 {
+                        store
+                            .with_entry("counter", |mut entry| {
+                                assert!(!inside.swap(true, Ordering::SeqCst));
+                                let next = entry
+                                    .row()
+                                    .map_or(0, |row| row.parse_value::<u64>().unwrap())
+                                    + 1;
+                                entry.set(&next.to_string()).unwrap();
+                                inside.store(false, Ordering::SeqCst);
+                            })
+                            .unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(store.get_clone("counter").unwrap().value(), "1000");
+    }
+
+    #[test]
+    fn get_or_insert_with() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let store = helpers::store_with(&[("existing", "value")]);
+        let calls = AtomicUsize::new(0);
+        let make = || {
+            calls.fetch_add(1, Ordering::SeqCst);
+            "made".to_string()
+        };
+
+        let row = store.get_or_insert_with("existing", make).unwrap();
+        assert_eq!(row.value(), "value");
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        let row = store.get_or_insert_with("new", make).unwrap();
+        assert_eq!(row.value(), "made");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(store.get_clone("new"), Ok(row.clone()));
+        assert_eq!(store.get_or_insert("new", "other"), Ok(row));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        assert!(store.get_or_insert("", "v").is_err());
+    }
+
+    #[test]
+    fn get_or_insert_with_race() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::{Arc, Barrier};
+
+        let store = Arc::new(DashStore::empty());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(8));
+        let handles = (0..8)
+            .map(|t| {
+                let (store, calls, barrier) =
+                    (Arc::clone(&store), Arc::clone(&calls), Arc::clone(&barrier));
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    store
+                        .get_or_insert_with("memo", || {
+                            calls.fetch_add(1, Ordering::SeqCst);
+                            format!("thread{}", t)
+                        })
+                        .unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+        let rows = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        let resident = store.get_clone("memo").unwrap();
+        assert!(rows.iter().all(|row| *row == resident));
+    }
+
+    #[test]
+    fn rename_bumps_updated() {
+        let store = helpers::store_with(&[("old", "value"), ("taken", "other")]);
+        let before = store.get_clone("old").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert_eq!(store.rename("old", "new"), Ok(()));
+        let after = store.get_clone("new").unwrap();
+        assert_eq!(after.created(), before.created());
+        assert!(after.updated() > before.updated());
+        assert!(after.verify_checksum());
+
+        assert_eq!(
+            store.rename("new", "taken"),
+            Err(crate::Error::duplicate_key("taken"))
+        );
+        assert_eq!(store.get_clone("new"), Ok(after));
+        assert_eq!(store.get_clone("taken").unwrap().value(), "other");
+        assert_eq!(
+            store.rename("old", "anything"),
+            Err(crate::Error::key_not_found("old"))
+        );
+        assert_eq!(
+            store.rename_overwrite("nothing", "new"),
+            Err(crate::Error::key_not_found("nothing"))
+        );
+    }
+
+    /// A reader can briefly miss the row under both keys, but never sees it
+    /// under `old` again once it has moved.
+    #[test]
+    fn rename_races_with_reader() {
+        use std::sync::{Arc, Barrier};
+
+        let store = Arc::new(helpers::store_with(&[("old", "value")]));
+        let barrier = Arc::new(Barrier::new(2));
+        let reader = {
+            let (store, barrier) = (Arc::clone(&store), Arc::clone(&barrier));
+            std::thread::spawn(move || {
+                barrier.wait();
+                let mut gone = false;
+                for _ in 0..10_000 {
+                    match store.get_clone("old") {
+                        Ok(row) => {
+                            // Once the row has moved it never comes back.
+                            assert!(!gone);
+                            assert_eq!(row.value(), "value");
+                        }
+                        Err(err) => {
+                            assert_eq!(err, crate::Error::key_not_found("old"));
+                            gone = true;
+                        }
+                    }
+                }
+            })
+        };
+        barrier.wait();
+        assert_eq!(store.rename("old", "new"), Ok(()));
+        reader.join().unwrap();
+        assert_eq!(store.contains("old"), Ok(false));
+        assert_eq!(store.get_clone("new").unwrap().value(), "value");
+    }
+
+    #[test]
+    fn clear_and_is_empty() {
+        let store = DashStore::empty();
+        assert_eq!(store.is_empty(), Ok(true));
+        assert_eq!(store.clear(), Ok(0));
+
+        let store = helpers::store_with(&[("key1", "a"), ("key2", "b"), ("key3", "c")]);
+        assert_eq!(store.is_empty(), Ok(false));
+        assert_eq!(store.clear(), Ok(3));
+        assert_eq!(store.is_empty(), Ok(true));
+        assert_eq!(store.len(), Ok(0));
+        assert_eq!(
+            store.get_clone("key1"),
+            Err(crate::Error::key_not_found("key1"))
+        );
+        assert_eq!(store.insert("key1", "again"), Ok(()));
+        assert_eq!(store.is_empty(), Ok(false));
+    }
+
+    #[test]
+    fn clear_alongside_readers() {
+        use std::sync::Arc;
+
+        let store = Arc::new(helpers::fill_single_thread(1000));
+        let readers = (0..4)
+            .map(|_| {
+                let store = Arc::clone(&store);
+                std::thread::spawn(move || {
+                    for i in 0..1000 {
+                        match store.get_clone(&format!("key{}", i)) {
+                            Ok(row) => assert_eq!(row.key(), format!("key{}", i)),
+                            Err(err) => assert!(matches!(err, crate::Error::KeyNotFound(..))),
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(store.clear(), Ok(1000));
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(store.is_empty(), Ok(true));
+    }
+
+    #[test]
+    fn retain() {
+        let store = helpers::store_with(&[("key1", "a"), ("key2", "b"), ("key3", "c")]);
+        assert_eq!(store.retain(|_, _| true), Ok(0));
+        assert_eq!(store.len(), Ok(3));
+        assert_eq!(store.retain(|key, _| key != "key2"), Ok(1));
+        assert_eq!(
+            store.keys_sorted(),
+            Ok(vec!["key1".to_string(), "key3".to_string()])
+        );
+        assert_eq!(store.retain(|_, row| row.value() == "z"), Ok(2));
+        assert_eq!(store.is_empty(), Ok(true));
+        assert_eq!(store.retain(|_, _| false), Ok(0));
+    }
+
+    #[test]
+    fn retain_panic_leaves_store_usable() {
+        let store = helpers::store_with(&[("key1", "a"), ("key2", "b")]);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            store.retain(|_, _| panic!("predicate failed"))
+        }));
+        assert!(result.is_err());
+        assert_eq!(store.len(), Ok(2));
+        assert_eq!(store.insert("key3", "c"), Ok(()));
+        assert_eq!(store.retain(|_, _| false), Ok(3));
+    }
+
+    #[test]
+    fn retain_updated_since() {
+        let store = helpers::store_with(&[("key1", "a"), ("key2", "b"), ("key3", "c")]);
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let cutoff = super::super::create_now();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        store.set_or_insert("key2", "fresh").unwrap();
+        assert_eq!(store.retain_updated_since(cutoff), Ok(2));
+        assert_eq!(store.get_clone("key2").unwrap().value(), "fresh");
+        assert_eq!(store.len(), Ok(1));
+    }
+
+    #[test]
+    fn merge_from_and_diff_defaults() {
+        use super::super::Store;
+        use crate::MergeStrategy;
+
+        let store = helpers::store_with(&[("key1", "a"), ("key2", "b")]);
+        let snapshot = Store::to_disk_repr(&store).unwrap();
+        assert!(Store::diff(&store, &snapshot).unwrap().is_empty());
+
+        store.delete("key1").unwrap();
+        store.insert_row(&Row::new("key3", "c", 1, 10)).unwrap();
+        let diff = Store::diff(&store, &snapshot).unwrap();
+        assert_eq!(diff.only_in_self, vec!["key3".to_string()]);
+        assert_eq!(diff.only_in_other, vec!["key1".to_string()]);
+        assert!(diff.different.is_empty());
+
+        let other = StoreDiskRepr::from(vec![
+            Row::new("key1", "a", 1, 10),
+            Row::new("key3", "newer", 1, 20),
+        ]);
+        let report = Store::merge_from(&store, &other, MergeStrategy::LastWriteWins).unwrap();
+        assert_eq!((report.inserted, report.merged), (1, 1));
+        assert_eq!(store.get_clone("key3").unwrap().value(), "newer");
+        let again = Store::merge_from(&store, &other, MergeStrategy::LastWriteWins).unwrap();
+        assert_eq!(again.changed(), 0);
+        assert!(Store::diff(&store, &other).unwrap().different.is_empty());
+    }
+
+    #[test]
+    fn memory_usage() {
+        use std::mem::size_of;
+
+        let store = DashStore::empty();
+        assert_eq!(store.memory_usage(), Ok(StoreSize::default()));
+        assert_eq!(store.largest_rows(3), Ok(vec![]));
+
+        let store = helpers::store_with(&[("a", "12345"), ("bb", "123"), ("ccc", "")]);
+        let size = store.memory_usage().unwrap();
+        assert_eq!(size.row_count, 3);
+        assert_eq!(size.key_bytes, 6);
+        assert_eq!(size.value_bytes, 8);
+        assert_eq!(
+            size.approx_total_bytes,
+            3 * (size_of::<Row>() + StoreSize::ENTRY_OVERHEAD_BYTES) + 2 * 6 + 8
+        );
+    }
+
+    #[test]
+    fn largest_rows() {
+        let store = helpers::store_with(&[
+            ("small", "1"),
+            ("tie-b", "12345"),
+            ("big", "1234567890"),
+            ("tie-a", "54321"),
+        ]);
+        let keys = |rows: Vec<Row>| {
+            rows.iter()
+                .map(|row| row.key().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            keys(store.largest_rows(3).unwrap()),
+            vec!["big", "tie-a", "tie-b"]
+        );
+        assert_eq!(keys(store.largest_rows(10).unwrap()).len(), 4);
+        assert_eq!(store.largest_rows(0), Ok(vec![]));
+
+        store.delete("big").unwrap();
+        assert_eq!(keys(store.largest_rows(1).unwrap()), vec!["tie-a"]);
+    }
+
+    #[test]
+    fn stats() {
+        let store = DashStore::empty();
+        assert_eq!(store.stats(), StoreStatsSnapshot::default());
+        assert_eq!(store.stats().hit_rate(), None);
+
+        store.insert("a", "1").unwrap();
+        store.insert("b", "1").unwrap();
+        assert!(store.insert("b", "2").is_err());
+        store.get_clone("a").unwrap();
+        assert!(store.get_clone("c").is_err());
+        store.set_or_insert("a", "1").unwrap();
+        store.set_or_insert("a", "2").unwrap();
+        store.set_or_insert("c", "3").unwrap();
+        store
+            .update_with("b", |row| {
+                row.update("4");
+            })
+            .unwrap();
+        store.delete("a").unwrap();
+        assert!(store.delete("a").is_err());
+        assert!(store.get_clone("a").is_err());
+
+        let stats = store.stats();
+        assert_eq!(
+            stats,
+            StoreStatsSnapshot {
+                gets: 3,
+                get_misses: 2,
+                inserts: 3,
+                updates: 2,
+                deletes: 1,
+                delete_misses: 1,
+            }
+        );
+        assert_eq!(stats.hit_rate(), Some(1.0 / 3.0));
+
+        store.reset_stats();
+        assert_eq!(store.stats(), StoreStatsSnapshot::default());
+        assert_eq!(store.len(), Ok(2));
+    }
+
+    #[test]
+    fn stats_across_threads() {
+        use std::sync::Arc;
+
+        let store = Arc::new(DashStore::empty());
+        let handles = (0..8)
+            .map(|t| {
+                let store = Arc::clone(&store);
+                std::thread::spawn(move || {
+                    for i in 0..100 {
+                        let key = format!("{}-{}", t, i);
+                        store.insert(&key, "value").unwrap();
+                        store.get_clone(&key).unwrap();
+                        assert!(store.get_clone(&format!("missing-{}", key)).is_err());
+                        store.delete(&key).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let stats = store.stats();
+        assert_eq!(
+            stats,
+            StoreStatsSnapshot {
+                gets: 1600,
+                get_misses: 800,
+                inserts: 800,
+                updates: 0,
+                deletes: 800,
+                delete_misses: 0,
+            }
+        );
+        assert_eq!(stats.hit_rate(), Some(0.5));
+    }
+
+    #[test]
+    fn subscribe() {
+        use super::super::test_helpers::drain_events;
+
+        let store = DashStore::empty();
+        let events = store.subscribe();
+        store.insert("key", "one").unwrap();
+        store.set_or_insert("key", "two").unwrap();
+        store.set_or_insert("key", "two").unwrap();
+        store
+            .update_with("key", |row| {
+                row.update("three");
+            })
+            .unwrap();
+        store.delete("key").unwrap();
+        assert_eq!(
+            drain_events(&events),
+            vec![
+                "inserted key: one",
+                "updated key: one -> two",
+                "updated key: two -> three",
+                "deleted key: three",
+            ]
+        );
+
+        // Failed operations send nothing.
+        store.insert("other", "1").unwrap();
+        assert!(store.insert("other", "2").is_err());
+        assert!(store.delete("missing").is_err());
+        assert!(store.update_with("missing", |_| {}).is_err());
+        assert_eq!(
+            store.compare_and_swap("other", "2", "3"),
+            Ok(CasOutcome::ValueMismatch {
+                actual: "1".to_string()
+            })
+        );
+        assert_eq!(drain_events(&events), vec!["inserted other: 1"]);
+    }
+
+    #[test]
+    fn subscribe_many() {
+        use super::super::test_helpers::drain_events;
+
+        let store = DashStore::empty();
+        let first = store.subscribe();
+        let second = store.subscribe();
+        store.insert("a", "1").unwrap();
+        assert_eq!(drain_events(&first), vec!["inserted a: 1"]);
+        assert_eq!(drain_events(&second), vec!["inserted a: 1"]);
+
+        drop(first);
+        store.set_or_insert("a", "2").unwrap();
+        assert_eq!(drain_events(&second), vec!["updated a: 1 -> 2"]);
+        assert!(store.events.is_watched());
+
+        drop(second);
+        store.delete("a").unwrap();
+        assert!(!store.events.is_watched());
+        store.insert("b", "1").unwrap();
+        assert_eq!(store.len(), Ok(1));
+    }
+
+    #[test]
+    fn snapshot() {
+        let store = helpers::store_with(&[("a", "1"), ("b", "2"), ("c", "3")]);
+        let snapshot = store.snapshot().unwrap();
+
+        store.set_or_insert("a", "changed").unwrap();
+        store.delete("b").unwrap();
+        store.insert("d", "4").unwrap();
+
+        assert_eq!(snapshot.len(), 3);
+        assert_eq!(*snapshot.get("a").unwrap().value(), *"1");
+        assert!(snapshot.contains("b"));
+        assert!(!snapshot.contains("d"));
+        assert_eq!(snapshot.get("d"), None);
+        let mut keys = snapshot.keys().collect::<Vec<_>>();
+        keys.sort_unstable();
+        assert_eq!(keys, vec!["a", "b", "c"]);
+        assert_eq!(snapshot.rows().count(), 3);
+        assert_eq!(snapshot.to_disk_repr().data.len(), 3);
+
+        let empty = DashStore::empty().snapshot().unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(empty.keys().count(), 0);
+        assert!(empty.to_disk_repr().data.is_empty());
+    }
+
+    #[test]
+    fn into_iter_and_extend() {
+        let pairs = vec![
+            ("a".to_string(), "1".to_string()),
+            ("b".to_string(), "2".to_string()),
+            ("a".to_string(), "3".to_string()),
+        ];
+        let mut store = pairs.into_iter().collect::<DashStore>();
+        assert_eq!(store.len(), Ok(2));
+        assert_eq!((&store).into_iter().count(), 2);
+        for (key, row) in &store {
+            assert_eq!(key, row.key());
+        }
+
+        store.extend(vec![
+            ("b".to_string(), "changed".to_string()),
+            ("c".to_string(), "4".to_string()),
+        ]);
+        store.extend(vec![Row::create("d", "5")]);
+        assert_eq!(store.get_clone("b").unwrap().version(), 2);
+        assert_eq!((&store).into_iter().count(), store.len().unwrap());
+
+        let mut values = store
+            .into_iter()
+            .map(|(key, row)| (key, row.value().to_display_string()))
+            .collect::<Vec<_>>();
+        values.sort();
+        assert_eq!(
+            values,
+            vec![
+                ("a".to_string(), "3".to_string()),
+                ("b".to_string(), "changed".to_string()),
+                ("c".to_string(), "4".to_string()),
+                ("d".to_string(), "5".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "unable to extend store with key 'big'")]
+    fn extend_past_limits() {
+        let mut store = DashStore::with_limits(8, 4);
+        store.extend(vec![("big".to_string(), "too large".to_string())]);
+    }
+
+    #[test]
+    fn apply_transaction_unsupported() {
+        let store = helpers::store_with(&[("a", "1")]);
+        assert_eq!(
+            store.apply_transaction(&[Op::Delete {
+                key: "a".to_string()
+            }]),
+            Err(crate::Error::unsupported("apply_transaction"))
+        );
+        assert!(store.contains("a").unwrap());
+    }
+
+    #[test]
+    fn increment() {
+        let store = helpers::store_with(&[("text", "hello"), ("max", "9223372036854775807")]);
+        assert_eq!(store.increment("count", 5), Ok(5));
+        assert_eq!(store.increment("count", -8), Ok(-3));
+        assert_eq!(store.decrement("count", 2), Ok(-5));
+        assert_eq!(store.decrement("count", -10), Ok(5));
+        assert_eq!(
+            store
+                .get_clone("count")
+                .and_then(|row| row.parse_value::<i64>()),
+            Ok(5)
+        );
+
+        assert!(matches!(
+            store.increment("text", 1),
+            Err(crate::Error::ValueParse { .. })
+        ));
+        assert_eq!(*store.get_clone("text").unwrap().value(), *"hello");
+
+        assert_eq!(
+            store.increment("max", 1),
+            Err(crate::Error::overflow("max", i64::MAX, '+', 1))
+        );
+        assert_eq!(
+            store
+                .get_clone("max")
+                .and_then(|row| row.parse_value::<i64>()),
+            Ok(i64::MAX)
+        );
+        assert_eq!(store.decrement("max", i64::MAX), Ok(0));
+        assert_eq!(
+            store.decrement("max", i64::MIN),
+            Err(crate::Error::overflow("max", 0, '-', i64::MIN))
+        );
+    }
+
+    #[test]
+    fn decrement_by_min() {
+        let store = helpers::store_with(&[("count", "-5")]);
+        assert_eq!(store.decrement("count", i64::MIN), Ok(i64::MAX - 4));
+        assert_eq!(
+            store
+                .get_clone("count")
+                .and_then(|row| row.parse_value::<i64>()),
+            Ok(i64::MAX - 4)
+        );
+        assert_eq!(store.increment("count", i64::MIN), Ok(-5));
+        assert_eq!(
+            store.decrement("missing", i64::MIN),
+            Err(crate::Error::overflow("missing", 0, '-', i64::MIN))
+        );
+        assert_eq!(
+            store.get_clone("missing"),
+            Err(crate::Error::key_not_found("missing"))
+        );
+    }
+
+    #[test]
+    fn increment_across_threads() {
+        use std::sync::Arc;
+
+        let store = Arc::new(DashStore::empty());
+        let handles = (0..16)
+            .map(|_| {
+                let store = Arc::clone(&store);
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        store.increment("counter", 1).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(
+            store
+                .get_clone("counter")
+                .and_then(|row| row.parse_value::<i64>()),
+            Ok(16_000)
+        );
+    }
+
+    #[test]
+    fn find_keys() {
+        let store = helpers::store_with(&[
+            ("user:1:name", "alice"),
+            ("user:2:name", "bob"),
+            ("user:10:name", "carol"),
+            ("user:1:email", "alice@example.com"),
+            ("session:1", "token"),
+            ("user.name", "literal"),
+        ]);
+        store.delete("user:2:name").unwrap();
+
+        assert_eq!(
+            store.find_keys("user:*"),
+            Ok(vec![
+                "user:10:name".to_string(),
+                "user:1:email".to_string(),
+                "user:1:name".to_string(),
+            ])
+        );
+        assert_eq!(
+            store.find_keys("*:?:name"),
+            Ok(vec!["user:1:name".to_string()])
+        );
+        assert_eq!(
+            store.find_keys("session:1"),
+            Ok(vec!["session:1".to_string()])
+        );
+        assert_eq!(store.find_keys("session:"), Ok(vec![]));
+        assert_eq!(
+            store.find_keys("user.name"),
+            Ok(vec!["user.name".to_string()])
+        );
+        assert_eq!(store.find_keys(""), Ok(vec![]));
+        assert_eq!(store.find_keys("*").unwrap().len(), 5);
+
+        assert_eq!(store.find_keys_limited("user:*", 2).unwrap().len(), 2);
+        assert_eq!(store.find_keys_limited("user:*", 0), Ok(vec![]));
+        let rows = store.find_rows("user:1*").unwrap();
+        assert_eq!(
+            rows.iter().map(|row| row.key()).collect::<Vec<_>>(),
+            vec!["user:10:name", "user:1:email", "user:1:name"]
+        );
+    }
+
+    #[test]
+    fn time_queries() {
+        let store = DashStore::empty();
+        assert_eq!(store.purge_older_than(i64::MAX), Ok(vec![]));
+        for (key, created, updated) in [("a", 100, 100), ("b", 100, 200), ("c", 200, 300)] {
+            store
+                .insert_row(&Row::new(key, "value", created, updated))
+                .unwrap();
+        }
+        let keys = |rows: crate::Result<Vec<Row>>| {
+            rows.unwrap()
+                .iter()
+                .map(|row| row.key().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        // `rows_updated_since` includes the boundary, the others don't.
+        assert_eq!(keys(store.rows_updated_since(200)), vec!["b", "c"]);
+        assert_eq!(keys(store.rows_updated_since(201)), vec!["c"]);
+        assert_eq!(keys(store.rows_created_before(200)), vec!["a", "b"]);
+        assert_eq!(keys(store.rows_created_before(100)), Vec::<String>::new());
+
+        assert_eq!(keys(store.purge_older_than(200)), vec!["a"]);
+        assert_eq!(store.get_clone("a"), Err(crate::Error::key_not_found("a")));
+        assert_eq!(keys(store.purge_older_than(301)), vec!["b", "c"]);
+        assert_eq!(store.get_clone("c"), Err(crate::Error::key_not_found("c")));
+        assert_eq!(store.is_empty(), Ok(true));
+    }
+
+    #[test]
+    fn touch() {
+        let store = helpers::store_with(&[("a", "1"), ("b", "2")]);
+        let before = store.get_clone("a").unwrap();
+        let events = store.subscribe();
+
+        let updated = store.touch("a").unwrap();
+        assert!(updated > before.updated());
+        let after = store.get_clone("a").unwrap();
+        assert_eq!(after.updated(), updated);
+        assert_eq!(*after.value(), *"1");
+        assert_eq!(after.version(), before.version());
+        assert_eq!(after.created(), before.created());
+        assert!(store.touch("a").unwrap() > updated);
+        assert_eq!(events.try_iter().count(), 2);
+
+        assert_eq!(
+            store.touch("missing"),
+            Err(crate::Error::key_not_found("missing"))
+        );
+        let results = store.touch_many(&["a", "missing", "b"]).unwrap();
+        assert_eq!(
+            results
+                .iter()
+                .map(|(key, _)| key.as_str())
+                .collect::<Vec<_>>(),
+            vec!["a", "missing", "b"]
+        );
+        assert!(results[0].1.is_ok());
+        assert_eq!(results[1].1, Err(crate::Error::key_not_found("missing")));
+        assert_eq!(results[2].1, Ok(store.get_clone("b").unwrap().updated()));
+    }
+
+    #[test]
+    fn read_only() {
+        use super::super::Store;
+        use crate::MergeStrategy;
+
+        let mut store = helpers::store_with(&[("a", "1"), ("b", "2"), ("n", "5")]);
+        assert!(store
+            .insert_row(&Row::create_with_ttl("expired", "x", -1))
+            .is_ok());
+        assert!(!store.is_read_only());
+
+        store.set_read_only(true);
+        assert!(store.is_read_only());
+        let attempts = [
+            ("get_or_insert", store.get_or_insert("new", "v").map(drop)),
+            ("insert", store.insert("new", "v")),
+            ("insert_row", store.insert_row(&Row::create("new", "v"))),
+            ("insert_many", store.insert_many(&[("new", "v")]).map(drop)),
+            ("set_or_insert", store.set_or_insert("a", "x").map(drop)),
+            (
+                "set_or_insert_row",
+                store.set_or_insert_row(&Row::create("a", "x")).map(drop),
+            ),
+            ("increment", store.increment("n", 1).map(drop)),
+            ("decrement", store.decrement("n", 1).map(drop)),
+            ("touch", store.touch("a").map(drop)),
+            ("touch_many", store.touch_many(&["a"]).map(drop)),
+            ("update_with", store.update_with("a", |_| {}).map(drop)),
+            (
+                "with_entry",
+                store.with_entry("a", |mut entry| entry.delete()).map(drop),
+            ),
+            (
+                "upsert_with",
+                store
+                    .upsert_with("a", || Row::create("a", "x"), |_| {})
+                    .map(drop),
+            ),
+            (
+                "compare_and_swap",
+                store.compare_and_swap("a", "1", "x").map(drop),
+            ),
+            (
+                "compare_and_delete",
+                store.compare_and_delete("a", "1").map(drop),
+            ),
+            ("rename", store.rename("a", "renamed")),
+            (
+                "rename_overwrite",
+                store.rename_overwrite("a", "b").map(drop),
+            ),
+            ("delete", store.delete("a").map(drop)),
+            ("take", store.take("a").map(drop)),
+            ("take_if", store.take_if("a", |_| true).map(drop)),
+            ("delete_many", store.delete_many(&["a"]).map(drop)),
+            ("try_delete_many", store.try_delete_many(&["a"]).map(drop)),
+            ("delete_matching", store.delete_matching(|_| true).map(drop)),
+            ("retain", store.retain(|_, _| false).map(drop)),
+            (
+                "retain_updated_since",
+                store.retain_updated_since(i64::MAX).map(drop),
+            ),
+            (
+                "purge_older_than",
+                store.purge_older_than(i64::MAX).map(drop),
+            ),
+            ("clear", store.clear().map(drop)),
+            (
+                "Store::insert_many",
+                Store::insert_many(&store, &[("new", "v")]).map(drop),
+            ),
+            (
+                "Store::set_many",
+                Store::set_many(&store, &[("new", "v"), ("a", "x")]).map(drop),
+            ),
+            (
+                "Store::merge_from",
+                Store::merge_from(
+                    &store,
+                    &StoreDiskRepr::from(vec![Row::create("new", "v")]),
+                    MergeStrategy::LastWriteWins,
+                )
+                .map(drop),
+            ),
+        ];
+        for (method, result) in attempts {
+            assert_eq!(result, Err(crate::Error::ReadOnly), "{}", method);
+        }
+
+        let extend_pairs = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            store.extend(vec![("new".to_string(), "v".to_string())])
+        }));
+        assert_eq!(
+            extend_pairs.unwrap_err().downcast_ref::<String>().unwrap(),
+            "unable to extend store with key 'new': store is read-only"
+        );
+        let extend_rows = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            store.extend(vec![Row::create("a", "x")])
+        }));
+        assert_eq!(
+            extend_rows.unwrap_err().downcast_ref::<String>().unwrap(),
+            "unable to extend store with key 'a': store is read-only"
+        );
+
+        assert_eq!(store.get_clone("a").unwrap().value(), "1");
+        assert_eq!(
+            store.get_clone("expired"),
+            Err(crate::Error::key_not_found("expired"))
+        );
+        assert_eq!(store.len(), Ok(4));
+        assert_eq!(
+            store.keys_sorted(),
+            Ok(vec!["a".into(), "b".into(), "n".into()])
+        );
+
+        store.set_read_only(false);
+        assert!(!store.is_read_only());
+        assert_eq!(store.insert("new", "v"), Ok(()));
+        assert_eq!(store.delete("a").unwrap().value(), "1");
+    }
+
+    #[test]
+    fn read_only_rejects_waiting_writes() {
+        let store = &helpers::store_with(&[("a", "1")]);
+        std::thread::scope(|scope| {
+            // Holding a reference to the row keeps its shard locked.
+            let row = store.data.get("a").unwrap();
+            let writer = scope.spawn(move || store.set_or_insert("a", "2"));
+            // Give the writer time to get past the early check and queue up
+            // behind the lock.
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            store.set_read_only(true);
+            drop(row);
+            assert_eq!(writer.join().unwrap(), Err(crate::Error::ReadOnly));
+        });
+        assert_eq!(store.get_clone("a").unwrap().value(), "1");
+    }
+
+    #[test]
+    fn read_only_through_trait() {
+        use super::super::Store;
+
+        let store = helpers::store_with(&[("a", "1")]);
+        let dyn_store: &dyn Store = &store;
+        assert_eq!(dyn_store.set_read_only(true), Ok(()));
+        assert!(dyn_store.is_read_only());
+        assert_eq!(dyn_store.insert("b", "2"), Err(crate::Error::ReadOnly));
+        assert_eq!(dyn_store.delete("a"), Err(crate::Error::ReadOnly));
+        assert_eq!(dyn_store.get_clone("a").unwrap().value(), "1");
+        assert_eq!(dyn_store.set_read_only(false), Ok(()));
+        assert_eq!(dyn_store.insert("b", "2"), Ok(()));
+    }
+
+    #[test]
+    fn capacity_limit() {
+        assert_eq!(DashStore::empty().remaining_capacity(), Ok(None));
+        let store = DashStore::with_capacity_limit(3);
+        assert_eq!(store.remaining_capacity(), Ok(Some(3)));
+        assert_eq!(store.insert_many(&[("a", "1"), ("b", "2")]), Ok(2));
+        assert_eq!(store.insert_row(&Row::create("c", "3")), Ok(()));
+        assert_eq!(store.remaining_capacity(), Ok(Some(0)));
+
+        let full = Err(crate::Error::capacity_exceeded(3, 4));
+        assert_eq!(store.insert("d", "4"), full);
+        assert_eq!(store.set_or_insert("d", "4").map(|_| ()), full);
+        assert_eq!(store.increment("d", 1).map(|_| ()), full);
+        assert_eq!(
+            helpers::set_outcome(&store, "a", "updated"),
+            Ok(SetOutcome::Updated)
+        );
+        assert_eq!(store.len(), Ok(3));
+
+        assert!(store.delete("c").is_ok());
+        assert_eq!(store.remaining_capacity(), Ok(Some(1)));
+        assert_eq!(
+            store.insert_many(&[("d", "4"), ("e", "5")]),
+            Err(crate::Error::capacity_exceeded(3, 4))
+        );
+        assert_eq!(store.get_clone("d"), Err(crate::Error::key_not_found("d")));
+        assert_eq!(store.len(), Ok(2));
+        assert_eq!(store.insert_many(&[("d", "4")]), Ok(1));
+        assert_eq!(store.remaining_capacity(), Ok(Some(0)));
+    }
+
+    #[test]
+    fn clone_and_content_eq() {
+        let original: DashStore = helpers::store_with(&[("a", "1"), ("b", "2")]);
+        let clone = original.clone();
+        assert_eq!(original.content_eq(&clone, false), Ok(true));
+
+        assert!(clone.set_or_insert("a", "changed").is_ok());
+        assert!(clone.insert("c", "3").is_ok());
+        assert_eq!(original.get_clone("a").unwrap().value(), "1");
+        assert_eq!(original.len(), Ok(2));
+        assert_eq!(original.content_eq(&clone, true), Ok(false));
+
+        let rebuilt = DashStore::empty();
+        for (key, value) in [("a", "1"), ("b", "2")] {
+            let row = original.get_clone(key).unwrap();
+            assert!(rebuilt
+                .insert_row(&Row::new(
+                    key,
+                    value,
+                    row.created() - 10,
+                    row.updated() - 10
+                ))
+                .is_ok());
+        }
+        assert_eq!(original.content_eq(&rebuilt, true), Ok(true));
+        assert_eq!(original.content_eq(&rebuilt, false), Ok(false));
+        assert_eq!(rebuilt.content_eq(&rebuilt, false), Ok(true));
+    }
+
+    #[test]
+    fn sample() {
+        let pairs = (0..20)
+            .map(|i| (format!("key{}", i), format!("value{}", i)))
+            .collect::<Vec<_>>();
+        let pairs = pairs
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect::<Vec<_>>();
+        let store = helpers::store_with(&pairs);
+        assert!(store
+            .insert_row(&Row::create_with_ttl("expired", "x", -1))
+            .is_ok());
+        assert!(store.insert("deleted", "x").is_ok());
+        assert!(store.delete("deleted").is_ok());
+
+        let all = store.sample(100).unwrap();
+        assert_eq!(all.len(), 20);
+        let mut keys = all
+            .iter()
+            .map(|row| row.key().to_string())
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys, store.keys_sorted().unwrap());
+
+        for _ in 0..100 {
+            let picked = store.sample(5).unwrap();
+            assert_eq!(picked.len(), 5);
+            assert!(picked.iter().all(|row| row.key().starts_with("key")));
+            let key = store.random_key().unwrap().unwrap();
+            assert!(key.starts_with("key"));
+        }
+        assert_eq!(store.sample(0), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn take_if() {
+        let store = helpers::store_with(&[("job", "pending"), ("other", "x")]);
+
+        let kept = store.take_if("job", |row| row.value() == "done");
+        assert_eq!(kept, Ok(None));
+        assert_eq!(store.get_clone("job").unwrap().value(), "pending");
+
+        let taken = store
+            .take_if("job", |row| row.value() == "pending")
+            .unwrap();
+        assert_eq!(taken.map(|row| row.value().clone()), Some("pending".into()));
+        assert!(store.get_clone("job").is_err());
+        assert_eq!(
+            store.take_if("job", |_| true),
+            Err(crate::Error::key_not_found("job"))
+        );
+
+        assert_eq!(store.take("other").unwrap().value(), "x");
+        assert_eq!(
+            store.take("other"),
+            Err(crate::Error::key_not_found("other"))
+        );
+        assert_eq!(store.is_empty(), Ok(true));
+        let stats = store.stats();
+        assert_eq!((stats.deletes, stats.delete_misses), (2, 2));
+    }
+
+    #[test]
+    fn take_if_across_threads() {
+        use std::sync::Arc;
+
+        for _ in 0..20 {
+            let store = Arc::new(helpers::store_with(&[("job", "pending")]));
+            let handles = (0..8)
+                .map(|_| {
+                    let store = Arc::clone(&store);
+                    std::thread::spawn(move || {
+                        match store.take_if("job", |row| row.value() == "pending") {
+                            Ok(taken) => taken.is_some(),
+                            Err(err) => {
+                                assert_eq!(err, crate::Error::key_not_found("job"));
+                                false
+                            }
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            let winners = handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .filter(|&won| won)
+                .count();
+            assert_eq!(winners, 1);
+            assert!(store.get_clone("job").is_err());
+        }
+    }
+
+    #[test]
+    fn find() {
+        use std::cell::Cell;
+
+        let store = helpers::store_with(&[("a", "same"), ("b", "other"), ("c", "same")]);
+        assert!(store
+            .insert_row(&Row::create_with_ttl("expired", "same", -1))
+            .is_ok());
+
+        assert_eq!(store.contains_value("same"), Ok(true));
+        assert_eq!(store.contains_value("missing"), Ok(false));
+        assert_eq!(
+            store.keys_with_value("same"),
+            Ok(vec!["a".to_string(), "c".to_string()])
+        );
+        assert_eq!(store.keys_with_value("missing"), Ok(vec![]));
+
+        let found = store.find(|row| row.value() == "same").unwrap();
+        assert_eq!(
+            found.iter().map(Row::key).collect::<Vec<_>>(),
+            vec!["a", "c"]
+        );
+        assert_eq!(store.find(|_| false), Ok(vec![]));
+        assert_eq!(store.find_first(|_| false), Ok(None));
+
+        let calls = Cell::new(0);
+        let first = store
+            .find_first(|_| {
+                calls.set(calls.get() + 1);
+                true
+            })
+            .unwrap();
+        assert!(first.is_some());
+        assert_eq!(calls.get(), 1);
     }
 }
@@ -4,27 +4,62 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::collections::HashMap;
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap},
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+    sync::Arc,
+};
 
 use serde::{Deserialize, Serialize};
 
-use crate::Row;
+use super::value_repr::ValueRepr;
+use crate::{Row, Value};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct RowDiskRepr {
+    /// The shape of this row, see `RowDiskRepr::upgrade`. Rows written before
+    /// this field existed load as `0` and follow the version of their store.
+    #[serde(default)]
+    pub schema: u8,
     pub key: String,
-    pub value: String,
+    pub value: ValueRepr,
+    #[serde(alias = "created_at")]
     pub created: i64,
+    #[serde(alias = "updated_at")]
     pub updated: i64,
+    #[serde(default = "Row::initial_version")]
+    pub version: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub meta: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<(Value, i64)>,
+    #[serde(default)]
+    pub history_limit: usize,
+    #[serde(default)]
+    pub checksum: u32,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deleted: bool,
 }
 
 impl From<Row> for RowDiskRepr {
     fn from(row: Row) -> Self {
         Self {
-            key: row.key().to_string(),
-            value: row.value().to_string(),
-            created: row.created(),
-            updated: row.updated(),
+            schema: Self::current_schema(),
+            key: row.key.to_string(),
+            value: row.value,
+            created: row.created,
+            updated: row.updated,
+            version: row.version,
+            expires_at: row.expires_at,
+            meta: row.meta,
+            history: row.history,
+            history_limit: row.history_limit,
+            checksum: row.checksum,
+            deleted: row.deleted,
         }
     }
 }
@@ -32,10 +67,18 @@ impl From<Row> for RowDiskRepr {
 impl From<&Row> for RowDiskRepr {
     fn from(row: &Row) -> Self {
         Self {
+            schema: Self::current_schema(),
             key: row.key().to_string(),
-            value: row.value().to_string(),
+            value: row.value.clone(),
             created: row.created(),
             updated: row.updated(),
+            version: row.version(),
+            expires_at: row.expires_at(),
+            meta: row.meta().clone(),
+            history: row.history().to_vec(),
+            history_limit: row.history_limit,
+            checksum: row.checksum(),
+            deleted: row.is_deleted(),
         }
     }
 }
@@ -43,10 +86,18 @@ impl From<&Row> for RowDiskRepr {
 impl From<RowDiskRepr> for Row {
     fn from(row: RowDiskRepr) -> Self {
         Self {
-            key: row.key,
+            key: row.key.into(),
             value: row.value,
+            cached_value: Default::default(),
             created: row.created,
             updated: row.updated,
+            version: row.version,
+            expires_at: row.expires_at,
+            meta: row.meta,
+            history: row.history,
+            history_limit: row.history_limit,
+            checksum: row.checksum,
+            deleted: row.deleted,
         }
     }
 }
@@ -54,20 +105,111 @@ impl From<RowDiskRepr> for Row {
 impl From<&RowDiskRepr> for Row {
     fn from(row: &RowDiskRepr) -> Self {
         let RowDiskRepr {
+            schema: _,
             key,
             value,
             created,
             updated,
+            version,
+            expires_at,
+            meta,
+            history,
+            history_limit,
+            checksum,
+            deleted,
         } = row.clone();
         Self {
-            key,
+            key: key.into(),
             value,
+            cached_value: Default::default(),
             created,
             updated,
+            version,
+            expires_at,
+            meta,
+            history,
+            history_limit,
+            checksum,
+            deleted,
         }
     }
 }
 
+impl RowDiskRepr {
+    /// Schema 2 switched timestamps from seconds to milliseconds.
+    const SCHEMA: u8 = 2;
+    pub const fn current_schema() -> u8 {
+        Self::SCHEMA
+    }
+
+    /// Converts a row written with an older schema into the current one.
+    /// Missing fields are already filled in with their defaults while
+    /// deserializing, so this only has to fix up timestamps. A `schema` of `0`
+    /// is treated as the oldest schema.
+    ///
+    /// Returns whether anything was upgraded. Rows from a newer schema than
+    /// this build knows about are left alone. Fails with
+    /// [`crate::Error::InvalidTimestamp`] if a timestamp can't be converted,
+    /// in which case the row is left unchanged.
+    pub fn upgrade(&mut self) -> crate::Result<bool> {
+        if self.schema >= Self::SCHEMA {
+            return Ok(false);
+        }
+        if self.schema < 2 {
+            self.seconds_to_millis()?;
+        }
+        self.schema = Self::SCHEMA;
+        Ok(true)
+    }
+
+    /// Converts the timestamps of a row written by a version 1 repr from seconds
+    /// to milliseconds. A valid checksum is recomputed for the new timestamps,
+    /// an invalid one is left alone so the row still fails verification. Fails
+    /// with [`crate::Error::InvalidTimestamp`] (holding the timestamp in
+    /// seconds) if one of them doesn't fit in an `i64` as milliseconds.
+    fn seconds_to_millis(&mut self) -> crate::Result<()> {
+        let to_millis = |seconds: i64| {
+            seconds
+                .checked_mul(1000)
+                .ok_or(crate::Error::InvalidTimestamp(seconds))
+        };
+        let had_valid_checksum =
+            self.checksum != Row::NO_CHECKSUM && Row::from(&*self).verify_checksum();
+        let created = to_millis(self.created)?;
+        let updated = to_millis(self.updated)?;
+        let expires_at = self.expires_at.map(to_millis).transpose()?;
+        let replaced = self
+            .history
+            .iter()
+            .map(|(_, replaced)| to_millis(*replaced))
+            .collect::<crate::Result<Vec<_>>>()?;
+        self.created = created;
+        self.updated = updated;
+        self.expires_at = expires_at;
+        for ((_, old), new) in self.history.iter_mut().zip(replaced) {
+            *old = new;
+        }
+        if had_valid_checksum {
+            let mut row = Row::from(&*self);
+            row.recompute_checksum();
+            self.checksum = row.checksum();
+        }
+        Ok(())
+    }
+}
+
+/// What happened to the rows of a [`StoreDiskRepr`] while it was loaded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoadReport {
+    /// How many rows were kept.
+    pub rows: usize,
+    /// How many of the kept rows were upgraded from an older schema.
+    pub upgraded: usize,
+    /// How many rows were dropped because their schema is newer than this
+    /// build understands.
+    pub skipped: usize,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct StoreDiskRepr {
     pub version: u8,
@@ -75,11 +217,71 @@ pub struct StoreDiskRepr {
 }
 
 impl StoreDiskRepr {
-    const VERSION: u8 = 1;
+    /// Version 2 switched timestamps from seconds to milliseconds.
+    const VERSION: u8 = 2;
     pub const fn current_version() -> u8 {
         Self::VERSION
     }
 
+    /// Upgrades data loaded from an older version of this repr to the current
+    /// version, calling `RowDiskRepr::upgrade` for every row. Rows with a
+    /// schema newer than `RowDiskRepr::current_schema` are dropped.
+    ///
+    /// Fails with the first error returned by `RowDiskRepr::upgrade`, after
+    /// which this repr is only partly migrated and shouldn't be used.
+    pub fn migrate(&mut self) -> crate::Result<LoadReport> {
+        let store_version = self.version;
+        let mut report = LoadReport::default();
+        let mut data = Vec::with_capacity(self.data.len());
+        for mut row in std::mem::take(&mut self.data) {
+            // Stores written before rows had a schema (and every version 1
+            // store) describe the shape of their rows with their own version.
+            if row.schema == 0 || store_version < 2 {
+                row.schema = store_version.min(RowDiskRepr::current_schema());
+            }
+            if row.schema > RowDiskRepr::current_schema() {
+                report.skipped += 1;
+                continue;
+            }
+            if row.upgrade()? {
+                report.upgraded += 1;
+            }
+            data.push(row);
+        }
+        self.data = data;
+        report.rows = self.data.len();
+        self.version = Self::current_version();
+        Ok(report)
+    }
+
+    /// Checks that this repr was written by a version this build can read,
+    /// failing with [`crate::Error::UnsupportedVersion`] otherwise.
+    pub fn check_version(&self) -> crate::Result<()> {
+        if (1..=Self::current_version()).contains(&self.version) {
+            Ok(())
+        } else {
+            Err(crate::Error::UnsupportedVersion(self.version))
+        }
+    }
+
+    /// Checks and migrates a copy of this repr, then collects its rows by key.
+    /// Fails with [`crate::Error::DuplicateKey`] if a key appears twice.
+    pub(crate) fn rows_by_key(&self) -> crate::Result<HashMap<String, Row>> {
+        self.check_version()?;
+        let mut disk = self.clone();
+        disk.migrate()?;
+        let mut rows = HashMap::with_capacity(disk.data.len());
+        for row in disk.data {
+            match rows.entry(row.key.clone()) {
+                Entry::Occupied(_) => return Err(crate::Error::duplicate_key(&row.key)),
+                Entry::Vacant(entry) => {
+                    entry.insert(Row::from(row));
+                }
+            }
+        }
+        Ok(rows)
+    }
+
     pub fn new(data: &[RowDiskRepr]) -> Self {
         Self::from_vec(data.to_vec())
     }
@@ -90,6 +292,113 @@ impl StoreDiskRepr {
             data,
         }
     }
+
+    /// Sorts the rows of this repr by key, so that writing it out produces the
+    /// same output for the same data.
+    pub fn sorted_by_key(mut self) -> Self {
+        self.data.sort_by(|a, b| a.key.cmp(&b.key));
+        self
+    }
+
+    /// Computes a CRC32 fingerprint of the rows in this repr. Rows are hashed in
+    /// key order so two reprs holding the same data always produce the same
+    /// fingerprint, regardless of the order they were collected in.
+    pub fn fingerprint(&self) -> u32 {
+        let mut rows: Vec<&RowDiskRepr> = self.data.iter().collect();
+        rows.sort_by(|a, b| a.key.cmp(&b.key));
+        let mut hasher = crc32fast::Hasher::new();
+        for row in rows {
+            hasher.update(row.key.as_bytes());
+            hasher.update(&[0]);
+            row.value.get().hash_into(&mut hasher);
+            hasher.update(&[0]);
+            hasher.update(&row.created.to_le_bytes());
+            hasher.update(&row.updated.to_le_bytes());
+            if let Some(expires_at) = row.expires_at {
+                hasher.update(&expires_at.to_le_bytes());
+            }
+            for (key, value) in &row.meta {
+                hasher.update(key.as_bytes());
+                hasher.update(&[0]);
+                hasher.update(value.as_bytes());
+                hasher.update(&[0]);
+            }
+            if row.deleted {
+                hasher.update(&[1]);
+            }
+        }
+        hasher.finalize()
+    }
+
+    /// Serializes this repr as json and writes it to the file at `path`,
+    /// creating or truncating it as needed.
+    pub fn write_to<P: AsRef<Path>>(&self, path: P) -> crate::Result<()> {
+        self.write_to_with_progress(path, |_| {})
+    }
+
+    /// Same as `write_to`, but writes the rows one at a time and calls
+    /// `progress` with how many have been written after each of them.
+    pub fn write_to_with_progress<P, F>(&self, path: P, mut progress: F) -> crate::Result<()>
+    where
+        P: AsRef<Path>,
+        F: FnMut(usize),
+    {
+        let io = |err: std::io::Error| crate::Error::io(&err);
+        let file = File::create(path).map_err(io)?;
+        let mut writer = BufWriter::new(file);
+        write!(writer, "{{\"version\":{},\"data\":[", self.version).map_err(io)?;
+        for (index, row) in self.data.iter().enumerate() {
+            if index > 0 {
+                writer.write_all(b",").map_err(io)?;
+            }
+            serde_json::to_writer(&mut writer, row).map_err(|err| crate::Error::json_ser(&err))?;
+            progress(index + 1);
+        }
+        writer.write_all(b"]}").map_err(io)?;
+        writer.flush().map_err(io)
+    }
+
+    /// Gets the keys of every row whose timestamps fail
+    /// [`Row::validate_timestamps`].
+    pub fn invalid_timestamps(&self) -> Vec<String> {
+        self.data
+            .iter()
+            .filter(|row| Row::from(*row).validate_timestamps().is_err())
+            .map(|row| row.key.clone())
+            .collect()
+    }
+
+    /// Same as `read_from`, but also validates the timestamps of every row and
+    /// returns the keys of the rows that failed along with the repr.
+    pub fn read_from_validated<P: AsRef<Path>>(path: P) -> crate::Result<(Self, Vec<String>)> {
+        let repr = Self::read_from(path)?;
+        let invalid = repr.invalid_timestamps();
+        Ok((repr, invalid))
+    }
+
+    /// Reads a repr previously written with [`StoreDiskRepr::write_to`],
+    /// migrating it to the current version if needed.
+    pub fn read_from<P: AsRef<Path>>(path: P) -> crate::Result<Self> {
+        Self::read_from_with_report(path).map(|(repr, _)| repr)
+    }
+
+    /// Same as `read_from`, but also returns the [`LoadReport`] from migrating
+    /// the repr.
+    pub fn read_from_with_report<P: AsRef<Path>>(path: P) -> crate::Result<(Self, LoadReport)> {
+        let file = File::open(path).map_err(|err| crate::Error::io(&err))?;
+        let mut repr: Self = serde_json::from_reader(BufReader::new(file))
+            .map_err(|err| crate::Error::json_de(&err))?;
+        repr.check_version()?;
+        let report = repr.migrate()?;
+        Ok((repr, report))
+    }
+}
+
+/// The on-disk form of a `StoreSet`: the rows of every namespace, keyed by
+/// the namespace's name.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct MultiStoreDiskRepr {
+    pub namespaces: HashMap<String, StoreDiskRepr>,
 }
 
 impl<'row> FromIterator<&'row Row> for StoreDiskRepr {
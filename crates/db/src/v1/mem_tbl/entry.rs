@@ -0,0 +1,119 @@
+// Copyright (c) 2022 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use super::StoreLimits;
+use crate::{Row, Value};
+
+/// The row a `with_entry` closure is working on, as it has changed so far.
+#[derive(Debug)]
+pub(crate) struct EntryState {
+    row: Option<Row>,
+    existed: bool,
+    changed: bool,
+}
+
+/// What a store has to do once a `with_entry` closure has returned.
+#[derive(Debug)]
+pub(crate) enum EntryChange {
+    Unchanged,
+    /// Store `row`, which is a new row for the key if `inserted` is set.
+    Set {
+        row: Row,
+        inserted: bool,
+    },
+    /// Delete the key's live row.
+    Deleted,
+}
+
+impl EntryState {
+    /// Starts from `current`, the key's live row if it has one.
+    pub(crate) fn new(current: Option<Row>) -> Self {
+        Self {
+            existed: current.is_some(),
+            row: current,
+            changed: false,
+        }
+    }
+
+    pub(crate) fn into_change(self) -> EntryChange {
+        match (self.changed, self.row) {
+            (false, _) => EntryChange::Unchanged,
+            (true, Some(row)) => EntryChange::Set {
+                row,
+                inserted: !self.existed,
+            },
+            (true, None) if self.existed => EntryChange::Deleted,
+            (true, None) => EntryChange::Unchanged,
+        }
+    }
+}
+
+/// Scoped access to a single key of a store, handed to the closure passed to
+/// `KeyValueStore::with_entry`. It starts out holding the key's live row, if
+/// it has one, and every change made through it is applied to the store once
+/// the closure returns, under the same lock the row was read under.
+#[derive(Debug)]
+pub struct EntryRef<'a> {
+    key: &'a str,
+    state: &'a mut EntryState,
+    limits: &'a StoreLimits,
+}
+
+impl<'a> EntryRef<'a> {
+    pub(crate) fn new(key: &'a str, state: &'a mut EntryState, limits: &'a StoreLimits) -> Self {
+        Self { key, state, limits }
+    }
+
+    pub fn key(&self) -> &str {
+        self.key
+    }
+
+    /// Checks whether the key has a live row, counting changes made through
+    /// this entry.
+    pub fn exists(&self) -> bool {
+        self.state.row.is_some()
+    }
+
+    /// Gets the live row for the key, as changed so far through this entry.
+    pub fn row(&self) -> Option<&Row> {
+        self.state.row.as_ref()
+    }
+
+    /// Gets the value of the live row for the key, see `row`.
+    pub fn value(&self) -> Option<&Value> {
+        self.row().map(Row::value)
+    }
+
+    /// Sets the value of the key, inserting a new row if it has none. Fails
+    /// if `value` doesn't fit the store's limits, leaving the entry as it was.
+    pub fn set(&mut self, value: &str) -> crate::Result<()> {
+        self.limits.check(self.key, value.len())?;
+        match &mut self.state.row {
+            Some(row) => self.state.changed |= row.update(value),
+            None => {
+                self.state.row = Some(Row::create(self.key, value));
+                self.state.changed = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Same as `set`, but fails with [`crate::Error::DuplicateKey`] if the key
+    /// already has a live row.
+    pub fn insert(&mut self, value: &str) -> crate::Result<()> {
+        if self.exists() {
+            return Err(crate::Error::duplicate_key(self.key));
+        }
+        self.set(value)
+    }
+
+    /// Deletes the row for the key, returning it, or `None` if it had none.
+    pub fn delete(&mut self) -> Option<Row> {
+        let row = self.state.row.take();
+        self.state.changed |= row.is_some();
+        row
+    }
+}
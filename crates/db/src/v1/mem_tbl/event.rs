@@ -0,0 +1,279 @@
+// Copyright (c) 2022 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::{self, Receiver, Sender},
+    Mutex, PoisonError,
+};
+
+use prost::Message;
+use serde::{Deserialize, Serialize};
+
+use super::PostWriteHook;
+use crate::{
+    rpc::{row_event, RowUpdate},
+    Row,
+};
+
+/// A change made to a single row, as seen by anyone watching a store.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RowEvent {
+    Inserted(Row),
+    Updated { before: Row, after: Row },
+    Deleted(Row),
+}
+
+impl RowEvent {
+    /// Gets the key of the row this event is about.
+    pub fn key(&self) -> &str {
+        self.row().key()
+    }
+
+    /// Gets the row as it is after this event. For `Deleted` this is the row
+    /// that was deleted.
+    pub fn row(&self) -> &Row {
+        match self {
+            RowEvent::Inserted(row) | RowEvent::Deleted(row) => row,
+            RowEvent::Updated { after, .. } => after,
+        }
+    }
+
+    /// Encodes this event as a protobuf [`crate::rpc::RowEvent`] message. Rows
+    /// are encoded like `Row::to_proto_bytes`.
+    pub fn to_proto_bytes(&self) -> crate::Result<Vec<u8>> {
+        let event = crate::rpc::RowEvent::from(self.clone());
+        let mut bytes = Vec::with_capacity(event.encoded_len());
+        event
+            .encode(&mut bytes)
+            .map_err(|err| crate::Error::proto_encode(&err))?;
+        Ok(bytes)
+    }
+
+    /// Decodes an event previously encoded with `RowEvent::to_proto_bytes`.
+    pub fn from_proto_bytes(bytes: &[u8]) -> crate::Result<Self> {
+        crate::rpc::RowEvent::decode(bytes)
+            .map_err(|err| crate::Error::proto_decode(&err))
+            .and_then(RowEvent::try_from)
+    }
+}
+
+impl Row {
+    /// Wraps this `Row` in a [`RowEvent::Inserted`].
+    pub fn into_event_inserted(self) -> RowEvent {
+        RowEvent::Inserted(self)
+    }
+
+    /// Wraps this `Row`, as it was before an update, and `after` in a
+    /// [`RowEvent::Updated`].
+    pub fn into_event_updated(self, after: Row) -> RowEvent {
+        RowEvent::Updated {
+            before: self,
+            after,
+        }
+    }
+
+    /// Wraps this `Row` in a [`RowEvent::Deleted`].
+    pub fn into_event_deleted(self) -> RowEvent {
+        RowEvent::Deleted(self)
+    }
+}
+
+/// The senders for everyone subscribed to a store's [`RowEvent`]s.
+#[derive(Debug, Default)]
+pub(crate) struct Subscribers {
+    senders: Mutex<Vec<Sender<RowEvent>>>,
+    /// Whether `senders` is non-empty, so stores nobody is watching can skip
+    /// building events (and taking the lock) altogether.
+    watched: AtomicBool,
+    /// Called with every event before it's sent to `senders`.
+    hook: PostWriteHook,
+}
+
+impl Subscribers {
+    pub(crate) fn subscribe(&self) -> Receiver<RowEvent> {
+        let (sender, receiver) = mpsc::channel();
+        let mut senders = self.senders.lock().unwrap_or_else(PoisonError::into_inner);
+        senders.push(sender);
+        self.watched.store(true, Ordering::Release);
+        receiver
+    }
+
+    pub(crate) fn set_hook(&mut self, hook: PostWriteHook) {
+        self.hook = hook;
+    }
+
+    pub(crate) fn is_watched(&self) -> bool {
+        self.hook.is_set() || self.watched.load(Ordering::Acquire)
+    }
+
+    /// Passes the event made by `make` to the hook and every subscriber,
+    /// dropping the subscribers whose receiver is gone. `make` only runs if
+    /// someone is watching.
+    pub(crate) fn send<F: FnOnce() -> RowEvent>(&self, make: F) {
+        if !self.is_watched() {
+            return;
+        }
+        let event = make();
+        self.hook.call(&event);
+        if !self.watched.load(Ordering::Acquire) {
+            return;
+        }
+        let mut senders = self.senders.lock().unwrap_or_else(PoisonError::into_inner);
+        senders.retain(|sender| sender.send(event.clone()).is_ok());
+        self.watched.store(!senders.is_empty(), Ordering::Release);
+    }
+
+    pub(crate) fn inserted(&self, row: &Row) {
+        self.send(|| row.clone().into_event_inserted());
+    }
+
+    /// Sends a [`RowEvent::Updated`] from `before` to `after`. Callers only
+    /// copy the row `before` while `is_watched`, so nothing is sent when
+    /// it's `None`.
+    pub(crate) fn updated(&self, before: Option<Row>, after: &Row) {
+        if let Some(before) = before {
+            self.send(|| before.into_event_updated(after.clone()));
+        }
+    }
+
+    /// Sends a [`RowEvent::Updated`] if `replaced` was a live row, or a
+    /// [`RowEvent::Inserted`] otherwise.
+    pub(crate) fn replaced(&self, replaced: Option<&Row>, row: &Row) {
+        match replaced.filter(|before| before.is_live()) {
+            Some(before) => self.send(|| before.clone().into_event_updated(row.clone())),
+            None => self.inserted(row),
+        }
+    }
+
+    pub(crate) fn deleted(&self, row: &Row) {
+        self.send(|| row.clone().into_event_deleted());
+    }
+}
+
+impl From<RowEvent> for crate::rpc::RowEvent {
+    fn from(event: RowEvent) -> Self {
+        let event = match event {
+            RowEvent::Inserted(row) => row_event::Event::Inserted(row.into()),
+            RowEvent::Updated { before, after } => row_event::Event::Updated(RowUpdate {
+                before: Some(before.into()),
+                after: Some(after.into()),
+            }),
+            RowEvent::Deleted(row) => row_event::Event::Deleted(row.into()),
+        };
+        Self { event: Some(event) }
+    }
+}
+
+/// Fails with [`crate::Error::ProtoDecode`] if the message is missing its event
+/// or either half of an update.
+impl TryFrom<crate::rpc::RowEvent> for RowEvent {
+    type Error = crate::Error;
+
+    fn try_from(event: crate::rpc::RowEvent) -> crate::Result<Self> {
+        let missing =
+            |what: &str| crate::Error::ProtoDecode(format!("row event is missing {}", what));
+        match event.event.ok_or_else(|| missing("its event"))? {
+            row_event::Event::Inserted(row) => Ok(RowEvent::Inserted(row.into())),
+            row_event::Event::Updated(RowUpdate { before, after }) => Ok(RowEvent::Updated {
+                before: before
+                    .ok_or_else(|| missing("the row before the update"))?
+                    .into(),
+                after: after
+                    .ok_or_else(|| missing("the row after the update"))?
+                    .into(),
+            }),
+            row_event::Event::Deleted(row) => Ok(RowEvent::Deleted(row.into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn events() -> Vec<RowEvent> {
+        let mut before = Row::new("key", "old", 1_000, 2_000);
+        before.set_meta("source", "test");
+        let mut after = before.clone();
+        after.update("new");
+        vec![
+            Row::new("key", "value", 1_000, 2_000).into_event_inserted(),
+            before.into_event_updated(after),
+            Row::new("key", "gone", 1_000, 3_000).into_event_deleted(),
+        ]
+    }
+
+    #[test]
+    fn accessors() {
+        for event in events() {
+            assert_eq!(event.key(), "key");
+        }
+        let events = events();
+        assert_eq!(events[1].row().value(), "new");
+        assert_eq!(events[2].row().value(), "gone");
+    }
+
+    #[test]
+    fn json_roundtrip() {
+        for event in events() {
+            let json = serde_json::to_string(&event).expect("unable to serialize event");
+            let back: RowEvent = serde_json::from_str(&json).expect("unable to deserialize event");
+            assert_eq!(back, event);
+        }
+    }
+
+    #[test]
+    fn proto_roundtrip() {
+        for event in events() {
+            let bytes = event.to_proto_bytes().expect("unable to encode event");
+            let back = RowEvent::from_proto_bytes(&bytes).expect("unable to decode event");
+            // Protobuf only carries the fields `RowData` has.
+            match (&back, &event) {
+                (RowEvent::Inserted(a), RowEvent::Inserted(b))
+                | (RowEvent::Deleted(a), RowEvent::Deleted(b)) => {
+                    assert!(a.content_eq(b));
+                    assert_eq!((a.created(), a.updated()), (b.created(), b.updated()));
+                }
+                (
+                    RowEvent::Updated { before, after },
+                    RowEvent::Updated {
+                        before: expected_before,
+                        after: expected_after,
+                    },
+                ) => {
+                    assert!(before.content_eq(expected_before));
+                    assert!(after.content_eq(expected_after));
+                    assert_eq!(before.get_meta("source"), Some("test"));
+                    assert_eq!(after.updated(), expected_after.updated());
+                }
+                _ => panic!("expected {:?} but decoded {:?}", event, back),
+            }
+        }
+    }
+
+    #[test]
+    fn proto_missing_rows() {
+        let empty = crate::rpc::RowEvent { event: None };
+        assert!(matches!(
+            RowEvent::try_from(empty),
+            Err(crate::Error::ProtoDecode(_))
+        ));
+        let half = crate::rpc::RowEvent {
+            event: Some(row_event::Event::Updated(RowUpdate {
+                before: Some(Row::create("key", "value").into()),
+                after: None,
+            })),
+        };
+        assert_eq!(
+            RowEvent::try_from(half),
+            Err(crate::Error::ProtoDecode(
+                "row event is missing the row after the update".to_string()
+            ))
+        );
+    }
+}
@@ -4,15 +4,66 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::{collections::HashMap, sync::Mutex};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError,
+    },
+};
 
-use crate::{Row, RowDiskRepr, StoreByteRepr, StoreDiskRepr};
+use prost::Message;
+
+use super::{
+    checked_apply, largest_rows, run_unpoisoned, sample_with, upsert_base, CasOutcome,
+    CompressionOptions, DeleteManyReport, DeletePolicy, EntryChange, EntryRef, EntryState,
+    KeyPattern, MergeReport, PoisonPolicy, PostWriteHook, PreWriteHook, StoreDiff, StoreLimits,
+    StoreSize, StoreStats, Subscribers,
+};
+use crate::{
+    MergeStrategy, Op, OpResult, Row, RowDiskRepr, RowEvent, SetManyOutcome, SetOutcome,
+    StoreByteRepr, StoreDiskRepr, StoreIntoIter, StoreSnapshot, StoreStatsSnapshot, Value,
+};
 
 pub type Data = HashMap<String, Row>;
 
+/// Removes a store from `ITERATING` when dropped, even if the callback it was
+/// running panicked.
+struct Iterating(usize);
+
+impl Drop for Iterating {
+    fn drop(&mut self) {
+        ITERATING.with(|stores| {
+            let mut stores = stores.borrow_mut();
+            if let Some(i) = stores.iter().rposition(|&store| store == self.0) {
+                stores.remove(i);
+            }
+        });
+    }
+}
+
+thread_local! {
+    /// Addresses of the stores this thread is currently iterating with
+    /// `KeyValueStore::try_for_each`, used to catch re-entrant calls.
+    static ITERATING: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
 #[derive(Debug, Default)]
 pub struct KeyValueStore {
-    data: Mutex<Data>,
+    data: RwLock<Data>,
+    limits: StoreLimits,
+    delete_policy: DeletePolicy,
+    poison_policy: PoisonPolicy,
+    compression: Option<CompressionOptions>,
+    stats: StoreStats,
+    events: Subscribers,
+    pre_write: PreWriteHook,
+    read_only: AtomicBool,
+    /// How many times `data` has been locked, for reading or writing, so tests
+    /// can check how often an operation takes the lock.
+    #[cfg(test)]
+    lock_count: std::sync::atomic::AtomicUsize,
 }
 
 impl KeyValueStore {
@@ -20,816 +71,5074 @@ impl KeyValueStore {
         Self::default()
     }
 
-    pub fn get_clone(&self, key: &str) -> crate::Result<Row> {
-        self.data
-            .lock()
-            .map_err(|err| crate::Error::mutex_poisoned(&err))
-            .and_then(|data| {
-                data.get(key)
-                    .cloned()
-                    .ok_or(crate::Error::key_not_found(key))
-            })
+    /// Creates an empty store that rejects keys longer than `max_key_bytes` and
+    /// values larger than `max_value_bytes`. Stores created any other way have
+    /// no limits.
+    pub fn with_limits(max_key_bytes: usize, max_value_bytes: usize) -> Self {
+        Self {
+            limits: StoreLimits::new(max_key_bytes, max_value_bytes),
+            ..Self::default()
+        }
     }
 
-    pub fn insert(&self, key: &str, value: &str) -> crate::Result<()> {
-        self.data
-            .lock()
-            .map_err(|err| crate::Error::mutex_poisoned(&err))
-            .and_then(|mut data| {
-                if data.contains_key(key) {
-                    Err(crate::Error::duplicate_key(key))
-                } else {
-                    data.insert(key.to_string(), Row::create(key, value));
-                    Ok(())
-                }
-            })
+    /// Creates an empty store that holds at most `max_rows` rows. Writes that
+    /// would add rows past the limit fail with
+    /// [`crate::Error::CapacityExceeded`] and change nothing, while writes to
+    /// keys the store already holds are always allowed. Batches are checked as
+    /// a whole, so they are either fully applied or rejected.
+    ///
+    /// Rows are counted like `len_raw`, so expired rows and tombstones take up
+    /// capacity until they are purged. Bulk operations like `merge_from` or
+    /// loading from disk aren't checked.
+    pub fn with_capacity_limit(max_rows: usize) -> Self {
+        Self {
+            limits: StoreLimits::with_max_rows(max_rows),
+            ..Self::default()
+        }
     }
 
-    pub fn insert_row(&self, row: &Row) -> crate::Result<()> {
-        self.data
-            .lock()
-            .map_err(|err| crate::Error::mutex_poisoned(&err))
-            .and_then(|mut data| {
-                let key = row.key().to_string();
-                if data.contains_key(&key) {
-                    Err(crate::Error::duplicate_key(row.key()))
-                } else {
-                    data.insert(key, row.clone());
-                    Ok(())
-                }
-            })
+    /// Gets how many more rows this store can take before it reaches its
+    /// capacity limit, or `None` if it has no limit (the default).
+    pub fn remaining_capacity(&self) -> crate::Result<Option<usize>> {
+        self.read()
+            .map(|data| self.limits.remaining_capacity(data.len()))
     }
 
-    pub fn set_or_insert(&self, key: &str, value: &str) -> crate::Result<()> {
-        self.data
-            .lock()
-            .map_err(|err| crate::Error::mutex_poisoned(&err))
-            .and_then(|mut data| {
-                let k = key.to_string();
-                data.entry(k)
-                    .and_modify(|v| v.update(value))
-                    .or_insert(Row::create(key, value));
-                Ok(())
-            })
+    /// Checks whether this store holds the same live rows as `other`, with the
+    /// same values, metadata and (unless `ignore_timestamps` is set)
+    /// timestamps. Versions and value history aren't compared.
+    ///
+    /// `other` is read into a snapshot first, so only one store is ever locked
+    /// at a time.
+    pub fn content_eq(&self, other: &Self, ignore_timestamps: bool) -> crate::Result<bool> {
+        if std::ptr::eq(self, other) {
+            return Ok(true);
+        }
+        let theirs = other.rows()?;
+        self.read()
+            .map(|data| super::rows_equal(data.values(), &theirs, ignore_timestamps))
     }
 
-    pub fn set_or_insert_row(&self, row: &Row) -> crate::Result<()> {
-        self.data
-            .lock()
-            .map_err(|err| crate::Error::mutex_poisoned(&err))
-            .and_then(|mut data| {
-                // TODO: Is this 'to_string' avoidable?
-                data.entry(row.key().to_string())
-                    .and_modify(|v| v.overwrite_with(row))
-                    .or_insert(row.clone());
-                Ok(())
-            })
+    /// Fails with [`crate::Error::CapacityExceeded`] if storing a row for each
+    /// of `keys` would take `data` past this store's capacity limit.
+    fn check_capacity<'k>(
+        &self,
+        data: &Data,
+        keys: impl IntoIterator<Item = &'k str>,
+    ) -> crate::Result<()> {
+        self.limits
+            .check_capacity(|| data.len(), keys, |key| data.contains_key(key))
     }
 
-    pub fn contains(&self, key: &str) -> crate::Result<bool> {
-        self.data
-            .lock()
-            .map_err(|err| crate::Error::mutex_poisoned(&err))
-            .map(|data| data.contains_key(key))
+    /// Sets what `delete` does with the rows it deletes, see [`DeletePolicy`].
+    pub fn with_delete_policy(mut self, policy: DeletePolicy) -> Self {
+        self.delete_policy = policy;
+        self
     }
 
-    pub fn len(&self) -> crate::Result<usize> {
-        self.data
-            .lock()
-            .map_err(|err| crate::Error::mutex_poisoned(&err))
-            .map(|data| data.len())
+    /// Sets what this store does once a thread has panicked while holding its
+    /// lock, see [`PoisonPolicy`].
+    pub fn with_poison_policy(mut self, policy: PoisonPolicy) -> Self {
+        self.poison_policy = policy;
+        self
     }
 
-    pub fn delete(&self, key: &str) -> crate::Result<Row> {
-        self.data
-            .lock()
-            .map_err(|err| crate::Error::mutex_poisoned(&err))
-            .and_then(|mut data| data.remove(key).ok_or(crate::Error::key_not_found(key)))
+    /// Clears the poisoning left by a thread that panicked while holding this
+    /// store's lock, so [`PoisonPolicy::Error`] stops failing every call.
+    /// Returns whether the store was poisoned.
+    pub fn heal(&self) -> bool {
+        let poisoned = self.data.is_poisoned();
+        self.data.clear_poison();
+        poisoned
     }
 
-    pub fn to_bytes(&self) -> crate::Result<Vec<u8>> {
-        self.data
-            .lock()
-            .map_err(|err| crate::Error::mutex_poisoned(&err))
-            .and_then(|data| serde_json::to_vec(&*data).map_err(|err| crate::Error::json_ser(&err)))
+    /// Makes this store reject every write with [`crate::Error::ReadOnly`]
+    /// while reads keep working, or lets writes through again. Writes still
+    /// running when the flag flips may complete, but a write still waiting for
+    /// the lock is rejected once it gets it.
+    ///
+    /// The flag is checked when a write takes the lock and never on the read
+    /// path, so reads cost the same either way. Expired rows are reported as
+    /// not found as usual but left in place while the store is read-only.
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::SeqCst);
     }
 
-    pub fn from_bytes(bytes: &[u8]) -> crate::Result<Self> {
-        serde_json::from_slice(bytes)
-            .map_err(|err| crate::Error::json_de(&err))
-            .map(|data| Self {
-                data: Mutex::new(data),
-            })
+    /// Checks whether this store is currently rejecting writes, see
+    /// `set_read_only`.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::SeqCst)
     }
 
-    pub fn to_disk(&self) -> crate::Result<StoreDiskRepr> {
-        self.data
-            .lock()
-            .map_err(|err| crate::Error::mutex_poisoned(&err))
-            .map(|data| data.values().cloned().collect::<Vec<_>>())
-            .map(|rows| rows.into())
+    /// Handles a poisoned lock according to this store's [`PoisonPolicy`].
+    fn recover<G>(&self, err: PoisonError<G>) -> crate::Result<G> {
+        match self.poison_policy {
+            PoisonPolicy::Error => Err(crate::Error::mutex_poisoned(&err)),
+            PoisonPolicy::Recover => Ok(err.into_inner()),
+        }
     }
 
-    pub fn into_disk(self) -> crate::Result<StoreDiskRepr> {
-        let disk = self
-            .data
-            .into_inner()
-            .unwrap_or_else(|e| e.into_inner())
-            .into_values()
-            .collect::<Vec<_>>()
-            .into();
-        Ok(disk)
+    /// Makes this store compress large values as they are written, see
+    /// [`CompressionOptions`]. Values are also kept compressed by `to_bytes`
+    /// and `to_disk`.
+    pub fn with_options(mut self, compression: CompressionOptions) -> Self {
+        self.compression = Some(compression);
+        self
     }
 
-    pub fn from_disk(disk: &StoreDiskRepr) -> crate::Result<Self> {
-        todo!()
+    /// Sets a check run on the key and value of every write before it's made,
+    /// replacing any check set before. If `hook` fails nothing is written and
+    /// its error is returned to the caller, so it can be used to enforce
+    /// naming conventions and the like.
+    ///
+    /// The hook runs for single-row writes (inserts, sets, `update_with`,
+    /// `upsert_with`, `compare_and_swap` and renames, with the new row) and
+    /// for every pair of `insert_many` and `set_many`, after the write has
+    /// been checked against the store's limits and existing rows, so it isn't
+    /// called for duplicate inserts. It isn't called by bulk operations like
+    /// `merge_from` or loading from disk. The store is write-locked while the
+    /// hook runs, so it must not use the store itself.
+    pub fn set_pre_write_hook<F>(&mut self, hook: F)
+    where
+        F: Fn(&str, &str) -> crate::Result<()> + Send + Sync + 'static,
+    {
+        self.pre_write = PreWriteHook::new(hook);
     }
-}
 
-impl super::Store for KeyValueStore {
-    fn get_clone(&self, key: &str) -> crate::Result<Row> {
-        KeyValueStore::get_clone(self, key)
+    /// Sets a callback run with every [`RowEvent`] this store sends to its
+    /// subscribers (see `subscribe`), replacing any callback set before. It
+    /// runs after the write has been made but before the store is unlocked,
+    /// so it sees the final row and must not use the store itself.
+    pub fn set_post_write_hook<F>(&mut self, hook: F)
+    where
+        F: Fn(&RowEvent) + Send + Sync + 'static,
+    {
+        self.events.set_hook(PostWriteHook::new(hook));
     }
 
-    fn insert(&self, key: &str, value: &str) -> crate::Result<()> {
-        KeyValueStore::insert(self, key, value)
+    /// Locks `data` for reading, shared with any other readers. Fails with
+    /// [`crate::Error::ReentrantAccess`] when called from inside one of this
+    /// store's iteration callbacks: taking a second read lock on the same
+    /// thread can deadlock if a writer is already waiting.
+    fn read(&self) -> crate::Result<RwLockReadGuard<'_, Data>> {
+        #[cfg(test)]
+        self.lock_count
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        if self.is_iterating() {
+            return Err(crate::Error::ReentrantAccess);
+        }
+        self.data.read().or_else(|err| self.recover(err))
     }
 
-    fn insert_row(&self, row: &Row) -> crate::Result<()> {
-        KeyValueStore::insert_row(self, row)
+    /// Locks `data` for writing. Fails with [`crate::Error::ReentrantAccess`]
+    /// instead of deadlocking when called from inside one of this store's
+    /// iteration callbacks.
+    ///
+    /// Every change to the store goes through here, so this is also where
+    /// writes are turned away with [`crate::Error::ReadOnly`] while the store
+    /// is read-only. The flag is checked again once the lock is held, so a
+    /// write that was waiting for the lock when the store was made read-only
+    /// is turned away too.
+    fn write(&self) -> crate::Result<RwLockWriteGuard<'_, Data>> {
+        if self.is_read_only() {
+            return Err(crate::Error::ReadOnly);
+        }
+        let data = match self.data.try_write() {
+            Ok(data) => data,
+            Err(TryLockError::Poisoned(err)) => self.recover(err)?,
+            Err(TryLockError::WouldBlock) if self.is_iterating() => {
+                return Err(crate::Error::ReentrantAccess)
+            }
+            Err(TryLockError::WouldBlock) => self.data.write().or_else(|err| self.recover(err))?,
+        };
+        if self.is_read_only() {
+            return Err(crate::Error::ReadOnly);
+        }
+        #[cfg(test)]
+        self.lock_count
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Ok(data)
     }
 
-    fn set_or_insert(&self, key: &str, value: &str) -> crate::Result<()> {
-        KeyValueStore::set_or_insert(self, key, value)
+    fn address(&self) -> usize {
+        self as *const Self as usize
     }
 
-    fn set_or_insert_row(&self, row: &Row) -> crate::Result<()> {
-        KeyValueStore::set_or_insert_row(self, row)
+    fn is_iterating(&self) -> bool {
+        ITERATING.with(|stores| stores.borrow().contains(&self.address()))
     }
 
-    fn contains(&self, key: &str) -> crate::Result<bool> {
-        KeyValueStore::contains(self, key)
+    /// Marks this store as running a callback on this thread until the guard
+    /// is dropped, so any method called on it meanwhile fails with
+    /// [`crate::Error::ReentrantAccess`] instead of deadlocking.
+    fn enter_callback(&self) -> Iterating {
+        ITERATING.with(|stores| stores.borrow_mut().push(self.address()));
+        Iterating(self.address())
     }
 
-    fn len(&self) -> crate::Result<usize> {
-        KeyValueStore::len(self)
+    /// Calls `f` with every live (unexpired and not deleted) row, stopping as
+    /// soon as it returns `false`. Returns whether every row was visited.
+    ///
+    /// The store stays locked while `f` runs, so `f` must not use this store:
+    /// any method called on it from inside `f` fails with
+    /// [`crate::Error::ReentrantAccess`].
+    pub fn try_for_each<F: FnMut(&Row) -> bool>(&self, f: F) -> crate::Result<bool> {
+        let data = self.read()?;
+        let _iterating = self.enter_callback();
+        Ok(data.values().filter(|row| row.is_live()).all(f))
     }
 
-    fn delete(&self, key: &str) -> crate::Result<Row> {
-        KeyValueStore::delete(self, key)
+    /// Calls `f` with every live row, see `try_for_each`.
+    pub fn for_each<F: FnMut(&Row)>(&self, mut f: F) -> crate::Result<()> {
+        self.try_for_each(|row| {
+            f(row);
+            true
+        })
+        .map(|_| ())
     }
 
-    fn to_disk_repr(&self) -> crate::Result<StoreDiskRepr> {
-        KeyValueStore::to_disk_repr(self)
+    /// Folds every live row into an accumulator, see `try_for_each`.
+    pub fn fold<B, F: FnMut(B, &Row) -> B>(&self, init: B, mut f: F) -> crate::Result<B> {
+        let mut acc = Some(init);
+        self.for_each(|row| acc = acc.take().map(|acc| f(acc, row)))?;
+        Ok(acc.expect("KeyValueStore::fold - accumulator is always put back"))
     }
-}
 
-impl<'s> FromIterator<(&'s str, Row)> for KeyValueStore {
-    fn from_iter<T: IntoIterator<Item = (&'s str, Row)>>(iter: T) -> Self {
-        let mut data: HashMap<String, Row> =
-            iter.into_iter().map(|(s, r)| (s.to_string(), r)).collect();
-        Self {
-            data: Mutex::new(data),
-        }
+    /// Counts the live rows matching `predicate`, see `try_for_each`.
+    pub fn count_where<F: FnMut(&Row) -> bool>(&self, mut predicate: F) -> crate::Result<usize> {
+        self.fold(0, |count, row| count + usize::from(predicate(row)))
     }
-}
 
-impl<'t, 's: 't> FromIterator<&'t (&'s str, Row)> for KeyValueStore {
-    fn from_iter<T: IntoIterator<Item = &'t (&'s str, Row)>>(iter: T) -> Self {
-        let mut data: HashMap<String, Row> = iter
-            .into_iter()
-            .map(|(s, r)| (s.to_string(), r.clone()))
-            .collect();
-        Self {
-            data: Mutex::new(data),
-        }
+    /// Gets every live row matching `predicate`, sorted by key, see
+    /// `try_for_each`.
+    pub fn find<F: Fn(&Row) -> bool>(&self, predicate: F) -> crate::Result<Vec<Row>> {
+        let mut rows = self.fold(Vec::new(), |mut rows, row| {
+            if predicate(row) {
+                rows.push(row.clone());
+            }
+            rows
+        })?;
+        rows.sort();
+        Ok(rows)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use pretty_assertions::{assert_eq, assert_ne};
+    /// Gets the first live row the scan reaches that matches `predicate`,
+    /// which could be any of them, and stops scanning there. See
+    /// `try_for_each`.
+    pub fn find_first<F: Fn(&Row) -> bool>(&self, predicate: F) -> crate::Result<Option<Row>> {
+        let mut found = None;
+        self.try_for_each(|row| {
+            if predicate(row) {
+                found = Some(row.clone());
+            }
+            found.is_none()
+        })?;
+        Ok(found)
+    }
 
-    mod helpers {
-        use super::super::*;
-        use pretty_assertions::{assert_eq, assert_ne};
+    /// Checks whether any live row holds `value`, stopping at the first one.
+    /// This is a linear scan over the whole store.
+    pub fn contains_value(&self, value: &str) -> crate::Result<bool> {
+        self.find_first(|row| row.value() == value)
+            .map(|row| row.is_some())
+    }
 
-        pub fn store_with(values: &[(&str, &str)]) -> KeyValueStore {
-            let values = values.to_vec();
-            let mut store = KeyValueStore::empty();
-            for &(key, value) in &values {
-                assert!(
-                    store.insert(key, value).is_ok(),
-                    "store_with - failed to insert ({}, {})",
-                    key,
-                    value
-                );
+    /// Gets the keys of every live row holding `value`, sorted. This is a
+    /// linear scan over the whole store.
+    pub fn keys_with_value(&self, value: &str) -> crate::Result<Vec<String>> {
+        let mut keys = self.fold(Vec::new(), |mut keys, row| {
+            if row.value() == value {
+                keys.push(row.key().to_string());
             }
+            keys
+        })?;
+        keys.sort();
+        Ok(keys)
+    }
 
-            assert_eq!(
-                store.len().expect("store_with - failed to get length"),
-                values.len()
-            );
-
-            store
+    fn compress(&self, row: &mut Row) {
+        if let Some(options) = &self.compression {
+            row.compress(options.threshold);
         }
+    }
 
-        pub fn fill_single_thread(values: usize) -> KeyValueStore {
-            let store = KeyValueStore::empty();
-            for i in 0..values {
-                let key = format!("key{}", i);
-                let value = format!("value{}", i);
-                assert!(
-                    store.insert(key.as_str(), value.as_str()).is_ok(),
-                    "fill_single_thread - unable to insert ({},{})",
-                    key,
-                    value
-                );
+    /// Gets a copy of the row for `key`. Expired rows are removed and reported
+    /// as not found, as are rows that have been deleted.
+    ///
+    /// Only takes a read lock, unless the row has expired and needs removing.
+    pub fn get_clone(&self, key: &str) -> crate::Result<Row> {
+        let result = self.lookup(key);
+        self.stats.get(&result);
+        result
+    }
+
+    fn lookup(&self, key: &str) -> crate::Result<Row> {
+        match self.read()?.get(key) {
+            Some(row) if row.is_expired() => {}
+            row => {
+                return row
+                    .filter(|row| !row.is_deleted())
+                    .cloned()
+                    .ok_or(crate::Error::key_not_found(key))
             }
+        }
+        if self.is_read_only() {
+            return Err(crate::Error::key_not_found(key));
+        }
+        self.write()
+            .and_then(|mut data| Self::get_locked(&mut data, key))
+    }
 
-            assert_eq!(
-                store
-                    .len()
-                    .expect("fill_single_thread - unable to get length"),
-                values,
-                "fill_single_thread - did not add the expected number of values",
-            );
+    /// Gets a copy of the row for each of `keys`, locking the store only once.
+    /// Each key is answered separately, in order, so missing keys show up as
+    /// [`crate::Error::KeyNotFound`] without failing the others. This only
+    /// takes a read lock, so expired rows are reported as not found but left
+    /// for a later write to remove.
+    pub fn get_many(&self, keys: &[&str]) -> crate::Result<Vec<(String, crate::Result<Row>)>> {
+        self.read().map(|data| {
+            keys.iter()
+                .map(|&key| {
+                    let row = Self::get_live(&data, key);
+                    self.stats.get(&row);
+                    (key.to_string(), row)
+                })
+                .collect()
+        })
+    }
 
-            store
+    /// Same as `get_many`, but only returns the rows that were found.
+    pub fn get_existing(&self, keys: &[&str]) -> crate::Result<Vec<Row>> {
+        self.read().map(|data| {
+            keys.iter()
+                .filter_map(|&key| {
+                    let row = Self::get_live(&data, key);
+                    self.stats.get(&row);
+                    row.ok()
+                })
+                .collect()
+        })
+    }
+
+    fn get_live(data: &Data, key: &str) -> crate::Result<Row> {
+        data.get(key)
+            .filter(|row| row.is_live())
+            .cloned()
+            .ok_or(crate::Error::key_not_found(key))
+    }
+
+    fn get_locked(data: &mut Data, key: &str) -> crate::Result<Row> {
+        if data.get(key).map_or(false, Row::is_expired) {
+            data.remove(key);
         }
+        data.get(key)
+            .filter(|row| !row.is_deleted())
+            .cloned()
+            .ok_or(crate::Error::key_not_found(key))
+    }
 
-        pub fn fill_multi_thread(values: usize, threads: usize) -> KeyValueStore {
-            use std::sync::Arc;
-            use std::thread;
-
-            if values == 0 {
-                eprintln!("fill_multi_thread - called with values = 0");
-                return KeyValueStore::empty();
-            }
-
-            if threads < 2 {
-                return fill_single_thread(values);
-            }
-
-            let step_size = values / threads;
-            let store = Arc::new(KeyValueStore::empty());
-            let mut ts = Vec::new();
-
-            for t in 0..(threads - 1) {
-                let clone = Arc::clone(&store);
-                let start = t * step_size;
-                let end = start + step_size;
-                // println!("Starting thread #{} with range {}..{}", t + 1, start, end);
-                ts.push(thread::spawn(move || {
-                    for i in start..end {
-                        let key = format!("key{}", i);
-                        let value = format!("value{}", i);
-                        assert!(
-                            clone.insert(key.as_str(), value.as_str()).is_ok(),
-                            "fill_multi_thread - T{} - unable to insert ({},{})",
-                            t + 1,
-                            key,
-                            value
-                        );
-                    }
-                    drop(clone);
-                }));
+    /// Gets the live row for `key`, or else inserts a row holding the value made
+    /// by `make` and returns that, all under a single lock. Two racing callers
+    /// both get the same row and `make` only runs for the one that inserts it.
+    pub fn get_or_insert_with<F: FnOnce() -> String>(
+        &self,
+        key: &str,
+        make: F,
+    ) -> crate::Result<Row> {
+        self.limits.check(key, 0)?;
+        self.write().and_then(|mut data| {
+            if let Ok(row) = Self::get_locked(&mut data, key) {
+                self.stats.get_hit();
+                return Ok(row);
             }
+            self.stats.get_miss();
+            self.check_capacity(&data, [key])?;
+            let value = make();
+            self.limits.check(key, value.len())?;
+            self.pre_write.check(key, &value)?;
+            self.stats.inserted(1);
+            Ok(self.store_locked(&mut data, Row::create(key, &value)))
+        })
+    }
 
-            for i in ((threads - 1) * step_size)..values {
-                let key = format!("key{}", i);
-                let value = format!("value{}", i);
-                assert!(
-                    store.insert(key.as_str(), value.as_str()).is_ok(),
-                    "fill_multi_thread - unable to insert ({},{})",
-                    key,
-                    value
-                );
+    /// Same as `get_or_insert_with`, with a value that's already been made.
+    pub fn get_or_insert(&self, key: &str, value: &str) -> crate::Result<Row> {
+        self.get_or_insert_with(key, || value.to_string())
+    }
+
+    pub fn insert(&self, key: &str, value: &str) -> crate::Result<()> {
+        self.limits.check(key, value.len())?;
+        self.write().and_then(|mut data| {
+            if data.get(key).map_or(false, Row::is_live) {
+                Err(crate::Error::duplicate_key(key))
+            } else {
+                self.check_capacity(&data, [key])?;
+                self.pre_write.check(key, value)?;
+                let mut row = Row::create(key, value);
+                self.compress(&mut row);
+                let row = data.entry(key.to_string()).insert_entry(row);
+                self.stats.inserted(1);
+                self.events.inserted(row.get());
+                Ok(())
+            }
+        })
+    }
+
+    pub fn insert_row(&self, row: &Row) -> crate::Result<()> {
+        self.limits.check_row(row)?;
+        self.write().and_then(|mut data| {
+            let key = row.key().to_string();
+            if data.get(&key).map_or(false, Row::is_live) {
+                Err(crate::Error::duplicate_key(row.key()))
+            } else {
+                self.check_capacity(&data, [row.key()])?;
+                self.pre_write.check_row(row)?;
+                let mut row = row.clone();
+                self.compress(&mut row);
+                let row = data.entry(key).insert_entry(row);
+                self.stats.inserted(1);
+                self.events.inserted(row.get());
+                Ok(())
             }
+        })
+    }
 
-            let mut i = 1;
-            for handle in ts.into_iter() {
-                handle.join().expect(
-                    format!("fill_multi_thread - unable to join thread {}", i + 1).as_str(),
-                );
-                i += 1;
+    /// Inserts every pair under a single lock. If any key already exists, or
+    /// appears twice in `pairs`, nothing is inserted and the first conflicting
+    /// key is reported as [`crate::Error::DuplicateKey`].
+    pub fn insert_many(&self, pairs: &[(&str, &str)]) -> crate::Result<usize> {
+        for &(key, value) in pairs {
+            self.limits.check(key, value.len())?;
+        }
+        self.write().and_then(|mut data| {
+            let mut seen = HashSet::with_capacity(pairs.len());
+            for &(key, _) in pairs {
+                if !seen.insert(key) || data.get(key).map_or(false, Row::is_live) {
+                    return Err(crate::Error::duplicate_key(key));
+                }
+            }
+            self.check_capacity(&data, pairs.iter().map(|&(key, _)| key))?;
+            for &(key, value) in pairs {
+                self.pre_write.check(key, value)?;
             }
+            for &(key, value) in pairs {
+                let mut row = Row::create(key, value);
+                self.compress(&mut row);
+                let row = data.entry(key.to_string()).insert_entry(row);
+                self.events.inserted(row.get());
+            }
+            self.stats.inserted(pairs.len());
+            Ok(pairs.len())
+        })
+    }
 
-            let inner: KeyValueStore =
-                Arc::try_unwrap(store).expect("fill_multi_thread - unable to take inner store");
+    /// Sets the value of `key`, inserting a new row if it doesn't exist (or has
+    /// expired or been deleted). Returns a copy of the row it replaced, taken
+    /// before the update, or `None` if it inserted a new one.
+    pub fn set_or_insert(&self, key: &str, value: &str) -> crate::Result<Option<Row>> {
+        self.limits.check(key, value.len())?;
+        self.write().and_then(|mut data| {
+            self.check_capacity(&data, [key])?;
+            self.pre_write.check(key, value)?;
+            let (outcome, previous) = self.set_or_insert_locked(&mut data, key, value);
+            self.stats.set(outcome);
+            Ok(previous)
+        })
+    }
 
-            assert_eq!(
-                inner
-                    .len()
-                    .expect("fill_multi_thread - unable to get length"),
-                values,
-                "fill_multi_thread - did not add the expected number of values",
-            );
-            inner
+    /// Calls `set_or_insert` for every pair under a single lock. Every pair is
+    /// checked against the store's limits before anything is set.
+    pub fn set_many(&self, pairs: &[(&str, &str)]) -> crate::Result<SetManyOutcome> {
+        for &(key, value) in pairs {
+            self.limits.check(key, value.len())?;
         }
+        self.write().and_then(|mut data| {
+            self.check_capacity(&data, pairs.iter().map(|&(key, _)| key))?;
+            for &(key, value) in pairs {
+                self.pre_write.check(key, value)?;
+            }
+            let mut outcome = SetManyOutcome::default();
+            for &(key, value) in pairs {
+                let (set, _) = self.set_or_insert_locked(&mut data, key, value);
+                self.stats.set(set);
+                outcome.record(set);
+            }
+            Ok(outcome)
+        })
     }
 
-    #[test]
-    fn collect() {
-        let data = vec![
-            ("key1", Row::create("key1", "value1")),
-            ("key2", Row::create("key2", "value2")),
-            ("key3", Row::create("key3", "value3")),
-        ];
+    /// Sets `key` to `value`, returning what that did along with a copy of the
+    /// live row it replaced.
+    fn set_or_insert_locked(
+        &self,
+        data: &mut Data,
+        key: &str,
+        value: &str,
+    ) -> (SetOutcome, Option<Row>) {
+        let mut outcome = SetOutcome::Inserted;
+        let mut previous = None;
+        let row = data
+            .entry(key.to_string())
+            .and_modify(|v| {
+                if !v.is_live() {
+                    *v = Row::create(key, value);
+                    return;
+                }
+                previous = Some(v.clone());
+                if v.update(value) {
+                    outcome = SetOutcome::Updated;
+                } else {
+                    outcome = SetOutcome::Unchanged;
+                }
+            })
+            .or_insert_with(|| Row::create(key, value));
+        self.compress(row);
+        if outcome != SetOutcome::Unchanged {
+            self.events.replaced(previous.as_ref(), row);
+        }
+        (outcome, previous)
+    }
 
-        let store: KeyValueStore = data.iter().collect();
+    /// Adds `delta` to the number stored under `key` and returns the result,
+    /// all under a single lock, so concurrent calls never lose an update. A
+    /// key with no live row counts as `0`, so it ends up holding `delta`.
+    ///
+    /// Fails with [`crate::Error::ValueParse`] if the current value isn't an
+    /// `i64`, and with [`crate::Error::Overflow`] if the result doesn't fit in
+    /// one. Either way the stored value is left alone.
+    pub fn increment(&self, key: &str, delta: i64) -> crate::Result<i64> {
+        self.apply_delta(key, '+', delta)
     }
 
-    #[test]
-    fn it_works() {
-        let store = KeyValueStore::empty();
-        let result = store.contains("key");
-        assert!(result.is_ok());
-        assert!(!result.unwrap());
-        let result = store.insert("key", "value");
-        assert!(result.is_ok());
-        let result = store.contains("key");
-        assert!(result.is_ok());
-        assert!(result.unwrap());
-        let result = store.insert("key", "whoops");
-        assert!(result.is_err());
-        let result = store.set_or_insert("key", "whoops");
-        assert!(result.is_ok());
-        let result = store.get_clone("key");
-        assert!(result.is_ok());
-        let row = result.unwrap();
-        assert_eq!(row.value(), "whoops");
-        let result = store.len();
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 1);
-        let result = store.delete("key");
-        assert!(result.is_ok());
-        let result = store.delete("key");
-        assert!(result.is_err());
+    /// Same as `increment`, but subtracts `delta` instead.
+    pub fn decrement(&self, key: &str, delta: i64) -> crate::Result<i64> {
+        self.apply_delta(key, '-', delta)
     }
 
-    #[test]
-    fn byte_roundtrip() {
-        let original = KeyValueStore::empty();
-        assert!(original.insert("key1", "value1").is_ok());
-        assert!(original.insert("key2", "value2").is_ok());
-        assert!(original.insert("key3", "value3").is_ok());
-        assert_eq!(
-            original
-                .len()
-                .expect("byte_roundtrip (original) - unable to get length"),
-            3
-        );
-        assert_eq!(
-            original
-                .get_clone("key1")
-                .expect("byte_roundtrip (original) - unable to get key1")
-                .value(),
-            "value1"
-        );
-        assert_eq!(
-            original
-                .get_clone("key2")
-                .expect("byte_roundtrip (original) - unable to get key2")
-                .value(),
-            "value2"
-        );
-        assert_eq!(
-            original
-                .get_clone("key3")
-                .expect("byte_roundtrip (original) - unable to get key3")
-                .value(),
-            "value3"
-        );
-        let result = original.to_bytes();
-        assert!(result.is_ok());
-        let bytes = result.unwrap();
-        assert!(original.insert("key4", "value4").is_ok());
-        assert_eq!(
-            original
-                .len()
-                .expect("byte_roundtrip (original) - unable to get length"),
-            4
-        );
-        let result = KeyValueStore::from_bytes(&bytes);
-        assert!(result.is_ok());
-        let clone = result.unwrap();
-        assert_eq!(
-            clone
-                .len()
-                .expect("byte_roundtrip (clone) - unable to get length"),
-            3
-        );
-        assert_eq!(
-            clone
-                .get_clone("key1")
-                .expect("byte_roundtrip (clone) - unable to get key1")
-                .value(),
-            "value1"
-        );
-        assert_eq!(
-            clone
-                .get_clone("key2")
-                .expect("byte_roundtrip (clone) - unable to get key2")
-                .value(),
-            "value2"
-        );
-        assert_eq!(
-            clone
-                .get_clone("key3")
-                .expect("byte_roundtrip (clone) - unable to get key3")
-                .value(),
-            "value3"
-        );
-        assert!(clone.get_clone("key4").is_err());
+    /// Adds (`op` is `'+'`) or subtracts (`'-'`) `delta` for `increment` and
+    /// `decrement`.
+    fn apply_delta(&self, key: &str, op: char, delta: i64) -> crate::Result<i64> {
+        self.limits.check(key, 0)?;
+        self.write().and_then(|mut data| {
+            let value = match Self::get_locked(&mut data, key) {
+                Ok(row) => row.parse_value::<i64>()?,
+                Err(_) => 0,
+            };
+            let next = checked_apply(value, op, delta)
+                .ok_or_else(|| crate::Error::overflow(key, value, op, delta))?;
+            let next_value = next.to_string();
+            self.limits.check(key, next_value.len())?;
+            self.check_capacity(&data, [key])?;
+            self.pre_write.check(key, &next_value)?;
+            let (outcome, _) = self.set_or_insert_locked(&mut data, key, &next_value);
+            self.stats.set(outcome);
+            Ok(next)
+        })
     }
 
-    #[test]
-    fn tempfile_roundtrip() {
-        use std::fs::File;
-        use std::io::{Read, Seek, SeekFrom, Write};
+    /// Gets the value of `key` parsed into a `T`, see [`Row::parse_value`].
+    pub fn get_parsed<T>(&self, key: &str) -> crate::Result<T>
+    where
+        T: std::str::FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.get_clone(key).and_then(|row| row.parse_value())
+    }
 
-        let original = KeyValueStore::empty();
-        // Load and assert original
-        {
-            assert!(original.insert("key1", "value1").is_ok());
-            assert!(original.insert("key2", "value2").is_ok());
-            assert!(original.insert("key3", "value3").is_ok());
-            assert_eq!(
-                original
-                    .len()
-                    .expect("tempfile_roundtrip (original) - unable to get length"),
-                3
-            );
-            assert_eq!(
-                original
-                    .get_clone("key1")
-                    .expect("tempfile_roundtrip (original) - unable to get key1")
-                    .value(),
-                "value1"
-            );
-            assert_eq!(
-                original
-                    .get_clone("key2")
-                    .expect("tempfile_roundtrip (original) - unable to get key2")
-                    .value(),
-                "value2"
-            );
-            assert_eq!(
-                original
-                    .get_clone("key3")
-                    .expect("tempfile_roundtrip (original) - unable to get key3")
-                    .value(),
+    /// Same as `set_or_insert`, but stores the `Display` output of `value`.
+    pub fn set_display(
+        &self,
+        key: &str,
+        value: &impl std::fmt::Display,
+    ) -> crate::Result<Option<Row>> {
+        self.set_or_insert(key, &value.to_string())
+    }
+
+    /// Updates the value of `key` only if the stored row is still at
+    /// `expected_version`, failing with [`crate::Error::VersionMismatch`] when
+    /// another write got there first.
+    pub fn set_if_version(
+        &self,
+        key: &str,
+        value: &str,
+        expected_version: u64,
+    ) -> crate::Result<()> {
+        self.limits.check(key, value.len())?;
+        self.write().and_then(|mut data| {
+            let row = data
+                .get_mut(key)
+                .filter(|row| !row.is_deleted())
+                .ok_or_else(|| crate::Error::key_not_found(key))?;
+            if row.version() != expected_version {
+                return Err(crate::Error::version_mismatch(
+                    expected_version,
+                    row.version(),
+                ));
+            }
+            self.pre_write.check(key, value)?;
+            let before = self.events.is_watched().then(|| row.clone());
+            let changed = row.update(value);
+            self.compress(row);
+            if changed {
+                self.stats.updated(1);
+                self.events.updated(before, row);
+            }
+            Ok(())
+        })
+    }
+
+    /// Same as `set_or_insert`, with a whole row.
+    pub fn set_or_insert_row(&self, row: &Row) -> crate::Result<Option<Row>> {
+        self.limits.check_row(row)?;
+        self.write().and_then(|mut data| {
+            self.check_capacity(&data, [row.key()])?;
+            self.pre_write.check_row(row)?;
+            // TODO: Is this 'to_string' avoidable?
+            let mut previous = None;
+            let row = data
+                .entry(row.key().to_string())
+                .and_modify(|v| {
+                    previous = v.is_live().then(|| v.clone());
+                    v.overwrite_with(row)
+                })
+                .or_insert_with(|| row.clone());
+            self.compress(row);
+            match previous {
+                Some(_) => self.stats.updated(1),
+                None => self.stats.inserted(1),
+            }
+            self.events.replaced(previous.as_ref(), row);
+            Ok(previous)
+        })
+    }
+
+    /// Gets a snapshot of the value history of the row for `key`, see
+    /// [`Row::history`].
+    pub fn get_history(&self, key: &str) -> crate::Result<Vec<(Value, i64)>> {
+        self.read().and_then(|data| {
+            data.get(key)
+                .filter(|row| row.is_live())
+                .map(|row| row.history().to_vec())
+                .ok_or(crate::Error::key_not_found(key))
+        })
+    }
+
+    pub fn contains(&self, key: &str) -> crate::Result<bool> {
+        self.read()
+            .map(|data| data.get(key).map_or(false, Row::is_live))
+    }
+
+    /// Gets the number of rows in this store, not counting deleted rows.
+    pub fn len(&self) -> crate::Result<usize> {
+        self.read()
+            .map(|data| data.values().filter(|row| !row.is_deleted()).count())
+    }
+
+    /// Removes every row from this store, returning how many there were (not
+    /// counting deleted rows, like `len`).
+    ///
+    /// This ignores the store's [`DeletePolicy`]: tombstones are removed too,
+    /// and none are left behind.
+    pub fn clear(&self) -> crate::Result<usize> {
+        self.write()
+            .map(|mut data| data.drain().filter(|(_, row)| !row.is_deleted()).count())
+    }
+
+    /// Checks whether this store has no rows, not counting deleted rows.
+    pub fn is_empty(&self) -> crate::Result<bool> {
+        self.len().map(|len| len == 0)
+    }
+
+    /// Same as `len`, but rows that have expired are not counted either.
+    pub fn len_unexpired(&self) -> crate::Result<usize> {
+        self.read()
+            .map(|data| data.values().filter(|row| row.is_live()).count())
+    }
+
+    /// Gets a snapshot of the keys of every live (unexpired and not deleted)
+    /// row, in no particular order.
+    pub fn keys(&self) -> crate::Result<Vec<String>> {
+        self.read().map(|data| {
+            data.iter()
+                .filter(|(_, row)| row.is_live())
+                .map(|(key, _)| key.clone())
+                .collect()
+        })
+    }
+
+    /// Same as `keys`, but sorted.
+    pub fn keys_sorted(&self) -> crate::Result<Vec<String>> {
+        let mut keys = self.keys()?;
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// Gets a snapshot of every live (unexpired and not deleted) row, in no
+    /// particular order.
+    pub fn rows(&self) -> crate::Result<Vec<Row>> {
+        self.read()
+            .map(|data| data.values().filter(|row| row.is_live()).cloned().collect())
+    }
+
+    /// Picks the key of a live row at random, each equally likely, or `None`
+    /// if there are none. This is a linear scan over the whole store.
+    pub fn random_key(&self) -> crate::Result<Option<String>> {
+        self.read().map(|data| {
+            let keys = data
+                .iter()
+                .filter(|(_, row)| row.is_live())
+                .map(|(key, _)| key);
+            sample_with(keys, 1, String::clone).pop()
+        })
+    }
+
+    /// Picks up to `n` distinct live rows at random, in random order, in one
+    /// scan over the store under a single read lock. Asking for at least as
+    /// many rows as the store holds gets all of them.
+    pub fn sample(&self, n: usize) -> crate::Result<Vec<Row>> {
+        self.read().map(|data| {
+            let rows = data.values().filter(|row| row.is_live());
+            sample_with(rows, n, Row::clone)
+        })
+    }
+
+    /// Gets the keys of every live row starting with `prefix`, sorted. This is
+    /// a linear scan over the whole store.
+    pub fn keys_with_prefix(&self, prefix: &str) -> crate::Result<Vec<String>> {
+        let mut keys = self.read().map(|data| {
+            data.iter()
+                .filter(|(key, row)| key.starts_with(prefix) && row.is_live())
+                .map(|(key, _)| key.clone())
+                .collect::<Vec<_>>()
+        })?;
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// Gets every live row whose key starts with `prefix`, sorted by key. This
+    /// is a linear scan over the whole store.
+    pub fn scan_prefix(&self, prefix: &str) -> crate::Result<Vec<Row>> {
+        let mut rows = self.read().map(|data| {
+            data.iter()
+                .filter(|(key, row)| key.starts_with(prefix) && row.is_live())
+                .map(|(_, row)| row.clone())
+                .collect::<Vec<_>>()
+        })?;
+        rows.sort();
+        Ok(rows)
+    }
+
+    /// Gets the keys of every live row matching the glob-style `pattern`,
+    /// sorted, like Redis' `KEYS`. `*` matches any run of characters and `?`
+    /// matches a single one; every other character only matches itself. This
+    /// is a linear scan over the whole store.
+    pub fn find_keys(&self, pattern: &str) -> crate::Result<Vec<String>> {
+        self.find_keys_limited(pattern, usize::MAX)
+    }
+
+    /// Same as `find_keys`, but stops scanning once `max` keys have matched.
+    /// Only those keys are sorted, so which `max` keys are returned is
+    /// unspecified when more than that match.
+    pub fn find_keys_limited(&self, pattern: &str, max: usize) -> crate::Result<Vec<String>> {
+        let pattern = KeyPattern::new(pattern);
+        let mut keys = self.read().map(|data| {
+            data.iter()
+                .filter(|(key, row)| row.is_live() && pattern.matches(key))
+                .map(|(key, _)| key.clone())
+                .take(max)
+                .collect::<Vec<_>>()
+        })?;
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// Gets every live row whose key matches the glob-style `pattern`, sorted
+    /// by key, see `find_keys`.
+    pub fn find_rows(&self, pattern: &str) -> crate::Result<Vec<Row>> {
+        let pattern = KeyPattern::new(pattern);
+        let mut rows = self.read().map(|data| {
+            data.iter()
+                .filter(|(key, row)| row.is_live() && pattern.matches(key))
+                .map(|(_, row)| row.clone())
+                .collect::<Vec<_>>()
+        })?;
+        rows.sort();
+        Ok(rows)
+    }
+
+    /// Counts the live rows whose key starts with `prefix`. This is a linear
+    /// scan over the whole store.
+    pub fn count_prefix(&self, prefix: &str) -> crate::Result<usize> {
+        self.read().map(|data| {
+            data.iter()
+                .filter(|(key, row)| key.starts_with(prefix) && row.is_live())
+                .count()
+        })
+    }
+
+    /// Gets the number of rows physically held by this store, including
+    /// expired and deleted rows.
+    pub fn len_raw(&self) -> crate::Result<usize> {
+        self.read().map(|data| data.len())
+    }
+
+    /// Estimates how much memory the rows of this store take up, counting
+    /// every row it holds, like `len_raw`.
+    pub fn memory_usage(&self) -> crate::Result<StoreSize> {
+        self.read().map(|data| data.values().collect())
+    }
+
+    /// Gets the `n` live rows with the largest values, largest first. Rows
+    /// with values of the same size are ordered by key.
+    pub fn largest_rows(&self, n: usize) -> crate::Result<Vec<Row>> {
+        self.read().map(|data| largest_rows(data.values(), n))
+    }
+
+    /// Gets the number of reads, writes and deletes run against this store
+    /// since it was created or `reset_stats` was last called. Bulk operations
+    /// like `clear` and `retain` aren't counted.
+    ///
+    /// The counters are read one at a time without locking the store, so a
+    /// snapshot taken while other threads are writing may be slightly skewed.
+    pub fn stats(&self) -> StoreStatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    /// Sets every counter reported by `stats` back to zero.
+    pub fn reset_stats(&self) {
+        self.stats.reset()
+    }
+
+    /// Subscribes to the changes made to this store. Every successful insert,
+    /// update and delete after this call sends a [`RowEvent`] to the returned
+    /// receiver, and to every other subscriber. Sets that leave a row
+    /// unchanged send nothing, and a rename is sent as a delete of the old key
+    /// followed by an insert (or update) of the new one. Bulk operations like
+    /// `clear`, `retain` and `merge_from` aren't sent either.
+    ///
+    /// Events are sent while the store is still write-locked, so the events
+    /// for any one key arrive in the order the changes were made. Sending
+    /// never blocks, since the channel is unbounded; to unsubscribe just drop
+    /// the receiver.
+    pub fn subscribe(&self) -> std::sync::mpsc::Receiver<RowEvent> {
+        self.events.subscribe()
+    }
+
+    /// Deletes the row for `key` according to this store's [`DeletePolicy`],
+    /// returning the row as it was before the delete.
+    pub fn delete(&self, key: &str) -> crate::Result<Row> {
+        let result = self
+            .write()
+            .and_then(|mut data| self.delete_locked(&mut data, key));
+        self.stats.delete(&result);
+        result
+    }
+
+    /// Deletes the live row for `key` and returns it, failing with
+    /// [`crate::Error::KeyNotFound`] if there is none. Same as `take_if` with
+    /// a predicate that always passes.
+    pub fn take(&self, key: &str) -> crate::Result<Row> {
+        self.take_if(key, |_| true)?
+            .ok_or_else(|| crate::Error::key_not_found(key))
+    }
+
+    /// Deletes the live row for `key` and returns it if `predicate` passes for
+    /// it, or returns `None` and leaves it in place if it doesn't. Fails with
+    /// [`crate::Error::KeyNotFound`] if there is no live row.
+    ///
+    /// The store stays write-locked from checking the row until it's deleted,
+    /// so the row `predicate` passed is the one deleted, and when several
+    /// threads take the same key only one of them gets it. `predicate` must
+    /// not use the store: any method called on it from inside `predicate`
+    /// fails with [`crate::Error::ReentrantAccess`].
+    pub fn take_if<P>(&self, key: &str, predicate: P) -> crate::Result<Option<Row>>
+    where
+        P: FnOnce(&Row) -> bool,
+    {
+        let result = self.write().and_then(|mut data| {
+            let row = Self::get_live(&data, key)?;
+            let passed = {
+                let _callback = self.enter_callback();
+                predicate(&row)
+            };
+            match passed {
+                true => self.delete_locked(&mut data, key).map(Some),
+                false => Ok(None),
+            }
+        });
+        if !matches!(result, Ok(None)) {
+            self.stats.delete(&result);
+        }
+        result
+    }
+
+    /// Bumps the `updated` timestamp of the live row for `key` with
+    /// [`Row::touch`], without changing its value or version, and returns the
+    /// new timestamp. Subscribers see this as an update.
+    pub fn touch(&self, key: &str) -> crate::Result<i64> {
+        self.write()
+            .and_then(|mut data| self.touch_locked(&mut data, key))
+    }
+
+    /// Calls `touch` for each of `keys` under a single lock, returning the
+    /// result for each key in order. Missing keys are answered by
+    /// [`crate::Error::KeyNotFound`] without stopping the rest.
+    pub fn touch_many(&self, keys: &[&str]) -> crate::Result<Vec<(String, crate::Result<i64>)>> {
+        self.write().map(|mut data| {
+            keys.iter()
+                .map(|&key| (key.to_string(), self.touch_locked(&mut data, key)))
+                .collect()
+        })
+    }
+
+    fn touch_locked(&self, data: &mut Data, key: &str) -> crate::Result<i64> {
+        let row = data
+            .get_mut(key)
+            .filter(|row| row.is_live())
+            .ok_or_else(|| crate::Error::key_not_found(key))?;
+        let before = self.events.is_watched().then(|| row.clone());
+        row.touch();
+        self.events.updated(before, row);
+        Ok(row.updated())
+    }
+
+    /// Runs `f` with an [`EntryRef`] for `key`, through which it can check
+    /// whether the key has a live row, read it, and set, insert or delete it,
+    /// and returns what `f` returned. The store stays write-locked from
+    /// reading the row until `f`'s changes have been applied, so no other
+    /// write can come between them, and two `with_entry` calls on the same
+    /// store never interleave.
+    ///
+    /// Values set through the entry are checked against the store's size
+    /// limits as they're set. Once `f` returns its change is checked against
+    /// the capacity limit and the pre-write hook, and if either fails the
+    /// error is returned and nothing is applied. The store is locked while `f`
+    /// runs, so `f` must not use it: any method called on this store from
+    /// inside `f` fails with [`crate::Error::ReentrantAccess`].
+    pub fn with_entry<R, F>(&self, key: &str, f: F) -> crate::Result<R>
+    where
+        F: FnOnce(EntryRef<'_>) -> R,
+    {
+        self.limits.check(key, 0)?;
+        self.write().and_then(|mut data| {
+            let mut state = EntryState::new(Self::get_live(&data, key).ok());
+            let result = {
+                let _callback = self.enter_callback();
+                f(EntryRef::new(key, &mut state, &self.limits))
+            };
+            match state.into_change() {
+                EntryChange::Unchanged => {}
+                EntryChange::Set { row, inserted } => {
+                    if inserted {
+                        self.check_capacity(&data, [key])?;
+                    }
+                    self.pre_write.check_row(&row)?;
+                    match inserted {
+                        true => self.stats.inserted(1),
+                        false => self.stats.updated(1),
+                    }
+                    self.store_locked(&mut data, row);
+                }
+                EntryChange::Deleted => {
+                    self.delete_locked(&mut data, key)?;
+                    self.stats.deleted(1);
+                }
+            }
+            Ok(result)
+        })
+    }
+
+    /// Runs `f` on the live row for `key` and stores the result, returning a
+    /// copy of it. The store stays locked while `f` runs, so `f` must not use
+    /// it: any method called on this store from inside `f` fails with
+    /// [`crate::Error::ReentrantAccess`]. Fails with
+    /// [`crate::Error::KeyNotFound`] if there is no live row, or if `f` changes
+    /// the key or breaks this store's limits, in which case nothing is stored.
+    pub fn update_with<F: FnOnce(&mut Row)>(&self, key: &str, f: F) -> crate::Result<Row> {
+        self.write().and_then(|mut data| {
+            let row = Self::get_locked(&mut data, key)?;
+            let row = self.limits.modify(row, f)?;
+            self.pre_write.check_row(&row)?;
+            self.stats.updated(1);
+            Ok(self.store_locked(&mut data, row))
+        })
+    }
+
+    /// Same as `update_with`, but when `key` has no live row `f` is run on the
+    /// row made by `default` (which must be for `key`) instead.
+    pub fn upsert_with<D, F>(&self, key: &str, default: D, f: F) -> crate::Result<Row>
+    where
+        D: FnOnce() -> Row,
+        F: FnOnce(&mut Row),
+    {
+        self.write().and_then(|mut data| {
+            let current = Self::get_locked(&mut data, key).ok();
+            let inserting = current.is_none();
+            let row = self.limits.modify(upsert_base(current, key, default)?, f)?;
+            self.check_capacity(&data, [key])?;
+            self.pre_write.check_row(&row)?;
+            match inserting {
+                true => self.stats.inserted(1),
+                false => self.stats.updated(1),
+            }
+            Ok(self.store_locked(&mut data, row))
+        })
+    }
+
+    fn store_locked(&self, data: &mut Data, mut row: Row) -> Row {
+        self.compress(&mut row);
+        let replaced = data.insert(row.key().to_string(), row.clone());
+        self.events.replaced(replaced.as_ref(), &row);
+        row
+    }
+
+    /// Sets the value of `key` to `new` if it currently holds `expected`, all
+    /// under a single lock.
+    pub fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: &str,
+        new: &str,
+    ) -> crate::Result<CasOutcome> {
+        self.limits.check(key, new.len())?;
+        self.write().and_then(|mut data| {
+            if CasOutcome::check(data.get(key), expected).is_swapped() {
+                self.pre_write.check(key, new)?;
+            }
+            let row = data.get_mut(key);
+            let before = row.as_deref().filter(|_| self.events.is_watched()).cloned();
+            let outcome = CasOutcome::swap(row, expected, new);
+            if outcome.is_swapped() {
+                self.stats.updated(1);
+                if let Some(row) = data.get_mut(key) {
+                    self.compress(row);
+                    self.events.updated(before, row);
+                }
+            }
+            Ok(outcome)
+        })
+    }
+
+    /// Deletes the row for `key` according to this store's [`DeletePolicy`] if
+    /// it currently holds `expected`, all under a single lock.
+    pub fn compare_and_delete(&self, key: &str, expected: &str) -> crate::Result<CasOutcome> {
+        self.write().and_then(|mut data| {
+            let outcome = CasOutcome::check(data.get(key), expected);
+            if outcome.is_swapped() {
+                self.delete_locked(&mut data, key)?;
+                self.stats.deleted(1);
+            }
+            Ok(outcome)
+        })
+    }
+
+    /// Moves the live row for `old_key` to `new_key`, keeping its `created`
+    /// timestamp but bumping `updated`, all under a single lock. Under
+    /// [`DeletePolicy::Tombstone`] a tombstone is left behind for `old_key`.
+    /// Fails with [`crate::Error::KeyNotFound`] if there is no live row for
+    /// `old_key`, and with [`crate::Error::DuplicateKey`] if `new_key` already
+    /// has one.
+    pub fn rename(&self, old_key: &str, new_key: &str) -> crate::Result<()> {
+        self.rename_inner(old_key, new_key, false).map(|_| ())
+    }
+
+    /// Same as `rename`, but any live row for `new_key` is replaced and
+    /// returned instead.
+    pub fn rename_overwrite(&self, old_key: &str, new_key: &str) -> crate::Result<Option<Row>> {
+        self.rename_inner(old_key, new_key, true)
+    }
+
+    fn rename_inner(
+        &self,
+        old_key: &str,
+        new_key: &str,
+        overwrite: bool,
+    ) -> crate::Result<Option<Row>> {
+        self.limits.check(new_key, 0)?;
+        self.write().and_then(|mut data| {
+            let mut row = Self::get_locked(&mut data, old_key)?;
+            let replaced = Self::get_locked(&mut data, new_key).ok();
+            if replaced.is_some() && !overwrite {
+                return Err(crate::Error::duplicate_key(new_key));
+            }
+            row.rename(new_key);
+            self.pre_write.check_row(&row)?;
+            self.delete_locked(&mut data, old_key)?;
+            self.store_locked(&mut data, row);
+            Ok(replaced.filter(|_| old_key != new_key))
+        })
+    }
+
+    /// Deletes the rows for all of `keys` under a single lock, returning them
+    /// in the order they were given. If any key has no row (or is listed more
+    /// than once) nothing is deleted and that key is reported as
+    /// [`crate::Error::KeyNotFound`].
+    pub fn delete_many(&self, keys: &[&str]) -> crate::Result<Vec<Row>> {
+        self.write().and_then(|mut data| {
+            let mut seen = HashSet::with_capacity(keys.len());
+            for &key in keys {
+                if !seen.insert(key) || !data.get(key).map_or(false, |row| !row.is_deleted()) {
+                    self.stats.delete_missed(1);
+                    return Err(crate::Error::key_not_found(key));
+                }
+            }
+            let rows = keys
+                .iter()
+                .map(|&key| self.delete_locked(&mut data, key))
+                .collect::<crate::Result<Vec<_>>>()?;
+            self.stats.deleted(rows.len());
+            Ok(rows)
+        })
+    }
+
+    /// Same as `delete_many`, but keys without a row are skipped and listed in
+    /// the returned report instead of aborting the batch.
+    pub fn try_delete_many(&self, keys: &[&str]) -> crate::Result<DeleteManyReport> {
+        self.write().map(|mut data| {
+            let mut report = DeleteManyReport::default();
+            for &key in keys {
+                match self.delete_locked(&mut data, key) {
+                    Ok(row) => report.deleted.push(row),
+                    Err(_) => report.missing.push(key.to_string()),
+                }
+            }
+            self.stats.deleted(report.deleted.len());
+            self.stats.delete_missed(report.missing.len());
+            report
+        })
+    }
+
+    /// Deletes every live row matching `predicate` under a single lock,
+    /// returning the deleted rows in no particular order.
+    ///
+    /// The store stays locked while `predicate` runs, so it must not use the
+    /// store itself.
+    pub fn delete_matching<F: Fn(&Row) -> bool>(&self, predicate: F) -> crate::Result<Vec<Row>> {
+        self.write().and_then(|mut data| {
+            let keys = data
+                .values()
+                .filter(|row| row.is_live() && predicate(row))
+                .map(|row| row.key().to_string())
+                .collect::<Vec<_>>();
+            let rows = keys
+                .iter()
+                .map(|key| self.delete_locked(&mut data, key))
+                .collect::<crate::Result<Vec<_>>>()?;
+            self.stats.deleted(rows.len());
+            Ok(rows)
+        })
+    }
+
+    /// Removes every row for which `predicate` returns false, returning how
+    /// many were removed. Deleted rows are left alone and aren't passed to
+    /// `predicate`.
+    ///
+    /// The store stays locked while `predicate` runs, so it must not use the
+    /// store itself. Rows are removed outright, whatever the
+    /// [`DeletePolicy`]. If `predicate` panics the panic is passed on to the
+    /// caller, the rows it already rejected stay removed and the rest are
+    /// kept; the lock is released without being poisoned, so the store can
+    /// still be used.
+    pub fn retain<F: Fn(&str, &Row) -> bool>(&self, predicate: F) -> crate::Result<usize> {
+        self.write().map(|data| {
+            run_unpoisoned(data, |data| {
+                let before = data.len();
+                data.retain(|key, row| row.is_deleted() || predicate(key, row));
+                before - data.len()
+            })
+        })
+    }
+
+    /// Removes every row last updated before `since` (in milliseconds, like
+    /// `Row::updated`), returning how many were removed.
+    pub fn retain_updated_since(&self, since: i64) -> crate::Result<usize> {
+        self.retain(|_, row| row.updated() >= since)
+    }
+
+    /// Gets every live row last updated at or after `ts` (in milliseconds,
+    /// like `Row::updated`), sorted by key. A row updated exactly at `ts` is
+    /// included, so passing the time of the last backup never misses a row.
+    pub fn rows_updated_since(&self, ts: i64) -> crate::Result<Vec<Row>> {
+        let mut rows = self.read().map(|data| {
+            data.values()
+                .filter(|row| row.is_live() && row.updated() >= ts)
+                .cloned()
+                .collect::<Vec<_>>()
+        })?;
+        rows.sort();
+        Ok(rows)
+    }
+
+    /// Gets every live row created strictly before `ts`, sorted by key. A row
+    /// created exactly at `ts` isn't included.
+    pub fn rows_created_before(&self, ts: i64) -> crate::Result<Vec<Row>> {
+        let mut rows = self.read().map(|data| {
+            data.values()
+                .filter(|row| row.is_live() && row.created() < ts)
+                .cloned()
+                .collect::<Vec<_>>()
+        })?;
+        rows.sort();
+        Ok(rows)
+    }
+
+    /// Deletes every live row last updated strictly before `ts`, returning the
+    /// deleted rows sorted by key. A row updated exactly at `ts` is kept, so
+    /// this removes exactly the rows `rows_updated_since(ts)` leaves out.
+    /// Unlike `retain_updated_since` the rows go through `delete_matching`.
+    pub fn purge_older_than(&self, ts: i64) -> crate::Result<Vec<Row>> {
+        let mut rows = self.delete_matching(|row| row.updated() < ts)?;
+        rows.sort();
+        Ok(rows)
+    }
+
+    fn delete_locked(&self, data: &mut Data, key: &str) -> crate::Result<Row> {
+        let deleted = match self.delete_policy {
+            DeletePolicy::Remove => data
+                .remove(key)
+                .filter(|row| !row.is_deleted())
+                .ok_or(crate::Error::key_not_found(key)),
+            DeletePolicy::Tombstone => {
+                let row = data
+                    .get_mut(key)
+                    .filter(|row| !row.is_deleted())
+                    .ok_or_else(|| crate::Error::key_not_found(key))?;
+                let deleted = row.clone();
+                row.tombstone();
+                Ok(deleted)
+            }
+        }?;
+        self.events.deleted(&deleted);
+        Ok(deleted)
+    }
+
+    /// Applies every operation in `ops`, in order, under a single lock. Each
+    /// operation sees the effects of the ones before it, so a transaction
+    /// can, say, insert a key and then swap its value.
+    ///
+    /// The transaction is all or nothing: the operations are first run
+    /// against a staged copy of the rows they touch, and the store is only
+    /// changed once all of them have succeeded. If any operation's
+    /// precondition fails (inserting a key with a live row, deleting or
+    /// swapping one without, swapping a different value) or it breaks this
+    /// store's limits or the pre-write hook, nothing is applied and the
+    /// error is returned as [`crate::Error::TransactionFailed`] with the
+    /// index of that operation. A transaction that would take the store past
+    /// its capacity limit fails with [`crate::Error::CapacityExceeded`], again
+    /// without applying anything.
+    ///
+    /// Subscribers are sent one event for every key the transaction changed,
+    /// going from the key's row before the transaction to its row after.
+    pub fn apply_transaction(&self, ops: &[Op]) -> crate::Result<Vec<OpResult>> {
+        for (index, op) in ops.iter().enumerate() {
+            op.check_limits(&self.limits)
+                .map_err(|err| crate::Error::transaction_failed(index, err))?;
+        }
+        self.write().and_then(|mut data| {
+            let mut staged: HashMap<&str, Option<Row>> = HashMap::new();
+            let mut results = Vec::with_capacity(ops.len());
+            for (index, op) in ops.iter().enumerate() {
+                let key = op.key();
+                let current = match staged.get(key) {
+                    Some(row) => row.clone(),
+                    None => Self::get_live(&data, key).ok(),
+                };
+                let (result, row) = op
+                    .apply(current)
+                    .and_then(|(result, row)| {
+                        if let Some(value) = op.value() {
+                            self.pre_write.check(key, value)?;
+                        }
+                        Ok((result, row))
+                    })
+                    .map_err(|err| crate::Error::transaction_failed(index, err))?;
+                staged.insert(key, row);
+                results.push(result);
+            }
+
+            let freed = match self.delete_policy {
+                DeletePolicy::Remove => staged
+                    .iter()
+                    .filter(|(&key, row)| {
+                        row.is_none() && data.get(key).map_or(false, Row::is_live)
+                    })
+                    .count(),
+                DeletePolicy::Tombstone => 0,
+            };
+            self.limits.check_capacity(
+                || data.len() - freed,
+                staged
+                    .iter()
+                    .filter(|(_, row)| row.is_some())
+                    .map(|(&key, _)| key),
+                |key| data.contains_key(key),
+            )?;
+
+            for (key, row) in staged {
+                match row {
+                    // Rows the transaction only set to their current value.
+                    Some(row) if data.get(key) == Some(&row) => {}
+                    Some(row) => {
+                        self.store_locked(&mut data, row);
+                    }
+                    None if data.get(key).map_or(false, Row::is_live) => {
+                        self.delete_locked(&mut data, key)?;
+                    }
+                    None => {}
+                }
+            }
+            for result in &results {
+                match result {
+                    OpResult::Set(outcome) => self.stats.set(*outcome),
+                    OpResult::Inserted => self.stats.inserted(1),
+                    OpResult::Deleted(_) => self.stats.deleted(1),
+                    OpResult::Swapped => self.stats.updated(1),
+                }
+            }
+            Ok(results)
+        })
+    }
+
+    /// Removes every deleted row from this store, returning how many were
+    /// removed.
+    pub fn purge_tombstones(&self) -> crate::Result<usize> {
+        self.write().map(|mut data| {
+            let before = data.len();
+            data.retain(|_, row| !row.is_deleted());
+            before - data.len()
+        })
+    }
+
+    pub fn to_bytes(&self) -> crate::Result<Vec<u8>> {
+        self.read()
+            .and_then(|data| serde_json::to_vec(&*data).map_err(|err| crate::Error::json_ser(&err)))
+    }
+
+    /// Loads a store previously written with `to_bytes`, dropping any rows that
+    /// have expired since. Fails with [`crate::Error::ChecksumMismatch`] if any
+    /// row doesn't match its checksum.
+    pub fn from_bytes(bytes: &[u8]) -> crate::Result<Self> {
+        Self::parse_bytes(bytes)
+            .and_then(|data| Self::load(data, false))
+            .map(|(store, _)| store)
+    }
+
+    /// Same as `from_bytes`, but rows that don't match their checksum are
+    /// dropped instead of failing the load. Returns the store along with the
+    /// number of rows that were dropped.
+    pub fn from_bytes_lenient(bytes: &[u8]) -> crate::Result<(Self, usize)> {
+        Self::parse_bytes(bytes).and_then(|data| Self::load(data, true))
+    }
+
+    fn parse_bytes(bytes: &[u8]) -> crate::Result<Data> {
+        serde_json::from_slice(bytes).map_err(|err| crate::Error::json_de(&err))
+    }
+
+    /// Verifies the checksum of every row in `data` and drops expired rows.
+    /// When `lenient` is set, corrupted rows are dropped and counted instead of
+    /// causing an error.
+    fn load(mut data: Data, lenient: bool) -> crate::Result<(Self, usize)> {
+        let corrupted = if lenient {
+            let before = data.len();
+            data.retain(|_, row| row.verify_checksum());
+            before - data.len()
+        } else if let Some(row) = data.values().find(|row| !row.verify_checksum()) {
+            return Err(crate::Error::checksum_mismatch(row.key()));
+        } else {
+            0
+        };
+        data.retain(|_, row| !row.is_expired());
+        let store = Self {
+            data: RwLock::new(data),
+            ..Self::default()
+        };
+        Ok((store, corrupted))
+    }
+
+    /// Copies every row of this store into a [`StoreSnapshot`], under a single
+    /// read lock. The snapshot can then be read, exported or compared for as
+    /// long as needed while writes carry on.
+    pub fn snapshot(&self) -> crate::Result<StoreSnapshot> {
+        self.read().map(|data| StoreSnapshot::new(data.clone()))
+    }
+
+    /// Replaces every row of this store with the rows of `snapshot`, under a
+    /// single lock. Like the other bulk operations this isn't sent to
+    /// subscribers or checked by the pre-write hook.
+    pub fn restore_from_snapshot(&self, snapshot: &StoreSnapshot) -> crate::Result<()> {
+        self.write().map(|mut data| {
+            *data = snapshot.all_rows().clone();
+        })
+    }
+
+    pub fn to_disk(&self) -> crate::Result<StoreDiskRepr> {
+        self.read()
+            .map(|data| data.values().cloned().collect::<Vec<_>>())
+            .map(|rows| rows.into())
+    }
+
+    pub fn into_disk(self) -> crate::Result<StoreDiskRepr> {
+        let disk = self
+            .data
+            .into_inner()
+            .unwrap_or_else(|e| e.into_inner())
+            .into_values()
+            .collect::<Vec<_>>()
+            .into();
+        Ok(disk)
+    }
+
+    /// Encodes every row in this store as a protobuf `StoreData` message, which
+    /// is much smaller than the json produced by `to_bytes`. See
+    /// [`Row::to_proto_bytes`] for which fields are kept.
+    pub fn to_proto_bytes(&self) -> crate::Result<Vec<u8>> {
+        let store = self.read().map(|data| crate::rpc::StoreData {
+            rows: data.values().cloned().map(Into::into).collect(),
+        })?;
+        let mut bytes = Vec::with_capacity(store.encoded_len());
+        store
+            .encode(&mut bytes)
+            .map_err(|err| crate::Error::proto_encode(&err))?;
+        Ok(bytes)
+    }
+
+    /// Loads a store previously written with `to_proto_bytes`.
+    pub fn from_proto_bytes(bytes: &[u8]) -> crate::Result<Self> {
+        let store =
+            crate::rpc::StoreData::decode(bytes).map_err(|err| crate::Error::proto_decode(&err))?;
+        let data = store
+            .rows
+            .into_iter()
+            .map(|row| (row.key.clone(), Row::from(row)))
+            .collect();
+        Self::load(data, false).map(|(store, _)| store)
+    }
+
+    /// Merges every row of `other` into this store with [`Row::merge`],
+    /// inserting rows whose keys don't exist here yet, all under a single lock.
+    pub fn merge_from(
+        &self,
+        other: &StoreDiskRepr,
+        strategy: MergeStrategy,
+    ) -> crate::Result<MergeReport> {
+        let incoming = other.rows_by_key()?;
+        self.write().map(|mut data| {
+            let mut report = MergeReport::default();
+            for (key, row) in incoming {
+                match data.get_mut(&key).filter(|local| !local.is_expired()) {
+                    Some(local) => report.record(local.merge(&row, strategy)),
+                    None => {
+                        data.insert(key, row);
+                        report.inserted += 1;
+                    }
+                }
+            }
+            report
+        })
+    }
+
+    /// Compares the live rows of this store against those of `other`, without
+    /// loading `other` into a second store.
+    pub fn diff(&self, other: &StoreDiskRepr) -> crate::Result<StoreDiff> {
+        let theirs = other.rows_by_key()?;
+        self.read()
+            .map(|data| StoreDiff::between(data.values(), theirs))
+    }
+
+    /// Loads a store from `disk`, see `from_bytes`. Fails with
+    /// [`crate::Error::UnsupportedVersion`] if `disk` was written by a newer
+    /// version, and with [`crate::Error::DuplicateKey`] if it holds the same key
+    /// twice.
+    pub fn from_disk(disk: &StoreDiskRepr) -> crate::Result<Self> {
+        Self::load(disk.rows_by_key()?, false).map(|(store, _)| store)
+    }
+
+    /// Loads a store from `disk`, see `from_bytes_lenient` and `from_disk`.
+    pub fn from_disk_lenient(disk: &StoreDiskRepr) -> crate::Result<(Self, usize)> {
+        Self::load(disk.rows_by_key()?, true)
+    }
+
+    /// Same as `to_disk`.
+    pub fn to_disk_repr(&self) -> crate::Result<StoreDiskRepr> {
+        self.to_disk()
+    }
+}
+
+/// Deep-copies the rows of a store, read under its lock, along with its limits,
+/// policies, compression and pre-write hook. The copy starts with fresh stats
+/// and has no subscribers or post-write hook. A poisoned store is copied as the
+/// panicking thread left it, whatever its [`PoisonPolicy`].
+impl Clone for KeyValueStore {
+    fn clone(&self) -> Self {
+        let data = self
+            .data
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        Self {
+            data: RwLock::new(data),
+            limits: self.limits,
+            delete_policy: self.delete_policy,
+            poison_policy: self.poison_policy,
+            compression: self.compression,
+            pre_write: self.pre_write.clone(),
+            ..Self::default()
+        }
+    }
+}
+
+impl super::Store for KeyValueStore {
+    fn get_clone(&self, key: &str) -> crate::Result<Row> {
+        KeyValueStore::get_clone(self, key)
+    }
+
+    fn get_many(&self, keys: &[&str]) -> crate::Result<Vec<(String, crate::Result<Row>)>> {
+        KeyValueStore::get_many(self, keys)
+    }
+
+    fn insert(&self, key: &str, value: &str) -> crate::Result<()> {
+        KeyValueStore::insert(self, key, value)
+    }
+
+    fn insert_row(&self, row: &Row) -> crate::Result<()> {
+        KeyValueStore::insert_row(self, row)
+    }
+
+    fn set_or_insert(&self, key: &str, value: &str) -> crate::Result<Option<Row>> {
+        KeyValueStore::set_or_insert(self, key, value)
+    }
+
+    fn insert_many(&self, pairs: &[(&str, &str)]) -> crate::Result<usize> {
+        KeyValueStore::insert_many(self, pairs)
+    }
+
+    fn set_many(&self, pairs: &[(&str, &str)]) -> crate::Result<SetManyOutcome> {
+        KeyValueStore::set_many(self, pairs)
+    }
+
+    fn set_or_insert_row(&self, row: &Row) -> crate::Result<Option<Row>> {
+        KeyValueStore::set_or_insert_row(self, row)
+    }
+
+    fn update_with(&self, key: &str, f: &mut dyn FnMut(&mut Row)) -> crate::Result<Row> {
+        KeyValueStore::update_with(self, key, f)
+    }
+
+    fn upsert_with(
+        &self,
+        key: &str,
+        default: &mut dyn FnMut() -> Row,
+        f: &mut dyn FnMut(&mut Row),
+    ) -> crate::Result<Row> {
+        KeyValueStore::upsert_with(self, key, default, f)
+    }
+
+    fn contains(&self, key: &str) -> crate::Result<bool> {
+        KeyValueStore::contains(self, key)
+    }
+
+    fn len(&self) -> crate::Result<usize> {
+        KeyValueStore::len(self)
+    }
+
+    fn remaining_capacity(&self) -> crate::Result<Option<usize>> {
+        KeyValueStore::remaining_capacity(self)
+    }
+
+    fn clear(&self) -> crate::Result<usize> {
+        KeyValueStore::clear(self)
+    }
+
+    fn set_read_only(&self, read_only: bool) -> crate::Result<()> {
+        KeyValueStore::set_read_only(self, read_only);
+        Ok(())
+    }
+
+    fn is_read_only(&self) -> bool {
+        KeyValueStore::is_read_only(self)
+    }
+
+    fn delete(&self, key: &str) -> crate::Result<Row> {
+        KeyValueStore::delete(self, key)
+    }
+
+    fn touch(&self, key: &str) -> crate::Result<i64> {
+        KeyValueStore::touch(self, key)
+    }
+
+    fn touch_many(&self, keys: &[&str]) -> crate::Result<Vec<(String, crate::Result<i64>)>> {
+        KeyValueStore::touch_many(self, keys)
+    }
+
+    fn compare_and_swap(&self, key: &str, expected: &str, new: &str) -> crate::Result<CasOutcome> {
+        KeyValueStore::compare_and_swap(self, key, expected, new)
+    }
+
+    fn compare_and_delete(&self, key: &str, expected: &str) -> crate::Result<CasOutcome> {
+        KeyValueStore::compare_and_delete(self, key, expected)
+    }
+
+    fn rename(&self, old_key: &str, new_key: &str) -> crate::Result<()> {
+        KeyValueStore::rename(self, old_key, new_key)
+    }
+
+    fn rename_overwrite(&self, old_key: &str, new_key: &str) -> crate::Result<Option<Row>> {
+        KeyValueStore::rename_overwrite(self, old_key, new_key)
+    }
+
+    fn rows_updated_since(&self, ts: i64) -> crate::Result<Vec<Row>> {
+        KeyValueStore::rows_updated_since(self, ts)
+    }
+
+    fn rows_created_before(&self, ts: i64) -> crate::Result<Vec<Row>> {
+        KeyValueStore::rows_created_before(self, ts)
+    }
+
+    fn purge_older_than(&self, ts: i64) -> crate::Result<Vec<Row>> {
+        KeyValueStore::purge_older_than(self, ts)
+    }
+
+    fn retain(&self, predicate: &dyn Fn(&str, &Row) -> bool) -> crate::Result<usize> {
+        KeyValueStore::retain(self, predicate)
+    }
+
+    fn delete_many(&self, keys: &[&str]) -> crate::Result<Vec<Row>> {
+        KeyValueStore::delete_many(self, keys)
+    }
+
+    fn try_delete_many(&self, keys: &[&str]) -> crate::Result<DeleteManyReport> {
+        KeyValueStore::try_delete_many(self, keys)
+    }
+
+    fn delete_matching(&self, predicate: &dyn Fn(&Row) -> bool) -> crate::Result<Vec<Row>> {
+        KeyValueStore::delete_matching(self, predicate)
+    }
+
+    fn keys(&self) -> crate::Result<Vec<String>> {
+        KeyValueStore::keys(self)
+    }
+
+    fn rows(&self) -> crate::Result<Vec<Row>> {
+        KeyValueStore::rows(self)
+    }
+
+    fn random_key(&self) -> crate::Result<Option<String>> {
+        KeyValueStore::random_key(self)
+    }
+
+    fn sample(&self, n: usize) -> crate::Result<Vec<Row>> {
+        KeyValueStore::sample(self, n)
+    }
+
+    fn keys_with_prefix(&self, prefix: &str) -> crate::Result<Vec<String>> {
+        KeyValueStore::keys_with_prefix(self, prefix)
+    }
+
+    fn scan_prefix(&self, prefix: &str) -> crate::Result<Vec<Row>> {
+        KeyValueStore::scan_prefix(self, prefix)
+    }
+
+    fn count_prefix(&self, prefix: &str) -> crate::Result<usize> {
+        KeyValueStore::count_prefix(self, prefix)
+    }
+
+    fn find_keys(&self, pattern: &str) -> crate::Result<Vec<String>> {
+        KeyValueStore::find_keys(self, pattern)
+    }
+
+    fn find_rows(&self, pattern: &str) -> crate::Result<Vec<Row>> {
+        KeyValueStore::find_rows(self, pattern)
+    }
+
+    fn contains_value(&self, value: &str) -> crate::Result<bool> {
+        KeyValueStore::contains_value(self, value)
+    }
+
+    fn keys_with_value(&self, value: &str) -> crate::Result<Vec<String>> {
+        KeyValueStore::keys_with_value(self, value)
+    }
+
+    fn find(&self, predicate: &dyn Fn(&Row) -> bool) -> crate::Result<Vec<Row>> {
+        KeyValueStore::find(self, predicate)
+    }
+
+    fn find_first(&self, predicate: &dyn Fn(&Row) -> bool) -> crate::Result<Option<Row>> {
+        KeyValueStore::find_first(self, predicate)
+    }
+
+    fn merge_from(
+        &self,
+        other: &StoreDiskRepr,
+        strategy: MergeStrategy,
+    ) -> crate::Result<MergeReport> {
+        KeyValueStore::merge_from(self, other, strategy)
+    }
+
+    fn diff(&self, other: &StoreDiskRepr) -> crate::Result<StoreDiff> {
+        KeyValueStore::diff(self, other)
+    }
+
+    fn memory_usage(&self) -> crate::Result<StoreSize> {
+        KeyValueStore::memory_usage(self)
+    }
+
+    fn largest_rows(&self, n: usize) -> crate::Result<Vec<Row>> {
+        KeyValueStore::largest_rows(self, n)
+    }
+
+    fn to_disk_repr(&self) -> crate::Result<StoreDiskRepr> {
+        KeyValueStore::to_disk_repr(self)
+    }
+
+    fn to_bytes(&self) -> crate::Result<Vec<u8>> {
+        KeyValueStore::to_bytes(self)
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "KeyValueStore"
+    }
+}
+
+impl super::StoreFactory for KeyValueStore {
+    fn from_bytes(bytes: &[u8]) -> crate::Result<Self> {
+        KeyValueStore::from_bytes(bytes)
+    }
+
+    fn from_disk(disk: &StoreDiskRepr) -> crate::Result<Self> {
+        KeyValueStore::from_disk(disk)
+    }
+}
+
+impl<'s> FromIterator<(&'s str, Row)> for KeyValueStore {
+    fn from_iter<T: IntoIterator<Item = (&'s str, Row)>>(iter: T) -> Self {
+        let mut data: HashMap<String, Row> =
+            iter.into_iter().map(|(s, r)| (s.to_string(), r)).collect();
+        Self {
+            data: RwLock::new(data),
+            ..Self::default()
+        }
+    }
+}
+
+impl<'t, 's: 't> FromIterator<&'t (&'s str, Row)> for KeyValueStore {
+    fn from_iter<T: IntoIterator<Item = &'t (&'s str, Row)>>(iter: T) -> Self {
+        let mut data: HashMap<String, Row> = iter
+            .into_iter()
+            .map(|(s, r)| (s.to_string(), r.clone()))
+            .collect();
+        Self {
+            data: RwLock::new(data),
+            ..Self::default()
+        }
+    }
+}
+
+impl FromIterator<(String, String)> for KeyValueStore {
+    /// Builds a store with a new row for every key and value. If a key appears
+    /// more than once the last value wins.
+    fn from_iter<T: IntoIterator<Item = (String, String)>>(iter: T) -> Self {
+        let data = iter
+            .into_iter()
+            .map(|(key, value)| {
+                let row = Row::create(&key, &value);
+                (key, row)
+            })
+            .collect();
+        Self {
+            data: RwLock::new(data),
+            ..Self::default()
+        }
+    }
+}
+
+impl IntoIterator for KeyValueStore {
+    type Item = (String, Row);
+    type IntoIter = StoreIntoIter;
+
+    /// Takes every row out of this store except deleted ones, see
+    /// [`StoreIntoIter`].
+    fn into_iter(self) -> Self::IntoIter {
+        StoreIntoIter::new(
+            self.data
+                .into_inner()
+                .unwrap_or_else(PoisonError::into_inner),
+        )
+    }
+}
+
+impl IntoIterator for &KeyValueStore {
+    type Item = (String, Row);
+    type IntoIter = StoreIntoIter;
+
+    /// Iterates over copies of the rows in this store, taken with `snapshot`
+    /// so the store isn't locked while iterating.
+    ///
+    /// Panics if the snapshot can't be taken, see `snapshot`.
+    fn into_iter(self) -> Self::IntoIter {
+        self.snapshot()
+            .expect("unable to take a snapshot of the store")
+            .into_iter()
+    }
+}
+
+impl Extend<(String, String)> for KeyValueStore {
+    /// Sets the value of every key with `set_or_insert`, inserting the keys
+    /// that don't exist yet.
+    ///
+    /// Panics if any of the writes fails, e.g. because it breaks this store's
+    /// limits; use `set_many` to get the error instead.
+    fn extend<T: IntoIterator<Item = (String, String)>>(&mut self, iter: T) {
+        for (key, value) in iter {
+            if let Err(err) = self.set_or_insert(&key, &value) {
+                panic!("unable to extend store with key '{}': {}", key, err);
+            }
+        }
+    }
+}
+
+impl Extend<Row> for KeyValueStore {
+    /// Stores every row with `set_or_insert_row`, replacing any rows with the
+    /// same keys.
+    ///
+    /// Panics if any of the writes fails, see `Extend<(String, String)>`.
+    fn extend<T: IntoIterator<Item = Row>>(&mut self, iter: T) {
+        for row in iter {
+            if let Err(err) = self.set_or_insert_row(&row) {
+                panic!("unable to extend store with key '{}': {}", row.key(), err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LoadReport;
+    use pretty_assertions::{assert_eq, assert_ne};
+
+    mod helpers {
+        use super::super::super::test_helpers;
+        pub use super::super::super::test_helpers::set_outcome;
+        use super::super::*;
+
+        pub fn store_with(values: &[(&str, &str)]) -> KeyValueStore {
+            test_helpers::store_with(values)
+        }
+
+        pub fn fill_single_thread(values: usize) -> KeyValueStore {
+            test_helpers::fill_single_thread(values)
+        }
+
+        pub fn fill_multi_thread(values: usize, threads: usize) -> KeyValueStore {
+            test_helpers::fill_multi_thread(values, threads)
+        }
+    }
+
+    #[test]
+    fn collect() {
+        let data = vec![
+            ("key1", Row::create("key1", "value1")),
+            ("key2", Row::create("key2", "value2")),
+            ("key3", Row::create("key3", "value3")),
+        ];
+
+        let store: KeyValueStore = data.iter().collect();
+    }
+
+    #[test]
+    fn byte_roundtrip() {
+        let original = KeyValueStore::empty();
+        assert!(original.insert("key1", "value1").is_ok());
+        assert!(original.insert("key2", "value2").is_ok());
+        assert!(original.insert("key3", "value3").is_ok());
+        let bytes = original
+            .to_bytes()
+            .expect("byte_roundtrip - unable to get bytes");
+
+        let clone =
+            KeyValueStore::from_bytes(&bytes).expect("byte_roundtrip - unable to load bytes");
+        assert_eq!(original.content_eq(&clone, false), Ok(true));
+        assert!(original.insert("key4", "value4").is_ok());
+        assert_eq!(original.content_eq(&clone, true), Ok(false));
+    }
+
+    #[test]
+    fn tempfile_roundtrip() {
+        use std::fs::File;
+        use std::io::{Read, Seek, SeekFrom, Write};
+
+        let original = KeyValueStore::empty();
+        // Load and assert original
+        {
+            assert!(original.insert("key1", "value1").is_ok());
+            assert!(original.insert("key2", "value2").is_ok());
+            assert!(original.insert("key3", "value3").is_ok());
+            assert_eq!(
+                original
+                    .len()
+                    .expect("tempfile_roundtrip (original) - unable to get length"),
+                3
+            );
+            assert_eq!(
+                original
+                    .get_clone("key1")
+                    .expect("tempfile_roundtrip (original) - unable to get key1")
+                    .value(),
+                "value1"
+            );
+            assert_eq!(
+                original
+                    .get_clone("key2")
+                    .expect("tempfile_roundtrip (original) - unable to get key2")
+                    .value(),
+                "value2"
+            );
+            assert_eq!(
+                original
+                    .get_clone("key3")
+                    .expect("tempfile_roundtrip (original) - unable to get key3")
+                    .value(),
+                "value3"
+            );
+        }
+
+        let result = original.to_bytes();
+        assert!(result.is_ok());
+        let bytes = result.unwrap();
+        let mut tempfile = tempfile::tempfile().expect("Unable to open tempfile");
+        tempfile
+            .write_all(&bytes)
+            .expect("Unable to write to tempfile");
+
+        assert!(original.insert("key4", "value4").is_ok());
+        assert_eq!(
+            original
+                .len()
+                .expect("tempfile_roundtrip (original) - unable to get length"),
+            4
+        );
+
+        tempfile
+            .seek(SeekFrom::Start(0))
+            .expect("Unable to seek to start in tempfile");
+        let rbytes = tempfile
+            .bytes()
+            .collect::<Result<Vec<u8>, _>>()
+            .expect("Unable to read tempfile");
+
+        let result = KeyValueStore::from_bytes(&rbytes);
+        assert!(result.is_ok());
+        let clone = result.unwrap();
+
+        // Assert clone
+        {
+            assert_eq!(
+                clone
+                    .len()
+                    .expect("tempfile_roundtrip (clone) - unable to get length"),
+                3
+            );
+            assert_eq!(
+                clone
+                    .get_clone("key1")
+                    .expect("tempfile_roundtrip (clone) - unable to get key1")
+                    .value(),
+                "value1"
+            );
+            assert_eq!(
+                clone
+                    .get_clone("key2")
+                    .expect("tempfile_roundtrip (clone) - unable to get key2")
+                    .value(),
+                "value2"
+            );
+            assert_eq!(
+                clone
+                    .get_clone("key3")
+                    .expect("tempfile_roundtrip (clone) - unable to get key3")
+                    .value(),
                 "value3"
             );
+            assert!(clone.get_clone("key4").is_err());
+        }
+    }
+
+    #[test]
+    fn check_fill_single() {
+        use helpers::fill_single_thread;
+        let mut vals: usize = 100;
+        let store = fill_single_thread(vals);
+        assert_eq!(
+            store
+                .len()
+                .expect("check_fill_single - unable to get length"),
+            vals,
+            "check_fill_single - did not add the expected number of values",
+        );
+
+        vals = 200;
+        let store = fill_single_thread(vals);
+        assert_eq!(
+            store
+                .len()
+                .expect("check_fill_single - unable to get length"),
+            vals,
+            "check_fill_single - did not add the expected number of values",
+        );
+    }
+
+    #[test]
+    fn check_fill_multi() {
+        use helpers::fill_multi_thread;
+        let mut vals: usize = 100;
+        let mut threads: usize = 1;
+
+        let (vals, threads) = (100, 1);
+        let store = fill_multi_thread(vals, threads);
+        assert_eq!(
+            store
+                .len()
+                .expect("check_fill_multi_thread - unable to get length"),
+            vals,
+            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
+            vals,
+            threads
+        );
+
+        let (vals, threads) = (100, 2);
+        let store = fill_multi_thread(vals, threads);
+        assert_eq!(
+            store
+                .len()
+                .expect("check_fill_multi_thread - unable to get length"),
+            vals,
+            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
+            vals,
+            threads
+        );
+
+        let (vals, threads) = (100, 3);
+        let store = fill_multi_thread(vals, threads);
+        assert_eq!(
+            store
+                .len()
+                .expect("check_fill_multi_thread - unable to get length"),
+            vals,
+            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
+            vals,
+            threads
+        );
+
+        let (vals, threads) = (100, 4);
+        let store = fill_multi_thread(vals, threads);
+        assert_eq!(
+            store
+                .len()
+                .expect("check_fill_multi_thread - unable to get length"),
+            vals,
+            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
+            vals,
+            threads
+        );
+
+        let (vals, threads) = (177, 3);
+        let store = fill_multi_thread(vals, threads);
+        assert_eq!(
+            store
+                .len()
+                .expect("check_fill_multi_thread - unable to get length"),
+            vals,
+            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
+            vals,
+            threads
+        );
+
+        let (vals, threads) = (10000, 1);
+        let store = fill_multi_thread(vals, threads);
+        assert_eq!(
+            store
+                .len()
+                .expect("check_fill_multi_thread - unable to get length"),
+            vals,
+            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
+            vals,
+            threads
+        );
+
+        let (vals, threads) = (10000, 2);
+        let store = fill_multi_thread(vals, threads);
+        assert_eq!(
+            store
+                .len()
+                .expect("check_fill_multi_thread - unable to get length"),
+            vals,
+            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
+            vals,
+            threads
+        );
+
+        let (vals, threads) = (10000, 3);
+        let store = fill_multi_thread(vals, threads);
+        assert_eq!(
+            store
+                .len()
+                .expect("check_fill_multi_thread - unable to get length"),
+            vals,
+            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
+            vals,
+            threads
+        );
+
+        let (vals, threads) = (10000, 4);
+        let store = fill_multi_thread(vals, threads);
+        assert_eq!(
+            store
+                .len()
+                .expect("check_fill_multi_thread - unable to get length"),
+            vals,
+            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
+            vals,
+            threads
+        );
+
+        let (vals, threads) = (10000, 5);
+        let store = fill_multi_thread(vals, threads);
+        assert_eq!(
+            store
+                .len()
+                .expect("check_fill_multi_thread - unable to get length"),
+            vals,
+            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
+            vals,
+            threads
+        );
+
+        let (vals, threads) = (10000, 6);
+        let store = fill_multi_thread(vals, threads);
+        assert_eq!(
+            store
+                .len()
+                .expect("check_fill_multi_thread - unable to get length"),
+            vals,
+            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
+            vals,
+            threads
+        );
+
+        let (vals, threads) = (10000, 7);
+        let store = fill_multi_thread(vals, threads);
+        assert_eq!(
+            store
+                .len()
+                .expect("check_fill_multi_thread - unable to get length"),
+            vals,
+            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
+            vals,
+            threads
+        );
+
+        let (vals, threads) = (10000, 8);
+        let store = fill_multi_thread(vals, threads);
+        assert_eq!(
+            store
+                .len()
+                .expect("check_fill_multi_thread - unable to get length"),
+            vals,
+            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
+            vals,
+            threads
+        );
+
+        let (vals, threads) = (10000, 9);
+        let store = fill_multi_thread(vals, threads);
+        assert_eq!(
+            store
+                .len()
+                .expect("check_fill_multi_thread - unable to get length"),
+            vals,
+            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
+            vals,
+            threads
+        );
+
+        let (vals, threads) = (10000, 10);
+        let store = fill_multi_thread(vals, threads);
+        assert_eq!(
+            store
+                .len()
+                .expect("check_fill_multi_thread - unable to get length"),
+            vals,
+            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
+            vals,
+            threads
+        );
+
+        let (vals, threads) = (50, 12);
+        let store = fill_multi_thread(vals, threads);
+        assert_eq!(
+            store
+                .len()
+                .expect("check_fill_multi_thread - unable to get length"),
+            vals,
+            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
+            vals,
+            threads
+        );
+
+        let (vals, threads) = (20, 20);
+        let store = fill_multi_thread(vals, threads);
+        assert_eq!(
+            store
+                .len()
+                .expect("check_fill_multi_thread - unable to get length"),
+            vals,
+            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
+            vals,
+            threads
+        );
+    }
+
+    #[test]
+    fn check_fill_multi_rng() {
+        use helpers::fill_multi_thread;
+        use utils::repeat_assert;
+        const ITERS: usize = 25;
+
+        repeat_assert!(ITERS, seed, |n, rng_seed| {
+            let rng = fastrand::Rng::with_seed(rng_seed);
+            let vals = rng.usize(100..40000);
+            let threads = rng.usize(1..10);
+            println!(
+                "Iteration #{} with {} values and {} threads",
+                n, vals, threads
+            );
+            let store = fill_multi_thread(vals, threads);
+            assert_eq!(
+                store
+                    .len()
+                    .expect("check_fill_multi_random - unable to get length"),
+                vals,
+                "check_fill_multi_random (v = {} t = {}) - did not add the expected number of values",
+                vals,
+                threads
+            );
+            let r = rng.usize(0..vals);
+            let key = format!("key{}", r);
+            let value = format!("value{}", r);
+            let row = store.get_clone(&key);
+            assert!(
+                row.is_ok(),
+                "check_fill_multi_random ({},{},{}) - unable to get value",
+                n,
+                vals,
+                threads
+            );
+            let row = row.unwrap();
+            assert_eq!(
+                row.key(),
+                key,
+                "check_fill_multi_random #{} - row {} does not have the expected key",
+                n,
+                r,
+            );
+            assert_eq!(
+                row.value(),
+                value,
+                "check_fill_multi_random #{} - row {} does not have the expected value",
+                n,
+                r,
+            );
+        });
+    }
+
+    #[test]
+    fn set_or_insert_bumps_version() {
+        let store = KeyValueStore::empty();
+        assert!(store.set_or_insert("key", "value1").is_ok());
+        assert_eq!(store.get_clone("key").unwrap().version(), 1);
+        assert!(store.set_or_insert("key", "value2").is_ok());
+        assert_eq!(store.get_clone("key").unwrap().version(), 2);
+    }
+
+    #[test]
+    fn set_if_version() {
+        let store = KeyValueStore::empty();
+        assert_eq!(
+            store.set_if_version("key", "value", 1),
+            Err(crate::Error::key_not_found("key"))
+        );
+        assert!(store.insert("key", "value1").is_ok());
+        assert!(store.set_if_version("key", "value2", 1).is_ok());
+        assert_eq!(
+            store.set_if_version("key", "value3", 1),
+            Err(crate::Error::VersionMismatch(1, 2))
+        );
+        let row = store.get_clone("key").unwrap();
+        assert_eq!(row.value(), "value2");
+        assert_eq!(row.version(), 2);
+    }
+
+    #[test]
+    fn set_if_version_concurrent() {
+        use std::sync::Arc;
+        const THREADS: usize = 8;
+        const UPDATES: usize = 50;
+
+        let store = Arc::new(KeyValueStore::empty());
+        assert!(store.insert("key", "initial").is_ok());
+        let handles = (0..THREADS)
+            .map(|t| {
+                let store = Arc::clone(&store);
+                std::thread::spawn(move || {
+                    let mut conflicts = 0;
+                    for i in 0..UPDATES {
+                        let value = format!("thread{}-update{}", t, i);
+                        loop {
+                            let version = store.get_clone("key").unwrap().version();
+                            match store.set_if_version("key", &value, version) {
+                                Ok(()) => break,
+                                Err(crate::Error::VersionMismatch(expected, actual)) => {
+                                    assert_eq!(expected, version);
+                                    assert!(actual > expected);
+                                    conflicts += 1;
+                                }
+                                Err(err) => panic!("unexpected error: {}", err),
+                            }
+                        }
+                    }
+                    conflicts
+                })
+            })
+            .collect::<Vec<_>>();
+        let conflicts: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        println!("set_if_version_concurrent - {} conflicts", conflicts);
+
+        let row = store.get_clone("key").unwrap();
+        assert_eq!(row.version(), 1 + (THREADS * UPDATES) as u64);
+    }
+
+    #[test]
+    fn expired_rows() {
+        let store = KeyValueStore::empty();
+        assert!(store.insert("key1", "value1").is_ok());
+        assert!(store
+            .insert_row(&Row::create_with_ttl("key2", "value2", 0))
+            .is_ok());
+        assert!(store
+            .insert_row(&Row::create_with_ttl("key3", "value3", 3600))
+            .is_ok());
+
+        assert_eq!(store.len().unwrap(), 3);
+        assert_eq!(store.len_unexpired().unwrap(), 2);
+        assert!(!store.contains("key2").unwrap());
+        assert!(store.contains("key3").unwrap());
+        assert_eq!(
+            store.get_clone("key2"),
+            Err(crate::Error::key_not_found("key2"))
+        );
+        assert_eq!(store.len().unwrap(), 2, "expired row was not removed");
+        assert_eq!(store.get_clone("key3").unwrap().value(), "value3");
+    }
+
+    #[test]
+    fn set_or_insert_expired_row() {
+        let store = KeyValueStore::empty();
+        let mut row = Row::new("key", "old", 100, 200);
+        row.expires_at = Some(300);
+        assert!(store.insert_row(&row).is_ok());
+        assert!(store.set_or_insert("key", "new").is_ok());
+
+        let row = store.get_clone("key").unwrap();
+        assert_eq!(row.value(), "new");
+        assert_eq!(row.expires_at(), None);
+        assert_eq!(row.version(), 1);
+        assert_ne!(row.created(), 100);
+    }
+
+    #[test]
+    fn ttl_roundtrip() {
+        let store = KeyValueStore::empty();
+        let row = Row::create_with_ttl("key1", "value1", 3600);
+        assert!(store.insert_row(&row).is_ok());
+        assert!(store
+            .insert_row(&Row::create_with_ttl("key2", "value2", 0))
+            .is_ok());
+
+        let bytes = store.to_bytes().expect("unable to get bytes");
+        let loaded = KeyValueStore::from_bytes(&bytes).expect("unable to load bytes");
+        assert_eq!(loaded.len().unwrap(), 1, "expired row was not dropped");
+        assert_eq!(loaded.get_clone("key1"), Ok(row.clone()));
+
+        let disk = store.to_disk().expect("unable to get disk repr");
+        let restored = disk
+            .data
+            .iter()
+            .map(Row::from)
+            .find(|r| r.key() == "key1")
+            .expect("row missing from disk repr");
+        assert_eq!(restored.expires_at(), row.expires_at());
+    }
+
+    #[test]
+    fn set_or_insert_keeps_meta() {
+        let store = KeyValueStore::empty();
+        let mut row = Row::create("key", "value1");
+        row.set_meta("source", "import");
+        assert!(store.insert_row(&row).is_ok());
+        assert!(store.set_or_insert("key", "value2").is_ok());
+
+        let row = store.get_clone("key").unwrap();
+        assert_eq!(row.value(), "value2");
+        assert_eq!(row.get_meta("source"), Some("import"));
+    }
+
+    #[test]
+    fn get_history() {
+        let store = KeyValueStore::empty();
+        assert!(store
+            .insert_row(&Row::create("key", "value0").with_history_limit(2))
+            .is_ok());
+        for i in 1..=4 {
+            assert!(store.set_or_insert("key", &format!("value{}", i)).is_ok());
+        }
+        assert!(store.set_or_insert("other", "value").is_ok());
+
+        let history = store.get_history("key").unwrap();
+        let values = history
+            .iter()
+            .map(|(value, _)| value.to_display_string())
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec!["value2", "value3"]);
+        assert!(store.get_history("other").unwrap().is_empty());
+        assert_eq!(
+            store.get_history("missing"),
+            Err(crate::Error::key_not_found("missing"))
+        );
+
+        let bytes = store.to_bytes().expect("unable to get bytes");
+        let loaded = KeyValueStore::from_bytes(&bytes).expect("unable to load bytes");
+        assert_eq!(loaded.get_history("key"), Ok(history));
+        assert!(loaded.set_or_insert("key", "value5").is_ok());
+        assert_eq!(
+            loaded.get_history("key").unwrap().len(),
+            2,
+            "history limit was not preserved"
+        );
+    }
+
+    #[test]
+    fn set_or_insert_previous() {
+        use super::super::Store;
+
+        let store = KeyValueStore::empty();
+        assert_eq!(store.set_or_insert("key", "value1"), Ok(None));
+        let first = store.get_clone("key").unwrap();
+        assert_eq!(store.set_or_insert("key", "value2"), Ok(Some(first)));
+        let second = store.get_clone("key").unwrap();
+        assert_eq!(
+            store.set_or_insert("key", "value2"),
+            Ok(Some(second.clone()))
+        );
+        assert_eq!(store.get_clone("key"), Ok(second.clone()));
+        assert_eq!(second.version(), 2);
+        assert!(store
+            .insert_row(&Row::create_with_ttl("expired", "value", 0))
+            .is_ok());
+        assert_eq!(store.set_or_insert("expired", "value"), Ok(None));
+
+        assert_eq!(
+            store.set_or_insert_row(&Row::create("key", "value3")),
+            Ok(Some(second))
+        );
+        assert_eq!(
+            store.set_or_insert_row(&Row::create("fresh", "v")),
+            Ok(None)
+        );
+
+        let dyn_store: &dyn Store = &store;
+        let third = store.get_clone("key").unwrap();
+        assert_eq!(dyn_store.set_or_insert("key", "value4"), Ok(Some(third)));
+        assert_eq!(dyn_store.set_or_insert("other", "v"), Ok(None));
+        let fourth = store.get_clone("key").unwrap();
+        assert_eq!(
+            dyn_store.set_or_insert_row(&Row::create("key", "value5")),
+            Ok(Some(fourth))
+        );
+        assert_eq!(
+            helpers::set_outcome(&store, "key", "value5"),
+            Ok(SetOutcome::Unchanged)
+        );
+    }
+
+    #[test]
+    fn size_limits() {
+        let store = KeyValueStore::with_limits(4, 8);
+        assert!(store.insert("key1", "12345678").is_ok());
+        assert_eq!(
+            store.insert("key2", "123456789"),
+            Err(crate::Error::value_too_large("key2", 9, 8))
+        );
+        assert_eq!(
+            store.insert("key10", "value"),
+            Err(crate::Error::key_too_large("key10", 5, 4))
+        );
+        assert!(store.insert_row(&Row::create("key3", "12345678")).is_ok());
+        assert_eq!(
+            store.insert_row(&Row::create("key4", "123456789")),
+            Err(crate::Error::value_too_large("key4", 9, 8))
+        );
+
+        assert_eq!(
+            store.set_or_insert("key1", "123456789"),
+            Err(crate::Error::value_too_large("key1", 9, 8))
+        );
+        assert_eq!(store.get_clone("key1").unwrap().value(), "12345678");
+        assert_eq!(
+            store.set_or_insert_row(&Row::create("key1", "123456789")),
+            Err(crate::Error::value_too_large("key1", 9, 8))
+        );
+        assert_eq!(
+            helpers::set_outcome(&store, "key1", "1234"),
+            Ok(SetOutcome::Updated)
+        );
+        assert_eq!(store.len().unwrap(), 2);
+
+        let unlimited = KeyValueStore::empty();
+        assert!(unlimited
+            .insert(&"k".repeat(1024), &"v".repeat(1 << 20))
+            .is_ok());
+    }
+
+    #[test]
+    fn checksum_verified_on_load() {
+        let store = KeyValueStore::empty();
+        assert!(store.insert("key1", "value1").is_ok());
+        assert!(store.insert("key2", "value2").is_ok());
+        assert!(store.insert("key3", "value3").is_ok());
+
+        let bytes = store.to_bytes().expect("unable to get bytes");
+        let text = String::from_utf8(bytes).unwrap();
+        assert_eq!(text.matches("\"value2\"").count(), 1);
+        let tampered = text.replace("\"value2\"", "\"valueX\"").into_bytes();
+
+        assert_eq!(
+            KeyValueStore::from_bytes(&tampered).err(),
+            Some(crate::Error::checksum_mismatch("key2"))
+        );
+        let (loaded, dropped) =
+            KeyValueStore::from_bytes_lenient(&tampered).expect("lenient load failed");
+        assert_eq!(dropped, 1);
+        assert_eq!(loaded.len().unwrap(), 2);
+        assert!(!loaded.contains("key2").unwrap());
+        assert_eq!(loaded.get_clone("key1").unwrap().value(), "value1");
+
+        let mut disk = store.to_disk().expect("unable to get disk repr");
+        let restored = KeyValueStore::from_disk(&disk).expect("unable to load disk repr");
+        assert_eq!(restored.len().unwrap(), 3);
+        let row = disk.data.iter_mut().find(|row| row.key == "key3").unwrap();
+        row.created += 1;
+        assert_eq!(
+            KeyValueStore::from_disk(&disk).err(),
+            Some(crate::Error::checksum_mismatch("key3"))
+        );
+        let (restored, dropped) =
+            KeyValueStore::from_disk_lenient(&disk).expect("lenient load failed");
+        assert_eq!((restored.len().unwrap(), dropped), (2, 1));
+    }
+
+    #[test]
+    fn loads_rows_without_checksums() {
+        let json = r#"{"key":{"key":"key","value":"value","created":1,"updated":2}}"#;
+        let store = KeyValueStore::from_bytes(json.as_bytes()).expect("unable to load v1 data");
+        assert_eq!(store.get_clone("key").unwrap().value(), "value");
+    }
+
+    #[test]
+    fn loads_v1_disk_repr_in_seconds() {
+        let old = Row::new("key1", "value1", 1_650_000_000, 1_650_000_001);
+        let json = format!(
+            r#"{{"version":1,"data":[{},{{"key":"key2","value":"value2","created":1650000002,"updated":1650000003}}]}}"#,
+            serde_json::to_string(&RowDiskRepr::from(&old)).unwrap()
+        );
+        let dir = tempfile::tempdir().expect("unable to create tempdir");
+        let path = dir.path().join("v1.json");
+        std::fs::write(&path, &json).expect("unable to write v1 repr");
+
+        let disk = StoreDiskRepr::read_from(&path).expect("unable to read v1 repr");
+        assert_eq!(disk.version, StoreDiskRepr::current_version());
+        let old_disk: StoreDiskRepr = serde_json::from_str(&json).unwrap();
+        assert_eq!(old_disk.version, 1);
+
+        for disk in [disk, old_disk] {
+            let store = KeyValueStore::from_disk(&disk).expect("unable to load v1 repr");
+            let row = store.get_clone("key1").unwrap();
+            assert_eq!(row.created(), 1_650_000_000_000);
+            assert_eq!(row.updated(), 1_650_000_001_000);
+            assert!(row.verify_checksum());
+            let row = store.get_clone("key2").unwrap();
+            assert_eq!(row.created(), 1_650_000_002_000);
+            assert_eq!(
+                row.updated_at().map(|at| at.unix_timestamp()),
+                Ok(1_650_000_003)
+            );
+        }
+    }
+
+    #[test]
+    fn upgrades_v1_era_rows() {
+        let json = r#"{"version":1,"data":[
+            {"key":"plain","value":"value","created":1650000000,"updated":1650000001},
+            {"key":"renamed","value":"value","created_at":1650000002,"updated_at":1650000003}
+        ]}"#;
+        let mut disk: StoreDiskRepr = serde_json::from_str(json).expect("unable to parse v1 repr");
+        assert!(disk.data.iter().all(|row| row.schema == 0));
+        assert_eq!(
+            disk.migrate(),
+            Ok(LoadReport {
+                rows: 2,
+                upgraded: 2,
+                skipped: 0
+            })
+        );
+        assert!(disk
+            .data
+            .iter()
+            .all(|row| row.schema == RowDiskRepr::current_schema()));
+
+        let store = KeyValueStore::from_disk(&disk).expect("unable to load upgraded repr");
+        let row = store.get_clone("plain").unwrap();
+        assert_eq!(
+            (row.created(), row.updated()),
+            (1_650_000_000_000, 1_650_000_001_000)
+        );
+        assert_eq!(row.version(), 1);
+        assert!(row.meta().is_empty());
+        let row = store.get_clone("renamed").unwrap();
+        assert_eq!(
+            (row.created(), row.updated()),
+            (1_650_000_002_000, 1_650_000_003_000)
+        );
+    }
+
+    #[test]
+    fn upgrade_report() {
+        let json = r#"{"version":2,"data":[
+            {"key":"unversioned","value":"value","created":1650000000000,"updated":1650000000000},
+            {"key":"current","value":"value","created":1,"updated":2,"schema":2},
+            {"key":"future","value":"value","created":1,"updated":2,"schema":200}
+        ]}"#;
+        let dir = tempfile::tempdir().expect("unable to create tempdir");
+        let path = dir.path().join("store.json");
+        std::fs::write(&path, json).expect("unable to write repr");
+
+        let (disk, report) =
+            StoreDiskRepr::read_from_with_report(&path).expect("unable to read repr");
+        assert_eq!(
+            report,
+            LoadReport {
+                rows: 2,
+                upgraded: 0,
+                skipped: 1
+            }
+        );
+        let store = KeyValueStore::from_disk(&disk).expect("unable to load repr");
+        assert_eq!(
+            store.get_clone("unversioned").unwrap().created(),
+            1_650_000_000_000
+        );
+        assert!(!store.contains("future").unwrap());
+
+        let mut row = RowDiskRepr::from(Row::new("key", "value", 1, 2));
+        assert_eq!(row.schema, RowDiskRepr::current_schema());
+        assert_eq!(row.upgrade(), Ok(false));
+        row.schema = 0;
+        assert_eq!(row.upgrade(), Ok(true));
+        assert_eq!((row.created, row.updated), (1_000, 2_000));
+    }
+
+    #[test]
+    fn upgrade_overflowing_timestamps() {
+        let mut row = RowDiskRepr::from(Row::new("key", "value", 1, i64::MAX / 10));
+        row.schema = 1;
+        assert_eq!(
+            row.upgrade(),
+            Err(crate::Error::InvalidTimestamp(i64::MAX / 10))
+        );
+        assert_eq!(
+            (row.schema, row.created, row.updated),
+            (1, 1, i64::MAX / 10)
+        );
+
+        let mut row = RowDiskRepr::from(Row::new("key", "value", 1, 2));
+        row.schema = 1;
+        row.history = vec![(Value::from("old"), i64::MIN)];
+        assert_eq!(row.upgrade(), Err(crate::Error::InvalidTimestamp(i64::MIN)));
+        assert_eq!(row.created, 1);
+
+        let mut disk = StoreDiskRepr::from(vec![Row::new("key", "value", 1, 2)]);
+        disk.version = 1;
+        disk.data[0].expires_at = Some(i64::MAX);
+        assert!(matches!(
+            KeyValueStore::from_disk(&disk),
+            Err(crate::Error::InvalidTimestamp(i64::MAX))
+        ));
+        assert_eq!(
+            disk.migrate(),
+            Err(crate::Error::InvalidTimestamp(i64::MAX))
+        );
+    }
+
+    #[test]
+    fn read_from_validated() {
+        let disk = StoreDiskRepr::from(vec![
+            Row::new("good", "value", -5_000, 1_000),
+            Row::new("future", "value", 0, i64::MAX),
+            Row::new("backwards", "value", 2_000, 1_000),
+        ]);
+        let dir = tempfile::tempdir().expect("unable to create tempdir");
+        let path = dir.path().join("store.json");
+        disk.write_to(&path).expect("unable to write repr");
+
+        let (loaded, mut invalid) =
+            StoreDiskRepr::read_from_validated(&path).expect("unable to read repr");
+        invalid.sort();
+        assert_eq!(invalid, vec!["backwards", "future"]);
+        assert_eq!(loaded.data.len(), 3);
+    }
+
+    #[test]
+    fn write_to_with_progress() {
+        let disk = StoreDiskRepr::from(vec![
+            Row::new("key1", "value1", 1, 2),
+            Row::new("key2", "value2", 3, 4),
+            Row::new("key3", "value3", 5, 6),
+        ]);
+        let dir = tempfile::tempdir().expect("unable to create tempdir");
+        let path = dir.path().join("store.json");
+        let mut progress = Vec::new();
+        disk.write_to_with_progress(&path, |written| progress.push(written))
+            .expect("unable to write repr");
+        assert_eq!(progress, vec![1, 2, 3]);
+
+        let loaded = StoreDiskRepr::read_from(&path).expect("unable to read repr");
+        assert_eq!(loaded.fingerprint(), disk.fingerprint());
+        let empty = dir.path().join("empty.json");
+        StoreDiskRepr::from(Vec::<Row>::new())
+            .write_to(&empty)
+            .expect("unable to write repr");
+        assert_eq!(
+            StoreDiskRepr::read_from(&empty).map(|repr| repr.data.len()),
+            Ok(0)
+        );
+    }
+
+    #[test]
+    fn merge_from() {
+        let store = KeyValueStore::empty();
+        assert!(store.insert_row(&Row::new("same", "local", 1, 10)).is_ok());
+        assert!(store.insert_row(&Row::new("older", "local", 1, 10)).is_ok());
+        assert!(store.insert_row(&Row::new("newer", "local", 1, 30)).is_ok());
+        assert!(store
+            .insert_row(&Row::new("local-only", "local", 1, 10))
+            .is_ok());
+
+        let other = StoreDiskRepr::from(vec![
+            Row::new("same", "local", 1, 10),
+            Row::new("older", "remote", 1, 20),
+            Row::new("newer", "remote", 1, 20),
+            Row::new("remote-only", "remote", 1, 10),
+        ]);
+        assert_eq!(
+            store.merge_from(&other, MergeStrategy::LastWriteWins),
+            Ok(MergeReport {
+                inserted: 1,
+                merged: 1,
+                unchanged: 2,
+            })
+        );
+        assert_eq!(store.len().unwrap(), 5);
+        assert_eq!(store.get_clone("older").unwrap().value(), "remote");
+        assert_eq!(store.get_clone("newer").unwrap().value(), "local");
+        assert_eq!(store.get_clone("remote-only").unwrap().value(), "remote");
+        assert_eq!(store.get_clone("local-only").unwrap().value(), "local");
+
+        let report = store
+            .merge_from(&other, MergeStrategy::PreferOther)
+            .unwrap();
+        assert_eq!((report.changed(), report.unchanged), (1, 3));
+        assert_eq!(store.get_clone("newer").unwrap().value(), "remote");
+    }
+
+    #[test]
+    fn proto_roundtrip() {
+        use helpers::fill_single_thread;
+        let store = fill_single_thread(500);
+        let proto = store.to_proto_bytes().expect("unable to encode store");
+        let json = store.to_bytes().expect("unable to serialize store");
+        assert!(
+            proto.len() < json.len(),
+            "proto bytes ({}) should be smaller than json bytes ({})",
+            proto.len(),
+            json.len()
+        );
+
+        let loaded = KeyValueStore::from_proto_bytes(&proto).expect("unable to decode store");
+        assert_eq!(loaded.len().unwrap(), 500);
+        for i in [0, 250, 499] {
+            let key = format!("key{}", i);
+            let expected = store.get_clone(&key).unwrap();
+            let actual = loaded.get_clone(&key).unwrap();
+            assert!(expected.content_eq(&actual));
+            assert_eq!(expected.created(), actual.created());
+        }
+
+        // Dropping the last byte always leaves the final field incomplete, where
+        // an arbitrary cut can land on a field boundary and still decode.
+        let mut corrupt = proto.clone();
+        corrupt.pop();
+        assert!(matches!(
+            KeyValueStore::from_proto_bytes(&corrupt),
+            Err(crate::Error::ProtoDecode(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_keys() {
+        let store = KeyValueStore::empty();
+        for key in ["", "line\nbreak"] {
+            assert!(matches!(
+                store.insert(key, "value"),
+                Err(crate::Error::InvalidKey { .. })
+            ));
+            assert!(matches!(
+                store.set_or_insert(key, "value"),
+                Err(crate::Error::InvalidKey { .. })
+            ));
+        }
+        let long = "k".repeat(super::super::MAX_KEY_LEN + 1);
+        assert!(matches!(
+            store.insert(&long, "value"),
+            Err(crate::Error::InvalidKey { .. })
+        ));
+        assert!(store.insert(&long[1..], "value").is_ok());
+        assert_eq!(store.len().unwrap(), 1);
+    }
+
+    #[test]
+    fn delete_policy() {
+        let store = helpers::store_with(&[("key1", "value1"), ("key2", "value2")]);
+        let deleted = store.delete("key1").expect("unable to delete key1");
+        assert_eq!(deleted.value(), "value1");
+        assert_eq!(store.len_raw().unwrap(), 1);
+
+        let store = helpers::store_with(&[("key1", "value1"), ("key2", "value2")])
+            .with_delete_policy(DeletePolicy::Tombstone);
+        let deleted = store.delete("key1").expect("unable to delete key1");
+        assert_eq!(deleted.value(), "value1");
+        assert!(!deleted.is_deleted());
+        assert_eq!(store.contains("key1"), Ok(false));
+        assert_eq!(
+            store.get_clone("key1"),
+            Err(crate::Error::key_not_found("key1"))
+        );
+        assert_eq!(
+            store.delete("key1"),
+            Err(crate::Error::key_not_found("key1"))
+        );
+        assert_eq!(store.len().unwrap(), 1);
+        assert_eq!(store.len_raw().unwrap(), 2);
+    }
+
+    #[test]
+    fn reinsert_after_tombstone() {
+        let store =
+            helpers::store_with(&[("key1", "value1")]).with_delete_policy(DeletePolicy::Tombstone);
+        store.delete("key1").unwrap();
+        assert_eq!(
+            helpers::set_outcome(&store, "key1", "value2"),
+            Ok(SetOutcome::Inserted)
+        );
+        let row = store.get_clone("key1").unwrap();
+        assert!(!row.is_deleted());
+        assert_eq!(row.value(), "value2");
+
+        store.delete("key1").unwrap();
+        assert!(store.insert("key1", "value3").is_ok());
+        assert!(!store.get_clone("key1").unwrap().is_deleted());
+        assert_eq!(store.len().unwrap(), 1);
+    }
+
+    #[test]
+    fn tombstones_survive_roundtrip() {
+        let store = helpers::store_with(&[("key1", "value1"), ("key2", "value2")])
+            .with_delete_policy(DeletePolicy::Tombstone);
+        store.delete("key1").unwrap();
+
+        let bytes = store.to_bytes().unwrap();
+        let loaded = KeyValueStore::from_bytes(&bytes).expect("unable to load store");
+        assert_eq!(loaded.len_raw().unwrap(), 2);
+        assert_eq!(loaded.len().unwrap(), 1);
+        assert_eq!(loaded.contains("key1"), Ok(false));
+
+        let disk = loaded.to_disk().unwrap();
+        let reloaded = KeyValueStore::from_disk(&disk).expect("unable to load store");
+        assert_eq!(reloaded.len_raw().unwrap(), 2);
+        assert_eq!(reloaded.contains("key1"), Ok(false));
+    }
+
+    #[test]
+    fn purge_tombstones() {
+        let store = helpers::store_with(&[("key1", "value1"), ("key2", "value2"), ("key3", "")])
+            .with_delete_policy(DeletePolicy::Tombstone);
+        store.delete("key1").unwrap();
+        store.delete("key2").unwrap();
+        assert_eq!(store.len_raw().unwrap(), 3);
+        assert_eq!(store.purge_tombstones(), Ok(2));
+        assert_eq!(store.len_raw().unwrap(), 1);
+        assert_eq!(store.len().unwrap(), 1);
+        assert_eq!(store.purge_tombstones(), Ok(0));
+    }
+
+    // Slow-ish because of the large value, run with `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn get_clone_shares_large_values() {
+        let store = KeyValueStore::empty();
+        assert!(store.insert("big", &"v".repeat(1 << 20)).is_ok());
+
+        let first = store.get_clone("big").unwrap();
+        let start = std::time::Instant::now();
+        for _ in 0..10_000 {
+            let row = store.get_clone("big").unwrap();
+            assert!(std::ptr::eq(row.value(), first.value()));
+        }
+        println!("10000 clones of a 1MB row took {:?}", start.elapsed());
+        // One reference held by the store and one by `first`.
+        match &first.value {
+            crate::ValueRepr::Plain(value) => assert_eq!(std::sync::Arc::strong_count(value), 2),
+            repr => panic!("expected a plain value, found {:?}", repr),
+        }
+    }
+
+    #[test]
+    fn get_parsed_and_set_display() {
+        let store = KeyValueStore::empty();
+        assert_eq!(store.set_display("count", &41), Ok(None));
+        let count: i64 = store.get_parsed("count").unwrap();
+        assert_eq!(
+            store
+                .set_display("count", &(count + 1))
+                .map(|previous| previous.map(|row| row.value().to_string())),
+            Ok(Some("41".to_string()))
+        );
+        assert_eq!(store.get_parsed::<i64>("count"), Ok(42));
+        assert!(store.set_display("ratio", &0.25).is_ok());
+        assert_eq!(store.get_parsed::<f64>("ratio"), Ok(0.25));
+        assert!(store.set_display("flag", &true).is_ok());
+        assert_eq!(store.get_parsed::<bool>("flag"), Ok(true));
+
+        assert!(matches!(
+            store.get_parsed::<bool>("count"),
+            Err(crate::Error::ValueParse { .. })
+        ));
+        assert_eq!(
+            store.get_parsed::<i64>("missing"),
+            Err(crate::Error::key_not_found("missing"))
+        );
+    }
+
+    #[test]
+    fn disk_repr_sorted_by_key() {
+        let store = helpers::store_with(&[("key3", "a"), ("key1", "c"), ("key2", "b")]);
+        let disk = store.to_disk().unwrap().sorted_by_key();
+        let keys = disk
+            .data
+            .iter()
+            .map(|row| row.key.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec!["key1", "key2", "key3"]);
+    }
+
+    #[cfg(feature = "compression")]
+    fn json_blob(len: usize) -> String {
+        let mut blob = String::from("[");
+        while blob.len() < len {
+            blob.push_str(r#"{"id":1,"name":"example","tags":["a","b"]},"#);
+        }
+        blob.push(']');
+        blob
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compression_roundtrip() {
+        let store = KeyValueStore::empty().with_options(CompressionOptions::default());
+        let blob = json_blob(64 * 1024);
+        assert!(store.insert("small", "value").is_ok());
+        assert!(store.insert("blob", &blob).is_ok());
+
+        let row = store.get_clone("small").unwrap();
+        assert!(!row.is_compressed());
+        let row = store.get_clone("blob").unwrap();
+        assert!(row.is_compressed());
+        assert!(row.size_bytes() < blob.len() / 10);
+        assert_eq!(row.value(), blob.as_str());
+        assert!(row.verify_checksum());
+
+        let loaded = KeyValueStore::from_bytes(&store.to_bytes().unwrap()).unwrap();
+        let row = loaded.get_clone("blob").unwrap();
+        assert!(row.is_compressed());
+        assert_eq!(row.value(), blob.as_str());
+        assert_eq!(loaded.get_clone("small").unwrap().value(), "value");
+
+        let disk = store.to_disk().unwrap();
+        let loaded = KeyValueStore::from_disk(&disk).unwrap();
+        assert_eq!(
+            loaded.get_clone("blob").unwrap(),
+            store.get_clone("blob").unwrap()
+        );
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compression_on_update() {
+        let store = KeyValueStore::empty().with_options(CompressionOptions::with_threshold(100));
+        assert!(store.insert("key", "short").is_ok());
+        assert!(!store.get_clone("key").unwrap().is_compressed());
+
+        let blob = json_blob(1000);
+        assert_eq!(
+            helpers::set_outcome(&store, "key", &blob),
+            Ok(SetOutcome::Updated)
+        );
+        let row = store.get_clone("key").unwrap();
+        assert!(row.is_compressed());
+        assert_eq!(row.value(), blob.as_str());
+
+        let other = json_blob(2000);
+        assert_eq!(
+            helpers::set_outcome(&store, "key", &other),
+            Ok(SetOutcome::Updated)
+        );
+        let row = store.get_clone("key").unwrap();
+        assert!(row.is_compressed());
+        assert_eq!(row.value(), other.as_str());
+        assert_eq!(row.version(), 3);
+        assert_eq!(
+            helpers::set_outcome(&store, "key", &other),
+            Ok(SetOutcome::Unchanged)
+        );
+
+        assert_eq!(
+            helpers::set_outcome(&store, "key", "short"),
+            Ok(SetOutcome::Updated)
+        );
+        assert!(!store.get_clone("key").unwrap().is_compressed());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compression_shrinks_snapshots() {
+        let plain = KeyValueStore::empty();
+        let compressed = KeyValueStore::empty().with_options(CompressionOptions::default());
+        for i in 0..20 {
+            let key = format!("key{}", i);
+            let blob = json_blob(32 * 1024);
+            assert!(plain.insert(&key, &blob).is_ok());
+            assert!(compressed.insert(&key, &blob).is_ok());
+        }
+        let plain_len = plain.to_bytes().unwrap().len();
+        let compressed_len = compressed.to_bytes().unwrap().len();
+        assert!(
+            compressed_len * 4 < plain_len,
+            "expected {} to be much smaller than {}",
+            compressed_len,
+            plain_len
+        );
+    }
+
+    #[test]
+    fn from_disk_roundtrip() {
+        let store = helpers::store_with(&[("key1", "value1"), ("key2", "value2"), ("key3", "")]);
+        let disk = store.to_disk().unwrap();
+        let loaded = KeyValueStore::from_disk(&disk).expect("unable to load disk repr");
+        assert_eq!(loaded.len(), store.len());
+        for key in ["key1", "key2", "key3"] {
+            assert_eq!(loaded.get_clone(key), store.get_clone(key));
+        }
+        let disk = super::super::Store::to_disk_repr(&loaded).unwrap();
+        assert_eq!(disk.fingerprint(), store.to_disk().unwrap().fingerprint());
+    }
+
+    #[test]
+    fn from_disk_rejects_bad_reprs() {
+        let mut disk = StoreDiskRepr::from(vec![
+            Row::new("key1", "value1", 1, 2),
+            Row::new("key2", "value2", 1, 2),
+            Row::new("key1", "value3", 3, 4),
+        ]);
+        assert_eq!(
+            KeyValueStore::from_disk(&disk).err(),
+            Some(crate::Error::duplicate_key("key1"))
+        );
+        assert!(KeyValueStore::from_disk_lenient(&disk).is_err());
+        disk.data.pop();
+        assert!(KeyValueStore::from_disk(&disk).is_ok());
+
+        for version in [0, StoreDiskRepr::current_version() + 1] {
+            disk.version = version;
+            assert_eq!(
+                KeyValueStore::from_disk(&disk).err(),
+                Some(crate::Error::UnsupportedVersion(version))
+            );
+        }
+
+        let dir = tempfile::tempdir().expect("unable to create tempdir");
+        let path = dir.path().join("store.json");
+        disk.write_to(&path).expect("unable to write repr");
+        assert_eq!(
+            StoreDiskRepr::read_from(&path).err(),
+            Some(crate::Error::UnsupportedVersion(disk.version))
+        );
+    }
+
+    #[test]
+    fn keys_and_rows() {
+        let store = KeyValueStore::empty();
+        assert_eq!(store.keys(), Ok(vec![]));
+        assert_eq!(store.rows(), Ok(vec![]));
+
+        let store = helpers::store_with(&[("key2", "b"), ("key1", "a"), ("key3", "c")])
+            .with_delete_policy(DeletePolicy::Tombstone);
+        assert!(store
+            .insert_row(&Row::create_with_ttl("expired", "x", -1))
+            .is_ok());
+        store.delete("key3").unwrap();
+
+        let keys = store.keys_sorted().unwrap();
+        assert_eq!(keys, vec!["key1", "key2"]);
+        let mut rows = store.rows().unwrap();
+        rows.sort();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0], store.get_clone("key1").unwrap());
+
+        assert!(store.insert("key4", "d").is_ok());
+        assert!(store.set_or_insert("key1", "changed").is_ok());
+        assert_eq!(keys, vec!["key1", "key2"]);
+        assert_eq!(rows[0].value(), "a");
+        assert_eq!(super::super::Store::keys(&store).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn iteration() {
+        let store = helpers::store_with(&[("key1", "a"), ("key2", "bb"), ("key3", "ccc")]);
+        assert!(store
+            .insert_row(&Row::create_with_ttl("expired", "x", -1))
+            .is_ok());
+
+        let total = store.fold(0, |total, row| total + row.value().size_bytes());
+        assert_eq!(total, Ok(6));
+        let mut keys = Vec::new();
+        assert!(store
+            .for_each(|row| keys.push(row.key().to_string()))
+            .is_ok());
+        keys.sort();
+        assert_eq!(keys, vec!["key1", "key2", "key3"]);
+        assert_eq!(store.count_where(|row| row.value().size_bytes() > 1), Ok(2));
+
+        let mut visited = 0;
+        let completed = store.try_for_each(|_| {
+            visited += 1;
+            visited < 2
+        });
+        assert_eq!((completed, visited), (Ok(false), 2));
+        assert_eq!(store.try_for_each(|_| true), Ok(true));
+    }
+
+    #[test]
+    fn reentrant_iteration_fails() {
+        let store = helpers::store_with(&[("key1", "a"), ("key2", "b")]);
+        let mut results = Vec::new();
+        assert!(store
+            .for_each(|row| {
+                results.push(store.get_clone(row.key()));
+                results.push(store.count_where(|_| true).map(|_| row.clone()));
+            })
+            .is_ok());
+        assert_eq!(results.len(), 4);
+        assert!(results
+            .iter()
+            .all(|result| result == &Err(crate::Error::ReentrantAccess)));
+        assert_eq!(store.len(), Ok(2), "store should be usable after iterating");
+
+        // Iterating a different store from inside the callback is fine.
+        let other = helpers::store_with(&[("other", "c")]);
+        let nested = store.fold(0, |total, _| total + other.len().unwrap());
+        assert_eq!(nested, Ok(2));
+    }
+
+    #[test]
+    fn get_many() {
+        let store = helpers::store_with(&[("key1", "a"), ("key2", "b")]);
+        assert!(store
+            .insert_row(&Row::create_with_ttl("expired", "x", -1))
+            .is_ok());
+        assert_eq!(store.get_many(&[]), Ok(vec![]));
+        assert_eq!(store.get_existing(&[]), Ok(vec![]));
+
+        let results = store
+            .get_many(&["key1", "missing", "expired", "key2", "key1"])
+            .unwrap();
+        let summary = results
+            .iter()
+            .map(|(key, row)| (key.as_str(), row.as_ref().map(|row| row.value().clone())))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("key1", Ok(Value::from("a"))),
+                ("missing", Err(&crate::Error::key_not_found("missing"))),
+                ("expired", Err(&crate::Error::key_not_found("expired"))),
+                ("key2", Ok(Value::from("b"))),
+                ("key1", Ok(Value::from("a"))),
+            ]
+        );
+
+        let rows = store.get_existing(&["key2", "missing", "key1"]).unwrap();
+        let keys = rows.iter().map(Row::key).collect::<Vec<_>>();
+        assert_eq!(keys, vec!["key2", "key1"]);
+    }
+
+    #[test]
+    fn get_many_locks_once() {
+        use std::sync::atomic::Ordering;
+
+        let store = helpers::store_with(&[("key1", "a"), ("key2", "b")]);
+        let keys = (0..1000)
+            .map(|i| if i % 2 == 0 { "key1" } else { "key2" })
+            .collect::<Vec<_>>();
+
+        let before = store.lock_count.load(Ordering::Relaxed);
+        let results = store.get_many(&keys).unwrap();
+        assert_eq!(results.len(), 1000);
+        assert_eq!(store.lock_count.load(Ordering::Relaxed) - before, 1);
+
+        let before = store.lock_count.load(Ordering::Relaxed);
+        for key in &keys {
+            assert!(store.get_clone(key).is_ok());
+        }
+        assert_eq!(store.lock_count.load(Ordering::Relaxed) - before, 1000);
+    }
+
+    #[test]
+    fn insert_many() {
+        let store = helpers::store_with(&[("key2", "old")]);
+        assert_eq!(store.insert_many(&[]), Ok(0));
+        assert_eq!(store.len(), Ok(1));
+
+        assert_eq!(
+            store.insert_many(&[("key1", "a"), ("key2", "b"), ("key3", "c")]),
+            Err(crate::Error::duplicate_key("key2"))
+        );
+        assert_eq!(
+            store.insert_many(&[("key1", "a"), ("key3", "c"), ("key1", "d")]),
+            Err(crate::Error::duplicate_key("key1"))
+        );
+        assert_eq!(store.keys_sorted(), Ok(vec!["key2".to_string()]));
+        assert_eq!(store.get_clone("key2").unwrap().value(), "old");
+
+        assert_eq!(store.insert_many(&[("key1", "a"), ("key3", "c")]), Ok(2));
+        assert_eq!(store.len(), Ok(3));
+        assert_eq!(store.get_clone("key3").unwrap().value(), "c");
+    }
+
+    #[test]
+    fn set_many() {
+        let store = helpers::store_with(&[("key1", "a"), ("key2", "b")]);
+        assert_eq!(store.set_many(&[]), Ok(SetManyOutcome::default()));
+
+        let outcome = store
+            .set_many(&[("key1", "a"), ("key2", "changed"), ("key3", "new")])
+            .unwrap();
+        assert_eq!(
+            outcome,
+            SetManyOutcome {
+                inserted: 1,
+                updated: 1,
+                unchanged: 1
+            }
+        );
+        assert_eq!(outcome.total(), 3);
+        assert_eq!(store.get_clone("key2").unwrap().value(), "changed");
+        assert_eq!(store.len(), Ok(3));
+    }
+
+    #[test]
+    fn insert_many_through_trait() {
+        use std::sync::atomic::Ordering;
+
+        let store = KeyValueStore::empty();
+        let owned = (0..1000)
+            .map(|i| (format!("key{}", i), format!("value{}", i)))
+            .collect::<Vec<_>>();
+        let pairs = owned
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect::<Vec<_>>();
+
+        let before = store.lock_count.load(Ordering::Relaxed);
+        let dyn_store: &dyn super::super::Store = &store;
+        assert_eq!(dyn_store.insert_many(&pairs), Ok(1000));
+        assert_eq!(store.lock_count.load(Ordering::Relaxed) - before, 1);
+        assert_eq!(dyn_store.len(), Ok(1000));
+        assert_eq!(dyn_store.set_many(&pairs).unwrap().unchanged, 1000);
+    }
+
+    #[test]
+    fn delete_many() {
+        let store = helpers::store_with(&[("key1", "a"), ("key2", "b"), ("key3", "c")]);
+        assert_eq!(
+            store.delete_many(&["key1", "missing", "key2"]),
+            Err(crate::Error::key_not_found("missing"))
+        );
+        assert_eq!(
+            store.delete_many(&["key1", "key1"]),
+            Err(crate::Error::key_not_found("key1"))
+        );
+        assert_eq!(store.len(), Ok(3));
+
+        let deleted = store.delete_many(&["key3", "key1"]).unwrap();
+        assert_eq!(
+            deleted.iter().map(Row::key).collect::<Vec<_>>(),
+            vec!["key3", "key1"]
+        );
+        assert_eq!(store.keys_sorted(), Ok(vec!["key2".to_string()]));
+        assert_eq!(store.delete_many(&[]), Ok(vec![]));
+    }
+
+    #[test]
+    fn try_delete_many() {
+        let store = helpers::store_with(&[("key1", "a"), ("key2", "b"), ("key3", "c")])
+            .with_delete_policy(DeletePolicy::Tombstone);
+        let report = store
+            .try_delete_many(&["key1", "missing", "key2", "key1"])
+            .unwrap();
+        assert_eq!(
+            report.deleted.iter().map(Row::key).collect::<Vec<_>>(),
+            vec!["key1", "key2"]
+        );
+        assert_eq!(
+            report.missing,
+            vec!["missing".to_string(), "key1".to_string()]
+        );
+        assert_eq!(store.len(), Ok(1));
+        assert_eq!(store.len_raw(), Ok(3));
+    }
+
+    #[test]
+    fn delete_matching() {
+        let store = helpers::store_with(&[("key1", "a"), ("key2", "bb"), ("key3", "ccc")]);
+        assert_eq!(store.delete_matching(|_| false), Ok(vec![]));
+        assert_eq!(store.len(), Ok(3));
+
+        let mut deleted = store
+            .delete_matching(|row| row.value().to_string().len() > 1)
+            .unwrap();
+        deleted.sort();
+        assert_eq!(
+            deleted.iter().map(Row::key).collect::<Vec<_>>(),
+            vec!["key2", "key3"]
+        );
+        assert_eq!(store.keys_sorted(), Ok(vec!["key1".to_string()]));
+
+        let cutoff = super::super::create_now() + 1000;
+        assert_eq!(
+            store
+                .delete_matching(|row| row.created() < cutoff)
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(store.len(), Ok(0));
+    }
+
+    #[test]
+    fn prefix_scans() {
+        let store = helpers::store_with(&[
+            ("user:1", "a"),
+            ("user:1:name", "b"),
+            ("user:10", "c"),
+            ("user:2:name", "d"),
+            ("group:1", "e"),
+            ("ключ:1", "f"),
+            ("ключ:2", "g"),
+        ]);
+        assert!(store.delete("user:10").is_ok());
+
+        assert_eq!(store.keys_with_prefix(""), store.keys_sorted());
+        assert_eq!(store.count_prefix(""), Ok(6));
+        assert_eq!(store.keys_with_prefix("nope"), Ok(vec![]));
+        assert_eq!(store.scan_prefix("nope"), Ok(vec![]));
+        assert_eq!(store.count_prefix("nope"), Ok(0));
+
+        assert_eq!(
+            store.keys_with_prefix("user:1"),
+            Ok(vec!["user:1".to_string(), "user:1:name".to_string()])
+        );
+        let rows = store.scan_prefix("user:").unwrap();
+        assert_eq!(
+            rows.iter().map(Row::key).collect::<Vec<_>>(),
+            vec!["user:1", "user:1:name", "user:2:name"]
+        );
+        assert_eq!(store.count_prefix("user:1:name"), Ok(1));
+
+        assert_eq!(store.count_prefix("клю"), Ok(2));
+        assert_eq!(store.scan_prefix("ключ:2").unwrap()[0].value(), "g");
+    }
+
+    #[test]
+    fn shared_suite() {
+        super::super::test_helpers::check_store::<KeyValueStore>();
+    }
+
+    #[test]
+    fn compare_and_swap() {
+        let store = KeyValueStore::empty();
+        let row = Row::builder()
+            .key("key")
+            .value("old")
+            .created(1)
+            .updated(1)
+            .build()
+            .unwrap();
+        assert!(store.insert_row(&row).is_ok());
+
+        assert_eq!(
+            store.compare_and_swap("key", "wrong", "new"),
+            Ok(CasOutcome::ValueMismatch {
+                actual: "old".to_string()
+            })
+        );
+        assert_eq!(store.get_clone("key"), Ok(row.clone()));
+        assert_eq!(
+            store.compare_and_swap("missing", "old", "new"),
+            Ok(CasOutcome::KeyMissing)
+        );
+
+        assert_eq!(
+            store.compare_and_swap("key", "old", "new"),
+            Ok(CasOutcome::Swapped)
+        );
+        let swapped = store.get_clone("key").unwrap();
+        assert_eq!(swapped.value(), "new");
+        assert_eq!(swapped.created(), 1);
+        assert!(swapped.updated() > 1);
+
+        let store = store.with_delete_policy(DeletePolicy::Tombstone);
+        assert_eq!(
+            store.compare_and_delete("key", "new"),
+            Ok(CasOutcome::Swapped)
+        );
+        assert_eq!(
+            store.compare_and_swap("key", "new", "x"),
+            Ok(CasOutcome::KeyMissing)
+        );
+        assert_eq!(store.len_raw(), Ok(1));
+    }
+
+    #[test]
+    fn compare_and_swap_race() {
+        use std::sync::{Arc, Barrier};
+
+        for _ in 0..20 {
+            let store = Arc::new(helpers::store_with(&[("key", "start")]));
+            let barrier = Arc::new(Barrier::new(2));
+            let handles = (0..2)
+                .map(|t| {
+                    let store = Arc::clone(&store);
+                    let barrier = Arc::clone(&barrier);
+                    std::thread::spawn(move || {
+                        barrier.wait();
+                        store
+                            .compare_and_swap("key", "start", &format!("thread{}", t))
+                            .unwrap()
+                    })
+                })
+                .collect::<Vec<_>>();
+            let outcomes = handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<Vec<_>>();
+
+            assert_eq!(outcomes.iter().filter(|o| o.is_swapped()).count(), 1);
+            let winner = store.get_clone("key").unwrap().value().to_display_string();
+            let loser = outcomes.iter().find(|o| !o.is_swapped()).unwrap();
+            assert_eq!(loser, &CasOutcome::ValueMismatch { actual: winner });
+        }
+    }
+
+    #[test]
+    fn update_with() {
+        let store = KeyValueStore::with_limits(16, 4);
+        let row = Row::builder()
+            .key("key")
+            .value("same")
+            .created(1)
+            .updated(1)
+            .build()
+            .unwrap();
+        assert!(store.insert_row(&row).is_ok());
+
+        let unchanged = store
+            .update_with("key", |row| {
+                row.update("same");
+            })
+            .unwrap();
+        assert_eq!(unchanged.updated(), 1);
+        assert_eq!(
+            store.update_with("missing", |_| {}),
+            Err(crate::Error::key_not_found("missing"))
+        );
+
+        assert_eq!(
+            store.update_with("key", |row| {
+                row.update("much too long");
+            }),
+            Err(crate::Error::value_too_large("key", 13, 4))
+        );
+        assert_eq!(store.get_clone("key"), Ok(row));
+
+        let changed = store.update_with("key", |row| {
+            row.update("new");
+        });
+        assert!(changed.unwrap().updated() > 1);
+    }
+
+    #[test]
+    fn update_with_counter() {
+        use std::sync::Arc;
+
+        let store = Arc::new(KeyValueStore::empty());
+        let handles = (0..8)
+            .map(|_| {
+                let store = Arc::clone(&store);
+                std::thread::spawn(move || {
+                    for _ in 0..250 {
+                        let result = store.upsert_with(
+                            "counter",
+                            || Row::create("counter", "0"),
+                            |row| {
+                                let next = row.parse_value::<u64>().unwrap() + 1;
+                                row.update(&next.to_string());
+                            },
+                        );
+                        assert!(result.is_ok());
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(store.get_parsed::<u64>("counter"), Ok(2000));
+    }
+
+    #[test]
+    fn with_entry() {
+        let store = helpers::store_with(&[("keep", "1"), ("drop", "2"), ("bump", "3")]);
+
+        // Conditional delete: only rows holding an even number go.
+        for key in ["keep", "drop"] {
+            let deleted = store
+                .with_entry(key, |mut entry| match entry.row() {
+                    Some(row) if row.parse_value::<u64>().unwrap() % 2 == 0 => entry.delete(),
+                    _ => None,
+                })
+                .unwrap();
+            assert_eq!(deleted.is_some(), key == "drop");
+        }
+        assert!(store.get_clone("keep").is_ok());
+        assert!(store.get_clone("drop").is_err());
+
+        // Conditional update: only bump values under 5.
+        let updated = store
+            .with_entry("bump", |mut entry| {
+                let next = entry.row().unwrap().parse_value::<u64>().unwrap() + 1;
+                (next < 5).then(|| entry.set(&next.to_string())).is_some()
+            })
+            .unwrap();
+        assert!(updated);
+        assert_eq!(store.get_clone("bump").unwrap().value(), "4");
+
+        // Insert through the entry, which refuses existing keys.
+        let inserted = store
+            .with_entry("new", |mut entry| {
+                assert!(!entry.exists());
+                entry.insert("fresh")
+            })
+            .unwrap();
+        assert_eq!(inserted, Ok(()));
+        assert_eq!(store.get_clone("new").unwrap().value(), "fresh");
+        let duplicate = store
+            .with_entry("new", |mut entry| entry.insert("again"))
+            .unwrap();
+        assert_eq!(duplicate, Err(crate::Error::duplicate_key("new")));
+        assert_eq!(store.get_clone("new").unwrap().value(), "fresh");
+
+        // Reading only leaves the store alone.
+        let stats = store.stats();
+        let key = store
+            .with_entry("keep", |entry| entry.key().to_string())
+            .unwrap();
+        assert_eq!(key, "keep");
+        assert_eq!(store.stats(), stats);
+    }
+
+    #[test]
+    fn with_entry_does_not_interleave() {
+        use std::sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        };
+
+        let store = Arc::new(KeyValueStore::empty());
+        let inside = Arc::new(AtomicBool::new(false));
+        let handles = (0..2)
+            .map(|_| {
+                let store = Arc::clone(&store);
+                let inside = Arc::clone(&inside);
+                std::thread::spawn(move || {
+                    for _ in 0..500 {
+                        store
+                            .with_entry("counter", |mut entry| {
+                                assert!(!inside.swap(true, Ordering::SeqCst));
+                                let next = entry
+                                    .row()
+                                    .map_or(0, |row| row.parse_value::<u64>().unwrap())
+                                    + 1;
+                                entry.set(&next.to_string()).unwrap();
+                                inside.store(false, Ordering::SeqCst);
+                            })
+                            .unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(store.get_clone("counter").unwrap().value(), "1000");
+    }
+
+    #[test]
+    fn with_entry_reentrant() {
+        let store = helpers::store_with(&[("key", "value")]);
+        let inner = store.with_entry("key", |_| store.get_clone("key")).unwrap();
+        assert_eq!(inner, Err(crate::Error::ReentrantAccess));
+        assert!(store.get_clone("key").is_ok());
+    }
+
+    #[test]
+    fn get_or_insert_with() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let store = helpers::store_with(&[("existing", "value")]);
+        let calls = AtomicUsize::new(0);
+        let make = || {
+            calls.fetch_add(1, Ordering::SeqCst);
+            "made".to_string()
+        };
+
+        let row = store.get_or_insert_with("existing", make).unwrap();
+        assert_eq!(row.value(), "value");
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        let row = store.get_or_insert_with("new", make).unwrap();
+        assert_eq!(row.value(), "made");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(store.get_clone("new"), Ok(row.clone()));
+        assert_eq!(store.get_or_insert("new", "other"), Ok(row));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        assert!(store.get_or_insert("", "v").is_err());
+    }
+
+    #[test]
+    fn get_or_insert_with_race() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::{Arc, Barrier};
+
+        let store = Arc::new(KeyValueStore::empty());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(8));
+        let handles = (0..8)
+            .map(|t| {
+                let (store, calls, barrier) =
+                    (Arc::clone(&store), Arc::clone(&calls), Arc::clone(&barrier));
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    store
+                        .get_or_insert_with("memo", || {
+                            calls.fetch_add(1, Ordering::SeqCst);
+                            format!("thread{}", t)
+                        })
+                        .unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+        let rows = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        let resident = store.get_clone("memo").unwrap();
+        assert!(rows.iter().all(|row| *row == resident));
+    }
+
+    #[test]
+    fn rename_bumps_updated() {
+        let store = helpers::store_with(&[("old", "value"), ("taken", "other")]);
+        let before = store.get_clone("old").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert_eq!(store.rename("old", "new"), Ok(()));
+        let after = store.get_clone("new").unwrap();
+        assert_eq!(after.created(), before.created());
+        assert!(after.updated() > before.updated());
+        assert!(after.verify_checksum());
+
+        assert_eq!(
+            store.rename("new", "taken"),
+            Err(crate::Error::duplicate_key("taken"))
+        );
+        assert_eq!(store.get_clone("new"), Ok(after));
+        assert_eq!(store.get_clone("taken").unwrap().value(), "other");
+        assert_eq!(
+            store.rename("old", "anything"),
+            Err(crate::Error::key_not_found("old"))
+        );
+        assert_eq!(
+            store.rename_overwrite("nothing", "new"),
+            Err(crate::Error::key_not_found("nothing"))
+        );
+    }
+
+    #[test]
+    fn rename_races_with_reader() {
+        use std::sync::{Arc, Barrier};
+
+        let store = Arc::new(helpers::store_with(&[("old", "value")]));
+        let barrier = Arc::new(Barrier::new(2));
+        let reader = {
+            let (store, barrier) = (Arc::clone(&store), Arc::clone(&barrier));
+            std::thread::spawn(move || {
+                barrier.wait();
+                let mut gone = false;
+                for _ in 0..10_000 {
+                    match store.get_clone("old") {
+                        Ok(row) => {
+                            // Once the row has moved it never comes back.
+                            assert!(!gone);
+                            assert_eq!(row.value(), "value");
+                        }
+                        Err(err) => {
+                            assert_eq!(err, crate::Error::key_not_found("old"));
+                            // The rename is atomic, so the row is already under its new key.
+                            assert!(store.contains("new").unwrap());
+                            gone = true;
+                        }
+                    }
+                }
+            })
+        };
+        barrier.wait();
+        assert_eq!(store.rename("old", "new"), Ok(()));
+        reader.join().unwrap();
+        assert_eq!(store.contains("old"), Ok(false));
+        assert_eq!(store.get_clone("new").unwrap().value(), "value");
+    }
+
+    #[test]
+    fn clear_and_is_empty() {
+        let store = KeyValueStore::empty();
+        assert_eq!(store.is_empty(), Ok(true));
+        assert_eq!(store.clear(), Ok(0));
+
+        let store = helpers::store_with(&[("key1", "a"), ("key2", "b"), ("key3", "c")]);
+        assert_eq!(store.is_empty(), Ok(false));
+        assert_eq!(store.clear(), Ok(3));
+        assert_eq!(store.is_empty(), Ok(true));
+        assert_eq!(store.len(), Ok(0));
+        assert_eq!(
+            store.get_clone("key1"),
+            Err(crate::Error::key_not_found("key1"))
+        );
+        assert_eq!(store.insert("key1", "again"), Ok(()));
+        assert_eq!(store.is_empty(), Ok(false));
+    }
+
+    #[test]
+    fn clear_removes_tombstones() {
+        let store = helpers::store_with(&[("key1", "a"), ("key2", "b")])
+            .with_delete_policy(DeletePolicy::Tombstone);
+        store.delete("key1").unwrap();
+        assert_eq!(store.len_raw(), Ok(2));
+        assert_eq!(store.clear(), Ok(1));
+        assert_eq!(store.len_raw(), Ok(0));
+    }
+
+    #[test]
+    fn retain() {
+        let store = helpers::store_with(&[("key1", "a"), ("key2", "b"), ("key3", "c")]);
+        assert_eq!(store.retain(|_, _| true), Ok(0));
+        assert_eq!(store.len(), Ok(3));
+        assert_eq!(store.retain(|key, _| key != "key2"), Ok(1));
+        assert_eq!(
+            store.keys_sorted(),
+            Ok(vec!["key1".to_string(), "key3".to_string()])
+        );
+        assert_eq!(store.retain(|_, row| row.value() == "z"), Ok(2));
+        assert_eq!(store.is_empty(), Ok(true));
+        assert_eq!(store.retain(|_, _| false), Ok(0));
+    }
+
+    #[test]
+    fn retain_panic_leaves_store_usable() {
+        let store = helpers::store_with(&[("key1", "a"), ("key2", "b")]);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            store.retain(|_, _| panic!("predicate failed"))
+        }));
+        assert!(result.is_err());
+        assert_eq!(store.len(), Ok(2));
+        assert_eq!(store.insert("key3", "c"), Ok(()));
+        assert_eq!(store.retain(|_, _| false), Ok(3));
+    }
+
+    #[test]
+    fn retain_updated_since() {
+        let store = helpers::store_with(&[("key1", "a"), ("key2", "b"), ("key3", "c")]);
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let cutoff = super::super::create_now();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        store.set_or_insert("key2", "fresh").unwrap();
+        assert_eq!(store.retain_updated_since(cutoff), Ok(2));
+        assert_eq!(store.get_clone("key2").unwrap().value(), "fresh");
+        assert_eq!(store.len(), Ok(1));
+    }
+
+    #[test]
+    fn merge_from_strategies_and_idempotence() {
+        let local = [
+            Row::new("older", "local", 1, 10),
+            Row::new("newer", "local", 1, 30),
+        ];
+        let other = StoreDiskRepr::from(vec![
+            Row::new("older", "remote", 1, 20),
+            Row::new("newer", "remote", 1, 20),
+            Row::new("remote-only", "remote", 1, 10),
+        ]);
+        for (strategy, older, newer) in [
+            (MergeStrategy::LastWriteWins, "remote", "local"),
+            (MergeStrategy::FirstWriteWins, "local", "remote"),
+            (MergeStrategy::PreferOther, "remote", "remote"),
+        ] {
+            let store = KeyValueStore::empty();
+            for row in &local {
+                store.insert_row(row).unwrap();
+            }
+            let report = store.merge_from(&other, strategy).unwrap();
+            assert_eq!(report.inserted, 1, "{:?}", strategy);
+            assert_eq!(store.get_clone("older").unwrap().value(), older);
+            assert_eq!(store.get_clone("newer").unwrap().value(), newer);
+
+            let rows = store.to_disk().unwrap().sorted_by_key();
+            let again = store.merge_from(&other, strategy).unwrap();
+            assert_eq!(again.changed(), 0, "{:?}", strategy);
+            assert_eq!(again.unchanged, 3);
+            assert_eq!(
+                store.to_disk().unwrap().sorted_by_key().fingerprint(),
+                rows.fingerprint()
+            );
         }
+    }
 
-        let result = original.to_bytes();
-        assert!(result.is_ok());
-        let bytes = result.unwrap();
-        let mut tempfile = tempfile::tempfile().expect("Unable to open tempfile");
-        tempfile
-            .write_all(&bytes)
-            .expect("Unable to write to tempfile");
+    #[test]
+    fn diff() {
+        let store = helpers::store_with(&[("key1", "a"), ("key2", "b"), ("key3", "c")]);
+        let snapshot = store.to_disk().unwrap();
+        assert!(store.diff(&snapshot).unwrap().is_empty());
+        assert!(KeyValueStore::empty()
+            .diff(&StoreDiskRepr::from(Vec::<Row>::new()))
+            .unwrap()
+            .is_empty());
 
-        assert!(original.insert("key4", "value4").is_ok());
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        store.insert("key4", "d").unwrap();
+        store.set_or_insert("key1", "changed").unwrap();
+        store.delete("key2").unwrap();
+        let diff = store.diff(&snapshot).unwrap();
+        assert_eq!(diff.only_in_self, vec!["key4".to_string()]);
+        assert_eq!(diff.only_in_other, vec!["key2".to_string()]);
+        assert_eq!(diff.different.len(), 1);
+        let (ours, theirs) = &diff.different[0];
         assert_eq!(
-            original
-                .len()
-                .expect("tempfile_roundtrip (original) - unable to get length"),
-            4
+            (ours.value(), theirs.value()),
+            (&"changed".into(), &"a".into())
         );
 
-        tempfile
-            .seek(SeekFrom::Start(0))
-            .expect("Unable to seek to start in tempfile");
-        let rbytes = tempfile
-            .bytes()
-            .collect::<Result<Vec<u8>, _>>()
-            .expect("Unable to read tempfile");
+        assert_eq!(
+            store
+                .merge_from(&snapshot, MergeStrategy::PreferOther)
+                .map(|report| report.changed()),
+            Ok(2)
+        );
+        let diff = store.diff(&snapshot).unwrap();
+        assert_eq!(diff.only_in_self, vec!["key4".to_string()]);
+        assert!(diff.only_in_other.is_empty() && diff.different.is_empty());
+    }
 
-        let result = KeyValueStore::from_bytes(&rbytes);
-        assert!(result.is_ok());
-        let clone = result.unwrap();
+    #[test]
+    fn readers_alongside_writer() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
 
-        // Assert clone
+        const UPDATES: i64 = 2000;
+
+        let store = Arc::new(helpers::store_with(&[("counter", "0")]));
+        let done = Arc::new(AtomicBool::new(false));
+        let readers = (0..8)
+            .map(|_| {
+                let (store, done) = (Arc::clone(&store), Arc::clone(&done));
+                std::thread::spawn(move || {
+                    let mut last = 0;
+                    while !done.load(Ordering::Acquire) {
+                        let seen = store
+                            .get_clone("counter")
+                            .unwrap()
+                            .parse_value::<i64>()
+                            .unwrap();
+                        assert!(seen >= last, "counter went backwards");
+                        last = seen;
+                        assert_eq!(store.contains("counter"), Ok(true));
+                        assert_eq!(store.len(), Ok(1));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for _ in 0..UPDATES {
+            store
+                .update_with("counter", |row| {
+                    let next = row.parse_value::<i64>().unwrap() + 1;
+                    row.update(next.to_string());
+                })
+                .unwrap();
+        }
+        done.store(true, Ordering::Release);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(
+            store.get_clone("counter").unwrap().parse_value::<i64>(),
+            Ok(UPDATES)
+        );
+    }
+
+    #[test]
+    #[ignore = "benchmark, run with --ignored --nocapture"]
+    fn read_throughput() {
+        use std::sync::{Arc, Mutex};
+        use std::time::{Duration, Instant};
+
+        const READS: usize = 200_000;
+        const THREADS: usize = 8;
+
+        fn time_readers<F: Fn(&str) + Send + Sync + 'static>(read: F) -> Duration {
+            let read = Arc::new(read);
+            let start = Instant::now();
+            let handles = (0..THREADS)
+                .map(|t| {
+                    let read = Arc::clone(&read);
+                    std::thread::spawn(move || {
+                        for i in 0..READS {
+                            read(&format!("key{}", (i * THREADS + t) % 1000));
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+            start.elapsed()
+        }
+
+        let store = Arc::new(helpers::fill_single_thread(1000));
+        // The store as it was before it switched to a `RwLock`.
+        let mutex = Arc::new(Mutex::new(
+            store
+                .rows()
+                .unwrap()
+                .into_iter()
+                .map(|row| (row.key().to_string(), row))
+                .collect::<Data>(),
+        ));
+
+        let locked = time_readers(move |key| {
+            assert!(mutex.lock().unwrap().get(key).cloned().is_some());
+        });
+        let shared = time_readers(move |key| {
+            assert!(store.get_clone(key).is_ok());
+        });
+        println!(
+            "{} reads on {} threads: Mutex {:?}, RwLock {:?} ({:.2}x)",
+            READS * THREADS,
+            THREADS,
+            locked,
+            shared,
+            locked.as_secs_f64() / shared.as_secs_f64()
+        );
+    }
+
+    /// Panics inside `update_with` while the store is locked for writing.
+    fn poison(store: &std::sync::Arc<KeyValueStore>) {
+        let store = std::sync::Arc::clone(store);
+        let result = std::thread::spawn(move || {
+            let _ = store.update_with("key1", |_| panic!("poisoning the store"));
+        })
+        .join();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn poison_policy_error() {
+        let store = std::sync::Arc::new(helpers::store_with(&[("key1", "a")]));
+        assert!(!store.heal());
+        poison(&store);
+
+        assert!(matches!(
+            store.get_clone("key1"),
+            Err(crate::Error::MutexPoisoned(..))
+        ));
+        assert!(matches!(
+            store.insert("key2", "b"),
+            Err(crate::Error::MutexPoisoned(..))
+        ));
+        assert!(store.heal());
+        assert!(!store.heal());
+        assert_eq!(store.get_clone("key1").unwrap().value(), "a");
+        assert_eq!(store.insert("key2", "b"), Ok(()));
+    }
+
+    #[test]
+    fn poison_policy_recover() {
+        let store = std::sync::Arc::new(
+            helpers::store_with(&[("key1", "a")]).with_poison_policy(PoisonPolicy::Recover),
+        );
+        poison(&store);
+
+        assert_eq!(store.get_clone("key1").unwrap().value(), "a");
+        assert_eq!(store.insert("key2", "b"), Ok(()));
+        assert_eq!(store.len(), Ok(2));
+        assert!(store.heal(), "recovering doesn't clear the poisoning");
+    }
+
+    #[test]
+    fn memory_usage() {
+        use std::mem::size_of;
+
+        let store = KeyValueStore::empty();
+        assert_eq!(store.memory_usage(), Ok(StoreSize::default()));
+        assert_eq!(store.largest_rows(3), Ok(vec![]));
+
+        let store = helpers::store_with(&[("a", "12345"), ("bb", "123"), ("ccc", "")]);
+        let size = store.memory_usage().unwrap();
+        assert_eq!(size.row_count, 3);
+        assert_eq!(size.key_bytes, 6);
+        assert_eq!(size.value_bytes, 8);
+        assert_eq!(
+            size.approx_total_bytes,
+            3 * (size_of::<Row>() + StoreSize::ENTRY_OVERHEAD_BYTES) + 2 * 6 + 8
+        );
+    }
+
+    #[test]
+    fn largest_rows() {
+        let store = helpers::store_with(&[
+            ("small", "1"),
+            ("tie-b", "12345"),
+            ("big", "1234567890"),
+            ("tie-a", "54321"),
+        ]);
+        let keys = |rows: Vec<Row>| {
+            rows.iter()
+                .map(|row| row.key().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            keys(store.largest_rows(3).unwrap()),
+            vec!["big", "tie-a", "tie-b"]
+        );
+        assert_eq!(keys(store.largest_rows(10).unwrap()).len(), 4);
+        assert_eq!(store.largest_rows(0), Ok(vec![]));
+
+        store.delete("big").unwrap();
+        assert_eq!(keys(store.largest_rows(1).unwrap()), vec!["tie-a"]);
+    }
+
+    #[test]
+    fn stats() {
+        let store = KeyValueStore::empty();
+        assert_eq!(store.stats(), StoreStatsSnapshot::default());
+        assert_eq!(store.stats().hit_rate(), None);
+
+        store.insert("a", "1").unwrap();
+        store.insert("b", "1").unwrap();
+        assert!(store.insert("b", "2").is_err());
+        store.get_clone("a").unwrap();
+        assert!(store.get_clone("c").is_err());
+        store.set_or_insert("a", "1").unwrap();
+        store.set_or_insert("a", "2").unwrap();
+        store.set_or_insert("c", "3").unwrap();
+        store
+            .update_with("b", |row| {
+                row.update("4");
+            })
+            .unwrap();
+        store.delete("a").unwrap();
+        assert!(store.delete("a").is_err());
+        assert!(store.get_clone("a").is_err());
+
+        let stats = store.stats();
+        assert_eq!(
+            stats,
+            StoreStatsSnapshot {
+                gets: 3,
+                get_misses: 2,
+                inserts: 3,
+                updates: 2,
+                deletes: 1,
+                delete_misses: 1,
+            }
+        );
+        assert_eq!(stats.hit_rate(), Some(1.0 / 3.0));
+
+        store.reset_stats();
+        assert_eq!(store.stats(), StoreStatsSnapshot::default());
+        assert_eq!(store.len(), Ok(2));
+    }
+
+    #[test]
+    fn stats_across_threads() {
+        use std::sync::Arc;
+
+        let store = Arc::new(KeyValueStore::empty());
+        let handles = (0..8)
+            .map(|t| {
+                let store = Arc::clone(&store);
+                std::thread::spawn(move || {
+                    for i in 0..100 {
+                        let key = format!("{}-{}", t, i);
+                        store.insert(&key, "value").unwrap();
+                        store.get_clone(&key).unwrap();
+                        assert!(store.get_clone(&format!("missing-{}", key)).is_err());
+                        store.delete(&key).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let stats = store.stats();
+        assert_eq!(
+            stats,
+            StoreStatsSnapshot {
+                gets: 1600,
+                get_misses: 800,
+                inserts: 800,
+                updates: 0,
+                deletes: 800,
+                delete_misses: 0,
+            }
+        );
+        assert_eq!(stats.hit_rate(), Some(0.5));
+    }
+
+    #[test]
+    fn subscribe() {
+        use super::super::test_helpers::drain_events;
+
+        let store = KeyValueStore::empty();
+        let events = store.subscribe();
+        store.insert("key", "one").unwrap();
+        store.set_or_insert("key", "two").unwrap();
+        store.set_or_insert("key", "two").unwrap();
+        store
+            .update_with("key", |row| {
+                row.update("three");
+            })
+            .unwrap();
+        store.delete("key").unwrap();
+        assert_eq!(
+            drain_events(&events),
+            vec![
+                "inserted key: one",
+                "updated key: one -> two",
+                "updated key: two -> three",
+                "deleted key: three",
+            ]
+        );
+
+        // Failed operations send nothing.
+        store.insert("other", "1").unwrap();
+        assert!(store.insert("other", "2").is_err());
+        assert!(store.delete("missing").is_err());
+        assert!(store.update_with("missing", |_| {}).is_err());
+        assert_eq!(
+            store.compare_and_swap("other", "2", "3"),
+            Ok(CasOutcome::ValueMismatch {
+                actual: "1".to_string()
+            })
+        );
+        assert_eq!(drain_events(&events), vec!["inserted other: 1"]);
+    }
+
+    #[test]
+    fn subscribe_many() {
+        use super::super::test_helpers::drain_events;
+
+        let store = KeyValueStore::empty();
+        let first = store.subscribe();
+        let second = store.subscribe();
+        store.insert("a", "1").unwrap();
+        assert_eq!(drain_events(&first), vec!["inserted a: 1"]);
+        assert_eq!(drain_events(&second), vec!["inserted a: 1"]);
+
+        drop(first);
+        store.set_or_insert("a", "2").unwrap();
+        assert_eq!(drain_events(&second), vec!["updated a: 1 -> 2"]);
+        assert!(store.events.is_watched());
+
+        drop(second);
+        store.delete("a").unwrap();
+        assert!(!store.events.is_watched());
+        store.insert("b", "1").unwrap();
+        assert_eq!(store.len(), Ok(1));
+    }
+
+    #[test]
+    fn pre_write_hook() {
+        let mut store = KeyValueStore::empty();
+        store.set_pre_write_hook(|key, _| match key.starts_with("user:") {
+            true => Ok(()),
+            false => Err(crate::Error::invalid_key(key, "must start with 'user:'")),
+        });
+        let rejected = |key: &str| crate::Error::invalid_key(key, "must start with 'user:'");
+
+        assert_eq!(store.insert("user:a", "1"), Ok(()));
+        assert_eq!(store.insert("bad", "1"), Err(rejected("bad")));
+        assert_eq!(store.set_or_insert("bad", "1"), Err(rejected("bad")));
+        assert_eq!(
+            store.insert_many(&[("user:b", "1"), ("bad", "1")]),
+            Err(rejected("bad"))
+        );
+        assert_eq!(
+            store.set_many(&[("user:a", "2"), ("bad", "1")]),
+            Err(rejected("bad"))
+        );
+        assert_eq!(store.rename("user:a", "bad"), Err(rejected("bad")));
+
+        assert_eq!(store.keys(), Ok(vec!["user:a".to_string()]));
+        assert_eq!(*store.get_clone("user:a").unwrap().value(), *"1");
+        assert_eq!(store.get_clone("user:a").unwrap().version(), 1);
+
+        // Replacing the hook takes effect straight away.
+        store.set_pre_write_hook(|_, value| match value.is_empty() {
+            true => Err(crate::Error::invalid_row("empty value")),
+            false => Ok(()),
+        });
+        assert_eq!(store.insert("bad", "1"), Ok(()));
+        assert_eq!(
+            store.set_or_insert("bad", ""),
+            Err(crate::Error::invalid_row("empty value"))
+        );
+    }
+
+    #[test]
+    fn post_write_hook() {
+        use std::sync::{Arc, Mutex};
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut store = KeyValueStore::empty();
+        let hook_seen = Arc::clone(&seen);
+        store.set_post_write_hook(move |event| hook_seen.lock().unwrap().push(event.clone()));
+
+        store.insert("key", "1").unwrap();
+        assert!(store.insert("key", "2").is_err());
+        let row = store
+            .update_with("key", |row| {
+                row.update("2");
+                row.set_meta("source", "hook");
+            })
+            .unwrap();
         {
+            let seen = seen.lock().unwrap();
+            assert_eq!(seen.len(), 2);
+            assert!(matches!(&seen[0], RowEvent::Inserted(row) if *row.value() == *"1"));
+            match &seen[1] {
+                RowEvent::Updated { before, after } => {
+                    assert_eq!(*before.value(), *"1");
+                    assert_eq!(after, &row);
+                }
+                event => panic!("expected an update, got {:?}", event),
+            }
+        }
+
+        // Replacing the hook takes effect straight away.
+        let replaced = Arc::new(Mutex::new(Vec::new()));
+        let hook_replaced = Arc::clone(&replaced);
+        store.set_post_write_hook(move |event| {
+            hook_replaced.lock().unwrap().push(event.key().to_string())
+        });
+        store.delete("key").unwrap();
+        assert_eq!(seen.lock().unwrap().len(), 2);
+        assert_eq!(*replaced.lock().unwrap(), vec!["key"]);
+    }
+
+    #[test]
+    fn snapshot() {
+        let store = helpers::store_with(&[("a", "1"), ("b", "2"), ("c", "3")]);
+        let snapshot = store.snapshot().unwrap();
+
+        store.set_or_insert("a", "changed").unwrap();
+        store.delete("b").unwrap();
+        store.insert("d", "4").unwrap();
+
+        assert_eq!(snapshot.len(), 3);
+        assert_eq!(*snapshot.get("a").unwrap().value(), *"1");
+        assert!(snapshot.contains("b"));
+        assert!(!snapshot.contains("d"));
+        assert_eq!(snapshot.get("d"), None);
+        let mut keys = snapshot.keys().collect::<Vec<_>>();
+        keys.sort_unstable();
+        assert_eq!(keys, vec!["a", "b", "c"]);
+        assert_eq!(snapshot.rows().count(), 3);
+        assert_eq!(snapshot.to_disk_repr().data.len(), 3);
+
+        let empty = KeyValueStore::empty().snapshot().unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(empty.keys().count(), 0);
+        assert!(empty.to_disk_repr().data.is_empty());
+    }
+
+    #[test]
+    fn restore_from_snapshot() {
+        let store = KeyValueStore::empty().with_delete_policy(DeletePolicy::Tombstone);
+        store.insert("a", "1").unwrap();
+        store.insert("b", "2").unwrap();
+        store.delete("b").unwrap();
+        let snapshot = store.snapshot().unwrap();
+        assert_eq!(snapshot.len(), 1);
+
+        store.set_or_insert("a", "changed").unwrap();
+        store.insert("c", "3").unwrap();
+        store.restore_from_snapshot(&snapshot).unwrap();
+
+        assert_eq!(store.snapshot(), Ok(snapshot.clone()));
+        assert_eq!(*store.get_clone("a").unwrap().value(), *"1");
+        assert_eq!(store.get_clone("c"), Err(crate::Error::key_not_found("c")));
+        assert_eq!(store.len_raw(), Ok(2));
+
+        store
+            .restore_from_snapshot(&StoreSnapshot::default())
+            .unwrap();
+        assert_eq!(store.is_empty(), Ok(true));
+    }
+
+    #[test]
+    fn into_iter_and_extend() {
+        let pairs = vec![
+            ("a".to_string(), "1".to_string()),
+            ("b".to_string(), "2".to_string()),
+            ("a".to_string(), "3".to_string()),
+        ];
+        let mut store = pairs.into_iter().collect::<KeyValueStore>();
+        assert_eq!(store.len(), Ok(2));
+        assert_eq!((&store).into_iter().count(), 2);
+        for (key, row) in &store {
+            assert_eq!(key, row.key());
+        }
+
+        store.extend(vec![
+            ("b".to_string(), "changed".to_string()),
+            ("c".to_string(), "4".to_string()),
+        ]);
+        store.extend(vec![Row::create("d", "5")]);
+        assert_eq!(store.get_clone("b").unwrap().version(), 2);
+        assert_eq!((&store).into_iter().count(), store.len().unwrap());
+
+        let mut values = store
+            .into_iter()
+            .map(|(key, row)| (key, row.value().to_display_string()))
+            .collect::<Vec<_>>();
+        values.sort();
+        assert_eq!(
+            values,
+            vec![
+                ("a".to_string(), "3".to_string()),
+                ("b".to_string(), "changed".to_string()),
+                ("c".to_string(), "4".to_string()),
+                ("d".to_string(), "5".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "unable to extend store with key 'big'")]
+    fn extend_past_limits() {
+        let mut store = KeyValueStore::with_limits(8, 4);
+        store.extend(vec![("big".to_string(), "too large".to_string())]);
+    }
+
+    #[test]
+    fn apply_transaction() {
+        let op_set = |key: &str, value: &str| Op::Set {
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+        let op_insert = |key: &str, value: &str| Op::Insert {
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+        let op_delete = |key: &str| Op::Delete {
+            key: key.to_string(),
+        };
+        let op_cas = |key: &str, expected: &str, value: &str| Op::Cas {
+            key: key.to_string(),
+            expected: expected.to_string(),
+            value: value.to_string(),
+        };
+
+        let store = helpers::store_with(&[("a", "1"), ("b", "2")]);
+        assert_eq!(store.apply_transaction(&[]), Ok(vec![]));
+
+        let deleted = store.get_clone("b").unwrap();
+        assert_eq!(
+            store.apply_transaction(&[
+                op_set("a", "1"),
+                op_insert("c", "3"),
+                op_delete("b"),
+                op_cas("a", "1", "10"),
+            ]),
+            Ok(vec![
+                OpResult::Set(SetOutcome::Unchanged),
+                OpResult::Inserted,
+                OpResult::Deleted(deleted),
+                OpResult::Swapped,
+            ])
+        );
+        assert_eq!(
+            store.keys_sorted(),
+            Ok(vec!["a".to_string(), "c".to_string()])
+        );
+        assert_eq!(*store.get_clone("a").unwrap().value(), *"10");
+
+        // Later operations see the effects of earlier ones on the same key.
+        let results = store
+            .apply_transaction(&[
+                op_insert("d", "1"),
+                op_cas("d", "1", "2"),
+                op_set("d", "3"),
+                op_delete("c"),
+                op_insert("c", "again"),
+            ])
+            .unwrap();
+        assert_eq!(results.len(), 5);
+        assert_eq!(*store.get_clone("d").unwrap().value(), *"3");
+        assert_eq!(*store.get_clone("c").unwrap().value(), *"again");
+
+        // A failing precondition part-way through applies nothing.
+        let before = store.snapshot().unwrap();
+        let events = store.subscribe();
+        for (ops, error) in [
+            (
+                vec![op_set("a", "changed"), op_insert("c", "dup")],
+                crate::Error::duplicate_key("c"),
+            ),
+            (
+                vec![op_delete("d"), op_delete("d")],
+                crate::Error::key_not_found("d"),
+            ),
+            (
+                vec![op_insert("e", "1"), op_cas("a", "1", "2")],
+                crate::Error::value_mismatch("a", "10"),
+            ),
+        ] {
             assert_eq!(
-                clone
-                    .len()
-                    .expect("tempfile_roundtrip (clone) - unable to get length"),
-                3
-            );
-            assert_eq!(
-                clone
-                    .get_clone("key1")
-                    .expect("tempfile_roundtrip (clone) - unable to get key1")
-                    .value(),
-                "value1"
-            );
-            assert_eq!(
-                clone
-                    .get_clone("key2")
-                    .expect("tempfile_roundtrip (clone) - unable to get key2")
-                    .value(),
-                "value2"
-            );
-            assert_eq!(
-                clone
-                    .get_clone("key3")
-                    .expect("tempfile_roundtrip (clone) - unable to get key3")
-                    .value(),
-                "value3"
+                store.apply_transaction(&ops),
+                Err(crate::Error::transaction_failed(1, error))
             );
-            assert!(clone.get_clone("key4").is_err());
         }
+        assert_eq!(store.snapshot(), Ok(before));
+        assert_eq!(events.try_iter().count(), 0);
     }
 
     #[test]
-    fn check_fill_single() {
-        use helpers::fill_single_thread;
-        let mut vals: usize = 100;
-        let store = fill_single_thread(vals);
+    fn increment() {
+        let store = helpers::store_with(&[("text", "hello"), ("max", "9223372036854775807")]);
+        assert_eq!(store.increment("count", 5), Ok(5));
+        assert_eq!(store.increment("count", -8), Ok(-3));
+        assert_eq!(store.decrement("count", 2), Ok(-5));
+        assert_eq!(store.decrement("count", -10), Ok(5));
+        assert_eq!(store.get_parsed::<i64>("count"), Ok(5));
+
+        assert!(matches!(
+            store.increment("text", 1),
+            Err(crate::Error::ValueParse { .. })
+        ));
+        assert_eq!(*store.get_clone("text").unwrap().value(), *"hello");
+
         assert_eq!(
-            store
-                .len()
-                .expect("check_fill_single - unable to get length"),
-            vals,
-            "check_fill_single - did not add the expected number of values",
+            store.increment("max", 1),
+            Err(crate::Error::overflow("max", i64::MAX, '+', 1))
         );
+        assert_eq!(store.get_parsed::<i64>("max"), Ok(i64::MAX));
+        assert_eq!(store.decrement("max", i64::MAX), Ok(0));
+        assert_eq!(
+            store.decrement("max", i64::MIN),
+            Err(crate::Error::overflow("max", 0, '-', i64::MIN))
+        );
+        assert_eq!(store.get_parsed::<i64>("max"), Ok(0));
+    }
 
-        vals = 200;
-        let store = fill_single_thread(vals);
+    #[test]
+    fn decrement_by_min() {
+        let store = helpers::store_with(&[("count", "-5")]);
+        assert_eq!(store.decrement("count", i64::MIN), Ok(i64::MAX - 4));
+        assert_eq!(store.get_parsed::<i64>("count"), Ok(i64::MAX - 4));
+        assert_eq!(store.increment("count", i64::MIN), Ok(-5));
+        assert_eq!(
+            store.decrement("missing", i64::MIN),
+            Err(crate::Error::overflow("missing", 0, '-', i64::MIN))
+        );
         assert_eq!(
-            store
-                .len()
-                .expect("check_fill_single - unable to get length"),
-            vals,
-            "check_fill_single - did not add the expected number of values",
+            store.get_clone("missing"),
+            Err(crate::Error::key_not_found("missing"))
         );
     }
 
     #[test]
-    fn check_fill_multi() {
-        use helpers::fill_multi_thread;
-        let mut vals: usize = 100;
-        let mut threads: usize = 1;
+    fn increment_across_threads() {
+        use std::sync::Arc;
+
+        let store = Arc::new(KeyValueStore::empty());
+        let handles = (0..16)
+            .map(|_| {
+                let store = Arc::clone(&store);
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        store.increment("counter", 1).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(store.get_parsed::<i64>("counter"), Ok(16_000));
+    }
+
+    #[test]
+    fn find_keys() {
+        let store = helpers::store_with(&[
+            ("user:1:name", "alice"),
+            ("user:2:name", "bob"),
+            ("user:10:name", "carol"),
+            ("user:1:email", "alice@example.com"),
+            ("session:1", "token"),
+            ("user.name", "literal"),
+        ]);
+        store.delete("user:2:name").unwrap();
 
-        let (vals, threads) = (100, 1);
-        let store = fill_multi_thread(vals, threads);
         assert_eq!(
-            store
-                .len()
-                .expect("check_fill_multi_thread - unable to get length"),
-            vals,
-            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
-            vals,
-            threads
+            store.find_keys("user:*"),
+            Ok(vec![
+                "user:10:name".to_string(),
+                "user:1:email".to_string(),
+                "user:1:name".to_string(),
+            ])
         );
-
-        let (vals, threads) = (100, 2);
-        let store = fill_multi_thread(vals, threads);
         assert_eq!(
-            store
-                .len()
-                .expect("check_fill_multi_thread - unable to get length"),
-            vals,
-            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
-            vals,
-            threads
+            store.find_keys("*:?:name"),
+            Ok(vec!["user:1:name".to_string()])
         );
-
-        let (vals, threads) = (100, 3);
-        let store = fill_multi_thread(vals, threads);
         assert_eq!(
-            store
-                .len()
-                .expect("check_fill_multi_thread - unable to get length"),
-            vals,
-            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
-            vals,
-            threads
+            store.find_keys("session:1"),
+            Ok(vec!["session:1".to_string()])
         );
-
-        let (vals, threads) = (100, 4);
-        let store = fill_multi_thread(vals, threads);
+        assert_eq!(store.find_keys("session:"), Ok(vec![]));
         assert_eq!(
-            store
-                .len()
-                .expect("check_fill_multi_thread - unable to get length"),
-            vals,
-            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
-            vals,
-            threads
+            store.find_keys("user.name"),
+            Ok(vec!["user.name".to_string()])
         );
+        assert_eq!(store.find_keys(""), Ok(vec![]));
+        assert_eq!(store.find_keys("*").unwrap().len(), 5);
 
-        let (vals, threads) = (177, 3);
-        let store = fill_multi_thread(vals, threads);
+        assert_eq!(store.find_keys_limited("user:*", 2).unwrap().len(), 2);
+        assert_eq!(store.find_keys_limited("user:*", 0), Ok(vec![]));
+        let rows = store.find_rows("user:1*").unwrap();
         assert_eq!(
-            store
-                .len()
-                .expect("check_fill_multi_thread - unable to get length"),
-            vals,
-            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
-            vals,
-            threads
+            rows.iter().map(|row| row.key()).collect::<Vec<_>>(),
+            vec!["user:10:name", "user:1:email", "user:1:name"]
         );
+    }
 
-        let (vals, threads) = (10000, 1);
-        let store = fill_multi_thread(vals, threads);
-        assert_eq!(
+    #[test]
+    fn time_queries() {
+        let store = KeyValueStore::empty();
+        assert_eq!(store.purge_older_than(i64::MAX), Ok(vec![]));
+        for (key, created, updated) in [("a", 100, 100), ("b", 100, 200), ("c", 200, 300)] {
             store
-                .len()
-                .expect("check_fill_multi_thread - unable to get length"),
-            vals,
-            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
-            vals,
-            threads
-        );
+                .insert_row(&Row::new(key, "value", created, updated))
+                .unwrap();
+        }
+        let keys = |rows: crate::Result<Vec<Row>>| {
+            rows.unwrap()
+                .iter()
+                .map(|row| row.key().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        // `rows_updated_since` includes the boundary, the others don't.
+        assert_eq!(keys(store.rows_updated_since(200)), vec!["b", "c"]);
+        assert_eq!(keys(store.rows_updated_since(201)), vec!["c"]);
+        assert_eq!(keys(store.rows_created_before(200)), vec!["a", "b"]);
+        assert_eq!(keys(store.rows_created_before(100)), Vec::<String>::new());
+
+        assert_eq!(keys(store.purge_older_than(200)), vec!["a"]);
+        assert_eq!(store.get_clone("a"), Err(crate::Error::key_not_found("a")));
+        assert_eq!(keys(store.purge_older_than(301)), vec!["b", "c"]);
+        assert_eq!(store.get_clone("c"), Err(crate::Error::key_not_found("c")));
+        assert_eq!(store.is_empty(), Ok(true));
+    }
+
+    #[test]
+    fn touch() {
+        let store = helpers::store_with(&[("a", "1"), ("b", "2")]);
+        let before = store.get_clone("a").unwrap();
+        let events = store.subscribe();
+
+        let updated = store.touch("a").unwrap();
+        assert!(updated > before.updated());
+        let after = store.get_clone("a").unwrap();
+        assert_eq!(after.updated(), updated);
+        assert_eq!(*after.value(), *"1");
+        assert_eq!(after.version(), before.version());
+        assert_eq!(after.created(), before.created());
+        assert!(store.touch("a").unwrap() > updated);
+        assert_eq!(events.try_iter().count(), 2);
 
-        let (vals, threads) = (10000, 2);
-        let store = fill_multi_thread(vals, threads);
         assert_eq!(
-            store
-                .len()
-                .expect("check_fill_multi_thread - unable to get length"),
-            vals,
-            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
-            vals,
-            threads
+            store.touch("missing"),
+            Err(crate::Error::key_not_found("missing"))
         );
-
-        let (vals, threads) = (10000, 3);
-        let store = fill_multi_thread(vals, threads);
+        let results = store.touch_many(&["a", "missing", "b"]).unwrap();
         assert_eq!(
-            store
-                .len()
-                .expect("check_fill_multi_thread - unable to get length"),
-            vals,
-            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
-            vals,
-            threads
+            results
+                .iter()
+                .map(|(key, _)| key.as_str())
+                .collect::<Vec<_>>(),
+            vec!["a", "missing", "b"]
         );
+        assert!(results[0].1.is_ok());
+        assert_eq!(results[1].1, Err(crate::Error::key_not_found("missing")));
+        assert_eq!(results[2].1, Ok(store.get_clone("b").unwrap().updated()));
+    }
 
-        let (vals, threads) = (10000, 4);
-        let store = fill_multi_thread(vals, threads);
+    #[test]
+    fn read_only() {
+        let store = helpers::store_with(&[("a", "1"), ("b", "2"), ("n", "5")]);
+        assert!(store
+            .insert_row(&Row::create_with_ttl("expired", "x", -1))
+            .is_ok());
+        assert!(!store.is_read_only());
+        let before = store.to_disk().unwrap().sorted_by_key();
+        let snapshot = store.snapshot().unwrap();
+
+        store.set_read_only(true);
+        assert!(store.is_read_only());
+        let attempts = [
+            ("get_or_insert", store.get_or_insert("new", "v").map(drop)),
+            ("insert", store.insert("new", "v")),
+            ("insert_row", store.insert_row(&Row::create("new", "v"))),
+            ("insert_many", store.insert_many(&[("new", "v")]).map(drop)),
+            ("set_or_insert", store.set_or_insert("a", "x").map(drop)),
+            ("set_many", store.set_many(&[("a", "x")]).map(drop)),
+            ("set_display", store.set_display("a", &7).map(drop)),
+            ("set_if_version", store.set_if_version("a", "x", 1)),
+            (
+                "set_or_insert_row",
+                store.set_or_insert_row(&Row::create("a", "x")).map(drop),
+            ),
+            ("increment", store.increment("n", 1).map(drop)),
+            ("decrement", store.decrement("n", 1).map(drop)),
+            ("touch", store.touch("a").map(drop)),
+            ("touch_many", store.touch_many(&["a"]).map(drop)),
+            ("update_with", store.update_with("a", |_| {}).map(drop)),
+            (
+                "with_entry",
+                store.with_entry("a", |mut entry| entry.delete()).map(drop),
+            ),
+            (
+                "upsert_with",
+                store
+                    .upsert_with("a", || Row::create("a", "x"), |_| {})
+                    .map(drop),
+            ),
+            (
+                "compare_and_swap",
+                store.compare_and_swap("a", "1", "x").map(drop),
+            ),
+            (
+                "compare_and_delete",
+                store.compare_and_delete("a", "1").map(drop),
+            ),
+            ("rename", store.rename("a", "renamed")),
+            (
+                "rename_overwrite",
+                store.rename_overwrite("a", "b").map(drop),
+            ),
+            ("delete", store.delete("a").map(drop)),
+            ("take", store.take("a").map(drop)),
+            ("take_if", store.take_if("a", |_| true).map(drop)),
+            ("delete_many", store.delete_many(&["a"]).map(drop)),
+            ("try_delete_many", store.try_delete_many(&["a"]).map(drop)),
+            ("delete_matching", store.delete_matching(|_| true).map(drop)),
+            ("retain", store.retain(|_, _| false).map(drop)),
+            (
+                "retain_updated_since",
+                store.retain_updated_since(i64::MAX).map(drop),
+            ),
+            (
+                "purge_older_than",
+                store.purge_older_than(i64::MAX).map(drop),
+            ),
+            ("purge_tombstones", store.purge_tombstones().map(drop)),
+            ("clear", store.clear().map(drop)),
+            (
+                "apply_transaction",
+                store
+                    .apply_transaction(&[Op::Delete { key: "a".into() }])
+                    .map(drop),
+            ),
+            (
+                "restore_from_snapshot",
+                store.restore_from_snapshot(&snapshot),
+            ),
+            (
+                "merge_from",
+                store
+                    .merge_from(&before, MergeStrategy::PreferOther)
+                    .map(drop),
+            ),
+        ];
+        for (method, result) in attempts {
+            assert_eq!(result, Err(crate::Error::ReadOnly), "{}", method);
+        }
+
+        assert_eq!(store.get_clone("a").unwrap().value(), "1");
         assert_eq!(
-            store
-                .len()
-                .expect("check_fill_multi_thread - unable to get length"),
-            vals,
-            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
-            vals,
-            threads
+            store.get_clone("expired"),
+            Err(crate::Error::key_not_found("expired"))
         );
-
-        let (vals, threads) = (10000, 5);
-        let store = fill_multi_thread(vals, threads);
+        assert_eq!(store.len_raw(), Ok(4));
         assert_eq!(
-            store
-                .len()
-                .expect("check_fill_multi_thread - unable to get length"),
-            vals,
-            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
-            vals,
-            threads
+            store.keys_sorted(),
+            Ok(vec!["a".into(), "b".into(), "n".into()])
         );
+        assert_eq!(store.to_disk().unwrap().sorted_by_key().data.len(), 4);
 
-        let (vals, threads) = (10000, 6);
-        let store = fill_multi_thread(vals, threads);
+        store.set_read_only(false);
+        assert!(!store.is_read_only());
+        assert_eq!(store.insert("new", "v"), Ok(()));
+        assert_eq!(store.delete("a").unwrap().value(), "1");
+    }
+
+    #[test]
+    fn read_only_rejects_waiting_writes() {
+        let store = &helpers::store_with(&[("a", "1")]);
+        let (reading_tx, reading_rx) = std::sync::mpsc::channel();
+        let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+        std::thread::scope(|scope| {
+            scope.spawn(move || {
+                store.for_each(|_| {
+                    reading_tx.send(()).unwrap();
+                    release_rx.recv().unwrap();
+                })
+            });
+            reading_rx.recv().unwrap();
+            let writer = scope.spawn(move || store.insert("b", "2"));
+            // Give the writer time to get past the early check and queue up
+            // behind the reader.
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            store.set_read_only(true);
+            release_tx.send(()).unwrap();
+            assert_eq!(writer.join().unwrap(), Err(crate::Error::ReadOnly));
+        });
+        assert_eq!(store.keys(), Ok(vec!["a".to_string()]));
+    }
+
+    #[test]
+    fn read_only_through_trait() {
+        use super::super::Store;
+
+        let store = helpers::store_with(&[("a", "1")]);
+        let dyn_store: &dyn Store = &store;
+        assert_eq!(dyn_store.set_read_only(true), Ok(()));
+        assert!(dyn_store.is_read_only());
+        assert!(store.is_read_only());
+        assert_eq!(dyn_store.insert("b", "2"), Err(crate::Error::ReadOnly));
+        assert_eq!(dyn_store.delete("a"), Err(crate::Error::ReadOnly));
+        assert_eq!(dyn_store.get_clone("a").unwrap().value(), "1");
+        assert_eq!(dyn_store.set_read_only(false), Ok(()));
+        assert_eq!(dyn_store.insert("b", "2"), Ok(()));
+    }
+
+    #[test]
+    fn capacity_limit() {
+        assert_eq!(KeyValueStore::empty().remaining_capacity(), Ok(None));
+        let store = KeyValueStore::with_capacity_limit(3);
+        assert_eq!(store.remaining_capacity(), Ok(Some(3)));
+        assert_eq!(store.insert_many(&[("a", "1"), ("b", "2")]), Ok(2));
+        assert_eq!(store.insert_row(&Row::create("c", "3")), Ok(()));
+        assert_eq!(store.remaining_capacity(), Ok(Some(0)));
+
+        let full = Err(crate::Error::capacity_exceeded(3, 4));
+        assert_eq!(store.insert("d", "4"), full);
+        assert_eq!(store.set_or_insert("d", "4").map(|_| ()), full);
+        assert_eq!(store.increment("d", 1).map(|_| ()), full);
         assert_eq!(
-            store
-                .len()
-                .expect("check_fill_multi_thread - unable to get length"),
-            vals,
-            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
-            vals,
-            threads
+            helpers::set_outcome(&store, "a", "updated"),
+            Ok(SetOutcome::Updated)
         );
+        assert_eq!(store.len(), Ok(3));
 
-        let (vals, threads) = (10000, 7);
-        let store = fill_multi_thread(vals, threads);
+        assert!(store.delete("c").is_ok());
+        assert_eq!(store.remaining_capacity(), Ok(Some(1)));
         assert_eq!(
-            store
-                .len()
-                .expect("check_fill_multi_thread - unable to get length"),
-            vals,
-            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
-            vals,
-            threads
+            store.insert_many(&[("d", "4"), ("e", "5")]),
+            Err(crate::Error::capacity_exceeded(3, 4))
         );
+        assert_eq!(store.get_clone("d"), Err(crate::Error::key_not_found("d")));
+        assert_eq!(store.len(), Ok(2));
+        assert_eq!(store.insert_many(&[("d", "4")]), Ok(1));
+        assert_eq!(store.remaining_capacity(), Ok(Some(0)));
 
-        let (vals, threads) = (10000, 8);
-        let store = fill_multi_thread(vals, threads);
+        let op_insert = |key: &str| Op::Insert {
+            key: key.to_string(),
+            value: "value".to_string(),
+        };
+        let op_delete = |key: &str| Op::Delete {
+            key: key.to_string(),
+        };
         assert_eq!(
-            store
-                .len()
-                .expect("check_fill_multi_thread - unable to get length"),
-            vals,
-            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
-            vals,
-            threads
+            store.apply_transaction(&[op_insert("e")]),
+            Err(crate::Error::capacity_exceeded(3, 4))
         );
-
-        let (vals, threads) = (10000, 9);
-        let store = fill_multi_thread(vals, threads);
+        let a = store.get_clone("a").unwrap();
         assert_eq!(
-            store
-                .len()
-                .expect("check_fill_multi_thread - unable to get length"),
-            vals,
-            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
-            vals,
-            threads
+            store.apply_transaction(&[op_delete("a"), op_insert("e")]),
+            Ok(vec![OpResult::Deleted(a), OpResult::Inserted])
         );
-
-        let (vals, threads) = (10000, 10);
-        let store = fill_multi_thread(vals, threads);
         assert_eq!(
-            store
-                .len()
-                .expect("check_fill_multi_thread - unable to get length"),
-            vals,
-            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
-            vals,
-            threads
+            store.keys().map(|mut keys| {
+                keys.sort();
+                keys
+            }),
+            Ok(vec!["b".to_string(), "d".to_string(), "e".to_string()])
         );
+    }
 
-        let (vals, threads) = (50, 12);
-        let store = fill_multi_thread(vals, threads);
+    #[test]
+    fn clone_and_content_eq() {
+        let original: KeyValueStore = helpers::store_with(&[("a", "1"), ("b", "2")]);
+        let clone = original.clone();
+        assert_eq!(original.content_eq(&clone, false), Ok(true));
+
+        assert!(clone.set_or_insert("a", "changed").is_ok());
+        assert!(clone.insert("c", "3").is_ok());
+        assert_eq!(original.get_clone("a").unwrap().value(), "1");
+        assert_eq!(original.len(), Ok(2));
+        assert_eq!(original.content_eq(&clone, true), Ok(false));
+
+        let rebuilt = KeyValueStore::empty();
+        for (key, value) in [("a", "1"), ("b", "2")] {
+            let row = original.get_clone(key).unwrap();
+            assert!(rebuilt
+                .insert_row(&Row::new(
+                    key,
+                    value,
+                    row.created() - 10,
+                    row.updated() - 10
+                ))
+                .is_ok());
+        }
+        assert_eq!(original.content_eq(&rebuilt, true), Ok(true));
+        assert_eq!(original.content_eq(&rebuilt, false), Ok(false));
+        assert_eq!(rebuilt.content_eq(&rebuilt, false), Ok(true));
+    }
+
+    #[test]
+    fn equal_across_backends() {
+        use super::super::{test_helpers::stores_equal, DashStore};
+
+        let store: KeyValueStore = helpers::store_with(&[("a", "1"), ("b", "2")]);
+        let dash = DashStore::empty();
+        for row in store.rows().unwrap() {
+            assert!(dash.insert_row(&row).is_ok());
+        }
+        assert!(stores_equal(&store, &dash));
+        assert!(dash.set_or_insert("b", "changed").is_ok());
+        assert!(!stores_equal(&store, &dash));
+        assert!(dash.delete("b").is_ok());
+        assert!(!stores_equal(&dash, &store));
+    }
+
+    #[test]
+    fn sample() {
+        let pairs = (0..20)
+            .map(|i| (format!("key{}", i), format!("value{}", i)))
+            .collect::<Vec<_>>();
+        let pairs = pairs
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect::<Vec<_>>();
+        let store = helpers::store_with(&pairs);
+        assert!(store
+            .insert_row(&Row::create_with_ttl("expired", "x", -1))
+            .is_ok());
+        assert!(store.insert("deleted", "x").is_ok());
+        assert!(store.delete("deleted").is_ok());
+
+        let all = store.sample(100).unwrap();
+        assert_eq!(all.len(), 20);
+        let mut keys = all
+            .iter()
+            .map(|row| row.key().to_string())
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys, store.keys_sorted().unwrap());
+
+        for _ in 0..100 {
+            let picked = store.sample(5).unwrap();
+            assert_eq!(picked.len(), 5);
+            assert!(picked.iter().all(|row| row.key().starts_with("key")));
+            let key = store.random_key().unwrap().unwrap();
+            assert!(key.starts_with("key"));
+        }
+        assert_eq!(store.sample(0), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn take_if() {
+        let store = helpers::store_with(&[("job", "pending"), ("other", "x")]);
+
+        let kept = store.take_if("job", |row| row.value() == "done");
+        assert_eq!(kept, Ok(None));
+        assert_eq!(store.get_clone("job").unwrap().value(), "pending");
+
+        let taken = store
+            .take_if("job", |row| row.value() == "pending")
+            .unwrap();
+        assert_eq!(taken.map(|row| row.value().clone()), Some("pending".into()));
+        assert!(store.get_clone("job").is_err());
         assert_eq!(
-            store
-                .len()
-                .expect("check_fill_multi_thread - unable to get length"),
-            vals,
-            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
-            vals,
-            threads
+            store.take_if("job", |_| true),
+            Err(crate::Error::key_not_found("job"))
         );
 
-        let (vals, threads) = (20, 20);
-        let store = fill_multi_thread(vals, threads);
+        assert_eq!(store.take("other").unwrap().value(), "x");
         assert_eq!(
-            store
-                .len()
-                .expect("check_fill_multi_thread - unable to get length"),
-            vals,
-            "check_fill_multi_thread (v = {} t = {}) - did not add the expected number of values",
-            vals,
-            threads
+            store.take("other"),
+            Err(crate::Error::key_not_found("other"))
         );
+        assert_eq!(store.is_empty(), Ok(true));
+        let stats = store.stats();
+        assert_eq!((stats.deletes, stats.delete_misses), (2, 2));
     }
 
     #[test]
-    fn check_fill_multi_rng() {
-        use helpers::fill_multi_thread;
-        use std::iter::repeat_with;
-        const ITERS: usize = 25;
+    fn take_if_across_threads() {
+        use std::sync::Arc;
 
-        let mut i: usize = 0;
-        let mut next = move || {
-            i += 1;
-            i
-        };
+        for _ in 0..20 {
+            let store = Arc::new(helpers::store_with(&[("job", "pending")]));
+            let handles = (0..8)
+                .map(|_| {
+                    let store = Arc::clone(&store);
+                    std::thread::spawn(move || {
+                        match store.take_if("job", |row| row.value() == "pending") {
+                            Ok(taken) => taken.is_some(),
+                            Err(err) => {
+                                assert_eq!(err, crate::Error::key_not_found("job"));
+                                false
+                            }
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            let winners = handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .filter(|&won| won)
+                .count();
+            assert_eq!(winners, 1);
+            assert!(store.get_clone("job").is_err());
+        }
+    }
 
-        let rand_v = || fastrand::usize(100..40000);
-        let rand_t = || fastrand::usize(1..10);
+    #[test]
+    fn take_if_reentrant() {
+        let store = helpers::store_with(&[("key", "value")]);
+        let taken = store.take_if("key", |_| store.get_clone("key").is_ok());
+        assert_eq!(taken, Ok(None));
+        assert_eq!(store.get_clone("key").unwrap().value(), "value");
+    }
 
-        repeat_with(|| (next(), rand_v(), rand_t()))
-            .take(ITERS)
-            .for_each(|(n, vals, threads)| {
-                println!("Iteration #{} with {} values and {} threads", n, vals, threads);
-                let store = fill_multi_thread(vals, threads);
-                assert_eq!(
-                    store
-                        .len()
-                        .expect("check_fill_multi_random - unable to get length"),
-                    vals,
-                    "check_fill_multi_random (v = {} t = {}) - did not add the expected number of values",
-                    vals,
-                    threads
-                );
-                let r = fastrand::usize(0..vals);
-                let key = format!("key{}", r);
-                let value = format!("value{}", r);
-                let row = store.get_clone(&key);
-                assert!(row.is_ok(), "check_fill_multi_random ({},{},{}) - unable to get value", n, vals, threads);
-                let row = row.unwrap();
-                assert_eq!(
-                    row.key(),
-                    key,
-                    "check_fill_multi_random #{} - row {} does not have the expected key",
-                    n,
-                    r,
-                );
-                assert_eq!(
-                    row.value(),
-                    value,
-                    "check_fill_multi_random #{} - row {} does not have the expected value",
-                    n,
-                    r,
-                );
-            });
+    #[test]
+    fn find() {
+        use std::cell::Cell;
+
+        let store = helpers::store_with(&[("a", "same"), ("b", "other"), ("c", "same")]);
+        assert!(store
+            .insert_row(&Row::create_with_ttl("expired", "same", -1))
+            .is_ok());
+
+        assert_eq!(store.contains_value("same"), Ok(true));
+        assert_eq!(store.contains_value("missing"), Ok(false));
+        assert_eq!(
+            store.keys_with_value("same"),
+            Ok(vec!["a".to_string(), "c".to_string()])
+        );
+        assert_eq!(store.keys_with_value("missing"), Ok(vec![]));
+
+        let found = store.find(|row| row.value() == "same").unwrap();
+        assert_eq!(
+            found.iter().map(Row::key).collect::<Vec<_>>(),
+            vec!["a", "c"]
+        );
+        assert_eq!(store.find(|_| false), Ok(vec![]));
+        assert_eq!(store.find_first(|_| false), Ok(None));
+
+        let calls = Cell::new(0);
+        let first = store
+            .find_first(|_| {
+                calls.set(calls.get() + 1);
+                true
+            })
+            .unwrap();
+        assert!(first.is_some());
+        assert_eq!(calls.get(), 1);
     }
 }
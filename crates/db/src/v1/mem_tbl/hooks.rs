@@ -0,0 +1,82 @@
+// Copyright (c) 2022 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{fmt, sync::Arc};
+
+use crate::{Row, RowEvent};
+
+type PreWriteFn = dyn Fn(&str, &str) -> crate::Result<()> + Send + Sync;
+type PostWriteFn = dyn Fn(&RowEvent) + Send + Sync;
+
+/// A check run on the key and value of every write before it's made, see
+/// `KeyValueStore::set_pre_write_hook`.
+#[derive(Clone, Default)]
+pub(crate) struct PreWriteHook(Option<Arc<PreWriteFn>>);
+
+impl PreWriteHook {
+    pub(crate) fn new<F>(f: F) -> Self
+    where
+        F: Fn(&str, &str) -> crate::Result<()> + Send + Sync + 'static,
+    {
+        Self(Some(Arc::new(f)))
+    }
+
+    pub(crate) fn check(&self, key: &str, value: &str) -> crate::Result<()> {
+        match &self.0 {
+            Some(hook) => hook(key, value),
+            None => Ok(()),
+        }
+    }
+
+    /// Same as `check`, for a whole row. The value is only turned into a
+    /// string if there's a hook to pass it to.
+    pub(crate) fn check_row(&self, row: &Row) -> crate::Result<()> {
+        match &self.0 {
+            Some(hook) => hook(row.key(), &row.value().to_display_string()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for PreWriteHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PreWriteHook")
+            .field(&self.0.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+/// A callback run on the [`RowEvent`] of every successful write, see
+/// `KeyValueStore::set_post_write_hook`.
+#[derive(Clone, Default)]
+pub(crate) struct PostWriteHook(Option<Arc<PostWriteFn>>);
+
+impl PostWriteHook {
+    pub(crate) fn new<F>(f: F) -> Self
+    where
+        F: Fn(&RowEvent) + Send + Sync + 'static,
+    {
+        Self(Some(Arc::new(f)))
+    }
+
+    pub(crate) fn is_set(&self) -> bool {
+        self.0.is_some()
+    }
+
+    pub(crate) fn call(&self, event: &RowEvent) {
+        if let Some(hook) = &self.0 {
+            hook(event);
+        }
+    }
+}
+
+impl fmt::Debug for PostWriteHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PostWriteHook")
+            .field(&self.0.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
@@ -0,0 +1,788 @@
+// Copyright (c) 2022 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Mutex, MutexGuard},
+};
+
+use super::{run_unpoisoned, upsert_base, CasOutcome, DeleteManyReport, StoreLimits};
+use crate::{Row, RowDiskRepr, SetOutcome, StoreDiskRepr};
+
+type EvictionCallback = Box<dyn Fn(&Row) + Send + Sync>;
+
+#[derive(Debug)]
+struct Entry {
+    row: Row,
+    /// When this row was last accessed, see `Inner::tick`.
+    tick: u64,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    rows: HashMap<String, Entry>,
+    /// The key of every row, ordered from least to most recently accessed.
+    order: BTreeMap<u64, String>,
+    /// Counts accesses, so every access gets a bigger tick than the last.
+    tick: u64,
+    /// Rows that were evicted while there was no callback to hand them to.
+    evicted: Vec<Row>,
+}
+
+impl Inner {
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    /// Marks the row for `key` as the most recently accessed.
+    fn touch(&mut self, key: &str) {
+        let tick = self.next_tick();
+        if let Some(entry) = self.rows.get_mut(key) {
+            let key = self
+                .order
+                .remove(&entry.tick)
+                .expect("LruStore - every row has an entry in the order");
+            entry.tick = tick;
+            self.order.insert(tick, key);
+        }
+    }
+
+    /// Inserts `row` as the most recently accessed row, replacing any row with
+    /// the same key.
+    fn put(&mut self, row: Row) {
+        let key = row.key().to_string();
+        self.remove(&key);
+        let tick = self.next_tick();
+        self.order.insert(tick, key.clone());
+        self.rows.insert(key, Entry { row, tick });
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Row> {
+        let entry = self.rows.remove(key)?;
+        self.order.remove(&entry.tick);
+        Some(entry.row)
+    }
+
+    fn get_live(&self, key: &str) -> Option<&Row> {
+        self.rows
+            .get(key)
+            .map(|entry| &entry.row)
+            .filter(|row| row.is_live())
+    }
+
+    /// Evicts the least recently accessed rows until a row for `key` fits in
+    /// `capacity`, returning the evicted rows.
+    fn make_room(&mut self, key: &str, capacity: usize) -> Vec<Row> {
+        let mut evicted = Vec::new();
+        if self.rows.contains_key(key) {
+            return evicted;
+        }
+        while self.rows.len() >= capacity {
+            let oldest = match self.order.keys().next() {
+                Some(&tick) => self.order.remove(&tick),
+                None => break,
+            };
+            if let Some(entry) = oldest.and_then(|key| self.rows.remove(&key)) {
+                evicted.push(entry.row);
+            }
+        }
+        evicted
+    }
+}
+
+/// A store holding at most `capacity` rows, for use as a cache. Once it is
+/// full, inserting a new key evicts the least recently accessed row.
+///
+/// Inserting, setting and reading a row with `get_clone` all count as
+/// accessing it; `contains`, `keys` and `rows` do not. Evicted rows are passed
+/// to the callback given to `with_on_evict`, or queued for `take_evicted` if
+/// there isn't one.
+pub struct LruStore {
+    inner: Mutex<Inner>,
+    capacity: usize,
+    on_evict: Option<EvictionCallback>,
+}
+
+impl std::fmt::Debug for LruStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LruStore")
+            .field("inner", &self.inner)
+            .field("capacity", &self.capacity)
+            .field("on_evict", &self.on_evict.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+impl LruStore {
+    /// Creates an empty store holding at most `capacity` rows. A capacity of
+    /// zero rejects every insert with [`crate::Error::CapacityExceeded`].
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::default(),
+            capacity,
+            on_evict: None,
+        }
+    }
+
+    /// Calls `callback` with every row evicted from this store, after the
+    /// store has been unlocked, so `callback` is free to use the store.
+    pub fn with_on_evict<F: Fn(&Row) + Send + Sync + 'static>(mut self, callback: F) -> Self {
+        self.on_evict = Some(Box::new(callback));
+        self
+    }
+
+    /// Gets the maximum number of rows this store holds.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn lock(&self) -> crate::Result<MutexGuard<'_, Inner>> {
+        self.inner
+            .lock()
+            .map_err(|err| crate::Error::mutex_poisoned(&err))
+    }
+
+    fn check_capacity(&self) -> crate::Result<()> {
+        if self.capacity == 0 {
+            Err(crate::Error::capacity_exceeded(self.capacity, 1))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Hands `evicted` to the eviction callback, or queues them for
+    /// `take_evicted`. Must be called without the store locked.
+    fn evicted(&self, evicted: Vec<Row>) -> crate::Result<()> {
+        if evicted.is_empty() {
+            return Ok(());
+        }
+        match &self.on_evict {
+            Some(callback) => evicted.iter().for_each(callback),
+            None => self.lock()?.evicted.extend(evicted),
+        }
+        Ok(())
+    }
+
+    /// Takes every row evicted since the last call, oldest eviction first.
+    /// Always empty when an eviction callback is set.
+    pub fn take_evicted(&self) -> crate::Result<Vec<Row>> {
+        self.lock()
+            .map(|mut inner| std::mem::take(&mut inner.evicted))
+    }
+
+    /// Gets a copy of the row for `key`, marking it as the most recently
+    /// accessed. Expired rows are removed and reported as not found, as are
+    /// rows that have been deleted.
+    pub fn get_clone(&self, key: &str) -> crate::Result<Row> {
+        self.lock()
+            .and_then(|mut inner| Self::get_locked(&mut inner, key))
+    }
+
+    /// Gets a copy of the row for each of `keys` under a single lock, marking
+    /// each row found as accessed in turn.
+    pub fn get_many(&self, keys: &[&str]) -> crate::Result<Vec<(String, crate::Result<Row>)>> {
+        self.lock().map(|mut inner| {
+            keys.iter()
+                .map(|&key| (key.to_string(), Self::get_locked(&mut inner, key)))
+                .collect()
+        })
+    }
+
+    fn get_locked(inner: &mut Inner, key: &str) -> crate::Result<Row> {
+        if inner
+            .rows
+            .get(key)
+            .map_or(false, |entry| entry.row.is_expired())
+        {
+            inner.remove(key);
+        }
+        let row = inner
+            .rows
+            .get(key)
+            .map(|entry| &entry.row)
+            .filter(|row| !row.is_deleted())
+            .cloned()
+            .ok_or(crate::Error::key_not_found(key))?;
+        inner.touch(key);
+        Ok(row)
+    }
+
+    pub fn insert(&self, key: &str, value: &str) -> crate::Result<()> {
+        self.insert_row(&Row::create(key, value))
+    }
+
+    pub fn insert_row(&self, row: &Row) -> crate::Result<()> {
+        self.check_capacity()?;
+        let evicted = self.lock().and_then(|mut inner| {
+            if inner.get_live(row.key()).is_some() {
+                return Err(crate::Error::duplicate_key(row.key()));
+            }
+            let evicted = inner.make_room(row.key(), self.capacity);
+            inner.put(row.clone());
+            Ok(evicted)
+        })?;
+        self.evicted(evicted)
+    }
+
+    /// Sets the value of `key`, inserting a new row if it doesn't exist (or has
+    /// expired or been deleted). Returns a copy of the row it replaced, or
+    /// `None` if it inserted a new one. Setting an existing key marks it as
+    /// accessed but never evicts anything.
+    pub fn set_or_insert(&self, key: &str, value: &str) -> crate::Result<Option<Row>> {
+        self.check_capacity()?;
+        let (previous, evicted) = self.lock().map(|mut inner| {
+            let previous = inner
+                .rows
+                .get_mut(key)
+                .map(|entry| &mut entry.row)
+                .filter(|row| row.is_live())
+                .map(|row| {
+                    let previous = row.clone();
+                    row.update(value);
+                    previous
+                });
+            match previous {
+                Some(previous) => {
+                    inner.touch(key);
+                    (Some(previous), Vec::new())
+                }
+                None => {
+                    let evicted = inner.make_room(key, self.capacity);
+                    inner.put(Row::create(key, value));
+                    (None, evicted)
+                }
+            }
+        })?;
+        self.evicted(evicted)?;
+        Ok(previous)
+    }
+
+    pub fn set_or_insert_row(&self, row: &Row) -> crate::Result<Option<Row>> {
+        self.check_capacity()?;
+        let (previous, evicted) = self.lock().map(|mut inner| {
+            if let Some(entry) = inner.rows.get_mut(row.key()) {
+                let previous = entry.row.is_live().then(|| entry.row.clone());
+                entry.row.overwrite_with(row);
+                inner.touch(row.key());
+                (previous, Vec::new())
+            } else {
+                let evicted = inner.make_room(row.key(), self.capacity);
+                inner.put(row.clone());
+                (None, evicted)
+            }
+        })?;
+        self.evicted(evicted)?;
+        Ok(previous)
+    }
+
+    /// Checks whether `key` has a live row, without marking it as accessed.
+    pub fn contains(&self, key: &str) -> crate::Result<bool> {
+        self.lock().map(|inner| inner.get_live(key).is_some())
+    }
+
+    /// Gets the number of rows in this store, not counting deleted rows.
+    pub fn len(&self) -> crate::Result<usize> {
+        self.lock().map(|inner| {
+            inner
+                .rows
+                .values()
+                .filter(|entry| !entry.row.is_deleted())
+                .count()
+        })
+    }
+
+    /// Removes every row from this store, returning how many there were (not
+    /// counting deleted rows, like `len`).
+    /// Cleared rows don't count as evicted.
+    pub fn clear(&self) -> crate::Result<usize> {
+        self.lock().map(|mut inner| {
+            inner.order.clear();
+            inner
+                .rows
+                .drain()
+                .filter(|(_, entry)| !entry.row.is_deleted())
+                .count()
+        })
+    }
+
+    /// Checks whether this store has no rows, not counting deleted rows.
+    pub fn is_empty(&self) -> crate::Result<bool> {
+        self.len().map(|len| len == 0)
+    }
+
+    /// Deletes the row for `key`, freeing its slot.
+    pub fn delete(&self, key: &str) -> crate::Result<Row> {
+        self.lock()
+            .and_then(|mut inner| Self::delete_locked(&mut inner, key))
+    }
+
+    fn delete_locked(inner: &mut Inner, key: &str) -> crate::Result<Row> {
+        if inner
+            .rows
+            .get(key)
+            .map_or(true, |entry| entry.row.is_deleted())
+        {
+            return Err(crate::Error::key_not_found(key));
+        }
+        inner.remove(key).ok_or(crate::Error::key_not_found(key))
+    }
+
+    /// Runs `f` on the live row for `key` and stores the result, returning a
+    /// copy of it, and marks the row as accessed. The store's mutex is held
+    /// while `f` runs, so `f` must not use the store: calling it from inside
+    /// `f` deadlocks. Fails with [`crate::Error::KeyNotFound`] if there is no
+    /// live row, or if `f` changes the key or breaks this store's limits, in
+    /// which case nothing is stored.
+    pub fn update_with<F: FnOnce(&mut Row)>(&self, key: &str, f: F) -> crate::Result<Row> {
+        self.lock().and_then(|mut inner| {
+            let row = StoreLimits::default().modify(Self::get_locked(&mut inner, key)?, f)?;
+            inner
+                .rows
+                .get_mut(key)
+                .expect("LruStore - row was just read")
+                .row = row.clone();
+            Ok(row)
+        })
+    }
+
+    /// Same as `update_with`, but when `key` has no live row `f` is run on the
+    /// row made by `default` (which must be for `key`) instead.
+    pub fn upsert_with<D, F>(&self, key: &str, default: D, f: F) -> crate::Result<Row>
+    where
+        D: FnOnce() -> Row,
+        F: FnOnce(&mut Row),
+    {
+        self.check_capacity()?;
+        let (row, evicted) = self.lock().and_then(|mut inner| {
+            let current = Self::get_locked(&mut inner, key).ok();
+            let row = upsert_base(current, key, default)?;
+            let row = StoreLimits::default().modify(row, f)?;
+            let evicted = inner.make_room(key, self.capacity);
+            match inner.rows.get_mut(key) {
+                Some(entry) => entry.row = row.clone(),
+                None => inner.put(row.clone()),
+            }
+            Ok((row, evicted))
+        })?;
+        self.evicted(evicted)?;
+        Ok(row)
+    }
+
+    /// Sets the value of `key` to `new` if it currently holds `expected`, all
+    /// under a single lock. A successful swap marks the row as accessed.
+    pub fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: &str,
+        new: &str,
+    ) -> crate::Result<CasOutcome> {
+        self.lock().map(|mut inner| {
+            let row = inner.rows.get_mut(key).map(|entry| &mut entry.row);
+            let outcome = CasOutcome::swap(row, expected, new);
+            if outcome.is_swapped() {
+                inner.touch(key);
+            }
+            outcome
+        })
+    }
+
+    /// Deletes the row for `key` if it currently holds `expected`, all under a
+    /// single lock.
+    pub fn compare_and_delete(&self, key: &str, expected: &str) -> crate::Result<CasOutcome> {
+        self.lock().map(|mut inner| {
+            let outcome = CasOutcome::check(inner.rows.get(key).map(|entry| &entry.row), expected);
+            if outcome.is_swapped() {
+                inner.remove(key);
+            }
+            outcome
+        })
+    }
+
+    /// Moves the live row for `old_key` to `new_key`, keeping its `created`
+    /// timestamp but bumping `updated`, all under a single lock. The row counts
+    /// as accessed. Fails with [`crate::Error::KeyNotFound`] if there is no
+    /// live row for `old_key`, and with [`crate::Error::DuplicateKey`] if
+    /// `new_key` already has one.
+    pub fn rename(&self, old_key: &str, new_key: &str) -> crate::Result<()> {
+        self.rename_inner(old_key, new_key, false).map(|_| ())
+    }
+
+    /// Same as `rename`, but any live row for `new_key` is replaced and
+    /// returned instead.
+    pub fn rename_overwrite(&self, old_key: &str, new_key: &str) -> crate::Result<Option<Row>> {
+        self.rename_inner(old_key, new_key, true)
+    }
+
+    fn rename_inner(
+        &self,
+        old_key: &str,
+        new_key: &str,
+        overwrite: bool,
+    ) -> crate::Result<Option<Row>> {
+        StoreLimits::default().check(new_key, 0)?;
+        self.lock().and_then(|mut inner| {
+            let mut row = Self::get_locked(&mut inner, old_key)?;
+            let replaced = inner.get_live(new_key).cloned();
+            if replaced.is_some() && !overwrite {
+                return Err(crate::Error::duplicate_key(new_key));
+            }
+            inner.remove(old_key);
+            row.rename(new_key);
+            inner.put(row);
+            Ok(replaced.filter(|_| old_key != new_key))
+        })
+    }
+
+    /// Deletes the rows for all of `keys` under a single lock, or none of them
+    /// if any key has no row, see `KeyValueStore::delete_many`.
+    pub fn delete_many(&self, keys: &[&str]) -> crate::Result<Vec<Row>> {
+        self.lock().and_then(|mut inner| {
+            let mut seen = HashSet::with_capacity(keys.len());
+            for &key in keys {
+                let exists = inner
+                    .rows
+                    .get(key)
+                    .map_or(false, |entry| !entry.row.is_deleted());
+                if !seen.insert(key) || !exists {
+                    return Err(crate::Error::key_not_found(key));
+                }
+            }
+            keys.iter()
+                .map(|&key| Self::delete_locked(&mut inner, key))
+                .collect()
+        })
+    }
+
+    /// Same as `delete_many`, but keys without a row are skipped and listed in
+    /// the returned report instead of aborting the batch.
+    pub fn try_delete_many(&self, keys: &[&str]) -> crate::Result<DeleteManyReport> {
+        self.lock().map(|mut inner| {
+            let mut report = DeleteManyReport::default();
+            for &key in keys {
+                match Self::delete_locked(&mut inner, key) {
+                    Ok(row) => report.deleted.push(row),
+                    Err(_) => report.missing.push(key.to_string()),
+                }
+            }
+            report
+        })
+    }
+
+    /// Deletes every live row matching `predicate`, returning the deleted rows
+    /// from least to most recently accessed. The store stays locked while
+    /// `predicate` runs, so it must not use the store itself.
+    pub fn delete_matching<F: Fn(&Row) -> bool>(&self, predicate: F) -> crate::Result<Vec<Row>> {
+        self.lock().map(|mut inner| {
+            let keys = inner
+                .order
+                .values()
+                .filter(|key| {
+                    let row = &inner.rows[key.as_str()].row;
+                    row.is_live() && predicate(row)
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            keys.iter().filter_map(|key| inner.remove(key)).collect()
+        })
+    }
+
+    /// Removes every row for which `predicate` returns false, returning how
+    /// many were removed. Deleted rows are left alone and aren't passed to
+    /// `predicate`.
+    ///
+    /// The store stays locked while `predicate` runs, so it must not use the
+    /// store itself. If `predicate` panics the panic is passed on to the
+    /// caller, nothing is removed; the lock is released without being poisoned, so the store can
+    /// still be used.
+    pub fn retain<F: Fn(&str, &Row) -> bool>(&self, predicate: F) -> crate::Result<usize> {
+        self.lock().map(|inner| {
+            run_unpoisoned(inner, |inner| {
+                let dropped = inner
+                    .rows
+                    .iter()
+                    .filter(|(key, entry)| !entry.row.is_deleted() && !predicate(key, &entry.row))
+                    .map(|(key, _)| key.clone())
+                    .collect::<Vec<_>>();
+                for key in &dropped {
+                    inner.remove(key);
+                }
+                dropped.len()
+            })
+        })
+    }
+
+    /// Gets the keys of every live (unexpired and not deleted) row, from least
+    /// to most recently accessed, which is the order they would be evicted in.
+    pub fn keys(&self) -> crate::Result<Vec<String>> {
+        self.lock().map(|inner| {
+            inner
+                .order
+                .values()
+                .filter(|key| inner.get_live(key).is_some())
+                .cloned()
+                .collect()
+        })
+    }
+
+    /// Gets every live (unexpired and not deleted) row, from least to most
+    /// recently accessed.
+    pub fn rows(&self) -> crate::Result<Vec<Row>> {
+        self.lock().map(|inner| {
+            inner
+                .order
+                .values()
+                .filter_map(|key| inner.get_live(key))
+                .cloned()
+                .collect()
+        })
+    }
+
+    pub fn to_disk(&self) -> crate::Result<StoreDiskRepr> {
+        self.lock().map(|inner| {
+            StoreDiskRepr::from_iter(
+                inner
+                    .rows
+                    .values()
+                    .map(|entry| RowDiskRepr::from(&entry.row)),
+            )
+        })
+    }
+
+    /// Same as `to_disk`.
+    pub fn to_disk_repr(&self) -> crate::Result<StoreDiskRepr> {
+        self.to_disk()
+    }
+}
+
+impl super::Store for LruStore {
+    fn get_clone(&self, key: &str) -> crate::Result<Row> {
+        LruStore::get_clone(self, key)
+    }
+
+    fn get_many(&self, keys: &[&str]) -> crate::Result<Vec<(String, crate::Result<Row>)>> {
+        LruStore::get_many(self, keys)
+    }
+
+    fn insert(&self, key: &str, value: &str) -> crate::Result<()> {
+        LruStore::insert(self, key, value)
+    }
+
+    fn insert_row(&self, row: &Row) -> crate::Result<()> {
+        LruStore::insert_row(self, row)
+    }
+
+    fn set_or_insert(&self, key: &str, value: &str) -> crate::Result<Option<Row>> {
+        LruStore::set_or_insert(self, key, value)
+    }
+
+    fn set_or_insert_row(&self, row: &Row) -> crate::Result<Option<Row>> {
+        LruStore::set_or_insert_row(self, row)
+    }
+
+    fn update_with(&self, key: &str, f: &mut dyn FnMut(&mut Row)) -> crate::Result<Row> {
+        LruStore::update_with(self, key, f)
+    }
+
+    fn upsert_with(
+        &self,
+        key: &str,
+        default: &mut dyn FnMut() -> Row,
+        f: &mut dyn FnMut(&mut Row),
+    ) -> crate::Result<Row> {
+        LruStore::upsert_with(self, key, default, f)
+    }
+
+    fn contains(&self, key: &str) -> crate::Result<bool> {
+        LruStore::contains(self, key)
+    }
+
+    fn len(&self) -> crate::Result<usize> {
+        LruStore::len(self)
+    }
+
+    fn clear(&self) -> crate::Result<usize> {
+        LruStore::clear(self)
+    }
+
+    fn delete(&self, key: &str) -> crate::Result<Row> {
+        LruStore::delete(self, key)
+    }
+
+    fn compare_and_swap(&self, key: &str, expected: &str, new: &str) -> crate::Result<CasOutcome> {
+        LruStore::compare_and_swap(self, key, expected, new)
+    }
+
+    fn compare_and_delete(&self, key: &str, expected: &str) -> crate::Result<CasOutcome> {
+        LruStore::compare_and_delete(self, key, expected)
+    }
+
+    fn rename(&self, old_key: &str, new_key: &str) -> crate::Result<()> {
+        LruStore::rename(self, old_key, new_key)
+    }
+
+    fn rename_overwrite(&self, old_key: &str, new_key: &str) -> crate::Result<Option<Row>> {
+        LruStore::rename_overwrite(self, old_key, new_key)
+    }
+
+    fn retain(&self, predicate: &dyn Fn(&str, &Row) -> bool) -> crate::Result<usize> {
+        LruStore::retain(self, predicate)
+    }
+
+    fn delete_many(&self, keys: &[&str]) -> crate::Result<Vec<Row>> {
+        LruStore::delete_many(self, keys)
+    }
+
+    fn try_delete_many(&self, keys: &[&str]) -> crate::Result<DeleteManyReport> {
+        LruStore::try_delete_many(self, keys)
+    }
+
+    fn delete_matching(&self, predicate: &dyn Fn(&Row) -> bool) -> crate::Result<Vec<Row>> {
+        LruStore::delete_matching(self, predicate)
+    }
+
+    fn keys(&self) -> crate::Result<Vec<String>> {
+        LruStore::keys(self)
+    }
+
+    fn rows(&self) -> crate::Result<Vec<Row>> {
+        LruStore::rows(self)
+    }
+
+    fn to_disk_repr(&self) -> crate::Result<StoreDiskRepr> {
+        LruStore::to_disk_repr(self)
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "LruStore"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn store_with(capacity: usize, keys: &[&str]) -> LruStore {
+        let store = LruStore::new(capacity);
+        for key in keys {
+            assert!(store.insert(key, "value").is_ok());
+        }
+        store
+    }
+
+    fn evicted_keys(store: &LruStore) -> Vec<String> {
+        store
+            .take_evicted()
+            .unwrap()
+            .iter()
+            .map(|row| row.key().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn shared_suite() {
+        super::super::test_helpers::check_store_on(LruStore::new(5));
+    }
+
+    #[test]
+    fn evicts_least_recently_inserted() {
+        let store = store_with(3, &["a", "b", "c"]);
+        assert_eq!(evicted_keys(&store), Vec::<String>::new());
+
+        assert!(store.insert("d", "value").is_ok());
+        assert!(store.insert("e", "value").is_ok());
+        assert_eq!(evicted_keys(&store), vec!["a", "b"]);
+        assert_eq!(evicted_keys(&store), Vec::<String>::new());
+        assert_eq!(store.keys(), Ok(vec!["c".into(), "d".into(), "e".into()]));
+        assert_eq!(store.len(), Ok(3));
+    }
+
+    #[test]
+    fn reads_refresh_recency() {
+        let store = store_with(3, &["a", "b", "c"]);
+        assert!(store.get_clone("a").is_ok());
+        assert_eq!(store.contains("b"), Ok(true));
+        assert!(store.insert("d", "value").is_ok());
+        assert_eq!(evicted_keys(&store), vec!["b"]);
+
+        assert!(store.get_many(&["c", "missing"]).is_ok());
+        assert!(store.insert("e", "value").is_ok());
+        assert_eq!(evicted_keys(&store), vec!["a"]);
+    }
+
+    #[test]
+    fn set_refreshes_without_evicting() {
+        let store = store_with(2, &["a", "b"]);
+        assert_eq!(
+            super::super::test_helpers::set_outcome(&store, "a", "new"),
+            Ok(SetOutcome::Updated)
+        );
+        assert_eq!(
+            super::super::test_helpers::set_outcome(&store, "a", "new"),
+            Ok(SetOutcome::Unchanged)
+        );
+        assert_eq!(evicted_keys(&store), Vec::<String>::new());
+        assert_eq!(store.keys(), Ok(vec!["b".into(), "a".into()]));
+
+        assert_eq!(
+            super::super::test_helpers::set_outcome(&store, "c", "value"),
+            Ok(SetOutcome::Inserted)
+        );
+        assert_eq!(evicted_keys(&store), vec!["b"]);
+    }
+
+    #[test]
+    fn delete_frees_a_slot() {
+        let store = store_with(2, &["a", "b"]);
+        assert!(store.delete("a").is_ok());
+        assert!(store.insert("c", "value").is_ok());
+        assert_eq!(evicted_keys(&store), Vec::<String>::new());
+        assert_eq!(store.len(), Ok(2));
+    }
+
+    #[test]
+    fn zero_capacity() {
+        let store = LruStore::new(0);
+        assert_eq!(
+            store.insert("a", "b"),
+            Err(crate::Error::capacity_exceeded(0, 1))
+        );
+        assert_eq!(
+            store.set_or_insert("a", "b"),
+            Err(crate::Error::capacity_exceeded(0, 1))
+        );
+        assert_eq!(
+            store.set_or_insert_row(&Row::create("a", "b")),
+            Err(crate::Error::capacity_exceeded(0, 1))
+        );
+        assert_eq!(store.len(), Ok(0));
+        assert_eq!(
+            crate::Error::capacity_exceeded(0, 1).to_string(),
+            "store is full, it holds at most 0 rows but the write needs 1"
+        );
+    }
+
+    #[test]
+    fn eviction_callback() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let store = {
+            let seen = Arc::clone(&seen);
+            LruStore::new(1).with_on_evict(move |row| {
+                seen.lock().unwrap().push(row.key().to_string());
+            })
+        };
+
+        for key in ["a", "b", "c"] {
+            assert!(store.insert(key, "value").is_ok());
+        }
+        assert_eq!(*seen.lock().unwrap(), vec!["a", "b"]);
+        assert_eq!(store.take_evicted(), Ok(vec![]));
+        assert_eq!(store.keys(), Ok(vec!["c".to_string()]));
+    }
+}
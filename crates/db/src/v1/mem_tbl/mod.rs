@@ -4,47 +4,994 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use std::collections::HashMap;
+
 use time::OffsetDateTime;
 
+mod btree_store;
 mod dashmap_store;
 mod disk;
+mod entry;
+mod event;
 mod hashmap_store;
+mod hooks;
+mod lru_store;
 mod row;
+mod set_store;
+mod sharded_store;
+mod snapshot;
+mod stats;
+mod store_set;
+#[cfg(test)]
+mod test_helpers;
+mod transaction;
+mod value_repr;
 
+pub use btree_store::BTreeStore;
 pub use dashmap_store::DashStore;
-pub use disk::{RowDiskRepr, StoreByteRepr, StoreDiskRepr};
+pub use disk::{LoadReport, MultiStoreDiskRepr, RowDiskRepr, StoreByteRepr, StoreDiskRepr};
+pub use entry::EntryRef;
+pub(crate) use entry::{EntryChange, EntryState};
+pub use event::RowEvent;
+pub(crate) use event::Subscribers;
 pub use hashmap_store::KeyValueStore;
-pub use row::Row;
+pub(crate) use hooks::{PostWriteHook, PreWriteHook};
+pub use lru_store::LruStore;
+pub use row::{FieldDiff, MergeResult, MergeStrategy, Row, RowBuilder, Value};
+pub use set_store::SetStore;
+pub use sharded_store::ShardedStore;
+pub use snapshot::{StoreIntoIter, StoreSnapshot};
+pub(crate) use stats::StoreStats;
+pub use stats::StoreStatsSnapshot;
+pub use store_set::{SharedStore, StoreSet};
+pub use transaction::{Op, OpResult};
+pub use value_repr::ValueRepr;
 
+/// Gets the current time as a unix timestamp in milliseconds.
 pub fn create_now() -> i64 {
-    OffsetDateTime::now_utc().unix_timestamp()
+    (OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000) as i64
+}
+
+/// Converts a unix timestamp in milliseconds, like the ones returned by
+/// `create_now`, back into an `OffsetDateTime`. Fails with
+/// [`crate::Error::InvalidTimestamp`] if `input` is out of range.
+pub fn reverse_timestamp(input: i64) -> crate::Result<OffsetDateTime> {
+    OffsetDateTime::from_unix_timestamp_nanos(i128::from(input) * 1_000_000)
+        .map_err(|_| crate::Error::InvalidTimestamp(input))
+}
+
+/// The longest key (in bytes) accepted by `validate_key`.
+pub const MAX_KEY_LEN: usize = 1024;
+
+/// Checks that `key` is usable as a row key: it must not be empty, be at most
+/// [`MAX_KEY_LEN`] bytes long and contain no control characters.
+pub fn validate_key(key: &str) -> crate::Result<()> {
+    if key.is_empty() {
+        return Err(crate::Error::invalid_key(key, "key must not be empty"));
+    }
+    if key.len() > MAX_KEY_LEN {
+        return Err(crate::Error::invalid_key(
+            key,
+            &format!("key is {} bytes, the maximum is {}", key.len(), MAX_KEY_LEN),
+        ));
+    }
+    if let Some(c) = key.chars().find(|c| c.is_control()) {
+        return Err(crate::Error::invalid_key(
+            key,
+            &format!("key contains the control character {:?}", c),
+        ));
+    }
+    Ok(())
+}
+
+/// A glob-style pattern keys can be matched against, see
+/// `KeyValueStore::find_keys`. `*` matches any run of characters (including
+/// none) and `?` matches exactly one; every other character, `.` and `[`
+/// included, only matches itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct KeyPattern(Vec<char>);
+
+impl KeyPattern {
+    pub(crate) fn new(pattern: &str) -> Self {
+        Self(pattern.chars().collect())
+    }
+
+    pub(crate) fn matches(&self, key: &str) -> bool {
+        let pattern = &self.0;
+        // Where the last `*` was seen, and the byte in `key` it's currently
+        // assumed to stop matching at.
+        let mut star: Option<(usize, usize)> = None;
+        let (mut p, mut k) = (0, 0);
+        while let Some(c) = key[k..].chars().next() {
+            match pattern.get(p) {
+                Some('*') => {
+                    star = Some((p, k));
+                    p += 1;
+                }
+                Some(&expected) if expected == '?' || expected == c => {
+                    p += 1;
+                    k += c.len_utf8();
+                }
+                // Backtrack, letting the last `*` swallow one more character.
+                _ => match star {
+                    Some((star_p, star_k)) => {
+                        let skipped = key[star_k..].chars().next().map_or(0, char::len_utf8);
+                        star = Some((star_p, star_k + skipped));
+                        p = star_p + 1;
+                        k = star_k + skipped;
+                    }
+                    None => return false,
+                },
+            }
+        }
+        pattern[p..].iter().all(|&c| c == '*')
+    }
+}
+
+/// Optional limits on the size of the keys and values a store accepts, and on
+/// how many rows it holds.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct StoreLimits {
+    max_key_bytes: Option<usize>,
+    max_value_bytes: Option<usize>,
+    max_rows: Option<usize>,
+}
+
+impl StoreLimits {
+    pub(crate) fn new(max_key_bytes: usize, max_value_bytes: usize) -> Self {
+        Self {
+            max_key_bytes: Some(max_key_bytes),
+            max_value_bytes: Some(max_value_bytes),
+            max_rows: None,
+        }
+    }
+
+    pub(crate) fn with_max_rows(max_rows: usize) -> Self {
+        Self {
+            max_rows: Some(max_rows),
+            ..Self::default()
+        }
+    }
+
+    /// Checks that a store holding `len` rows has room for a row for each of
+    /// `keys` that `exists` doesn't find, counting repeated keys once. Nothing
+    /// is counted unless there is a row limit. Writes that add no rows always
+    /// pass, even if the store is already over the limit.
+    pub(crate) fn check_capacity<'k, K, E>(
+        &self,
+        len: impl FnOnce() -> usize,
+        keys: K,
+        exists: E,
+    ) -> crate::Result<()>
+    where
+        K: IntoIterator<Item = &'k str>,
+        E: Fn(&str) -> bool,
+    {
+        let limit = match self.max_rows {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        let new_rows = keys
+            .into_iter()
+            .filter(|key| !exists(key))
+            .collect::<std::collections::HashSet<_>>()
+            .len();
+        let attempted = len() + new_rows;
+        if new_rows > 0 && attempted > limit {
+            Err(crate::Error::capacity_exceeded(limit, attempted))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Gets how many more rows a store holding `len` rows has room for, or
+    /// `None` if there is no row limit.
+    pub(crate) fn remaining_capacity(&self, len: usize) -> Option<usize> {
+        self.max_rows.map(|limit| limit.saturating_sub(len))
+    }
+
+    /// Validates `key` with [`validate_key`] and then checks it and a value of
+    /// `value_size` bytes against these limits.
+    pub(crate) fn check(&self, key: &str, value_size: usize) -> crate::Result<()> {
+        validate_key(key)?;
+        if let Some(limit) = self.max_key_bytes.filter(|&limit| key.len() > limit) {
+            return Err(crate::Error::key_too_large(key, key.len(), limit));
+        }
+        if let Some(limit) = self.max_value_bytes.filter(|&limit| value_size > limit) {
+            return Err(crate::Error::value_too_large(key, value_size, limit));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_row(&self, row: &Row) -> crate::Result<()> {
+        self.check(row.key(), row.value().size_bytes())
+    }
+
+    /// Runs `f` on `row`, failing if it changed the key or left a row that no
+    /// longer fits these limits. Stores only write the result back once this
+    /// succeeds, so a rejected change leaves them untouched.
+    pub(crate) fn modify<F: FnOnce(&mut Row)>(&self, mut row: Row, f: F) -> crate::Result<Row> {
+        let key = row.key.clone();
+        f(&mut row);
+        if row.key != key {
+            return Err(crate::Error::KeyValueMismatch(key.to_string(), row));
+        }
+        self.check_row(&row)?;
+        Ok(row)
+    }
+}
+
+/// Runs `f` on a store's locked `guard`. If `f` panics the guard is dropped
+/// before the panic carries on, so the lock is released cleanly instead of
+/// being poisoned and the store stays usable.
+pub(crate) fn run_unpoisoned<G, R, F: FnOnce(&mut G) -> R>(mut guard: G, f: F) -> R {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| f(&mut guard))) {
+        Ok(result) => result,
+        Err(panic) => {
+            drop(guard);
+            std::panic::resume_unwind(panic)
+        }
+    }
+}
+
+/// Gets the row `upsert_with` starts from: `current` if there is one, or else
+/// the row made by `default`, which must be for `key`.
+pub(crate) fn upsert_base<D: FnOnce() -> Row>(
+    current: Option<Row>,
+    key: &str,
+    default: D,
+) -> crate::Result<Row> {
+    match current {
+        Some(row) => Ok(row),
+        None => {
+            let row = default();
+            if row.key() == key {
+                Ok(row)
+            } else {
+                Err(crate::Error::KeyValueMismatch(key.to_string(), row))
+            }
+        }
+    }
+}
+
+/// Adds (`op` is `'+'`) or subtracts (`'-'`) `delta` from `value`, returning
+/// `None` if the result doesn't fit in an `i64`.
+pub(crate) fn checked_apply(value: i64, op: char, delta: i64) -> Option<i64> {
+    match op {
+        '-' => value.checked_sub(delta),
+        _ => value.checked_add(delta),
+    }
+}
+
+/// What setting a key (with `set_many`, or an [`crate::Op::Set`]) did with the
+/// value it was given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOutcome {
+    /// The key did not exist (or had expired) so a new row was created.
+    Inserted,
+    /// The key existed and its value was changed.
+    Updated,
+    /// The key existed and already held the given value.
+    Unchanged,
+}
+
+impl SetOutcome {
+    /// Works out what setting `value` did from the row `set_or_insert`
+    /// returned, the one it replaced.
+    pub fn from_previous(previous: Option<&Row>, value: &str) -> Self {
+        match previous {
+            None => SetOutcome::Inserted,
+            Some(row) if *row.value() == *value => SetOutcome::Unchanged,
+            Some(_) => SetOutcome::Updated,
+        }
+    }
+}
+
+/// How many of the pairs given to `set_many` ended up with each [`SetOutcome`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SetManyOutcome {
+    pub inserted: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+}
+
+impl SetManyOutcome {
+    fn record(&mut self, outcome: SetOutcome) {
+        match outcome {
+            SetOutcome::Inserted => self.inserted += 1,
+            SetOutcome::Updated => self.updated += 1,
+            SetOutcome::Unchanged => self.unchanged += 1,
+        }
+    }
+
+    /// The total number of pairs that were set.
+    pub fn total(&self) -> usize {
+        self.inserted + self.updated + self.unchanged
+    }
+}
+
+/// What `merge_from` did with the rows it was given.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MergeReport {
+    /// Rows whose keys didn't exist in the store yet.
+    pub inserted: usize,
+    /// Rows merged into an existing row that changed it.
+    pub merged: usize,
+    /// Rows merged into an existing row that left it as it was.
+    pub unchanged: usize,
+}
+
+impl MergeReport {
+    pub(crate) fn record(&mut self, result: MergeResult) {
+        match result {
+            MergeResult::Modified => self.merged += 1,
+            MergeResult::Unchanged => self.unchanged += 1,
+        }
+    }
+
+    /// The number of rows that were inserted or changed.
+    pub fn changed(&self) -> usize {
+        self.inserted + self.merged
+    }
+}
+
+/// What `compare_and_swap` (or `compare_and_delete`) did with a row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CasOutcome {
+    /// The row held the expected value and was changed.
+    Swapped,
+    /// The row held a different value and was left untouched.
+    ValueMismatch { actual: String },
+    /// There is no live row with the key.
+    KeyMissing,
+}
+
+impl CasOutcome {
+    /// Checks `row` against `expected` without changing it.
+    pub(crate) fn check(row: Option<&Row>, expected: &str) -> Self {
+        match row.filter(|row| row.is_live()) {
+            None => CasOutcome::KeyMissing,
+            Some(row) if *row.value() == *expected => CasOutcome::Swapped,
+            Some(row) => CasOutcome::ValueMismatch {
+                actual: row.value().to_display_string(),
+            },
+        }
+    }
+
+    /// Sets the value of `row` to `new` if it currently holds `expected`. The
+    /// caller must hold whatever lock guards `row` for this to be atomic.
+    pub(crate) fn swap(row: Option<&mut Row>, expected: &str, new: &str) -> Self {
+        match row {
+            Some(row) => {
+                let outcome = Self::check(Some(row), expected);
+                if outcome.is_swapped() {
+                    row.update(new);
+                }
+                outcome
+            }
+            None => CasOutcome::KeyMissing,
+        }
+    }
+
+    pub fn is_swapped(&self) -> bool {
+        *self == CasOutcome::Swapped
+    }
+}
+
+/// What a store does with a row when its key is deleted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeletePolicy {
+    /// The row is removed from the store.
+    Remove,
+    /// The row is kept but marked deleted with `Row::tombstone`, so the delete
+    /// itself can be persisted and merged. Tombstones are hidden from readers
+    /// until they are removed with `purge_tombstones`.
+    Tombstone,
+}
+
+impl Default for DeletePolicy {
+    fn default() -> Self {
+        DeletePolicy::Remove
+    }
+}
+
+/// What a store does once a thread has panicked while holding its lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoisonPolicy {
+    /// Every later call fails with [`crate::Error::MutexPoisoned`] until the
+    /// store is healed.
+    Error,
+    /// Later calls ignore the poisoning and carry on with the data as the
+    /// panicking thread left it. Writes build their new rows before storing
+    /// them, so that is almost always the data from before the panic.
+    Recover,
+}
+
+impl Default for PoisonPolicy {
+    fn default() -> Self {
+        PoisonPolicy::Error
+    }
+}
+
+/// What `try_delete_many` did with each of the keys it was given.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeleteManyReport {
+    /// The deleted rows, as they were before the delete.
+    pub deleted: Vec<Row>,
+    /// The keys that had no row to delete, in the order they were given.
+    pub missing: Vec<String>,
+}
+
+/// How the live rows of a store differ from a [`StoreDiskRepr`], see
+/// `Store::diff`. Every list is sorted by key.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StoreDiff {
+    /// Keys with a live row in the store but not in the other repr.
+    pub only_in_self: Vec<String>,
+    /// Keys with a live row in the other repr but not in the store.
+    pub only_in_other: Vec<String>,
+    /// Rows on both sides whose data differs, as `(ours, theirs)`. Versions and
+    /// value history aren't compared.
+    pub different: Vec<(Row, Row)>,
+}
+
+impl StoreDiff {
+    pub(crate) fn between<'a, I>(ours: I, mut theirs: HashMap<String, Row>) -> Self
+    where
+        I: IntoIterator<Item = &'a Row>,
+    {
+        theirs.retain(|_, row| row.is_live());
+        let mut diff = StoreDiff::default();
+        for row in ours.into_iter().filter(|row| row.is_live()) {
+            match theirs.remove(row.key()) {
+                None => diff.only_in_self.push(row.key().to_string()),
+                Some(other) if !row.same_data(&other) => diff.different.push((row.clone(), other)),
+                Some(_) => {}
+            }
+        }
+        diff.only_in_other = theirs.into_keys().collect();
+        diff.only_in_self.sort_unstable();
+        diff.only_in_other.sort_unstable();
+        diff.different.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        diff
+    }
+
+    /// Checks whether the two sides hold the same live rows.
+    pub fn is_empty(&self) -> bool {
+        self.only_in_self.is_empty() && self.only_in_other.is_empty() && self.different.is_empty()
+    }
+}
+
+/// Roughly how much memory the rows of a store take up, see
+/// `Store::memory_usage`. Compressed values count their compressed size.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StoreSize {
+    pub row_count: usize,
+    pub key_bytes: usize,
+    pub value_bytes: usize,
+    /// Every row's `Row::size_bytes`, plus its key stored again by the map and
+    /// [`StoreSize::ENTRY_OVERHEAD_BYTES`].
+    pub approx_total_bytes: usize,
+}
+
+impl StoreSize {
+    /// The estimated cost of one map entry on top of its row and key bytes: the
+    /// `String` holding the key plus about 8 bytes of hash table control bytes
+    /// and spare capacity.
+    pub const ENTRY_OVERHEAD_BYTES: usize = std::mem::size_of::<String>() + 8;
+
+    fn add(&mut self, row: &Row) {
+        self.row_count += 1;
+        self.key_bytes += row.key().len();
+        self.value_bytes += row.value.size_bytes();
+        self.approx_total_bytes += row.size_bytes() + row.key().len() + Self::ENTRY_OVERHEAD_BYTES;
+    }
 }
 
-pub fn reverse_timestamp(input: i64) -> OffsetDateTime {
-    match OffsetDateTime::from_unix_timestamp(input) {
-        Ok(odt) => odt,
-        Err(err) => panic!("error occurred reversing timestamp: {}", err),
+impl<'row> FromIterator<&'row Row> for StoreSize {
+    fn from_iter<T: IntoIterator<Item = &'row Row>>(iter: T) -> Self {
+        let mut size = StoreSize::default();
+        iter.into_iter().for_each(|row| size.add(row));
+        size
     }
 }
 
-/// TODO: Generalize `KeyValueStore` to this trait, and allow for multiple
-/// implementations of the `Store` to measure and compare performance.
-/// First up will be implementing this as a `HashSet` instead of `HashMap` using
-/// the newly added `Hash` implementation for `Row` (hashing based only on the key field).
+/// Gets copies of the `n` live rows with the largest values, largest first.
+/// Rows with values of the same size are ordered by key.
+pub(crate) fn largest_rows<'a, I: IntoIterator<Item = &'a Row>>(rows: I, n: usize) -> Vec<Row> {
+    let mut rows = rows
+        .into_iter()
+        .filter(|row| row.is_live())
+        .collect::<Vec<_>>();
+    rows.sort_unstable_by(|a, b| {
+        b.value
+            .size_bytes()
+            .cmp(&a.value.size_bytes())
+            .then_with(|| a.cmp(b))
+    });
+    rows.into_iter().take(n).cloned().collect()
+}
+
+/// Picks up to `n` of `items` at random, each at most once and in random
+/// order, in a single pass. Only the items that end up picked are passed to
+/// `f`, so stores can sample borrowed rows without cloning all of them.
+pub(crate) fn sample_with<T, U, F>(items: impl Iterator<Item = T>, n: usize, f: F) -> Vec<U>
+where
+    F: Fn(T) -> U,
+{
+    let mut picked = Vec::new();
+    for (i, item) in items.enumerate() {
+        if i < n {
+            picked.push(f(item));
+        } else {
+            let j = fastrand::usize(..=i);
+            if j < n {
+                picked[j] = f(item);
+            }
+        }
+    }
+    fastrand::shuffle(&mut picked);
+    picked
+}
+
+/// Checks whether `ours` and `theirs` hold the same live rows, comparing them
+/// with `Row::same_data`, or `Row::same_data_untimed` if `ignore_timestamps`
+/// is set. Versions and value history aren't compared, like `StoreDiff`.
+pub(crate) fn rows_equal<'a, I, J>(ours: I, theirs: J, ignore_timestamps: bool) -> bool
+where
+    I: IntoIterator<Item = &'a Row>,
+    J: IntoIterator<Item = &'a Row>,
+{
+    let mut theirs = theirs
+        .into_iter()
+        .filter(|row| row.is_live())
+        .map(|row| (row.key(), row))
+        .collect::<HashMap<_, _>>();
+    for row in ours.into_iter().filter(|row| row.is_live()) {
+        match theirs.remove(row.key()) {
+            Some(other) if ignore_timestamps && row.same_data_untimed(other) => {}
+            Some(other) if !ignore_timestamps && row.same_data(other) => {}
+            _ => return false,
+        }
+    }
+    theirs.is_empty()
+}
+
+/// Which values a store keeps compressed, see `KeyValueStore::with_options`.
+///
+/// Only string values of at least `threshold` bytes are compressed, and only
+/// when the `compression` feature is enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionOptions {
+    pub threshold: usize,
+}
+
+impl CompressionOptions {
+    pub const DEFAULT_THRESHOLD: usize = 4 * 1024;
+
+    pub fn with_threshold(threshold: usize) -> Self {
+        Self { threshold }
+    }
+}
+
+impl Default for CompressionOptions {
+    fn default() -> Self {
+        Self::with_threshold(Self::DEFAULT_THRESHOLD)
+    }
+}
+
+/// The operations shared by every store, so their performance can be measured
+/// and compared: `KeyValueStore` (a `HashMap`), `DashStore` (a `DashMap`),
+/// `BTreeStore` (an ordered `BTreeMap`), `SetStore` (a `HashSet` of rows,
+/// using the key-only `Hash` implementation for `Row`), `ShardedStore`
+/// (several independently locked `HashMap`s) and `LruStore` (a bounded cache).
+///
+/// TODO: Generalize `KeyValueStore` to this trait.
 pub trait Store {
     fn get_clone(&self, key: &str) -> crate::Result<Row>;
+    fn get_many(&self, keys: &[&str]) -> crate::Result<Vec<(String, crate::Result<Row>)>>;
     fn insert(&self, key: &str, value: &str) -> crate::Result<()>;
     fn insert_row(&self, row: &Row) -> crate::Result<()>;
-    fn set_or_insert(&self, key: &str, value: &str) -> crate::Result<()>;
-    fn set_or_insert_row(&self, row: &Row) -> crate::Result<()>;
+    /// Sets the value of `key`, inserting a new row if it has no live row.
+    /// Returns a copy of the live row it replaced, taken before the update, or
+    /// `None` if it inserted a new row.
+    fn set_or_insert(&self, key: &str, value: &str) -> crate::Result<Option<Row>>;
+    /// Inserts every pair, or none of them if any key already exists (or
+    /// appears twice in `pairs`), returning how many rows were inserted.
+    ///
+    /// This default checks every key before inserting anything, but does not
+    /// stop other threads from racing it; stores that can should override it
+    /// to do the whole batch atomically.
+    fn insert_many(&self, pairs: &[(&str, &str)]) -> crate::Result<usize> {
+        let mut seen = std::collections::HashSet::with_capacity(pairs.len());
+        for &(key, _) in pairs {
+            if !seen.insert(key) || self.contains(key)? {
+                return Err(crate::Error::duplicate_key(key));
+            }
+        }
+        for &(key, value) in pairs {
+            self.insert(key, value)?;
+        }
+        Ok(pairs.len())
+    }
+    /// Calls `set_or_insert` for every pair, in order, and counts the outcomes.
+    fn set_many(&self, pairs: &[(&str, &str)]) -> crate::Result<SetManyOutcome> {
+        let mut outcome = SetManyOutcome::default();
+        for &(key, value) in pairs {
+            let previous = self.set_or_insert(key, value)?;
+            outcome.record(SetOutcome::from_previous(previous.as_ref(), value));
+        }
+        Ok(outcome)
+    }
+    /// Same as `set_or_insert`, with a whole row.
+    fn set_or_insert_row(&self, row: &Row) -> crate::Result<Option<Row>>;
     fn contains(&self, key: &str) -> crate::Result<bool>;
     fn len(&self) -> crate::Result<usize>;
+    /// Gets how many more rows this store can take before it reaches its
+    /// capacity limit, or `None` if it has no limit.
+    fn remaining_capacity(&self) -> crate::Result<Option<usize>> {
+        Ok(None)
+    }
+    fn is_empty(&self) -> crate::Result<bool> {
+        self.len().map(|len| len == 0)
+    }
+    /// Removes every row, returning how many there were (not counting deleted
+    /// rows, like `len`).
+    fn clear(&self) -> crate::Result<usize>;
+    /// Makes this store reject every write with [`crate::Error::ReadOnly`]
+    /// while reads keep working, or lets writes through again. Fails with
+    /// [`crate::Error::Unsupported`] on stores without a read-only mode.
+    fn set_read_only(&self, _read_only: bool) -> crate::Result<()> {
+        Err(crate::Error::unsupported("read-only mode"))
+    }
+    /// Checks whether this store is currently rejecting writes.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+    /// Runs `f` on the live row for `key` and stores the result, all
+    /// atomically, returning a copy of the stored row.
+    fn update_with(&self, key: &str, f: &mut dyn FnMut(&mut Row)) -> crate::Result<Row>;
+    /// Same as `update_with`, but when `key` has no live row `f` is run on the
+    /// row made by `default` instead.
+    fn upsert_with(
+        &self,
+        key: &str,
+        default: &mut dyn FnMut() -> Row,
+        f: &mut dyn FnMut(&mut Row),
+    ) -> crate::Result<Row>;
     fn delete(&self, key: &str) -> crate::Result<Row>;
+    /// Bumps the `updated` timestamp of the live row for `key` with
+    /// [`Row::touch`], without changing its value, and returns the new
+    /// timestamp.
+    fn touch(&self, key: &str) -> crate::Result<i64> {
+        self.update_with(key, &mut |row| row.touch())
+            .map(|row| row.updated())
+    }
+    /// Calls `touch` for each of `keys`, in order, with missing keys answered
+    /// by [`crate::Error::KeyNotFound`].
+    fn touch_many(&self, keys: &[&str]) -> crate::Result<Vec<(String, crate::Result<i64>)>> {
+        Ok(keys
+            .iter()
+            .map(|&key| (key.to_string(), self.touch(key)))
+            .collect())
+    }
+    /// Atomically sets the value of `key` to `new`, if it currently holds
+    /// `expected`.
+    fn compare_and_swap(&self, key: &str, expected: &str, new: &str) -> crate::Result<CasOutcome>;
+    /// Atomically deletes the row for `key`, if it currently holds `expected`.
+    /// Reports a successful delete as [`CasOutcome::Swapped`].
+    fn compare_and_delete(&self, key: &str, expected: &str) -> crate::Result<CasOutcome>;
+    /// Moves the live row for `old_key` to `new_key`, keeping its `created`
+    /// timestamp. Fails if `new_key` already has a live row.
+    fn rename(&self, old_key: &str, new_key: &str) -> crate::Result<()>;
+    /// Same as `rename`, but replaces and returns any live row for `new_key`.
+    fn rename_overwrite(&self, old_key: &str, new_key: &str) -> crate::Result<Option<Row>>;
+    fn delete_many(&self, keys: &[&str]) -> crate::Result<Vec<Row>>;
+    fn try_delete_many(&self, keys: &[&str]) -> crate::Result<DeleteManyReport>;
+    fn delete_matching(&self, predicate: &dyn Fn(&Row) -> bool) -> crate::Result<Vec<Row>>;
+    /// Removes every row for which `predicate` returns false, returning how
+    /// many were removed. Deleted rows are left alone.
+    fn retain(&self, predicate: &dyn Fn(&str, &Row) -> bool) -> crate::Result<usize>;
+    /// Removes every row last updated before `since` (in milliseconds, like
+    /// `Row::updated`), returning how many were removed.
+    fn retain_updated_since(&self, since: i64) -> crate::Result<usize> {
+        self.retain(&|_, row| row.updated() >= since)
+    }
+    /// Gets every live row last updated at or after `ts` (in milliseconds,
+    /// like `Row::updated`), sorted by key.
+    fn rows_updated_since(&self, ts: i64) -> crate::Result<Vec<Row>> {
+        let mut rows = self.rows()?;
+        rows.retain(|row| row.updated() >= ts);
+        rows.sort();
+        Ok(rows)
+    }
+    /// Picks the key of a live row at random, or `None` if there are none.
+    fn random_key(&self) -> crate::Result<Option<String>> {
+        Ok(sample_with(self.keys()?.into_iter(), 1, |key| key).pop())
+    }
+    /// Picks up to `n` distinct live rows at random, in random order. Asking
+    /// for at least as many rows as the store holds gets all of them.
+    fn sample(&self, n: usize) -> crate::Result<Vec<Row>> {
+        Ok(sample_with(self.rows()?.into_iter(), n, |row| row))
+    }
+    /// Gets every live row created strictly before `ts`, sorted by key.
+    fn rows_created_before(&self, ts: i64) -> crate::Result<Vec<Row>> {
+        let mut rows = self.rows()?;
+        rows.retain(|row| row.created() < ts);
+        rows.sort();
+        Ok(rows)
+    }
+    /// Deletes every live row last updated strictly before `ts` with
+    /// `delete_matching`, returning the deleted rows sorted by key.
+    fn purge_older_than(&self, ts: i64) -> crate::Result<Vec<Row>> {
+        let mut rows = self.delete_matching(&|row| row.updated() < ts)?;
+        rows.sort();
+        Ok(rows)
+    }
+    fn keys(&self) -> crate::Result<Vec<String>>;
+    fn rows(&self) -> crate::Result<Vec<Row>>;
+    /// Gets the keys of every live row starting with `prefix`, sorted.
+    ///
+    /// The default scans every row; ordered stores should override this (and
+    /// `scan_prefix` / `count_prefix`) to only visit the matching range.
+    fn keys_with_prefix(&self, prefix: &str) -> crate::Result<Vec<String>> {
+        let mut keys = self.keys()?;
+        keys.retain(|key| key.starts_with(prefix));
+        keys.sort();
+        Ok(keys)
+    }
+    /// Gets the keys of every live row matching the glob-style `pattern`,
+    /// sorted, see `KeyValueStore::find_keys`.
+    fn find_keys(&self, pattern: &str) -> crate::Result<Vec<String>> {
+        let pattern = KeyPattern::new(pattern);
+        let mut keys = self.keys()?;
+        keys.retain(|key| pattern.matches(key));
+        keys.sort();
+        Ok(keys)
+    }
+    /// Gets every live row whose key matches the glob-style `pattern`, sorted
+    /// by key.
+    fn find_rows(&self, pattern: &str) -> crate::Result<Vec<Row>> {
+        let pattern = KeyPattern::new(pattern);
+        let mut rows = self.rows()?;
+        rows.retain(|row| pattern.matches(row.key()));
+        rows.sort();
+        Ok(rows)
+    }
+    /// Checks whether any live row holds `value`.
+    fn contains_value(&self, value: &str) -> crate::Result<bool> {
+        self.find_first(&|row| row.value() == value)
+            .map(|row| row.is_some())
+    }
+    /// Gets the keys of every live row holding `value`, sorted.
+    fn keys_with_value(&self, value: &str) -> crate::Result<Vec<String>> {
+        self.find(&|row| row.value() == value)
+            .map(|rows| rows.iter().map(|row| row.key().to_string()).collect())
+    }
+    /// Gets every live row matching `predicate`, sorted by key.
+    fn find(&self, predicate: &dyn Fn(&Row) -> bool) -> crate::Result<Vec<Row>> {
+        let mut rows = self.rows()?;
+        rows.retain(|row| predicate(row));
+        rows.sort();
+        Ok(rows)
+    }
+    /// Gets a live row matching `predicate`, or `None` if none do. Stores
+    /// should stop scanning at the first match; the default copies every row
+    /// first, but still stops calling `predicate` once it has matched.
+    fn find_first(&self, predicate: &dyn Fn(&Row) -> bool) -> crate::Result<Option<Row>> {
+        Ok(self.rows()?.into_iter().find(|row| predicate(row)))
+    }
+    /// Gets every live row whose key starts with `prefix`, sorted by key.
+    fn scan_prefix(&self, prefix: &str) -> crate::Result<Vec<Row>> {
+        let mut rows = self.rows()?;
+        rows.retain(|row| row.key().starts_with(prefix));
+        rows.sort();
+        Ok(rows)
+    }
+    /// Counts the live rows whose key starts with `prefix`.
+    fn count_prefix(&self, prefix: &str) -> crate::Result<usize> {
+        self.keys_with_prefix(prefix).map(|keys| keys.len())
+    }
     fn to_disk_repr(&self) -> crate::Result<StoreDiskRepr>;
-    // fn from_disk_repr(disk_repr: &StoreDiskRepr) -> crate::Result<Self>;
+    /// Writes the same json as `KeyValueStore::to_bytes`, a map from each key
+    /// to its row, which [`StoreFactory::from_bytes`] loads back.
+    ///
+    /// The default implementation only writes live rows, from `rows`.
+    fn to_bytes(&self) -> crate::Result<Vec<u8>> {
+        let rows = self.rows()?;
+        let map = rows
+            .iter()
+            .map(|row| (row.key(), row))
+            .collect::<HashMap<_, _>>();
+        serde_json::to_vec(&map).map_err(|err| crate::Error::json_ser(&err))
+    }
+    /// Gets the name of the type implementing this store, so diagnostics can
+    /// report which backend is running behind a `dyn Store`.
+    fn backend_name(&self) -> &'static str;
+    /// Merges every row of `other` into this store with [`Row::merge`],
+    /// inserting rows whose keys have no live row here yet.
+    ///
+    /// The default implementation goes through `get_clone` and
+    /// `set_or_insert_row` one row at a time, so it isn't atomic.
+    fn merge_from(
+        &self,
+        other: &StoreDiskRepr,
+        strategy: MergeStrategy,
+    ) -> crate::Result<MergeReport> {
+        let mut report = MergeReport::default();
+        for (key, row) in other.rows_by_key()? {
+            match self.get_clone(&key) {
+                Ok(mut local) => {
+                    let result = local.merge(&row, strategy);
+                    if result.is_modified() {
+                        self.set_or_insert_row(&local)?;
+                    }
+                    report.record(result);
+                }
+                Err(crate::Error::KeyNotFound(_)) => {
+                    self.set_or_insert_row(&row)?;
+                    report.inserted += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(report)
+    }
+    /// Estimates how much memory the rows of this store take up.
+    ///
+    /// The default implementation only sees the rows returned by `rows`, so
+    /// deleted and expired rows the store still holds aren't counted.
+    fn memory_usage(&self) -> crate::Result<StoreSize> {
+        self.rows().map(|rows| rows.iter().collect())
+    }
+    /// Gets the `n` live rows with the largest values, largest first. Rows
+    /// with values of the same size are ordered by key.
+    fn largest_rows(&self, n: usize) -> crate::Result<Vec<Row>> {
+        self.rows().map(|rows| largest_rows(&rows, n))
+    }
+    /// Compares the live rows of this store against those of `other`.
+    fn diff(&self, other: &StoreDiskRepr) -> crate::Result<StoreDiff> {
+        let theirs = other.rows_by_key()?;
+        self.rows().map(|rows| StoreDiff::between(&rows, theirs))
+    }
+}
+
+/// Loads a [`Store`] from the forms it's saved in. This is kept apart from
+/// `Store` so that `Store` can still be used as a trait object.
+pub trait StoreFactory: Store + Sized {
+    /// Loads a store previously written with `Store::to_bytes`, dropping any
+    /// rows that have expired since.
+    fn from_bytes(bytes: &[u8]) -> crate::Result<Self>;
+    /// Loads a store from `disk`, dropping any rows that have expired.
+    fn from_disk(disk: &StoreDiskRepr) -> crate::Result<Self>;
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn valid_keys() {
+        assert_eq!(validate_key("key"), Ok(()));
+        assert_eq!(validate_key("key with spaces/and:symbols"), Ok(()));
+        assert_eq!(validate_key(&"k".repeat(MAX_KEY_LEN)), Ok(()));
+    }
+
+    #[test]
+    fn invalid_keys() {
+        assert_eq!(
+            validate_key(""),
+            Err(crate::Error::invalid_key("", "key must not be empty"))
+        );
+        let long = "k".repeat(MAX_KEY_LEN + 1);
+        assert_eq!(
+            validate_key(&long),
+            Err(crate::Error::invalid_key(
+                &long,
+                "key is 1025 bytes, the maximum is 1024"
+            ))
+        );
+        let err = validate_key("line\nbreak").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid key \"line\\nbreak\": key contains the control character '\\n'"
+        );
+        assert!(validate_key("tab\there").is_err());
+    }
+
+    #[test]
+    fn key_patterns() {
+        let matches = |pattern: &str, key: &str| KeyPattern::new(pattern).matches(key);
+        assert!(matches("user:*", "user:"));
+        assert!(matches("user:*", "user:42:name"));
+        assert!(!matches("user:*", "users:42"));
+        assert!(matches("*:?:name", "user:7:name"));
+        assert!(matches("*:?:name", "a:b:c:7:name"));
+        assert!(!matches("*:?:name", "user:42:name"));
+        assert!(matches("*a*b*", "xxaxxbxx"));
+        assert!(!matches("*a*b*", "xxbxxaxx"));
+        assert!(matches("?é?", "aéb"));
+        assert!(matches("**", ""));
+
+        // Everything else is literal.
+        assert!(matches("user.name", "user.name"));
+        assert!(!matches("user.name", "user_name"));
+        assert!(matches("[ab]", "[ab]"));
+        assert!(!matches("[ab]", "a"));
+        assert!(!matches("user", "user:1"));
+
+        assert!(matches("", ""));
+        assert!(!matches("", "a"));
+    }
+
+    #[test]
+    fn boxed_backends() {
+        let stores: Vec<Box<dyn Store + Send + Sync>> = vec![
+            Box::new(KeyValueStore::empty()),
+            Box::new(DashStore::empty()),
+            Box::new(BTreeStore::empty()),
+            Box::new(LruStore::new(5)),
+            Box::new(SetStore::empty()),
+            Box::new(ShardedStore::empty()),
+        ];
+        assert_eq!(
+            stores
+                .iter()
+                .map(|store| store.backend_name())
+                .collect::<Vec<_>>(),
+            vec![
+                "KeyValueStore",
+                "DashStore",
+                "BTreeStore",
+                "LruStore",
+                "SetStore",
+                "ShardedStore"
+            ]
+        );
+        for store in &stores {
+            test_helpers::exercise_store(store.as_ref());
+        }
+    }
+
+    #[test]
+    fn factories() {
+        test_helpers::check_factory::<KeyValueStore>();
+        test_helpers::check_factory::<DashStore>();
+        test_helpers::check_factory::<BTreeStore>();
+        test_helpers::check_factory::<SetStore>();
+        test_helpers::check_factory::<ShardedStore>();
+    }
+
+    #[test]
+    fn search_backends() {
+        let stores: Vec<Box<dyn Store>> = vec![
+            Box::new(KeyValueStore::empty()),
+            Box::new(DashStore::empty()),
+            Box::new(BTreeStore::empty()),
+            Box::new(LruStore::new(5)),
+            Box::new(SetStore::empty()),
+            Box::new(ShardedStore::empty()),
+        ];
+        for store in &stores {
+            for (key, value) in [("a", "same"), ("b", "other"), ("c", "same")] {
+                assert_eq!(store.insert(key, value), Ok(()));
+            }
+            let name = store.backend_name();
+            assert_eq!(store.contains_value("same"), Ok(true), "{}", name);
+            assert_eq!(store.contains_value("missing"), Ok(false), "{}", name);
+            assert_eq!(
+                store.keys_with_value("same"),
+                Ok(vec!["a".to_string(), "c".to_string()]),
+                "{}",
+                name
+            );
+            let found = store.find(&|row| row.value() != "other").unwrap();
+            assert_eq!(found.len(), 2, "{}", name);
+            assert!(found[0].key() < found[1].key(), "{}", name);
+            let first = store.find_first(&|row| row.value() == "other").unwrap();
+            assert_eq!(first.map(|row| row.key().to_string()), Some("b".into()));
+            assert_eq!(store.find_first(&|_| false), Ok(None), "{}", name);
+        }
+    }
 }
@@ -4,14 +4,380 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use std::{borrow::Cow, cmp::Ordering, collections::BTreeMap, sync::Arc};
+
+use prost::Message;
 use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use super::value_repr::{ValueCache, ValueRepr};
+
+/// A typed value stored in a [`Row`].
+///
+/// Values are serialized with an explicit type tag (`{"type": "Int", "value": 5}`),
+/// but a plain json string also deserializes (as [`Value::Str`]) so data
+/// written before values were typed can still be loaded.
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", content = "value")]
+pub enum Value {
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Bytes(Vec<u8>),
+    Null,
+}
+
+impl Value {
+    /// Gets the contained string if this is a [`Value::Str`].
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Gets the contained integer if this is a [`Value::Int`].
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    /// Gets the contained float if this is a [`Value::Float`].
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Value::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    /// Gets the contained bool if this is a [`Value::Bool`].
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// Gets the contained bytes if this is a [`Value::Bytes`].
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// Gets the size of the data held by this value in bytes, not counting
+    /// the enum itself.
+    pub fn size_bytes(&self) -> usize {
+        match self {
+            Value::Str(s) => s.len(),
+            Value::Int(_) => std::mem::size_of::<i64>(),
+            Value::Float(_) => std::mem::size_of::<f64>(),
+            Value::Bool(_) => std::mem::size_of::<bool>(),
+            Value::Bytes(bytes) => bytes.len(),
+            Value::Null => 0,
+        }
+    }
+
+    /// Feeds this value into `hasher`. Strings hash as their raw bytes, every
+    /// other variant is prefixed with a tag byte.
+    pub(crate) fn hash_into(&self, hasher: &mut crc32fast::Hasher) {
+        match self {
+            Value::Str(s) => hasher.update(s.as_bytes()),
+            Value::Int(i) => {
+                hasher.update(&[1]);
+                hasher.update(&i.to_le_bytes());
+            }
+            Value::Float(f) => {
+                hasher.update(&[2]);
+                hasher.update(&f.to_le_bytes());
+            }
+            Value::Bool(b) => hasher.update(&[3, u8::from(*b)]),
+            Value::Bytes(bytes) => {
+                hasher.update(&[4]);
+                hasher.update(bytes);
+            }
+            Value::Null => hasher.update(&[5]),
+        }
+    }
+
+    /// Checks whether this is [`Value::Null`].
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    /// Renders this value for places that can only hold a string, like the
+    /// `value` field of rpc responses. Strings are returned as-is.
+    pub fn to_display_string(&self) -> String {
+        self.to_string()
+    }
+
+    fn variant_index(&self) -> u8 {
+        match self {
+            Value::Str(_) => 0,
+            Value::Int(_) => 1,
+            Value::Float(_) => 2,
+            Value::Bool(_) => 3,
+            Value::Bytes(_) => 4,
+            Value::Null => 5,
+        }
+    }
+}
+
+impl Default for Value {
+    fn default() -> Self {
+        Value::Str(String::new())
+    }
+}
+
+/// Floats are compared with [`f64::total_cmp`] so that `Value` (and therefore
+/// `Row`) can be `Eq` and `Ord`.
+impl Ord for Value {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Value::Str(a), Value::Str(b)) => a.cmp(b),
+            (Value::Int(a), Value::Int(b)) => a.cmp(b),
+            (Value::Float(a), Value::Float(b)) => a.total_cmp(b),
+            (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+            (Value::Bytes(a), Value::Bytes(b)) => a.cmp(b),
+            _ => self.variant_index().cmp(&other.variant_index()),
+        }
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
 
-#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+impl Eq for Value {}
+
+impl PartialEq<str> for Value {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == Some(other)
+    }
+}
+
+impl PartialEq<&str> for Value {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == Some(*other)
+    }
+}
+
+impl PartialEq<String> for Value {
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == Some(other.as_str())
+    }
+}
+
+impl PartialEq<String> for &Value {
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == Some(other.as_str())
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Bytes(bytes) => {
+                write!(f, "0x")?;
+                bytes.iter().try_for_each(|b| write!(f, "{:02x}", b))
+            }
+            Value::Null => write!(f, "null"),
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Str(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Str(s)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Self {
+        Value::Int(i)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(f: f64) -> Self {
+        Value::Float(f)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(bytes: Vec<u8>) -> Self {
+        Value::Bytes(bytes)
+    }
+}
+
+/// Mirrors [`Value`] for deserialization, see [`ValueInput`].
+#[derive(Deserialize)]
+#[serde(tag = "type", content = "value")]
+enum TaggedValue {
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Bytes(Vec<u8>),
+    Null,
+}
+
+/// Accepts both the tagged representation and the plain strings written
+/// before `Value` existed.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ValueInput {
+    Legacy(String),
+    Tagged(TaggedValue),
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match ValueInput::deserialize(deserializer)? {
+            ValueInput::Legacy(s) => Value::Str(s),
+            ValueInput::Tagged(TaggedValue::Str(s)) => Value::Str(s),
+            ValueInput::Tagged(TaggedValue::Int(i)) => Value::Int(i),
+            ValueInput::Tagged(TaggedValue::Float(f)) => Value::Float(f),
+            ValueInput::Tagged(TaggedValue::Bool(b)) => Value::Bool(b),
+            ValueInput::Tagged(TaggedValue::Bytes(bytes)) => Value::Bytes(bytes),
+            ValueInput::Tagged(TaggedValue::Null) => Value::Null,
+        })
+    }
+}
+
+/// A single key/value entry in a store.
+///
+/// Rows are ordered (and hashed) by key alone so that sorting rows gives a
+/// stable, key-ordered listing. This means two rows with the same key but
+/// different values compare as `Ordering::Equal` while still being `!=`. Use
+/// `Row::cmp_by_created` or `Row::cmp_by_updated` to order rows by time.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
 pub struct Row {
-    pub(crate) key: String,
-    pub(crate) value: String,
+    #[serde(with = "shared")]
+    pub(crate) key: Arc<str>,
+    pub(crate) value: ValueRepr,
+    #[serde(skip)]
+    pub(crate) cached_value: ValueCache,
+    #[serde(alias = "created_at")]
     pub(crate) created: i64,
+    #[serde(alias = "updated_at")]
     pub(crate) updated: i64,
+    #[serde(default = "Row::initial_version")]
+    pub(crate) version: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) expires_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) meta: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) history: Vec<(Value, i64)>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub(crate) history_limit: usize,
+    #[serde(default)]
+    pub(crate) checksum: u32,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) deleted: bool,
+}
+
+/// How `Row::merge` decides between two versions of the same row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Keep whichever row has the greater `updated` timestamp. The merged row
+    /// keeps the earliest `created` timestamp of the two.
+    LastWriteWins,
+    /// Keep whichever row has the smaller `updated` timestamp.
+    FirstWriteWins,
+    /// Always take the other row.
+    PreferOther,
+}
+
+/// Whether `Row::merge` changed the row it was called on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeResult {
+    Unchanged,
+    Modified,
+}
+
+impl MergeResult {
+    pub fn is_modified(&self) -> bool {
+        *self == MergeResult::Modified
+    }
+}
+
+/// A single field that differs between two rows, see `Row::diff`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldDiff {
+    pub field: &'static str,
+    pub left: String,
+    pub right: String,
+}
+
+impl std::fmt::Display for FieldDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} != {}", self.field, self.left, self.right)
+    }
+}
+
+/// 0001-01-01T00:00:00Z in milliseconds.
+const MIN_TIMESTAMP: i64 = -62_135_596_800_000;
+/// 9999-12-31T23:59:59.999Z in milliseconds.
+const MAX_TIMESTAMP: i64 = 253_402_300_799_999;
+
+/// `Row` keeps its key and value behind an `Arc` so that cloning a row doesn't
+/// copy them. These (de)serialize the pointed-to data directly, so the json
+/// looks exactly like it did when they were owned.
+mod shared {
+    use std::sync::Arc;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<T, S>(value: &Arc<T>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Serialize + ?Sized,
+        S: Serializer,
+    {
+        (**value).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Arc<T>, D::Error>
+    where
+        T: ?Sized,
+        Box<T>: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Box::<T>::deserialize(deserializer).map(Arc::from)
+    }
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
 }
 
 impl Row {
@@ -21,8 +387,53 @@ impl Row {
     }
 
     /// Gets a reference to the `value` of this `Row`.
-    pub fn value(&self) -> &str {
-        &self.value
+    ///
+    /// A compressed value is decompressed on the first call and the result is
+    /// kept with the row, so later calls are free but the row then holds both
+    /// forms until its value is next written. Rows returned by a store are
+    /// copies, so reading them doesn't grow the store itself. Internal
+    /// bookkeeping like checksums goes through `Row::load_value` instead, which
+    /// never caches.
+    pub fn value(&self) -> &Value {
+        match &self.value {
+            ValueRepr::Plain(value) => value,
+            repr @ ValueRepr::Compressed { .. } => self.cached_value.get_or_init(repr),
+        }
+    }
+
+    /// Checks whether the value of this `Row` is currently stored compressed.
+    pub fn is_compressed(&self) -> bool {
+        self.value.is_compressed()
+    }
+
+    /// Compresses the value of this `Row` if it is a string of at least
+    /// `threshold` bytes, see [`ValueRepr::compress`]. Returns whether the
+    /// value was compressed.
+    pub fn compress(&mut self, threshold: usize) -> bool {
+        if self.is_compressed() {
+            return false;
+        }
+        match ValueRepr::compress(&self.value.get(), threshold) {
+            Some(compressed) => {
+                self.value = compressed;
+                self.cached_value = ValueCache::default();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Gets the value without filling in the cache used by `Row::value`.
+    fn load_value(&self) -> Cow<'_, Value> {
+        match self.cached_value.get() {
+            Some(value) => Cow::Borrowed(value),
+            None => self.value.get(),
+        }
+    }
+
+    fn set_value(&mut self, value: Value) {
+        self.value = value.into();
+        self.cached_value = ValueCache::default();
     }
 
     /// Gets the `created` timestamp value of this `Row`.
@@ -35,59 +446,599 @@ impl Row {
         self.updated
     }
 
+    /// Gets the `created` timestamp of this `Row` as an `OffsetDateTime`.
+    pub fn created_at(&self) -> crate::Result<OffsetDateTime> {
+        super::reverse_timestamp(self.created)
+    }
+
+    /// Gets the `updated` timestamp of this `Row` as an `OffsetDateTime`.
+    pub fn updated_at(&self) -> crate::Result<OffsetDateTime> {
+        super::reverse_timestamp(self.updated)
+    }
+
+    /// Checks that `created` and `updated` are both between the years 1 and 9999
+    /// (inclusive) and that `created` is not after `updated`.
+    pub fn validate_timestamps(&self) -> crate::Result<()> {
+        for timestamp in [self.created, self.updated] {
+            if !(MIN_TIMESTAMP..=MAX_TIMESTAMP).contains(&timestamp) {
+                return Err(crate::Error::InvalidTimestamp(timestamp));
+            }
+        }
+        if self.created > self.updated {
+            return Err(crate::Error::invalid_row(&format!(
+                "created timestamp {} is after updated timestamp {}",
+                self.created, self.updated
+            )));
+        }
+        Ok(())
+    }
+
+    /// Gets the `version` of this `Row`. Versions start at 1 and are
+    /// incremented every time the value of the row changes.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub(crate) const fn initial_version() -> u64 {
+        1
+    }
+
+    /// The `checksum` of rows that were stored before rows had checksums.
+    pub(crate) const NO_CHECKSUM: u32 = 0;
+
+    /// Computes the CRC32 of the `key`, `value`, `created` and `updated` fields.
+    fn compute_checksum(&self) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(self.key.as_bytes());
+        hasher.update(&[0]);
+        self.load_value().hash_into(&mut hasher);
+        hasher.update(&[0]);
+        hasher.update(&self.created.to_le_bytes());
+        hasher.update(&self.updated.to_le_bytes());
+        hasher.finalize()
+    }
+
+    /// Gets the stored checksum of this `Row`.
+    pub fn checksum(&self) -> u32 {
+        self.checksum
+    }
+
+    /// Checks that the stored checksum matches the current contents of this
+    /// `Row`. Rows without a checksum (loaded from data written before
+    /// checksums existed) always pass.
+    pub fn verify_checksum(&self) -> bool {
+        self.checksum == Self::NO_CHECKSUM || self.checksum == self.compute_checksum()
+    }
+
+    /// Recomputes and stores the checksum of this `Row`.
+    pub fn recompute_checksum(&mut self) {
+        self.checksum = self.compute_checksum();
+    }
+
+    /// Parses the value of this `Row` into a `T`, using the same string `Display`
+    /// produces for it. Fails with [`crate::Error::ValueParse`] if the value
+    /// isn't a valid `T`.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_db::Row;
+    /// let row = Row::create("counter", "41");
+    /// assert_eq!(row.parse_value::<i64>().unwrap() + 1, 42);
+    /// assert!(row.parse_value::<bool>().is_err());
+    /// ```
+    pub fn parse_value<T>(&self) -> crate::Result<T>
+    where
+        T: std::str::FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.load_value()
+            .to_display_string()
+            .parse()
+            .map_err(|err| crate::Error::value_parse::<T>(&self.key, &err))
+    }
+
+    /// Gets the timestamp this `Row` expires at, if it was given a TTL.
+    pub fn expires_at(&self) -> Option<i64> {
+        self.expires_at
+    }
+
+    /// Checks whether this `Row` has been deleted with `Row::tombstone`.
+    pub fn is_deleted(&self) -> bool {
+        self.deleted
+    }
+
+    /// Checks whether this `Row` should be visible to readers, i.e. it is
+    /// neither expired nor deleted.
+    pub fn is_live(&self) -> bool {
+        !self.deleted && !self.is_expired()
+    }
+
+    /// Gets the approximate amount of memory used by this `Row`: the length of
+    /// its key and value plus the fixed size of the struct itself. Compressed
+    /// values count their compressed size. Metadata and history are not counted.
+    pub fn size_bytes(&self) -> usize {
+        std::mem::size_of::<Self>() + self.key.len() + self.value.size_bytes()
+    }
+
+    /// Gets all metadata attached to this `Row`.
+    pub fn meta(&self) -> &BTreeMap<String, String> {
+        &self.meta
+    }
+
+    /// Gets the metadata value stored under `key`, if any.
+    pub fn get_meta(&self, key: &str) -> Option<&str> {
+        self.meta.get(key).map(String::as_str)
+    }
+
+    /// Attaches `value` to this `Row` under the metadata `key`, returning the
+    /// previous value if there was one. Metadata is not part of the row's value,
+    /// so `updated` and `version` are left alone.
+    pub fn set_meta<Key: AsRef<str>, Val: AsRef<str>>(
+        &mut self,
+        key: Key,
+        value: Val,
+    ) -> Option<String> {
+        self.meta
+            .insert(key.as_ref().to_string(), value.as_ref().to_string())
+    }
+
+    /// Removes the metadata stored under `key`, returning it if it existed.
+    pub fn remove_meta(&mut self, key: &str) -> Option<String> {
+        self.meta.remove(key)
+    }
+
+    /// Gets the previous values of this `Row`, oldest first, along with the
+    /// timestamp each one was replaced at. Always empty unless a history limit
+    /// was set with `Row::set_history_limit`.
+    pub fn history(&self) -> &[(Value, i64)] {
+        &self.history
+    }
+
+    /// Gets the value this `Row` held before the current one, if it is being
+    /// tracked.
+    pub fn previous_value(&self) -> Option<&Value> {
+        self.history.last().map(|(value, _)| value)
+    }
+
+    /// Sets how many previous values this `Row` keeps, dropping the oldest
+    /// entries if there are already more than `limit`. A limit of 0 (the
+    /// default) disables history completely.
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history_limit = limit;
+        self.trim_history();
+    }
+
+    /// Same as `Row::set_history_limit`, but usable while building a `Row`.
+    pub fn with_history_limit(mut self, limit: usize) -> Self {
+        self.set_history_limit(limit);
+        self
+    }
+
+    fn record_history(&mut self, now: i64) {
+        if self.history_limit > 0 {
+            self.history.push((self.load_value().into_owned(), now));
+            self.trim_history();
+        }
+    }
+
+    fn trim_history(&mut self) {
+        let excess = self.history.len().saturating_sub(self.history_limit);
+        self.history.drain(..excess);
+    }
+
+    /// Checks whether this `Row` has an expiration time that has already passed.
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .map_or(false, |expires_at| expires_at <= super::create_now())
+    }
+
     /// Creates a new `Row` object with the given values. Use this to create a
     /// row object that matches data you already have on hand. Use `Row::create`
     /// when a new `Row` is being created by the user.
+    ///
+    /// Panics if `key` is not valid according to [`super::validate_key`], use
+    /// `Row::builder` to handle that case instead.
     pub fn new(key: &str, value: &str, created: i64, updated: i64) -> Self {
-        Row {
-            key: key.to_string(),
-            value: value.to_string(),
-            created,
-            updated,
-        }
+        Self::builder()
+            .key(key)
+            .value(value)
+            .created(created)
+            .updated(updated)
+            .build()
+            .unwrap_or_else(|err| panic!("Row::new - {}", err))
+    }
+
+    /// Creates a [`RowBuilder`] for constructing a `Row` one field at a time.
+    pub fn builder() -> RowBuilder {
+        RowBuilder::default()
     }
 
     /// Creates a new Row with the given `key` and `value`, setting `created`
     /// and `updated` to the current time. Use `Row::new` to create a row with
     /// full control over the `created` and `updated` fields.
-    pub fn create<Key: AsRef<str>, Value: AsRef<str>>(key: Key, value: Value) -> Self {
-        let now = super::create_now();
-        Self {
-            key: key.as_ref().to_string(),
-            value: value.as_ref().to_string(),
-            created: now,
-            updated: now,
-        }
+    ///
+    /// Panics if `key` is not valid according to [`super::validate_key`].
+    pub fn create<Key: AsRef<str>, Val: AsRef<str>>(key: Key, value: Val) -> Self {
+        Self::create_typed(key, Value::from(value.as_ref()))
     }
 
-    /// Updates the `value` of this `Row` and sets `updated` to the current
-    /// timestamp.
-    pub fn update<Value: AsRef<str>>(&mut self, value: Value) {
+    /// Same as `Row::create`, but the row expires `ttl_secs` seconds from now.
+    /// An expiry too far away to represent saturates instead of overflowing.
+    pub fn create_with_ttl<Key: AsRef<str>, Val: AsRef<str>>(
+        key: Key,
+        value: Val,
+        ttl_secs: i64,
+    ) -> Self {
+        let mut row = Self::create(key, value);
+        row.expires_at = Some(row.created.saturating_add(ttl_secs.saturating_mul(1000)));
+        row
+    }
+
+    /// Same as `Row::create`, but for any kind of [`Value`].
+    pub fn create_typed<Key: AsRef<str>>(key: Key, value: Value) -> Self {
+        Self::builder()
+            .key(key)
+            .value(value)
+            .build()
+            .unwrap_or_else(|err| panic!("Row::create_typed - {}", err))
+    }
+
+    /// Updates the `value` of this `Row`, sets `updated` to the current
+    /// timestamp and increments `version`. Nothing changes if `value` is
+    /// already the current value.
+    ///
+    /// Returns whether the value was changed.
+    pub fn update<Val: AsRef<str>>(&mut self, value: Val) -> bool {
         let value = value.as_ref();
-        if value != &self.value {
-            self.value = value.to_string();
-            self.updated = super::create_now();
+        if *self.load_value() == *value {
+            return false;
         }
+        let now = super::create_now();
+        self.record_history(now);
+        self.set_value(Value::from(value));
+        self.updated = now;
+        self.version += 1;
+        self.recompute_checksum();
+        true
     }
 
-    /// Clears the `value` of this row and changes `updated` to the current timestamp.
+    /// Clears the `value` of this row, changes `updated` to the current timestamp
+    /// and increments `version`. The row still exists afterwards, use
+    /// `Row::tombstone` to mark it deleted.
     pub fn clear(&mut self) {
-        self.value = "".to_string();
-        self.updated = super::create_now();
+        let now = super::create_now();
+        self.record_history(now);
+        self.set_value(Value::default());
+        self.updated = now;
+        self.version += 1;
+        self.recompute_checksum();
+    }
+
+    /// Marks this row as deleted, replacing the value with [`Value::Null`].
+    /// Like `Row::clear` this changes `updated` and increments `version`, so
+    /// the delete can be ordered against other writes to the same key.
+    pub fn tombstone(&mut self) {
+        let now = super::create_now();
+        self.record_history(now);
+        self.set_value(Value::Null);
+        self.deleted = true;
+        self.updated = now;
+        self.version += 1;
+        self.recompute_checksum();
     }
 
     /// Updates the `updated` field to the current timestamp without changing
-    /// any other data.
+    /// any other data. `updated` always moves forward, by at least one
+    /// millisecond, even if the row was last updated within the same
+    /// millisecond (or the clock has gone backwards).
     pub fn touch(&mut self) {
+        self.updated = super::create_now().max(self.updated + 1);
+        self.recompute_checksum();
+    }
+
+    /// Moves this row to `key`, keeping its `created` timestamp and history but
+    /// bumping `updated` and `version`. Only stores can do this, since they key
+    /// their rows by `key`.
+    pub(crate) fn rename(&mut self, key: &str) {
+        self.key = Arc::from(key);
         self.updated = super::create_now();
+        self.version += 1;
+        self.recompute_checksum();
     }
 
     /// Overwrites all values in this [`Row`] with the values from `other`.
+    ///
+    /// The `version` is not copied, it becomes one more than the larger of the
+    /// two versions so that it never goes backwards for anyone watching this row.
     pub fn overwrite_with(&mut self, other: &Row) {
         self.key = other.key.clone();
         self.value = other.value.clone();
+        self.cached_value = other.cached_value.clone();
         self.created = other.created;
         self.updated = other.updated;
+        self.version = self.version.max(other.version) + 1;
+        self.expires_at = other.expires_at;
+        self.meta = other.meta.clone();
+        self.history = other.history.clone();
+        self.history_limit = other.history_limit;
+        self.deleted = other.deleted;
+        self.recompute_checksum();
+    }
+}
+
+/// Builder for [`Row`], created with `Row::builder`.
+///
+/// ## Example(s)
+/// ```rust
+/// # use stupid_db::Row;
+/// let row = Row::builder().key("key").value("value").created(1).build().unwrap();
+/// assert_eq!(row.key(), "key");
+/// assert_eq!(row.created(), 1);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RowBuilder {
+    key: Option<String>,
+    value: Value,
+    created: Option<i64>,
+    updated: Option<i64>,
+}
+
+impl RowBuilder {
+    /// Sets the `key` of the row. Required.
+    pub fn key<Key: AsRef<str>>(mut self, key: Key) -> Self {
+        self.key = Some(key.as_ref().to_string());
+        self
+    }
+
+    /// Sets the `value` of the row. Defaults to an empty string.
+    pub fn value<Val: Into<Value>>(mut self, value: Val) -> Self {
+        self.value = value.into();
+        self
+    }
+
+    /// Sets the `created` timestamp of the row. Defaults to the current time.
+    pub fn created(mut self, created: i64) -> Self {
+        self.created = Some(created);
+        self
+    }
+
+    /// Sets the `updated` timestamp of the row. Defaults to the current time.
+    pub fn updated(mut self, updated: i64) -> Self {
+        self.updated = Some(updated);
+        self
+    }
+
+    /// Builds the `Row`, failing with [`crate::Error::InvalidRow`] if no key
+    /// was given or [`crate::Error::InvalidKey`] if it isn't valid.
+    pub fn build(self) -> crate::Result<Row> {
+        let key = self
+            .key
+            .ok_or_else(|| crate::Error::invalid_row("a key is required"))?;
+        super::validate_key(&key)?;
+        let now = super::create_now();
+        let mut row = Row {
+            key: key.into(),
+            value: self.value.into(),
+            cached_value: ValueCache::default(),
+            created: self.created.unwrap_or(now),
+            updated: self.updated.unwrap_or(now),
+            version: Row::initial_version(),
+            expires_at: None,
+            meta: BTreeMap::new(),
+            history: Vec::new(),
+            history_limit: 0,
+            checksum: Row::NO_CHECKSUM,
+            deleted: false,
+        };
+        row.recompute_checksum();
+        Ok(row)
+    }
+}
+
+impl Row {
+    /// Merges `other`, which should be another version of the same key, into
+    /// this `Row` using `strategy`. Ties on `updated` always keep this row so
+    /// the result is deterministic.
+    pub fn merge(&mut self, other: &Row, strategy: MergeStrategy) -> MergeResult {
+        debug_assert_eq!(self.key, other.key, "Row::merge - keys should match");
+        let take_other = match strategy {
+            MergeStrategy::LastWriteWins => other.updated > self.updated,
+            MergeStrategy::FirstWriteWins => other.updated < self.updated,
+            MergeStrategy::PreferOther => true,
+        };
+        let created = match strategy {
+            MergeStrategy::LastWriteWins => self.created.min(other.created),
+            _ if take_other => other.created,
+            _ => self.created,
+        };
+
+        let mut result = MergeResult::Unchanged;
+        if take_other && !self.same_data(other) {
+            self.overwrite_with(other);
+            result = MergeResult::Modified;
+        }
+        if self.created != created {
+            self.created = created;
+            self.recompute_checksum();
+            result = MergeResult::Modified;
+        }
+        result
+    }
+
+    /// Checks whether this `Row` has the same key and value as `other`,
+    /// ignoring timestamps and every other bit of bookkeeping.
+    pub fn content_eq(&self, other: &Row) -> bool {
+        self.same_key(other) && self.value == other.value
+    }
+
+    /// Orders rows by their `created` timestamp, then by key. Meant to be
+    /// passed to `sort_by`.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_db::Row;
+    /// let mut rows = vec![Row::new("a", "1", 20, 20), Row::new("b", "2", 10, 30)];
+    /// rows.sort_by(Row::cmp_by_created);
+    /// assert_eq!(rows[0].key(), "b");
+    /// ```
+    pub fn cmp_by_created(a: &Row, b: &Row) -> Ordering {
+        a.created.cmp(&b.created).then_with(|| a.cmp(b))
+    }
+
+    /// Orders rows by their `updated` timestamp, then by key. Meant to be
+    /// passed to `sort_by`.
+    pub fn cmp_by_updated(a: &Row, b: &Row) -> Ordering {
+        a.updated.cmp(&b.updated).then_with(|| a.cmp(b))
+    }
+
+    /// Checks whether this `Row` has the same key as `other`.
+    pub fn same_key(&self, other: &Row) -> bool {
+        self.key == other.key
+    }
+
+    /// Lists every field that differs between this `Row` and `other`, along
+    /// with the value on each side. Empty if the rows are equal.
+    pub fn diff(&self, other: &Row) -> Vec<FieldDiff> {
+        let mut diffs = Vec::new();
+        let mut check = |field: &'static str, left: String, right: String| {
+            if left != right {
+                diffs.push(FieldDiff { field, left, right });
+            }
+        };
+        check("key", format!("{:?}", self.key), format!("{:?}", other.key));
+        check(
+            "value",
+            format!("{:?}", self.load_value()),
+            format!("{:?}", other.load_value()),
+        );
+        check(
+            "created",
+            self.created.to_string(),
+            other.created.to_string(),
+        );
+        check(
+            "updated",
+            self.updated.to_string(),
+            other.updated.to_string(),
+        );
+        check(
+            "version",
+            self.version.to_string(),
+            other.version.to_string(),
+        );
+        check(
+            "expires_at",
+            format!("{:?}", self.expires_at),
+            format!("{:?}", other.expires_at),
+        );
+        check(
+            "meta",
+            format!("{:?}", self.meta),
+            format!("{:?}", other.meta),
+        );
+        check(
+            "history",
+            format!("{:?}", self.history),
+            format!("{:?}", other.history),
+        );
+        check(
+            "history_limit",
+            self.history_limit.to_string(),
+            other.history_limit.to_string(),
+        );
+        check(
+            "checksum",
+            self.checksum.to_string(),
+            other.checksum.to_string(),
+        );
+        check(
+            "deleted",
+            self.deleted.to_string(),
+            other.deleted.to_string(),
+        );
+        diffs
+    }
+
+    /// Renders this `Row` with its timestamps for debugging, like
+    /// `key = "foo", value = "bar", created = 2022-05-01T12:00:00Z, updated = 2022-05-01T12:00:00Z`.
+    /// String values are quoted, and timestamps that can't be represented as a
+    /// date are printed as raw milliseconds. This is also what `{:#}` prints.
+    pub fn to_pretty_string(&self) -> String {
+        let value = match &*self.load_value() {
+            Value::Str(s) => format!("{:?}", s),
+            value => value.to_string(),
+        };
+        format!(
+            "key = {:?}, value = {}, created = {}, updated = {}",
+            self.key,
+            value,
+            format_timestamp(self.created),
+            format_timestamp(self.updated)
+        )
+    }
+
+    /// Same as the `Display` output (`key:value`), but values longer than
+    /// `max_value_len` bytes are cut short with an ellipsis followed by the
+    /// size of the full value.
+    pub fn summary(&self, max_value_len: usize) -> String {
+        let full = self.load_value();
+        let value = full.to_string();
+        if value.len() <= max_value_len {
+            return format!("{}:{}", self.key, value);
+        }
+        let mut end = max_value_len;
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+        format!(
+            "{}:{}… ({} bytes)",
+            self.key,
+            &value[..end],
+            full.size_bytes()
+        )
+    }
+
+    /// Encodes this `Row` as a protobuf [`crate::rpc::RowData`] message. Only
+    /// the fields `RowData` has are kept, and the value is stored as its
+    /// display string.
+    pub fn to_proto_bytes(&self) -> crate::Result<Vec<u8>> {
+        let data = crate::rpc::RowData::from(self.clone());
+        let mut bytes = Vec::with_capacity(data.encoded_len());
+        data.encode(&mut bytes)
+            .map_err(|err| crate::Error::proto_encode(&err))?;
+        Ok(bytes)
+    }
+
+    /// Decodes a `Row` previously encoded with `Row::to_proto_bytes`.
+    pub fn from_proto_bytes(bytes: &[u8]) -> crate::Result<Self> {
+        crate::rpc::RowData::decode(bytes)
+            .map(Row::from)
+            .map_err(|err| crate::Error::proto_decode(&err))
+    }
+
+    pub(crate) fn same_data(&self, other: &Row) -> bool {
+        self.same_data_untimed(other)
+            && self.created == other.created
+            && self.updated == other.updated
+            && self.expires_at == other.expires_at
+    }
+
+    /// Same as `same_data`, without comparing `created`, `updated` or
+    /// `expires_at`.
+    pub(crate) fn same_data_untimed(&self, other: &Row) -> bool {
+        self.value == other.value && self.meta == other.meta && self.deleted == other.deleted
+    }
+}
+
+impl Ord for Row {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
+impl PartialOrd for Row {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
@@ -99,28 +1050,50 @@ impl std::hash::Hash for Row {
 
 impl std::fmt::Display for Row {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}:{}", self.key, self.value)
+        if f.alternate() {
+            f.write_str(&self.to_pretty_string())
+        } else {
+            write!(f, "{}:{}", self.key, self.load_value())
+        }
     }
 }
 
+fn format_timestamp(millis: i64) -> String {
+    super::reverse_timestamp(millis)
+        .ok()
+        .and_then(|at| at.format(&Rfc3339).ok())
+        .unwrap_or_else(|| millis.to_string())
+}
+
 impl From<crate::rpc::RowData> for Row {
     fn from(data: crate::rpc::RowData) -> Self {
-        Self {
-            key: data.key,
-            value: data.value,
+        let mut row = Self {
+            key: data.key.into(),
+            value: Value::Str(data.value).into(),
+            cached_value: ValueCache::default(),
             created: data.created,
             updated: data.updated,
-        }
+            version: Self::initial_version(),
+            expires_at: None,
+            meta: data.meta.into_iter().collect(),
+            history: Vec::new(),
+            history_limit: 0,
+            checksum: Self::NO_CHECKSUM,
+            deleted: false,
+        };
+        row.recompute_checksum();
+        row
     }
 }
 
 impl From<Row> for crate::rpc::RowData {
     fn from(row: Row) -> Self {
         Self {
-            key: row.key,
-            value: row.value,
+            key: row.key.to_string(),
+            value: row.load_value().to_display_string(),
             created: row.created,
             updated: row.updated,
+            meta: row.meta.into_iter().collect(),
         }
     }
 }
@@ -130,16 +1103,17 @@ mod tests {
     use super::super::create_now;
     use super::*;
     use pretty_assertions::{assert_eq, assert_str_eq};
-    use utils::assert_within;
+    use utils::asserts::Millis;
+    use utils::{assert_err, assert_time_close, assert_within};
 
     #[test]
     fn new() {
         let now = create_now();
         let row = Row::new("key", "value", now, now);
         assert_str_eq!(row.key(), "key");
-        assert_str_eq!(row.value(), "value");
-        assert_within!(row.created(), now - 1, now + 1);
-        assert_within!(row.updated(), now - 1, now + 1);
+        assert_eq!(row.value(), "value");
+        assert_eq!(row.created(), now);
+        assert_eq!(row.updated(), now);
     }
 
     #[test]
@@ -147,8 +1121,674 @@ mod tests {
         let now = create_now();
         let row = Row::create("key", "value");
         assert_str_eq!(row.key(), "key");
-        assert_str_eq!(row.value(), "value");
-        assert_within!(row.created(), now - 1, now + 1);
-        assert_within!(row.updated(), now - 1, now + 1);
+        assert_eq!(row.value(), "value");
+        assert_time_close!(row.created(), now, Millis(1_000));
+        assert_eq!(row.updated(), row.created());
+    }
+
+    #[test]
+    fn create_typed() {
+        let row = Row::create_typed("key", Value::Int(5));
+        assert_eq!(row.value().as_int(), Some(5));
+        assert_eq!(row.value().as_str(), None);
+        assert_eq!(row.value().to_display_string(), "5");
+    }
+
+    #[test]
+    fn value_display() {
+        assert_eq!(Value::from("text").to_display_string(), "text");
+        assert_eq!(Value::Int(-3).to_display_string(), "-3");
+        assert_eq!(Value::Float(1.5).to_display_string(), "1.5");
+        assert_eq!(Value::Bool(true).to_display_string(), "true");
+        assert_eq!(
+            Value::Bytes(vec![0, 171, 16]).to_display_string(),
+            "0x00ab10"
+        );
+        assert_eq!(Value::Null.to_display_string(), "null");
+    }
+
+    #[test]
+    fn value_serde_roundtrip() {
+        let values = [
+            Value::Str("value".to_string()),
+            Value::Str("Null".to_string()),
+            Value::Int(i64::MIN),
+            Value::Float(-0.25),
+            Value::Bool(false),
+            Value::Bytes(vec![1, 2, 3]),
+            Value::Bytes(Vec::new()),
+            Value::Null,
+        ];
+        for value in values {
+            let json = serde_json::to_string(&value).expect("unable to serialize value");
+            let back: Value = serde_json::from_str(&json).expect("unable to deserialize value");
+            assert_eq!(back, value, "json was {}", json);
+        }
+        assert_eq!(
+            serde_json::to_string(&Value::Int(5)).unwrap(),
+            r#"{"type":"Int","value":5}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&Value::Null).unwrap(),
+            r#"{"type":"Null"}"#
+        );
+    }
+
+    #[test]
+    fn row_serde_roundtrip() {
+        let row = Row::create_typed("key", Value::Bytes(vec![7, 8]));
+        let json = serde_json::to_string(&row).expect("unable to serialize row");
+        let back: Row = serde_json::from_str(&json).expect("unable to deserialize row");
+        assert_eq!(back, row);
+    }
+
+    #[test]
+    fn row_deserializes_v1_string_values() {
+        let json = r#"{"key":"key","value":"value","created":1,"updated":2}"#;
+        let row: Row = serde_json::from_str(json).expect("unable to deserialize v1 row");
+        assert_eq!((row.key(), row.created(), row.updated()), ("key", 1, 2));
+        assert_eq!(row.value(), &Value::Str("value".to_string()));
+
+        assert_err!(serde_json::from_str::<Row>(
+            r#"{"key":"key","value":5,"created":1,"updated":2}"#
+        ));
+    }
+
+    #[test]
+    fn float_values_are_totally_ordered() {
+        assert_eq!(Value::Float(f64::NAN), Value::Float(f64::NAN));
+        assert!(Value::Float(-1.0) < Value::Float(1.0));
+        assert!(Value::Str("z".to_string()) < Value::Int(0));
+    }
+
+    #[test]
+    fn version_tracks_changes() {
+        let mut row = Row::create("key", "value");
+        assert_eq!(row.version(), 1);
+        assert!(row.update("value2"));
+        assert_eq!(row.version(), 2);
+        assert!(!row.update("value2"));
+        assert_eq!(
+            row.version(),
+            2,
+            "unchanged value should not bump the version"
+        );
+        row.clear();
+        assert_eq!(row.version(), 3);
+        let updated = row.updated();
+        row.touch();
+        assert_eq!(row.version(), 3);
+        assert!(row.updated() > updated);
+
+        let mut other = Row::create("key", "other");
+        other.update("other2");
+        other.update("other3");
+        other.update("other4");
+        assert_eq!(other.version(), 4);
+        row.overwrite_with(&other);
+        assert_eq!(row.value(), "other4");
+        assert_eq!(row.version(), 5);
+        other.overwrite_with(&row);
+        assert_eq!(other.version(), 6);
+    }
+
+    #[test]
+    fn version_defaults_when_missing() {
+        let json = r#"{"key":"key","value":"value","created":1,"updated":2}"#;
+        let row: Row = serde_json::from_str(json).expect("unable to deserialize row");
+        assert_eq!(row.version(), 1);
+
+        let mut row = row;
+        row.update("value2");
+        let json = serde_json::to_string(&row).expect("unable to serialize row");
+        let back: Row = serde_json::from_str(&json).expect("unable to deserialize row");
+        assert_eq!(back.version(), 2);
+    }
+
+    #[test]
+    fn ttl() {
+        let row = Row::create("key", "value");
+        assert_eq!(row.expires_at(), None);
+        assert!(!row.is_expired());
+
+        let row = Row::create_with_ttl("key", "value", 0);
+        assert_eq!(row.expires_at(), Some(row.created()));
+        assert!(row.is_expired());
+
+        let row = Row::create_with_ttl("key", "value", 3600);
+        assert_eq!(row.expires_at(), Some(row.created() + 3_600_000));
+        assert!(!row.is_expired());
+
+        let row = Row::create_with_ttl("key", "value", i64::MAX);
+        assert_eq!(row.expires_at(), Some(i64::MAX));
+        assert!(!row.is_expired());
+    }
+
+    #[test]
+    fn ttl_serde() {
+        let row = Row::create("key", "value");
+        let json = serde_json::to_string(&row).expect("unable to serialize row");
+        assert!(!json.contains("expires_at"), "json was {}", json);
+
+        let row = Row::create_with_ttl("key", "value", 3600);
+        let json = serde_json::to_string(&row).expect("unable to serialize row");
+        let back: Row = serde_json::from_str(&json).expect("unable to deserialize row");
+        assert_eq!(back.expires_at(), row.expires_at());
+    }
+
+    #[test]
+    fn meta() {
+        let mut row = Row::create("key", "value");
+        assert!(row.meta().is_empty());
+        assert_eq!(row.set_meta("source", "import"), None);
+        assert_eq!(row.set_meta("content-type", "text"), None);
+        assert_eq!(
+            row.set_meta("content-type", "json"),
+            Some("text".to_string())
+        );
+        assert_eq!(row.get_meta("content-type"), Some("json"));
+        assert_eq!(row.get_meta("missing"), None);
+
+        row.update("value2");
+        assert_eq!(row.meta().len(), 2, "update should keep metadata");
+
+        let mut other = Row::create("key", "other");
+        other.overwrite_with(&row);
+        assert_eq!(other.meta(), row.meta());
+
+        assert_eq!(row.remove_meta("source"), Some("import".to_string()));
+        assert_eq!(row.remove_meta("source"), None);
+        assert_eq!(row.meta().len(), 1);
+    }
+
+    #[test]
+    fn meta_serde() {
+        let json = r#"{"key":"key","value":"value","created":1,"updated":2}"#;
+        let row: Row = serde_json::from_str(json).expect("unable to deserialize row");
+        assert!(row.meta().is_empty());
+        let json = serde_json::to_string(&row).expect("unable to serialize row");
+        assert!(!json.contains("meta"), "json was {}", json);
+
+        let mut row = Row::create("key", "value");
+        row.set_meta("source", "import");
+        let json = serde_json::to_string(&row).expect("unable to serialize row");
+        let back: Row = serde_json::from_str(&json).expect("unable to deserialize row");
+        assert_eq!(back.get_meta("source"), Some("import"));
+
+        let disk = crate::RowDiskRepr::from(&row);
+        assert_eq!(Row::from(disk), row);
+
+        let data = crate::rpc::RowData::from(row.clone());
+        assert_eq!(data.meta.get("source").map(String::as_str), Some("import"));
+        assert_eq!(Row::from(data).meta(), row.meta());
+    }
+
+    #[test]
+    fn history_disabled_by_default() {
+        let mut row = Row::create("key", "value1");
+        row.update("value2");
+        row.clear();
+        assert!(row.history().is_empty());
+        assert_eq!(row.previous_value(), None);
+    }
+
+    #[test]
+    fn history() {
+        let mut row = Row::create("key", "value1").with_history_limit(3);
+        row.update("value2");
+        assert_eq!(row.previous_value(), Some(&Value::from("value1")));
+        row.update("value2");
+        assert_eq!(
+            row.history().len(),
+            1,
+            "unchanged value should not be recorded"
+        );
+        row.clear();
+        row.update("value3");
+        row.update("value4");
+
+        let values = row
+            .history()
+            .iter()
+            .map(|(value, _)| value.to_display_string())
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec!["value2", "", "value3"]);
+        assert_eq!(row.previous_value(), Some(&Value::from("value3")));
+        for (_, replaced) in row.history() {
+            assert_within!(*replaced, row.created(), row.updated());
+        }
+
+        row.set_history_limit(1);
+        assert_eq!(row.history().len(), 1);
+        assert_eq!(row.previous_value(), Some(&Value::from("value3")));
+        row.set_history_limit(0);
+        assert!(row.history().is_empty());
+    }
+
+    #[test]
+    fn size_bytes() {
+        let overhead = std::mem::size_of::<Row>();
+        assert_eq!(Row::create("k", "").size_bytes(), overhead + 1);
+        assert_eq!(Row::create("key", "value").size_bytes(), overhead + 8);
+        assert_eq!(
+            Row::create_typed("key", Value::Int(5)).size_bytes(),
+            overhead + 3 + 8
+        );
+        assert_eq!(Value::Bytes(vec![0; 16]).size_bytes(), 16);
+        assert_eq!(Value::Null.size_bytes(), 0);
+    }
+
+    #[test]
+    fn checksum() {
+        let mut row = Row::create("key", "value");
+        assert_ne!(row.checksum(), Row::NO_CHECKSUM);
+        assert!(row.verify_checksum());
+
+        let before = row.checksum();
+        row.update("value2");
+        assert_ne!(row.checksum(), before);
+        assert!(row.verify_checksum());
+        row.clear();
+        assert!(row.verify_checksum());
+        row.overwrite_with(&Row::new("key", "other", 1, 2));
+        assert!(row.verify_checksum());
+        assert_eq!(row.checksum(), Row::new("key", "other", 1, 2).checksum());
+
+        row.value = Value::from("tampered").into();
+        assert!(!row.verify_checksum());
+        row.recompute_checksum();
+        assert!(row.verify_checksum());
+    }
+
+    #[test]
+    fn checksum_defaults_when_missing() {
+        let json = r#"{"key":"key","value":"value","created":1,"updated":2}"#;
+        let row: Row = serde_json::from_str(json).expect("unable to deserialize row");
+        assert_eq!(row.checksum(), Row::NO_CHECKSUM);
+        assert!(row.verify_checksum());
+    }
+
+    #[test]
+    fn builder_defaults() {
+        let now = create_now();
+        let row = Row::builder()
+            .key("key")
+            .build()
+            .expect("unable to build row");
+        assert_eq!(row.key(), "key");
+        assert_eq!(row.value(), "");
+        assert_eq!(row.version(), 1);
+        assert_time_close!(row.created(), now, Millis(1_000));
+        assert_eq!(row.updated(), row.created());
+        assert!(row.verify_checksum());
+    }
+
+    #[test]
+    fn builder_explicit() {
+        let row = Row::builder()
+            .key("key")
+            .value(Value::Int(5))
+            .created(1)
+            .updated(2)
+            .build()
+            .expect("unable to build row");
+        assert_eq!(row.value().as_int(), Some(5));
+        assert_eq!(row.created(), 1);
+        assert_eq!(row.updated(), 2);
+
+        let row = Row::builder()
+            .value("value")
+            .created(1)
+            .updated(2)
+            .key("key");
+        assert_eq!(row.build(), Ok(Row::new("key", "value", 1, 2)));
+    }
+
+    #[test]
+    fn builder_requires_key() {
+        assert_eq!(
+            Row::builder().value("value").build(),
+            Err(crate::Error::invalid_row("a key is required"))
+        );
+    }
+
+    #[test]
+    fn builder_reuse() {
+        let template = Row::builder().value("shared").created(1).updated(1);
+        let first = template.clone().key("key1").build().unwrap();
+        let second = template.key("key2").build().unwrap();
+        assert_eq!(first.key(), "key1");
+        assert_eq!(second.key(), "key2");
+        assert_eq!(first.value(), second.value());
+        assert_eq!(first.created(), second.created());
+    }
+
+    #[test]
+    fn millisecond_timestamps() {
+        let mut row = Row::create("key", "value0");
+        let mut last = row.updated();
+        for i in 1..=1000 {
+            assert!(row.update(format!("value{}", i)));
+            assert!(
+                row.updated() >= last,
+                "updated went backwards: {} -> {}",
+                last,
+                row.updated()
+            );
+            last = row.updated();
+        }
+
+        let row = Row::new("key", "value", 1_650_000_000_123, 1_650_000_001_456);
+        let created_at = row.created_at().expect("created should be valid");
+        assert_eq!(created_at.unix_timestamp(), 1_650_000_000);
+        assert_eq!(created_at.millisecond(), 123);
+        assert_eq!(row.updated_at().map(|at| at.millisecond()), Ok(456));
+        assert_eq!(
+            super::super::reverse_timestamp(row.created()),
+            Ok(created_at)
+        );
+    }
+
+    #[test]
+    fn validate_timestamps() {
+        assert_eq!(Row::create("key", "value").validate_timestamps(), Ok(()));
+        assert_eq!(
+            Row::new("key", "value", -1_000, 0).validate_timestamps(),
+            Ok(())
+        );
+        assert_eq!(
+            Row::new("key", "value", 0, i64::MAX).validate_timestamps(),
+            Err(crate::Error::InvalidTimestamp(i64::MAX))
+        );
+        assert_eq!(
+            Row::new("key", "value", i64::MIN, 0).validate_timestamps(),
+            Err(crate::Error::InvalidTimestamp(i64::MIN))
+        );
+        assert!(matches!(
+            Row::new("key", "value", 2, 1).validate_timestamps(),
+            Err(crate::Error::InvalidRow(_))
+        ));
+    }
+
+    #[test]
+    fn reverse_timestamp() {
+        use super::super::reverse_timestamp;
+        assert_eq!(
+            reverse_timestamp(i64::MAX),
+            Err(crate::Error::InvalidTimestamp(i64::MAX))
+        );
+        let before_epoch = reverse_timestamp(-86_400_000).expect("pre-epoch should be valid");
+        assert_eq!(before_epoch.year(), 1969);
+        assert_eq!(
+            Row::new("key", "value", i64::MIN, 0).created_at(),
+            Err(crate::Error::InvalidTimestamp(i64::MIN))
+        );
+    }
+
+    #[test]
+    fn merge_last_write_wins() {
+        let mut row = Row::new("key", "mine", 10, 20);
+        let other = Row::new("key", "theirs", 5, 30);
+        assert_eq!(
+            row.merge(&other, MergeStrategy::LastWriteWins),
+            MergeResult::Modified
+        );
+        assert_eq!(row.value(), "theirs");
+        assert_eq!((row.created(), row.updated()), (5, 30));
+
+        let mut row = Row::new("key", "mine", 1, 40);
+        assert_eq!(
+            row.merge(&other, MergeStrategy::LastWriteWins),
+            MergeResult::Unchanged
+        );
+        assert_eq!(row.value(), "mine");
+
+        let mut row = Row::new("key", "mine", 10, 40);
+        assert_eq!(
+            row.merge(&other, MergeStrategy::LastWriteWins),
+            MergeResult::Modified
+        );
+        assert_eq!(row.value(), "mine");
+        assert_eq!(
+            row.created(),
+            5,
+            "created should be the minimum of both rows"
+        );
+        assert!(row.verify_checksum());
+    }
+
+    #[test]
+    fn merge_ties_keep_self() {
+        let other = Row::new("key", "theirs", 1, 20);
+        for strategy in [MergeStrategy::LastWriteWins, MergeStrategy::FirstWriteWins] {
+            let mut row = Row::new("key", "mine", 1, 20);
+            assert_eq!(row.merge(&other, strategy), MergeResult::Unchanged);
+            assert_eq!(row.value(), "mine");
+        }
+    }
+
+    #[test]
+    fn merge_first_write_wins_and_prefer_other() {
+        let older = Row::new("key", "older", 1, 10);
+        let mut row = Row::new("key", "newer", 1, 20);
+        assert_eq!(
+            row.merge(&older, MergeStrategy::FirstWriteWins),
+            MergeResult::Modified
+        );
+        assert_eq!(row.value(), "older");
+
+        let newer = Row::new("key", "newer", 1, 20);
+        assert_eq!(
+            row.merge(&newer, MergeStrategy::FirstWriteWins),
+            MergeResult::Unchanged
+        );
+        assert_eq!(
+            row.merge(&newer, MergeStrategy::PreferOther),
+            MergeResult::Modified
+        );
+        assert_eq!(row.value(), "newer");
+        assert_eq!(
+            row.merge(&newer, MergeStrategy::PreferOther),
+            MergeResult::Unchanged
+        );
+    }
+
+    #[test]
+    fn content_eq() {
+        let row = Row::new("key", "value", 1, 2);
+        assert!(row.content_eq(&Row::new("key", "value", 3, 4)));
+        assert!(row.content_eq(&Row::create("key", "value")));
+        assert!(!row.content_eq(&Row::new("key", "other", 1, 2)));
+        assert!(!row.content_eq(&Row::new("other", "value", 1, 2)));
+        assert!(row.same_key(&Row::new("key", "other", 3, 4)));
+        assert!(!row.same_key(&Row::new("other", "value", 1, 2)));
+    }
+
+    #[test]
+    fn diff() {
+        let row = Row::new("key", "value", 1, 2);
+        assert!(row.diff(&row.clone()).is_empty());
+
+        let other = Row::new("key", "value", 1, 3);
+        assert!(row.content_eq(&other));
+        let diff = row.diff(&other);
+        let fields = diff.iter().map(|d| d.field).collect::<Vec<_>>();
+        assert_eq!(fields, vec!["updated", "checksum"]);
+        assert_eq!(diff[0].to_string(), "updated: 2 != 3");
+
+        let mut other = Row::new("other", "value2", 1, 2);
+        other.set_meta("source", "import");
+        let fields = row
+            .diff(&other)
+            .into_iter()
+            .map(|d| d.field)
+            .collect::<Vec<_>>();
+        assert_eq!(fields, vec!["key", "value", "meta", "checksum"]);
+    }
+
+    #[test]
+    fn proto_roundtrip() {
+        let mut row = Row::new("key", "value", 1, 2);
+        row.set_meta("source", "import");
+        let bytes = row.to_proto_bytes().expect("unable to encode row");
+        let back = Row::from_proto_bytes(&bytes).expect("unable to decode row");
+        assert!(back.diff(&row).is_empty(), "{:?}", back.diff(&row));
+
+        let typed = Row::create_typed("key", Value::Int(5));
+        let back = Row::from_proto_bytes(&typed.to_proto_bytes().unwrap()).unwrap();
+        assert_eq!(back.value(), "5");
+
+        assert!(matches!(
+            Row::from_proto_bytes(&[0xff, 0xff, 0xff]),
+            Err(crate::Error::ProtoDecode(_))
+        ));
+    }
+
+    #[test]
+    fn builder_validates_key() {
+        assert!(matches!(
+            Row::builder().key("").build(),
+            Err(crate::Error::InvalidKey { .. })
+        ));
+        assert!(matches!(
+            Row::builder().key("a\nb").build(),
+            Err(crate::Error::InvalidKey { .. })
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "key must not be empty")]
+    fn create_panics_on_invalid_key() {
+        Row::create("", "value");
+    }
+
+    #[test]
+    fn tombstone() {
+        let mut row = Row::create("key", "value");
+        assert!(row.is_live());
+        row.tombstone();
+        assert!(row.is_deleted());
+        assert!(!row.is_live());
+        assert!(row.value().is_null());
+        assert_eq!(row.version(), 2);
+        assert!(row.verify_checksum());
+
+        let json = serde_json::to_string(&row).expect("unable to serialize row");
+        let back: Row = serde_json::from_str(&json).expect("unable to deserialize row");
+        assert!(back.is_deleted());
+        let live = serde_json::to_string(&Row::create("key", "value")).unwrap();
+        assert!(!live.contains("deleted"));
+    }
+
+    #[test]
+    fn pretty_string() {
+        let row = Row::new("foo", "bar", 1_651_406_400_000, 1_651_406_401_500);
+        let expected = r#"key = "foo", value = "bar", created = 2022-05-01T12:00:00Z, updated = 2022-05-01T12:00:01.5Z"#;
+        assert_eq!(row.to_pretty_string(), expected);
+        assert_eq!(format!("{:#}", row), expected);
+        assert_eq!(format!("{}", row), "foo:bar");
+
+        let row = Row::builder()
+            .key("num")
+            .value(5)
+            .created(i64::MAX)
+            .updated(0)
+            .build()
+            .unwrap();
+        assert_eq!(
+            row.to_pretty_string(),
+            format!(
+                "key = \"num\", value = 5, created = {}, updated = 1970-01-01T00:00:00Z",
+                i64::MAX
+            )
+        );
+    }
+
+    #[test]
+    fn summary() {
+        let row = Row::create("key", "value");
+        assert_eq!(row.summary(5), "key:value");
+        assert_eq!(row.summary(3), "key:val… (5 bytes)");
+        assert_eq!(row.summary(0), "key:… (5 bytes)");
+
+        let row = Row::create("key", "日本語");
+        assert_eq!(row.summary(4), "key:日… (9 bytes)");
+
+        let row = Row::create("big", "v".repeat(1 << 20));
+        assert_eq!(row.summary(4), "big:vvvv… (1048576 bytes)");
+    }
+
+    #[test]
+    fn parse_value() {
+        assert_eq!(Row::create("key", "-12").parse_value::<i64>(), Ok(-12));
+        assert_eq!(Row::create("key", "2.5").parse_value::<f64>(), Ok(2.5));
+        assert_eq!(Row::create("key", "true").parse_value::<bool>(), Ok(true));
+        let row = Row::create_typed("key", Value::Int(7));
+        assert_eq!(row.parse_value::<u8>(), Ok(7));
+
+        let err = Row::create("key", "twelve")
+            .parse_value::<i64>()
+            .unwrap_err();
+        assert_eq!(
+            err,
+            crate::Error::ValueParse {
+                key: "key".to_string(),
+                target_type: "i64".to_string(),
+                source_msg: "invalid digit found in string".to_string(),
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "unable to parse value of key 'key' as i64: invalid digit found in string"
+        );
+    }
+
+    #[test]
+    fn ordering_uses_key_only() {
+        let a = Row::new("a", "zzz", 30, 30);
+        let b = Row::new("b", "aaa", 10, 10);
+        let other_a = Row::new("a", "aaa", 1, 2);
+        assert!(a < b);
+        assert_eq!(a.cmp(&other_a), Ordering::Equal);
+        assert_ne!(a, other_a);
+
+        let mut rows = vec![b.clone(), a.clone(), Row::new("c", "", 20, 5)];
+        rows.sort();
+        let keys = |rows: &[Row]| rows.iter().map(|r| r.key().to_string()).collect::<Vec<_>>();
+        assert_eq!(keys(&rows), vec!["a", "b", "c"]);
+        rows.sort_by(Row::cmp_by_created);
+        assert_eq!(keys(&rows), vec!["b", "c", "a"]);
+        rows.sort_by(Row::cmp_by_updated);
+        assert_eq!(keys(&rows), vec!["c", "b", "a"]);
+
+        let mut set = std::collections::BTreeSet::new();
+        assert!(set.insert(a));
+        assert!(
+            !set.insert(other_a),
+            "same key should already be in the set"
+        );
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compressed_values() {
+        let value = "abc".repeat(100);
+        let plain = Row::create("key", &value);
+        let mut row = plain.clone();
+        assert!(!row.compress(1000), "values under the threshold stay plain");
+        assert!(row.compress(100));
+        assert!(!row.compress(100));
+        assert!(row.is_compressed());
+        assert!(row.size_bytes() < plain.size_bytes());
+        assert_eq!(row, plain);
+        assert_eq!(row.checksum(), plain.checksum());
+        assert_eq!(row.to_string(), plain.to_string());
+        assert_eq!(row.value(), value.as_str());
+
+        let mut row = Row::create_typed("key", Value::Int(5));
+        assert!(!row.compress(0), "only strings are compressed");
+
+        let json = r#"{"key":"key","value":{"type":"Lz4","value":{"bytes":[1,2,3],"original_len":300}},"created":1,"updated":1}"#;
+        assert!(serde_json::from_str::<Row>(json).is_err());
     }
 }
@@ -0,0 +1,620 @@
+// Copyright (c) 2022 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    borrow::Borrow,
+    collections::{HashMap, HashSet},
+    hash::{Hash, Hasher},
+    sync::{Mutex, MutexGuard},
+};
+
+use super::{run_unpoisoned, upsert_base, CasOutcome, DeleteManyReport, StoreLimits};
+use crate::{Row, RowDiskRepr, SetOutcome, StoreDiskRepr};
+
+/// A `Row` that is compared by its key alone, so a set of them can be looked
+/// up by `&str`.
+///
+/// `Row`'s own `Hash` only uses the key, but its `Eq` compares every field,
+/// which is what the rest of the crate (and its tests) expect. Rather than
+/// change that, the set holds this wrapper.
+#[derive(Debug, Clone)]
+struct KeyedRow(Row);
+
+impl PartialEq for KeyedRow {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.key() == other.0.key()
+    }
+}
+
+impl Eq for KeyedRow {}
+
+impl Hash for KeyedRow {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Must hash exactly like the `str` it borrows as.
+        self.0.key().hash(state);
+    }
+}
+
+impl Borrow<str> for KeyedRow {
+    fn borrow(&self) -> &str {
+        self.0.key()
+    }
+}
+
+type Data = HashSet<KeyedRow>;
+
+/// A store holding its rows in a `HashSet`, keyed by the rows themselves.
+#[derive(Debug, Default)]
+pub struct SetStore {
+    data: Mutex<Data>,
+    limits: StoreLimits,
+}
+
+impl SetStore {
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty store that rejects keys longer than `max_key_bytes` and
+    /// values larger than `max_value_bytes`. Stores created any other way have
+    /// no limits.
+    pub fn with_limits(max_key_bytes: usize, max_value_bytes: usize) -> Self {
+        Self {
+            limits: StoreLimits::new(max_key_bytes, max_value_bytes),
+            ..Self::default()
+        }
+    }
+
+    fn lock(&self) -> crate::Result<MutexGuard<'_, Data>> {
+        self.data
+            .lock()
+            .map_err(|err| crate::Error::mutex_poisoned(&err))
+    }
+
+    fn is_live(data: &Data, key: &str) -> bool {
+        data.get(key).map_or(false, |row| row.0.is_live())
+    }
+
+    /// Gets a copy of the row for `key`. Expired rows are removed and reported
+    /// as not found, as are rows that have been deleted.
+    pub fn get_clone(&self, key: &str) -> crate::Result<Row> {
+        self.lock()
+            .and_then(|mut data| Self::get_locked(&mut data, key))
+    }
+
+    /// Gets a copy of the row for each of `keys` under a single lock, see
+    /// `KeyValueStore::get_many`.
+    pub fn get_many(&self, keys: &[&str]) -> crate::Result<Vec<(String, crate::Result<Row>)>> {
+        self.lock().map(|mut data| {
+            keys.iter()
+                .map(|&key| (key.to_string(), Self::get_locked(&mut data, key)))
+                .collect()
+        })
+    }
+
+    fn get_locked(data: &mut Data, key: &str) -> crate::Result<Row> {
+        if data.get(key).map_or(false, |row| row.0.is_expired()) {
+            data.remove(key);
+        }
+        data.get(key)
+            .filter(|row| !row.0.is_deleted())
+            .map(|row| row.0.clone())
+            .ok_or(crate::Error::key_not_found(key))
+    }
+
+    pub fn insert(&self, key: &str, value: &str) -> crate::Result<()> {
+        self.limits.check(key, value.len())?;
+        self.lock().and_then(|mut data| {
+            if Self::is_live(&data, key) {
+                Err(crate::Error::duplicate_key(key))
+            } else {
+                // `insert` would keep an expired or deleted row with this key.
+                data.replace(KeyedRow(Row::create(key, value)));
+                Ok(())
+            }
+        })
+    }
+
+    pub fn insert_row(&self, row: &Row) -> crate::Result<()> {
+        self.limits.check_row(row)?;
+        self.lock().and_then(|mut data| {
+            if Self::is_live(&data, row.key()) {
+                Err(crate::Error::duplicate_key(row.key()))
+            } else {
+                data.replace(KeyedRow(row.clone()));
+                Ok(())
+            }
+        })
+    }
+
+    /// Sets the value of `key`, inserting a new row if it doesn't exist (or has
+    /// expired or been deleted). Returns a copy of the row it replaced, or
+    /// `None` if it inserted a new one.
+    pub fn set_or_insert(&self, key: &str, value: &str) -> crate::Result<Option<Row>> {
+        self.limits.check(key, value.len())?;
+        self.lock().map(|mut data| {
+            let (row, previous) = match data.take(key) {
+                Some(KeyedRow(mut row)) if row.is_live() => {
+                    let previous = row.clone();
+                    row.update(value);
+                    (row, Some(previous))
+                }
+                _ => (Row::create(key, value), None),
+            };
+            data.insert(KeyedRow(row));
+            previous
+        })
+    }
+
+    pub fn set_or_insert_row(&self, row: &Row) -> crate::Result<Option<Row>> {
+        self.limits.check_row(row)?;
+        self.lock().map(|mut data| {
+            let (row, previous) = match data.take(row.key()) {
+                Some(KeyedRow(mut existing)) => {
+                    let previous = existing.is_live().then(|| existing.clone());
+                    existing.overwrite_with(row);
+                    (existing, previous)
+                }
+                None => (row.clone(), None),
+            };
+            data.insert(KeyedRow(row));
+            previous
+        })
+    }
+
+    pub fn contains(&self, key: &str) -> crate::Result<bool> {
+        self.lock().map(|data| Self::is_live(&data, key))
+    }
+
+    /// Gets the number of rows in this store, not counting deleted rows.
+    pub fn len(&self) -> crate::Result<usize> {
+        self.lock()
+            .map(|data| data.iter().filter(|row| !row.0.is_deleted()).count())
+    }
+
+    /// Removes every row from this store, returning how many there were (not
+    /// counting deleted rows, like `len`).
+    pub fn clear(&self) -> crate::Result<usize> {
+        self.lock()
+            .map(|mut data| data.drain().filter(|row| !row.0.is_deleted()).count())
+    }
+
+    /// Checks whether this store has no rows, not counting deleted rows.
+    pub fn is_empty(&self) -> crate::Result<bool> {
+        self.len().map(|len| len == 0)
+    }
+
+    pub fn delete(&self, key: &str) -> crate::Result<Row> {
+        self.lock().and_then(|mut data| {
+            data.take(key)
+                .map(|row| row.0)
+                .filter(|row| !row.is_deleted())
+                .ok_or(crate::Error::key_not_found(key))
+        })
+    }
+
+    /// Runs `f` on the live row for `key` and stores the result, returning a
+    /// copy of it. The store's mutex is held while `f` runs, so `f` must not
+    /// use the store: calling it from inside `f` deadlocks. Fails with
+    /// [`crate::Error::KeyNotFound`] if there is no live row, or if `f` changes
+    /// the key or breaks this store's limits, in which case nothing is stored.
+    pub fn update_with<F: FnOnce(&mut Row)>(&self, key: &str, f: F) -> crate::Result<Row> {
+        self.lock().and_then(|mut data| {
+            let row = self.limits.modify(Self::get_locked(&mut data, key)?, f)?;
+            data.replace(KeyedRow(row.clone()));
+            Ok(row)
+        })
+    }
+
+    /// Same as `update_with`, but when `key` has no live row `f` is run on the
+    /// row made by `default` (which must be for `key`) instead.
+    pub fn upsert_with<D, F>(&self, key: &str, default: D, f: F) -> crate::Result<Row>
+    where
+        D: FnOnce() -> Row,
+        F: FnOnce(&mut Row),
+    {
+        self.lock().and_then(|mut data| {
+            let row = upsert_base(Self::get_locked(&mut data, key).ok(), key, default)?;
+            let row = self.limits.modify(row, f)?;
+            data.replace(KeyedRow(row.clone()));
+            Ok(row)
+        })
+    }
+
+    /// Sets the value of `key` to `new` if it currently holds `expected`, all
+    /// under a single lock.
+    pub fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: &str,
+        new: &str,
+    ) -> crate::Result<CasOutcome> {
+        self.limits.check(key, new.len())?;
+        self.lock().map(|mut data| match data.take(key) {
+            Some(KeyedRow(mut row)) => {
+                let outcome = CasOutcome::swap(Some(&mut row), expected, new);
+                data.insert(KeyedRow(row));
+                outcome
+            }
+            None => CasOutcome::KeyMissing,
+        })
+    }
+
+    /// Deletes the row for `key` if it currently holds `expected`, all under a
+    /// single lock.
+    pub fn compare_and_delete(&self, key: &str, expected: &str) -> crate::Result<CasOutcome> {
+        self.lock().map(|mut data| {
+            let outcome = CasOutcome::check(data.get(key).map(|row| &row.0), expected);
+            if outcome.is_swapped() {
+                data.remove(key);
+            }
+            outcome
+        })
+    }
+
+    /// Moves the live row for `old_key` to `new_key`, keeping its `created`
+    /// timestamp but bumping `updated`, all under a single lock. Fails with
+    /// [`crate::Error::KeyNotFound`] if there is no live row for `old_key`, and
+    /// with [`crate::Error::DuplicateKey`] if `new_key` already has one.
+    pub fn rename(&self, old_key: &str, new_key: &str) -> crate::Result<()> {
+        self.rename_inner(old_key, new_key, false).map(|_| ())
+    }
+
+    /// Same as `rename`, but any live row for `new_key` is replaced and
+    /// returned instead.
+    pub fn rename_overwrite(&self, old_key: &str, new_key: &str) -> crate::Result<Option<Row>> {
+        self.rename_inner(old_key, new_key, true)
+    }
+
+    fn rename_inner(
+        &self,
+        old_key: &str,
+        new_key: &str,
+        overwrite: bool,
+    ) -> crate::Result<Option<Row>> {
+        self.limits.check(new_key, 0)?;
+        self.lock().and_then(|mut data| {
+            let mut row = Self::get_locked(&mut data, old_key)?;
+            let replaced = Self::get_locked(&mut data, new_key).ok();
+            if replaced.is_some() && !overwrite {
+                return Err(crate::Error::duplicate_key(new_key));
+            }
+            data.remove(old_key);
+            row.rename(new_key);
+            data.replace(KeyedRow(row));
+            Ok(replaced.filter(|_| old_key != new_key))
+        })
+    }
+
+    /// Deletes the rows for all of `keys` under a single lock, or none of them
+    /// if any key has no row, see `KeyValueStore::delete_many`.
+    pub fn delete_many(&self, keys: &[&str]) -> crate::Result<Vec<Row>> {
+        self.lock().and_then(|mut data| {
+            let mut seen = HashSet::with_capacity(keys.len());
+            for &key in keys {
+                if !seen.insert(key) || !data.get(key).map_or(false, |row| !row.0.is_deleted()) {
+                    return Err(crate::Error::key_not_found(key));
+                }
+            }
+            Ok(keys
+                .iter()
+                .filter_map(|&key| data.take(key))
+                .map(|row| row.0)
+                .collect())
+        })
+    }
+
+    /// Same as `delete_many`, but keys without a row are skipped and listed in
+    /// the returned report instead of aborting the batch.
+    pub fn try_delete_many(&self, keys: &[&str]) -> crate::Result<DeleteManyReport> {
+        self.lock().map(|mut data| {
+            let mut report = DeleteManyReport::default();
+            for &key in keys {
+                match data.take(key).filter(|row| !row.0.is_deleted()) {
+                    Some(row) => report.deleted.push(row.0),
+                    None => report.missing.push(key.to_string()),
+                }
+            }
+            report
+        })
+    }
+
+    /// Deletes every live row matching `predicate`, returning the deleted rows
+    /// in no particular order. The store stays locked while `predicate` runs,
+    /// so it must not use the store itself.
+    pub fn delete_matching<F: Fn(&Row) -> bool>(&self, predicate: F) -> crate::Result<Vec<Row>> {
+        self.lock().map(|mut data| {
+            let mut deleted = Vec::new();
+            data.retain(|row| {
+                if row.0.is_live() && predicate(&row.0) {
+                    deleted.push(row.0.clone());
+                    false
+                } else {
+                    true
+                }
+            });
+            deleted
+        })
+    }
+
+    /// Removes every row for which `predicate` returns false, returning how
+    /// many were removed. Deleted rows are left alone and aren't passed to
+    /// `predicate`.
+    ///
+    /// The store stays locked while `predicate` runs, so it must not use the
+    /// store itself. If `predicate` panics the panic is passed on to the
+    /// caller, the rows it already rejected stay removed and the rest are
+    /// kept; the lock is released without being poisoned, so the store can
+    /// still be used.
+    pub fn retain<F: Fn(&str, &Row) -> bool>(&self, predicate: F) -> crate::Result<usize> {
+        self.lock().map(|data| {
+            run_unpoisoned(data, |data| {
+                let before = data.len();
+                data.retain(|row| row.0.is_deleted() || predicate(row.0.key(), &row.0));
+                before - data.len()
+            })
+        })
+    }
+
+    /// Gets a snapshot of the keys of every live (unexpired and not deleted)
+    /// row, in no particular order.
+    pub fn keys(&self) -> crate::Result<Vec<String>> {
+        self.lock().map(|data| {
+            data.iter()
+                .filter(|row| row.0.is_live())
+                .map(|row| row.0.key().to_string())
+                .collect()
+        })
+    }
+
+    /// Same as `keys`, but sorted.
+    pub fn keys_sorted(&self) -> crate::Result<Vec<String>> {
+        let mut keys = self.keys()?;
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// Gets a snapshot of every live (unexpired and not deleted) row, in no
+    /// particular order.
+    pub fn rows(&self) -> crate::Result<Vec<Row>> {
+        self.lock().map(|data| {
+            data.iter()
+                .filter(|row| row.0.is_live())
+                .map(|row| row.0.clone())
+                .collect()
+        })
+    }
+
+    /// Writes the same json as `KeyValueStore::to_bytes`, a map from each key
+    /// to its row.
+    pub fn to_bytes(&self) -> crate::Result<Vec<u8>> {
+        self.lock().and_then(|data| {
+            let map = data
+                .iter()
+                .map(|row| (row.0.key(), &row.0))
+                .collect::<HashMap<_, _>>();
+            serde_json::to_vec(&map).map_err(|err| crate::Error::json_ser(&err))
+        })
+    }
+
+    /// Loads a store previously written with `to_bytes`, dropping any rows that
+    /// have expired since.
+    pub fn from_bytes(bytes: &[u8]) -> crate::Result<Self> {
+        serde_json::from_slice(bytes)
+            .map_err(|err| crate::Error::json_de(&err))
+            .map(|data: HashMap<String, Row>| Self::from_rows(data.into_values()))
+    }
+
+    fn from_rows(rows: impl IntoIterator<Item = Row>) -> Self {
+        let data = rows
+            .into_iter()
+            .filter(|row| !row.is_expired())
+            .map(KeyedRow)
+            .collect();
+        Self {
+            data: Mutex::new(data),
+            ..Self::default()
+        }
+    }
+
+    pub fn to_disk(&self) -> crate::Result<StoreDiskRepr> {
+        self.lock()
+            .map(|data| StoreDiskRepr::from_iter(data.iter().map(|row| RowDiskRepr::from(&row.0))))
+    }
+
+    pub fn into_disk(self) -> crate::Result<StoreDiskRepr> {
+        let data = self.data.into_inner().unwrap_or_else(|e| e.into_inner());
+        Ok(StoreDiskRepr::from_iter(
+            data.into_iter().map(|row| RowDiskRepr::from(row.0)),
+        ))
+    }
+
+    /// Loads a store from `disk`, dropping any rows that have expired. See
+    /// `KeyValueStore::from_disk` for the ways this can fail.
+    pub fn from_disk(disk: &StoreDiskRepr) -> crate::Result<Self> {
+        Ok(Self::from_rows(disk.rows_by_key()?.into_values()))
+    }
+
+    /// Same as `to_disk`.
+    pub fn to_disk_repr(&self) -> crate::Result<StoreDiskRepr> {
+        self.to_disk()
+    }
+}
+
+impl super::Store for SetStore {
+    fn get_clone(&self, key: &str) -> crate::Result<Row> {
+        SetStore::get_clone(self, key)
+    }
+
+    fn get_many(&self, keys: &[&str]) -> crate::Result<Vec<(String, crate::Result<Row>)>> {
+        SetStore::get_many(self, keys)
+    }
+
+    fn insert(&self, key: &str, value: &str) -> crate::Result<()> {
+        SetStore::insert(self, key, value)
+    }
+
+    fn insert_row(&self, row: &Row) -> crate::Result<()> {
+        SetStore::insert_row(self, row)
+    }
+
+    fn set_or_insert(&self, key: &str, value: &str) -> crate::Result<Option<Row>> {
+        SetStore::set_or_insert(self, key, value)
+    }
+
+    fn set_or_insert_row(&self, row: &Row) -> crate::Result<Option<Row>> {
+        SetStore::set_or_insert_row(self, row)
+    }
+
+    fn update_with(&self, key: &str, f: &mut dyn FnMut(&mut Row)) -> crate::Result<Row> {
+        SetStore::update_with(self, key, f)
+    }
+
+    fn upsert_with(
+        &self,
+        key: &str,
+        default: &mut dyn FnMut() -> Row,
+        f: &mut dyn FnMut(&mut Row),
+    ) -> crate::Result<Row> {
+        SetStore::upsert_with(self, key, default, f)
+    }
+
+    fn contains(&self, key: &str) -> crate::Result<bool> {
+        SetStore::contains(self, key)
+    }
+
+    fn len(&self) -> crate::Result<usize> {
+        SetStore::len(self)
+    }
+
+    fn clear(&self) -> crate::Result<usize> {
+        SetStore::clear(self)
+    }
+
+    fn delete(&self, key: &str) -> crate::Result<Row> {
+        SetStore::delete(self, key)
+    }
+
+    fn compare_and_swap(&self, key: &str, expected: &str, new: &str) -> crate::Result<CasOutcome> {
+        SetStore::compare_and_swap(self, key, expected, new)
+    }
+
+    fn compare_and_delete(&self, key: &str, expected: &str) -> crate::Result<CasOutcome> {
+        SetStore::compare_and_delete(self, key, expected)
+    }
+
+    fn rename(&self, old_key: &str, new_key: &str) -> crate::Result<()> {
+        SetStore::rename(self, old_key, new_key)
+    }
+
+    fn rename_overwrite(&self, old_key: &str, new_key: &str) -> crate::Result<Option<Row>> {
+        SetStore::rename_overwrite(self, old_key, new_key)
+    }
+
+    fn retain(&self, predicate: &dyn Fn(&str, &Row) -> bool) -> crate::Result<usize> {
+        SetStore::retain(self, predicate)
+    }
+
+    fn delete_many(&self, keys: &[&str]) -> crate::Result<Vec<Row>> {
+        SetStore::delete_many(self, keys)
+    }
+
+    fn try_delete_many(&self, keys: &[&str]) -> crate::Result<DeleteManyReport> {
+        SetStore::try_delete_many(self, keys)
+    }
+
+    fn delete_matching(&self, predicate: &dyn Fn(&Row) -> bool) -> crate::Result<Vec<Row>> {
+        SetStore::delete_matching(self, predicate)
+    }
+
+    fn keys(&self) -> crate::Result<Vec<String>> {
+        SetStore::keys(self)
+    }
+
+    fn rows(&self) -> crate::Result<Vec<Row>> {
+        SetStore::rows(self)
+    }
+
+    fn to_disk_repr(&self) -> crate::Result<StoreDiskRepr> {
+        SetStore::to_disk_repr(self)
+    }
+
+    fn to_bytes(&self) -> crate::Result<Vec<u8>> {
+        SetStore::to_bytes(self)
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "SetStore"
+    }
+}
+
+impl super::StoreFactory for SetStore {
+    fn from_bytes(bytes: &[u8]) -> crate::Result<Self> {
+        SetStore::from_bytes(bytes)
+    }
+
+    fn from_disk(disk: &StoreDiskRepr) -> crate::Result<Self> {
+        SetStore::from_disk(disk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{test_helpers, KeyValueStore};
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn shared_suite() {
+        test_helpers::check_store::<SetStore>();
+    }
+
+    #[test]
+    fn check_fill() {
+        for threads in 1..=8 {
+            let store: SetStore = test_helpers::fill_multi_thread(1000, threads);
+            assert_eq!(store.get_clone("key999").unwrap().value(), "value999");
+        }
+    }
+
+    #[test]
+    fn replaces_existing_key() {
+        let store = test_helpers::store_with::<SetStore>(&[("key", "old")]);
+        let created = store.get_clone("key").unwrap().created();
+
+        assert_eq!(
+            test_helpers::set_outcome(&store, "key", "new"),
+            Ok(SetOutcome::Updated)
+        );
+        assert_eq!(store.len(), Ok(1));
+        let row = store.get_clone("key").unwrap();
+        assert_eq!(row.value(), "new");
+        assert_eq!(row.created(), created);
+
+        let replacement = Row::create("key", "replaced");
+        assert!(store.set_or_insert_row(&replacement).is_ok());
+        assert_eq!(store.len(), Ok(1));
+        assert_eq!(store.get_clone("key").unwrap().value(), "replaced");
+
+        // An expired row is replaced by a plain insert rather than kept.
+        assert!(store
+            .insert_row(&Row::create_with_ttl("gone", "expired", -1))
+            .is_ok());
+        assert!(store.insert("gone", "fresh").is_ok());
+        assert_eq!(store.get_clone("gone").unwrap().value(), "fresh");
+    }
+
+    #[test]
+    fn interchanges_with_key_value_store() {
+        let store: SetStore = test_helpers::fill_single_thread(100);
+        let kv = KeyValueStore::from_bytes(&store.to_bytes().unwrap()).unwrap();
+        assert_eq!(kv.keys_sorted(), store.keys_sorted());
+
+        let back = SetStore::from_disk(&kv.to_disk().unwrap()).unwrap();
+        assert_eq!(back.keys_sorted(), store.keys_sorted());
+        assert_eq!(back.into_disk().unwrap().data.len(), 100);
+    }
+}
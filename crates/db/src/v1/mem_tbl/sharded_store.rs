@@ -0,0 +1,704 @@
+// Copyright (c) 2022 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    sync::{Mutex, MutexGuard},
+};
+
+use super::{run_unpoisoned, upsert_base, CasOutcome, DeleteManyReport, StoreLimits};
+use crate::{Row, RowDiskRepr, SetOutcome, StoreDiskRepr};
+
+type Shard = HashMap<String, Row>;
+
+/// A store that splits its rows between several independently locked
+/// `HashMap`s, so writers to different keys rarely wait on each other.
+#[derive(Debug)]
+pub struct ShardedStore {
+    shards: Vec<Mutex<Shard>>,
+    limits: StoreLimits,
+}
+
+impl Default for ShardedStore {
+    fn default() -> Self {
+        Self::with_shards(Self::DEFAULT_SHARDS)
+    }
+}
+
+impl ShardedStore {
+    pub const DEFAULT_SHARDS: usize = 16;
+
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty store with `shards` shards (at least one).
+    pub fn with_shards(shards: usize) -> Self {
+        Self {
+            shards: (0..shards.max(1)).map(|_| Mutex::default()).collect(),
+            limits: StoreLimits::default(),
+        }
+    }
+
+    /// Creates an empty store with the default number of shards that rejects
+    /// keys longer than `max_key_bytes` and values larger than
+    /// `max_value_bytes`. Stores created any other way have no limits.
+    pub fn with_limits(max_key_bytes: usize, max_value_bytes: usize) -> Self {
+        Self {
+            limits: StoreLimits::new(max_key_bytes, max_value_bytes),
+            ..Self::default()
+        }
+    }
+
+    /// Gets the number of shards in this store.
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    fn shard_index(&self, key: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    fn lock_shard(&self, index: usize) -> crate::Result<MutexGuard<'_, Shard>> {
+        self.shards[index]
+            .lock()
+            .map_err(|err| crate::Error::mutex_poisoned(&err))
+    }
+
+    fn lock(&self, key: &str) -> crate::Result<MutexGuard<'_, Shard>> {
+        self.lock_shard(self.shard_index(key))
+    }
+
+    /// Locks every shard, always in the same order so two callers can't
+    /// deadlock each other.
+    fn lock_all(&self) -> crate::Result<Vec<MutexGuard<'_, Shard>>> {
+        (0..self.shards.len())
+            .map(|index| self.lock_shard(index))
+            .collect()
+    }
+
+    /// Gets a copy of the row for `key`. Expired rows are removed and reported
+    /// as not found, as are rows that have been deleted.
+    pub fn get_clone(&self, key: &str) -> crate::Result<Row> {
+        self.lock(key)
+            .and_then(|mut shard| Self::get_locked(&mut shard, key))
+    }
+
+    fn get_locked(shard: &mut Shard, key: &str) -> crate::Result<Row> {
+        if shard.get(key).map_or(false, Row::is_expired) {
+            shard.remove(key);
+        }
+        shard
+            .get(key)
+            .filter(|row| !row.is_deleted())
+            .cloned()
+            .ok_or(crate::Error::key_not_found(key))
+    }
+
+    /// Gets a copy of the row for each of `keys`, in order. Each key only
+    /// locks its own shard, so the rows are not read atomically.
+    pub fn get_many(&self, keys: &[&str]) -> crate::Result<Vec<(String, crate::Result<Row>)>> {
+        Ok(keys
+            .iter()
+            .map(|&key| (key.to_string(), self.get_clone(key)))
+            .collect())
+    }
+
+    pub fn insert(&self, key: &str, value: &str) -> crate::Result<()> {
+        self.limits.check(key, value.len())?;
+        let mut shard = self.lock(key)?;
+        if shard.get(key).map_or(false, Row::is_live) {
+            return Err(crate::Error::duplicate_key(key));
+        }
+        shard.insert(key.to_string(), Row::create(key, value));
+        Ok(())
+    }
+
+    pub fn insert_row(&self, row: &Row) -> crate::Result<()> {
+        self.limits.check_row(row)?;
+        let mut shard = self.lock(row.key())?;
+        if shard.get(row.key()).map_or(false, Row::is_live) {
+            return Err(crate::Error::duplicate_key(row.key()));
+        }
+        shard.insert(row.key().to_string(), row.clone());
+        Ok(())
+    }
+
+    /// Sets the value of `key`, inserting a new row if it doesn't exist (or has
+    /// expired or been deleted). Returns a copy of the row it replaced, or
+    /// `None` if it inserted a new one.
+    pub fn set_or_insert(&self, key: &str, value: &str) -> crate::Result<Option<Row>> {
+        self.limits.check(key, value.len())?;
+        let mut previous = None;
+        self.lock(key)?
+            .entry(key.to_string())
+            .and_modify(|row| {
+                if row.is_live() {
+                    previous = Some(row.clone());
+                    row.update(value);
+                } else {
+                    *row = Row::create(key, value);
+                }
+            })
+            .or_insert_with(|| Row::create(key, value));
+        Ok(previous)
+    }
+
+    pub fn set_or_insert_row(&self, row: &Row) -> crate::Result<Option<Row>> {
+        self.limits.check_row(row)?;
+        let mut previous = None;
+        self.lock(row.key())?
+            .entry(row.key().to_string())
+            .and_modify(|v| {
+                previous = v.is_live().then(|| v.clone());
+                v.overwrite_with(row)
+            })
+            .or_insert_with(|| row.clone());
+        Ok(previous)
+    }
+
+    pub fn contains(&self, key: &str) -> crate::Result<bool> {
+        self.lock(key)
+            .map(|shard| shard.get(key).map_or(false, Row::is_live))
+    }
+
+    /// Gets the number of rows in this store, not counting deleted rows. Each
+    /// shard is counted in turn, so concurrent writes may or may not be seen.
+    pub fn len(&self) -> crate::Result<usize> {
+        (0..self.shards.len()).try_fold(0, |len, index| {
+            self.lock_shard(index)
+                .map(|shard| len + shard.values().filter(|row| !row.is_deleted()).count())
+        })
+    }
+
+    /// Removes every row from this store, returning how many there were (not
+    /// counting deleted rows, like `len`).
+    ///
+    /// Every shard is locked first, so the whole store is cleared at once.
+    pub fn clear(&self) -> crate::Result<usize> {
+        self.lock_all().map(|mut shards| {
+            shards
+                .iter_mut()
+                .map(|shard| shard.drain().filter(|(_, row)| !row.is_deleted()).count())
+                .sum()
+        })
+    }
+
+    /// Checks whether this store has no rows, not counting deleted rows.
+    pub fn is_empty(&self) -> crate::Result<bool> {
+        self.len().map(|len| len == 0)
+    }
+
+    pub fn delete(&self, key: &str) -> crate::Result<Row> {
+        self.lock(key)?
+            .remove(key)
+            .filter(|row| !row.is_deleted())
+            .ok_or(crate::Error::key_not_found(key))
+    }
+
+    /// Runs `f` on the live row for `key` and stores the result, returning a
+    /// copy of it. The key's shard stays locked while `f` runs, so `f` must not
+    /// use the store: calling it from inside `f` deadlocks once it touches
+    /// that shard. Fails with [`crate::Error::KeyNotFound`] if there is no live
+    /// row, or if `f` changes the key or breaks this store's limits, in which
+    /// case nothing is stored.
+    pub fn update_with<F: FnOnce(&mut Row)>(&self, key: &str, f: F) -> crate::Result<Row> {
+        self.lock(key).and_then(|mut shard| {
+            let row = self.limits.modify(Self::get_locked(&mut shard, key)?, f)?;
+            shard.insert(key.to_string(), row.clone());
+            Ok(row)
+        })
+    }
+
+    /// Same as `update_with`, but when `key` has no live row `f` is run on the
+    /// row made by `default` (which must be for `key`) instead.
+    pub fn upsert_with<D, F>(&self, key: &str, default: D, f: F) -> crate::Result<Row>
+    where
+        D: FnOnce() -> Row,
+        F: FnOnce(&mut Row),
+    {
+        self.lock(key).and_then(|mut shard| {
+            let row = upsert_base(Self::get_locked(&mut shard, key).ok(), key, default)?;
+            let row = self.limits.modify(row, f)?;
+            shard.insert(key.to_string(), row.clone());
+            Ok(row)
+        })
+    }
+
+    /// Sets the value of `key` to `new` if it currently holds `expected`, with
+    /// the key's shard locked throughout.
+    pub fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: &str,
+        new: &str,
+    ) -> crate::Result<CasOutcome> {
+        self.limits.check(key, new.len())?;
+        self.lock(key)
+            .map(|mut shard| CasOutcome::swap(shard.get_mut(key), expected, new))
+    }
+
+    /// Deletes the row for `key` if it currently holds `expected`, see
+    /// `compare_and_swap`.
+    pub fn compare_and_delete(&self, key: &str, expected: &str) -> crate::Result<CasOutcome> {
+        self.lock(key).map(|mut shard| {
+            let outcome = CasOutcome::check(shard.get(key), expected);
+            if outcome.is_swapped() {
+                shard.remove(key);
+            }
+            outcome
+        })
+    }
+
+    /// Moves the live row for `old_key` to `new_key`, keeping its `created`
+    /// timestamp but bumping `updated`, with both keys' shards locked. Fails
+    /// with [`crate::Error::KeyNotFound`] if there is no live row for
+    /// `old_key`, and with [`crate::Error::DuplicateKey`] if `new_key` already
+    /// has one.
+    pub fn rename(&self, old_key: &str, new_key: &str) -> crate::Result<()> {
+        self.rename_inner(old_key, new_key, false).map(|_| ())
+    }
+
+    /// Same as `rename`, but any live row for `new_key` is replaced and
+    /// returned instead.
+    pub fn rename_overwrite(&self, old_key: &str, new_key: &str) -> crate::Result<Option<Row>> {
+        self.rename_inner(old_key, new_key, true)
+    }
+
+    fn rename_inner(
+        &self,
+        old_key: &str,
+        new_key: &str,
+        overwrite: bool,
+    ) -> crate::Result<Option<Row>> {
+        self.limits.check(new_key, 0)?;
+        let (old_index, new_index) = (self.shard_index(old_key), self.shard_index(new_key));
+        // Lock in index order, like `lock_all`, so this can't deadlock with it.
+        let mut indices = vec![old_index, new_index];
+        indices.sort_unstable();
+        indices.dedup();
+        let mut shards = indices
+            .iter()
+            .map(|&index| self.lock_shard(index))
+            .collect::<crate::Result<Vec<_>>>()?;
+        let position = |index| indices.iter().position(|&i| i == index).unwrap_or(0);
+        let (old_shard, new_shard) = (position(old_index), position(new_index));
+
+        let mut row = Self::get_locked(&mut shards[old_shard], old_key)?;
+        let replaced = Self::get_locked(&mut shards[new_shard], new_key).ok();
+        if replaced.is_some() && !overwrite {
+            return Err(crate::Error::duplicate_key(new_key));
+        }
+        shards[old_shard].remove(old_key);
+        row.rename(new_key);
+        shards[new_shard].insert(new_key.to_string(), row);
+        Ok(replaced.filter(|_| old_key != new_key))
+    }
+
+    /// Deletes the rows for all of `keys`, or none of them if any key has no
+    /// row, see `KeyValueStore::delete_many`. Every shard is locked for the
+    /// duration, so the batch is atomic.
+    pub fn delete_many(&self, keys: &[&str]) -> crate::Result<Vec<Row>> {
+        let mut shards = self.lock_all()?;
+        let mut seen = HashSet::with_capacity(keys.len());
+        for &key in keys {
+            let shard = &shards[self.shard_index(key)];
+            if !seen.insert(key) || !shard.get(key).map_or(false, |row| !row.is_deleted()) {
+                return Err(crate::Error::key_not_found(key));
+            }
+        }
+        Ok(keys
+            .iter()
+            .filter_map(|&key| shards[self.shard_index(key)].remove(key))
+            .collect())
+    }
+
+    /// Same as `delete_many`, but keys without a row are skipped and listed in
+    /// the returned report instead of aborting the batch.
+    pub fn try_delete_many(&self, keys: &[&str]) -> crate::Result<DeleteManyReport> {
+        let mut report = DeleteManyReport::default();
+        for &key in keys {
+            match self.delete(key) {
+                Ok(row) => report.deleted.push(row),
+                Err(_) => report.missing.push(key.to_string()),
+            }
+        }
+        Ok(report)
+    }
+
+    /// Deletes every live row matching `predicate`, one shard at a time,
+    /// returning the deleted rows in no particular order. The shard being
+    /// visited stays locked while `predicate` runs, so it must not use the
+    /// store itself.
+    pub fn delete_matching<F: Fn(&Row) -> bool>(&self, predicate: F) -> crate::Result<Vec<Row>> {
+        let mut deleted = Vec::new();
+        for index in 0..self.shards.len() {
+            self.lock_shard(index)?.retain(|_, row| {
+                if row.is_live() && predicate(row) {
+                    deleted.push(row.clone());
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+        Ok(deleted)
+    }
+
+    /// Removes every row for which `predicate` returns false, returning how
+    /// many were removed. Deleted rows are left alone and aren't passed to
+    /// `predicate`.
+    ///
+    /// Every shard is locked while `predicate` runs, so it must not use the
+    /// store itself. If `predicate` panics the panic is passed on to the
+    /// caller, the rows it already rejected stay removed and the rest are
+    /// kept; the locks are released without being poisoned, so the store can
+    /// still be used.
+    pub fn retain<F: Fn(&str, &Row) -> bool>(&self, predicate: F) -> crate::Result<usize> {
+        self.lock_all().map(|shards| {
+            run_unpoisoned(shards, |shards| {
+                shards
+                    .iter_mut()
+                    .map(|shard| {
+                        let before = shard.len();
+                        shard.retain(|key, row| row.is_deleted() || predicate(key, row));
+                        before - shard.len()
+                    })
+                    .sum()
+            })
+        })
+    }
+
+    /// Gets the keys of every live (unexpired and not deleted) row, in no
+    /// particular order, reading one shard at a time.
+    pub fn keys(&self) -> crate::Result<Vec<String>> {
+        let mut keys = Vec::new();
+        for index in 0..self.shards.len() {
+            keys.extend(
+                self.lock_shard(index)?
+                    .iter()
+                    .filter(|(_, row)| row.is_live())
+                    .map(|(key, _)| key.clone()),
+            );
+        }
+        Ok(keys)
+    }
+
+    /// Same as `keys`, but sorted.
+    pub fn keys_sorted(&self) -> crate::Result<Vec<String>> {
+        let mut keys = self.keys()?;
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// Gets every live (unexpired and not deleted) row, in no particular order,
+    /// reading one shard at a time.
+    pub fn rows(&self) -> crate::Result<Vec<Row>> {
+        let mut rows = Vec::new();
+        for index in 0..self.shards.len() {
+            rows.extend(
+                self.lock_shard(index)?
+                    .values()
+                    .filter(|row| row.is_live())
+                    .cloned(),
+            );
+        }
+        Ok(rows)
+    }
+
+    /// Writes the same json as `KeyValueStore::to_bytes`, a map from each key
+    /// to its row. Every shard is locked while the map is written.
+    pub fn to_bytes(&self) -> crate::Result<Vec<u8>> {
+        let shards = self.lock_all()?;
+        let map = shards
+            .iter()
+            .flat_map(|shard| shard.iter())
+            .collect::<HashMap<_, _>>();
+        serde_json::to_vec(&map).map_err(|err| crate::Error::json_ser(&err))
+    }
+
+    /// Loads a store previously written with `to_bytes`, dropping any rows that
+    /// have expired since.
+    pub fn from_bytes(bytes: &[u8]) -> crate::Result<Self> {
+        serde_json::from_slice(bytes)
+            .map_err(|err| crate::Error::json_de(&err))
+            .map(|data: Shard| Self::from_rows(Self::DEFAULT_SHARDS, data))
+    }
+
+    /// Creates a store with `shards` shards holding the unexpired rows of
+    /// `rows`, each in the shard its key routes to.
+    fn from_rows(shards: usize, rows: Shard) -> Self {
+        let mut store = Self::with_shards(shards);
+        for (key, row) in rows.into_iter().filter(|(_, row)| !row.is_expired()) {
+            let index = store.shard_index(&key);
+            store.shards[index]
+                .get_mut()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(key, row);
+        }
+        store
+    }
+
+    /// Snapshots every shard in turn into a single [`StoreDiskRepr`].
+    pub fn to_disk(&self) -> crate::Result<StoreDiskRepr> {
+        let mut rows = Vec::new();
+        for index in 0..self.shards.len() {
+            rows.extend(self.lock_shard(index)?.values().map(RowDiskRepr::from));
+        }
+        Ok(StoreDiskRepr::from_iter(rows))
+    }
+
+    pub fn into_disk(self) -> crate::Result<StoreDiskRepr> {
+        Ok(StoreDiskRepr::from_iter(
+            self.shards
+                .into_iter()
+                .flat_map(|shard| shard.into_inner().unwrap_or_else(|e| e.into_inner()))
+                .map(|(_, row)| RowDiskRepr::from(row)),
+        ))
+    }
+
+    /// Loads a store with the default number of shards from `disk`, dropping
+    /// any rows that have expired. See `KeyValueStore::from_disk` for the ways
+    /// this can fail.
+    pub fn from_disk(disk: &StoreDiskRepr) -> crate::Result<Self> {
+        Self::from_disk_with_shards(disk, Self::DEFAULT_SHARDS)
+    }
+
+    /// Same as `from_disk`, but with `shards` shards.
+    pub fn from_disk_with_shards(disk: &StoreDiskRepr, shards: usize) -> crate::Result<Self> {
+        Ok(Self::from_rows(shards, disk.rows_by_key()?))
+    }
+
+    /// Same as `to_disk`.
+    pub fn to_disk_repr(&self) -> crate::Result<StoreDiskRepr> {
+        self.to_disk()
+    }
+}
+
+impl super::Store for ShardedStore {
+    fn get_clone(&self, key: &str) -> crate::Result<Row> {
+        ShardedStore::get_clone(self, key)
+    }
+
+    fn get_many(&self, keys: &[&str]) -> crate::Result<Vec<(String, crate::Result<Row>)>> {
+        ShardedStore::get_many(self, keys)
+    }
+
+    fn insert(&self, key: &str, value: &str) -> crate::Result<()> {
+        ShardedStore::insert(self, key, value)
+    }
+
+    fn insert_row(&self, row: &Row) -> crate::Result<()> {
+        ShardedStore::insert_row(self, row)
+    }
+
+    fn set_or_insert(&self, key: &str, value: &str) -> crate::Result<Option<Row>> {
+        ShardedStore::set_or_insert(self, key, value)
+    }
+
+    fn set_or_insert_row(&self, row: &Row) -> crate::Result<Option<Row>> {
+        ShardedStore::set_or_insert_row(self, row)
+    }
+
+    fn update_with(&self, key: &str, f: &mut dyn FnMut(&mut Row)) -> crate::Result<Row> {
+        ShardedStore::update_with(self, key, f)
+    }
+
+    fn upsert_with(
+        &self,
+        key: &str,
+        default: &mut dyn FnMut() -> Row,
+        f: &mut dyn FnMut(&mut Row),
+    ) -> crate::Result<Row> {
+        ShardedStore::upsert_with(self, key, default, f)
+    }
+
+    fn contains(&self, key: &str) -> crate::Result<bool> {
+        ShardedStore::contains(self, key)
+    }
+
+    fn len(&self) -> crate::Result<usize> {
+        ShardedStore::len(self)
+    }
+
+    fn clear(&self) -> crate::Result<usize> {
+        ShardedStore::clear(self)
+    }
+
+    fn delete(&self, key: &str) -> crate::Result<Row> {
+        ShardedStore::delete(self, key)
+    }
+
+    fn compare_and_swap(&self, key: &str, expected: &str, new: &str) -> crate::Result<CasOutcome> {
+        ShardedStore::compare_and_swap(self, key, expected, new)
+    }
+
+    fn compare_and_delete(&self, key: &str, expected: &str) -> crate::Result<CasOutcome> {
+        ShardedStore::compare_and_delete(self, key, expected)
+    }
+
+    fn rename(&self, old_key: &str, new_key: &str) -> crate::Result<()> {
+        ShardedStore::rename(self, old_key, new_key)
+    }
+
+    fn rename_overwrite(&self, old_key: &str, new_key: &str) -> crate::Result<Option<Row>> {
+        ShardedStore::rename_overwrite(self, old_key, new_key)
+    }
+
+    fn retain(&self, predicate: &dyn Fn(&str, &Row) -> bool) -> crate::Result<usize> {
+        ShardedStore::retain(self, predicate)
+    }
+
+    fn delete_many(&self, keys: &[&str]) -> crate::Result<Vec<Row>> {
+        ShardedStore::delete_many(self, keys)
+    }
+
+    fn try_delete_many(&self, keys: &[&str]) -> crate::Result<DeleteManyReport> {
+        ShardedStore::try_delete_many(self, keys)
+    }
+
+    fn delete_matching(&self, predicate: &dyn Fn(&Row) -> bool) -> crate::Result<Vec<Row>> {
+        ShardedStore::delete_matching(self, predicate)
+    }
+
+    fn keys(&self) -> crate::Result<Vec<String>> {
+        ShardedStore::keys(self)
+    }
+
+    fn rows(&self) -> crate::Result<Vec<Row>> {
+        ShardedStore::rows(self)
+    }
+
+    fn to_disk_repr(&self) -> crate::Result<StoreDiskRepr> {
+        ShardedStore::to_disk_repr(self)
+    }
+
+    fn to_bytes(&self) -> crate::Result<Vec<u8>> {
+        ShardedStore::to_bytes(self)
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "ShardedStore"
+    }
+}
+
+impl super::StoreFactory for ShardedStore {
+    fn from_bytes(bytes: &[u8]) -> crate::Result<Self> {
+        ShardedStore::from_bytes(bytes)
+    }
+
+    fn from_disk(disk: &StoreDiskRepr) -> crate::Result<Self> {
+        ShardedStore::from_disk(disk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{test_helpers, KeyValueStore};
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn shared_suite() {
+        test_helpers::check_store::<ShardedStore>();
+    }
+
+    #[test]
+    fn shard_count() {
+        assert_eq!(ShardedStore::empty().shard_count(), 16);
+        assert_eq!(ShardedStore::with_shards(4).shard_count(), 4);
+        assert_eq!(ShardedStore::with_shards(0).shard_count(), 1);
+    }
+
+    #[test]
+    fn check_fill() {
+        for vals in [100, 200] {
+            let store: ShardedStore = test_helpers::fill_single_thread(vals);
+            assert_eq!(store.len(), Ok(vals));
+        }
+
+        for threads in 1..=8 {
+            for vals in [100, 1000, 10000] {
+                let store: ShardedStore = test_helpers::fill_multi_thread(vals, threads);
+                assert_eq!(
+                    store.len(),
+                    Ok(vals),
+                    "check_fill (v = {} t = {}) - did not add the expected number of values",
+                    vals,
+                    threads
+                );
+                let key = format!("key{}", vals - 1);
+                assert_eq!(
+                    store.get_clone(&key).unwrap().value(),
+                    format!("value{}", vals - 1)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rows_are_spread_across_shards() {
+        let store: ShardedStore = test_helpers::fill_single_thread(1000);
+        for shard in &store.shards {
+            assert!(!shard.lock().unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn disk_roundtrip_routes_rows() {
+        let store: ShardedStore = test_helpers::fill_single_thread(500);
+        let disk = store.to_disk().unwrap();
+        assert_eq!(disk.data.len(), 500);
+
+        for shards in [1, 3, 16, 64] {
+            let loaded = ShardedStore::from_disk_with_shards(&disk, shards).unwrap();
+            assert_eq!(loaded.len(), Ok(500));
+            for (index, shard) in loaded.shards.iter().enumerate() {
+                for key in shard.lock().unwrap().keys() {
+                    assert_eq!(loaded.shard_index(key), index);
+                }
+            }
+            assert!(loaded.get_clone("key250").is_ok());
+        }
+
+        let kv = KeyValueStore::from_disk(&disk).unwrap();
+        assert_eq!(kv.keys_sorted(), store.keys_sorted());
+        let from_bytes = ShardedStore::from_bytes(&kv.to_bytes().unwrap()).unwrap();
+        assert_eq!(from_bytes.keys_sorted(), store.keys_sorted());
+        assert_eq!(from_bytes.into_disk().unwrap().data.len(), 500);
+    }
+
+    #[test]
+    #[ignore = "benchmark, run with --ignored --nocapture"]
+    fn insert_throughput() {
+        use std::time::Instant;
+
+        const VALUES: usize = 400_000;
+        const THREADS: usize = 8;
+
+        let start = Instant::now();
+        let store: KeyValueStore = test_helpers::fill_multi_thread(VALUES, THREADS);
+        let single = start.elapsed();
+        drop(store);
+
+        let start = Instant::now();
+        let store: ShardedStore = test_helpers::fill_multi_thread(VALUES, THREADS);
+        let sharded = start.elapsed();
+        drop(store);
+
+        println!(
+            "{} inserts on {} threads: KeyValueStore {:?}, ShardedStore {:?} ({:.2}x)",
+            VALUES,
+            THREADS,
+            single,
+            sharded,
+            single.as_secs_f64() / sharded.as_secs_f64()
+        );
+    }
+}
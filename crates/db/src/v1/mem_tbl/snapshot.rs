@@ -0,0 +1,110 @@
+// Copyright (c) 2022 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashMap;
+
+use crate::{Row, StoreDiskRepr};
+
+/// A copy of every row in a store at one point in time, see
+/// `KeyValueStore::snapshot`. It owns its rows, so it can be read for as long
+/// as needed without locking (or blocking writes to) the store it came from.
+///
+/// Like the stores, the read methods hide deleted rows, and `get`, `contains`,
+/// `keys` and `rows` hide rows that have expired by the time they're called.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StoreSnapshot {
+    rows: HashMap<String, Row>,
+}
+
+impl StoreSnapshot {
+    pub(crate) fn new(rows: HashMap<String, Row>) -> Self {
+        Self { rows }
+    }
+
+    /// Gets every row of this snapshot by key, including deleted and expired
+    /// rows, for restoring it.
+    pub(crate) fn all_rows(&self) -> &HashMap<String, Row> {
+        &self.rows
+    }
+
+    /// Gets the live row for `key`.
+    pub fn get(&self, key: &str) -> Option<&Row> {
+        self.rows.get(key).filter(|row| row.is_live())
+    }
+
+    /// Checks whether there is a live row for `key`.
+    pub fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Gets the number of rows in this snapshot, not counting deleted rows.
+    pub fn len(&self) -> usize {
+        self.rows.values().filter(|row| !row.is_deleted()).count()
+    }
+
+    /// Checks whether this snapshot has no rows, not counting deleted rows.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterates over the keys of every live row, in no particular order.
+    pub fn keys(&self) -> impl Iterator<Item = &str> + '_ {
+        self.rows().map(Row::key)
+    }
+
+    /// Iterates over every live row, in no particular order.
+    pub fn rows(&self) -> impl Iterator<Item = &Row> + '_ {
+        self.rows.values().filter(|row| row.is_live())
+    }
+
+    /// Converts this snapshot to its on-disk form, keeping every row like
+    /// `KeyValueStore::to_disk`.
+    pub fn to_disk_repr(&self) -> StoreDiskRepr {
+        self.rows.values().collect()
+    }
+}
+
+impl IntoIterator for StoreSnapshot {
+    type Item = (String, Row);
+    type IntoIter = StoreIntoIter;
+
+    /// Iterates over every row of this snapshot except deleted ones, see
+    /// [`StoreIntoIter`].
+    fn into_iter(self) -> Self::IntoIter {
+        StoreIntoIter::new(self.rows)
+    }
+}
+
+/// An iterator over the keys and rows taken out of a store or
+/// [`StoreSnapshot`], in no particular order. Deleted rows are skipped, so it
+/// yields as many rows as `len` reported.
+pub struct StoreIntoIter(Box<dyn Iterator<Item = (String, Row)> + Send>);
+
+impl StoreIntoIter {
+    pub(crate) fn new<I>(rows: I) -> Self
+    where
+        I: IntoIterator<Item = (String, Row)>,
+        I::IntoIter: Send + 'static,
+    {
+        Self(Box::new(
+            rows.into_iter().filter(|(_, row)| !row.is_deleted()),
+        ))
+    }
+}
+
+impl Iterator for StoreIntoIter {
+    type Item = (String, Row);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+impl std::fmt::Debug for StoreIntoIter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StoreIntoIter").finish_non_exhaustive()
+    }
+}
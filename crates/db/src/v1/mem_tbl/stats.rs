@@ -0,0 +1,132 @@
+// Copyright (c) 2022 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::SetOutcome;
+
+/// Counts the operations run against a store. Every counter is a separate
+/// atomic, so counting never takes the store's lock.
+#[derive(Debug, Default)]
+pub(crate) struct StoreStats {
+    gets: AtomicU64,
+    get_misses: AtomicU64,
+    inserts: AtomicU64,
+    updates: AtomicU64,
+    deletes: AtomicU64,
+    delete_misses: AtomicU64,
+}
+
+fn bump(counter: &AtomicU64, n: usize) {
+    counter.fetch_add(n as u64, Ordering::Relaxed);
+}
+
+impl StoreStats {
+    pub(crate) fn get_hit(&self) {
+        bump(&self.gets, 1);
+    }
+
+    pub(crate) fn get_miss(&self) {
+        bump(&self.gets, 1);
+        bump(&self.get_misses, 1);
+    }
+
+    /// Counts the `result` of reading a single key. Errors other than
+    /// [`crate::Error::KeyNotFound`] mean the read never happened, so they
+    /// aren't counted.
+    pub(crate) fn get<T>(&self, result: &crate::Result<T>) {
+        match result {
+            Ok(_) => self.get_hit(),
+            Err(crate::Error::KeyNotFound(_)) => self.get_miss(),
+            Err(_) => {}
+        }
+    }
+
+    pub(crate) fn inserted(&self, n: usize) {
+        bump(&self.inserts, n);
+    }
+
+    pub(crate) fn updated(&self, n: usize) {
+        bump(&self.updates, n);
+    }
+
+    /// Counts a set, as an insert or update depending on `outcome`. Sets that
+    /// left the row unchanged aren't counted.
+    pub(crate) fn set(&self, outcome: SetOutcome) {
+        match outcome {
+            SetOutcome::Inserted => self.inserted(1),
+            SetOutcome::Updated => self.updated(1),
+            SetOutcome::Unchanged => {}
+        }
+    }
+
+    pub(crate) fn deleted(&self, n: usize) {
+        bump(&self.deletes, n);
+    }
+
+    pub(crate) fn delete_missed(&self, n: usize) {
+        bump(&self.delete_misses, n);
+    }
+
+    /// Counts the `result` of deleting a single key, see `get`.
+    pub(crate) fn delete<T>(&self, result: &crate::Result<T>) {
+        match result {
+            Ok(_) => self.deleted(1),
+            Err(crate::Error::KeyNotFound(_)) => self.delete_missed(1),
+            Err(_) => {}
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> StoreStatsSnapshot {
+        StoreStatsSnapshot {
+            gets: self.gets.load(Ordering::Relaxed),
+            get_misses: self.get_misses.load(Ordering::Relaxed),
+            inserts: self.inserts.load(Ordering::Relaxed),
+            updates: self.updates.load(Ordering::Relaxed),
+            deletes: self.deletes.load(Ordering::Relaxed),
+            delete_misses: self.delete_misses.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        for counter in [
+            &self.gets,
+            &self.get_misses,
+            &self.inserts,
+            &self.updates,
+            &self.deletes,
+            &self.delete_misses,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The operation counts of a store at one point in time, see
+/// `KeyValueStore::stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StoreStatsSnapshot {
+    /// Reads of a single key, including the ones that missed.
+    pub gets: u64,
+    /// Reads that found no live row.
+    pub get_misses: u64,
+    /// Rows inserted under a key with no live row.
+    pub inserts: u64,
+    /// Live rows whose value was changed.
+    pub updates: u64,
+    /// Rows deleted.
+    pub deletes: u64,
+    /// Deletes that found no row to delete.
+    pub delete_misses: u64,
+}
+
+impl StoreStatsSnapshot {
+    /// Gets the share of reads that found a live row, or `None` if there
+    /// haven't been any reads.
+    pub fn hit_rate(&self) -> Option<f64> {
+        (self.gets > 0).then(|| (self.gets - self.get_misses) as f64 / self.gets as f64)
+    }
+}
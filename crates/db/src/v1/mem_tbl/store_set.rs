@@ -0,0 +1,225 @@
+// Copyright (c) 2022 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fmt,
+    sync::{Arc, PoisonError, RwLock},
+};
+
+use super::{validate_key, KeyValueStore, Store};
+use crate::{MergeStrategy, MultiStoreDiskRepr};
+
+/// A store shared between the users of a [`StoreSet`].
+pub type SharedStore = Arc<dyn Store + Send + Sync>;
+
+type StoreFactory = dyn Fn() -> SharedStore + Send + Sync;
+
+/// A set of named stores ("namespaces"), so separate keyspaces like users and
+/// sessions can live side by side without prefixing their keys.
+///
+/// Every namespace is made by the set's factory, a [`KeyValueStore`] unless
+/// the set was created with `with_factory`. Namespaces are handed out as
+/// `Arc`s, so dropping a namespace only removes it from the set; anyone still
+/// holding it can keep using it.
+pub struct StoreSet {
+    namespaces: RwLock<HashMap<String, SharedStore>>,
+    factory: Box<StoreFactory>,
+}
+
+impl StoreSet {
+    /// Creates an empty set whose namespaces are [`KeyValueStore`]s.
+    pub fn new() -> Self {
+        Self::with_factory(|| Arc::new(KeyValueStore::empty()))
+    }
+
+    /// Creates an empty set whose namespaces are made by `factory`.
+    pub fn with_factory<F>(factory: F) -> Self
+    where
+        F: Fn() -> SharedStore + Send + Sync + 'static,
+    {
+        Self {
+            namespaces: RwLock::default(),
+            factory: Box::new(factory),
+        }
+    }
+
+    /// Creates an empty namespace called `name` and returns it. Names follow
+    /// the same rules as keys, see [`validate_key`], and fail with
+    /// [`crate::Error::DuplicateKey`] if the namespace already exists.
+    pub fn create_namespace(&self, name: &str) -> crate::Result<SharedStore> {
+        validate_key(name)?;
+        match self.write().entry(name.to_string()) {
+            Entry::Occupied(_) => Err(crate::Error::duplicate_key(name)),
+            Entry::Vacant(entry) => Ok(Arc::clone(entry.insert((self.factory)()))),
+        }
+    }
+
+    /// Gets the namespace called `name`.
+    pub fn namespace(&self, name: &str) -> Option<SharedStore> {
+        self.read().get(name).cloned()
+    }
+
+    /// Removes the namespace called `name` from this set and returns it, or
+    /// fails with [`crate::Error::KeyNotFound`] if there is no such namespace.
+    pub fn drop_namespace(&self, name: &str) -> crate::Result<SharedStore> {
+        self.write()
+            .remove(name)
+            .ok_or_else(|| crate::Error::key_not_found(name))
+    }
+
+    /// Gets the names of every namespace, sorted.
+    pub fn list_namespaces(&self) -> Vec<String> {
+        let mut names = self.read().keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Converts every namespace to its on-disk form. Each namespace is
+    /// converted separately, so writes made meanwhile may show up in some
+    /// namespaces and not others.
+    pub fn to_disk(&self) -> crate::Result<MultiStoreDiskRepr> {
+        let namespaces = self
+            .read()
+            .iter()
+            .map(|(name, store)| Ok((name.clone(), store.to_disk_repr()?)))
+            .collect::<crate::Result<_>>()?;
+        Ok(MultiStoreDiskRepr { namespaces })
+    }
+
+    /// Loads a set of [`KeyValueStore`]s from `disk`, see `from_disk_with`.
+    pub fn from_disk(disk: &MultiStoreDiskRepr) -> crate::Result<Self> {
+        Self::from_disk_with(disk, || Arc::new(KeyValueStore::empty()))
+    }
+
+    /// Loads a set from `disk`, making each namespace with `factory` and
+    /// filling it with the namespace's rows. Fails if a namespace name isn't
+    /// valid, or if any namespace fails to load.
+    pub fn from_disk_with<F>(disk: &MultiStoreDiskRepr, factory: F) -> crate::Result<Self>
+    where
+        F: Fn() -> SharedStore + Send + Sync + 'static,
+    {
+        let set = Self::with_factory(factory);
+        for (name, store) in &disk.namespaces {
+            set.create_namespace(name)?
+                .merge_from(store, MergeStrategy::PreferOther)?;
+        }
+        Ok(set)
+    }
+
+    // The map is only ever changed by a single `insert` or `remove`, so a
+    // panic can't leave it half-updated and poisoning is safe to ignore.
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, HashMap<String, SharedStore>> {
+        self.namespaces
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, HashMap<String, SharedStore>> {
+        self.namespaces
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for StoreSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for StoreSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StoreSet")
+            .field("namespaces", &self.list_namespaces())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::mem_tbl::DashStore;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn namespaces() {
+        let set = StoreSet::new();
+        let users = set.create_namespace("users").unwrap();
+        let sessions = set.create_namespace("sessions").unwrap();
+        assert_eq!(
+            set.create_namespace("users").err(),
+            Some(crate::Error::duplicate_key("users"))
+        );
+        assert!(matches!(
+            set.create_namespace(""),
+            Err(crate::Error::InvalidKey { .. })
+        ));
+        assert_eq!(set.list_namespaces(), vec!["sessions", "users"]);
+
+        users.insert("id", "alice").unwrap();
+        sessions.insert("id", "token").unwrap();
+        let users = set.namespace("users").unwrap();
+        assert_eq!(*users.get_clone("id").unwrap().value(), *"alice");
+        assert_eq!(*sessions.get_clone("id").unwrap().value(), *"token");
+        assert!(set.namespace("missing").is_none());
+    }
+
+    #[test]
+    fn drop_namespace_in_use() {
+        let set = Arc::new(StoreSet::new());
+        let users = set.create_namespace("users").unwrap();
+        let sessions = set.create_namespace("sessions").unwrap();
+
+        let writer = std::thread::spawn(move || {
+            for i in 0..1000 {
+                sessions.insert(&format!("session{}", i), "token").unwrap();
+            }
+            sessions.len().unwrap()
+        });
+        users.insert("id", "alice").unwrap();
+        set.drop_namespace("sessions").unwrap();
+        assert_eq!(writer.join().unwrap(), 1000);
+
+        assert_eq!(set.list_namespaces(), vec!["users"]);
+        assert!(set.namespace("sessions").is_none());
+        assert_eq!(
+            set.drop_namespace("sessions").err(),
+            Some(crate::Error::key_not_found("sessions"))
+        );
+        assert_eq!(set.namespace("users").unwrap().len(), Ok(1));
+    }
+
+    #[test]
+    fn disk_round_trip() {
+        let set = StoreSet::with_factory(|| Arc::new(DashStore::empty()));
+        set.create_namespace("users")
+            .unwrap()
+            .insert("id", "alice")
+            .unwrap();
+        let sessions = set.create_namespace("sessions").unwrap();
+        sessions.insert("id", "token").unwrap();
+        sessions.insert("other", "token").unwrap();
+        set.create_namespace("empty").unwrap();
+
+        let disk = set.to_disk().unwrap();
+        assert_eq!(disk.namespaces.len(), 3);
+        let json = serde_json::to_string(&disk).unwrap();
+        let disk = serde_json::from_str::<MultiStoreDiskRepr>(&json).unwrap();
+
+        let loaded = StoreSet::from_disk(&disk).unwrap();
+        assert_eq!(loaded.list_namespaces(), vec!["empty", "sessions", "users"]);
+        let users = loaded.namespace("users").unwrap();
+        assert_eq!(*users.get_clone("id").unwrap().value(), *"alice");
+        assert_eq!(loaded.namespace("sessions").unwrap().len(), Ok(2));
+        assert_eq!(loaded.namespace("empty").unwrap().is_empty(), Ok(true));
+
+        let mut bad = disk;
+        bad.namespaces
+            .insert(String::new(), crate::StoreDiskRepr::from_vec(vec![]));
+        assert!(StoreSet::from_disk(&bad).is_err());
+    }
+}
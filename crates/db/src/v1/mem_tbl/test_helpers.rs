@@ -0,0 +1,430 @@
+// Copyright (c) 2022 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Test helpers shared by every [`Store`] implementation.
+
+use std::{sync::Arc, thread};
+
+use pretty_assertions::assert_eq;
+
+use super::Store;
+
+/// Checks whether `a` and `b` hold the same live rows, timestamps included,
+/// whatever kind of store they are.
+pub fn stores_equal(a: &dyn Store, b: &dyn Store) -> bool {
+    let a = a.rows().expect("stores_equal - unable to get rows of a");
+    let b = b.rows().expect("stores_equal - unable to get rows of b");
+    super::rows_equal(&a, &b, false)
+}
+
+pub fn store_with<S: Store + Default>(values: &[(&str, &str)]) -> S {
+    let store = S::default();
+    for &(key, value) in values {
+        assert!(
+            store.insert(key, value).is_ok(),
+            "store_with - failed to insert ({}, {})",
+            key,
+            value
+        );
+    }
+
+    assert_eq!(
+        store.len().expect("store_with - failed to get length"),
+        values.len()
+    );
+
+    store
+}
+
+pub fn fill_single_thread<S: Store + Default>(values: usize) -> S {
+    let store = S::default();
+    for i in 0..values {
+        let key = format!("key{}", i);
+        let value = format!("value{}", i);
+        assert!(
+            store.insert(key.as_str(), value.as_str()).is_ok(),
+            "fill_single_thread - unable to insert ({},{})",
+            key,
+            value
+        );
+    }
+
+    assert_eq!(
+        store
+            .len()
+            .expect("fill_single_thread - unable to get length"),
+        values,
+        "fill_single_thread - did not add the expected number of values",
+    );
+
+    store
+}
+
+pub fn fill_multi_thread<S>(values: usize, threads: usize) -> S
+where
+    S: Store + Default + Send + Sync + 'static,
+{
+    if values == 0 {
+        eprintln!("fill_multi_thread - called with values = 0");
+        return S::default();
+    }
+
+    if threads < 2 {
+        return fill_single_thread(values);
+    }
+
+    let step_size = values / threads;
+    let store = Arc::new(S::default());
+    let mut ts = Vec::new();
+
+    for t in 0..(threads - 1) {
+        let clone = Arc::clone(&store);
+        let start = t * step_size;
+        let end = start + step_size;
+        ts.push(thread::spawn(move || {
+            for i in start..end {
+                let key = format!("key{}", i);
+                let value = format!("value{}", i);
+                assert!(
+                    clone.insert(key.as_str(), value.as_str()).is_ok(),
+                    "fill_multi_thread - T{} - unable to insert ({},{})",
+                    t + 1,
+                    key,
+                    value
+                );
+            }
+        }));
+    }
+
+    for i in ((threads - 1) * step_size)..values {
+        let key = format!("key{}", i);
+        let value = format!("value{}", i);
+        assert!(
+            store.insert(key.as_str(), value.as_str()).is_ok(),
+            "fill_multi_thread - unable to insert ({},{})",
+            key,
+            value
+        );
+    }
+
+    for (i, handle) in ts.into_iter().enumerate() {
+        handle
+            .join()
+            .unwrap_or_else(|_| panic!("fill_multi_thread - unable to join thread {}", i + 1));
+    }
+
+    let inner = Arc::try_unwrap(store)
+        .unwrap_or_else(|_| panic!("fill_multi_thread - unable to take inner store"));
+
+    assert_eq!(
+        inner
+            .len()
+            .expect("fill_multi_thread - unable to get length"),
+        values,
+        "fill_multi_thread - did not add the expected number of values",
+    );
+    inner
+}
+
+/// Runs the standard insert/get/update/delete scenario against `store`, which
+/// must be empty, leaving it empty again. It only uses `store` as a trait
+/// object, so every backend can be run through it boxed.
+pub fn exercise_store(store: &dyn Store) {
+    assert!(!store.backend_name().is_empty());
+    assert_eq!(store.contains("key"), Ok(false));
+    assert_eq!(store.insert("key", "value"), Ok(()));
+    assert_eq!(store.contains("key"), Ok(true));
+    assert_eq!(
+        store.insert("key", "whoops"),
+        Err(crate::Error::duplicate_key("key"))
+    );
+    assert_eq!(
+        set_outcome(store, "key", "whoops"),
+        Ok(super::SetOutcome::Updated)
+    );
+    assert_eq!(store.get_clone("key").unwrap().value(), "whoops");
+    assert_eq!(store.len(), Ok(1));
+
+    let bytes = store
+        .to_bytes()
+        .expect("exercise_store - unable to get bytes");
+    let rows: std::collections::HashMap<String, crate::Row> =
+        serde_json::from_slice(&bytes).expect("exercise_store - unable to parse bytes");
+    assert_eq!(rows["key"].value(), "whoops");
+
+    assert_eq!(store.delete("key").unwrap().value(), "whoops");
+    assert_eq!(store.delete("key"), Err(crate::Error::key_not_found("key")));
+    assert_eq!(store.is_empty(), Ok(true));
+}
+
+/// Calls `set_or_insert` and works out its [`super::SetOutcome`] from the row
+/// it replaced.
+pub fn set_outcome(store: &dyn Store, key: &str, value: &str) -> crate::Result<super::SetOutcome> {
+    store
+        .set_or_insert(key, value)
+        .map(|previous| super::SetOutcome::from_previous(previous.as_ref(), value))
+}
+
+/// Checks that `S` loads back the same rows it saves, through both
+/// `to_bytes` and `to_disk_repr`.
+pub fn check_factory<S: super::StoreFactory + Default>() {
+    let store: S = store_with(&[("a", "1"), ("b", "2")]);
+    let from_bytes =
+        S::from_bytes(&store.to_bytes().unwrap()).expect("check_factory - unable to load bytes");
+    assert!(
+        stores_equal(&store, &from_bytes),
+        "{}",
+        store.backend_name()
+    );
+    let from_disk = S::from_disk(&store.to_disk_repr().unwrap())
+        .expect("check_factory - unable to load disk repr");
+    assert!(stores_equal(&store, &from_disk), "{}", store.backend_name());
+}
+
+/// Runs the behaviour every [`Store`] must share against an empty `S`.
+pub fn check_store<S: Store + Default>() {
+    check_store_on(S::default());
+}
+
+/// Same as `check_store`, for stores without a `Default`. `store` must be
+/// empty and hold at least five rows.
+pub fn check_store_on<S: Store>(store: S) {
+    exercise_store(&store);
+    assert_eq!(store.len(), Ok(0));
+    assert_eq!(store.random_key(), Ok(None));
+    assert_eq!(store.sample(3), Ok(Vec::new()));
+    for (key, value) in [("key1", "a"), ("key2", "b"), ("other", "c")] {
+        assert_eq!(store.insert(key, value), Ok(()));
+    }
+
+    assert_eq!(store.get_clone("key1").unwrap().value(), "a");
+    assert_eq!(
+        store.get_clone("missing"),
+        Err(crate::Error::key_not_found("missing"))
+    );
+    assert_eq!(
+        store.insert("key1", "x"),
+        Err(crate::Error::duplicate_key("key1"))
+    );
+    assert_eq!(store.contains("key2"), Ok(true));
+    assert_eq!(store.contains("missing"), Ok(false));
+
+    assert_eq!(
+        set_outcome(&store, "key1", "a"),
+        Ok(super::SetOutcome::Unchanged)
+    );
+    assert_eq!(
+        set_outcome(&store, "key1", "z"),
+        Ok(super::SetOutcome::Updated)
+    );
+    assert_eq!(
+        set_outcome(&store, "key3", "d"),
+        Ok(super::SetOutcome::Inserted)
+    );
+    assert_eq!(store.get_clone("key1").unwrap().value(), "z");
+    assert_eq!(store.len(), Ok(4));
+
+    let expired = crate::Row::create_with_ttl("expired", "x", -1);
+    assert!(store.insert_row(&expired).is_ok());
+    assert!(store.get_clone("expired").is_err());
+    assert_eq!(store.contains("expired"), Ok(false));
+    assert!(store.insert("expired", "back").is_ok());
+    assert_eq!(store.get_clone("expired").unwrap().value(), "back");
+    assert!(store.delete("expired").is_ok());
+
+    let mut keys = store.keys().unwrap();
+    keys.sort();
+    assert_eq!(keys, vec!["key1", "key2", "key3", "other"]);
+    assert_eq!(store.rows().unwrap().len(), 4);
+    check_sampling(&store, &keys);
+    assert_eq!(
+        store.keys_with_prefix("key"),
+        Ok(vec!["key1".into(), "key2".into(), "key3".into()])
+    );
+    assert_eq!(store.count_prefix("o"), Ok(1));
+
+    let many = store.get_many(&["key2", "missing"]).unwrap();
+    assert!(many[0].1.is_ok() && many[1].1.is_err());
+
+    assert_eq!(
+        store.delete_many(&["key3", "missing"]),
+        Err(crate::Error::key_not_found("missing"))
+    );
+    assert_eq!(store.len(), Ok(4));
+    assert_eq!(store.delete("key3").unwrap().value(), "d");
+    assert_eq!(
+        store.delete("key3"),
+        Err(crate::Error::key_not_found("key3"))
+    );
+    assert_eq!(
+        store
+            .delete_matching(&|row| row.key().starts_with("key"))
+            .unwrap()
+            .len(),
+        2
+    );
+    assert_eq!(store.keys(), Ok(vec!["other".to_string()]));
+
+    assert_eq!(
+        store.compare_and_swap("other", "nope", "x"),
+        Ok(super::CasOutcome::ValueMismatch { actual: "c".into() })
+    );
+    assert_eq!(
+        store.compare_and_swap("missing", "c", "x"),
+        Ok(super::CasOutcome::KeyMissing)
+    );
+    assert_eq!(
+        store.compare_and_swap("other", "c", "x"),
+        Ok(super::CasOutcome::Swapped)
+    );
+    assert_eq!(store.get_clone("other").unwrap().value(), "x");
+    assert!(store.insert("doomed", "y").is_ok());
+    assert_eq!(
+        store.compare_and_delete("doomed", "nope"),
+        Ok(super::CasOutcome::ValueMismatch { actual: "y".into() })
+    );
+    assert_eq!(
+        store.compare_and_delete("doomed", "y"),
+        Ok(super::CasOutcome::Swapped)
+    );
+    assert_eq!(
+        store.compare_and_delete("doomed", "y"),
+        Ok(super::CasOutcome::KeyMissing)
+    );
+
+    assert_eq!(
+        store.update_with("missing", &mut |_| {}),
+        Err(crate::Error::key_not_found("missing"))
+    );
+    let updated = store.update_with("other", &mut |row| {
+        row.update("x2");
+    });
+    assert_eq!(updated.map(|row| row.value().clone()), Ok("x2".into()));
+    assert_eq!(store.get_clone("other").unwrap().value(), "x2");
+    let counted = store
+        .upsert_with(
+            "counter",
+            &mut || crate::Row::create("counter", "41"),
+            &mut |row| {
+                let next = row.parse_value::<i64>().unwrap() + 1;
+                row.update(&next.to_string());
+            },
+        )
+        .unwrap();
+    assert_eq!(counted.value(), "42");
+    assert!(matches!(
+        store.upsert_with(
+            "wrong",
+            &mut || crate::Row::create("other", "v"),
+            &mut |_| {}
+        ),
+        Err(crate::Error::KeyValueMismatch(..))
+    ));
+    assert_eq!(store.contains("wrong"), Ok(false));
+    assert!(store.delete("counter").is_ok());
+
+    assert!(matches!(
+        store.rename("missing", "renamed"),
+        Err(crate::Error::KeyNotFound(..))
+    ));
+    assert_eq!(store.insert("to_rename", "r"), Ok(()));
+    let before = store.get_clone("to_rename").unwrap();
+    assert_eq!(store.rename("to_rename", "renamed"), Ok(()));
+    assert_eq!(store.contains("to_rename"), Ok(false));
+    let after = store.get_clone("renamed").unwrap();
+    assert_eq!(after.value(), "r");
+    assert_eq!(after.created(), before.created());
+    assert!(after.updated() >= before.updated());
+    assert!(after.version() > before.version());
+    for new_key in ["other", "renamed"] {
+        assert!(matches!(
+            store.rename("renamed", new_key),
+            Err(crate::Error::DuplicateKey(..))
+        ));
+    }
+    assert_eq!(store.get_clone("renamed"), Ok(after));
+    let replaced = store.rename_overwrite("renamed", "other").unwrap();
+    assert_eq!(replaced.map(|row| row.value().clone()), Some("x2".into()));
+    assert_eq!(store.contains("renamed"), Ok(false));
+    assert_eq!(store.get_clone("other").unwrap().value(), "r");
+
+    let disk = store.to_disk_repr().unwrap();
+    assert_eq!(disk.data.len(), 1);
+    assert_eq!(disk.data[0].key, "other");
+
+    assert_eq!(store.retain(&|_, _| true), Ok(0));
+    assert_eq!(store.retain_updated_since(0), Ok(0));
+    assert_eq!(store.insert("dropped", "d"), Ok(()));
+    assert_eq!(store.retain(&|key, _| key != "dropped"), Ok(1));
+    assert_eq!(store.contains("dropped"), Ok(false));
+
+    assert_eq!(store.is_empty(), Ok(false));
+    assert_eq!(store.clear(), Ok(1));
+    assert_eq!(store.is_empty(), Ok(true));
+    assert_eq!(store.clear(), Ok(0));
+    assert_eq!(
+        store.get_clone("other"),
+        Err(crate::Error::key_not_found("other"))
+    );
+    assert_eq!(store.insert("other", "again"), Ok(()));
+    assert_eq!(store.is_empty(), Ok(false));
+}
+
+/// Checks `random_key` and `sample` on a store whose live keys are `keys`.
+/// Random picks are drawn often enough that every key should turn up.
+pub fn check_sampling(store: &dyn Store, keys: &[String]) {
+    use std::collections::HashSet;
+
+    let sample_keys = |n| {
+        store
+            .sample(n)
+            .unwrap()
+            .into_iter()
+            .map(|row| row.key().to_string())
+            .collect::<Vec<_>>()
+    };
+    let mut all = sample_keys(keys.len() + 5);
+    all.sort();
+    assert_eq!(all, keys, "{}", store.backend_name());
+
+    let mut seen_by_key = HashSet::new();
+    let mut seen_by_sample = HashSet::new();
+    for _ in 0..200 {
+        seen_by_key.insert(store.random_key().unwrap().unwrap());
+        let picked = sample_keys(2);
+        assert_eq!(picked.len(), keys.len().min(2));
+        assert_ne!(picked.first(), picked.get(1));
+        seen_by_sample.extend(picked);
+    }
+    for seen in [seen_by_key, seen_by_sample] {
+        let mut seen = seen.into_iter().collect::<Vec<_>>();
+        seen.sort();
+        assert_eq!(seen, keys, "{}", store.backend_name());
+    }
+}
+
+/// Drains the events waiting in `receiver` into short descriptions like
+/// `"updated key: old -> new"`, leaving out timestamps so they can be compared.
+pub fn drain_events(receiver: &std::sync::mpsc::Receiver<crate::RowEvent>) -> Vec<String> {
+    use crate::RowEvent;
+
+    let value = |row: &crate::Row| row.value().to_display_string();
+    receiver
+        .try_iter()
+        .map(|event| match &event {
+            RowEvent::Inserted(row) => format!("inserted {}: {}", row.key(), value(row)),
+            RowEvent::Updated { before, after } => {
+                format!(
+                    "updated {}: {} -> {}",
+                    after.key(),
+                    value(before),
+                    value(after)
+                )
+            }
+            RowEvent::Deleted(row) => format!("deleted {}: {}", row.key(), value(row)),
+        })
+        .collect()
+}
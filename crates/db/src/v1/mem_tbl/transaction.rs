@@ -0,0 +1,104 @@
+// Copyright (c) 2022 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use serde::{Deserialize, Serialize};
+
+use super::StoreLimits;
+use crate::{Row, SetOutcome};
+
+/// A single operation of a transaction, see `KeyValueStore::apply_transaction`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Op {
+    /// Sets the value of `key`, inserting it if it has no live row.
+    Set { key: String, value: String },
+    /// Inserts `key`, which must not have a live row.
+    Insert { key: String, value: String },
+    /// Deletes `key`, which must have a live row.
+    Delete { key: String },
+    /// Sets the value of `key` to `value`, which must currently be `expected`.
+    Cas {
+        key: String,
+        expected: String,
+        value: String,
+    },
+}
+
+/// What a single [`Op`] of a transaction did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpResult {
+    Set(SetOutcome),
+    Inserted,
+    /// Holds the row as it was before it was deleted.
+    Deleted(Row),
+    Swapped,
+}
+
+impl Op {
+    /// Gets the key this operation is about.
+    pub fn key(&self) -> &str {
+        match self {
+            Op::Set { key, .. }
+            | Op::Insert { key, .. }
+            | Op::Delete { key }
+            | Op::Cas { key, .. } => key,
+        }
+    }
+
+    /// Gets the value this operation writes, if any.
+    pub fn value(&self) -> Option<&str> {
+        match self {
+            Op::Set { value, .. } | Op::Insert { value, .. } | Op::Cas { value, .. } => Some(value),
+            Op::Delete { .. } => None,
+        }
+    }
+
+    pub(crate) fn check_limits(&self, limits: &StoreLimits) -> crate::Result<()> {
+        limits.check(self.key(), self.value().map_or(0, str::len))
+    }
+
+    /// Applies this operation to `current`, the live row for its key (if
+    /// any), returning what happened and the row the key should be left
+    /// with. Fails without changing anything if the operation's precondition
+    /// doesn't hold.
+    pub(crate) fn apply(&self, current: Option<Row>) -> crate::Result<(OpResult, Option<Row>)> {
+        match (self, current) {
+            (Op::Set { key, value }, None) | (Op::Insert { key, value }, None) => {
+                let result = match self {
+                    Op::Set { .. } => OpResult::Set(SetOutcome::Inserted),
+                    _ => OpResult::Inserted,
+                };
+                Ok((result, Some(Row::create(key, value))))
+            }
+            (Op::Set { value, .. }, Some(mut row)) => {
+                let outcome = match row.update(value) {
+                    true => SetOutcome::Updated,
+                    false => SetOutcome::Unchanged,
+                };
+                Ok((OpResult::Set(outcome), Some(row)))
+            }
+            (Op::Insert { key, .. }, Some(_)) => Err(crate::Error::duplicate_key(key)),
+            (Op::Delete { .. }, Some(row)) => Ok((OpResult::Deleted(row), None)),
+            (
+                Op::Cas {
+                    expected, value, ..
+                },
+                Some(mut row),
+            ) => {
+                if *row.value() != **expected {
+                    return Err(crate::Error::value_mismatch(
+                        row.key(),
+                        &row.value().to_display_string(),
+                    ));
+                }
+                row.update(value);
+                Ok((OpResult::Swapped, Some(row)))
+            }
+            (Op::Delete { key } | Op::Cas { key, .. }, None) => {
+                Err(crate::Error::key_not_found(key))
+            }
+        }
+    }
+}
@@ -0,0 +1,208 @@
+// Copyright (c) 2022 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{borrow::Cow, sync::Arc};
+
+use once_cell::sync::OnceCell;
+use serde::{de::Error as _, Deserialize, Serialize};
+
+use crate::Value;
+
+/// How a [`crate::Row`] holds its value, in memory and on disk.
+///
+/// Stores created with `KeyValueStore::with_options` compress large string
+/// values, everything else is kept as-is.
+#[derive(Debug, Clone)]
+pub enum ValueRepr {
+    Plain(Arc<Value>),
+    /// A [`Value::Str`] compressed with lz4, along with the length of the
+    /// original string in bytes.
+    Compressed {
+        bytes: Arc<[u8]>,
+        original_len: usize,
+    },
+}
+
+impl ValueRepr {
+    /// Compresses `value` if it is a string of at least `threshold` bytes and
+    /// compressing it actually saves space. Always `None` when the
+    /// `compression` feature is disabled.
+    pub fn compress(value: &Value, threshold: usize) -> Option<Self> {
+        let s = value.as_str().filter(|s| s.len() >= threshold)?;
+        let bytes = codec::compress(s.as_bytes()).filter(|bytes| bytes.len() < s.len())?;
+        Some(ValueRepr::Compressed {
+            bytes: bytes.into(),
+            original_len: s.len(),
+        })
+    }
+
+    /// Checks whether this is [`ValueRepr::Compressed`].
+    pub fn is_compressed(&self) -> bool {
+        matches!(self, ValueRepr::Compressed { .. })
+    }
+
+    /// Gets the value held by this repr, decompressing it if needed.
+    pub fn get(&self) -> Cow<'_, Value> {
+        match self {
+            ValueRepr::Plain(value) => Cow::Borrowed(value),
+            ValueRepr::Compressed {
+                bytes,
+                original_len,
+            } => Cow::Owned(Value::Str(
+                codec::decompress(bytes, *original_len)
+                    .expect("ValueRepr::get - compressed value was validated on creation"),
+            )),
+        }
+    }
+
+    /// Gets the number of bytes this repr takes up, which is the compressed
+    /// size for compressed values.
+    pub fn size_bytes(&self) -> usize {
+        match self {
+            ValueRepr::Plain(value) => value.size_bytes(),
+            ValueRepr::Compressed { bytes, .. } => bytes.len(),
+        }
+    }
+}
+
+impl From<Value> for ValueRepr {
+    fn from(value: Value) -> Self {
+        ValueRepr::Plain(Arc::new(value))
+    }
+}
+
+/// Compares the values held by each repr, so a compressed value is equal to
+/// the same value stored plainly.
+impl PartialEq for ValueRepr {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ValueRepr::Plain(a), ValueRepr::Plain(b)) => a == b,
+            (ValueRepr::Compressed { bytes: a, .. }, ValueRepr::Compressed { bytes: b, .. })
+                if a == b =>
+            {
+                true
+            }
+            _ => self.get() == other.get(),
+        }
+    }
+}
+
+impl Eq for ValueRepr {}
+
+/// The json form of [`ValueRepr::Compressed`]. Plain values are written exactly
+/// like a [`Value`] so data without compressed values looks the same as before.
+#[derive(Serialize)]
+#[serde(tag = "type", content = "value")]
+enum CompressedWire<'a> {
+    Lz4 {
+        bytes: &'a [u8],
+        original_len: usize,
+    },
+}
+
+/// Owned version of [`CompressedWire`] for deserialization.
+#[derive(Deserialize)]
+#[serde(tag = "type", content = "value")]
+enum CompressedInput {
+    Lz4 { bytes: Vec<u8>, original_len: usize },
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ValueReprInput {
+    Plain(Value),
+    Compressed(CompressedInput),
+}
+
+impl Serialize for ValueRepr {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ValueRepr::Plain(value) => value.serialize(serializer),
+            ValueRepr::Compressed {
+                bytes,
+                original_len,
+            } => CompressedWire::Lz4 {
+                bytes,
+                original_len: *original_len,
+            }
+            .serialize(serializer),
+        }
+    }
+}
+
+/// Compressed values are decompressed once while loading so that a corrupted
+/// value (or one that can't be read without the `compression` feature) fails
+/// here instead of when it is first read.
+impl<'de> Deserialize<'de> for ValueRepr {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match ValueReprInput::deserialize(deserializer)? {
+            ValueReprInput::Plain(value) => Ok(value.into()),
+            ValueReprInput::Compressed(CompressedInput::Lz4 {
+                bytes,
+                original_len,
+            }) => {
+                codec::decompress(&bytes, original_len).map_err(D::Error::custom)?;
+                Ok(ValueRepr::Compressed {
+                    bytes: bytes.into(),
+                    original_len,
+                })
+            }
+        }
+    }
+}
+
+/// The decompressed copy of a compressed value that lets `Row::value` hand
+/// out a reference. It is ignored when comparing rows.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ValueCache(OnceCell<Arc<Value>>);
+
+impl ValueCache {
+    pub(crate) fn get(&self) -> Option<&Value> {
+        self.0.get().map(|value| &**value)
+    }
+
+    pub(crate) fn get_or_init(&self, repr: &ValueRepr) -> &Value {
+        self.0.get_or_init(|| Arc::new(repr.get().into_owned()))
+    }
+}
+
+impl PartialEq for ValueCache {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for ValueCache {}
+
+#[cfg(feature = "compression")]
+mod codec {
+    pub fn compress(data: &[u8]) -> Option<Vec<u8>> {
+        Some(lz4_flex::compress(data))
+    }
+
+    pub fn decompress(data: &[u8], original_len: usize) -> Result<String, String> {
+        let bytes = lz4_flex::decompress(data, original_len).map_err(|err| err.to_string())?;
+        if bytes.len() != original_len {
+            return Err(format!(
+                "expected {} decompressed bytes but found {}",
+                original_len,
+                bytes.len()
+            ));
+        }
+        String::from_utf8(bytes).map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(not(feature = "compression"))]
+mod codec {
+    pub fn compress(_: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+
+    pub fn decompress(_: &[u8], _: usize) -> Result<String, String> {
+        Err("reading compressed values requires the `compression` feature".to_string())
+    }
+}
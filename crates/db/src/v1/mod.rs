@@ -9,4 +9,10 @@ mod mem_tbl;
 mod wal;
 
 pub use error::{Error, Result};
-pub use mem_tbl::{KeyValueStore, Row, RowDiskRepr, StoreByteRepr, StoreDiskRepr};
+pub use mem_tbl::{
+    validate_key, CasOutcome, CompressionOptions, DeleteManyReport, DeletePolicy, EntryRef,
+    FieldDiff, KeyValueStore, LoadReport, MergeReport, MergeResult, MergeStrategy,
+    MultiStoreDiskRepr, Op, OpResult, PoisonPolicy, Row, RowBuilder, RowDiskRepr, RowEvent,
+    SetManyOutcome, SetOutcome, StoreByteRepr, StoreDiff, StoreDiskRepr, StoreIntoIter, StoreSize,
+    StoreSnapshot, StoreStatsSnapshot, Value, ValueRepr, MAX_KEY_LEN,
+};
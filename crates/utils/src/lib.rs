@@ -1,6 +1,13 @@
+pub mod pattern;
+
 pub mod asserts {
     /// Checks whether all elements in `left` (the first collection) are contained
-    /// within `right` (the second collection).
+    /// within `right` (the second collection). Every missing element is collected
+    /// and reported in a single panic, followed by the custom message (if any) on
+    /// its own line. Iterators such as `map.keys()` can be passed as well as
+    /// collections; they are cloned and collected once. Elements of `right` are
+    /// put in a `HashSet` when they implement `Hash + Eq`, otherwise `right` is
+    /// scanned for each element of `left`.
     ///
     /// ## Example(s)
     /// ```rust
@@ -12,26 +19,302 @@ pub mod asserts {
     #[macro_export]
     macro_rules! assert_contains_all {
         ($left:expr, $right:expr $(,) ?) => {{
-            match (&$left, &$right) {
-                (left_val, right_val) => {
-                    for n in left_val.iter() {
-                        assert!(
-                            right_val.contains(n),
-                            "Expected right {:?} to contain value {:?}",
-                            right_val,
-                            n
+            if let Err(msg) = $crate::check_contains_all!($left, $right) {
+                panic!("{}", msg);
+            }
+        }};
+        ($left:expr, $right:expr, $($arg:tt) +) => {{
+            if let Err(msg) = $crate::check_contains_all!($left, $right, $($arg)*) {
+                panic!("{}", msg);
+            }
+        }};
+    }
+
+    /// Checks that `small` is a subset of `big`, i.e. every element of `small` is
+    /// contained in `big`. All elements missing from `big` are reported.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_subset;
+    /// let small = vec![1, 2];
+    /// let big = vec![3, 2, 1];
+    /// assert_subset!(small, big);
+    /// ```
+    #[macro_export]
+    macro_rules! assert_subset {
+        ($small:expr, $big:expr $(,) ?) => {{
+            match (&$small, &$big) {
+                (small_val, big_val) => {
+                    let _missing =
+                        $crate::asserts::__private::missing(small_val.iter(), |n| big_val.contains(n));
+                    assert!(
+                        _missing.is_empty(),
+                        "left is not a subset of right: missing {:?}",
+                        _missing
+                    );
+                }
+            }
+        }};
+        ($small:expr, $big:expr, $($arg:tt) +) => {{
+            match (&$small, &$big) {
+                (small_val, big_val) => {
+                    let _missing =
+                        $crate::asserts::__private::missing(small_val.iter(), |n| big_val.contains(n));
+                    if !_missing.is_empty() {
+                        panic!(
+                            "left is not a subset of right: missing {:?}\n{}",
+                            _missing,
+                            format_args!($($arg)*)
+                        );
+                    }
+                }
+            }
+        }};
+    }
+
+    /// Checks that `big` is a superset of `small`, i.e. `big` contains every
+    /// element of `small`. All elements of `small` missing from `big` are reported.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_superset;
+    /// let big = vec![3, 2, 1];
+    /// let small = vec![1, 2];
+    /// assert_superset!(big, small);
+    /// ```
+    #[macro_export]
+    macro_rules! assert_superset {
+        ($big:expr, $small:expr $(,) ?) => {{
+            match (&$big, &$small) {
+                (big_val, small_val) => {
+                    let _missing =
+                        $crate::asserts::__private::missing(small_val.iter(), |n| big_val.contains(n));
+                    assert!(
+                        _missing.is_empty(),
+                        "left is not a superset of right: missing {:?}",
+                        _missing
+                    );
+                }
+            }
+        }};
+        ($big:expr, $small:expr, $($arg:tt) +) => {{
+            match (&$big, &$small) {
+                (big_val, small_val) => {
+                    let _missing =
+                        $crate::asserts::__private::missing(small_val.iter(), |n| big_val.contains(n));
+                    if !_missing.is_empty() {
+                        panic!(
+                            "left is not a superset of right: missing {:?}\n{}",
+                            _missing,
+                            format_args!($($arg)*)
                         );
                     }
                 }
             }
         }};
+    }
+
+    /// Checks that `collection` does not contain `value`. Works with anything that
+    /// has a `contains` method taking a reference, like `Vec`, slices, `HashSet`
+    /// and ranges.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_not_contains;
+    /// let values = vec![1, 2, 3];
+    /// assert_not_contains!(values, 4);
+    /// assert_not_contains!(0..10, 10, "10 should be outside the range");
+    /// ```
+    #[macro_export]
+    macro_rules! assert_not_contains {
+        ($collection:expr, $value:expr $(,) ?) => {{
+            match (&$collection, &$value) {
+                (collection_val, value_val) => {
+                    assert!(
+                        !collection_val.contains(value_val),
+                        "Expected collection {:?} to not contain {:?}",
+                        collection_val,
+                        value_val
+                    );
+                }
+            }
+        }};
+        ($collection:expr, $value:expr, $($arg:tt) +) => {{
+            match (&$collection, &$value) {
+                (collection_val, value_val) => {
+                    assert!(
+                        !collection_val.contains(value_val),
+                        "Expected collection {:?} to not contain {:?}\n{}",
+                        collection_val,
+                        value_val,
+                        format_args!($($arg)*)
+                    );
+                }
+            }
+        }};
+    }
+
+    /// Checks that no element of `left` is contained in `right`. On failure every
+    /// overlapping element is listed, not just the first one found.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_disjoint;
+    /// let first = vec![1, 2, 3];
+    /// let second = [4, 5, 6];
+    /// assert_disjoint!(first, second);
+    /// ```
+    #[macro_export]
+    macro_rules! assert_disjoint {
+        ($left:expr, $right:expr $(,) ?) => {{
+            match (&$left, &$right) {
+                (left_val, right_val) => {
+                    let _common: Vec<_> = left_val
+                        .iter()
+                        .filter(|n| right_val.contains(*n))
+                        .collect();
+                    assert!(
+                        _common.is_empty(),
+                        "Expected left {:?} and right {:?} to be disjoint, found common elements {:?}",
+                        left_val,
+                        right_val,
+                        _common
+                    );
+                }
+            }
+        }};
         ($left:expr, $right:expr, $($arg:tt) +) => {{
             match (&$left, &$right) {
                 (left_val, right_val) => {
-                    for n in left_val.iter() {
-                        assert!(
-                            right_val.contains(n),
-                            $($arg)*
+                    let _common: Vec<_> = left_val
+                        .iter()
+                        .filter(|n| right_val.contains(*n))
+                        .collect();
+                    assert!(
+                        _common.is_empty(),
+                        "Expected left {:?} and right {:?} to be disjoint, found common elements {:?}\n{}",
+                        left_val,
+                        right_val,
+                        _common,
+                        format_args!($($arg)*)
+                    );
+                }
+            }
+        }};
+    }
+
+    /// Checks that the set of elements common to `left` and `right` matches
+    /// `expected`, regardless of order. Accepts collections or iterators like
+    /// [`assert_unordered_match!`].
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_intersection;
+    /// let first = vec![1, 2, 3, 3];
+    /// let second = vec![5, 4, 3, 2];
+    /// assert_intersection!(first, second, [3, 2]);
+    /// ```
+    #[macro_export]
+    macro_rules! assert_intersection {
+        ($left:expr, $right:expr, $expected:expr $(,) ?) => {{
+            match (&$left, &$right, &$expected) {
+                (left_val, right_val, expected_val) => {
+                    #[allow(unused_imports)]
+                    use $crate::asserts::__private::{CloneSource as _, EqCount as _, HashCount as _, RefSource as _};
+                    let _left = (&$crate::asserts::__private::Source(left_val)).materialize();
+                    let _right = (&$crate::asserts::__private::Source(right_val)).materialize();
+                    let _expected = (&$crate::asserts::__private::Source(expected_val)).materialize();
+                    let _intersection = $crate::asserts::__private::intersection(&_left, &_right);
+                    let _multiset = $crate::asserts::__private::Multiset(
+                        _intersection.clone(),
+                        _expected.iter().collect(),
+                    );
+                    if _multiset.0.len() != _multiset.1.len()
+                        || (&_multiset).first_count_mismatch().is_some()
+                    {
+                        panic!(
+                            "Expected intersection to match {:?}, but it was {:?}",
+                            _expected, _intersection
+                        );
+                    }
+                }
+            }
+        }};
+        ($left:expr, $right:expr, $expected:expr, $($arg:tt) +) => {{
+            match (&$left, &$right, &$expected) {
+                (left_val, right_val, expected_val) => {
+                    #[allow(unused_imports)]
+                    use $crate::asserts::__private::{CloneSource as _, EqCount as _, HashCount as _, RefSource as _};
+                    let _left = (&$crate::asserts::__private::Source(left_val)).materialize();
+                    let _right = (&$crate::asserts::__private::Source(right_val)).materialize();
+                    let _expected = (&$crate::asserts::__private::Source(expected_val)).materialize();
+                    let _intersection = $crate::asserts::__private::intersection(&_left, &_right);
+                    let _multiset = $crate::asserts::__private::Multiset(
+                        _intersection.clone(),
+                        _expected.iter().collect(),
+                    );
+                    if _multiset.0.len() != _multiset.1.len()
+                        || (&_multiset).first_count_mismatch().is_some()
+                    {
+                        panic!(
+                            "Expected intersection to match {:?}, but it was {:?}\n{}",
+                            _expected,
+                            _intersection,
+                            format_args!($($arg)*)
+                        );
+                    }
+                }
+            }
+        }};
+    }
+
+    /// Checks that the union of `left` and `right`, with duplicates removed, has
+    /// exactly `expected` elements. Accepts collections or iterators like
+    /// [`assert_unordered_match!`].
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_union_len;
+    /// let first = vec!["key1", "key2"];
+    /// let second = vec!["key2", "key3", "key3"];
+    /// assert_union_len!(first, second, 3);
+    /// ```
+    #[macro_export]
+    macro_rules! assert_union_len {
+        ($left:expr, $right:expr, $expected:expr $(,) ?) => {{
+            match (&$left, &$right, $expected) {
+                (left_val, right_val, expected_val) => {
+                    #[allow(unused_imports)]
+                    use $crate::asserts::__private::{CloneSource as _, RefSource as _};
+                    let _left = (&$crate::asserts::__private::Source(left_val)).materialize();
+                    let _right = (&$crate::asserts::__private::Source(right_val)).materialize();
+                    let _union = $crate::asserts::__private::union(&_left, &_right);
+                    if _union.len() != expected_val {
+                        panic!(
+                            "Expected union to have {} elements, but it has {}: {:?}",
+                            expected_val,
+                            _union.len(),
+                            _union
+                        );
+                    }
+                }
+            }
+        }};
+        ($left:expr, $right:expr, $expected:expr, $($arg:tt) +) => {{
+            match (&$left, &$right, $expected) {
+                (left_val, right_val, expected_val) => {
+                    #[allow(unused_imports)]
+                    use $crate::asserts::__private::{CloneSource as _, RefSource as _};
+                    let _left = (&$crate::asserts::__private::Source(left_val)).materialize();
+                    let _right = (&$crate::asserts::__private::Source(right_val)).materialize();
+                    let _union = $crate::asserts::__private::union(&_left, &_right);
+                    if _union.len() != expected_val {
+                        panic!(
+                            "Expected union to have {} elements, but it has {}: {:?}\n{}",
+                            expected_val,
+                            _union.len(),
+                            _union,
+                            format_args!($($arg)*)
                         );
                     }
                 }
@@ -40,7 +323,14 @@ pub mod asserts {
     }
 
     /// Macro to check if the contents of the first argument are equal to the contents
-    /// of the second, regardless of the order.
+    /// of the second, regardless of the order. Duplicates are taken into account, so
+    /// each element must appear the same number of times in both collections.
+    /// Elements are counted with a `HashMap` when they implement `Hash + Eq`,
+    /// otherwise the (quadratic) `PartialEq` fallback is used. A custom message is
+    /// printed on its own line after the diagnostic. Iterators such as
+    /// `map.keys()` can be passed as well as collections; they are cloned and
+    /// collected once. Both sides must yield the same item type, so compare an
+    /// iterator of values with `collection.into_iter()` rather than a reference.
     ///
     /// ## Example(s)
     /// ```rust
@@ -51,16 +341,42 @@ pub mod asserts {
     /// ```
     #[macro_export]
     macro_rules! assert_unordered_match {
+        ($left:expr, $right:expr $(,) ?) => {{
+            if let Err(msg) = $crate::check_unordered_match!($left, $right) {
+                panic!("{}", msg);
+            }
+        }};
+        ($left:expr, $right:expr, $($arg:tt) +) => {{
+            if let Err(msg) = $crate::check_unordered_match!($left, $right, $($arg)*) {
+                panic!("{}", msg);
+            }
+        }};
+    }
+
+    /// Checks that `left` and `right` contain equal elements in the same order.
+    /// Any mix of collections (`Vec`, arrays, `LinkedList`, ...) or iterators can
+    /// be compared. On failure every mismatching index is listed as
+    /// `idx: left != right`, followed by any extra elements on the longer side.
+    /// A custom message is printed on its own line after the diff.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_ordered_match;
+    /// let first = vec![1, 2, 3];
+    /// let second = std::collections::LinkedList::from([1, 2, 3]);
+    /// assert_ordered_match!(first, second);
+    /// ```
+    #[macro_export]
+    macro_rules! assert_ordered_match {
         ($left:expr, $right:expr $(,) ?) => {{
             match (&$left, &$right) {
                 (left_val, right_val) => {
-                    {
-                        let _lcount = left_val.iter().count();
-                        let _rcount = right_val.iter().count();
-                        assert_eq!(_lcount, _rcount, "Expected: size of left and right collections to be equal, Actual: left = {}, right = {}", _lcount, _rcount);
-                    }
-                    for n in left_val.iter() {
-                        assert!(right_val.contains(n), "Expected right side collection to contain value {:?} from left side collection", n);
+                    #[allow(unused_imports)]
+                    use $crate::asserts::__private::{CloneSource as _, RefSource as _};
+                    let _left = (&$crate::asserts::__private::Source(left_val)).materialize();
+                    let _right = (&$crate::asserts::__private::Source(right_val)).materialize();
+                    if let Some(diff) = $crate::asserts::__private::ordered_diff(&_left, &_right) {
+                        panic!("{}", diff);
                     }
                 }
             }
@@ -68,20 +384,22 @@ pub mod asserts {
         ($left:expr, $right:expr, $($arg:tt) +) => {{
             match (&$left, &$right) {
                 (left_val, right_val) => {
-                    {
-                        let _lcount = left_val.iter().count();
-                        let _rcount = right_val.iter().count();
-                        assert_eq!(_lcount, _rcount, $($arg)*);
-                    }
-                    for n in left_val.iter() {
-                        assert!(right_val.contains(n), $($arg)*);
+                    #[allow(unused_imports)]
+                    use $crate::asserts::__private::{CloneSource as _, RefSource as _};
+                    let _left = (&$crate::asserts::__private::Source(left_val)).materialize();
+                    let _right = (&$crate::asserts::__private::Source(right_val)).materialize();
+                    if let Some(diff) = $crate::asserts::__private::ordered_diff(&_left, &_right) {
+                        panic!("{}\n{}", diff, format_args!($($arg)*));
                     }
                 }
             }
         }};
     }
 
-    /// Macro to check if the `actual` value is contained in the `range` given.
+    /// Macro to check if the `actual` value is contained in the `range` given. Any
+    /// of the std range types can be used (`a..b`, `a..=b`, `a..`, `..b`, `..=b`
+    /// and `..`). A custom message is printed on its own line after the
+    /// diagnostic.
     ///
     /// ## Example(s)
     /// ```rust
@@ -93,11 +411,35 @@ pub mod asserts {
     #[macro_export]
     macro_rules! assert_in_range {
         ($actual:expr, $range:expr $(,) ?) => {{
-            match(&$actual, &$range) {
+            if let Err(msg) = $crate::check_in_range!($actual, $range) {
+                panic!("{}", msg);
+            }
+        }};
+        ($actual:expr, $range:expr, $($arg:tt) +) => {{
+            if let Err(msg) = $crate::check_in_range!($actual, $range, $($arg)*) {
+                panic!("{}", msg);
+            }
+        }};
+    }
+
+    /// Macro to check that the `actual` value is NOT contained in the `range` given.
+    /// Accepts the same range types as [`assert_in_range!`].
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_not_in_range;
+    /// let range = 0..10;
+    /// let i = 10;
+    /// assert_not_in_range!(i, range, "Range should not contain i");
+    /// ```
+    #[macro_export]
+    macro_rules! assert_not_in_range {
+        ($actual:expr, $range:expr $(,) ?) => {{
+            match (&$actual, &$range) {
                 (actual_val, range_val) => {
-                    if !(*range_val).contains(actual_val) {
+                    if $crate::asserts::__private::range_contains(range_val, actual_val) {
                         panic!(
-                            "Expected range {:?} to contain {:?}",
+                            "Expected range {:?} to NOT contain {:?}",
                             &*range_val, &*actual_val
                         );
                     }
@@ -105,209 +447,4974 @@ pub mod asserts {
             }
         }};
         ($actual:expr, $range:expr, $($arg:tt) +) => {{
-            match(&$actual, &$range) {
+            match (&$actual, &$range) {
                 (actual_val, range_val) => {
-                    if !(*range_val).contains(actual_val) {
-                        panic!($($arg)*);
+                    if $crate::asserts::__private::range_contains(range_val, actual_val) {
+                        panic!(
+                            "Expected range {:?} to NOT contain {:?}\n{}",
+                            &*range_val,
+                            &*actual_val,
+                            format_args!($($arg)*)
+                        );
                     }
                 }
             }
         }};
     }
 
-    /// Macro to check if the `actual` value is gte `min` and lte `max`.
+    /// Checks that `collection` contains exactly `expected` elements. Anything that
+    /// implements `IntoIterator` by reference can be used. When the check fails the
+    /// collection is included in the message if it has fewer than 20 elements.
     ///
     /// ## Example(s)
     /// ```rust
-    /// # use stupid_utils::assert_within;
-    /// let i = 5;
-    /// assert_within!(i, 0, 10, "i should be within 0..10");
+    /// # use stupid_utils::assert_len;
+    /// let values = vec![1, 2, 3];
+    /// assert_len!(values, 3);
     /// ```
     #[macro_export]
-    macro_rules! assert_within {
-        ($actual:expr, $min:expr, $max:expr $(,) ?) => {{
-            assert!($actual >= $min, "Expected {:?} to be gte {:?}", $actual, $min);
-            assert!($actual <= $max, "Expected {:?} to be lte {:?}", $actual, $max);
+    macro_rules! assert_len {
+        ($collection:expr, $expected:expr $(,) ?) => {{
+            match (&$collection, $expected) {
+                (collection_val, expected_val) => {
+                    let _actual = ::std::iter::IntoIterator::into_iter(collection_val).count();
+                    if _actual != expected_val {
+                        if _actual < $crate::asserts::__private::MAX_DISPLAYED_LEN {
+                            panic!(
+                                "Expected collection to have {} elements, found {}: {:?}",
+                                expected_val, _actual, collection_val
+                            );
+                        } else {
+                            panic!(
+                                "Expected collection to have {} elements, found {}",
+                                expected_val, _actual
+                            );
+                        }
+                    }
+                }
+            }
         }};
-        ($actual:expr, $min:expr, $max:expr, $($arg:tt) +) => {{
-            assert!($actual >= $min, $($arg)*);
-            assert!($actual <= $max, $($arg)*);
+        ($collection:expr, $expected:expr, $($arg:tt) +) => {{
+            match (&$collection, $expected) {
+                (collection_val, expected_val) => {
+                    let _actual = ::std::iter::IntoIterator::into_iter(collection_val).count();
+                    if _actual != expected_val {
+                        if _actual < $crate::asserts::__private::MAX_DISPLAYED_LEN {
+                            panic!(
+                                "Expected collection to have {} elements, found {}: {:?}\n{}",
+                                expected_val,
+                                _actual,
+                                collection_val,
+                                format_args!($($arg)*)
+                            );
+                        } else {
+                            panic!(
+                                "Expected collection to have {} elements, found {}\n{}",
+                                expected_val,
+                                _actual,
+                                format_args!($($arg)*)
+                            );
+                        }
+                    }
+                }
+            }
         }};
     }
 
-    #[cfg(test)]
-    mod tests {
-        #[test]
-        fn contains_all() {
-            let first = vec![1, 2, 3];
-            let second = vec![5, 4, 3, 2, 1];
-            assert_contains_all!(first, second);
-            assert_contains_all!(
-                first,
-                second,
-                "Expected {:?} to contain {:?}",
-                second,
-                first
-            );
-        }
-
-        #[test]
-        #[should_panic]
-        fn not_contains_all() {
-            let first = vec![1, 2, 3];
-            let second = vec![5, 4, 3, 2, 1];
-            assert_contains_all!(second, first);
-        }
+    /// Checks that `collection` has no elements. Anything that implements
+    /// `IntoIterator` by reference can be used, as well as `str` and `String`,
+    /// which are treated as collections of `char`s. On failure the number of
+    /// elements and the first ten of them are printed.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_empty;
+    /// let values: Vec<i32> = Vec::new();
+    /// assert_empty!(values);
+    /// assert_empty!("");
+    /// ```
+    #[macro_export]
+    macro_rules! assert_empty {
+        ($collection:expr $(,) ?) => {{
+            match &$collection {
+                collection_val => {
+                    #[allow(unused_imports)]
+                    use $crate::asserts::__private::{IterElements as _, StrElements as _};
+                    let (_count, _preview) =
+                        (&$crate::asserts::__private::Elements(collection_val)).preview();
+                    if _count != 0 {
+                        panic!(
+                            "Expected collection to be empty, found {} elements: {}",
+                            _count, _preview
+                        );
+                    }
+                }
+            }
+        }};
+        ($collection:expr, $($arg:tt) +) => {{
+            match &$collection {
+                collection_val => {
+                    #[allow(unused_imports)]
+                    use $crate::asserts::__private::{IterElements as _, StrElements as _};
+                    let (_count, _preview) =
+                        (&$crate::asserts::__private::Elements(collection_val)).preview();
+                    if _count != 0 {
+                        panic!(
+                            "Expected collection to be empty, found {} elements: {}\n{}",
+                            _count,
+                            _preview,
+                            format_args!($($arg)*)
+                        );
+                    }
+                }
+            }
+        }};
+    }
 
-        #[test]
-        #[should_panic]
-        fn not_contains_all2() {
-            let first = vec![1, 2, 3];
-            let second = vec![5, 4, 3, 2, 1];
-            assert_contains_all!(
-                second,
-                first,
-                "Expected {:?} to contain {:?}",
-                second,
-                first
-            );
-        }
+    /// Checks that `collection` has at least one element. Accepts the same types as
+    /// [`assert_empty!`].
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_not_empty;
+    /// assert_not_empty!(vec![1]);
+    /// assert_not_empty!(String::from("a"));
+    /// ```
+    #[macro_export]
+    macro_rules! assert_not_empty {
+        ($collection:expr $(,) ?) => {{
+            match &$collection {
+                collection_val => {
+                    #[allow(unused_imports)]
+                    use $crate::asserts::__private::{IterElements as _, StrElements as _};
+                    let (_count, _) =
+                        (&$crate::asserts::__private::Elements(collection_val)).preview();
+                    assert!(_count != 0, "Expected collection to not be empty");
+                }
+            }
+        }};
+        ($collection:expr, $($arg:tt) +) => {{
+            match &$collection {
+                collection_val => {
+                    #[allow(unused_imports)]
+                    use $crate::asserts::__private::{IterElements as _, StrElements as _};
+                    let (_count, _) =
+                        (&$crate::asserts::__private::Elements(collection_val)).preview();
+                    assert!(
+                        _count != 0,
+                        "Expected collection to not be empty\n{}",
+                        format_args!($($arg)*)
+                    );
+                }
+            }
+        }};
+    }
 
-        #[test]
-        fn unordered_match() {
-            let first = vec![1, 2, 3];
-            let second = vec![3, 2, 1];
-            assert_unordered_match!(first, second);
-            assert_unordered_match!(second, first);
-            assert_unordered_match!(
-                first,
-                second,
-                "Expected {:?} to match contents of {:?}",
-                second,
-                first
-            );
-            assert_unordered_match!(
-                second,
-                first,
-                "Expected {:?} to match contents of {:?}",
-                second,
-                first
-            );
-        }
+    /// Checks that every element of `collection` satisfies `predicate`, which is
+    /// called with a reference to each element. On failure every element that
+    /// did not satisfy the predicate is listed along with its index.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_all;
+    /// let keys = vec!["key1", "key2"];
+    /// assert_all!(keys, |key| key.starts_with("key"));
+    /// ```
+    #[macro_export]
+    macro_rules! assert_all {
+        ($collection:expr, $predicate:expr $(,) ?) => {{
+            match &$collection {
+                collection_val => {
+                    let _failed =
+                        $crate::asserts::__private::failing(collection_val.iter(), $predicate);
+                    if !_failed.is_empty() {
+                        let _details: Vec<String> = _failed
+                            .iter()
+                            .map(|(i, item)| format!("[{}] {:?}", i, item))
+                            .collect();
+                        panic!(
+                            "Expected all elements to satisfy the predicate, {} did not: {}",
+                            _failed.len(),
+                            _details.join(", ")
+                        );
+                    }
+                }
+            }
+        }};
+        ($collection:expr, $predicate:expr, $($arg:tt) +) => {{
+            match &$collection {
+                collection_val => {
+                    let _failed =
+                        $crate::asserts::__private::failing(collection_val.iter(), $predicate);
+                    if !_failed.is_empty() {
+                        let _details: Vec<String> = _failed
+                            .iter()
+                            .map(|(i, item)| format!("[{}] {:?}", i, item))
+                            .collect();
+                        panic!(
+                            "Expected all elements to satisfy the predicate, {} did not: {}\n{}",
+                            _failed.len(),
+                            _details.join(", "),
+                            format_args!($($arg)*)
+                        );
+                    }
+                }
+            }
+        }};
+    }
 
-        #[test]
-        #[should_panic]
-        fn not_unordered_match() {
-            let first = vec![1, 2, 3];
+    /// Checks that at least one element of `collection` satisfies `predicate`,
+    /// which is called with a reference to each element. An empty collection
+    /// always fails.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_any;
+    /// let values = vec![1, 2, 3];
+    /// assert_any!(values, |n| *n > 2);
+    /// ```
+    #[macro_export]
+    macro_rules! assert_any {
+        ($collection:expr, $predicate:expr $(,) ?) => {{
+            match &$collection {
+                collection_val => {
+                    assert!(
+                        $crate::asserts::__private::any(collection_val.iter(), $predicate),
+                        "Expected at least one element of {:?} to satisfy the predicate",
+                        collection_val
+                    );
+                }
+            }
+        }};
+        ($collection:expr, $predicate:expr, $($arg:tt) +) => {{
+            match &$collection {
+                collection_val => {
+                    assert!(
+                        $crate::asserts::__private::any(collection_val.iter(), $predicate),
+                        "Expected at least one element of {:?} to satisfy the predicate\n{}",
+                        collection_val,
+                        format_args!($($arg)*)
+                    );
+                }
+            }
+        }};
+    }
+
+    /// Checks that no value appears more than once in `collection`. Only requires
+    /// `PartialEq`, so elements are compared pairwise. On failure every duplicated
+    /// value is listed along with how many times it appeared.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_unique;
+    /// let keys = vec!["key1", "key2", "key3"];
+    /// assert_unique!(keys);
+    /// ```
+    #[macro_export]
+    macro_rules! assert_unique {
+        ($collection:expr $(,) ?) => {{
+            match &$collection {
+                collection_val => {
+                    let _dupes = $crate::asserts::__private::duplicates(collection_val.iter());
+                    if !_dupes.is_empty() {
+                        let _details: Vec<String> = _dupes
+                            .iter()
+                            .map(|(value, count)| format!("{:?} (x{})", value, count))
+                            .collect();
+                        panic!(
+                            "Expected all elements to be unique, found duplicates: {}",
+                            _details.join(", ")
+                        );
+                    }
+                }
+            }
+        }};
+        ($collection:expr, $($arg:tt) +) => {{
+            match &$collection {
+                collection_val => {
+                    let _dupes = $crate::asserts::__private::duplicates(collection_val.iter());
+                    if !_dupes.is_empty() {
+                        let _details: Vec<String> = _dupes
+                            .iter()
+                            .map(|(value, count)| format!("{:?} (x{})", value, count))
+                            .collect();
+                        panic!(
+                            "Expected all elements to be unique, found duplicates: {}\n{}",
+                            _details.join(", "),
+                            format_args!($($arg)*)
+                        );
+                    }
+                }
+            }
+        }};
+    }
+
+    /// Checks that the elements of `collection` are in non-decreasing order
+    /// according to `PartialOrd`.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_sorted;
+    /// let values = vec![1, 2, 2, 3];
+    /// assert_sorted!(values);
+    /// ```
+    #[macro_export]
+    macro_rules! assert_sorted {
+        ($collection:expr $(,) ?) => {{
+            match &$collection {
+                collection_val => {
+                    if let Some((i, left, right)) =
+                        $crate::asserts::__private::first_unsorted(collection_val.iter())
+                    {
+                        panic!(
+                            "Expected collection to be sorted, but element {} ({:?}) is out of order with element {} ({:?})",
+                            i,
+                            left,
+                            i + 1,
+                            right
+                        );
+                    }
+                }
+            }
+        }};
+        ($collection:expr, $($arg:tt) +) => {{
+            match &$collection {
+                collection_val => {
+                    if let Some((i, left, right)) =
+                        $crate::asserts::__private::first_unsorted(collection_val.iter())
+                    {
+                        panic!(
+                            "Expected collection to be sorted, but element {} ({:?}) is out of order with element {} ({:?})\n{}",
+                            i,
+                            left,
+                            i + 1,
+                            right,
+                            format_args!($($arg)*)
+                        );
+                    }
+                }
+            }
+        }};
+    }
+
+    /// Checks that the elements of `collection` are sorted according to
+    /// `comparator`, which is called the same way `slice::sort_by` would call it.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_sorted_by;
+    /// let values = vec![3, 2, 2, 1];
+    /// assert_sorted_by!(values, |a, b| b.cmp(a));
+    /// ```
+    #[macro_export]
+    macro_rules! assert_sorted_by {
+        ($collection:expr, $comparator:expr $(,) ?) => {{
+            match &$collection {
+                collection_val => {
+                    if let Some((i, left, right)) = $crate::asserts::__private::first_unsorted_by(
+                        collection_val.iter(),
+                        $comparator,
+                    ) {
+                        panic!(
+                            "Expected collection to be sorted, but element {} ({:?}) is out of order with element {} ({:?})",
+                            i,
+                            left,
+                            i + 1,
+                            right
+                        );
+                    }
+                }
+            }
+        }};
+        ($collection:expr, $comparator:expr, $($arg:tt) +) => {{
+            match &$collection {
+                collection_val => {
+                    if let Some((i, left, right)) = $crate::asserts::__private::first_unsorted_by(
+                        collection_val.iter(),
+                        $comparator,
+                    ) {
+                        panic!(
+                            "Expected collection to be sorted, but element {} ({:?}) is out of order with element {} ({:?})\n{}",
+                            i,
+                            left,
+                            i + 1,
+                            right,
+                            format_args!($($arg)*)
+                        );
+                    }
+                }
+            }
+        }};
+    }
+
+    /// Checks that each element of `iterable` is gte the previous one. Takes
+    /// anything that implements `IntoIterator` (by value), so mapped iterators
+    /// like `rows.iter().map(|row| row.updated())` can be passed directly.
+    /// Empty and single element sequences always pass.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_monotonic;
+    /// let timestamps = vec![1, 2, 2, 5];
+    /// assert_monotonic!(&timestamps);
+    /// assert_monotonic!(timestamps.iter().map(|ts| ts * 1000));
+    /// ```
+    #[macro_export]
+    macro_rules! assert_monotonic {
+        ($iterable:expr $(,) ?) => {{
+            if let Some((i, prev, next)) =
+                $crate::asserts::__private::first_non_monotonic($iterable, false)
+            {
+                panic!(
+                    "Expected sequence to be monotonically increasing, but element {} ({:?}) is greater than element {} ({:?})",
+                    i,
+                    prev,
+                    i + 1,
+                    next
+                );
+            }
+        }};
+        ($iterable:expr, $($arg:tt) +) => {{
+            if let Some((i, prev, next)) =
+                $crate::asserts::__private::first_non_monotonic($iterable, false)
+            {
+                panic!(
+                    "Expected sequence to be monotonically increasing, but element {} ({:?}) is greater than element {} ({:?})\n{}",
+                    i,
+                    prev,
+                    i + 1,
+                    next,
+                    format_args!($($arg)*)
+                );
+            }
+        }};
+    }
+
+    /// Checks that each element of `iterable` is strictly greater than the
+    /// previous one. Accepts the same arguments as [`assert_monotonic!`].
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_strictly_monotonic;
+    /// assert_strictly_monotonic!(vec![1, 2, 3], "sequence numbers must not repeat");
+    /// ```
+    #[macro_export]
+    macro_rules! assert_strictly_monotonic {
+        ($iterable:expr $(,) ?) => {{
+            if let Some((i, prev, next)) =
+                $crate::asserts::__private::first_non_monotonic($iterable, true)
+            {
+                panic!(
+                    "Expected sequence to be strictly increasing, but element {} ({:?}) is not less than element {} ({:?})",
+                    i,
+                    prev,
+                    i + 1,
+                    next
+                );
+            }
+        }};
+        ($iterable:expr, $($arg:tt) +) => {{
+            if let Some((i, prev, next)) =
+                $crate::asserts::__private::first_non_monotonic($iterable, true)
+            {
+                panic!(
+                    "Expected sequence to be strictly increasing, but element {} ({:?}) is not less than element {} ({:?})\n{}",
+                    i,
+                    prev,
+                    i + 1,
+                    next,
+                    format_args!($($arg)*)
+                );
+            }
+        }};
+    }
+
+    /// Checks that two iterables yield equal items in the same order. Both sides
+    /// are walked in lockstep without being collected, and the first differing
+    /// index is reported, or the lengths of both sides if one ends early.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_iter_eq;
+    /// let values = vec![2, 4, 6];
+    /// assert_iter_eq!(values.iter().copied(), (1..=3).map(|i| i * 2));
+    /// assert_iter_eq!(&values, &[2, 4, 6], "values should be doubled");
+    /// ```
+    #[macro_export]
+    macro_rules! assert_iter_eq {
+        ($left:expr, $right:expr $(,) ?) => {{
+            if let Some(mismatch) = $crate::asserts::__private::iter_mismatch($left, $right) {
+                panic!("{}", mismatch);
+            }
+        }};
+        ($left:expr, $right:expr, $($arg:tt) +) => {{
+            if let Some(mismatch) = $crate::asserts::__private::iter_mismatch($left, $right) {
+                panic!("{}\n{}", mismatch, format_args!($($arg)*));
+            }
+        }};
+    }
+
+    /// Macro to check if the `actual` value is gte `min` and lte `max`. Each argument
+    /// is evaluated exactly once. A custom message is printed on its own line after
+    /// the diagnostic.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_within;
+    /// let i = 5;
+    /// assert_within!(i, 0, 10, "i should be within 0..10");
+    /// ```
+    #[macro_export]
+    macro_rules! assert_within {
+        ($actual:expr, $min:expr, $max:expr $(,) ?) => {{
+            if let Err(msg) = $crate::check_within!($actual, $min, $max) {
+                panic!("{}", msg);
+            }
+        }};
+        ($actual:expr, $min:expr, $max:expr, $($arg:tt) +) => {{
+            if let Err(msg) = $crate::check_within!($actual, $min, $max, $($arg)*) {
+                panic!("{}", msg);
+            }
+        }};
+    }
+
+    /// Macro to check if the `actual` value is strictly greater than `low` and
+    /// strictly less than `high`. Each argument is evaluated exactly once.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_strictly_between;
+    /// let created = 100;
+    /// assert_strictly_between!(created, 99, 101, "created should be between the two events");
+    /// ```
+    #[macro_export]
+    macro_rules! assert_strictly_between {
+        ($actual:expr, $low:expr, $high:expr $(,) ?) => {
+            $crate::__assert_bounds!($actual, $low, false, $high, false)
+        };
+        ($actual:expr, $low:expr, $high:expr, $($arg:tt) +) => {
+            $crate::__assert_bounds!($actual, $low, false, $high, false, $($arg)*)
+        };
+    }
+
+    /// Macro to check if the `actual` value is gt `low` and lte `high`. Each
+    /// argument is evaluated exactly once.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_gt_lte;
+    /// assert_gt_lte!(10, 0, 10);
+    /// ```
+    #[macro_export]
+    macro_rules! assert_gt_lte {
+        ($actual:expr, $low:expr, $high:expr $(,) ?) => {
+            $crate::__assert_bounds!($actual, $low, false, $high, true)
+        };
+        ($actual:expr, $low:expr, $high:expr, $($arg:tt) +) => {
+            $crate::__assert_bounds!($actual, $low, false, $high, true, $($arg)*)
+        };
+    }
+
+    /// Macro to check if the `actual` value is gte `low` and lt `high`. Each
+    /// argument is evaluated exactly once.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_gte_lt;
+    /// assert_gte_lt!(0, 0, 10);
+    /// ```
+    #[macro_export]
+    macro_rules! assert_gte_lt {
+        ($actual:expr, $low:expr, $high:expr $(,) ?) => {
+            $crate::__assert_bounds!($actual, $low, true, $high, false)
+        };
+        ($actual:expr, $low:expr, $high:expr, $($arg:tt) +) => {
+            $crate::__assert_bounds!($actual, $low, true, $high, false, $($arg)*)
+        };
+    }
+
+    #[doc(hidden)]
+    #[macro_export]
+    macro_rules! __assert_bounds {
+        ($actual:expr, $low:expr, $low_inclusive:expr, $high:expr, $high_inclusive:expr $(,) ?) => {{
+            match (&$actual, &$low, &$high) {
+                (actual_val, low_val, high_val) => {
+                    if let Some(violation) = $crate::asserts::__private::bound_violation(
+                        actual_val,
+                        low_val,
+                        $low_inclusive,
+                        high_val,
+                        $high_inclusive,
+                    ) {
+                        panic!("{}", violation);
+                    }
+                }
+            }
+        }};
+        ($actual:expr, $low:expr, $low_inclusive:expr, $high:expr, $high_inclusive:expr, $($arg:tt) +) => {{
+            match (&$actual, &$low, &$high) {
+                (actual_val, low_val, high_val) => {
+                    if let Some(violation) = $crate::asserts::__private::bound_violation(
+                        actual_val,
+                        low_val,
+                        $low_inclusive,
+                        high_val,
+                        $high_inclusive,
+                    ) {
+                        panic!("{}\n{}", violation, format_args!($($arg)*));
+                    }
+                }
+            }
+        }};
+    }
+
+    /// Checks that two unix timestamps (in seconds) are no more than `tolerance`
+    /// seconds apart, 2 by default. A tolerance wrapped in [`Millis`] reads both
+    /// timestamps as milliseconds instead. On failure both timestamps are also
+    /// shown in RFC 3339 form along with the difference between them. To add a
+    /// custom message the tolerance must be given explicitly.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::{assert_time_close, asserts::Millis};
+    /// let now = 1_650_000_000_i64;
+    /// assert_time_close!(now + 1, now);
+    /// assert_time_close!(now - 5, now, 5);
+    /// assert_time_close!(now, now, 0, "timestamps should be identical");
+    /// assert_time_close!(now * 1000 + 250, now * 1000, Millis(500));
+    /// ```
+    #[macro_export]
+    macro_rules! assert_time_close {
+        ($actual:expr, $expected:expr $(,) ?) => {
+            $crate::assert_time_close!($actual, $expected, 2)
+        };
+        ($actual:expr, $expected:expr, $tolerance:expr $(,) ?) => {{
+            if let Some(violation) =
+                $crate::asserts::__private::time_distance_violation($actual, $expected, $tolerance)
+            {
+                panic!("{}", violation);
+            }
+        }};
+        ($actual:expr, $expected:expr, $tolerance:expr, $($arg:tt) +) => {{
+            if let Some(violation) =
+                $crate::asserts::__private::time_distance_violation($actual, $expected, $tolerance)
+            {
+                panic!("{}\n{}", violation, format_args!($($arg)*));
+            }
+        }};
+    }
+
+    /// Checks that `expr` evaluates to `Ok` and evaluates to the unwrapped value
+    /// so further assertions can be chained. An optional pattern checks the
+    /// shape of the `Ok` value as well.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_ok;
+    /// let result: Result<i32, String> = Ok(5);
+    /// let value = assert_ok!(result);
+    /// assert_eq!(value, 5);
+    /// assert_ok!("42".parse::<i32>(), 40..=50);
+    /// let name = assert_ok!(Ok::<_, ()>(Some(String::from("x"))), Some(s));
+    /// assert_eq!(name.as_deref(), Some("x"));
+    /// ```
+    #[macro_export]
+    macro_rules! assert_ok {
+        ($expr:expr $(,) ?) => {{
+            match $expr {
+                Ok(value) => value,
+                Err(err) => panic!("Expected Ok, got Err({:?})", err),
+            }
+        }};
+        ($expr:expr, $pat:pat $(,) ?) => {{
+            match $expr {
+                Ok(value) => {
+                    // Matched by reference so bindings in `$pat` don't move
+                    // out of `value`, which is still returned.
+                    #[allow(unused_variables)]
+                    let matched = matches!(&value, $pat);
+                    if !matched {
+                        panic!(
+                            "Expected Ok matching `{}`, got Ok({:?})",
+                            stringify!($pat),
+                            value
+                        );
+                    }
+                    value
+                }
+                Err(err) => panic!(
+                    "Expected Ok matching `{}`, got Err({:?})",
+                    stringify!($pat),
+                    err
+                ),
+            }
+        }};
+    }
+
+    /// Checks that `expr` evaluates to `Err` and evaluates to the unwrapped error.
+    /// An optional pattern checks that the error is the expected variant.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_err;
+    /// let result: Result<i32, std::num::ParseIntError> = "nope".parse::<i32>();
+    /// let err = assert_err!(result);
+    /// assert_eq!(err.to_string(), "invalid digit found in string");
+    /// let result: Result<(), Option<u8>> = Err(Some(1));
+    /// assert_err!(result, Some(_));
+    /// let err = assert_err!(Err::<(), _>(Some(String::from("x"))), Some(s));
+    /// assert_eq!(err.as_deref(), Some("x"));
+    /// ```
+    #[macro_export]
+    macro_rules! assert_err {
+        ($expr:expr $(,) ?) => {{
+            match $expr {
+                Err(err) => err,
+                Ok(value) => panic!("Expected Err, got Ok({:?})", value),
+            }
+        }};
+        ($expr:expr, $pat:pat $(,) ?) => {{
+            match $expr {
+                Err(err) => {
+                    // See `assert_ok`.
+                    #[allow(unused_variables)]
+                    let matched = matches!(&err, $pat);
+                    if !matched {
+                        panic!(
+                            "Expected Err matching `{}`, got Err({:?})",
+                            stringify!($pat),
+                            err
+                        );
+                    }
+                    err
+                }
+                Ok(value) => panic!(
+                    "Expected Err matching `{}`, got Ok({:?})",
+                    stringify!($pat),
+                    value
+                ),
+            }
+        }};
+    }
+
+    /// Checks that `expr` evaluates to `Err` whose `Display` output contains
+    /// `expected`, and evaluates to the error. Pass an array to require several
+    /// substrings at once. The full rendered message is printed on failure.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_err_display;
+    /// let result: Result<i32, String> = Err("key 'foo' not found".to_string());
+    /// assert_err_display!(result.clone(), "not found");
+    /// let err = assert_err_display!(result, ["'foo'", "not found"]);
+    /// assert!(err.starts_with("key"));
+    /// ```
+    #[macro_export]
+    macro_rules! assert_err_display {
+        ($expr:expr, [$($expected:expr),+ $(,) ?] $(,) ?) => {{
+            match $expr {
+                Err(err) => {
+                    let _rendered = err.to_string();
+                    let _missing: ::std::vec::Vec<::std::string::String> = [$(::std::convert::AsRef::<str>::as_ref(&$expected)),+]
+                        .into_iter()
+                        .filter(|needle| !_rendered.contains(needle))
+                        .map(::std::string::ToString::to_string)
+                        .collect();
+                    if !_missing.is_empty() {
+                        panic!(
+                            "Expected error message to contain {:?}, got {:?}",
+                            _missing, _rendered
+                        );
+                    }
+                    err
+                }
+                Ok(value) => panic!("Expected Err, got Ok({:?})", value),
+            }
+        }};
+        ($expr:expr, $expected:expr $(,) ?) => {
+            $crate::assert_err_display!($expr, [$expected])
+        };
+    }
+
+    /// Checks that `expr` evaluates to `Some` and evaluates to the unwrapped value
+    /// so further assertions can be chained.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_some;
+    /// let value = assert_some!("a=1".split_once('='));
+    /// assert_eq!(value, ("a", "1"));
+    /// ```
+    #[macro_export]
+    macro_rules! assert_some {
+        ($expr:expr $(,) ?) => {{
+            match $expr {
+                Some(value) => value,
+                None => panic!("Expected Some, got None"),
+            }
+        }};
+        ($expr:expr, $($arg:tt) +) => {{
+            match $expr {
+                Some(value) => value,
+                None => panic!("Expected Some, got None\n{}", format_args!($($arg)*)),
+            }
+        }};
+    }
+
+    /// Checks that `expr` evaluates to `Some` holding a value equal to `expected`.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_some_eq;
+    /// let values = vec![1, 2, 3];
+    /// assert_some_eq!(values.first(), &1);
+    /// assert_some_eq!(values.iter().max().copied(), 3, "max should be 3");
+    /// ```
+    #[macro_export]
+    macro_rules! assert_some_eq {
+        ($expr:expr, $expected:expr $(,) ?) => {{
+            match (&$expr, &$expected) {
+                (option_val, expected_val) => match option_val {
+                    Some(value) => {
+                        if !(*value == *expected_val) {
+                            panic!("Expected Some({:?}), got Some({:?})", expected_val, value);
+                        }
+                    }
+                    None => panic!("Expected Some({:?}), got None", expected_val),
+                },
+            }
+        }};
+        ($expr:expr, $expected:expr, $($arg:tt) +) => {{
+            match (&$expr, &$expected) {
+                (option_val, expected_val) => match option_val {
+                    Some(value) => {
+                        if !(*value == *expected_val) {
+                            panic!(
+                                "Expected Some({:?}), got Some({:?})\n{}",
+                                expected_val,
+                                value,
+                                format_args!($($arg)*)
+                            );
+                        }
+                    }
+                    None => panic!(
+                        "Expected Some({:?}), got None\n{}",
+                        expected_val,
+                        format_args!($($arg)*)
+                    ),
+                },
+            }
+        }};
+    }
+
+    /// Checks that `expr` evaluates to `None`, showing the contained value if it
+    /// doesn't.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_none;
+    /// let values: Vec<i32> = Vec::new();
+    /// assert_none!(values.first());
+    /// ```
+    #[macro_export]
+    macro_rules! assert_none {
+        ($expr:expr $(,) ?) => {{
+            if let Some(value) = &$expr {
+                panic!("Expected None, got Some({:?})", value);
+            }
+        }};
+        ($expr:expr, $($arg:tt) +) => {{
+            if let Some(value) = &$expr {
+                panic!("Expected None, got Some({:?})\n{}", value, format_args!($($arg)*));
+            }
+        }};
+    }
+
+    /// Checks that `haystack` matches the regular expression `pattern`. Patterns
+    /// are compiled with [`crate::pattern::Pattern`], which supports a small regex
+    /// subset; an invalid pattern panics with the reason it was rejected.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_matches_regex;
+    /// let msg = "key 'foo' not found";
+    /// assert_matches_regex!(msg, "key '.+' not found");
+    /// ```
+    #[macro_export]
+    macro_rules! assert_matches_regex {
+        ($haystack:expr, $pattern:expr $(,) ?) => {{
+            match (&$haystack, &$pattern) {
+                (haystack_val, pattern_val) => {
+                    let _haystack: &str = ::std::convert::AsRef::<str>::as_ref(haystack_val);
+                    let _pattern = $crate::asserts::__private::compile_pattern(pattern_val);
+                    assert!(
+                        _pattern.is_match(_haystack),
+                        "Expected {:?} to match pattern {:?}",
+                        _haystack,
+                        _pattern.as_str()
+                    );
+                }
+            }
+        }};
+        ($haystack:expr, $pattern:expr, $($arg:tt) +) => {{
+            match (&$haystack, &$pattern) {
+                (haystack_val, pattern_val) => {
+                    let _haystack: &str = ::std::convert::AsRef::<str>::as_ref(haystack_val);
+                    let _pattern = $crate::asserts::__private::compile_pattern(pattern_val);
+                    assert!(
+                        _pattern.is_match(_haystack),
+                        "Expected {:?} to match pattern {:?}\n{}",
+                        _haystack,
+                        _pattern.as_str(),
+                        format_args!($($arg)*)
+                    );
+                }
+            }
+        }};
+    }
+
+    /// Checks that `haystack` does not match the regular expression `pattern`. See
+    /// [`assert_matches_regex!`] for the supported syntax.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_not_matches_regex;
+    /// let msg = "key 'foo' already exists";
+    /// assert_not_matches_regex!(msg, "key '.+' not found");
+    /// ```
+    #[macro_export]
+    macro_rules! assert_not_matches_regex {
+        ($haystack:expr, $pattern:expr $(,) ?) => {{
+            match (&$haystack, &$pattern) {
+                (haystack_val, pattern_val) => {
+                    let _haystack: &str = ::std::convert::AsRef::<str>::as_ref(haystack_val);
+                    let _pattern = $crate::asserts::__private::compile_pattern(pattern_val);
+                    assert!(
+                        !_pattern.is_match(_haystack),
+                        "Expected {:?} to not match pattern {:?}",
+                        _haystack,
+                        _pattern.as_str()
+                    );
+                }
+            }
+        }};
+        ($haystack:expr, $pattern:expr, $($arg:tt) +) => {{
+            match (&$haystack, &$pattern) {
+                (haystack_val, pattern_val) => {
+                    let _haystack: &str = ::std::convert::AsRef::<str>::as_ref(haystack_val);
+                    let _pattern = $crate::asserts::__private::compile_pattern(pattern_val);
+                    assert!(
+                        !_pattern.is_match(_haystack),
+                        "Expected {:?} to not match pattern {:?}\n{}",
+                        _haystack,
+                        _pattern.as_str(),
+                        format_args!($($arg)*)
+                    );
+                }
+            }
+        }};
+    }
+
+    /// Checks that `haystack` starts with `prefix`. Strings are compared with
+    /// `str::starts_with`, anything else that can be viewed as a slice (`Vec<T>`,
+    /// `[T; N]`, `&[T]`) element-wise. The failure message shows the start of
+    /// `haystack`.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_starts_with;
+    /// assert_starts_with!("key1", "key");
+    /// assert_starts_with!(vec![1, 2, 3], [1, 2]);
+    /// assert_starts_with!(b"\x00\x01\x02", [0, 1], "header should come first");
+    /// ```
+    #[macro_export]
+    macro_rules! assert_starts_with {
+        ($haystack:expr, $prefix:expr $(,) ?) => {{
+            match (&$haystack, &$prefix) {
+                (haystack_val, prefix_val) => {
+                    #[allow(unused_imports)]
+                    use $crate::asserts::__private::{SliceAffix as _, StrAffix as _};
+                    let _affix = $crate::asserts::__private::Affix(haystack_val, prefix_val);
+                    if !(&_affix).is_prefix() {
+                        panic!(
+                            "Expected {} to start with {}",
+                            (&_affix).head(),
+                            (&_affix).affix()
+                        );
+                    }
+                }
+            }
+        }};
+        ($haystack:expr, $prefix:expr, $($arg:tt) +) => {{
+            match (&$haystack, &$prefix) {
+                (haystack_val, prefix_val) => {
+                    #[allow(unused_imports)]
+                    use $crate::asserts::__private::{SliceAffix as _, StrAffix as _};
+                    let _affix = $crate::asserts::__private::Affix(haystack_val, prefix_val);
+                    if !(&_affix).is_prefix() {
+                        panic!(
+                            "Expected {} to start with {}\n{}",
+                            (&_affix).head(),
+                            (&_affix).affix(),
+                            format_args!($($arg)*)
+                        );
+                    }
+                }
+            }
+        }};
+    }
+
+    /// Checks that `haystack` ends with `suffix`. Accepts the same types as
+    /// [`assert_starts_with!`]. The failure message shows the end of `haystack`.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_ends_with;
+    /// assert_ends_with!(String::from("backup.json"), ".json");
+    /// assert_ends_with!(vec![1, 2, 3], [2, 3], "last two should be 2 and 3");
+    /// ```
+    #[macro_export]
+    macro_rules! assert_ends_with {
+        ($haystack:expr, $suffix:expr $(,) ?) => {{
+            match (&$haystack, &$suffix) {
+                (haystack_val, suffix_val) => {
+                    #[allow(unused_imports)]
+                    use $crate::asserts::__private::{SliceAffix as _, StrAffix as _};
+                    let _affix = $crate::asserts::__private::Affix(haystack_val, suffix_val);
+                    if !(&_affix).is_suffix() {
+                        panic!(
+                            "Expected {} to end with {}",
+                            (&_affix).tail(),
+                            (&_affix).affix()
+                        );
+                    }
+                }
+            }
+        }};
+        ($haystack:expr, $suffix:expr, $($arg:tt) +) => {{
+            match (&$haystack, &$suffix) {
+                (haystack_val, suffix_val) => {
+                    #[allow(unused_imports)]
+                    use $crate::asserts::__private::{SliceAffix as _, StrAffix as _};
+                    let _affix = $crate::asserts::__private::Affix(haystack_val, suffix_val);
+                    if !(&_affix).is_suffix() {
+                        panic!(
+                            "Expected {} to end with {}\n{}",
+                            (&_affix).tail(),
+                            (&_affix).affix(),
+                            format_args!($($arg)*)
+                        );
+                    }
+                }
+            }
+        }};
+    }
+
+    /// Repeatedly evaluates `condition` until it is true, panicking if it is still
+    /// false after `timeout_ms` milliseconds. The condition is re-checked every
+    /// `interval_ms` milliseconds (10 by default). A custom message can be given
+    /// after an explicit interval.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_eventually;
+    /// let start = std::time::Instant::now();
+    /// assert_eventually!(start.elapsed().as_millis() >= 20, 1000);
+    /// assert_eventually!(start.elapsed().as_millis() >= 30, 1000, 5, "should reach 30ms");
+    /// ```
+    #[macro_export]
+    macro_rules! assert_eventually {
+        ($condition:expr, $timeout_ms:expr $(,) ?) => {
+            $crate::assert_eventually!($condition, $timeout_ms, 10)
+        };
+        ($condition:expr, $timeout_ms:expr, $interval_ms:expr $(,) ?) => {{
+            if let Err(elapsed) = $crate::asserts::__private::eventually(
+                || $condition,
+                $timeout_ms,
+                $interval_ms,
+            ) {
+                panic!(
+                    "Condition `{}` was still false after {:?} (timeout {}ms)",
+                    stringify!($condition),
+                    elapsed,
+                    $timeout_ms
+                );
+            }
+        }};
+        ($condition:expr, $timeout_ms:expr, $interval_ms:expr, $($arg:tt) +) => {{
+            if let Err(elapsed) = $crate::asserts::__private::eventually(
+                || $condition,
+                $timeout_ms,
+                $interval_ms,
+            ) {
+                panic!(
+                    "Condition `{}` was still false after {:?} (timeout {}ms)\n{}",
+                    stringify!($condition),
+                    elapsed,
+                    $timeout_ms,
+                    format_args!($($arg)*)
+                );
+            }
+        }};
+    }
+
+    /// Runs `closure` once for each iteration in `0..iterations`, passing it the
+    /// iteration number. If an iteration panics, the panic is re-raised with the
+    /// iteration number prepended. With the `seed` form the closure also gets a
+    /// distinct `u64` seed for each iteration (e.g. for `fastrand::Rng::with_seed`),
+    /// which is included in the failure message so it can be reproduced.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::repeat_assert;
+    /// repeat_assert!(10, |i| assert!(i < 10));
+    /// repeat_assert!(10, seed, |i, rng_seed| {
+    ///     assert!(i < 10, "seed {} should not matter", rng_seed);
+    /// });
+    /// ```
+    #[macro_export]
+    macro_rules! repeat_assert {
+        ($iterations:expr, seed, $closure:expr $(,) ?) => {
+            $crate::asserts::__private::repeat_seeded($iterations, $closure)
+        };
+        ($iterations:expr, $closure:expr $(,) ?) => {
+            $crate::asserts::__private::repeat($iterations, $closure)
+        };
+    }
+
+    /// Times `block` and checks that it finished within `max_ms` milliseconds,
+    /// evaluating to the block's value. With three arguments the block must also
+    /// take at least `min_ms` milliseconds, which helps catch paths that
+    /// accidentally do nothing. Meant for rough regression guards, so keep the
+    /// limits generous.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_duration_within;
+    /// let sum: u64 = assert_duration_within!(5_000, { (0..1000).sum() });
+    /// assert_eq!(sum, 499_500);
+    /// assert_duration_within!(10, 5_000, {
+    ///     std::thread::sleep(std::time::Duration::from_millis(20));
+    /// });
+    /// ```
+    #[macro_export]
+    macro_rules! assert_duration_within {
+        ($max_ms:expr, $block:expr $(,) ?) => {{
+            let _start = ::std::time::Instant::now();
+            let _value = $block;
+            if let Some(violation) =
+                $crate::asserts::__private::duration_violation(_start.elapsed(), 0, $max_ms)
+            {
+                panic!("{}", violation);
+            }
+            _value
+        }};
+        ($min_ms:expr, $max_ms:expr, $block:expr $(,) ?) => {{
+            let _start = ::std::time::Instant::now();
+            let _value = $block;
+            if let Some(violation) =
+                $crate::asserts::__private::duration_violation(_start.elapsed(), $min_ms, $max_ms)
+            {
+                panic!("{}", violation);
+            }
+            _value
+        }};
+    }
+
+    /// Checks that calling `closure` panics. Panics raised by the closure aren't
+    /// printed, so the expected panic doesn't clutter test output; panics on
+    /// other threads are still reported as usual.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_panics;
+    /// assert_panics!(|| panic!("boom"));
+    /// ```
+    #[macro_export]
+    macro_rules! assert_panics {
+        ($closure:expr $(,) ?) => {{
+            assert!(
+                $crate::asserts::__private::catch_panic($closure).is_some(),
+                "Expected closure to panic, but it returned normally"
+            );
+        }};
+        ($closure:expr, $($arg:tt) +) => {{
+            assert!(
+                $crate::asserts::__private::catch_panic($closure).is_some(),
+                "Expected closure to panic, but it returned normally\n{}",
+                format_args!($($arg)*)
+            );
+        }};
+    }
+
+    /// Checks that calling `closure` panics with a message containing `expected`.
+    /// Panic payloads that aren't a `String` or `&str` always fail the check. Like
+    /// [`assert_panics!`], the closure's panic isn't printed.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_panics_with;
+    /// assert_panics_with!(|| panic!("key '{}' not found", "foo"), "not found");
+    /// ```
+    #[macro_export]
+    macro_rules! assert_panics_with {
+        ($closure:expr, $expected:expr $(,) ?) => {{
+            match (&$expected,) {
+                (expected_val,) => {
+                    let _expected: &str = ::std::convert::AsRef::<str>::as_ref(expected_val);
+                    match $crate::asserts::__private::catch_panic($closure) {
+                        None => panic!(
+                            "Expected closure to panic with {:?}, but it returned normally",
+                            _expected
+                        ),
+                        Some(payload) => match $crate::asserts::__private::panic_message(&*payload) {
+                            Some(message) => assert!(
+                                message.contains(_expected),
+                                "Expected panic message to contain {:?}, got {:?}",
+                                _expected,
+                                message
+                            ),
+                            None => panic!(
+                                "Expected panic message to contain {:?}, but the payload was not a string",
+                                _expected
+                            ),
+                        },
+                    }
+                }
+            }
+        }};
+        ($closure:expr, $expected:expr, $($arg:tt) +) => {{
+            match (&$expected,) {
+                (expected_val,) => {
+                    let _expected: &str = ::std::convert::AsRef::<str>::as_ref(expected_val);
+                    match $crate::asserts::__private::catch_panic($closure) {
+                        None => panic!(
+                            "Expected closure to panic with {:?}, but it returned normally\n{}",
+                            _expected,
+                            format_args!($($arg)*)
+                        ),
+                        Some(payload) => match $crate::asserts::__private::panic_message(&*payload) {
+                            Some(message) => assert!(
+                                message.contains(_expected),
+                                "Expected panic message to contain {:?}, got {:?}\n{}",
+                                _expected,
+                                message,
+                                format_args!($($arg)*)
+                            ),
+                            None => panic!(
+                                "Expected panic message to contain {:?}, but the payload was not a string\n{}",
+                                _expected,
+                                format_args!($($arg)*)
+                            ),
+                        },
+                    }
+                }
+            }
+        }};
+    }
+
+    /// Checks that two byte buffers are equal. `Vec<u8>`, `&[u8]` and `[u8; N]`
+    /// can be mixed freely. On failure the first differing offset, both lengths
+    /// and a hex dump of the 16 bytes around the difference are printed; a custom
+    /// message is appended after the dump.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_bytes_eq;
+    /// let bytes = "abc".as_bytes().to_vec();
+    /// assert_bytes_eq!(bytes, b"abc");
+    /// assert_bytes_eq!(&bytes[..2], [0x61, 0x62], "slices work too");
+    /// ```
+    #[macro_export]
+    macro_rules! assert_bytes_eq {
+        ($left:expr, $right:expr $(,) ?) => {{
+            match (&$left, &$right) {
+                (left_val, right_val) => {
+                    if let Some(diff) = $crate::asserts::__private::bytes_diff(left_val, right_val) {
+                        panic!("{}", diff);
+                    }
+                }
+            }
+        }};
+        ($left:expr, $right:expr, $($arg:tt) +) => {{
+            match (&$left, &$right) {
+                (left_val, right_val) => {
+                    if let Some(diff) = $crate::asserts::__private::bytes_diff(left_val, right_val) {
+                        panic!("{}\n{}", diff, format_args!($($arg)*));
+                    }
+                }
+            }
+        }};
+    }
+
+    /// Checks that two map-like collections hold the same `(key, value)` pairs.
+    /// Works with `HashMap`, `BTreeMap`, and anything whose `iter()` yields
+    /// `(&K, &V)` or `&(K, V)`. On failure, keys only present on one side and
+    /// keys whose values differ are all reported.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_map_match;
+    /// # use std::collections::{BTreeMap, HashMap};
+    /// let hash: HashMap<_, _> = [("a", 1), ("b", 2)].into_iter().collect();
+    /// let btree: BTreeMap<_, _> = [("b", 2), ("a", 1)].into_iter().collect();
+    /// assert_map_match!(hash, btree);
+    /// assert_map_match!(btree, vec![("a", 1), ("b", 2)]);
+    /// ```
+    #[macro_export]
+    macro_rules! assert_map_match {
+        ($left:expr, $right:expr $(,) ?) => {{
+            match (&$left, &$right) {
+                (left_val, right_val) => {
+                    if let Some(diff) = $crate::asserts::__private::map_diff(left_val.iter(), right_val.iter()) {
+                        panic!("{}", diff);
+                    }
+                }
+            }
+        }};
+        ($left:expr, $right:expr, $($arg:tt) +) => {{
+            match (&$left, &$right) {
+                (left_val, right_val) => {
+                    if let Some(diff) = $crate::asserts::__private::map_diff(left_val.iter(), right_val.iter()) {
+                        panic!("{}\n{}", diff, format_args!($($arg)*));
+                    }
+                }
+            }
+        }};
+    }
+
+    /// Checks that `map` contains `key`. Works with `HashMap`, `BTreeMap`, and
+    /// `DashMap` (with the `dashmap` feature), which are searched with their own
+    /// `contains_key`, as well as lists of `(key, value)` pairs, which are
+    /// scanned. On failure the keys that are present are printed, sorted and
+    /// truncated to 20.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_contains_key;
+    /// # use std::collections::HashMap;
+    /// let map: HashMap<String, i32> = [("key1".to_string(), 1)].into_iter().collect();
+    /// assert_contains_key!(map, "key1");
+    /// ```
+    #[macro_export]
+    macro_rules! assert_contains_key {
+        ($map:expr, $key:expr $(,) ?) => {{
+            match (&$map, &$key) {
+                (map_val, key_val) => {
+                    if !$crate::__map_contains_key!(map_val, key_val) {
+                        panic!(
+                            "Expected map to contain key {:?}, found keys: {}",
+                            key_val,
+                            $crate::asserts::__private::map_key_preview(map_val)
+                        );
+                    }
+                }
+            }
+        }};
+        ($map:expr, $key:expr, $($arg:tt) +) => {{
+            match (&$map, &$key) {
+                (map_val, key_val) => {
+                    if !$crate::__map_contains_key!(map_val, key_val) {
+                        panic!(
+                            "Expected map to contain key {:?}, found keys: {}\n{}",
+                            key_val,
+                            $crate::asserts::__private::map_key_preview(map_val),
+                            format_args!($($arg)*)
+                        );
+                    }
+                }
+            }
+        }};
+    }
+
+    /// Checks that `map` does not contain `key`. Accepts the same map types as
+    /// [`assert_contains_key!`].
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_not_contains_key;
+    /// # use std::collections::BTreeMap;
+    /// let map: BTreeMap<&str, i32> = [("key1", 1)].into_iter().collect();
+    /// assert_not_contains_key!(map, "key2", "key2 should have been removed");
+    /// ```
+    #[macro_export]
+    macro_rules! assert_not_contains_key {
+        ($map:expr, $key:expr $(,) ?) => {{
+            match (&$map, &$key) {
+                (map_val, key_val) => {
+                    if $crate::__map_contains_key!(map_val, key_val) {
+                        panic!("Expected map to not contain key {:?}", key_val);
+                    }
+                }
+            }
+        }};
+        ($map:expr, $key:expr, $($arg:tt) +) => {{
+            match (&$map, &$key) {
+                (map_val, key_val) => {
+                    if $crate::__map_contains_key!(map_val, key_val) {
+                        panic!(
+                            "Expected map to not contain key {:?}\n{}",
+                            key_val,
+                            format_args!($($arg)*)
+                        );
+                    }
+                }
+            }
+        }};
+    }
+
+    /// Checks that at least one entry of `map` has the given `value`. Accepts the
+    /// same map types as [`assert_contains_key!`].
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::assert_contains_value;
+    /// # use std::collections::HashMap;
+    /// let map: HashMap<&str, String> = [("key1", "value1".to_string())].into_iter().collect();
+    /// assert_contains_value!(map, "value1");
+    /// ```
+    #[macro_export]
+    macro_rules! assert_contains_value {
+        ($map:expr, $value:expr $(,) ?) => {{
+            match (&$map, &$value) {
+                (map_val, value_val) => {
+                    if !$crate::asserts::__private::map_contains_value(map_val, value_val) {
+                        panic!(
+                            "Expected map to contain value {:?}, scanned {} entries",
+                            value_val,
+                            $crate::asserts::__private::MapLike::entry_count(map_val)
+                        );
+                    }
+                }
+            }
+        }};
+        ($map:expr, $value:expr, $($arg:tt) +) => {{
+            match (&$map, &$value) {
+                (map_val, value_val) => {
+                    if !$crate::asserts::__private::map_contains_value(map_val, value_val) {
+                        panic!(
+                            "Expected map to contain value {:?}, scanned {} entries\n{}",
+                            value_val,
+                            $crate::asserts::__private::MapLike::entry_count(map_val),
+                            format_args!($($arg)*)
+                        );
+                    }
+                }
+            }
+        }};
+    }
+
+    #[doc(hidden)]
+    #[macro_export]
+    macro_rules! __map_contains_key {
+        ($map:expr, $key:expr) => {{
+            #[allow(unused_imports)]
+            use $crate::asserts::__private::{BorrowKey as _, DerefKey as _, ScanKey as _};
+            (&&$crate::asserts::__private::MapKey($map, $key)).contains_key()
+        }};
+    }
+
+    /// Non-panicking version of [`assert_contains_all!`]. Evaluates to
+    /// `Result<(), String>`, where the error holds the message the assert would
+    /// have panicked with.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::check_contains_all;
+    /// assert!(check_contains_all!(vec![1, 2], vec![3, 2, 1]).is_ok());
+    /// assert_eq!(
+    ///     check_contains_all!(vec![1, 4], vec![1, 2]),
+    ///     Err("1 element missing from right: [4]".to_string())
+    /// );
+    /// ```
+    #[macro_export]
+    macro_rules! check_contains_all {
+        ($left:expr, $right:expr $(,) ?) => {{
+            match (&$left, &$right) {
+                (left_val, right_val) => {
+                    #[allow(unused_imports)]
+                    use $crate::asserts::__private::{
+                        CloneSource as _, EqCount as _, HashCount as _, RefSource as _,
+                    };
+                    let _left = (&$crate::asserts::__private::Source(left_val)).materialize();
+                    let _right = (&$crate::asserts::__private::Source(right_val)).materialize();
+                    let _multiset = $crate::asserts::__private::Multiset(
+                        _left.iter().collect(),
+                        _right.iter().collect(),
+                    );
+                    let _missing = (&_multiset).missing_from_right();
+                    if _missing.is_empty() {
+                        ::std::result::Result::<(), ::std::string::String>::Ok(())
+                    } else {
+                        Err(format!(
+                            "{} {} missing from right: {:?}",
+                            _missing.len(),
+                            if _missing.len() == 1 { "element" } else { "elements" },
+                            _missing
+                        ))
+                    }
+                }
+            }
+        }};
+        ($left:expr, $right:expr, $($arg:tt) +) => {
+            $crate::check_contains_all!($left, $right)
+                .map_err(|msg| format!("{}\n{}", msg, format_args!($($arg)*)))
+        };
+    }
+
+    /// Non-panicking version of [`assert_unordered_match!`]. Evaluates to
+    /// `Result<(), String>`, where the error holds the message the assert would
+    /// have panicked with.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::check_unordered_match;
+    /// assert!(check_unordered_match!(vec![3, 2, 1], vec![1, 2, 3]).is_ok());
+    /// assert!(check_unordered_match!(vec![1, 1], vec![1, 2]).is_err());
+    /// ```
+    #[macro_export]
+    macro_rules! check_unordered_match {
+        ($left:expr, $right:expr $(,) ?) => {{
+            match (&$left, &$right) {
+                (left_val, right_val) => {
+                    #[allow(unused_imports)]
+                    use $crate::asserts::__private::{CloneSource as _, RefSource as _};
+                    let _left = (&$crate::asserts::__private::Source(left_val)).materialize();
+                    let _right = (&$crate::asserts::__private::Source(right_val)).materialize();
+                    let (_lcount, _rcount) = (_left.len(), _right.len());
+                    if _lcount != _rcount {
+                        Err(format!(
+                            "Expected: size of left and right collections to be equal, Actual: left = {}, right = {}",
+                            _lcount, _rcount
+                        ))
+                    } else {
+                        #[allow(unused_imports)]
+                        use $crate::asserts::__private::{EqCount as _, HashCount as _};
+                        let _multiset = $crate::asserts::__private::Multiset(
+                            _left.iter().collect(),
+                            _right.iter().collect(),
+                        );
+                        match (&_multiset).first_count_mismatch() {
+                            Some((elem, lcount, rcount)) => Err(format!(
+                                "element {:?} appears {} {} on the left but {} {} on the right",
+                                elem,
+                                lcount,
+                                $crate::asserts::__private::times(lcount),
+                                rcount,
+                                $crate::asserts::__private::times(rcount),
+                            )),
+                            None => ::std::result::Result::<(), ::std::string::String>::Ok(()),
+                        }
+                    }
+                }
+            }
+        }};
+        ($left:expr, $right:expr, $($arg:tt) +) => {
+            $crate::check_unordered_match!($left, $right)
+                .map_err(|msg| format!("{}\n{}", msg, format_args!($($arg)*)))
+        };
+    }
+
+    /// Non-panicking version of [`assert_in_range!`]. Evaluates to
+    /// `Result<(), String>`, where the error holds the message the assert would
+    /// have panicked with.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::check_in_range;
+    /// assert!(check_in_range!(5, 0..10).is_ok());
+    /// assert_eq!(
+    ///     check_in_range!(10, 0..10),
+    ///     Err("Expected range 0..10 to contain 10".to_string())
+    /// );
+    /// ```
+    #[macro_export]
+    macro_rules! check_in_range {
+        ($actual:expr, $range:expr $(,) ?) => {{
+            match (&$actual, &$range) {
+                (actual_val, range_val) => {
+                    if $crate::asserts::__private::range_contains(range_val, actual_val) {
+                        ::std::result::Result::<(), ::std::string::String>::Ok(())
+                    } else {
+                        Err(format!(
+                            "Expected range {:?} to contain {:?}",
+                            &*range_val, &*actual_val
+                        ))
+                    }
+                }
+            }
+        }};
+        ($actual:expr, $range:expr, $($arg:tt) +) => {
+            $crate::check_in_range!($actual, $range)
+                .map_err(|msg| format!("{}\n{}", msg, format_args!($($arg)*)))
+        };
+    }
+
+    /// Non-panicking version of [`assert_within!`]. Evaluates to
+    /// `Result<(), String>`, where the error holds the message the assert would
+    /// have panicked with.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::check_within;
+    /// assert!(check_within!(5, 0, 10).is_ok());
+    /// assert_eq!(
+    ///     check_within!(11, 0, 10, "loaded {} rows", 11),
+    ///     Err("Expected 11 to be lte 10\nloaded 11 rows".to_string())
+    /// );
+    /// ```
+    #[macro_export]
+    macro_rules! check_within {
+        ($actual:expr, $min:expr, $max:expr $(,) ?) => {{
+            match (&$actual, &$min, &$max) {
+                (actual_val, min_val, max_val) => {
+                    if !(*actual_val >= *min_val) {
+                        Err(format!("Expected {:?} to be gte {:?}", actual_val, min_val))
+                    } else if !(*actual_val <= *max_val) {
+                        Err(format!("Expected {:?} to be lte {:?}", actual_val, max_val))
+                    } else {
+                        ::std::result::Result::<(), ::std::string::String>::Ok(())
+                    }
+                }
+            }
+        }};
+        ($actual:expr, $min:expr, $max:expr, $($arg:tt) +) => {
+            $crate::check_within!($actual, $min, $max)
+                .map_err(|msg| format!("{}\n{}", msg, format_args!($($arg)*)))
+        };
+    }
+
+    /// Same as [`assert_contains_all!`], but only checked when `debug_assertions`
+    /// are enabled. The arguments are still type checked in release builds.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::debug_assert_contains_all;
+    /// debug_assert_contains_all!(vec![1, 2], vec![3, 2, 1]);
+    /// ```
+    #[macro_export]
+    macro_rules! debug_assert_contains_all {
+        ($($arg:tt)*) => {
+            if cfg!(debug_assertions) {
+                $crate::assert_contains_all!($($arg)*);
+            }
+        };
+    }
+
+    /// Same as [`assert_unordered_match!`], but only checked when
+    /// `debug_assertions` are enabled. The arguments are still type checked in
+    /// release builds.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::debug_assert_unordered_match;
+    /// debug_assert_unordered_match!(vec![1, 2], [2, 1]);
+    /// ```
+    #[macro_export]
+    macro_rules! debug_assert_unordered_match {
+        ($($arg:tt)*) => {
+            if cfg!(debug_assertions) {
+                $crate::assert_unordered_match!($($arg)*);
+            }
+        };
+    }
+
+    /// Same as [`assert_in_range!`], but only checked when `debug_assertions` are
+    /// enabled. The arguments are still type checked in release builds.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::debug_assert_in_range;
+    /// debug_assert_in_range!(5, 0..10);
+    /// ```
+    #[macro_export]
+    macro_rules! debug_assert_in_range {
+        ($($arg:tt)*) => {
+            if cfg!(debug_assertions) {
+                $crate::assert_in_range!($($arg)*);
+            }
+        };
+    }
+
+    /// Same as [`assert_within!`], but only checked when `debug_assertions` are
+    /// enabled. The arguments are still type checked in release builds.
+    ///
+    /// ## Example(s)
+    /// ```rust
+    /// # use stupid_utils::debug_assert_within;
+    /// debug_assert_within!(5, 0, 10);
+    /// ```
+    #[macro_export]
+    macro_rules! debug_assert_within {
+        ($($arg:tt)*) => {
+            if cfg!(debug_assertions) {
+                $crate::assert_within!($($arg)*);
+            }
+        };
+    }
+
+    /// A tolerance for `assert_time_close!` in milliseconds, which makes the
+    /// macro read both timestamps as unix timestamps in milliseconds too.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Millis(pub u64);
+
+    #[doc(hidden)]
+    pub mod __private {
+        use std::borrow::Borrow;
+        use std::collections::{HashMap, HashSet};
+        use std::hash::{BuildHasher, Hash};
+
+        /// The elements of the left and right collections being compared by
+        /// `assert_unordered_match!` or `assert_contains_all!`.
+        pub struct Multiset<'a, T>(pub Vec<&'a T>, pub Vec<&'a T>);
+
+        /// Counts elements with a `HashMap`. Implemented directly on `Multiset` so
+        /// method resolution prefers it over [`EqCount`] whenever `T: Hash + Eq`.
+        pub trait HashCount<'a, T> {
+            fn first_count_mismatch(&self) -> Option<(&'a T, usize, usize)>;
+
+            /// Every element of the left side that is not on the right side.
+            fn missing_from_right(&self) -> Vec<&'a T>;
+        }
+
+        impl<'a, T: Hash + Eq> HashCount<'a, T> for Multiset<'a, T> {
+            fn first_count_mismatch(&self) -> Option<(&'a T, usize, usize)> {
+                let mut counts: HashMap<&'a T, (usize, usize)> = HashMap::new();
+                for &elem in &self.0 {
+                    counts.entry(elem).or_default().0 += 1;
+                }
+                for &elem in &self.1 {
+                    counts.entry(elem).or_default().1 += 1;
+                }
+                self.0.iter().chain(self.1.iter()).find_map(|&elem| {
+                    let (lcount, rcount) = counts[&elem];
+                    (lcount != rcount).then_some((elem, lcount, rcount))
+                })
+            }
+
+            fn missing_from_right(&self) -> Vec<&'a T> {
+                let right: HashSet<&'a T> = self.1.iter().copied().collect();
+                missing(self.0.iter().copied(), |n| right.contains(n))
+            }
+        }
+
+        /// Quadratic fallback for element types that are only `PartialEq`.
+        pub trait EqCount<'a, T> {
+            fn first_count_mismatch(&self) -> Option<(&'a T, usize, usize)>;
+
+            /// Every element of the left side that is not on the right side.
+            fn missing_from_right(&self) -> Vec<&'a T>;
+        }
+
+        impl<'a, T: PartialEq> EqCount<'a, T> for &Multiset<'a, T> {
+            fn first_count_mismatch(&self) -> Option<(&'a T, usize, usize)> {
+                self.0.iter().chain(self.1.iter()).find_map(|&elem| {
+                    let lcount = self.0.iter().filter(|&&other| other == elem).count();
+                    let rcount = self.1.iter().filter(|&&other| other == elem).count();
+                    (lcount != rcount).then_some((elem, lcount, rcount))
+                })
+            }
+
+            fn missing_from_right(&self) -> Vec<&'a T> {
+                missing(self.0.iter().copied(), |n| self.1.contains(&n))
+            }
+        }
+
+        /// A collection or iterator passed to `assert_unordered_match!` or
+        /// `assert_contains_all!`.
+        pub struct Source<'a, C: ?Sized>(pub &'a C);
+
+        /// Collections that can be iterated by reference are collected without
+        /// cloning. Implemented directly on `Source` so it takes priority over
+        /// [`CloneSource`].
+        pub trait RefSource {
+            type Item;
+            fn materialize(&self) -> Vec<Self::Item>;
+        }
+
+        impl<'a, C: ?Sized> RefSource for Source<'a, C>
+        where
+            &'a C: IntoIterator,
+        {
+            type Item = <&'a C as IntoIterator>::Item;
+
+            fn materialize(&self) -> Vec<Self::Item> {
+                self.0.into_iter().collect()
+            }
+        }
+
+        /// Iterators (like `map.keys()`) are cloned and collected once.
+        pub trait CloneSource {
+            type Item;
+            fn materialize(&self) -> Vec<Self::Item>;
+        }
+
+        impl<I: IntoIterator + Clone> CloneSource for &Source<'_, I> {
+            type Item = I::Item;
+
+            fn materialize(&self) -> Vec<Self::Item> {
+                self.0.clone().into_iter().collect()
+            }
+        }
+
+        /// The distinct elements of `left` that are also in `right`, in the order
+        /// they first appear in `left`.
+        pub fn intersection<'a, T: PartialEq>(left: &'a [T], right: &[T]) -> Vec<&'a T> {
+            let mut common: Vec<&'a T> = Vec::new();
+            for item in left {
+                if right.contains(item) && !common.contains(&item) {
+                    common.push(item);
+                }
+            }
+            common
+        }
+
+        /// The distinct elements of `left` followed by the distinct elements of
+        /// `right` that are not in `left`.
+        pub fn union<'a, T: PartialEq>(left: &'a [T], right: &'a [T]) -> Vec<&'a T> {
+            let mut all: Vec<&'a T> = Vec::new();
+            for item in left.iter().chain(right) {
+                if !all.contains(&item) {
+                    all.push(item);
+                }
+            }
+            all
+        }
+
+        /// Lists every index where `left` and `right` differ (up to
+        /// [`MAX_DISPLAYED_LEN`] of them) and the extra elements of the longer one.
+        pub fn ordered_diff<T: PartialEq + std::fmt::Debug>(
+            left: &[T],
+            right: &[T],
+        ) -> Option<String> {
+            use std::fmt::Write;
+
+            let mismatches: Vec<_> = left
+                .iter()
+                .zip(right)
+                .enumerate()
+                .filter(|(_, (l, r))| l != r)
+                .collect();
+            if mismatches.is_empty() && left.len() == right.len() {
+                return None;
+            }
+
+            let mut out = String::from("Expected collections to match in order, but they differ:");
+            for (i, (l, r)) in mismatches.iter().take(MAX_DISPLAYED_LEN) {
+                let _ = write!(out, "\n  {}: {:?} != {:?}", i, l, r);
+            }
+            if mismatches.len() > MAX_DISPLAYED_LEN {
+                let _ = write!(
+                    out,
+                    "\n  ... and {} more mismatches",
+                    mismatches.len() - MAX_DISPLAYED_LEN
+                );
+            }
+            let (side, longer, shorter) = if left.len() > right.len() {
+                ("left", left, right)
+            } else {
+                ("right", right, left)
+            };
+            let extra = &longer[shorter.len()..];
+            if !extra.is_empty() {
+                let _ = write!(
+                    out,
+                    "\n  {} has {} extra {} starting at index {}: ",
+                    side,
+                    extra.len(),
+                    if extra.len() == 1 {
+                        "element"
+                    } else {
+                        "elements"
+                    },
+                    shorter.len()
+                );
+                if extra.len() < MAX_DISPLAYED_LEN {
+                    let _ = write!(out, "{:?}", extra);
+                } else {
+                    let _ = write!(out, "{:?}...", &extra[..PREVIEW_LEN]);
+                }
+            }
+            Some(out)
+        }
+
+        /// Collects every element of `iter` for which `contains` returns false.
+        pub fn missing<'a, T, I, F>(iter: I, mut contains: F) -> Vec<&'a T>
+        where
+            T: 'a,
+            I: IntoIterator<Item = &'a T>,
+            F: FnMut(&T) -> bool,
+        {
+            iter.into_iter().filter(|n| !contains(n)).collect()
+        }
+
+        /// Lists each value that appears more than once in `iter` along with its
+        /// number of occurrences, in order of first appearance.
+        pub fn duplicates<'a, T, I>(iter: I) -> Vec<(&'a T, usize)>
+        where
+            T: PartialEq + 'a,
+            I: IntoIterator<Item = &'a T>,
+        {
+            let mut counts: Vec<(&'a T, usize)> = Vec::new();
+            for item in iter {
+                match counts.iter_mut().find(|(seen, _)| *seen == item) {
+                    Some((_, count)) => *count += 1,
+                    None => counts.push((item, 1)),
+                }
+            }
+            counts.retain(|&(_, count)| count > 1);
+            counts
+        }
+
+        /// Collects every element (and its index) that does not satisfy `predicate`.
+        pub fn failing<'a, T, I, F>(iter: I, mut predicate: F) -> Vec<(usize, &'a T)>
+        where
+            T: 'a,
+            I: IntoIterator<Item = &'a T>,
+            F: FnMut(&T) -> bool,
+        {
+            iter.into_iter()
+                .enumerate()
+                .filter(|(_, item)| !predicate(item))
+                .collect()
+        }
+
+        /// Checks whether any element satisfies `predicate`.
+        pub fn any<'a, T, I, F>(iter: I, predicate: F) -> bool
+        where
+            T: 'a,
+            I: IntoIterator<Item = &'a T>,
+            F: FnMut(&T) -> bool,
+        {
+            iter.into_iter().any(predicate)
+        }
+
+        /// Polls `condition` every `interval_ms` until it returns true or `timeout_ms`
+        /// has elapsed, in which case the elapsed time is returned as the error.
+        pub fn eventually<F: FnMut() -> bool>(
+            mut condition: F,
+            timeout_ms: u64,
+            interval_ms: u64,
+        ) -> Result<(), std::time::Duration> {
+            let start = std::time::Instant::now();
+            let timeout = std::time::Duration::from_millis(timeout_ms);
+            let interval = std::time::Duration::from_millis(interval_ms);
+            loop {
+                if condition() {
+                    return Ok(());
+                }
+                let elapsed = start.elapsed();
+                if elapsed >= timeout {
+                    return Err(elapsed);
+                }
+                std::thread::sleep(interval.min(timeout - elapsed));
+            }
+        }
+
+        std::thread_local! {
+            /// How many `catch_panic` calls are running on this thread.
+            static CATCHING: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+        }
+
+        /// Runs `f`, returning the panic payload if it panicked. The first call
+        /// wraps the current panic hook in one that skips panics raised while a
+        /// `catch_panic` is running on the same thread, so the expected panic
+        /// isn't printed but panics on other threads still are.
+        pub fn catch_panic<F: FnOnce() -> R, R>(f: F) -> Option<Box<dyn std::any::Any + Send>> {
+            static INSTALL_HOOK: std::sync::Once = std::sync::Once::new();
+            INSTALL_HOOK.call_once(|| {
+                let previous = std::panic::take_hook();
+                std::panic::set_hook(Box::new(move |info| {
+                    if CATCHING.with(|catching| catching.get()) == 0 {
+                        previous(info);
+                    }
+                }));
+            });
+            CATCHING.with(|catching| catching.set(catching.get() + 1));
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+            CATCHING.with(|catching| catching.set(catching.get() - 1));
+            result.err()
+        }
+
+        /// Gets the message from a panic payload if it is a `String` or `&str`.
+        pub fn panic_message(payload: &(dyn std::any::Any + Send)) -> Option<&str> {
+            payload
+                .downcast_ref::<String>()
+                .map(String::as_str)
+                .or_else(|| payload.downcast_ref::<&'static str>().copied())
+        }
+
+        /// Number of bytes shown on each row of the `assert_bytes_eq!` hex dump.
+        const HEX_ROW_LEN: usize = 8;
+
+        /// Describes the first difference between two byte buffers, or returns
+        /// `None` if they are equal.
+        pub fn bytes_diff<L, R>(left: &L, right: &R) -> Option<String>
+        where
+            L: AsRef<[u8]> + ?Sized,
+            R: AsRef<[u8]> + ?Sized,
+        {
+            use std::fmt::Write;
+
+            let (left, right) = (left.as_ref(), right.as_ref());
+            let offset = match left.iter().zip(right).position(|(l, r)| l != r) {
+                Some(offset) => offset,
+                None if left.len() == right.len() => return None,
+                None => left.len().min(right.len()),
+            };
+
+            let hex_row = |bytes: &[u8], start: usize| {
+                (start..start + HEX_ROW_LEN)
+                    .map(|i| {
+                        bytes
+                            .get(i)
+                            .map_or("--".to_string(), |b| format!("{:02x}", b))
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            };
+            let width = HEX_ROW_LEN * 3 - 1;
+            let first_row = offset.saturating_sub(HEX_ROW_LEN) / HEX_ROW_LEN * HEX_ROW_LEN;
+
+            let mut out = format!(
+                "Byte buffers differ at offset {} (left len {}, right len {})\n  {:<8}  {:<width$} | right",
+                offset,
+                left.len(),
+                right.len(),
+                "offset",
+                "left",
+                width = width
+            );
+            for start in (first_row..first_row + 2 * HEX_ROW_LEN).step_by(HEX_ROW_LEN) {
+                let differs = (start..start + HEX_ROW_LEN).any(|i| left.get(i) != right.get(i));
+                let _ = write!(
+                    out,
+                    "\n{} {:08x}  {} | {}",
+                    if differs { '>' } else { ' ' },
+                    start,
+                    hex_row(left, start),
+                    hex_row(right, start)
+                );
+            }
+            Some(out)
+        }
+
+        /// An item yielded when iterating a map-like collection by reference.
+        pub trait KeyValue<'a, K: 'a, V: 'a> {
+            fn key_value(self) -> (&'a K, &'a V);
+        }
+
+        impl<'a, K, V> KeyValue<'a, K, V> for (&'a K, &'a V) {
+            fn key_value(self) -> (&'a K, &'a V) {
+                self
+            }
+        }
+
+        impl<'a, K, V> KeyValue<'a, K, V> for &'a (K, V) {
+            fn key_value(self) -> (&'a K, &'a V) {
+                (&self.0, &self.1)
+            }
+        }
+
+        /// Describes every key that is only in `left`, only in `right`, or has a
+        /// different value on each side. Returns `None` if the maps match.
+        pub fn map_diff<'a, K, V, L, R>(left: L, right: R) -> Option<String>
+        where
+            K: PartialEq + std::fmt::Debug + 'a,
+            V: PartialEq + std::fmt::Debug + 'a,
+            L: IntoIterator,
+            L::Item: KeyValue<'a, K, V>,
+            R: IntoIterator,
+            R::Item: KeyValue<'a, K, V>,
+        {
+            use std::fmt::Write;
+
+            let left: Vec<_> = left.into_iter().map(KeyValue::key_value).collect();
+            let right: Vec<_> = right.into_iter().map(KeyValue::key_value).collect();
+            let find = |pairs: &[(&'a K, &'a V)], key: &K| {
+                pairs.iter().find(|(k, _)| *k == key).map(|&(_, v)| v)
+            };
+
+            let mut only_left = Vec::new();
+            let mut differ = Vec::new();
+            for &(key, lvalue) in &left {
+                match find(&right, key) {
+                    None => only_left.push(key),
+                    Some(rvalue) if lvalue != rvalue => differ.push((key, lvalue, rvalue)),
+                    Some(_) => {}
+                }
+            }
+            let only_right: Vec<_> = right
+                .iter()
+                .filter(|(key, _)| find(&left, key).is_none())
+                .map(|&(key, _)| key)
+                .collect();
+            if only_left.is_empty() && only_right.is_empty() && differ.is_empty() {
+                return None;
+            }
+
+            let mut out = String::from("Expected maps to match, but");
+            if !only_left.is_empty() {
+                let _ = write!(out, "\n  keys only in left: {:?}", only_left);
+            }
+            if !only_right.is_empty() {
+                let _ = write!(out, "\n  keys only in right: {:?}", only_right);
+            }
+            for (key, lvalue, rvalue) in differ {
+                let _ = write!(
+                    out,
+                    "\n  values differ for key {:?}: left = {:?}, right = {:?}",
+                    key, lvalue, rvalue
+                );
+            }
+            Some(out)
+        }
+
+        /// Describes which bound `actual` violates, if any, naming the comparison
+        /// and whether the bound is inclusive.
+        pub fn bound_violation<A, L, H>(
+            actual: &A,
+            low: &L,
+            low_inclusive: bool,
+            high: &H,
+            high_inclusive: bool,
+        ) -> Option<String>
+        where
+            A: PartialOrd<L> + PartialOrd<H> + std::fmt::Debug,
+            L: std::fmt::Debug,
+            H: std::fmt::Debug,
+        {
+            let inclusive = |inclusive: bool| if inclusive { "inclusive" } else { "exclusive" };
+            let above_low = if low_inclusive {
+                actual >= low
+            } else {
+                actual > low
+            };
+            if !above_low {
+                return Some(format!(
+                    "Expected {:?} to be {} {:?} ({} lower bound)",
+                    actual,
+                    if low_inclusive { "gte" } else { "gt" },
+                    low,
+                    inclusive(low_inclusive)
+                ));
+            }
+            let below_high = if high_inclusive {
+                actual <= high
+            } else {
+                actual < high
+            };
+            if !below_high {
+                return Some(format!(
+                    "Expected {:?} to be {} {:?} ({} upper bound)",
+                    actual,
+                    if high_inclusive { "lte" } else { "lt" },
+                    high,
+                    inclusive(high_inclusive)
+                ));
+            }
+            None
+        }
+
+        /// Finds the first adjacent pair where the second item is less than (or,
+        /// when `strict`, not greater than) the first. Incomparable pairs count as
+        /// a violation.
+        pub fn first_non_monotonic<I>(iter: I, strict: bool) -> Option<(usize, I::Item, I::Item)>
+        where
+            I: IntoIterator,
+            I::Item: PartialOrd,
+        {
+            let mut iter = iter.into_iter();
+            let mut prev = iter.next()?;
+            for (i, next) in iter.enumerate() {
+                let ok = match prev.partial_cmp(&next) {
+                    Some(std::cmp::Ordering::Less) => true,
+                    Some(std::cmp::Ordering::Equal) => !strict,
+                    _ => false,
+                };
+                if !ok {
+                    return Some((i, prev, next));
+                }
+                prev = next;
+            }
+            None
+        }
+
+        /// The tolerance of `assert_time_close!`, in seconds unless `millis`.
+        pub struct TimeTolerance {
+            amount: u64,
+            millis: bool,
+        }
+
+        impl From<u64> for TimeTolerance {
+            fn from(amount: u64) -> Self {
+                Self {
+                    amount,
+                    millis: false,
+                }
+            }
+        }
+
+        impl From<super::Millis> for TimeTolerance {
+            fn from(super::Millis(amount): super::Millis) -> Self {
+                Self {
+                    amount,
+                    millis: true,
+                }
+            }
+        }
+
+        /// Describes how far apart `actual` and `expected` are if they differ by
+        /// more than `tolerance`, reading both in the tolerance's unit.
+        pub fn time_distance_violation(
+            actual: i64,
+            expected: i64,
+            tolerance: impl Into<TimeTolerance>,
+        ) -> Option<String> {
+            let TimeTolerance { amount, millis } = tolerance.into();
+            let (format, unit): (fn(i64) -> String, _) = if millis {
+                (rfc3339_millis, "ms")
+            } else {
+                (rfc3339, "s")
+            };
+            let delta = actual.abs_diff(expected);
+            (delta > amount).then(|| {
+                format!(
+                    "Expected timestamp {} ({}) to be within {}{} of {} ({}), but they are {}{} apart",
+                    actual,
+                    format(actual),
+                    amount,
+                    unit,
+                    expected,
+                    format(expected),
+                    delta,
+                    unit
+                )
+            })
+        }
+
+        /// Same as `rfc3339`, but for a unix timestamp in milliseconds.
+        pub fn rfc3339_millis(timestamp: i64) -> String {
+            let seconds = rfc3339(timestamp.div_euclid(1000));
+            format!(
+                "{}.{:03}Z",
+                seconds.trim_end_matches('Z'),
+                timestamp.rem_euclid(1000)
+            )
+        }
+
+        /// Formats a unix timestamp (in seconds) as an RFC 3339 UTC date-time.
+        pub fn rfc3339(timestamp: i64) -> String {
+            let days = timestamp.div_euclid(86_400);
+            let secs = timestamp.rem_euclid(86_400);
+            // Civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+            let z = days + 719_468;
+            let era = z.div_euclid(146_097);
+            let doe = z.rem_euclid(146_097);
+            let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+            let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+            let mp = (5 * doy + 2) / 153;
+            let day = doy - (153 * mp + 2) / 5 + 1;
+            let month = if mp < 10 { mp + 3 } else { mp - 9 };
+            let year = yoe + era * 400 + i64::from(month <= 2);
+            format!(
+                "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+                year,
+                month,
+                day,
+                secs / 3600,
+                secs % 3600 / 60,
+                secs % 60
+            )
+        }
+
+        /// Maps usable with `assert_contains_key!` and friends. Keys and values
+        /// are compared with `PartialEq` by visiting every entry, so lookups work
+        /// no matter how the key type borrows. Lists of `(key, value)` pairs are
+        /// maps too.
+        pub trait MapLike {
+            type Key;
+            type Value;
+
+            fn entry_count(&self) -> usize;
+
+            /// Visits entries until `f` returns true, returning whether it did.
+            fn any_entry<F: FnMut(&Self::Key, &Self::Value) -> bool>(&self, f: F) -> bool;
+        }
+
+        impl<M: MapLike + ?Sized> MapLike for &M {
+            type Key = M::Key;
+            type Value = M::Value;
+
+            fn entry_count(&self) -> usize {
+                (**self).entry_count()
+            }
+
+            fn any_entry<F: FnMut(&Self::Key, &Self::Value) -> bool>(&self, f: F) -> bool {
+                (**self).any_entry(f)
+            }
+        }
+
+        impl<K, V, S> MapLike for HashMap<K, V, S> {
+            type Key = K;
+            type Value = V;
+
+            fn entry_count(&self) -> usize {
+                self.len()
+            }
+
+            fn any_entry<F: FnMut(&K, &V) -> bool>(&self, mut f: F) -> bool {
+                self.iter().any(|(k, v)| f(k, v))
+            }
+        }
+
+        impl<K, V> MapLike for std::collections::BTreeMap<K, V> {
+            type Key = K;
+            type Value = V;
+
+            fn entry_count(&self) -> usize {
+                self.len()
+            }
+
+            fn any_entry<F: FnMut(&K, &V) -> bool>(&self, mut f: F) -> bool {
+                self.iter().any(|(k, v)| f(k, v))
+            }
+        }
+
+        impl<K, V> MapLike for [(K, V)] {
+            type Key = K;
+            type Value = V;
+
+            fn entry_count(&self) -> usize {
+                self.len()
+            }
+
+            fn any_entry<F: FnMut(&K, &V) -> bool>(&self, mut f: F) -> bool {
+                self.iter().any(|(k, v)| f(k, v))
+            }
+        }
+
+        impl<K, V> MapLike for Vec<(K, V)> {
+            type Key = K;
+            type Value = V;
+
+            fn entry_count(&self) -> usize {
+                self.len()
+            }
+
+            fn any_entry<F: FnMut(&K, &V) -> bool>(&self, f: F) -> bool {
+                self.as_slice().any_entry(f)
+            }
+        }
+
+        #[cfg(feature = "dashmap")]
+        impl<K, V, S> MapLike for dashmap::DashMap<K, V, S>
+        where
+            K: Eq + Hash,
+            S: std::hash::BuildHasher + Clone,
+        {
+            type Key = K;
+            type Value = V;
+
+            fn entry_count(&self) -> usize {
+                self.len()
+            }
+
+            fn any_entry<F: FnMut(&K, &V) -> bool>(&self, mut f: F) -> bool {
+                self.iter().any(|entry| f(entry.key(), entry.value()))
+            }
+        }
+
+        /// Maps that can look a key up without visiting every entry.
+        pub trait KeyedMap<Q: ?Sized> {
+            fn has_key(&self, key: &Q) -> bool;
+        }
+
+        impl<M: KeyedMap<Q> + ?Sized, Q: ?Sized> KeyedMap<Q> for &M {
+            fn has_key(&self, key: &Q) -> bool {
+                (**self).has_key(key)
+            }
+        }
+
+        impl<K, V, S, Q> KeyedMap<Q> for HashMap<K, V, S>
+        where
+            K: Borrow<Q> + Eq + Hash,
+            S: BuildHasher,
+            Q: Eq + Hash + ?Sized,
+        {
+            fn has_key(&self, key: &Q) -> bool {
+                self.contains_key(key)
+            }
+        }
+
+        impl<K, V, Q> KeyedMap<Q> for std::collections::BTreeMap<K, V>
+        where
+            K: Borrow<Q> + Ord,
+            Q: Ord + ?Sized,
+        {
+            fn has_key(&self, key: &Q) -> bool {
+                self.contains_key(key)
+            }
+        }
+
+        #[cfg(feature = "dashmap")]
+        impl<K, V, S, Q> KeyedMap<Q> for dashmap::DashMap<K, V, S>
+        where
+            K: Borrow<Q> + Eq + Hash,
+            S: BuildHasher + Clone,
+            Q: Eq + Hash + ?Sized,
+        {
+            fn has_key(&self, key: &Q) -> bool {
+                self.contains_key(key)
+            }
+        }
+
+        /// The map and key passed to `assert_contains_key!`, which picks the
+        /// first of [`BorrowKey`], [`DerefKey`] and [`ScanKey`] that applies.
+        pub struct MapKey<'a, M: ?Sized, Q: ?Sized>(pub &'a M, pub &'a Q);
+
+        /// Looks `key` up directly when the map's keys borrow as its type.
+        pub trait BorrowKey {
+            fn contains_key(&self) -> bool;
+        }
+
+        impl<M: KeyedMap<Q> + ?Sized, Q: ?Sized> BorrowKey for &MapKey<'_, M, Q> {
+            fn contains_key(&self) -> bool {
+                self.0.has_key(self.1)
+            }
+        }
+
+        /// Looks up what `key` points to, so `&str` keys work with `String` maps.
+        pub trait DerefKey {
+            fn contains_key(&self) -> bool;
+        }
+
+        impl<M, Q> DerefKey for &&MapKey<'_, M, Q>
+        where
+            M: KeyedMap<Q::Target> + ?Sized,
+            Q: std::ops::Deref + ?Sized,
+        {
+            fn contains_key(&self) -> bool {
+                self.0.has_key(&**self.1)
+            }
+        }
+
+        /// Falls back to comparing `key` with every key of the map.
+        pub trait ScanKey {
+            fn contains_key(&self) -> bool;
+        }
+
+        impl<M, Q> ScanKey for MapKey<'_, M, Q>
+        where
+            M: MapLike + ?Sized,
+            M::Key: PartialEq<Q>,
+            Q: ?Sized,
+        {
+            fn contains_key(&self) -> bool {
+                self.0.any_entry(|k, _| k == self.1)
+            }
+        }
+
+        /// Checks whether any value of `map` is equal to `value`.
+        pub fn map_contains_value<M, Q>(map: &M, value: &Q) -> bool
+        where
+            M: MapLike + ?Sized,
+            M::Value: PartialEq<Q>,
+            Q: ?Sized,
+        {
+            map.any_entry(|_, v| v == value)
+        }
+
+        /// Lists the keys of `map`, sorted by their debug representation and
+        /// truncated to [`MAX_DISPLAYED_LEN`].
+        pub fn map_key_preview<M>(map: &M) -> String
+        where
+            M: MapLike + ?Sized,
+            M::Key: std::fmt::Debug,
+        {
+            let mut keys = Vec::new();
+            map.any_entry(|k, _| {
+                keys.push(format!("{:?}", k));
+                false
+            });
+            keys.sort();
+            let total = keys.len();
+            if total > MAX_DISPLAYED_LEN {
+                keys.truncate(MAX_DISPLAYED_LEN);
+                keys.push(format!("... ({} keys total)", total));
+            }
+            format!("[{}]", keys.join(", "))
+        }
+
+        /// Walks `left` and `right` in lockstep, describing the first position
+        /// where they differ or where one of them runs out of items.
+        pub fn iter_mismatch<L, R>(left: L, right: R) -> Option<String>
+        where
+            L: IntoIterator,
+            R: IntoIterator,
+            L::Item: PartialEq<R::Item> + std::fmt::Debug,
+            R::Item: std::fmt::Debug,
+        {
+            let (mut left, mut right) = (left.into_iter(), right.into_iter());
+            let mut index = 0;
+            loop {
+                match (left.next(), right.next()) {
+                    (None, None) => return None,
+                    (Some(l), Some(r)) if l == r => {}
+                    (Some(l), Some(r)) => {
+                        return Some(format!(
+                            "Iterators differ at index {}: left = {:?}, right = {:?}",
+                            index, l, r
+                        ))
+                    }
+                    (Some(_), None) => {
+                        return Some(format!(
+                            "Right iterator ended early: left has {} elements, right has {}",
+                            index + 1 + left.count(),
+                            index
+                        ))
+                    }
+                    (None, Some(_)) => {
+                        return Some(format!(
+                            "Left iterator ended early: left has {} elements, right has {}",
+                            index,
+                            index + 1 + right.count()
+                        ))
+                    }
+                }
+                index += 1;
+            }
+        }
+
+        /// Runs `f` for each iteration, re-panicking with the iteration number if
+        /// it panics. The panic hook is left alone so the original panic is still
+        /// printed.
+        pub fn repeat<F: FnMut(usize)>(iterations: usize, mut f: F) {
+            for i in 0..iterations {
+                let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| f(i)));
+                if let Err(payload) = result {
+                    panic!("iteration {} failed: {}", i, payload_description(&*payload));
+                }
+            }
+        }
+
+        /// Same as [`repeat`], but also passes each iteration a seed derived from
+        /// a per-run random base seed, and includes it in the failure message.
+        pub fn repeat_seeded<F: FnMut(usize, u64)>(iterations: usize, mut f: F) {
+            use std::hash::{BuildHasher, Hasher};
+
+            let base = std::collections::hash_map::RandomState::new()
+                .build_hasher()
+                .finish();
+            for i in 0..iterations {
+                let seed = splitmix64(base.wrapping_add(i as u64));
+                let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| f(i, seed)));
+                if let Err(payload) = result {
+                    panic!(
+                        "iteration {} (seed {}) failed: {}",
+                        i,
+                        seed,
+                        payload_description(&*payload)
+                    );
+                }
+            }
+        }
+
+        fn splitmix64(x: u64) -> u64 {
+            let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        }
+
+        fn payload_description(payload: &(dyn std::any::Any + Send)) -> &str {
+            panic_message(payload).unwrap_or("<non-string panic payload>")
+        }
+
+        /// Describes how `elapsed` falls outside of `min_ms..=max_ms`, if it does.
+        pub fn duration_violation(
+            elapsed: std::time::Duration,
+            min_ms: u64,
+            max_ms: u64,
+        ) -> Option<String> {
+            if elapsed > std::time::Duration::from_millis(max_ms) {
+                Some(format!(
+                    "Expected block to finish within {}ms, but it took {:?}",
+                    max_ms, elapsed
+                ))
+            } else if elapsed < std::time::Duration::from_millis(min_ms) {
+                Some(format!(
+                    "Expected block to take at least {}ms, but it took {:?}",
+                    min_ms, elapsed
+                ))
+            } else {
+                None
+            }
+        }
+
+        /// Finds the first adjacent pair that is not in non-decreasing order.
+        pub fn first_unsorted<'a, T, I>(iter: I) -> Option<(usize, &'a T, &'a T)>
+        where
+            T: PartialOrd + 'a,
+            I: IntoIterator<Item = &'a T>,
+        {
+            first_unsorted_by(iter, |a: &T, b: &T| match a.partial_cmp(b) {
+                Some(ordering) => ordering,
+                None => std::cmp::Ordering::Greater,
+            })
+        }
+
+        /// Finds the first adjacent pair that `comparator` reports as out of order.
+        pub fn first_unsorted_by<'a, T, I, F>(
+            iter: I,
+            mut comparator: F,
+        ) -> Option<(usize, &'a T, &'a T)>
+        where
+            T: 'a,
+            I: IntoIterator<Item = &'a T>,
+            F: FnMut(&T, &T) -> std::cmp::Ordering,
+        {
+            let mut iter = iter.into_iter();
+            let mut prev = iter.next()?;
+            for (i, next) in iter.enumerate() {
+                if comparator(prev, next) == std::cmp::Ordering::Greater {
+                    return Some((i, prev, next));
+                }
+                prev = next;
+            }
+            None
+        }
+
+        /// Compiles the pattern given to `assert_matches_regex!`, panicking with the
+        /// compile error if it is invalid.
+        pub fn compile_pattern<P: AsRef<str> + ?Sized>(pattern: &P) -> crate::pattern::Pattern {
+            crate::pattern::Pattern::new(pattern.as_ref()).unwrap_or_else(|err| panic!("{}", err))
+        }
+
+        /// `RangeBounds::contains` with the item type pinned to the range's bound
+        /// type, so that `..` (which is `RangeBounds<T>` for every `T`) works too.
+        pub fn range_contains<T, R>(range: &R, item: &T) -> bool
+        where
+            T: PartialOrd,
+            R: std::ops::RangeBounds<T>,
+        {
+            range.contains(item)
+        }
+
+        /// Collections with fewer elements than this are printed in failure messages.
+        pub const MAX_DISPLAYED_LEN: usize = 20;
+
+        /// Number of elements shown by `assert_empty!` when it fails. Also the
+        /// minimum number of elements shown by `assert_starts_with!` /
+        /// `assert_ends_with!`.
+        pub const PREVIEW_LEN: usize = 10;
+
+        /// The haystack and prefix / suffix given to `assert_starts_with!` or
+        /// `assert_ends_with!`.
+        pub struct Affix<'a, H: ?Sized, P: ?Sized>(pub &'a H, pub &'a P);
+
+        /// Compares strings by `char`. Implemented directly on `Affix` so it takes
+        /// priority over [`SliceAffix`].
+        pub trait StrAffix {
+            fn is_prefix(&self) -> bool;
+            fn is_suffix(&self) -> bool;
+            fn head(&self) -> String;
+            fn tail(&self) -> String;
+            fn affix(&self) -> String;
+        }
+
+        impl<H, P> StrAffix for Affix<'_, H, P>
+        where
+            H: AsRef<str> + ?Sized,
+            P: AsRef<str> + ?Sized,
+        {
+            fn is_prefix(&self) -> bool {
+                self.0.as_ref().starts_with(self.1.as_ref())
+            }
+
+            fn is_suffix(&self) -> bool {
+                self.0.as_ref().ends_with(self.1.as_ref())
+            }
+
+            fn head(&self) -> String {
+                let (haystack, shown) = (self.0.as_ref(), self.preview_len());
+                match haystack.char_indices().nth(shown) {
+                    Some((end, _)) => format!("{:?}...", &haystack[..end]),
+                    None => format!("{:?}", haystack),
+                }
+            }
+
+            fn tail(&self) -> String {
+                let (haystack, shown) = (self.0.as_ref(), self.preview_len());
+                match haystack.char_indices().rev().nth(shown.saturating_sub(1)) {
+                    Some((start, _)) if start > 0 => format!("...{:?}", &haystack[start..]),
+                    _ => format!("{:?}", haystack),
+                }
+            }
+
+            fn affix(&self) -> String {
+                format!("{:?}", self.1.as_ref())
+            }
+        }
+
+        impl<H: ?Sized, P: AsRef<str> + ?Sized> Affix<'_, H, P> {
+            fn preview_len(&self) -> usize {
+                PREVIEW_LEN.max(self.1.as_ref().chars().count())
+            }
+        }
+
+        /// Compares anything that can be viewed as a slice element-wise.
+        pub trait SliceAffix<T> {
+            fn is_prefix(&self) -> bool;
+            fn is_suffix(&self) -> bool;
+            fn head(&self) -> String;
+            fn tail(&self) -> String;
+            fn affix(&self) -> String;
+        }
+
+        impl<'a, T, H, P> SliceAffix<T> for &Affix<'a, H, P>
+        where
+            T: PartialEq + std::fmt::Debug,
+            H: AsRef<[T]> + ?Sized,
+            P: AsRef<[T]> + ?Sized,
+        {
+            fn is_prefix(&self) -> bool {
+                self.0.as_ref().starts_with(self.1.as_ref())
+            }
+
+            fn is_suffix(&self) -> bool {
+                self.0.as_ref().ends_with(self.1.as_ref())
+            }
+
+            fn head(&self) -> String {
+                let haystack = self.0.as_ref();
+                let shown = PREVIEW_LEN.max(self.1.as_ref().len());
+                if haystack.len() > shown {
+                    format!("{:?}...", &haystack[..shown])
+                } else {
+                    format!("{:?}", haystack)
+                }
+            }
+
+            fn tail(&self) -> String {
+                let haystack = self.0.as_ref();
+                let shown = PREVIEW_LEN.max(self.1.as_ref().len());
+                if haystack.len() > shown {
+                    format!("...{:?}", &haystack[haystack.len() - shown..])
+                } else {
+                    format!("{:?}", haystack)
+                }
+            }
+
+            fn affix(&self) -> String {
+                format!("{:?}", self.1.as_ref())
+            }
+        }
+
+        /// A collection passed to `assert_empty!` / `assert_not_empty!`.
+        pub struct Elements<'a, C: ?Sized>(pub &'a C);
+
+        fn preview_of<I: Iterator<Item = D>, D: std::fmt::Debug>(iter: I) -> (usize, String) {
+            let mut count = 0;
+            let mut shown = Vec::new();
+            for item in iter {
+                if count < PREVIEW_LEN {
+                    shown.push(format!("{:?}", item));
+                }
+                count += 1;
+            }
+            if count > PREVIEW_LEN {
+                shown.push("...".to_string());
+            }
+            (count, format!("[{}]", shown.join(", ")))
+        }
+
+        /// Strings are previewed as their `char`s. Implemented directly on
+        /// `Elements` so it takes priority over [`IterElements`].
+        pub trait StrElements {
+            fn preview(&self) -> (usize, String);
+        }
+
+        impl StrElements for Elements<'_, str> {
+            fn preview(&self) -> (usize, String) {
+                preview_of(self.0.chars())
+            }
+        }
+
+        impl StrElements for Elements<'_, &str> {
+            fn preview(&self) -> (usize, String) {
+                preview_of(self.0.chars())
+            }
+        }
+
+        impl StrElements for Elements<'_, String> {
+            fn preview(&self) -> (usize, String) {
+                preview_of(self.0.chars())
+            }
+        }
+
+        /// Previews anything that can be iterated by reference.
+        pub trait IterElements {
+            fn preview(&self) -> (usize, String);
+        }
+
+        impl<'a, C: ?Sized> IterElements for &Elements<'a, C>
+        where
+            &'a C: IntoIterator,
+            <&'a C as IntoIterator>::Item: std::fmt::Debug,
+        {
+            fn preview(&self) -> (usize, String) {
+                preview_of(self.0.into_iter())
+            }
+        }
+
+        pub fn times(count: usize) -> &'static str {
+            if count == 1 {
+                "time"
+            } else {
+                "times"
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        #[test]
+        fn contains_all() {
+            let first = vec![1, 2, 3];
+            let second = vec![5, 4, 3, 2, 1];
+            assert_contains_all!(first, second);
+            assert_contains_all!(
+                first,
+                second,
+                "Expected {:?} to contain {:?}",
+                second,
+                first
+            );
+        }
+
+        #[test]
+        #[should_panic]
+        fn not_contains_all() {
+            let first = vec![1, 2, 3];
+            let second = vec![5, 4, 3, 2, 1];
+            assert_contains_all!(second, first);
+        }
+
+        #[test]
+        #[should_panic(expected = "3 elements missing from right: [4, 6, 8]")]
+        fn not_contains_all_reports_every_missing() {
+            let first = vec![1, 4, 2, 6, 8];
+            let second = vec![1, 2, 3];
+            assert_contains_all!(first, second);
+        }
+
+        #[test]
+        #[should_panic(expected = "1 element missing from right: [\"c\"]")]
+        fn not_contains_all_single_missing() {
+            let first = ["a", "b", "c"];
+            let second = std::collections::HashSet::from(["a", "b"]);
+            assert_contains_all!(first, second);
+        }
+
+        #[test]
+        #[should_panic]
+        fn not_contains_all2() {
+            let first = vec![1, 2, 3];
+            let second = vec![5, 4, 3, 2, 1];
+            assert_contains_all!(
+                second,
+                first,
+                "Expected {:?} to contain {:?}",
+                second,
+                first
+            );
+        }
+
+        #[test]
+        fn subset_and_superset() {
+            let small = vec![1, 2];
+            let big = std::collections::HashSet::from([1, 2, 3]);
+            let empty: Vec<i32> = Vec::new();
+            assert_subset!(small, big);
+            assert_subset!(empty, small);
+            assert_subset!(small, small, "a collection is a subset of itself");
+            assert_superset!(big, small);
+            assert_superset!(small, empty);
+            assert_superset!(small, small, "a collection is a superset of itself");
+        }
+
+        #[test]
+        #[should_panic(expected = "left is not a subset of right: missing [4, 5]")]
+        fn not_subset() {
+            let small = vec![1, 4, 5];
+            let big = vec![1, 2, 3];
+            assert_subset!(small, big);
+        }
+
+        #[test]
+        #[should_panic(expected = "whoops!")]
+        fn not_subset2() {
+            let small = [1, 4];
+            let big = [1, 2, 3];
+            assert_subset!(small, big, "whoops!");
+        }
+
+        #[test]
+        #[should_panic(expected = "left is not a superset of right: missing [4, 5]")]
+        fn not_superset() {
+            let big = std::collections::LinkedList::from([1, 2, 3]);
+            let small = vec![4, 1, 5];
+            assert_superset!(big, small);
+        }
+
+        #[test]
+        #[should_panic(expected = "whoops!")]
+        fn not_superset2() {
+            let big = [1, 2, 3];
+            let small = [4];
+            assert_superset!(big, small, "whoops!");
+        }
+
+        #[test]
+        fn not_contains() {
+            let values = vec![1, 2, 3];
+            assert_not_contains!(values, 4);
+            assert_not_contains!(values[..], 0);
+            assert_not_contains!(std::collections::HashSet::from([1, 2, 3]), 5);
+            assert_not_contains!(0..10, 10);
+            assert_not_contains!(values, 4, "Expected {:?} to not contain 4", values);
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected collection [1, 2, 3] to not contain 2")]
+        fn contains_unexpected() {
+            let values = vec![1, 2, 3];
+            assert_not_contains!(values, 2);
+        }
+
+        #[test]
+        #[should_panic(expected = "whoops!")]
+        fn contains_unexpected2() {
+            let values = std::collections::HashSet::from(["a", "b"]);
+            assert_not_contains!(values, "a", "whoops!");
+        }
+
+        #[test]
+        fn disjoint() {
+            let first = vec![1, 2, 3];
+            let second = [4, 5, 6];
+            let third = std::collections::HashSet::from([7, 8]);
+            let fourth = std::collections::LinkedList::from([9, 10]);
+            assert_disjoint!(first, second);
+            assert_disjoint!(second, third);
+            assert_disjoint!(third, fourth);
+            assert_disjoint!(
+                fourth,
+                first,
+                "Expected {:?} and {:?} to be disjoint",
+                fourth,
+                first
+            );
+        }
+
+        #[test]
+        fn disjoint_empty() {
+            let empty: Vec<i32> = Vec::new();
+            let values = vec![1, 2, 3];
+            assert_disjoint!(empty, values);
+            assert_disjoint!(values, empty);
+            assert_disjoint!(empty, empty);
+        }
+
+        #[test]
+        #[should_panic(expected = "found common elements [2, 3]")]
+        fn not_disjoint() {
+            let first = vec![1, 2, 3];
+            let second = std::collections::LinkedList::from([3, 4, 2]);
+            assert_disjoint!(first, second);
+        }
+
+        #[test]
+        #[should_panic(expected = "whoops!")]
+        fn not_disjoint2() {
+            let first = [1, 2, 3];
+            let second = std::collections::HashSet::from([3]);
+            assert_disjoint!(first, second, "whoops!");
+        }
+
+        #[test]
+        fn unordered_match() {
+            let first = vec![1, 2, 3];
+            let second = vec![3, 2, 1];
+            assert_unordered_match!(first, second);
+            assert_unordered_match!(second, first);
+            assert_unordered_match!(
+                first,
+                second,
+                "Expected {:?} to match contents of {:?}",
+                second,
+                first
+            );
+            assert_unordered_match!(
+                second,
+                first,
+                "Expected {:?} to match contents of {:?}",
+                second,
+                first
+            );
+        }
+
+        #[test]
+        #[should_panic]
+        fn not_unordered_match() {
+            let first = vec![1, 2, 3];
             let second = vec![5, 4, 3];
             assert_unordered_match!(first, second);
         }
 
         #[test]
-        #[should_panic]
-        fn not_unordered_match2() {
-            let first = vec![1, 2, 3];
-            let second = vec![5, 4, 3];
-            assert_unordered_match!(
-                first,
-                second,
-                "Expected {:?} to match contents of {:?}",
-                second,
-                first
+        #[should_panic]
+        fn not_unordered_match2() {
+            let first = vec![1, 2, 3];
+            let second = vec![5, 4, 3];
+            assert_unordered_match!(
+                first,
+                second,
+                "Expected {:?} to match contents of {:?}",
+                second,
+                first
+            );
+        }
+
+        #[test]
+        fn unordered_match_different_types() {
+            let first = vec![1, 2, 3];
+            let second = [3, 2, 1];
+            let third = {
+                let mut set = std::collections::HashSet::new();
+                assert!(set.insert(1), "Unable to add 1 to HashSet");
+                assert!(set.insert(2), "Unable to add 2 to HashSet");
+                assert!(set.insert(3), "Unable to add 3 to HashSet");
+                set
+            };
+            let fourth = std::collections::LinkedList::from([2, 1, 3]);
+
+            assert_unordered_match!(first, second);
+            assert_unordered_match!(second, third);
+            assert_unordered_match!(third, fourth);
+            assert_unordered_match!(fourth, first);
+
+            assert_unordered_match!(
+                first,
+                second,
+                "Expected {:?} to match contents of {:?}",
+                first,
+                second
+            );
+            assert_unordered_match!(
+                second,
+                third,
+                "Expected {:?} to match contents of {:?}",
+                second,
+                third
+            );
+            assert_unordered_match!(
+                third,
+                fourth,
+                "Expected {:?} to match contents of {:?}",
+                third,
+                fourth
+            );
+            assert_unordered_match!(
+                fourth,
+                first,
+                "Expected {:?} to match contents of {:?}",
+                fourth,
+                first
+            );
+        }
+
+        #[test]
+        #[should_panic(expected = "element 1 appears 2 times on the left but 1 time on the right")]
+        fn unordered_match_duplicates_vec() {
+            let first = vec![1, 1, 2];
+            let second = vec![1, 2, 2];
+            assert_unordered_match!(first, second);
+        }
+
+        #[test]
+        #[should_panic(expected = "element 2 appears 1 time on the left but 2 times on the right")]
+        fn unordered_match_duplicates_slice() {
+            let first: &[i32] = &[1, 2, 3, 3];
+            let second: &[i32] = &[3, 2, 1, 2];
+            assert_unordered_match!(first, second);
+        }
+
+        #[test]
+        #[should_panic(
+            expected = "element 'a' appears 3 times on the left but 1 time on the right"
+        )]
+        fn unordered_match_duplicates_linked_list() {
+            let first = std::collections::LinkedList::from(['a', 'a', 'a', 'b']);
+            let second = std::collections::LinkedList::from(['b', 'b', 'a', 'b']);
+            assert_unordered_match!(first, second);
+        }
+
+        #[test]
+        #[should_panic(
+            expected = "element 1.0 appears 2 times on the left but 1 time on the right"
+        )]
+        fn unordered_match_duplicates_without_hash() {
+            let first = vec![1.0, 1.0, 2.0];
+            let second = vec![2.0, 1.0, 2.0];
+            assert_unordered_match!(first, second);
+        }
+
+        #[test]
+        #[should_panic(
+            expected = "element 1 appears 2 times on the left but 1 time on the right\nduplicates differ"
+        )]
+        fn unordered_match_duplicates_custom_message() {
+            let first = vec![1, 1, 2];
+            let second = vec![1, 2, 2];
+            assert_unordered_match!(first, second, "duplicates differ");
+        }
+
+        #[test]
+        fn unordered_match_same_duplicates() {
+            let first = vec![1, 1, 2, 3, 3, 3];
+            let second = std::collections::LinkedList::from([3, 1, 3, 2, 3, 1]);
+            assert_unordered_match!(first, second);
+            assert_unordered_match!(vec![0.5, 0.5, 1.5], [1.5, 0.5, 0.5]);
+        }
+
+        #[derive(Debug, PartialEq, thiserror::Error)]
+        enum TestError {
+            #[error("key '{0}' not found")]
+            NotFound(String),
+            #[error("key '{0}' already exists")]
+            Duplicate(String),
+        }
+
+        fn lookup(key: &str) -> Result<usize, TestError> {
+            match key {
+                "dupe" => Err(TestError::Duplicate(key.to_string())),
+                "" => Err(TestError::NotFound(key.to_string())),
+                _ => Ok(key.len()),
+            }
+        }
+
+        #[test]
+        fn err_display() {
+            let err = assert_err_display!(lookup(""), "not found");
+            assert_eq!(err, TestError::NotFound(String::new()));
+            assert_err_display!(lookup("dupe"), String::from("'dupe'"));
+            assert_err_display!(lookup("dupe"), ["key", "'dupe'", "already exists"]);
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected Err, got Ok(3)")]
+        fn err_display_got_ok() {
+            assert_err_display!(lookup("key"), "not found");
+        }
+
+        #[test]
+        #[should_panic(
+            expected = "Expected error message to contain [\"not found\"], got \"key 'dupe' already exists\""
+        )]
+        fn err_display_wrong_message() {
+            assert_err_display!(lookup("dupe"), "not found");
+        }
+
+        #[test]
+        #[should_panic(
+            expected = "Expected error message to contain [\"'foo'\", \"missing\"], got \"key '' not found\""
+        )]
+        fn err_display_missing_several() {
+            assert_err_display!(lookup(""), ["key", "'foo'", "not found", "missing"]);
+        }
+
+        #[test]
+        fn ok_returns_value() {
+            let len = assert_ok!(lookup("key"));
+            assert_eq!(len, 3);
+            let len = assert_ok!(lookup("value"), 4..=6);
+            assert_eq!(len, 5);
+            let name = assert_ok!(Ok::<_, TestError>(Some(String::from("x"))), Some(name));
+            assert_eq!(name, Some("x".to_string()));
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected Ok, got Err(NotFound(\"\"))")]
+        fn ok_got_err() {
+            assert_ok!(lookup(""));
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected Ok matching `0..=2`, got Ok(3)")]
+        fn ok_wrong_pattern() {
+            assert_ok!(lookup("key"), 0..=2);
+        }
+
+        #[test]
+        fn err_returns_error() {
+            let err = assert_err!(lookup(""));
+            assert_eq!(err, TestError::NotFound("".to_string()));
+            let err = assert_err!(lookup("dupe"), TestError::Duplicate(_));
+            assert_eq!(err, TestError::Duplicate("dupe".to_string()));
+            let err = assert_err!(lookup(""), TestError::NotFound(key));
+            assert_eq!(err, TestError::NotFound("".to_string()));
+            assert_err!(
+                lookup("dupe"),
+                TestError::Duplicate(_) | TestError::NotFound(_)
+            );
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected Err, got Ok(3)")]
+        fn err_got_ok() {
+            assert_err!(lookup("key"));
+        }
+
+        #[test]
+        #[should_panic(
+            expected = "Expected Err matching `TestError::Duplicate(_)`, got Err(NotFound(\"\"))"
+        )]
+        fn err_wrong_pattern() {
+            assert_err!(lookup(""), TestError::Duplicate(_));
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected Err matching `TestError::Duplicate(_)`, got Ok(3)")]
+        fn err_pattern_got_ok() {
+            assert_err!(lookup("key"), TestError::Duplicate(_));
+        }
+
+        #[test]
+        fn matches_regex() {
+            let msg = String::from("key 'foo' not found");
+            assert_matches_regex!(msg, "key '.+' not found");
+            assert_matches_regex!("key1", String::from("^key\\d$"));
+            assert_matches_regex!(msg, "^key", "Expected {:?} to start with key", msg);
+            assert_not_matches_regex!(msg, "already exists");
+            assert_not_matches_regex!("key", "^\\d+$", "key is not a number");
+        }
+
+        #[test]
+        #[should_panic(
+            expected = "Expected \"key '' not found\" to match pattern \"key '.+' not found\""
+        )]
+        fn not_matches_regex() {
+            assert_matches_regex!("key '' not found", "key '.+' not found");
+        }
+
+        #[test]
+        #[should_panic(expected = "whoops!")]
+        fn not_matches_regex2() {
+            assert_matches_regex!("abc", "^b", "whoops!");
+        }
+
+        #[test]
+        #[should_panic(
+            expected = "Expected \"key 'a' not found\" to not match pattern \"not found$\""
+        )]
+        fn unexpected_regex_match() {
+            assert_not_matches_regex!("key 'a' not found", "not found$");
+        }
+
+        #[test]
+        #[should_panic(expected = "whoops!")]
+        fn unexpected_regex_match2() {
+            assert_not_matches_regex!("abc", "b", "whoops!");
+        }
+
+        #[test]
+        #[should_panic(expected = "invalid pattern \"key (.+\": unsupported syntax at position 4")]
+        fn invalid_regex() {
+            assert_matches_regex!("key (a", "key (.+");
+        }
+
+        #[test]
+        #[should_panic(
+            expected = "invalid pattern \"*\": quantifier without a target at position 0"
+        )]
+        fn invalid_regex_negated() {
+            assert_not_matches_regex!("abc", "*");
+        }
+
+        #[test]
+        fn eventually() {
+            use std::sync::atomic::{AtomicBool, Ordering};
+            use std::sync::Arc;
+
+            let flag = Arc::new(AtomicBool::new(false));
+            let handle = {
+                let flag = Arc::clone(&flag);
+                std::thread::spawn(move || {
+                    std::thread::sleep(std::time::Duration::from_millis(50));
+                    flag.store(true, Ordering::SeqCst);
+                })
+            };
+            assert_eventually!(flag.load(Ordering::SeqCst), 500);
+            handle.join().unwrap();
+
+            assert_eventually!(true, 0);
+            assert_eventually!(flag.load(Ordering::SeqCst), 500, 1, "flag should stay set");
+        }
+
+        #[test]
+        fn eventually_times_out() {
+            let start = std::time::Instant::now();
+            let result = std::panic::catch_unwind(|| {
+                assert_eventually!(1 + 1 == 3, 100, 5);
+            });
+            let elapsed = start.elapsed();
+            let payload = result.expect_err("assert_eventually should have panicked");
+            let message = payload.downcast_ref::<String>().unwrap();
+            assert!(
+                message.starts_with("Condition `1 + 1 == 3` was still false after"),
+                "unexpected message {:?}",
+                message
+            );
+            assert!(
+                message.ends_with("(timeout 100ms)"),
+                "unexpected message {:?}",
+                message
+            );
+            assert_within!(
+                elapsed.as_millis(),
+                100,
+                400,
+                "should give up shortly after the timeout"
+            );
+        }
+
+        #[test]
+        #[should_panic(expected = "whoops!")]
+        fn eventually_custom_message() {
+            assert_eventually!(false, 20, 5, "whoops!");
+        }
+
+        #[test]
+        fn panics() {
+            assert_panics!(|| panic!("boom"));
+            assert_panics!(
+                || std::panic::panic_any(42),
+                "non-string payloads count too"
+            );
+            assert_panics_with!(|| panic!("key '{}' not found", "foo"), "'foo' not found");
+            assert_panics_with!(|| panic!("static str"), String::from("static"));
+            assert_panics_with!(
+                || assert_eq!(1, 2),
+                "left == right",
+                "assert_eq! failures should be caught"
+            );
+        }
+
+        #[test]
+        fn panics_nested() {
+            assert_panics!(|| {
+                assert_panics!(|| panic!("inner"));
+                panic!("outer");
+            });
+            assert_panics_with!(|| assert_panics!(|| ()), "returned normally");
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected closure to panic, but it returned normally")]
+        fn panics_not_panicking() {
+            assert_panics!(|| 5);
+        }
+
+        #[test]
+        #[should_panic(
+            expected = "Expected closure to panic with \"boom\", but it returned normally"
+        )]
+        fn panics_with_not_panicking() {
+            assert_panics_with!(|| (), "boom");
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected panic message to contain \"boom\", got \"bang\"")]
+        fn panics_with_wrong_message() {
+            assert_panics_with!(|| panic!("bang"), "boom");
+        }
+
+        #[test]
+        #[should_panic(
+            expected = "Expected panic message to contain \"42\", but the payload was not a string"
+        )]
+        fn panics_with_non_string_payload() {
+            assert_panics_with!(|| std::panic::panic_any(42), "42");
+        }
+
+        #[test]
+        #[should_panic(expected = "whoops!")]
+        fn panics_with_custom_message() {
+            assert_panics_with!(|| panic!("bang"), "boom", "whoops!");
+        }
+
+        #[test]
+        fn bytes_eq() {
+            let bytes = vec![1u8, 2, 3];
+            assert_bytes_eq!(bytes, [1, 2, 3]);
+            assert_bytes_eq!(&bytes[..], bytes);
+            assert_bytes_eq!(b"abc", "abc".as_bytes(), "arrays and slices mix");
+            assert_bytes_eq!(Vec::<u8>::new(), []);
+        }
+
+        #[test]
+        #[should_panic(expected = "Byte buffers differ at offset 3 (left len 3, right len 5)")]
+        fn bytes_eq_different_lengths() {
+            assert_bytes_eq!(vec![1u8, 2, 3], [1, 2, 3, 4, 5]);
+        }
+
+        #[test]
+        fn bytes_eq_diff_in_long_buffer() {
+            let left: Vec<u8> = (0..64).collect();
+            let mut right = left.clone();
+            right[37] = 0xff;
+            let diff = super::__private::bytes_diff(&left, &right).unwrap();
+            assert_eq!(
+                diff,
+                "Byte buffers differ at offset 37 (left len 64, right len 64)\n  \
+                 offset    left                    | right\n  \
+                 00000018  18 19 1a 1b 1c 1d 1e 1f | 18 19 1a 1b 1c 1d 1e 1f\n\
+                 > 00000020  20 21 22 23 24 25 26 27 | 20 21 22 23 24 ff 26 27"
+            );
+        }
+
+        #[test]
+        fn bytes_eq_diff_past_end() {
+            let diff = super::__private::bytes_diff(&[1u8, 2], b"\x01\x02\x03").unwrap();
+            assert_eq!(
+                diff,
+                "Byte buffers differ at offset 2 (left len 2, right len 3)\n  \
+                 offset    left                    | right\n\
+                 > 00000000  01 02 -- -- -- -- -- -- | 01 02 03 -- -- -- -- --\n  \
+                 00000008  -- -- -- -- -- -- -- -- | -- -- -- -- -- -- -- --"
+            );
+        }
+
+        #[test]
+        #[should_panic(expected = "> 00000000  01 02 03 -- -- -- -- -- | 01 ff 03")]
+        fn bytes_eq_middle_difference() {
+            assert_bytes_eq!([1u8, 2, 3], [1u8, 0xff, 3]);
+        }
+
+        #[test]
+        #[should_panic(expected = "-- -- -- -- --\nwhile reading row 7")]
+        fn bytes_eq_custom_message_is_appended() {
+            assert_bytes_eq!(vec![1u8], vec![2u8], "while reading row {}", 7);
+        }
+
+        #[test]
+        fn map_match() {
+            use std::collections::{BTreeMap, HashMap};
+
+            let hash: HashMap<String, i32> = [("a".to_string(), 1), ("b".to_string(), 2)]
+                .into_iter()
+                .collect();
+            let btree: BTreeMap<String, i32> = hash.clone().into_iter().collect();
+            assert_map_match!(hash, btree);
+            assert_map_match!(btree, vec![("b".to_string(), 2), ("a".to_string(), 1)]);
+            assert_map_match!(HashMap::<i32, i32>::new(), BTreeMap::new(), "both empty");
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected maps to match, but\n  keys only in left: [\"c\"]")]
+        fn map_match_only_in_left() {
+            assert_map_match!(vec![("a", 1), ("c", 3)], vec![("a", 1)]);
+        }
+
+        #[test]
+        #[should_panic(
+            expected = "Expected maps to match, but\n  keys only in right: [\"b\", \"c\"]"
+        )]
+        fn map_match_only_in_right() {
+            let left: std::collections::BTreeMap<_, _> = [("a", 1)].into_iter().collect();
+            let right: std::collections::BTreeMap<_, _> =
+                [("a", 1), ("b", 2), ("c", 3)].into_iter().collect();
+            assert_map_match!(left, right);
+        }
+
+        #[test]
+        #[should_panic(expected = "values differ for key \"b\": left = 2, right = 20")]
+        fn map_match_different_values() {
+            let left: std::collections::HashMap<_, _> = [("a", 1), ("b", 2)].into_iter().collect();
+            let right: std::collections::HashMap<_, _> =
+                [("a", 1), ("b", 20)].into_iter().collect();
+            assert_map_match!(left, right);
+        }
+
+        #[test]
+        fn map_match_reports_every_category() {
+            let diff = super::__private::map_diff(
+                &[("a", 1), ("b", 2), ("c", 3)],
+                &[("b", 2), ("c", 30), ("d", 4)],
+            )
+            .unwrap();
+            assert_eq!(
+                diff,
+                "Expected maps to match, but\n  \
+                 keys only in left: [\"a\"]\n  \
+                 keys only in right: [\"d\"]\n  \
+                 values differ for key \"c\": left = 3, right = 30"
+            );
+        }
+
+        #[test]
+        #[should_panic(expected = "maps should match")]
+        fn map_match_custom_message() {
+            assert_map_match!(vec![(1, 1)], vec![(1, 2)], "maps should match");
+        }
+
+        #[test]
+        fn starts_and_ends_with() {
+            assert_starts_with!("key1", "key");
+            assert_starts_with!(String::from("key1"), String::from("key"), "string prefix");
+            assert_ends_with!("key1", '1'.to_string());
+            assert_starts_with!(b"\x00\x01\x02", [0, 1]);
+            assert_ends_with!("bytes".as_bytes(), b"tes", "byte suffix");
+            assert_starts_with!(vec![1, 2, 3], vec![1, 2]);
+            assert_ends_with!(vec![1, 2, 3], [2, 3]);
+            assert_starts_with!(&[1, 2, 3][..], [1, 2, 3]);
+        }
+
+        #[test]
+        fn starts_and_ends_with_empty_affix() {
+            assert_starts_with!("", "");
+            assert_starts_with!("abc", "");
+            assert_ends_with!("abc", String::new());
+            assert_starts_with!(Vec::<i32>::new(), []);
+            assert_ends_with!(vec![1, 2], Vec::<i32>::new());
+            assert_starts_with!(b"abc", b"");
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected \"hello, wor\"... to start with \"world\"")]
+        fn not_starts_with_str() {
+            assert_starts_with!("hello, world!", "world");
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected ...\"lo, world!\" to end with \"hello\"")]
+        fn not_ends_with_str() {
+            assert_ends_with!(String::from("hello, world!"), "hello");
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected \"ab\" to start with \"abc\"")]
+        fn not_starts_with_longer_prefix() {
+            assert_starts_with!("ab", "abc");
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected [1, 2, 3] to end with [1, 2]")]
+        fn not_ends_with_vec() {
+            assert_ends_with!(vec![1, 2, 3], vec![1, 2]);
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected [0, 1, 2, 3, 4, 5, 6, 7, 8, 9]... to start with [1]")]
+        fn not_starts_with_long_vec() {
+            let values: Vec<i32> = (0..100).collect();
+            assert_starts_with!(values, [1]);
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected [1, 2] to start with [2]")]
+        fn not_starts_with_bytes() {
+            assert_starts_with!(b"\x01\x02", b"\x02");
+        }
+
+        #[test]
+        #[should_panic(expected = "wrong header")]
+        fn not_starts_with_custom_message() {
+            assert_starts_with!(vec![1, 2], [2], "wrong {}", "header");
+        }
+
+        fn panic_text<F: FnOnce()>(f: F) -> String {
+            let payload = super::__private::catch_panic(f).expect("assert should have panicked");
+            super::__private::panic_message(&*payload)
+                .expect("panic payload should be a string")
+                .to_string()
+        }
+
+        /// Checks that the custom message form of an assert panics with the
+        /// plain form's diagnostic followed by the message on its own line.
+        fn assert_appended<P: FnOnce(), C: FnOnce()>(plain: P, custom: C, message: &str) {
+            assert_eq!(
+                panic_text(custom),
+                format!("{}\n{}", panic_text(plain), message)
+            );
+        }
+
+        #[test]
+        fn set_custom_messages_are_appended() {
+            assert_appended(
+                || assert_subset!(vec![1, 4], vec![1, 2]),
+                || assert_subset!(vec![1, 4], vec![1, 2], "row {}", 4),
+                "row 4",
+            );
+            assert_appended(
+                || assert_superset!(vec![1, 2], vec![1, 4]),
+                || assert_superset!(vec![1, 2], vec![1, 4], "row {}", 4),
+                "row 4",
+            );
+            assert_appended(
+                || assert_not_contains!(vec![1, 2], 2),
+                || assert_not_contains!(vec![1, 2], 2, "2 was deleted"),
+                "2 was deleted",
+            );
+            assert_appended(
+                || assert_disjoint!(vec![1, 2, 3], [3, 2]),
+                || assert_disjoint!(vec![1, 2, 3], [3, 2], "no overlap"),
+                "no overlap",
+            );
+        }
+
+        #[test]
+        fn range_custom_messages_are_appended() {
+            assert_appended(
+                || assert_not_in_range!(5, 0..10),
+                || assert_not_in_range!(5, 0..10, "{} is inside", 5),
+                "5 is inside",
+            );
+            assert_appended(
+                || assert_gt_lte!(0, 0, 10),
+                || assert_gt_lte!(0, 0, 10, "too low"),
+                "too low",
+            );
+            assert_appended(
+                || assert_gte_lt!(10, 0, 10),
+                || assert_gte_lt!(10, 0, 10, "too high"),
+                "too high",
+            );
+            assert_appended(
+                || assert_time_close!(100, 110, 2),
+                || assert_time_close!(100, 110, 2, "clock skew"),
+                "clock skew",
+            );
+        }
+
+        #[test]
+        fn size_custom_messages_are_appended() {
+            assert_appended(
+                || assert_len!(vec![1, 2], 3),
+                || assert_len!(vec![1, 2], 3, "lost a row"),
+                "lost a row",
+            );
+            assert_appended(
+                || assert_len!((0..50).collect::<Vec<_>>(), 3),
+                || assert_len!((0..50).collect::<Vec<_>>(), 3, "too many rows"),
+                "too many rows",
+            );
+            assert_appended(
+                || assert_empty!(vec![1]),
+                || assert_empty!(vec![1], "should be cleared"),
+                "should be cleared",
+            );
+            assert_appended(
+                || assert_not_empty!(""),
+                || assert_not_empty!("", "should be loaded"),
+                "should be loaded",
+            );
+        }
+
+        #[test]
+        fn predicate_custom_messages_are_appended() {
+            assert_appended(
+                || assert_all!(vec![1, 2, 3], |n| *n < 2),
+                || assert_all!(vec![1, 2, 3], |n| *n < 2, "below {}", 2),
+                "below 2",
+            );
+            assert_appended(
+                || assert_any!(vec![1, 2], |n| *n > 2),
+                || assert_any!(vec![1, 2], |n| *n > 2, "above 2"),
+                "above 2",
+            );
+        }
+
+        #[test]
+        fn order_custom_messages_are_appended() {
+            assert_appended(
+                || assert_unique!(vec![1, 2, 1]),
+                || assert_unique!(vec![1, 2, 1], "keys repeat"),
+                "keys repeat",
+            );
+            assert_appended(
+                || assert_sorted!(vec![2, 1]),
+                || assert_sorted!(vec![2, 1], "unsorted"),
+                "unsorted",
+            );
+            assert_appended(
+                || assert_sorted_by!(vec![1, 2], |a, b| b.cmp(a)),
+                || assert_sorted_by!(vec![1, 2], |a, b| b.cmp(a), "not descending"),
+                "not descending",
+            );
+            assert_appended(
+                || assert_monotonic!(vec![2, 1]),
+                || assert_monotonic!(vec![2, 1], "went back"),
+                "went back",
+            );
+            assert_appended(
+                || assert_strictly_monotonic!(vec![1, 1]),
+                || assert_strictly_monotonic!(vec![1, 1], "repeated"),
+                "repeated",
+            );
+            assert_appended(
+                || assert_iter_eq!([1, 2], [1, 3]),
+                || assert_iter_eq!([1, 2], [1, 3], "second differs"),
+                "second differs",
+            );
+        }
+
+        #[test]
+        fn option_custom_messages_are_appended() {
+            assert_appended(
+                || {
+                    assert_some!(None::<i32>);
+                },
+                || {
+                    assert_some!(None::<i32>, "missing {}", "value");
+                },
+                "missing value",
+            );
+            assert_appended(
+                || assert_some_eq!(Some(1), 2),
+                || assert_some_eq!(Some(1), 2, "wrong value"),
+                "wrong value",
+            );
+            assert_appended(
+                || assert_some_eq!(None::<i32>, 2),
+                || assert_some_eq!(None::<i32>, 2, "no value"),
+                "no value",
+            );
+            assert_appended(
+                || assert_none!(Some(1)),
+                || assert_none!(Some(1), "should be gone"),
+                "should be gone",
+            );
+        }
+
+        #[test]
+        fn string_custom_messages_are_appended() {
+            assert_appended(
+                || assert_matches_regex!("key", "^val"),
+                || assert_matches_regex!("key", "^val", "not a value"),
+                "not a value",
+            );
+            assert_appended(
+                || assert_not_matches_regex!("key", "^k"),
+                || assert_not_matches_regex!("key", "^k", "is a key"),
+                "is a key",
+            );
+            assert_appended(
+                || assert_starts_with!("key1", "val"),
+                || assert_starts_with!("key1", "val", "wrong prefix"),
+                "wrong prefix",
+            );
+            assert_appended(
+                || assert_ends_with!(vec![1, 2], [1]),
+                || assert_ends_with!(vec![1, 2], [1], "wrong suffix"),
+                "wrong suffix",
+            );
+        }
+
+        #[test]
+        fn eventually_custom_message_is_appended() {
+            let text = panic_text(|| assert_eventually!(false, 20, 5, "never {}", "true"));
+            assert_starts_with!(text, "Condition `false` was still false after ");
+            assert_ends_with!(text, " (timeout 20ms)\nnever true");
+        }
+
+        #[test]
+        fn panics_custom_messages_are_appended() {
+            assert_appended(
+                || assert_panics!(|| ()),
+                || assert_panics!(|| (), "should fail"),
+                "should fail",
+            );
+            assert_appended(
+                || assert_panics_with!(|| (), "boom"),
+                || assert_panics_with!(|| (), "boom", "should fail"),
+                "should fail",
+            );
+            assert_appended(
+                || assert_panics_with!(|| panic!("bang"), "boom"),
+                || assert_panics_with!(|| panic!("bang"), "boom", "wrong panic"),
+                "wrong panic",
+            );
+            assert_appended(
+                || assert_panics_with!(|| std::panic::panic_any(1), "boom"),
+                || assert_panics_with!(|| std::panic::panic_any(1), "boom", "not a string"),
+                "not a string",
+            );
+        }
+
+        #[test]
+        fn map_custom_messages_are_appended() {
+            use std::collections::BTreeMap;
+
+            let map: BTreeMap<&str, i32> = [("a", 1)].into_iter().collect();
+            assert_appended(
+                || assert_map_match!(vec![(1, 1)], vec![(1, 2)]),
+                || assert_map_match!(vec![(1, 1)], vec![(1, 2)], "maps differ"),
+                "maps differ",
+            );
+            assert_appended(
+                || assert_contains_key!(map, "b"),
+                || assert_contains_key!(map, "b", "b was inserted"),
+                "b was inserted",
+            );
+            assert_appended(
+                || assert_not_contains_key!(map, "a"),
+                || assert_not_contains_key!(map, "a", "a was deleted"),
+                "a was deleted",
+            );
+            assert_appended(
+                || assert_contains_value!(map, 2),
+                || assert_contains_value!(map, 2, "2 was set"),
+                "2 was set",
+            );
+        }
+
+        #[test]
+        fn check_ok() {
+            assert_eq!(check_contains_all!(vec![1, 2], vec![3, 2, 1]), Ok(()));
+            assert_eq!(
+                check_contains_all!(vec![1, 2], vec![2, 1], "custom"),
+                Ok(())
+            );
+            assert_eq!(check_unordered_match!(vec![1, 2, 2], [2, 1, 2]), Ok(()));
+            assert_eq!(check_unordered_match!(vec![1, 2], [2, 1], "custom"), Ok(()));
+            assert_eq!(check_in_range!(5, 0..10), Ok(()));
+            assert_eq!(check_in_range!(10, 0..=10, "custom"), Ok(()));
+            assert_eq!(check_within!(5, 0, 10), Ok(()));
+            assert_eq!(check_within!(0, 0, 10, "custom"), Ok(()));
+        }
+
+        #[test]
+        fn check_err_matches_assert_panic() {
+            let err = check_contains_all!(vec![1, 4, 6], vec![1, 2]).unwrap_err();
+            assert_eq!(err, "2 elements missing from right: [4, 6]");
+            assert_eq!(
+                err,
+                panic_text(|| assert_contains_all!(vec![1, 4, 6], vec![1, 2]))
+            );
+
+            let err = check_unordered_match!(vec![1, 2], vec![1, 2, 3]).unwrap_err();
+            assert_eq!(
+                err,
+                "Expected: size of left and right collections to be equal, Actual: left = 2, right = 3"
+            );
+            assert_eq!(
+                err,
+                panic_text(|| assert_unordered_match!(vec![1, 2], vec![1, 2, 3]))
+            );
+
+            let err = check_unordered_match!(vec![1, 1, 2], vec![1, 2, 2]).unwrap_err();
+            assert_eq!(
+                err,
+                "element 1 appears 2 times on the left but 1 time on the right"
+            );
+            assert_eq!(
+                err,
+                panic_text(|| assert_unordered_match!(vec![1, 1, 2], vec![1, 2, 2]))
+            );
+
+            let err = check_in_range!(10, 0..10).unwrap_err();
+            assert_eq!(err, "Expected range 0..10 to contain 10");
+            assert_eq!(err, panic_text(|| assert_in_range!(10, 0..10)));
+
+            let err = check_within!(-1, 0, 10).unwrap_err();
+            assert_eq!(err, "Expected -1 to be gte 0");
+            assert_eq!(err, panic_text(|| assert_within!(-1, 0, 10)));
+
+            let err = check_within!(11, 0, 10).unwrap_err();
+            assert_eq!(err, "Expected 11 to be lte 10");
+            assert_eq!(err, panic_text(|| assert_within!(11, 0, 10)));
+        }
+
+        #[test]
+        fn check_err_custom_message_matches_assert_panic() {
+            let err = check_contains_all!(vec![4], vec![1], "row {} missing", 4).unwrap_err();
+            assert_eq!(err, "1 element missing from right: [4]\nrow 4 missing");
+            assert_eq!(
+                err,
+                panic_text(|| assert_contains_all!(vec![4], vec![1], "row {} missing", 4))
+            );
+
+            let err = check_unordered_match!(vec![1], vec![1, 1], "mismatch").unwrap_err();
+            assert_eq!(
+                err,
+                "Expected: size of left and right collections to be equal, Actual: left = 1, right = 2\nmismatch"
+            );
+            assert_eq!(
+                err,
+                panic_text(|| assert_unordered_match!(vec![1], vec![1, 1], "mismatch"))
+            );
+
+            let err = check_in_range!(10, 0..10, "out of range").unwrap_err();
+            assert_eq!(err, "Expected range 0..10 to contain 10\nout of range");
+            assert_eq!(
+                err,
+                panic_text(|| assert_in_range!(10, 0..10, "out of range"))
+            );
+
+            let err = check_within!(-1, 0, 10, "too small").unwrap_err();
+            assert_eq!(err, "Expected -1 to be gte 0\ntoo small");
+            assert_eq!(err, panic_text(|| assert_within!(-1, 0, 10, "too small")));
+        }
+
+        #[test]
+        fn debug_asserts_type_check_and_pass() {
+            // These must compile (and not warn about unused values) in every profile.
+            let first = vec![1, 2];
+            let second = vec![2, 1, 3];
+            let value = 5;
+            debug_assert_contains_all!(first, second);
+            debug_assert_contains_all!(first, second, "custom {}", "message");
+            debug_assert_unordered_match!(first, [2, 1]);
+            debug_assert_unordered_match!(first, [2, 1], "custom message");
+            debug_assert_in_range!(value, 0..10);
+            debug_assert_in_range!(value, 0..10, "custom message");
+            debug_assert_within!(value, 0, 10);
+            debug_assert_within!(value, 0, 10, "custom message");
+        }
+
+        #[test]
+        #[cfg(debug_assertions)]
+        #[should_panic(expected = "1 element missing from right: [3]")]
+        fn debug_contains_all_panics() {
+            debug_assert_contains_all!(vec![1, 3], vec![1, 2]);
+        }
+
+        #[test]
+        #[cfg(debug_assertions)]
+        #[should_panic(expected = "whoops!")]
+        fn debug_unordered_match_panics() {
+            debug_assert_unordered_match!(vec![1, 3], vec![1, 2], "whoops!");
+        }
+
+        #[test]
+        #[cfg(debug_assertions)]
+        #[should_panic(expected = "Expected range 0..10 to contain 10")]
+        fn debug_in_range_panics() {
+            debug_assert_in_range!(10, 0..10);
+        }
+
+        #[test]
+        #[cfg(debug_assertions)]
+        #[should_panic(expected = "Expected 11 to be lte 10\nwhoops!")]
+        fn debug_within_panics() {
+            debug_assert_within!(11, 0, 10, "whoops!");
+        }
+
+        #[test]
+        #[cfg(not(debug_assertions))]
+        fn debug_asserts_skipped_in_release() {
+            debug_assert_contains_all!(vec![1, 3], vec![1, 2]);
+            debug_assert_unordered_match!(vec![1, 3], vec![1, 2]);
+            debug_assert_in_range!(10, 0..10);
+            debug_assert_within!(11, 0, 10);
+        }
+
+        #[test]
+        fn in_range() {
+            let range = 0..10;
+            let i = 5;
+            assert_in_range!(i, range);
+            assert_in_range!(i, range, "Expected i to be in range!");
+        }
+
+        #[test]
+        #[should_panic]
+        fn not_in_range() {
+            let range = 0..10;
+            let i = 11;
+            assert_in_range!(i, range);
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected range 0..10 to contain 11\nwhoops!")]
+        fn not_in_range2() {
+            let range = 0..10;
+            let i = 11;
+            assert_in_range!(i, range, "whoops!");
+        }
+
+        #[test]
+        fn len() {
+            let empty: Vec<i32> = Vec::new();
+            assert_len!(empty, 0);
+            assert_len!(vec![1, 2, 3], 3);
+            assert_len!([1, 2], 2);
+            let map = std::collections::HashMap::from([("key1", 1), ("key2", 2)]);
+            assert_len!(map, 2);
+            assert_len!(std::collections::HashMap::<i32, i32>::new(), 0);
+            assert_len!(map, 2, "Expected {} entries", 2);
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected collection to have 2 elements, found 3: [1, 2, 3]")]
+        fn wrong_len() {
+            assert_len!(vec![1, 2, 3], 2);
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected collection to have 1 elements, found 0: {}")]
+        fn wrong_len_map() {
+            assert_len!(std::collections::HashMap::<i32, i32>::new(), 1);
+        }
+
+        #[test]
+        fn wrong_len_large_collection_not_displayed() {
+            let result = std::panic::catch_unwind(|| {
+                let values: Vec<usize> = (0..25).collect();
+                assert_len!(values, 24);
+            });
+            let payload = result.expect_err("assert_len should have panicked");
+            let message = payload
+                .downcast_ref::<String>()
+                .expect("panic payload should be a String");
+            assert_eq!(message, "Expected collection to have 24 elements, found 25");
+        }
+
+        #[test]
+        #[should_panic(expected = "whoops!")]
+        fn wrong_len2() {
+            assert_len!(Vec::<i32>::new(), 1, "whoops!");
+        }
+
+        #[test]
+        fn empty() {
+            assert_empty!(Vec::<i32>::new());
+            assert_empty!([0u8; 0]);
+            assert_empty!(std::collections::HashMap::<i32, i32>::new());
+            assert_empty!("");
+            assert_empty!(String::new(), "Expected an empty string");
+        }
+
+        #[test]
+        #[should_panic(
+            expected = "Expected collection to be empty, found 12 elements: [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, ...]"
+        )]
+        fn not_empty_vec() {
+            let values: Vec<i32> = (0..12).collect();
+            assert_empty!(values);
+        }
+
+        #[test]
+        #[should_panic(
+            expected = "Expected collection to be empty, found 2 elements: [\'h\', \'i\']"
+        )]
+        fn not_empty_str() {
+            assert_empty!("hi");
+        }
+
+        #[test]
+        #[should_panic(expected = "whoops!")]
+        fn not_empty_custom() {
+            assert_empty!(vec![1], "whoops!");
+        }
+
+        #[test]
+        fn not_empty() {
+            assert_not_empty!(vec![1]);
+            assert_not_empty!(std::collections::HashSet::from([1]));
+            assert_not_empty!("a");
+            assert_not_empty!(String::from("a"), "Expected a non-empty string");
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected collection to not be empty")]
+        fn empty_unexpected() {
+            assert_not_empty!(String::new());
+        }
+
+        #[test]
+        #[should_panic(expected = "whoops!")]
+        fn empty_unexpected2() {
+            assert_not_empty!(Vec::<i32>::new(), "whoops!");
+        }
+
+        #[test]
+        fn all() {
+            let keys = vec!["key1", "key2", "key3"];
+            assert_all!(keys, |key| key.starts_with("key"));
+            assert_all!(Vec::<i32>::new(), |_| false);
+            assert_all!([2, 4, 6], |n| n % 2 == 0, "Expected only even numbers");
+        }
+
+        #[test]
+        #[should_panic(
+            expected = "Expected all elements to satisfy the predicate, 2 did not: [1] 3, [3] 5"
+        )]
+        fn not_all() {
+            assert_all!(vec![2, 3, 4, 5], |n| n % 2 == 0);
+        }
+
+        #[test]
+        #[should_panic(expected = "whoops!")]
+        fn not_all2() {
+            assert_all!(vec![1], |n| *n > 1, "whoops!");
+        }
+
+        #[test]
+        fn any() {
+            assert_any!(vec![1, 2, 3], |n| *n == 3);
+            assert_any!(["a", "bb"], |s| s.len() == 2, "Expected a two-char string");
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected at least one element of [] to satisfy the predicate")]
+        fn any_empty() {
+            assert_any!(Vec::<i32>::new(), |_| true);
+        }
+
+        #[test]
+        #[should_panic(expected = "whoops!")]
+        fn not_any() {
+            assert_any!(vec![1, 2, 3], |n| *n > 3, "whoops!");
+        }
+
+        #[test]
+        fn unique() {
+            let empty: Vec<i32> = Vec::new();
+            assert_unique!(empty);
+            assert_unique!(vec![1, 2, 3]);
+            assert_unique!([0.5, 1.5], "floats only need PartialEq");
+            assert_unique!(
+                std::collections::LinkedList::from(["a", "b"]),
+                "Expected keys to be unique"
+            );
+        }
+
+        #[test]
+        #[should_panic(expected = "found duplicates: 1 (x3), 3 (x2)")]
+        fn not_unique() {
+            assert_unique!(vec![1, 2, 1, 3, 1, 3]);
+        }
+
+        #[test]
+        #[should_panic(expected = "whoops!")]
+        fn not_unique2() {
+            assert_unique!([1, 2, 1], "whoops!");
+        }
+
+        #[test]
+        fn sorted() {
+            let empty: Vec<i32> = Vec::new();
+            assert_sorted!(empty);
+            assert_sorted!([42]);
+            assert_sorted!(vec![1, 2, 2, 3]);
+            assert_sorted!(vec!["a", "b", "c"], "Expected letters to be sorted");
+        }
+
+        #[test]
+        #[should_panic(expected = "element 1 (5) is out of order with element 2 (4)")]
+        fn not_sorted() {
+            assert_sorted!(vec![1, 5, 4, 6]);
+        }
+
+        #[test]
+        #[should_panic(expected = "element 0 (3) is out of order with element 1 (2)")]
+        fn not_sorted_reversed() {
+            assert_sorted!([3, 2, 1]);
+        }
+
+        #[test]
+        #[should_panic(expected = "whoops!")]
+        fn not_sorted2() {
+            assert_sorted!([3, 2, 1], "whoops!");
+        }
+
+        #[test]
+        fn sorted_by() {
+            let empty: Vec<i32> = Vec::new();
+            assert_sorted_by!(empty, |a, b| a.cmp(b));
+            assert_sorted_by!([42], |a, b| a.cmp(b));
+            assert_sorted_by!(vec![3, 2, 2, 1], |a, b| b.cmp(a));
+            assert_sorted_by!(
+                vec!["a", "bb", "ccc"],
+                |a, b| a.len().cmp(&b.len()),
+                "Expected strings sorted by length"
+            );
+        }
+
+        #[test]
+        #[should_panic(expected = "element 0 (1) is out of order with element 1 (2)")]
+        fn not_sorted_by() {
+            assert_sorted_by!(vec![1, 2, 3], |a, b| b.cmp(a));
+        }
+
+        #[test]
+        #[should_panic(expected = "whoops!")]
+        fn not_sorted_by2() {
+            assert_sorted_by!(vec![1, 2, 3], |a, b| b.cmp(a), "whoops!");
+        }
+
+        #[test]
+        fn in_range_inclusive_and_open() {
+            assert_in_range!(0, 0..=10);
+            assert_in_range!(10, 0..=10);
+            assert_in_range!(0, 0..);
+            assert_in_range!(i64::MAX, 0..);
+            assert_in_range!(-5, ..0);
+            assert_in_range!(0, ..=0);
+            assert_in_range!(42, .., "Full range contains everything");
+            assert_in_range!(0.5, 0.0..1.0);
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected range 0..10 to contain 10")]
+        fn in_range_exclusive_end() {
+            assert_in_range!(10, 0..10);
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected range 0.. to contain -1")]
+        fn in_range_from() {
+            assert_in_range!(-1, 0..);
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected range ..0 to contain 0")]
+        fn in_range_to() {
+            assert_in_range!(0, ..0);
+        }
+
+        #[test]
+        fn not_in_range_ok() {
+            assert_not_in_range!(10, 0..10);
+            assert_not_in_range!(11, 0..=10);
+            assert_not_in_range!(-1, 0..);
+            assert_not_in_range!(0, ..0);
+            assert_not_in_range!(1, ..=0, "Expected 1 to be outside ..=0");
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected range 0..=10 to NOT contain 10")]
+        fn not_in_range_inclusive_end() {
+            assert_not_in_range!(10, 0..=10);
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected range .. to NOT contain 1")]
+        fn not_in_range_full() {
+            assert_not_in_range!(1, ..);
+        }
+
+        #[test]
+        #[should_panic(expected = "whoops!")]
+        fn not_in_range_custom() {
+            assert_not_in_range!(0, 0..10, "whoops!");
+        }
+
+        #[test]
+        fn within() {
+            let i = 5;
+            assert_within!(i, 0, 10);
+            assert_within!(i, 0, 10, "Expected i to be in range!");
+        }
+
+        #[test]
+        #[should_panic]
+        fn not_within() {
+            let i = 11;
+            assert_within!(i, 0, 10);
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected 11 to be lte 10\nwhoops!")]
+        fn not_within2() {
+            let i = 11;
+            assert_within!(i, 0, 10, "whoops!");
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected -1 to be gte 0\nwhoops!")]
+        fn not_within_below_min() {
+            let i = -1;
+            assert_within!(i, 0, 10, "whoops!");
+        }
+
+        #[test]
+        fn within_evaluates_once() {
+            let mut calls = 0;
+            let mut next_id = || {
+                calls += 1;
+                calls
+            };
+            assert_within!(next_id(), 1, 1);
+            assert_within!(next_id(), 2, 2, "second call should return 2");
+            assert_eq!(calls, 2);
+        }
+
+        #[test]
+        fn strictly_between() {
+            assert_strictly_between!(5, 4, 6);
+            assert_strictly_between!(0.5, 0.0, 1.0, "floats work too");
+            assert_panics_with!(
+                || assert_strictly_between!(4, 4, 6),
+                "Expected 4 to be gt 4 (exclusive lower bound)"
+            );
+            assert_panics_with!(
+                || assert_strictly_between!(6, 4, 6),
+                "Expected 6 to be lt 6 (exclusive upper bound)"
+            );
+        }
+
+        #[test]
+        fn gt_lte() {
+            assert_gt_lte!(5, 4, 6);
+            assert_gt_lte!(6, 4, 6);
+            assert_panics_with!(
+                || assert_gt_lte!(4, 4, 6),
+                "Expected 4 to be gt 4 (exclusive lower bound)"
+            );
+            assert_panics_with!(
+                || assert_gt_lte!(7, 4, 6),
+                "Expected 7 to be lte 6 (inclusive upper bound)"
+            );
+        }
+
+        #[test]
+        fn gte_lt() {
+            assert_gte_lt!(4, 4, 6);
+            assert_gte_lt!(5, 4, 6);
+            assert_panics_with!(
+                || assert_gte_lt!(3, 4, 6),
+                "Expected 3 to be gte 4 (inclusive lower bound)"
+            );
+            assert_panics_with!(
+                || assert_gte_lt!(6, 4, 6),
+                "Expected 6 to be lt 6 (exclusive upper bound)"
+            );
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected 6 to be lt 6 (exclusive upper bound)\nts was 6")]
+        fn strictly_between_custom_message() {
+            let ts = 6;
+            assert_strictly_between!(ts, 4, 6, "ts was {}", ts);
+        }
+
+        #[test]
+        fn bounds_evaluate_once() {
+            let mut calls = 0;
+            let mut next_id = || {
+                calls += 1;
+                calls
+            };
+            assert_strictly_between!(next_id(), 0, 2);
+            assert_gt_lte!(next_id(), 1, 2);
+            assert_gte_lt!(next_id(), 3, 4, "third call should return 3");
+            assert_eq!(calls, 3);
+        }
+
+        #[test]
+        fn monotonic() {
+            let timestamps: Vec<i64> = vec![1, 2, 2, 5];
+            assert_monotonic!(&timestamps);
+            assert_monotonic!(
+                timestamps.iter().map(|ts| ts * 1000),
+                "mapped iterators work"
+            );
+            assert_strictly_monotonic!(vec![1.0, 1.5, 2.0]);
+            assert_strictly_monotonic!(1..10, "ranges work");
+        }
+
+        #[test]
+        fn monotonic_empty_and_single() {
+            assert_monotonic!(Vec::<i64>::new());
+            assert_monotonic!([42]);
+            assert_strictly_monotonic!(std::iter::empty::<i64>());
+            assert_strictly_monotonic!(std::iter::once(42), "single element");
+        }
+
+        #[test]
+        #[should_panic(
+            expected = "Expected sequence to be monotonically increasing, but element 2 (5) is greater than element 3 (4)"
+        )]
+        fn not_monotonic() {
+            assert_monotonic!(vec![1, 2, 5, 4]);
+        }
+
+        #[test]
+        #[should_panic(
+            expected = "Expected sequence to be strictly increasing, but element 1 (2) is not less than element 2 (2)"
+        )]
+        fn not_strictly_monotonic() {
+            assert_strictly_monotonic!(vec![1, 2, 2, 5]);
+        }
+
+        #[test]
+        #[should_panic(expected = "element 0 (NaN) is greater than element 1 (1.0)")]
+        fn not_monotonic_incomparable() {
+            assert_monotonic!([f64::NAN, 1.0]);
+        }
+
+        #[test]
+        #[should_panic(expected = "updated went backwards")]
+        fn not_monotonic_custom_message() {
+            assert_monotonic!([3, 2, 1], "updated went {}", "backwards");
+        }
+
+        #[test]
+        fn time_close() {
+            let now = 1_650_000_000_i64;
+            assert_time_close!(now, now);
+            assert_time_close!(now + 2, now);
+            assert_time_close!(now - 2, now);
+            assert_time_close!(now + 10, now, 10);
+            assert_time_close!(now, now, 0, "should be exact");
+        }
+
+        #[test]
+        #[should_panic(
+            expected = "Expected timestamp 1650000003 (2022-04-15T05:20:03Z) to be within 2s of 1650000000 (2022-04-15T05:20:00Z), but they are 3s apart"
+        )]
+        fn not_time_close() {
+            assert_time_close!(1_650_000_003, 1_650_000_000);
+        }
+
+        #[test]
+        #[should_panic(expected = "created drifted")]
+        fn not_time_close_custom_message() {
+            assert_time_close!(0, 60, 59, "created {}", "drifted");
+        }
+
+        #[test]
+        fn time_close_millis() {
+            use super::Millis;
+
+            let now = 1_650_000_000_000_i64;
+            assert_time_close!(now + 500, now, Millis(500));
+            assert_time_close!(now - 500, now, Millis(500), "should be close");
+            assert_eq!(
+                panic_text(|| assert_time_close!(now + 1_234, now, Millis(1_000))),
+                "Expected timestamp 1650000001234 (2022-04-15T05:20:01.234Z) to be within \
+                 1000ms of 1650000000000 (2022-04-15T05:20:00.000Z), but they are 1234ms apart"
             );
         }
 
         #[test]
-        fn unordered_match_different_types() {
-            let first = vec![1, 2, 3];
-            let second = [3, 2, 1];
-            let third = {
-                let mut set = std::collections::HashSet::new();
-                assert!(set.insert(1), "Unable to add 1 to HashSet");
-                assert!(set.insert(2), "Unable to add 2 to HashSet");
-                assert!(set.insert(3), "Unable to add 3 to HashSet");
-                set
-            };
-            let fourth = std::collections::LinkedList::from([2, 1, 3]);
+        fn rfc3339() {
+            use super::__private::rfc3339;
+            assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+            assert_eq!(rfc3339(-1), "1969-12-31T23:59:59Z");
+            assert_eq!(rfc3339(951_782_400), "2000-02-29T00:00:00Z");
+            assert_eq!(rfc3339(1_650_000_000), "2022-04-15T05:20:00Z");
+        }
 
-            assert_unordered_match!(first, second);
-            assert_unordered_match!(second, third);
-            assert_unordered_match!(third, fourth);
-            assert_unordered_match!(fourth, first);
+        #[test]
+        fn rfc3339_millis() {
+            use super::__private::rfc3339_millis;
+            assert_eq!(rfc3339_millis(0), "1970-01-01T00:00:00.000Z");
+            assert_eq!(rfc3339_millis(-1), "1969-12-31T23:59:59.999Z");
+            assert_eq!(
+                rfc3339_millis(1_650_000_000_123),
+                "2022-04-15T05:20:00.123Z"
+            );
+        }
 
-            assert_unordered_match!(
-                first,
-                second,
-                "Expected {:?} to match contents of {:?}",
-                first,
-                second
+        #[test]
+        fn contains_key_and_value() {
+            use std::collections::{BTreeMap, HashMap};
+
+            let hash: HashMap<String, String> = [("key1".to_string(), "value1".to_string())]
+                .into_iter()
+                .collect();
+            assert_contains_key!(hash, "key1");
+            assert_contains_key!(&hash, String::from("key1"), "references work too");
+            assert_not_contains_key!(hash, "key2");
+            assert_contains_value!(hash, "value1");
+
+            let btree: BTreeMap<i32, &str> = [(1, "one"), (2, "two")].into_iter().collect();
+            assert_contains_key!(btree, 2);
+            assert_not_contains_key!(btree, 3, "3 was never inserted");
+            assert_contains_value!(btree, "two", "value should be present");
+        }
+
+        #[test]
+        fn contains_key_lookup() {
+            use std::collections::HashMap;
+
+            /// A key that compares equal to its number but isn't hashable, so it
+            /// can only be found by scanning.
+            #[derive(Debug, PartialEq)]
+            struct Id(i32);
+            impl PartialEq<i32> for Id {
+                fn eq(&self, other: &i32) -> bool {
+                    self.0 == *other
+                }
+            }
+
+            std::thread_local! {
+                static COMPARED: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+            }
+            /// A key that counts how often it is compared.
+            #[derive(Debug, Eq)]
+            struct Counted(i32);
+            impl PartialEq for Counted {
+                fn eq(&self, other: &Self) -> bool {
+                    COMPARED.with(|compared| compared.set(compared.get() + 1));
+                    self.0 == other.0
+                }
+            }
+            impl std::hash::Hash for Counted {
+                fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                    self.0.hash(state);
+                }
+            }
+
+            let counted: HashMap<Counted, ()> = (0..1000).map(|i| (Counted(i), ())).collect();
+            COMPARED.with(|compared| compared.set(0));
+            assert_contains_key!(counted, Counted(500));
+            assert_not_contains_key!(counted, Counted(1000));
+            assert_within!(COMPARED.with(|compared| compared.get()), 0, 2);
+
+            let pairs = vec![(Id(1), "one"), (Id(2), "two")];
+            assert_contains_key!(pairs, 1);
+            assert_not_contains_key!(pairs, 3);
+            assert_contains_key!(pairs[..], Id(2), "slices of pairs work too");
+        }
+
+        #[cfg(feature = "dashmap")]
+        #[test]
+        fn contains_key_and_value_dashmap() {
+            let dash: dashmap::DashMap<String, i32> = dashmap::DashMap::new();
+            dash.insert("key1".to_string(), 1);
+            assert_contains_key!(dash, "key1");
+            assert_not_contains_key!(dash, "key2");
+            assert_contains_value!(dash, 1);
+            assert_panics_with!(
+                || assert_contains_key!(dash, "kye1"),
+                "Expected map to contain key \"kye1\", found keys: [\"key1\"]"
             );
-            assert_unordered_match!(
-                second,
-                third,
-                "Expected {:?} to match contents of {:?}",
-                second,
-                third
+            assert_panics_with!(
+                || assert_contains_value!(dash, 2),
+                "Expected map to contain value 2, scanned 1 entries"
             );
-            assert_unordered_match!(
-                third,
-                fourth,
-                "Expected {:?} to match contents of {:?}",
-                third,
-                fourth
+        }
+
+        #[test]
+        #[should_panic(
+            expected = "Expected map to contain key \"kye2\", found keys: [\"key1\", \"key2\"]"
+        )]
+        fn not_contains_key() {
+            let map: std::collections::HashMap<&str, i32> =
+                [("key2", 2), ("key1", 1)].into_iter().collect();
+            assert_contains_key!(map, "kye2");
+        }
+
+        #[test]
+        #[should_panic(
+            expected = "found keys: [0, 1, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 2, 20, 21, 22, 23, 24, 25, 26, ... (30 keys total)]"
+        )]
+        fn not_contains_key_truncates() {
+            let map: std::collections::BTreeMap<i32, i32> = (0..30).map(|i| (i, i)).collect();
+            assert_contains_key!(map, 30);
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected map to not contain key 1")]
+        fn contains_key_negated() {
+            let map: std::collections::BTreeMap<i32, i32> = [(1, 1)].into_iter().collect();
+            assert_not_contains_key!(map, 1);
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected map to contain value \"three\", scanned 2 entries")]
+        fn not_contains_value() {
+            let map: std::collections::BTreeMap<i32, &str> =
+                [(1, "one"), (2, "two")].into_iter().collect();
+            assert_contains_value!(map, "three");
+        }
+
+        #[test]
+        #[should_panic(expected = "missing key1")]
+        fn not_contains_key_custom_message() {
+            let map: std::collections::HashMap<&str, i32> = std::collections::HashMap::new();
+            assert_contains_key!(map, "key1", "missing {}", "key1");
+        }
+
+        #[test]
+        fn iter_eq() {
+            let values = vec![2, 4, 6];
+            assert_iter_eq!(values.iter().copied(), (1..=3).map(|i| i * 2));
+            assert_iter_eq!(&values, &[2, 4, 6]);
+            assert_iter_eq!(std::iter::empty::<i32>(), Vec::<i32>::new(), "both empty");
+            assert_iter_eq!("abc".chars(), vec!['a', 'b', 'c']);
+        }
+
+        #[test]
+        #[should_panic(expected = "Iterators differ at index 500: left = 500, right = 0")]
+        fn iter_eq_mid_stream_mismatch() {
+            assert_iter_eq!(0..1000, (0..1000).map(|i| if i == 500 { 0 } else { i }));
+        }
+
+        #[test]
+        #[should_panic(expected = "Left iterator ended early: left has 3 elements, right has 5")]
+        fn iter_eq_left_shorter() {
+            assert_iter_eq!(vec![1, 2, 3], 1..=5);
+        }
+
+        #[test]
+        #[should_panic(expected = "Right iterator ended early: left has 4 elements, right has 2")]
+        fn iter_eq_right_shorter() {
+            assert_iter_eq!([1, 2, 3, 4], vec![1, 2]);
+        }
+
+        #[test]
+        fn iter_eq_is_lazy() {
+            // An infinite iterator is fine as long as the other side differs first.
+            assert_panics_with!(
+                || assert_iter_eq!(0.., [0, 1, 5]),
+                "Iterators differ at index 2: left = 2, right = 5"
             );
-            assert_unordered_match!(
-                fourth,
-                first,
-                "Expected {:?} to match contents of {:?}",
-                fourth,
-                first
+        }
+
+        #[test]
+        #[should_panic(expected = "rows out of order")]
+        fn iter_eq_custom_message() {
+            assert_iter_eq!([1, 2], [2, 1], "rows out of {}", "order");
+        }
+
+        #[test]
+        fn some_and_none() {
+            let values = [1, 2, 3];
+            assert_eq!(assert_some!(values.first()), &1);
+            assert_eq!(assert_some!(values.last(), "should have a last value"), &3);
+            assert_some_eq!(values.first(), &1);
+            assert_some_eq!(values.get(1).copied(), 2, "second value should be 2");
+            assert_none!(values.get(3));
+            assert_none!(values.iter().find(|&&v| v > 3), "no value above 3");
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected Some, got None")]
+        fn some_got_none() {
+            assert_some!(Option::<i32>::None);
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected Some(1), got None")]
+        fn some_eq_got_none() {
+            assert_some_eq!(Option::<i32>::None, 1);
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected Some(\"value1\"), got Some(\"value2\")")]
+        fn some_eq_wrong_value() {
+            assert_some_eq!(Some("value2"), "value1");
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected None, got Some(\"value\")")]
+        fn none_got_some() {
+            assert_none!(Some("value"));
+        }
+
+        #[test]
+        fn some_and_none_custom_messages() {
+            assert_panics_with!(
+                || assert_some!(Option::<i32>::None, "key {} missing", 1),
+                "key 1 missing"
+            );
+            assert_panics_with!(|| assert_some_eq!(None::<i32>, 1, "no row"), "no row");
+            assert_panics_with!(|| assert_some_eq!(Some(2), 1, "wrong row"), "wrong row");
+            assert_panics_with!(
+                || assert_none!(Some(1), "row should be gone"),
+                "row should be gone"
             );
         }
 
         #[test]
-        fn in_range() {
-            let range = 0..10;
-            let i = 5;
-            assert_in_range!(i, range);
-            assert_in_range!(i, range, "Expected i to be in range!");
+        #[should_panic(expected = "2 elements missing from right: [4, 6]\nwhile checking row 7")]
+        fn contains_all_custom_message_is_appended() {
+            assert_contains_all!(vec![1, 4, 6], vec![1, 2], "while checking row {}", 7);
         }
 
         #[test]
-        #[should_panic]
-        fn not_in_range() {
-            let range = 0..10;
-            let i = 11;
-            assert_in_range!(i, range);
+        #[should_panic(expected = "Actual: left = 2, right = 3\nsizes differ")]
+        fn unordered_match_custom_message_keeps_counts() {
+            assert_unordered_match!(vec![1, 2], vec![1, 2, 3], "sizes differ");
         }
 
         #[test]
-        #[should_panic]
-        fn not_in_range2() {
-            let range = 0..10;
-            let i = 11;
-            assert_in_range!(i, range, "whoops!");
+        fn repeat_assert_honors_iterations() {
+            let mut seen = Vec::new();
+            repeat_assert!(25, |i| seen.push(i));
+            assert_iter_eq!(seen, 0..25);
+
+            let mut seeds = Vec::new();
+            repeat_assert!(25, seed, |i, rng_seed| seeds.push((i, rng_seed)));
+            assert_iter_eq!(seeds.iter().map(|&(i, _)| i), 0..25);
+            assert_unique!(seeds.iter().map(|&(_, seed)| seed).collect::<Vec<_>>());
+
+            repeat_assert!(0, |_| panic!("should never run"));
         }
 
         #[test]
-        fn within() {
-            let i = 5;
-            assert_within!(i, 0, 10);
-            assert_within!(i, 0, 10, "Expected i to be in range!");
+        fn repeat_assert_reports_failing_iteration() {
+            assert_panics_with!(
+                || repeat_assert!(10, |i| assert!(i != 7, "i was {}", i)),
+                "iteration 7 failed: i was 7"
+            );
+            assert_panics_with!(
+                || repeat_assert!(10, |i| {
+                    if i == 3 {
+                        std::panic::panic_any(3);
+                    }
+                }),
+                "iteration 3 failed: <non-string panic payload>"
+            );
         }
 
         #[test]
-        #[should_panic]
-        fn not_within() {
-            let i = 11;
-            assert_within!(i, 0, 10);
+        fn repeat_assert_reports_seed() {
+            let mut failing_seed = 0;
+            let text = panic_text(|| {
+                repeat_assert!(10, seed, |i, rng_seed| {
+                    if i == 4 {
+                        failing_seed = rng_seed;
+                        panic!("boom");
+                    }
+                })
+            });
+            assert_eq!(
+                text,
+                format!("iteration 4 (seed {}) failed: boom", failing_seed)
+            );
         }
 
         #[test]
-        #[should_panic]
-        fn not_within2() {
-            let i = 11;
-            assert_within!(i, 0, 10, "whoops!");
+        fn unordered_match_iterators() {
+            let map: std::collections::HashMap<String, i32> =
+                [("a".to_string(), 1), ("b".to_string(), 2)]
+                    .into_iter()
+                    .collect();
+            let keys = vec!["b".to_string(), "a".to_string()];
+            assert_unordered_match!(map.keys(), keys);
+            assert_unordered_match!(keys.iter().map(|k| k.as_str()), ["a", "b"].into_iter());
+            assert_unordered_match!(map.values().copied(), 1..=2, "values should be 1 and 2");
+            assert_unordered_match!(std::iter::empty::<i32>(), Vec::<i32>::new().into_iter());
+        }
+
+        #[test]
+        fn contains_all_iterators() {
+            let map: std::collections::BTreeMap<&str, i32> =
+                [("a", 1), ("b", 2), ("c", 3)].into_iter().collect();
+            assert_contains_all!(["a", "c"].iter(), map.keys());
+            assert_contains_all!(map.values().filter(|&&v| v > 1), [3, 2, 1].iter());
+            assert_contains_all!(2..4, map.values().copied(), "2 and 3 should be present");
+        }
+
+        #[test]
+        #[should_panic(
+            expected = "element \"c\" appears 1 time on the left but 0 times on the right"
+        )]
+        fn unordered_match_iterators_mismatch() {
+            let map: std::collections::BTreeMap<&str, i32> =
+                [("a", 1), ("c", 3)].into_iter().collect();
+            assert_unordered_match!(map.keys().copied(), ["a", "b"].into_iter());
+        }
+
+        #[test]
+        fn contains_all_without_hash() {
+            assert_contains_all!(vec![1.5, 2.5], vec![2.5, 0.5, 1.5]);
+            assert_eq!(
+                check_contains_all!([0.5, 1.5, 3.5], [1.5]),
+                Err("2 elements missing from right: [0.5, 3.5]".to_string())
+            );
+        }
+
+        #[test]
+        #[should_panic(expected = "1 element missing from right: [4]")]
+        fn contains_all_iterators_missing() {
+            let values = [1, 2, 3];
+            assert_contains_all!(values.iter().map(|v| v + 1), values.iter().copied());
+        }
+
+        #[test]
+        fn intersection() {
+            let first = vec![1, 2, 3, 3];
+            let second = std::collections::LinkedList::from([5, 4, 3, 2]);
+            assert_intersection!(first, second, [3, 2]);
+            assert_intersection!(first, vec![7, 8], Vec::<i32>::new(), "disjoint");
+            assert_intersection!(first, first, vec![1, 2, 3]);
+            let map: std::collections::BTreeMap<&str, i32> =
+                [("a", 1), ("b", 2)].into_iter().collect();
+            assert_intersection!(map.keys(), ["b", "c"].iter(), ["b"].iter());
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected intersection to match [3], but it was [2, 3]")]
+        fn intersection_too_large() {
+            assert_intersection!(vec![1, 2, 3], vec![2, 3, 4], [3]);
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected intersection to match [1], but it was []")]
+        fn intersection_disjoint() {
+            assert_intersection!(vec![1, 2], vec![3, 4], [1]);
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected intersection to match [1, 1], but it was [1]")]
+        fn intersection_is_a_set() {
+            assert_intersection!(vec![1, 1], vec![1, 1], [1, 1]);
+        }
+
+        #[test]
+        #[should_panic(
+            expected = "Expected intersection to match [\"key2\"], but it was []\nstores should share key2"
+        )]
+        fn intersection_custom_message() {
+            assert_intersection!(
+                vec!["key1"],
+                vec!["key2"],
+                ["key2"],
+                "stores should share {}",
+                "key2"
+            );
+        }
+
+        #[test]
+        fn union_len() {
+            let first = vec!["key1", "key2"];
+            let second = vec!["key2", "key3", "key3"];
+            assert_union_len!(first, second, 3);
+            assert_union_len!(first, vec!["key4"], 3, "disjoint");
+            assert_union_len!(second, second, 2);
+            assert_union_len!(Vec::<i32>::new(), [], 0);
+            assert_union_len!(1..4, 2..6, 5);
+        }
+
+        #[test]
+        #[should_panic(expected = "Expected union to have 4 elements, but it has 3: [1, 2, 3]")]
+        fn union_len_wrong() {
+            assert_union_len!(vec![1, 2, 2], vec![3, 1], 4);
+        }
+
+        #[test]
+        #[should_panic(
+            expected = "Expected union to have 2 elements, but it has 1: [1]\nidentical stores"
+        )]
+        fn union_len_custom_message() {
+            assert_union_len!(vec![1], vec![1], 2, "identical stores");
+        }
+
+        #[test]
+        fn duration_within() {
+            let value = assert_duration_within!(5_000, {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                42
+            });
+            assert_eq!(value, 42);
+            assert_duration_within!(10, 5_000, {
+                std::thread::sleep(std::time::Duration::from_millis(30));
+            });
+        }
+
+        #[test]
+        fn duration_within_too_slow() {
+            assert_panics_with!(
+                || assert_duration_within!(1, {
+                    std::thread::sleep(std::time::Duration::from_millis(50));
+                }),
+                "Expected block to finish within 1ms, but it took"
+            );
+        }
+
+        #[test]
+        fn duration_within_too_fast() {
+            assert_panics_with!(
+                || assert_duration_within!(1_000, 5_000, { 1 + 1 }),
+                "Expected block to take at least 1000ms, but it took"
+            );
+        }
+
+        #[test]
+        fn duration_violation() {
+            use super::__private::duration_violation;
+            use std::time::Duration;
+            assert_none!(duration_violation(Duration::from_millis(10), 10, 10));
+            assert_some_eq!(
+                duration_violation(Duration::from_millis(11), 0, 10),
+                "Expected block to finish within 10ms, but it took 11ms".to_string()
+            );
+            assert_some_eq!(
+                duration_violation(Duration::from_millis(9), 10, 20),
+                "Expected block to take at least 10ms, but it took 9ms".to_string()
+            );
+        }
+
+        #[test]
+        fn ordered_match() {
+            let first = vec![1, 2, 3];
+            assert_ordered_match!(first, [1, 2, 3]);
+            assert_ordered_match!(first, std::collections::LinkedList::from([1, 2, 3]));
+            assert_ordered_match!(first.iter().copied(), 1..4, "iterators work too");
+            assert_ordered_match!(Vec::<i32>::new(), []);
+        }
+
+        #[test]
+        #[should_panic(
+            expected = "Expected collections to match in order, but they differ:\n  0: 1 != 9"
+        )]
+        fn ordered_match_mismatch_at_start() {
+            assert_ordered_match!(vec![1, 2, 3], [9, 2, 3]);
+        }
+
+        #[test]
+        fn ordered_match_mismatch_in_middle_and_end() {
+            let diff = super::__private::ordered_diff(&[1, 2, 3, 4, 5], &[1, 7, 3, 4, 8]).unwrap();
+            assert_eq!(
+                diff,
+                "Expected collections to match in order, but they differ:\n  1: 2 != 7\n  4: 5 != 8"
+            );
+        }
+
+        #[test]
+        #[should_panic(
+            expected = "Expected collections to match in order, but they differ:\n  left has 2 extra elements starting at index 2: [3, 4]"
+        )]
+        fn ordered_match_left_longer() {
+            assert_ordered_match!(vec![1, 2, 3, 4], [1, 2]);
+        }
+
+        #[test]
+        fn ordered_match_right_longer_with_mismatch() {
+            let diff = super::__private::ordered_diff(&["a", "b"], &["a", "c", "d"]).unwrap();
+            assert_eq!(
+                diff,
+                "Expected collections to match in order, but they differ:\n  \
+                 1: \"b\" != \"c\"\n  \
+                 right has 1 extra element starting at index 2: [\"d\"]"
+            );
+        }
+
+        #[test]
+        fn ordered_match_truncates_long_diffs() {
+            let left: Vec<i32> = (0..50).collect();
+            let right: Vec<i32> = (100..125).collect();
+            let diff = super::__private::ordered_diff(&left, &right).unwrap();
+            assert!(diff.contains("\n  19: 19 != 119\n  ... and 5 more mismatches\n"));
+            assert!(diff.ends_with(
+                "left has 25 extra elements starting at index 25: [25, 26, 27, 28, 29, 30, 31, 32, 33, 34]..."
+            ));
+        }
+
+        #[test]
+        #[should_panic(expected = "  0: 1 != 2\nafter reordering rows")]
+        fn ordered_match_custom_message() {
+            assert_ordered_match!([1], [2], "after reordering {}", "rows");
         }
     }
 }
@@ -0,0 +1,353 @@
+// Copyright (c) 2022 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A tiny regular expression matcher used by `assert_matches_regex!`, so the
+//! utils crate doesn't need to pull in the `regex` crate.
+//!
+//! Supported syntax:
+//! - literal characters, and `\` to escape any meta character
+//! - `.` for any character
+//! - character classes like `[abc]`, `[a-z0-9]` and `[^"]`
+//! - `\d`, `\w`, `\s` and their negations `\D`, `\W`, `\S`
+//! - the `*`, `+` and `?` quantifiers (greedy)
+//! - `^` and `$` anchors at the start / end of the pattern
+//!
+//! Groups, alternation and counted repetition are not supported and are
+//! rejected when the pattern is compiled.
+
+use std::fmt;
+
+/// Error returned when a pattern can't be compiled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternError {
+    pattern: String,
+    position: usize,
+    reason: &'static str,
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid pattern {:?}: {} at position {}",
+            self.pattern, self.reason, self.position
+        )
+    }
+}
+
+impl std::error::Error for PatternError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ClassItem {
+    Range(char, char),
+    Digit(bool),
+    Word(bool),
+    Space(bool),
+}
+
+impl ClassItem {
+    fn matches(&self, c: char) -> bool {
+        match *self {
+            ClassItem::Range(lo, hi) => lo <= c && c <= hi,
+            ClassItem::Digit(negated) => c.is_ascii_digit() != negated,
+            ClassItem::Word(negated) => (c.is_alphanumeric() || c == '_') != negated,
+            ClassItem::Space(negated) => c.is_whitespace() != negated,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Atom {
+    Any,
+    Class {
+        negated: bool,
+        items: Vec<ClassItem>,
+    },
+}
+
+impl Atom {
+    fn literal(c: char) -> Self {
+        Atom::Class {
+            negated: false,
+            items: vec![ClassItem::Range(c, c)],
+        }
+    }
+
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Atom::Any => true,
+            Atom::Class { negated, items } => items.iter().any(|item| item.matches(c)) != *negated,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Repeat {
+    One,
+    Optional,
+    Star,
+    Plus,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Node {
+    atom: Atom,
+    repeat: Repeat,
+}
+
+/// A compiled pattern.
+///
+/// ## Example(s)
+/// ```rust
+/// # use stupid_utils::pattern::Pattern;
+/// let pattern = Pattern::new("key '.+' not found").unwrap();
+/// assert!(pattern.is_match("key 'foo' not found"));
+/// assert!(!pattern.is_match("key '' not found"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    source: String,
+    nodes: Vec<Node>,
+    anchored_start: bool,
+    anchored_end: bool,
+}
+
+impl Pattern {
+    /// Compiles `pattern`, returning an error describing the first unsupported or
+    /// malformed piece of syntax.
+    pub fn new(pattern: &str) -> Result<Self, PatternError> {
+        let error = |position: usize, reason: &'static str| PatternError {
+            pattern: pattern.to_string(),
+            position,
+            reason,
+        };
+
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut nodes: Vec<Node> = Vec::new();
+        let mut anchored_start = false;
+        let mut anchored_end = false;
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            let atom = match c {
+                '^' if i == 0 => {
+                    anchored_start = true;
+                    i += 1;
+                    continue;
+                }
+                '$' if i == chars.len() - 1 => {
+                    anchored_end = true;
+                    i += 1;
+                    continue;
+                }
+                '^' | '$' => return Err(error(i, "anchors are only supported at the ends")),
+                '(' | ')' | '|' | '{' | '}' => return Err(error(i, "unsupported syntax")),
+                ']' => return Err(error(i, "unopened character class")),
+                '*' | '+' | '?' => {
+                    let repeat = match c {
+                        '*' => Repeat::Star,
+                        '+' => Repeat::Plus,
+                        _ => Repeat::Optional,
+                    };
+                    match nodes.last_mut() {
+                        Some(node) if node.repeat == Repeat::One => node.repeat = repeat,
+                        Some(_) => return Err(error(i, "repeated quantifier")),
+                        None => return Err(error(i, "quantifier without a target")),
+                    }
+                    i += 1;
+                    continue;
+                }
+                '.' => Atom::Any,
+                '\\' => {
+                    i += 1;
+                    let escaped = *chars.get(i).ok_or_else(|| error(i, "trailing backslash"))?;
+                    match escape_class(escaped) {
+                        Some(item) => Atom::Class {
+                            negated: false,
+                            items: vec![item],
+                        },
+                        None => Atom::literal(escaped),
+                    }
+                }
+                '[' => {
+                    let start = i;
+                    i += 1;
+                    let negated = chars.get(i) == Some(&'^');
+                    if negated {
+                        i += 1;
+                    }
+                    let mut items = Vec::new();
+                    loop {
+                        let c = *chars
+                            .get(i)
+                            .ok_or_else(|| error(start, "unclosed character class"))?;
+                        match c {
+                            ']' => break,
+                            '\\' => {
+                                i += 1;
+                                let escaped =
+                                    *chars.get(i).ok_or_else(|| error(i, "trailing backslash"))?;
+                                items.push(
+                                    escape_class(escaped)
+                                        .unwrap_or(ClassItem::Range(escaped, escaped)),
+                                );
+                            }
+                            _ if chars.get(i + 1) == Some(&'-')
+                                && chars.get(i + 2).is_some_and(|&hi| hi != ']') =>
+                            {
+                                let hi = chars[i + 2];
+                                if hi < c {
+                                    return Err(error(i, "invalid character range"));
+                                }
+                                items.push(ClassItem::Range(c, hi));
+                                i += 2;
+                            }
+                            _ => items.push(ClassItem::Range(c, c)),
+                        }
+                        i += 1;
+                    }
+                    if items.is_empty() {
+                        return Err(error(start, "empty character class"));
+                    }
+                    Atom::Class { negated, items }
+                }
+                _ => Atom::literal(c),
+            };
+            nodes.push(Node {
+                atom,
+                repeat: Repeat::One,
+            });
+            i += 1;
+        }
+
+        Ok(Self {
+            source: pattern.to_string(),
+            nodes,
+            anchored_start,
+            anchored_end,
+        })
+    }
+
+    /// Gets the source text this pattern was compiled from.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Checks whether this pattern matches anywhere in `haystack`, unless it is
+    /// anchored with `^` and/or `$`.
+    pub fn is_match(&self, haystack: &str) -> bool {
+        let chars: Vec<char> = haystack.chars().collect();
+        if self.anchored_start {
+            return self.match_here(&self.nodes, &chars, 0);
+        }
+        (0..=chars.len()).any(|start| self.match_here(&self.nodes, &chars, start))
+    }
+
+    fn match_here(&self, nodes: &[Node], text: &[char], pos: usize) -> bool {
+        let (node, rest) = match nodes.split_first() {
+            Some(split) => split,
+            None => return !self.anchored_end || pos == text.len(),
+        };
+        let matches_at = |i: usize| i < text.len() && node.atom.matches(text[i]);
+        match node.repeat {
+            Repeat::One => matches_at(pos) && self.match_here(rest, text, pos + 1),
+            Repeat::Optional => {
+                (matches_at(pos) && self.match_here(rest, text, pos + 1))
+                    || self.match_here(rest, text, pos)
+            }
+            Repeat::Star | Repeat::Plus => {
+                let min = if node.repeat == Repeat::Plus { 1 } else { 0 };
+                let mut max = 0;
+                while matches_at(pos + max) {
+                    max += 1;
+                }
+                (min..=max)
+                    .rev()
+                    .any(|count| self.match_here(rest, text, pos + count))
+            }
+        }
+    }
+}
+
+fn escape_class(c: char) -> Option<ClassItem> {
+    match c {
+        'd' => Some(ClassItem::Digit(false)),
+        'D' => Some(ClassItem::Digit(true)),
+        'w' => Some(ClassItem::Word(false)),
+        'W' => Some(ClassItem::Word(true)),
+        's' => Some(ClassItem::Space(false)),
+        'S' => Some(ClassItem::Space(true)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_match(pattern: &str, haystack: &str) -> bool {
+        Pattern::new(pattern)
+            .unwrap_or_else(|err| panic!("{}", err))
+            .is_match(haystack)
+    }
+
+    #[test]
+    fn literals_and_wildcards() {
+        assert!(is_match("abc", "xxabcxx"));
+        assert!(!is_match("abc", "ab"));
+        assert!(is_match("a.c", "abc"));
+        assert!(is_match("a\\.c", "a.c"));
+        assert!(!is_match("a\\.c", "abc"));
+        assert!(is_match("", "anything"));
+    }
+
+    #[test]
+    fn quantifiers() {
+        assert!(is_match("^ab*c$", "ac"));
+        assert!(is_match("^ab*c$", "abbbc"));
+        assert!(!is_match("^ab+c$", "ac"));
+        assert!(is_match("^ab+c$", "abc"));
+        assert!(is_match("^colou?r$", "color"));
+        assert!(is_match("^colou?r$", "colour"));
+        assert!(is_match("^.*x$", "aaax"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(is_match("^[a-c]+$", "abcabc"));
+        assert!(!is_match("^[a-c]+$", "abcd"));
+        assert!(is_match("^[^0-9]+$", "abc"));
+        assert!(!is_match("^[^0-9]+$", "ab1"));
+        assert!(is_match("^\\d+\\s\\w+$", "42 apples"));
+        assert!(is_match("^[-a]+$", "-a-"));
+    }
+
+    #[test]
+    fn anchors() {
+        assert!(is_match("^key", "key1"));
+        assert!(!is_match("^key", "a key"));
+        assert!(is_match("1$", "key1"));
+        assert!(!is_match("1$", "key12"));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        for pattern in [
+            "*a", "a**", "[abc", "a\\", "(a)", "a|b", "a{2}", "a^b", "[]", "[z-a]",
+        ] {
+            assert!(
+                Pattern::new(pattern).is_err(),
+                "expected {:?} to be rejected",
+                pattern
+            );
+        }
+        let err = Pattern::new("[abc").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid pattern \"[abc\": unclosed character class at position 0"
+        );
+    }
+}